@@ -19,6 +19,12 @@ fn main() {
       }
     }
   }
+  if let Ok(v) = std::env::var("CLOUDTG_UPDATER_PUBKEY") {
+    if !v.trim().is_empty() {
+      println!("cargo:rustc-env=CLOUDTG_UPDATER_PUBKEY={}", v.trim());
+    }
+  }
+  println!("cargo:rerun-if-env-changed=CLOUDTG_UPDATER_PUBKEY");
   println!("cargo:rerun-if-env-changed=CLOUDTG_EMBED_API_KEYS");
   println!("cargo:rerun-if-env-changed=CLOUDTG_API_ID");
   println!("cargo:rerun-if-env-changed=CLOUDTG_API_HASH");
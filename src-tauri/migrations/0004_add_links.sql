@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS links (
+  id TEXT PRIMARY KEY NOT NULL,
+  dir_id TEXT NOT NULL,
+  target_kind TEXT NOT NULL,
+  target_id TEXT NOT NULL,
+  tg_msg_id INTEGER NULL,
+  created_at INTEGER NOT NULL,
+  FOREIGN KEY(dir_id) REFERENCES directories(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_links_dir ON links(dir_id);
+CREATE INDEX IF NOT EXISTS idx_links_target ON links(target_kind, target_id);
+
+CREATE TRIGGER IF NOT EXISTS trg_links_file_deleted AFTER DELETE ON files
+BEGIN
+  DELETE FROM links WHERE target_kind = 'file' AND target_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_links_dir_deleted AFTER DELETE ON directories
+BEGIN
+  DELETE FROM links WHERE target_kind = 'dir' AND target_id = OLD.id;
+END;
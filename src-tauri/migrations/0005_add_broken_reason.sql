@@ -0,0 +1,31 @@
+ALTER TABLE directories ADD COLUMN broken_reason TEXT NULL;
+ALTER TABLE directories ADD COLUMN broken_since INTEGER NULL;
+ALTER TABLE files ADD COLUMN broken_reason TEXT NULL;
+ALTER TABLE files ADD COLUMN broken_since INTEGER NULL;
+
+UPDATE directories SET broken_since = updated_at WHERE is_broken != 0;
+UPDATE files SET broken_since = created_at WHERE is_broken != 0;
+
+CREATE TRIGGER IF NOT EXISTS trg_directories_broken_set AFTER UPDATE OF is_broken ON directories
+WHEN NEW.is_broken != 0 AND OLD.is_broken = 0 AND NEW.broken_since IS NULL
+BEGIN
+  UPDATE directories SET broken_since = CAST(strftime('%s', 'now') AS INTEGER) WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_directories_broken_cleared AFTER UPDATE OF is_broken ON directories
+WHEN NEW.is_broken = 0 AND OLD.is_broken != 0
+BEGIN
+  UPDATE directories SET broken_reason = NULL, broken_since = NULL WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_files_broken_set AFTER UPDATE OF is_broken ON files
+WHEN NEW.is_broken != 0 AND OLD.is_broken = 0 AND NEW.broken_since IS NULL
+BEGIN
+  UPDATE files SET broken_since = CAST(strftime('%s', 'now') AS INTEGER) WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_files_broken_cleared AFTER UPDATE OF is_broken ON files
+WHEN NEW.is_broken = 0 AND OLD.is_broken != 0
+BEGIN
+  UPDATE files SET broken_reason = NULL, broken_since = NULL WHERE id = NEW.id;
+END;
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS import_rules (
+  id TEXT PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL,
+  priority INTEGER NOT NULL,
+  enabled INTEGER NOT NULL DEFAULT 1,
+  extensions TEXT NOT NULL DEFAULT '',
+  sender_id INTEGER NULL,
+  name_pattern TEXT NULL,
+  min_size INTEGER NULL,
+  max_size INTEGER NULL,
+  target_dir_id TEXT NOT NULL,
+  tags TEXT NOT NULL DEFAULT '',
+  created_at INTEGER NOT NULL,
+  FOREIGN KEY(target_dir_id) REFERENCES directories(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_import_rules_priority ON import_rules(priority);
@@ -0,0 +1,2 @@
+ALTER TABLE files ADD COLUMN media_group_id TEXT NULL;
+CREATE INDEX IF NOT EXISTS idx_files_media_group ON files(tg_chat_id, media_group_id) WHERE media_group_id IS NOT NULL;
@@ -0,0 +1,11 @@
+ALTER TABLE files ADD COLUMN media_kind TEXT NULL;
+
+CREATE TABLE IF NOT EXISTS file_transcripts (
+  file_id TEXT PRIMARY KEY NOT NULL,
+  source TEXT NOT NULL,
+  text TEXT NOT NULL,
+  created_at INTEGER NOT NULL,
+  FOREIGN KEY(file_id) REFERENCES files(id) ON DELETE CASCADE
+);
+
+-- Расшифровки ищутся через общий индекс file_search (0025_add_file_search).
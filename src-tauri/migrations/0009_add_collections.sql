@@ -0,0 +1,9 @@
+ALTER TABLE files ADD COLUMN pack_id TEXT NULL;
+CREATE INDEX IF NOT EXISTS idx_files_media_kind ON files(media_kind, pack_id);
+
+CREATE TABLE IF NOT EXISTS sticker_packs (
+  id TEXT PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL,
+  title TEXT NOT NULL,
+  updated_at INTEGER NOT NULL
+);
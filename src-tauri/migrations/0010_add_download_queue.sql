@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS download_queue (
+  file_id TEXT PRIMARY KEY NOT NULL,
+  priority INTEGER NOT NULL,
+  position INTEGER NOT NULL,
+  status TEXT NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  last_error TEXT NULL,
+  enqueued_at INTEGER NOT NULL,
+  FOREIGN KEY(file_id) REFERENCES files(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_download_queue_order ON download_queue(status, priority, position);
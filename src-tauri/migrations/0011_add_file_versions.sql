@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS file_versions (
+  id TEXT PRIMARY KEY NOT NULL,
+  file_id TEXT NOT NULL,
+  tg_chat_id INTEGER NOT NULL,
+  tg_msg_id INTEGER NOT NULL,
+  hash TEXT NOT NULL,
+  size INTEGER NOT NULL,
+  reason TEXT NOT NULL,
+  created_at INTEGER NOT NULL,
+  FOREIGN KEY(file_id) REFERENCES files(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_file_versions_file ON file_versions(file_id);
+CREATE INDEX IF NOT EXISTS idx_file_versions_msg ON file_versions(tg_chat_id, tg_msg_id);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS diagnostics_counters (
+  day TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  code TEXT NOT NULL,
+  count INTEGER NOT NULL DEFAULT 0,
+  PRIMARY KEY(day, kind, code)
+);
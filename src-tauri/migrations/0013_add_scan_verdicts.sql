@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS scan_verdicts (
+  sha256 TEXT NOT NULL,
+  scanner TEXT NOT NULL,
+  scanned_at INTEGER NOT NULL,
+  PRIMARY KEY(sha256, scanner)
+);
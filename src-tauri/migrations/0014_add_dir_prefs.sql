@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS dir_prefs (
+  dir_id TEXT PRIMARY KEY NOT NULL,
+  sort TEXT NOT NULL,
+  grouping TEXT NOT NULL,
+  view_mode TEXT NOT NULL,
+  updated_at INTEGER NOT NULL
+);
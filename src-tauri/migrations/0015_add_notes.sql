@@ -0,0 +1,29 @@
+CREATE TABLE IF NOT EXISTS notes (
+  file_id TEXT PRIMARY KEY NOT NULL,
+  body TEXT NOT NULL,
+  updated_at INTEGER NOT NULL,
+  FOREIGN KEY(file_id) REFERENCES files(id) ON DELETE CASCADE
+);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS notes_fts USING fts5(
+  body,
+  content = 'notes',
+  content_rowid = 'rowid',
+  tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE TRIGGER IF NOT EXISTS trg_notes_ai AFTER INSERT ON notes
+BEGIN
+  INSERT INTO notes_fts(rowid, body) VALUES (NEW.rowid, NEW.body);
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_notes_ad AFTER DELETE ON notes
+BEGIN
+  INSERT INTO notes_fts(notes_fts, rowid, body) VALUES ('delete', OLD.rowid, OLD.body);
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_notes_au AFTER UPDATE ON notes
+BEGIN
+  INSERT INTO notes_fts(notes_fts, rowid, body) VALUES ('delete', OLD.rowid, OLD.body);
+  INSERT INTO notes_fts(rowid, body) VALUES (NEW.rowid, NEW.body);
+END;
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS pending_uploads (
+  file_id TEXT PRIMARY KEY NOT NULL,
+  dir_id TEXT NOT NULL,
+  name TEXT NOT NULL,
+  size INTEGER NOT NULL,
+  hash TEXT NOT NULL,
+  created_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_pending_uploads_file ON pending_uploads(dir_id, name, size, hash);
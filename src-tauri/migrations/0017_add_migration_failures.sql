@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS migration_failures (
+  file_id TEXT PRIMARY KEY NOT NULL,
+  old_chat_id INTEGER NOT NULL,
+  old_msg_id INTEGER NOT NULL,
+  new_chat_id INTEGER NOT NULL,
+  reason TEXT NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  created_at INTEGER NOT NULL,
+  FOREIGN KEY(file_id) REFERENCES files(id) ON DELETE CASCADE
+);
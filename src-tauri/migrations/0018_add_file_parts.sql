@@ -0,0 +1,11 @@
+ALTER TABLE files ADD COLUMN part_count INTEGER NOT NULL DEFAULT 0;
+CREATE TABLE IF NOT EXISTS file_parts (
+  file_id TEXT NOT NULL,
+  part_index INTEGER NOT NULL,
+  tg_chat_id INTEGER NOT NULL,
+  tg_msg_id INTEGER NOT NULL,
+  size INTEGER NOT NULL,
+  PRIMARY KEY(file_id, part_index),
+  FOREIGN KEY(file_id) REFERENCES files(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_file_parts_message ON file_parts(tg_chat_id, tg_msg_id);
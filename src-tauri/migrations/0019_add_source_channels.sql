@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS source_channels (
+  chat_id INTEGER PRIMARY KEY NOT NULL,
+  title TEXT NOT NULL,
+  target_dir_id TEXT NOT NULL,
+  enabled INTEGER NOT NULL DEFAULT 1,
+  documents_only INTEGER NOT NULL DEFAULT 1,
+  last_message_id INTEGER NOT NULL DEFAULT 0,
+  created_at INTEGER NOT NULL,
+  FOREIGN KEY(target_dir_id) REFERENCES directories(id) ON DELETE CASCADE
+);
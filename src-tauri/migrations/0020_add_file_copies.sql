@@ -0,0 +1,12 @@
+ALTER TABLE files ADD COLUMN remote_unique_id TEXT NULL;
+CREATE INDEX IF NOT EXISTS idx_files_remote_unique ON files(remote_unique_id);
+
+CREATE TABLE IF NOT EXISTS file_copies (
+  tg_chat_id INTEGER NOT NULL,
+  tg_msg_id INTEGER NOT NULL,
+  file_id TEXT NOT NULL,
+  created_at INTEGER NOT NULL,
+  PRIMARY KEY(tg_chat_id, tg_msg_id),
+  FOREIGN KEY(file_id) REFERENCES files(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_file_copies_file ON file_copies(file_id);
@@ -0,0 +1 @@
+ALTER TABLE files ADD COLUMN enc_key_id TEXT NULL;
@@ -0,0 +1,12 @@
+-- Загрузки очереди передач; скачивания стоят в download_queue.
+CREATE TABLE IF NOT EXISTS upload_queue (
+  id TEXT PRIMARY KEY NOT NULL,
+  dir_id TEXT NOT NULL,
+  source_path TEXT NOT NULL,
+  name TEXT NOT NULL,
+  status TEXT NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  last_error TEXT NULL,
+  created_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_upload_queue_order ON upload_queue(status, created_at);
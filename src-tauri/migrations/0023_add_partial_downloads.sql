@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS partial_downloads (
+  file_id TEXT PRIMARY KEY NOT NULL,
+  downloaded INTEGER NOT NULL DEFAULT 0,
+  total INTEGER NOT NULL,
+  last_error TEXT NULL,
+  started_at INTEGER NOT NULL,
+  updated_at INTEGER NOT NULL,
+  FOREIGN KEY(file_id) REFERENCES files(id) ON DELETE CASCADE
+);
@@ -0,0 +1,2 @@
+ALTER TABLE files ADD COLUMN content_hash TEXT NULL;
+CREATE INDEX IF NOT EXISTS idx_files_content_hash ON files(content_hash);
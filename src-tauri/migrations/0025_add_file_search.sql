@@ -0,0 +1,90 @@
+CREATE TABLE IF NOT EXISTS file_search (
+  id INTEGER PRIMARY KEY,
+  file_id TEXT NOT NULL UNIQUE,
+  name TEXT NOT NULL,
+  folder TEXT NOT NULL DEFAULT '',
+  caption TEXT NOT NULL DEFAULT '',
+  transcript TEXT NOT NULL DEFAULT '',
+  stale INTEGER NOT NULL DEFAULT 1,
+  FOREIGN KEY(file_id) REFERENCES files(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_file_search_stale ON file_search(stale) WHERE stale = 1;
+
+CREATE VIRTUAL TABLE IF NOT EXISTS file_search_fts USING fts5(
+  name,
+  folder,
+  caption,
+  transcript,
+  content = 'file_search',
+  content_rowid = 'id',
+  tokenize = 'unicode61 remove_diacritics 2'
+);
+
+CREATE TRIGGER IF NOT EXISTS trg_file_search_ai AFTER INSERT ON file_search
+BEGIN
+  INSERT INTO file_search_fts(rowid, name, folder, caption, transcript)
+  VALUES (NEW.id, NEW.name, NEW.folder, NEW.caption, NEW.transcript);
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_file_search_ad AFTER DELETE ON file_search
+BEGIN
+  INSERT INTO file_search_fts(file_search_fts, rowid, name, folder, caption, transcript)
+  VALUES ('delete', OLD.id, OLD.name, OLD.folder, OLD.caption, OLD.transcript);
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_file_search_au AFTER UPDATE OF name, folder, caption, transcript ON file_search
+BEGIN
+  INSERT INTO file_search_fts(file_search_fts, rowid, name, folder, caption, transcript)
+  VALUES ('delete', OLD.id, OLD.name, OLD.folder, OLD.caption, OLD.transcript);
+  INSERT INTO file_search_fts(rowid, name, folder, caption, transcript)
+  VALUES (NEW.id, NEW.name, NEW.folder, NEW.caption, NEW.transcript);
+END;
+
+-- Расшифровки (0008_add_transcripts) ищутся вместе с именем и подписью.
+CREATE TRIGGER IF NOT EXISTS trg_file_transcripts_search_ai AFTER INSERT ON file_transcripts
+BEGIN
+  UPDATE file_search SET transcript = NEW.text WHERE file_id = NEW.file_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_file_transcripts_search_au AFTER UPDATE OF text ON file_transcripts
+BEGIN
+  UPDATE file_search SET transcript = NEW.text WHERE file_id = NEW.file_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_file_transcripts_search_ad AFTER DELETE ON file_transcripts
+BEGIN
+  UPDATE file_search SET transcript = '' WHERE file_id = OLD.file_id;
+END;
+
+-- Путь папки нельзя посчитать в триггере: рекурсивные запросы там запрещены.
+-- Триггеры только помечают строки устаревшими, путь досчитывается перед поиском.
+CREATE TABLE IF NOT EXISTS file_search_stale_dirs (
+  dir_id TEXT PRIMARY KEY NOT NULL
+);
+
+CREATE TRIGGER IF NOT EXISTS trg_files_search_ai AFTER INSERT ON files
+BEGIN
+  INSERT OR IGNORE INTO file_search(file_id, name) VALUES (NEW.id, NEW.name);
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_files_search_au_name AFTER UPDATE OF name ON files
+WHEN NEW.name IS NOT OLD.name
+BEGIN
+  UPDATE file_search SET name = NEW.name WHERE file_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_files_search_au_dir AFTER UPDATE OF dir_id ON files
+WHEN NEW.dir_id IS NOT OLD.dir_id
+BEGIN
+  UPDATE file_search SET stale = 1 WHERE file_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS trg_directories_search_au AFTER UPDATE OF name, parent_id ON directories
+WHEN NEW.name IS NOT OLD.name OR NEW.parent_id IS NOT OLD.parent_id
+BEGIN
+  INSERT OR IGNORE INTO file_search_stale_dirs(dir_id) VALUES (NEW.id);
+END;
+
+INSERT INTO file_search(file_id, name, transcript)
+SELECT f.id, f.name, COALESCE(t.text, '') FROM files f LEFT JOIN file_transcripts t ON t.file_id = f.id;
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS local_copies (
+  file_id TEXT PRIMARY KEY NOT NULL,
+  path TEXT NOT NULL,
+  size INTEGER NOT NULL,
+  mtime INTEGER NOT NULL,
+  hash TEXT NULL,
+  recorded_at INTEGER NOT NULL,
+  FOREIGN KEY(file_id) REFERENCES files(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_local_copies_path ON local_copies(path);
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS storage_channels (
+  chat_id INTEGER PRIMARY KEY NOT NULL,
+  title TEXT NOT NULL,
+  last_message_id INTEGER NOT NULL DEFAULT 0,
+  created_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS dir_storage_channels (
+  dir_id TEXT PRIMARY KEY NOT NULL,
+  chat_id INTEGER NOT NULL,
+  FOREIGN KEY(dir_id) REFERENCES directories(id) ON DELETE CASCADE,
+  FOREIGN KEY(chat_id) REFERENCES storage_channels(chat_id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_dir_storage_channels_chat ON dir_storage_channels(chat_id);
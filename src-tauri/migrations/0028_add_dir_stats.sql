@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS dir_stats (
+  dir_id TEXT PRIMARY KEY NOT NULL,
+  own_files INTEGER NOT NULL DEFAULT 0,
+  own_size INTEGER NOT NULL DEFAULT 0,
+  total_files INTEGER NOT NULL DEFAULT 0,
+  total_size INTEGER NOT NULL DEFAULT 0,
+  updated_at INTEGER NOT NULL,
+  FOREIGN KEY(dir_id) REFERENCES directories(id) ON DELETE CASCADE
+);
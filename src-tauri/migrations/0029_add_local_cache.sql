@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS local_cache (
+  file_id TEXT PRIMARY KEY NOT NULL,
+  last_access INTEGER NOT NULL,
+  FOREIGN KEY(file_id) REFERENCES files(id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_local_cache_last_access ON local_cache(last_access);
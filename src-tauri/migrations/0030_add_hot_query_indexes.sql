@@ -0,0 +1,48 @@
+-- Одно сообщение — один файл. В старых базах прежние ошибки индексации
+-- оставляли несколько записей на сообщение: остается исправная и самая
+-- ранняя, связанные данные остальных переносятся на нее.
+CREATE TEMP TABLE files_message_dups AS
+SELECT id AS dup_id, keep_id FROM (
+  SELECT id,
+         FIRST_VALUE(id) OVER (PARTITION BY tg_chat_id, tg_msg_id ORDER BY is_broken, created_at, id) AS keep_id
+  FROM files
+)
+WHERE id <> keep_id;
+
+-- У таблиц с одной строкой на файл запись оставшегося файла важнее:
+-- OR IGNORE не трогает конфликтующие строки, их убирает каскад при удалении.
+UPDATE OR IGNORE file_transcripts SET file_id = (SELECT keep_id FROM files_message_dups WHERE dup_id = file_id)
+WHERE file_id IN (SELECT dup_id FROM files_message_dups);
+UPDATE OR IGNORE notes SET file_id = (SELECT keep_id FROM files_message_dups WHERE dup_id = file_id)
+WHERE file_id IN (SELECT dup_id FROM files_message_dups);
+UPDATE OR IGNORE download_queue SET file_id = (SELECT keep_id FROM files_message_dups WHERE dup_id = file_id)
+WHERE file_id IN (SELECT dup_id FROM files_message_dups);
+UPDATE OR IGNORE partial_downloads SET file_id = (SELECT keep_id FROM files_message_dups WHERE dup_id = file_id)
+WHERE file_id IN (SELECT dup_id FROM files_message_dups);
+UPDATE OR IGNORE local_copies SET file_id = (SELECT keep_id FROM files_message_dups WHERE dup_id = file_id)
+WHERE file_id IN (SELECT dup_id FROM files_message_dups);
+UPDATE OR IGNORE local_cache SET file_id = (SELECT keep_id FROM files_message_dups WHERE dup_id = file_id)
+WHERE file_id IN (SELECT dup_id FROM files_message_dups);
+UPDATE OR IGNORE file_parts SET file_id = (SELECT keep_id FROM files_message_dups WHERE dup_id = file_id)
+WHERE file_id IN (SELECT dup_id FROM files_message_dups);
+-- Триггеры поиска следят только за текстом расшифровки, не за ее файлом.
+UPDATE file_search SET transcript = (SELECT text FROM file_transcripts t WHERE t.file_id = file_search.file_id)
+WHERE file_id IN (SELECT keep_id FROM files_message_dups) AND file_id IN (SELECT file_id FROM file_transcripts);
+UPDATE file_versions SET file_id = (SELECT keep_id FROM files_message_dups WHERE dup_id = file_id)
+WHERE file_id IN (SELECT dup_id FROM files_message_dups);
+UPDATE file_copies SET file_id = (SELECT keep_id FROM files_message_dups WHERE dup_id = file_id)
+WHERE file_id IN (SELECT dup_id FROM files_message_dups);
+UPDATE links SET target_id = (SELECT keep_id FROM files_message_dups WHERE dup_id = target_id)
+WHERE target_kind = 'file' AND target_id IN (SELECT dup_id FROM files_message_dups);
+
+DELETE FROM files WHERE id IN (SELECT dup_id FROM files_message_dups);
+DROP TABLE files_message_dups;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_files_message ON files(tg_chat_id, tg_msg_id);
+
+-- Списки папок выбираются по родителю и сортируются по имени: составные
+-- индексы отдают строки уже по порядку и заменяют одиночные.
+CREATE INDEX IF NOT EXISTS idx_files_dir_name ON files(dir_id, name);
+DROP INDEX IF EXISTS idx_files_dir;
+CREATE INDEX IF NOT EXISTS idx_directories_parent_name ON directories(parent_id, name);
+DROP INDEX IF EXISTS idx_directories_parent;
@@ -0,0 +1,15 @@
+-- Путь папки от корня (JSON-массив имен), чтобы не обходить родителей
+-- запросом на каждый уровень. NULL — путь еще не посчитан.
+ALTER TABLE directories ADD COLUMN path TEXT NULL;
+
+-- Как и для поиска, триггер только помечает папку: пути ее подпапок
+-- сбрасываются рекурсивным запросом перед следующим чтением.
+CREATE TABLE IF NOT EXISTS dir_paths_stale (
+  dir_id TEXT PRIMARY KEY NOT NULL
+);
+
+CREATE TRIGGER IF NOT EXISTS trg_directories_path_au AFTER UPDATE OF name, parent_id ON directories
+WHEN NEW.name IS NOT OLD.name OR NEW.parent_id IS NOT OLD.parent_id
+BEGIN
+  INSERT OR IGNORE INTO dir_paths_stale(dir_id) VALUES (NEW.id);
+END;
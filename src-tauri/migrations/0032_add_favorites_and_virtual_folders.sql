@@ -0,0 +1,10 @@
+ALTER TABLE files ADD COLUMN is_favorite INTEGER NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS idx_files_favorite ON files(is_favorite) WHERE is_favorite = 1;
+
+-- Умные папки: сохраненные фильтры поиска (JSON `SearchFilters`).
+CREATE TABLE IF NOT EXISTS virtual_folders (
+  id TEXT PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL,
+  query TEXT NOT NULL,
+  created_at INTEGER NOT NULL
+);
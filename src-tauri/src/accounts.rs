@@ -0,0 +1,136 @@
+use std::path::Path;
+
+use chrono::Utc;
+use ulid::Ulid;
+
+use crate::paths::Paths;
+
+/// Аккаунт, с которым приложение работало до появления нескольких аккаунтов.
+/// Его база, сессия TDLib и загрузки остаются в корне каталогов данных.
+pub const DEFAULT_ACCOUNT: &str = "default";
+const DEFAULT_LABEL: &str = "Основной";
+const MAX_LABEL_LEN: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AccountEntry {
+  pub id: String,
+  pub label: String,
+  pub created_at: i64
+}
+
+/// Список аккаунтов и выбранный из них. Хранится файлом рядом с базами:
+/// у каждого аккаунта своя база, и общей таблицы для списка нет.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AccountRegistry {
+  pub active: String,
+  pub accounts: Vec<AccountEntry>
+}
+
+impl Default for AccountRegistry {
+  fn default() -> Self {
+    Self {
+      active: DEFAULT_ACCOUNT.to_string(),
+      accounts: vec![AccountEntry { id: DEFAULT_ACCOUNT.to_string(), label: DEFAULT_LABEL.to_string(), created_at: 0 }]
+    }
+  }
+}
+
+impl AccountRegistry {
+  pub fn get(&self, id: &str) -> Option<&AccountEntry> {
+    self.accounts.iter().find(|a| a.id == id)
+  }
+
+  pub fn add(&mut self, label: &str) -> anyhow::Result<AccountEntry> {
+    let label = label.trim();
+    if label.is_empty() {
+      return Err(anyhow::anyhow!("Укажи название аккаунта"));
+    }
+    if label.chars().count() > MAX_LABEL_LEN {
+      return Err(anyhow::anyhow!("Название аккаунта длиннее {MAX_LABEL_LEN} символов"));
+    }
+    if self.accounts.iter().any(|a| a.label.to_lowercase() == label.to_lowercase()) {
+      return Err(anyhow::anyhow!("Аккаунт с таким названием уже есть"));
+    }
+    // id идет в имена каталогов, поэтому он не зависит от названия.
+    let entry = AccountEntry {
+      id: Ulid::new().to_string().to_lowercase(),
+      label: label.to_string(),
+      created_at: Utc::now().timestamp()
+    };
+    self.accounts.push(entry.clone());
+    Ok(entry)
+  }
+
+  /// Основной аккаунт есть всегда, а выбранный должен существовать.
+  fn normalized(mut self) -> AccountRegistry {
+    if self.get(DEFAULT_ACCOUNT).is_none() {
+      self.accounts.insert(0, AccountRegistry::default().accounts.remove(0));
+    }
+    if self.get(&self.active).is_none() {
+      self.active = DEFAULT_ACCOUNT.to_string();
+    }
+    self
+  }
+}
+
+pub fn load(paths: &Paths) -> anyhow::Result<AccountRegistry> {
+  let path = paths.accounts_path();
+  if !path.exists() {
+    return Ok(AccountRegistry::default());
+  }
+  let raw = std::fs::read(&path)?;
+  let registry: AccountRegistry = serde_json::from_slice(&raw)?;
+  Ok(registry.normalized())
+}
+
+pub fn save(paths: &Paths, registry: &AccountRegistry) -> anyhow::Result<()> {
+  write_atomic(&paths.accounts_path(), &serde_json::to_vec_pretty(registry)?)
+}
+
+fn write_atomic(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+  let tmp = path.with_extension("json.tmp");
+  std::fs::write(&tmp, data)?;
+  std::fs::rename(&tmp, path)?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::tempdir;
+
+  #[test]
+  fn registry_keeps_default_account_and_roundtrips() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let paths = Paths::from_base(tmp.path().to_path_buf());
+    let mut registry = load(&paths)?;
+    assert_eq!(registry, AccountRegistry::default());
+
+    let work = registry.add("  Работа ")?;
+    assert_eq!(work.label, "Работа");
+    assert!(registry.add("работа").is_err());
+    assert!(registry.add(" ").is_err());
+    registry.active = work.id.clone();
+    save(&paths, &registry)?;
+    assert_eq!(load(&paths)?, registry);
+
+    // Выбранный аккаунт пропал из файла — возвращаемся к основному.
+    let broken = AccountRegistry { active: "gone".into(), accounts: vec![work.clone()] };
+    save(&paths, &broken)?;
+    let loaded = load(&paths)?;
+    assert_eq!(loaded.active, DEFAULT_ACCOUNT);
+    assert_eq!(loaded.accounts.len(), 2);
+    assert_eq!(paths.for_account(&work.id).sqlite_path(), tmp.path().join("data").join("accounts").join(&work.id).join("cloudtg.sqlite"));
+    assert_eq!(
+      paths.for_account(DEFAULT_ACCOUNT).sqlite_path(),
+      tmp.path().join("data").join("accounts").join(DEFAULT_ACCOUNT).join("cloudtg.sqlite")
+    );
+    // База основного аккаунта из старой установки остается в корне.
+    std::fs::write(paths.sqlite_path(), b"")?;
+    assert_eq!(paths.for_account(DEFAULT_ACCOUNT).sqlite_path(), paths.sqlite_path());
+    Ok(())
+  }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+
+use crate::telegram::{ChatId, TelegramService};
+
+use super::{files, indexer, system_dirs};
+use super::system_dirs::SystemDir;
+
+/// Встроенные правила разбора «Неразобранного» по расширению: папка и расширения.
+/// Папки ищутся по имени в любом месте дерева и создаются в корне, если их нет.
+const SORT_RULES: &[(&str, &[&str])] = &[
+  ("Фото", &["jpg", "jpeg", "png", "gif", "webp", "heic", "heif", "bmp", "tif", "tiff"]),
+  ("Видео", &["mp4", "mkv", "mov", "avi", "webm", "m4v"]),
+  ("Музыка", &["mp3", "flac", "ogg", "oga", "m4a", "wav", "opus"]),
+  ("Документы", &["pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "odt", "ods", "rtf", "txt", "csv", "epub", "djvu"]),
+  ("Архивы", &["zip", "rar", "7z", "tar", "gz", "tgz", "bz2", "xz"]),
+  ("Приложения", &["apk", "xapk", "exe", "msi", "dmg", "pkg", "deb", "rpm", "appimage"])
+];
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AutoSortMove {
+  pub file_id: String,
+  pub name: String,
+  pub target_dir_name: String,
+  /// `None`, если папки еще нет и она будет создана.
+  pub target_dir_id: Option<String>
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct AutoSortReport {
+  pub dry_run: bool,
+  pub moves: Vec<AutoSortMove>,
+  /// Файлы, для которых не нашлось правила; остаются на месте.
+  pub unmatched: usize,
+  pub moved: usize,
+  pub failed: usize
+}
+
+pub fn target_for(name: &str) -> Option<&'static str> {
+  let ext = std::path::Path::new(name)
+    .extension()
+    .and_then(|e| e.to_str())?
+    .to_lowercase();
+  SORT_RULES
+    .iter()
+    .find(|(_, exts)| exts.contains(&ext.as_str()))
+    .map(|(dir, _)| *dir)
+}
+
+/// Раскладывает файлы из «Неразобранного» по типовым папкам. В режиме `dry_run`
+/// только возвращает план; иначе переносит файлы через `files::move_files`,
+/// то есть с правкой подписи в Telegram. Ошибка одного файла не останавливает остальные.
+pub async fn auto_sort_unassigned(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  storage_chat_id: ChatId,
+  dry_run: bool
+) -> anyhow::Result<AutoSortReport> {
+  let mut report = AutoSortReport { dry_run, ..Default::default() };
+  let Some(unassigned_id) = system_dirs::resolve_id(pool, SystemDir::Unassigned).await? else {
+    return Ok(report);
+  };
+  let rows = sqlx::query(
+    "SELECT f.id, f.name FROM files f
+     WHERE f.dir_id = ?
+     ORDER BY f.created_at, f.id"
+  )
+    .bind(unassigned_id)
+    .fetch_all(pool)
+    .await?;
+
+  let mut known_dirs: HashMap<String, Option<String>> = HashMap::new();
+  for row in rows {
+    let name: String = row.get("name");
+    let Some(target) = target_for(&name) else {
+      report.unmatched += 1;
+      continue;
+    };
+    if !known_dirs.contains_key(target) {
+      let found = indexer::find_dir_by_name(pool, target).await?.map(|(id, _)| id);
+      known_dirs.insert(target.to_string(), found);
+    }
+    report.moves.push(AutoSortMove {
+      file_id: row.get("id"),
+      name,
+      target_dir_name: target.to_string(),
+      target_dir_id: known_dirs[target].clone()
+    });
+  }
+  if dry_run {
+    return Ok(report);
+  }
+
+  let mut by_dir: Vec<(String, Vec<String>)> = Vec::new();
+  for item in report.moves.iter_mut() {
+    let dir_id = match known_dirs.get(&item.target_dir_name).cloned().flatten() {
+      Some(id) => id,
+      None => {
+        let (id, _) = indexer::ensure_dir_by_name(pool, tg, storage_chat_id, &item.target_dir_name).await?;
+        known_dirs.insert(item.target_dir_name.clone(), Some(id.clone()));
+        id
+      }
+    };
+    item.target_dir_id = Some(dir_id.clone());
+    match by_dir.iter_mut().find(|(id, _)| *id == dir_id) {
+      Some((_, ids)) => ids.push(item.file_id.clone()),
+      None => by_dir.push((dir_id, vec![item.file_id.clone()]))
+    }
+  }
+  for (dir_id, file_ids) in by_dir {
+    let outcome = files::move_files(pool, tg, storage_chat_id, &file_ids, &dir_id).await?;
+    report.moved += outcome.done.len();
+    report.failed += outcome.failed.len();
+    for (file_id, error) in outcome.failed {
+      tracing::warn!(event = "unassigned_auto_sort_move_failed", file_id = file_id.as_str(), error = error.as_str(), "Не удалось перенести файл при авторазборе");
+    }
+  }
+  tracing::info!(event = "unassigned_auto_sort_done", moved = report.moved, failed = report.failed, unmatched = report.unmatched, "Авторазбор «Неразобранного» завершен");
+  Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn target_follows_extension_case_insensitively() {
+    assert_eq!(target_for("IMG_0001.JPG"), Some("Фото"));
+    assert_eq!(target_for("contract.pdf"), Some("Документы"));
+    assert_eq!(target_for("app-release.apk"), Some("Приложения"));
+    assert_eq!(target_for("notes"), None);
+    assert_eq!(target_for("data.bin"), None);
+  }
+}
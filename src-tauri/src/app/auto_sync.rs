@@ -0,0 +1,284 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Manager};
+use tokio::sync::Notify;
+
+use crate::events;
+use crate::state::{AppState, AuthState};
+
+use super::{maintenance, reconcile};
+
+pub const DEFAULT_INTERVAL_SECS: u64 = 5 * 60;
+pub const MIN_INTERVAL_SECS: u64 = 30;
+pub const MAX_INTERVAL_SECS: u64 = 24 * 60 * 60;
+/// Первый повтор после ошибки; дальше пауза удваивается до `MAX_BACKOFF`.
+const RETRY_BASE: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+/// Как часто перепроверять, можно ли начать должный проход.
+const GATE_POLL: Duration = Duration::from_secs(5);
+/// Сколько последних сообщений сверяет реконсайл после каждого прохода.
+const RECONCILE_LIMIT: i64 = 100;
+
+static RUNTIME: Lazy<Mutex<Runtime>> = Lazy::new(|| Mutex::new(Runtime::default()));
+static WAKE: Lazy<Notify> = Lazy::new(Notify::new);
+/// Ручная и фоновая синхронизации не должны читать канал одновременно.
+static SYNC_LOCK: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
+
+/// Фоновая двусторонняя синхронизация: раз в `interval_secs` догоняет новые
+/// сообщения канала хранения и сверяет последние через реконсайл.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AutoSyncConfig {
+  #[serde(default)]
+  pub enabled: bool,
+  pub interval_secs: u64
+}
+
+impl Default for AutoSyncConfig {
+  fn default() -> Self {
+    Self { enabled: false, interval_secs: DEFAULT_INTERVAL_SECS }
+  }
+}
+
+impl AutoSyncConfig {
+  pub fn sanitized(self) -> AutoSyncConfig {
+    AutoSyncConfig {
+      enabled: self.enabled,
+      interval_secs: self.interval_secs.clamp(MIN_INTERVAL_SECS, MAX_INTERVAL_SECS)
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct AutoSyncStatus {
+  pub enabled: bool,
+  pub interval_secs: u64,
+  pub running: bool,
+  /// Ошибок подряд; после успешного прохода сбрасывается.
+  pub failures: u32,
+  pub last_run_at: Option<i64>,
+  pub last_success_at: Option<i64>,
+  pub last_error: Option<String>,
+  pub next_run_at: Option<i64>
+}
+
+#[derive(Default)]
+struct Runtime {
+  config: AutoSyncConfig,
+  running: bool,
+  failures: u32,
+  last_run_at: Option<i64>,
+  last_success_at: Option<i64>,
+  last_error: Option<String>,
+  next_run_at: Option<i64>
+}
+
+pub fn set_active(config: AutoSyncConfig) {
+  let config = config.sanitized();
+  {
+    let mut rt = RUNTIME.lock();
+    if !config.enabled {
+      rt.failures = 0;
+      rt.next_run_at = None;
+    }
+    rt.config = config;
+  }
+  WAKE.notify_one();
+}
+
+/// Забывает состояние прошлых проходов: при смене аккаунта оно относится к
+/// другой базе. Настройки загружаются заново через `set_active`.
+pub fn reset() {
+  *RUNTIME.lock() = Runtime::default();
+  WAKE.notify_one();
+}
+
+pub fn active() -> AutoSyncConfig {
+  RUNTIME.lock().config
+}
+
+pub fn status() -> AutoSyncStatus {
+  let rt = RUNTIME.lock();
+  AutoSyncStatus {
+    enabled: rt.config.enabled,
+    interval_secs: rt.config.interval_secs,
+    running: rt.running,
+    failures: rt.failures,
+    last_run_at: rt.last_run_at,
+    last_success_at: rt.last_success_at,
+    last_error: rt.last_error.clone(),
+    next_run_at: rt.next_run_at
+  }
+}
+
+pub async fn lock_sync() -> tokio::sync::MutexGuard<'static, ()> {
+  SYNC_LOCK.lock().await
+}
+
+/// Пауза перед следующим проходом: обычный интервал или растущая задержка
+/// после ошибок подряд.
+fn next_delay(config: AutoSyncConfig, failures: u32) -> Duration {
+  if failures == 0 {
+    return Duration::from_secs(config.interval_secs);
+  }
+  RETRY_BASE.saturating_mul(1u32 << (failures - 1).min(16)).min(MAX_BACKOFF)
+}
+
+/// Можно ли начать проход, когда подошло его время.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Gate {
+  Run,
+  /// Telegram еще не авторизован.
+  NotReady,
+  /// Фоновая работа сейчас запрещена окном обслуживания.
+  OutsideWindow
+}
+
+fn gate(auth: &AuthState, background_allowed: bool) -> Gate {
+  if *auth != AuthState::Ready {
+    Gate::NotReady
+  } else if !background_allowed {
+    Gate::OutsideWindow
+  } else {
+    Gate::Run
+  }
+}
+
+pub fn spawn_worker(app: AppHandle) -> JoinHandle<()> {
+  tauri::async_runtime::spawn(async move {
+    // Проход, время которого уже подошло, но начать его пока нельзя.
+    let mut due = false;
+    loop {
+      let config = active();
+      if !config.enabled {
+        due = false;
+        WAKE.notified().await;
+        continue;
+      }
+      if !due {
+        let delay = next_delay(config, RUNTIME.lock().failures);
+        RUNTIME.lock().next_run_at = Some(Utc::now().timestamp() + delay.as_secs() as i64);
+        // Изменение настроек будит цикл, и пауза считается заново.
+        if tokio::time::timeout(delay, WAKE.notified()).await.is_ok() {
+          continue;
+        }
+        due = true;
+      }
+      match gate(&app.state::<AppState>().auth_state(), maintenance::background_allowed()) {
+        Gate::Run => {
+          due = false;
+          run_once(&app).await;
+        }
+        Gate::NotReady | Gate::OutsideWindow => {
+          let _ = tokio::time::timeout(GATE_POLL, WAKE.notified()).await;
+        }
+      }
+    }
+  })
+}
+
+async fn run_once(app: &AppHandle) {
+  RUNTIME.lock().start(Utc::now().timestamp());
+  let res = sync_and_reconcile(app).await;
+  RUNTIME.lock().finish(res);
+}
+
+impl Runtime {
+  fn start(&mut self, now: i64) {
+    self.running = true;
+    self.next_run_at = None;
+    self.last_run_at = Some(now);
+  }
+
+  fn finish(&mut self, res: anyhow::Result<()>) {
+    self.running = false;
+    match res {
+      Ok(()) => {
+        self.failures = 0;
+        self.last_error = None;
+        self.last_success_at = self.last_run_at;
+      }
+      Err(e) => {
+        self.failures = self.failures.saturating_add(1);
+        tracing::warn!(event = "auto_sync_failed", failures = self.failures, error = %e, "Фоновая синхронизация не удалась");
+        self.last_error = Some(e.to_string());
+      }
+    }
+  }
+}
+
+async fn sync_and_reconcile(app: &AppHandle) -> anyhow::Result<()> {
+  let state = app.state::<AppState>();
+  // Смена аккаунта дождется конца прохода: база, клиент и канал хранения
+  // берутся у одного аккаунта.
+  let _session = state.session_guard().await;
+  let processed = crate::commands::run_storage_sync(app, &state, true).await.map_err(anyhow::Error::msg)?;
+  let db = state.db()?;
+  let pool = db.pool();
+  let tg = state.telegram()?;
+  let chat_id = crate::commands::ensure_storage_chat_id(&state).await?;
+  let outcome = {
+    let _guard = lock_sync().await;
+    reconcile::reconcile_recent(pool, tg.as_ref(), chat_id, RECONCILE_LIMIT).await?
+  };
+  let changed = outcome.imported + outcome.marked_dirs + outcome.marked_files + outcome.cleared_dirs + outcome.cleared_files;
+  if processed > 0 || changed > 0 {
+    state.invalidate_listings();
+    events::tree_updated(app);
+  }
+  tracing::debug!(
+    event = "auto_sync_done",
+    processed = processed,
+    reconciled = outcome.scanned,
+    changed = changed,
+    "Фоновая синхронизация завершена"
+  );
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn errors_back_off_exponentially() {
+    let config = AutoSyncConfig { enabled: true, interval_secs: 1 }.sanitized();
+    assert_eq!(config.interval_secs, MIN_INTERVAL_SECS);
+    assert_eq!(next_delay(config, 0), Duration::from_secs(MIN_INTERVAL_SECS));
+    assert_eq!(next_delay(config, 1), RETRY_BASE);
+    assert_eq!(next_delay(config, 3), RETRY_BASE * 4);
+    assert_eq!(next_delay(config, 40), MAX_BACKOFF);
+  }
+
+  #[test]
+  fn runs_only_when_authorized_and_inside_window() {
+    assert_eq!(gate(&AuthState::Ready, true), Gate::Run);
+    assert_eq!(gate(&AuthState::Ready, false), Gate::OutsideWindow);
+    assert_eq!(gate(&AuthState::WaitPhone, true), Gate::NotReady);
+    assert_eq!(gate(&AuthState::Closed, false), Gate::NotReady);
+  }
+
+  #[test]
+  fn run_results_update_failures_and_timestamps() {
+    let mut rt = Runtime { next_run_at: Some(50), ..Runtime::default() };
+    rt.start(100);
+    assert!(rt.running);
+    assert_eq!((rt.last_run_at, rt.next_run_at), (Some(100), None));
+
+    rt.finish(Err(anyhow::anyhow!("нет сети")));
+    rt.start(200);
+    rt.finish(Err(anyhow::anyhow!("нет сети")));
+    assert!(!rt.running);
+    assert_eq!(rt.failures, 2);
+    assert_eq!(rt.last_error.as_deref(), Some("нет сети"));
+    assert_eq!(rt.last_success_at, None);
+    assert_eq!(next_delay(rt.config.sanitized(), rt.failures), RETRY_BASE * 2);
+
+    rt.start(300);
+    rt.finish(Ok(()));
+    assert_eq!((rt.failures, rt.last_error.clone(), rt.last_success_at), (0, None, Some(300)));
+  }
+}
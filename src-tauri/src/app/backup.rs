@@ -1,15 +1,21 @@
 use std::path::{Path, PathBuf};
 
-use chrono::Utc;
-use crate::sqlx;
-use sqlx_sqlite::SqlitePool;
+use std::collections::HashSet;
+
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use crate::sqlx::{self, QueryBuilder, Row};
+use sqlx_sqlite::{SqliteConnectOptions, SqlitePool};
+use tauri::{AppHandle, Emitter};
 
 use crate::db::Db;
 use crate::paths::Paths;
 use crate::settings;
-use crate::telegram::{ChatId, TelegramService};
+use crate::fsmeta::{FileMeta, make_file_caption, mark_encrypted};
+use crate::telegram::{ChatId, MessageId, TelegramService};
 
 use super::{indexer, sync};
+use super::broken::{self, BrokenReason};
+use super::dirs::dir_exists;
 
 pub const BACKUP_TAG: &str = "#ocltg #backup #v1";
 
@@ -27,19 +33,208 @@ pub fn build_backup_caption(app_version: &str) -> String {
   format!("{BACKUP_TAG} ts={ts} app={app_version}")
 }
 
+/// Снимок базы для бэкапа. `VACUUM INTO` дает согласованную и сжатую копию
+/// даже при открытом WAL; перед отправкой снимок открывается отдельно и
+/// проверяется `PRAGMA integrity_check`, битый снимок удаляется.
 pub async fn create_backup_snapshot(pool: &SqlitePool, paths: &Paths) -> anyhow::Result<PathBuf> {
-  let dir = paths.backup_dir();
+  let dir = paths.layout().backup_dir();
   std::fs::create_dir_all(&dir)?;
   let ts = Utc::now().format("%Y%m%d-%H%M%S");
   let file_path = dir.join(format!("cloudtg-backup-{ts}.sqlite"));
+  if file_path.exists() {
+    std::fs::remove_file(&file_path)?;
+  }
 
   let escaped = escape_sqlite_path(&file_path);
   let sql = format!("VACUUM INTO '{}'", escaped);
   sqlx::query(&sql).execute(pool).await?;
 
+  if let Err(e) = verify_snapshot(&file_path).await {
+    let _ = std::fs::remove_file(&file_path);
+    return Err(e);
+  }
   Ok(file_path)
 }
 
+/// Открывает снимок только на чтение и прогоняет `PRAGMA integrity_check`.
+pub async fn verify_snapshot(path: &Path) -> anyhow::Result<()> {
+  let opts = SqliteConnectOptions::new().filename(path).read_only(true);
+  let snapshot = SqlitePool::connect_with(opts)
+    .await
+    .map_err(|e| anyhow::anyhow!("Снимок базы не открывается: {e}"))?;
+  let res = sqlx::query("PRAGMA integrity_check").fetch_all(&snapshot).await;
+  snapshot.close().await;
+  let problems: Vec<String> = res
+    .map_err(|e| anyhow::anyhow!("Снимок базы не прошел проверку целостности: {e}"))?
+    .into_iter()
+    .map(|r| r.get::<String,_>(0))
+    .filter(|line| line != "ok")
+    .collect();
+  if !problems.is_empty() {
+    tracing::error!(event = "backup_snapshot_corrupted", problems = problems.len(), first = problems[0].as_str(), "Снимок базы поврежден");
+    return Err(anyhow::anyhow!("Снимок базы поврежден: {}", problems[0]));
+  }
+  Ok(())
+}
+
+/// Проверяет скачанный бэкап перед тем, как подготовить его к восстановлению:
+/// целостность, наличие истории миграций и совместимость версии схемы.
+/// Более старая схема допустима — ее догонят миграции при запуске.
+pub async fn validate_restore_snapshot(path: &Path) -> anyhow::Result<i64> {
+  verify_snapshot(path).await?;
+  let opts = SqliteConnectOptions::new().filename(path).read_only(true);
+  let snapshot = SqlitePool::connect_with(opts).await?;
+  let version = sqlx::query("SELECT MAX(version) AS version FROM _sqlx_migrations WHERE success = 1")
+    .fetch_one(&snapshot)
+    .await
+    .ok()
+    .and_then(|r| r.try_get::<i64,_>("version").ok());
+  let has_files = sqlx::query("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'files'")
+    .fetch_optional(&snapshot)
+    .await
+    .map(|r| r.is_some())
+    .unwrap_or(false);
+  snapshot.close().await;
+
+  let Some(version) = version.filter(|_| has_files) else {
+    return Err(anyhow::anyhow!("Файл бэкапа не похож на базу CloudTG"));
+  };
+  if let Some(latest) = crate::db::latest_schema_version() {
+    if version > latest {
+      return Err(anyhow::anyhow!(
+        "Бэкап создан более новой версией приложения (схема {version}, поддерживается до {latest}). Обнови CloudTG"
+      ));
+    }
+  }
+  Ok(version)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestoreStage {
+  Searching,
+  Downloading,
+  Rebuilding,
+  Validating,
+  Pending,
+  Failed
+}
+
+/// Событие `backup_restore_progress`: этап подготовки восстановления.
+/// Подмена базы происходит при следующем запуске.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RestoreProgress {
+  pub stage: RestoreStage,
+  pub percent: Option<u8>,
+  pub message: String
+}
+
+pub fn emit_restore_progress(app: &AppHandle, stage: RestoreStage, percent: Option<u8>, message: impl Into<String>) {
+  let _ = app.emit("backup_restore_progress", RestoreProgress { stage, percent, message: message.into() });
+}
+
+/// Политика хранения бэкапов в канале: последний бэкап каждого из `keep_daily`
+/// последних дней и каждой из `keep_weekly` последних недель (по UTC).
+/// Самый свежий бэкап не удаляется никогда; 0/0 отключает очистку.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BackupRetention {
+  pub keep_daily: u32,
+  pub keep_weekly: u32
+}
+
+impl Default for BackupRetention {
+  fn default() -> Self {
+    Self { keep_daily: 7, keep_weekly: 4 }
+  }
+}
+
+impl BackupRetention {
+  pub fn is_disabled(&self) -> bool {
+    self.keep_daily == 0 && self.keep_weekly == 0
+  }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct RetentionPlan {
+  pub keep: Vec<MessageId>,
+  pub delete: Vec<MessageId>,
+  /// Ошибка удаления; сам бэкап при этом уже создан.
+  pub error: Option<String>
+}
+
+/// Раскладывает бэкапы `(message_id, date)` на оставляемые и удаляемые.
+pub fn plan_retention(backups: &[(MessageId, i64)], retention: BackupRetention) -> RetentionPlan {
+  let mut sorted = backups.to_vec();
+  sorted.sort_by(|a, b| b.1.cmp(&a.1).then(b.0.cmp(&a.0)));
+  let mut plan = RetentionPlan::default();
+  if retention.is_disabled() {
+    plan.keep = sorted.into_iter().map(|(id, _)| id).collect();
+    return plan;
+  }
+
+  let mut days: HashSet<NaiveDate> = HashSet::new();
+  let mut weeks: HashSet<(i32, u32)> = HashSet::new();
+  for (i, (id, date)) in sorted.into_iter().enumerate() {
+    let day = DateTime::from_timestamp(date, 0).unwrap_or_default().date_naive();
+    let week = (day.iso_week().year(), day.iso_week().week());
+    let mut keep = i == 0;
+    if !days.contains(&day) && days.len() < retention.keep_daily as usize {
+      days.insert(day);
+      keep = true;
+    }
+    if !weeks.contains(&week) && weeks.len() < retention.keep_weekly as usize {
+      weeks.insert(week);
+      keep = true;
+    }
+    if keep {
+      plan.keep.push(id);
+    } else {
+      plan.delete.push(id);
+    }
+  }
+  plan
+}
+
+/// Все бэкапы в канале в виде `(message_id, date)`.
+pub async fn list_backups(tg: &dyn TelegramService, backup_chat_id: ChatId) -> anyhow::Result<Vec<(MessageId, i64)>> {
+  let mut out = Vec::new();
+  let mut seen: HashSet<MessageId> = HashSet::new();
+  let mut from_message_id: MessageId = 0;
+  loop {
+    let page = tg
+      .search_chat_messages(backup_chat_id, BACKUP_TAG.to_string(), from_message_id, 100)
+      .await?;
+    if page.messages.is_empty() {
+      break;
+    }
+    for msg in page.messages {
+      if seen.insert(msg.id) {
+        out.push((msg.id, msg.date));
+      }
+    }
+    if page.next_from_message_id == 0 || page.next_from_message_id == from_message_id {
+      break;
+    }
+    from_message_id = page.next_from_message_id;
+  }
+  Ok(out)
+}
+
+/// Удаляет из канала бэкапы, вытесненные более свежими по политике хранения.
+pub async fn prune_backups(
+  tg: &dyn TelegramService,
+  backup_chat_id: ChatId,
+  retention: BackupRetention
+) -> anyhow::Result<RetentionPlan> {
+  let backups = list_backups(tg, backup_chat_id).await?;
+  let plan = plan_retention(&backups, retention);
+  if !plan.delete.is_empty() {
+    tg.delete_messages(backup_chat_id, plan.delete.clone(), true).await?;
+    tracing::info!(event = "backup_pruned", deleted = plan.delete.len(), kept = plan.keep.len(), "Старые бэкапы удалены");
+  }
+  Ok(plan)
+}
+
 pub async fn rebuild_storage_to_path(
   target_path: &Path,
   tg: &dyn TelegramService,
@@ -107,6 +302,195 @@ pub async fn rebuild_storage_to_path(
   Ok(stats)
 }
 
+/// Какие файлы достать из снимка: по id, по части имени и/или из папки снимка.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupFileSelector {
+  pub file_ids: Option<Vec<String>>,
+  pub name: Option<String>,
+  pub dir_id: Option<String>
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct ExtractStats {
+  pub restored: i64,
+  pub skipped: i64,
+  pub broken: i64,
+  pub file_ids: Vec<String>
+}
+
+/// Переносит выбранные строки `files` из снимка `snapshot` в живую базу,
+/// сохраняя ссылки на исходные сообщения. Если указана `target_dir_id`,
+/// файлы кладутся туда, иначе — в исходную папку (если она еще существует).
+pub async fn extract_files_from_snapshot(
+  pool: &SqlitePool,
+  snapshot: &SqlitePool,
+  tg: &dyn TelegramService,
+  storage_chat_id: ChatId,
+  selector: &BackupFileSelector,
+  target_dir_id: Option<&str>
+) -> anyhow::Result<ExtractStats> {
+  let file_ids = selector.file_ids.clone().unwrap_or_default();
+  let name = selector.name.as_deref().map(str::trim).filter(|v| !v.is_empty());
+  let snapshot_dir = selector.dir_id.as_deref().filter(|v| !v.trim().is_empty());
+  if file_ids.is_empty() && name.is_none() && snapshot_dir.is_none() {
+    return Err(anyhow::anyhow!("Не выбраны файлы для восстановления"));
+  }
+  let target_dir_id = target_dir_id.filter(|v| !v.trim().is_empty() && *v != "ROOT");
+  if let Some(dir_id) = target_dir_id {
+    if !dir_exists(pool, dir_id).await? {
+      return Err(anyhow::anyhow!("Папка не найдена"));
+    }
+  }
+
+  let mut builder = QueryBuilder::new(
+    // Снимки старых версий не знают новых колонок, поэтому берем все, что есть.
+    "SELECT * FROM files WHERE 1=1"
+  );
+  if !file_ids.is_empty() {
+    builder.push(" AND id IN (");
+    let mut separated = builder.separated(", ");
+    for id in &file_ids {
+      separated.push_bind(id);
+    }
+    separated.push_unseparated(")");
+  }
+  if let Some(name) = name {
+    builder.push(" AND lower(name) LIKE ").push_bind(format!("%{}%", name.to_lowercase()));
+  }
+  if let Some(dir_id) = snapshot_dir {
+    builder.push(" AND dir_id = ").push_bind(dir_id);
+  }
+  builder.push(" ORDER BY name LIMIT 1000");
+  let rows = builder.build().fetch_all(snapshot).await?;
+
+  let mut stats = ExtractStats::default();
+  for row in rows {
+    let id: String = row.get("id");
+    let original_dir: String = row.get("dir_id");
+    let name: String = row.get("name");
+    let hash: String = row.get("hash");
+    let chat_id: i64 = row.get("tg_chat_id");
+    let msg_id: i64 = row.get("tg_msg_id");
+    let enc_key_id = row.try_get::<String,_>("enc_key_id").ok();
+
+    let exists = sqlx::query("SELECT 1 FROM files WHERE id = ? OR (tg_chat_id = ? AND tg_msg_id = ?)")
+      .bind(&id)
+      .bind(chat_id)
+      .bind(msg_id)
+      .fetch_optional(pool)
+      .await?
+      .is_some();
+    if exists {
+      stats.skipped += 1;
+      continue;
+    }
+    let dir_id = match target_dir_id {
+      Some(dir_id) => dir_id.to_string(),
+      None if dir_exists(pool, &original_dir).await? => original_dir.clone(),
+      None => {
+        tracing::info!(event = "backup_extract_dir_missing", file_id = id.as_str(), "Исходной папки больше нет, файл пропущен");
+        stats.skipped += 1;
+        continue;
+      }
+    };
+
+    let message_alive = tg.message_exists(chat_id, msg_id).await.unwrap_or(false);
+    sqlx::query(
+      "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, is_broken, enc_key_id)
+       VALUES(?, ?, ?, ?, ?, ?, ?, ?, 0, ?)"
+    )
+      .bind(&id)
+      .bind(&dir_id)
+      .bind(&name)
+      .bind(row.get::<i64,_>("size"))
+      .bind(&hash)
+      .bind(chat_id)
+      .bind(msg_id)
+      .bind(row.get::<i64,_>("created_at"))
+      .bind(enc_key_id.as_deref())
+      .execute(pool)
+      .await?;
+
+    if !message_alive {
+      let reason = if chat_id == storage_chat_id { BrokenReason::MessageDeleted } else { BrokenReason::ChannelMigrated };
+      broken::mark_file_broken(pool, &id, reason).await?;
+      stats.broken += 1;
+    } else if dir_id != original_dir && chat_id == storage_chat_id {
+      // Иначе следующая синхронизация по подписи вернет файл в старую папку.
+      let mut caption = make_file_caption(&FileMeta { dir_id: dir_id.clone(), file_id: id.clone(), name, hash_short: hash });
+      if let Some(key_id) = enc_key_id.as_deref() {
+        caption = mark_encrypted(&caption, key_id);
+      }
+      if let Err(e) = tg.edit_message_caption(chat_id, msg_id, caption).await {
+        tracing::warn!(event = "backup_extract_caption_failed", file_id = id.as_str(), error = %e, "Не удалось обновить подпись восстановленного файла");
+      }
+    }
+    stats.restored += 1;
+    stats.file_ids.push(id);
+  }
+
+  Ok(stats)
+}
+
 fn escape_sqlite_path(path: &Path) -> String {
   path.to_string_lossy().replace('\'', "''")
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::tempdir;
+
+  #[test]
+  fn retention_keeps_latest_per_day_and_week() {
+    let day = 24 * 3600;
+    // 2024-01-01 (понедельник) 12:00 UTC.
+    let base = 1_704_110_400;
+    let backups: Vec<(MessageId, i64)> = (0..21).map(|i| (i + 1, base + i * day)).chain([(100, base + 20 * day - 60)]).collect();
+
+    let plan = plan_retention(&backups, BackupRetention { keep_daily: 3, keep_weekly: 2 });
+    // Дни 21, 20, 19 и неделя 15.01–21.01 (уже покрыта), плюс последний бэкап недели 08.01–14.01.
+    assert_eq!(plan.keep, vec![21, 20, 19, 14]);
+    assert!(plan.delete.contains(&100));
+    assert_eq!(plan.keep.len() + plan.delete.len(), backups.len());
+
+    let off = plan_retention(&backups, BackupRetention { keep_daily: 0, keep_weekly: 0 });
+    assert!(off.delete.is_empty());
+  }
+
+  #[tokio::test]
+  async fn snapshot_is_verified_before_use() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("live.sqlite")).await?;
+    db.migrate().await?;
+    let paths = Paths {
+      base_dir: tmp.path().to_path_buf(),
+      data_dir: tmp.path().join("data"),
+      cache_dir: tmp.path().join("cache"),
+      logs_dir: tmp.path().join("logs"),
+      resource_dir: None,
+      portable: false
+    };
+
+    let snapshot = create_backup_snapshot(db.pool(), &paths).await?;
+    assert!(snapshot.exists());
+    verify_snapshot(&snapshot).await?;
+    assert_eq!(validate_restore_snapshot(&snapshot).await?, crate::db::latest_schema_version().unwrap());
+
+    let garbage = tmp.path().join("garbage.sqlite");
+    std::fs::write(&garbage, vec![0x5a; 8192])?;
+    assert!(verify_snapshot(&garbage).await.is_err());
+
+    // Бэкап от более новой версии приложения не принимается.
+    sqlx::query(
+      "INSERT INTO _sqlx_migrations(version, description, installed_on, success, checksum, execution_time)
+       VALUES(99999999, 'future', CURRENT_TIMESTAMP, 1, X'00', 0)"
+    )
+      .execute(db.pool())
+      .await?;
+    let future = create_backup_snapshot(db.pool(), &paths).await?;
+    assert!(validate_restore_snapshot(&future).await.is_err());
+    Ok(())
+  }
+}
@@ -0,0 +1,203 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use chrono::Utc;
+use sqlx_sqlite::SqlitePool;
+
+use crate::app::{backup, sync};
+use crate::paths::Paths;
+use crate::secrets;
+use crate::telegram::{ChatId, MessageId, TelegramService};
+
+/// Бутстрап второго устройства: небольшой зашифрованный паролем блоб в
+/// «Избранном» с идентификаторами каналов, переносимыми настройками и
+/// указателем на последний бэкап. Новое устройство скачивает его, пишет
+/// идентификаторы в свою базу и восстанавливает бэкап вместо полной
+/// пересинхронизации истории.
+pub const BOOTSTRAP_TAG: &str = "#cloudtg_bootstrap";
+pub const BACKUP_LAST_MESSAGE_KEY: &str = "backup_last_message_id";
+const BOOTSTRAP_VERSION: u8 = 1;
+const BOOTSTRAP_FILE_NAME: &str = "cloudtg-bootstrap.bin";
+
+/// Настройки, которые имеют смысл на другом устройстве. Пути к TDLib,
+/// команда антивируса, согласие на диагностику и статус-страница остаются
+/// локальными.
+const PORTABLE_SETTINGS: &[&str] = &[
+  "search_index_enabled",
+  "vault_summary_enabled",
+  "read_only_channel",
+  "vault_encryption",
+  "vault_key",
+  "tdlib_timeout_preset",
+  "tdlib_timeout_custom",
+  "indexer_ignore_list",
+  "transcription_mode",
+  "maintenance_window",
+  "auto_sync",
+  "open_guard",
+  "file_actions",
+  "backup_retention"
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BootstrapData {
+  pub v: u8,
+  pub created_at: i64,
+  pub app_version: String,
+  pub storage_chat_id: ChatId,
+  #[serde(default)]
+  pub backup_chat_id: Option<ChatId>,
+  #[serde(default)]
+  pub last_backup_message_id: Option<MessageId>,
+  #[serde(default)]
+  pub settings: BTreeMap<String, String>
+}
+
+pub async fn collect(pool: &SqlitePool, storage_chat_id: ChatId, tg: &dyn TelegramService) -> anyhow::Result<BootstrapData> {
+  let backup_chat_id = sync::get_sync(pool, "backup_chat_id").await?.and_then(|v| v.parse::<ChatId>().ok());
+  let mut last_backup_message_id = sync::get_sync(pool, BACKUP_LAST_MESSAGE_KEY)
+    .await?
+    .and_then(|v| v.parse::<MessageId>().ok());
+  if last_backup_message_id.is_none() {
+    if let Some(chat_id) = backup_chat_id {
+      last_backup_message_id = backup::list_backups(tg, chat_id).await?.first().map(|(id, _)| *id);
+    }
+  }
+  let mut settings = BTreeMap::new();
+  for key in PORTABLE_SETTINGS {
+    if let Some(value) = sync::get_sync(pool, key).await? {
+      settings.insert(key.to_string(), value);
+    }
+  }
+  Ok(BootstrapData {
+    v: BOOTSTRAP_VERSION,
+    created_at: Utc::now().timestamp(),
+    app_version: env!("CARGO_PKG_VERSION").to_string(),
+    storage_chat_id,
+    backup_chat_id,
+    last_backup_message_id,
+    settings
+  })
+}
+
+/// Пишет идентификаторы каналов и переносимые настройки в текущую базу.
+pub async fn apply(pool: &SqlitePool, data: &BootstrapData) -> anyhow::Result<()> {
+  sync::set_sync(pool, "storage_chat_id", &data.storage_chat_id.to_string()).await?;
+  if let Some(chat_id) = data.backup_chat_id {
+    sync::set_sync(pool, "backup_chat_id", &chat_id.to_string()).await?;
+  }
+  if let Some(message_id) = data.last_backup_message_id {
+    sync::set_sync(pool, BACKUP_LAST_MESSAGE_KEY, &message_id.to_string()).await?;
+  }
+  for (key, value) in &data.settings {
+    if PORTABLE_SETTINGS.contains(&key.as_str()) {
+      sync::set_sync(pool, key, value).await?;
+    }
+  }
+  Ok(())
+}
+
+pub fn seal(data: &BootstrapData, password: &str) -> anyhow::Result<Vec<u8>> {
+  secrets::seal_blob(&serde_json::to_vec(data)?, password)
+}
+
+pub fn open(raw: &[u8], password: &str) -> anyhow::Result<BootstrapData> {
+  let plain = secrets::open_blob(raw, password)?;
+  let data: BootstrapData = serde_json::from_slice(&plain)
+    .map_err(|e| anyhow::anyhow!("Некорректные данные бутстрапа: {e}"))?;
+  if data.v != BOOTSTRAP_VERSION {
+    return Err(anyhow::anyhow!("Неподдерживаемая версия бутстрапа: {}", data.v));
+  }
+  Ok(data)
+}
+
+/// Загружает блоб в «Избранное» и удаляет прежние копии.
+pub async fn upload(tg: &dyn TelegramService, paths: &Paths, sealed: &[u8]) -> anyhow::Result<MessageId> {
+  let saved_chat_id = tg.saved_messages_chat().await?;
+  let previous = find_messages(tg, saved_chat_id).await?;
+  let file = work_path(paths)?;
+  std::fs::write(&file, sealed)?;
+  let caption = format!("{BOOTSTRAP_TAG} v{BOOTSTRAP_VERSION} {}", Utc::now().format("%Y-%m-%d %H:%M UTC"));
+  let res = tg.send_file(saved_chat_id, file.clone(), caption).await;
+  let _ = std::fs::remove_file(&file);
+  let uploaded = res?;
+  if !previous.is_empty() {
+    if let Err(e) = tg.delete_messages(saved_chat_id, previous, true).await {
+      tracing::warn!(event = "bootstrap_cleanup_failed", error = %e, "Не удалось удалить старые копии бутстрапа");
+    }
+  }
+  Ok(uploaded.message_id)
+}
+
+/// Скачивает последний блоб из «Избранного».
+pub async fn download(tg: &dyn TelegramService, paths: &Paths) -> anyhow::Result<Vec<u8>> {
+  let saved_chat_id = tg.saved_messages_chat().await?;
+  let Some(message_id) = find_messages(tg, saved_chat_id).await?.first().copied() else {
+    return Err(anyhow::anyhow!("В «Избранном» нет данных для подключения устройства. Сначала выполни экспорт на первом устройстве."));
+  };
+  let file = work_path(paths)?;
+  let _ = std::fs::remove_file(&file);
+  let downloaded = tg.download_message_file(saved_chat_id, message_id, file).await?;
+  let raw = std::fs::read(&downloaded);
+  let _ = std::fs::remove_file(&downloaded);
+  Ok(raw?)
+}
+
+async fn find_messages(tg: &dyn TelegramService, saved_chat_id: ChatId) -> anyhow::Result<Vec<MessageId>> {
+  let res = tg.search_chat_messages(saved_chat_id, BOOTSTRAP_TAG.to_string(), 0, 20).await?;
+  Ok(
+    res
+      .messages
+      .into_iter()
+      .filter(|m| m.caption.as_deref().unwrap_or("").starts_with(BOOTSTRAP_TAG))
+      .map(|m| m.id)
+      .collect()
+  )
+}
+
+fn work_path(paths: &Paths) -> anyhow::Result<PathBuf> {
+  let dir = paths.layout().backup_dir();
+  std::fs::create_dir_all(&dir)?;
+  Ok(dir.join(BOOTSTRAP_FILE_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[tokio::test]
+  async fn sealed_bootstrap_restores_ids_and_portable_settings() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let target = Db::connect(tmp.path().join("target.sqlite")).await?;
+    target.migrate().await?;
+
+    let mut settings = BTreeMap::new();
+    settings.insert("maintenance_window".to_string(), "{\"enabled\":true,\"start_minute\":60,\"end_minute\":360}".to_string());
+    settings.insert("tdlib_path".to_string(), "/other/device/libtdjson.so".to_string());
+    let data = BootstrapData {
+      v: BOOTSTRAP_VERSION,
+      created_at: 1,
+      app_version: "test".into(),
+      storage_chat_id: -100123,
+      backup_chat_id: Some(-100456),
+      last_backup_message_id: Some(42),
+      settings
+    };
+
+    let sealed = seal(&data, "bootstrap-pass")?;
+    assert!(open(&sealed, "wrong-pass").is_err());
+    let opened = open(&sealed, "bootstrap-pass")?;
+    assert_eq!(opened, data);
+
+    apply(target.pool(), &opened).await?;
+    let pool = target.pool();
+    assert_eq!(sync::get_sync(pool, "storage_chat_id").await?.as_deref(), Some("-100123"));
+    assert_eq!(sync::get_sync(pool, "backup_chat_id").await?.as_deref(), Some("-100456"));
+    assert_eq!(sync::get_sync(pool, BACKUP_LAST_MESSAGE_KEY).await?.as_deref(), Some("42"));
+    assert!(sync::get_sync(pool, "maintenance_window").await?.is_some());
+    assert!(sync::get_sync(pool, "tdlib_path").await?.is_none());
+    Ok(())
+  }
+}
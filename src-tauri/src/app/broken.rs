@@ -0,0 +1,232 @@
+use chrono::Utc;
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrokenReason {
+  MessageDeleted,
+  CaptionUnparsable,
+  ChannelMigrated,
+  ChecksumMismatch
+}
+
+impl BrokenReason {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      BrokenReason::MessageDeleted => "message_deleted",
+      BrokenReason::CaptionUnparsable => "caption_unparsable",
+      BrokenReason::ChannelMigrated => "channel_migrated",
+      BrokenReason::ChecksumMismatch => "checksum_mismatch"
+    }
+  }
+
+  pub fn parse(raw: &str) -> Option<Self> {
+    match raw {
+      "message_deleted" => Some(BrokenReason::MessageDeleted),
+      "caption_unparsable" => Some(BrokenReason::CaptionUnparsable),
+      "channel_migrated" => Some(BrokenReason::ChannelMigrated),
+      "checksum_mismatch" => Some(BrokenReason::ChecksumMismatch),
+      _ => None
+    }
+  }
+}
+
+const UNKNOWN_REASON: &str = "unknown";
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BrokenItem {
+  pub kind: String,
+  pub id: String,
+  pub name: String,
+  pub dir_id: Option<String>,
+  pub broken_since: Option<i64>
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BrokenGroup {
+  pub reason: String,
+  pub title: String,
+  pub severity: String,
+  pub action: String,
+  pub items: Vec<BrokenItem>
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BrokenReport {
+  pub total: i64,
+  pub groups: Vec<BrokenGroup>
+}
+
+pub async fn mark_file_broken(pool: &SqlitePool, file_id: &str, reason: BrokenReason) -> anyhow::Result<()> {
+  sqlx::query(
+    "UPDATE files SET is_broken = 1, broken_reason = ?, broken_since = COALESCE(broken_since, ?) WHERE id = ?"
+  )
+    .bind(reason.as_str())
+    .bind(Utc::now().timestamp())
+    .bind(file_id)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+pub async fn mark_dir_broken(pool: &SqlitePool, dir_id: &str, reason: BrokenReason) -> anyhow::Result<()> {
+  sqlx::query(
+    "UPDATE directories SET is_broken = 1, broken_reason = ?, broken_since = COALESCE(broken_since, ?) WHERE id = ?"
+  )
+    .bind(reason.as_str())
+    .bind(Utc::now().timestamp())
+    .bind(dir_id)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+pub async fn broken_report(pool: &SqlitePool) -> anyhow::Result<BrokenReport> {
+  let mut groups: Vec<BrokenGroup> = Vec::new();
+  let mut total = 0i64;
+
+  let dir_rows = sqlx::query(
+    "SELECT id, parent_id, name, broken_reason, broken_since FROM directories WHERE is_broken != 0 ORDER BY name"
+  )
+    .fetch_all(pool)
+    .await?;
+  for row in dir_rows {
+    let reason = row.try_get::<String,_>("broken_reason").ok();
+    push_item(&mut groups, reason.as_deref(), BrokenItem {
+      kind: "dir".to_string(),
+      id: row.get::<String,_>("id"),
+      name: row.get::<String,_>("name"),
+      dir_id: row.try_get::<String,_>("parent_id").ok(),
+      broken_since: row.try_get::<i64,_>("broken_since").ok()
+    });
+    total += 1;
+  }
+
+  let file_rows = sqlx::query(
+    "SELECT id, dir_id, name, broken_reason, broken_since FROM files WHERE is_broken != 0 ORDER BY name"
+  )
+    .fetch_all(pool)
+    .await?;
+  for row in file_rows {
+    let reason = row.try_get::<String,_>("broken_reason").ok();
+    push_item(&mut groups, reason.as_deref(), BrokenItem {
+      kind: "file".to_string(),
+      id: row.get::<String,_>("id"),
+      name: row.get::<String,_>("name"),
+      dir_id: Some(row.get::<String,_>("dir_id")),
+      broken_since: row.try_get::<i64,_>("broken_since").ok()
+    });
+    total += 1;
+  }
+
+  groups.sort_by_key(|g| severity_rank(&g.severity));
+  Ok(BrokenReport { total, groups })
+}
+
+fn push_item(groups: &mut Vec<BrokenGroup>, reason: Option<&str>, item: BrokenItem) {
+  let reason = reason
+    .and_then(BrokenReason::parse)
+    .map(|r| r.as_str())
+    .unwrap_or(UNKNOWN_REASON);
+  if let Some(group) = groups.iter_mut().find(|g| g.reason == reason) {
+    group.items.push(item);
+    return;
+  }
+  let (title, severity, action) = describe_reason(reason);
+  groups.push(BrokenGroup {
+    reason: reason.to_string(),
+    title: title.to_string(),
+    severity: severity.to_string(),
+    action: action.to_string(),
+    items: vec![item]
+  });
+}
+
+fn describe_reason(reason: &str) -> (&'static str, &'static str, &'static str) {
+  match BrokenReason::parse(reason) {
+    Some(BrokenReason::MessageDeleted) => (
+      "Сообщение удалено из канала",
+      "critical",
+      "Нажми «Восстановить» и выбери локальный файл для переотправки."
+    ),
+    Some(BrokenReason::ChecksumMismatch) => (
+      "Содержимое не совпадает с контрольной суммой",
+      "critical",
+      "Переотправь файл из надежной локальной копии."
+    ),
+    Some(BrokenReason::ChannelMigrated) => (
+      "Сообщение осталось в старом канале",
+      "warning",
+      "Нажми «Восстановить», чтобы найти или переотправить файл в текущий канал."
+    ),
+    Some(BrokenReason::CaptionUnparsable) => (
+      "Подпись сообщения повреждена",
+      "warning",
+      "Нажми «Восстановить», чтобы записать подпись заново."
+    ),
+    None => (
+      "Причина неизвестна",
+      "info",
+      "Запусти синхронизацию или проверку канала, затем попробуй «Восстановить»."
+    )
+  }
+}
+
+fn severity_rank(severity: &str) -> u8 {
+  match severity {
+    "critical" => 0,
+    "warning" => 1,
+    _ => 2
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::tempdir;
+  use crate::db::Db;
+
+  #[tokio::test]
+  async fn report_groups_by_reason_and_clears_on_repair() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d1', NULL, 'Документы', 1, 0)")
+      .execute(pool)
+      .await?;
+    for (id, msg_id) in [("f1", 10), ("f2", 11), ("f3", 12)] {
+      sqlx::query(
+        "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, is_broken)
+         VALUES(?, 'd1', ?, 0, 'deadbeef', -100, ?, 0, 0)"
+      )
+        .bind(id)
+        .bind(format!("{id}.txt"))
+        .bind(msg_id)
+        .execute(pool)
+        .await?;
+    }
+    mark_file_broken(pool, "f1", BrokenReason::MessageDeleted).await?;
+    mark_file_broken(pool, "f2", BrokenReason::CaptionUnparsable).await?;
+    sqlx::query("UPDATE files SET is_broken = 1 WHERE id = 'f3'")
+      .execute(pool)
+      .await?;
+
+    let report = broken_report(pool).await?;
+    assert_eq!(report.total, 3);
+    let reasons: Vec<&str> = report.groups.iter().map(|g| g.reason.as_str()).collect();
+    assert_eq!(reasons, vec!["message_deleted", "caption_unparsable", "unknown"]);
+    assert!(report.groups[2].items[0].broken_since.is_some());
+
+    sqlx::query("UPDATE files SET is_broken = 0 WHERE id = 'f1'")
+      .execute(pool)
+      .await?;
+    let row = sqlx::query("SELECT broken_reason, broken_since FROM files WHERE id = 'f1'")
+      .fetch_one(pool)
+      .await?;
+    assert!(row.try_get::<String,_>("broken_reason").is_err());
+    assert!(row.try_get::<i64,_>("broken_since").is_err());
+    Ok(())
+  }
+}
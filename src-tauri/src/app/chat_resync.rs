@@ -0,0 +1,162 @@
+use sqlx_sqlite::SqlitePool;
+
+use crate::app::sync;
+use crate::sqlx;
+use crate::telegram::{ChatId, ChatRef, TelegramService};
+
+/// После потери базы TDLib сохраненный chat_id может не открываться, пока
+/// чат не «увиден» заново. Поэтому рядом с storage_chat_id храним устойчивые
+/// признаки канала (supergroup_id, ссылку-приглашение), а при сбое проверки
+/// прогреваем списки чатов, находим канал по ним и переписываем ссылки в БД.
+const STORAGE_REF_KEY: &str = "storage_chat_ref";
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct StoredRef {
+  chat_id: ChatId,
+  #[serde(flatten)]
+  chat_ref: ChatRef
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ResyncResult {
+  pub old_chat_id: ChatId,
+  pub new_chat_id: ChatId,
+  pub files_updated: u64,
+  pub versions_updated: u64
+}
+
+/// Запоминает признаки канала, если для него их еще нет.
+pub async fn remember_storage_ref(pool: &SqlitePool, tg: &dyn TelegramService, chat_id: ChatId) -> anyhow::Result<()> {
+  if load_ref(pool).await?.is_some_and(|stored| stored.chat_id == chat_id) {
+    return Ok(());
+  }
+  let chat_ref = tg.storage_chat_ref(chat_id).await?;
+  if chat_ref == ChatRef::default() {
+    return Ok(());
+  }
+  let stored = StoredRef { chat_id, chat_ref };
+  sync::set_sync(pool, STORAGE_REF_KEY, &serde_json::to_string(&stored)?).await?;
+  Ok(())
+}
+
+/// Ищет канал хранения после прогрева. `Some` — канал найден (возможно, под
+/// тем же chat_id); ссылки в БД уже переписаны.
+pub async fn resync_storage_chat(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  stale_chat_id: ChatId
+) -> anyhow::Result<Option<ResyncResult>> {
+  let mut chat_ref = load_ref(pool).await?.map(|stored| stored.chat_ref).unwrap_or_default();
+  if chat_ref.supergroup_id.is_none() {
+    chat_ref.supergroup_id = supergroup_id_from_chat_id(stale_chat_id);
+  }
+  let Some(new_chat_id) = tg.storage_resolve_chat_ref(&chat_ref).await? else {
+    return Ok(None);
+  };
+  let mut result = ResyncResult { old_chat_id: stale_chat_id, new_chat_id, files_updated: 0, versions_updated: 0 };
+  if new_chat_id != stale_chat_id {
+    let (files_updated, versions_updated) = rewrite_chat_id(pool, stale_chat_id, new_chat_id).await?;
+    result.files_updated = files_updated;
+    result.versions_updated = versions_updated;
+    tracing::warn!(
+      event = "storage_chat_id_rewritten",
+      old_chat_id = stale_chat_id,
+      new_chat_id = new_chat_id,
+      files = files_updated,
+      versions = versions_updated,
+      "chat_id канала хранения изменился, ссылки в базе переписаны"
+    );
+  }
+  sync::set_sync(pool, "storage_chat_id", &new_chat_id.to_string()).await?;
+  Ok(Some(result))
+}
+
+/// Переписывает chat_id в файлах, версиях и сохраненных идентификаторах каналов.
+pub async fn rewrite_chat_id(pool: &SqlitePool, old_chat_id: ChatId, new_chat_id: ChatId) -> anyhow::Result<(u64, u64)> {
+  let files = sqlx::query("UPDATE files SET tg_chat_id = ? WHERE tg_chat_id = ?")
+    .bind(new_chat_id)
+    .bind(old_chat_id)
+    .execute(pool)
+    .await?
+    .rows_affected();
+  sqlx::query("UPDATE file_parts SET tg_chat_id = ? WHERE tg_chat_id = ?")
+    .bind(new_chat_id)
+    .bind(old_chat_id)
+    .execute(pool)
+    .await?;
+  let versions = sqlx::query("UPDATE file_versions SET tg_chat_id = ? WHERE tg_chat_id = ?")
+    .bind(new_chat_id)
+    .bind(old_chat_id)
+    .execute(pool)
+    .await?
+    .rows_affected();
+  sqlx::query("UPDATE sync_state SET value = ? WHERE key IN ('storage_chat_id', 'backup_chat_id') AND value = ?")
+    .bind(new_chat_id.to_string())
+    .bind(old_chat_id.to_string())
+    .execute(pool)
+    .await?;
+  Ok((files, versions))
+}
+
+/// chat_id канала в TDLib — это `-100` и supergroup_id в десятичной записи.
+pub fn supergroup_id_from_chat_id(chat_id: ChatId) -> Option<i64> {
+  chat_id
+    .checked_neg()
+    .and_then(|v| v.checked_sub(1_000_000_000_000))
+    .filter(|v| *v > 0)
+}
+
+async fn load_ref(pool: &SqlitePool) -> anyhow::Result<Option<StoredRef>> {
+  Ok(
+    sync::get_sync(pool, STORAGE_REF_KEY)
+      .await?
+      .and_then(|raw| serde_json::from_str::<StoredRef>(&raw).ok())
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use crate::sqlx::Row;
+  use tempfile::tempdir;
+
+  #[tokio::test]
+  async fn rewrite_moves_files_and_storage_id_to_new_chat() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d1', NULL, 'Docs', NULL, 0)")
+      .execute(pool)
+      .await?;
+    for (id, chat, msg_id) in [("f1", -100, 1), ("f2", -100, 2), ("f3", -300, 1)] {
+      sqlx::query(
+        "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at) VALUES(?, 'd1', ?, 1, 'h', ?, ?, 0)"
+      )
+        .bind(id)
+        .bind(format!("{id}.txt"))
+        .bind(chat)
+        .bind(msg_id)
+        .execute(pool)
+        .await?;
+    }
+    sync::set_sync(pool, "storage_chat_id", "-100").await?;
+    sync::set_sync(pool, "backup_chat_id", "-300").await?;
+
+    let (files, _) = rewrite_chat_id(pool, -100, -200).await?;
+    assert_eq!(files, 2);
+    assert_eq!(sync::get_sync(pool, "storage_chat_id").await?.as_deref(), Some("-200"));
+    assert_eq!(sync::get_sync(pool, "backup_chat_id").await?.as_deref(), Some("-300"));
+    let moved: i64 = sqlx::query("SELECT COUNT(*) AS n FROM files WHERE tg_chat_id = -200")
+      .fetch_one(pool)
+      .await?
+      .get("n");
+    assert_eq!(moved, 2);
+
+    assert_eq!(supergroup_id_from_chat_id(-1_001_234_567_890), Some(1_234_567_890));
+    assert_eq!(supergroup_id_from_chat_id(12345), None);
+    Ok(())
+  }
+}
@@ -0,0 +1,93 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Кладет файл в системный буфер обмена так, чтобы его можно было вставить
+/// в проводник или письмо. Плагин буфера обмена Tauri умеет только текст и
+/// картинки, поэтому используются средства ОС: `Set-Clipboard -LiteralPath` на
+/// Windows, `osascript` на macOS и `wl-copy`/`xclip` со списком URI в
+/// Linux.
+pub fn copy_file(path: &Path) -> anyhow::Result<()> {
+  #[cfg(target_os = "windows")]
+  {
+    let literal = path.to_string_lossy().replace('\'', "''");
+    let script = format!("Set-Clipboard -LiteralPath '{literal}'");
+    run(Command::new("powershell").args(["-NoProfile", "-NonInteractive", "-Command"]).arg(script), None)
+  }
+  #[cfg(target_os = "macos")]
+  {
+    let literal = path.to_string_lossy().replace('\\', "\\\\").replace('"', "\\\"");
+    let script = format!("set the clipboard to (POSIX file \"{literal}\")");
+    run(Command::new("osascript").arg("-e").arg(script), None)
+  }
+  #[cfg(all(not(target_os = "windows"), not(target_os = "macos")))]
+  {
+    let list = format!("{}\r\n", file_uri(path));
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+      run(Command::new("wl-copy").args(["--type", "text/uri-list"]), Some(&list))
+    } else {
+      run(Command::new("xclip").args(["-selection", "clipboard", "-t", "text/uri-list"]), Some(&list))
+    }
+  }
+}
+
+/// `wl-copy` и `xclip` остаются в фоне, пока буфер не перезапишут, поэтому
+/// их вывод не читается: ждем только завершения запущенного процесса.
+fn run(command: &mut Command, stdin: Option<&str>) -> anyhow::Result<()> {
+  let program = command.get_program().to_string_lossy().to_string();
+  hide_console(command);
+  let mut child = command
+    .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .spawn()
+    .map_err(|e| anyhow::anyhow!("Не удалось запустить {program}: {e}"))?;
+  if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+    pipe.write_all(input.as_bytes())?;
+  }
+  let status = child.wait()?;
+  if !status.success() {
+    return Err(anyhow::anyhow!("{program} завершился с ошибкой: {status}"));
+  }
+  Ok(())
+}
+
+/// PowerShell — консольная программа: без флага на время копирования
+/// мелькает окно консоли.
+#[cfg(windows)]
+fn hide_console(command: &mut Command) {
+  use std::os::windows::process::CommandExt;
+  const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+  command.creation_flags(CREATE_NO_WINDOW);
+}
+
+#[cfg(not(windows))]
+fn hide_console(_command: &mut Command) {}
+
+/// `file://` URI с процентным кодированием всего, кроме безопасных символов
+/// пути.
+#[cfg_attr(any(target_os = "windows", target_os = "macos"), allow(dead_code))]
+fn file_uri(path: &Path) -> String {
+  let mut out = String::from("file://");
+  for b in path.to_string_lossy().bytes() {
+    if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+      out.push(char::from(b));
+    } else {
+      out.push_str(&format!("%{b:02X}"));
+    }
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn file_uri_escapes_path() {
+    assert_eq!(
+      file_uri(Path::new("/home/user/Мои файлы/a&b.txt")),
+      "file:///home/user/%D0%9C%D0%BE%D0%B8%20%D1%84%D0%B0%D0%B9%D0%BB%D1%8B/a%26b.txt"
+    );
+  }
+}
@@ -0,0 +1,186 @@
+use chrono::Utc;
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+
+use crate::paths::Paths;
+use crate::telegram::TelegramService;
+
+use super::files::{self, FileItem};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CollectionKind {
+  Sticker,
+  Animation
+}
+
+impl CollectionKind {
+  /// Значение `files.media_kind` для этого вида.
+  pub fn media_kind(self) -> &'static str {
+    match self {
+      CollectionKind::Sticker => "sticker",
+      CollectionKind::Animation => "animation"
+    }
+  }
+}
+
+/// Группа коллекции: стикеры одного набора или все GIF (у них набора нет).
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CollectionGroup {
+  pub pack_id: Option<String>,
+  pub pack_name: Option<String>,
+  pub title: String,
+  pub files: Vec<FileItem>
+}
+
+pub fn is_collection_kind(media_kind: Option<&str>) -> bool {
+  matches!(media_kind, Some("sticker") | Some("animation"))
+}
+
+/// Запоминает название набора стикеров. Набор запрашивается у Telegram один раз;
+/// если запрос не удался, в списке коллекций останется его id.
+pub async fn remember_pack(pool: &SqlitePool, tg: &dyn TelegramService, set_id: &str) -> anyhow::Result<()> {
+  let known = sqlx::query("SELECT 1 FROM sticker_packs WHERE id = ?")
+    .bind(set_id)
+    .fetch_optional(pool)
+    .await?
+    .is_some();
+  if known {
+    return Ok(());
+  }
+  let info = match tg.sticker_set_info(set_id.to_string()).await {
+    Ok(info) => info,
+    Err(e) => {
+      tracing::debug!(event = "sticker_pack_lookup_failed", set_id = set_id, error = %e, "Не удалось получить набор стикеров");
+      return Ok(());
+    }
+  };
+  sqlx::query(
+    "INSERT INTO sticker_packs(id, name, title, updated_at) VALUES(?, ?, ?, ?)
+     ON CONFLICT(id) DO UPDATE SET name=excluded.name, title=excluded.title, updated_at=excluded.updated_at"
+  )
+    .bind(set_id)
+    .bind(&info.name)
+    .bind(&info.title)
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+/// Файлы коллекции, сгруппированные по наборам. Сначала наборы с известным
+/// названием, затем остальные; внутри группы — по времени отправки.
+pub async fn browse(pool: &SqlitePool, paths: &Paths, kind: CollectionKind) -> anyhow::Result<Vec<CollectionGroup>> {
+  let rows = sqlx::query(
+    "SELECT f.id, f.pack_id, p.name AS pack_name, p.title AS pack_title
+     FROM files f LEFT JOIN sticker_packs p ON p.id = f.pack_id
+     WHERE f.media_kind = ?
+     ORDER BY f.pack_id IS NULL, p.title IS NULL, COALESCE(p.title, f.pack_id, '') COLLATE NOCASE, f.pack_id, f.created_at, f.tg_msg_id"
+  )
+    .bind(kind.media_kind())
+    .fetch_all(pool)
+    .await?;
+
+  let ids: Vec<String> = rows.iter().map(|r| r.get::<String,_>("id")).collect();
+  let mut items = files::files_by_ids(pool, paths, &ids).await?.into_iter().peekable();
+
+  let mut groups: Vec<CollectionGroup> = Vec::new();
+  for row in rows {
+    let id: String = row.get("id");
+    let Some(item) = items.next_if(|f| f.id == id) else {
+      continue;
+    };
+    let pack_id: Option<String> = row.try_get("pack_id").ok();
+    match groups.last_mut() {
+      Some(group) if group.pack_id == pack_id => group.files.push(item),
+      _ => {
+        let title = row
+          .try_get::<String,_>("pack_title")
+          .ok()
+          .filter(|t| !t.trim().is_empty())
+          .or_else(|| pack_id.as_ref().map(|id| format!("Набор {id}")))
+          .unwrap_or_else(|| default_title(kind).to_string());
+        groups.push(CollectionGroup {
+          pack_id,
+          pack_name: row.try_get("pack_name").ok(),
+          title,
+          files: vec![item]
+        });
+      }
+    }
+  }
+  Ok(groups)
+}
+
+fn default_title(kind: CollectionKind) -> &'static str {
+  match kind {
+    CollectionKind::Sticker => "Стикеры без набора",
+    CollectionKind::Animation => "GIF"
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[tokio::test]
+  async fn browse_groups_stickers_by_pack() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    let paths = Paths {
+      base_dir: tmp.path().to_path_buf(),
+      data_dir: tmp.path().join("data"),
+      cache_dir: tmp.path().join("cache"),
+      logs_dir: tmp.path().join("logs"),
+      resource_dir: None,
+      portable: false
+    };
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d', NULL, ?, NULL, 0)")
+      .bind(crate::app::system_dirs::SystemDir::Collections.default_name())
+      .execute(pool)
+      .await?;
+    sqlx::query("INSERT INTO sticker_packs(id, name, title, updated_at) VALUES('7', 'cats', 'Котики', 0)")
+      .execute(pool)
+      .await?;
+    let files = [
+      ("s1", "sticker", Some("7"), 1),
+      ("s2", "sticker", Some("9"), 2),
+      ("s3", "sticker", Some("7"), 3),
+      ("g1", "animation", None, 4),
+      ("doc", "document", None, 5)
+    ];
+    for (id, kind, pack, msg_id) in files {
+      sqlx::query(
+        "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, media_kind, pack_id)
+         VALUES(?, 'd', ?, 1, 'h', 1, ?, ?, ?, ?)"
+      )
+        .bind(id)
+        .bind(format!("{id}.webp"))
+        .bind(msg_id)
+        .bind(msg_id)
+        .bind(kind)
+        .bind(pack)
+        .execute(pool)
+        .await?;
+    }
+
+    let stickers = browse(pool, &paths, CollectionKind::Sticker).await?;
+    let shape: Vec<(String, Vec<&str>)> = stickers
+      .iter()
+      .map(|g| (g.title.clone(), g.files.iter().map(|f| f.id.as_str()).collect()))
+      .collect();
+    assert_eq!(shape, vec![
+      ("Котики".to_string(), vec!["s1", "s3"]),
+      ("Набор 9".to_string(), vec!["s2"])
+    ]);
+
+    let gifs = browse(pool, &paths, CollectionKind::Animation).await?;
+    assert_eq!(gifs.len(), 1);
+    assert_eq!(gifs[0].title, "GIF");
+    Ok(())
+  }
+}
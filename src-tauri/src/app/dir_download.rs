@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::sqlx::{self, Row};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use sqlx_sqlite::SqlitePool;
+use ulid::Ulid;
+
+use crate::paths::Paths;
+use crate::telegram::{ChatId, TelegramService};
+
+use crate::app::dirs::dir_exists;
+use crate::app::download_queue::{self, Transfer, TransferKind};
+use crate::app::files;
+
+/// Управление идущими скачиваниями папок по id задачи.
+static JOBS: Lazy<Mutex<HashMap<String, Arc<JobControl>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Default)]
+struct JobControl {
+  cancelled: AtomicBool,
+  /// Передача файла, который скачивается сейчас: отмена прерывает и ее.
+  current: Mutex<Option<String>>
+}
+
+/// Скачивание папки целиком. Пока значение живо, задачу можно отменить
+/// через `cancel`.
+pub struct DirDownloadJob {
+  pub id: String,
+  pub dir_id: String,
+  /// Собрать после скачивания один .zip.
+  pub zip: bool,
+  control: Arc<JobControl>
+}
+
+impl DirDownloadJob {
+  pub fn start(dir_id: &str, zip: bool) -> DirDownloadJob {
+    let id = Ulid::new().to_string();
+    let control = Arc::new(JobControl::default());
+    JOBS.lock().insert(id.clone(), control.clone());
+    DirDownloadJob { id, dir_id: dir_id.to_string(), zip, control }
+  }
+
+  fn is_cancelled(&self) -> bool {
+    self.control.cancelled.load(Ordering::Relaxed)
+  }
+}
+
+impl Drop for DirDownloadJob {
+  fn drop(&mut self) {
+    JOBS.lock().remove(&self.id);
+  }
+}
+
+/// Останавливает задачу вместе с идущим скачиванием файла; уже скачанные
+/// файлы остаются. `false`, если такой задачи нет (уже закончилась).
+pub fn cancel(job_id: &str) -> bool {
+  let Some(control) = JOBS.lock().get(job_id).cloned() else {
+    return false;
+  };
+  control.cancelled.store(true, Ordering::Relaxed);
+  if let Some(transfer_id) = control.current.lock().as_deref() {
+    download_queue::abort(transfer_id);
+  }
+  true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DirDownloadStage {
+  Downloading,
+  Archiving,
+  Done,
+  Cancelled,
+  Failed
+}
+
+/// Событие `dir_download_progress`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DirDownloadProgress {
+  pub job_id: String,
+  pub dir_id: String,
+  pub stage: DirDownloadStage,
+  pub done: i64,
+  pub failed: i64,
+  pub total: i64,
+  /// Имя файла, который скачивается сейчас.
+  pub current: Option<String>,
+  pub archive_path: Option<String>,
+  pub message: Option<String>
+}
+
+impl DirDownloadProgress {
+  fn new(job: &DirDownloadJob, stage: DirDownloadStage, total: i64) -> DirDownloadProgress {
+    DirDownloadProgress {
+      job_id: job.id.clone(),
+      dir_id: job.dir_id.clone(),
+      stage,
+      done: 0,
+      failed: 0,
+      total,
+      current: None,
+      archive_path: None,
+      message: None
+    }
+  }
+
+  /// Итог задачи, которая не смогла начаться или оборвалась ошибкой.
+  pub fn failed(job: &DirDownloadJob, message: String) -> DirDownloadProgress {
+    DirDownloadProgress { message: Some(message), ..DirDownloadProgress::new(job, DirDownloadStage::Failed, 0) }
+  }
+}
+
+/// Все файлы поддерева папки: id и имя.
+async fn subtree_files(pool: &SqlitePool, dir_id: &str) -> anyhow::Result<Vec<(String, String)>> {
+  let rows = sqlx::query(
+    "WITH RECURSIVE subtree(id) AS (
+       SELECT id FROM directories WHERE id = ?
+       UNION SELECT d.id FROM directories d JOIN subtree s ON d.parent_id = s.id
+     )
+     SELECT f.id, f.name FROM files f WHERE f.dir_id IN (SELECT id FROM subtree) ORDER BY f.dir_id, f.name"
+  )
+    .bind(dir_id)
+    .fetch_all(pool)
+    .await?;
+  Ok(rows.into_iter().map(|r| (r.get("id"), r.get("name"))).collect())
+}
+
+/// Скачивает все файлы поддерева в те же пути под `cache_dir/downloads`,
+/// что и при скачивании по одному, и при `job.zip` собирает их в архив.
+/// Файлы идут через слоты очереди передач и видны в ее списке.
+/// Ошибка одного файла не останавливает остальные; итог — последнее событие.
+pub async fn run(
+  pool: &SqlitePool,
+  tg: Arc<dyn TelegramService>,
+  paths: &Paths,
+  storage_chat_id: ChatId,
+  job: &DirDownloadJob,
+  on_progress: &(dyn Fn(DirDownloadProgress) + Send + Sync)
+) -> anyhow::Result<DirDownloadProgress> {
+  if !dir_exists(pool, &job.dir_id).await? {
+    return Err(anyhow::anyhow!("Папка не найдена"));
+  }
+  let items = subtree_files(pool, &job.dir_id).await?;
+  let mut progress = DirDownloadProgress::new(job, DirDownloadStage::Downloading, items.len() as i64);
+  let mut downloaded: Vec<PathBuf> = Vec::with_capacity(items.len());
+  for (file_id, name) in items {
+    let transfer = Transfer::interactive(TransferKind::Download, &name, Some(&file_id), None);
+    // Передача записывается до проверки отмены, чтобы `cancel` между ними
+    // не пропустил начинающееся скачивание.
+    *job.control.current.lock() = Some(transfer.id.clone());
+    if job.is_cancelled() {
+      *job.control.current.lock() = None;
+      progress.stage = DirDownloadStage::Cancelled;
+      progress.current = None;
+      return Ok(progress);
+    }
+    progress.current = Some(name.clone());
+    on_progress(progress.clone());
+    let (task_pool, task_tg, task_paths, task_file_id) = (pool.clone(), tg.clone(), paths.clone(), file_id.clone());
+    let res = download_queue::run_interactive(pool, transfer, async move {
+      files::download_file(&task_pool, task_tg.as_ref(), &task_paths, storage_chat_id, &task_file_id, false, None).await
+    })
+      .await;
+    *job.control.current.lock() = None;
+    if job.is_cancelled() {
+      if res.is_ok() {
+        progress.done += 1;
+      }
+      progress.stage = DirDownloadStage::Cancelled;
+      progress.current = None;
+      return Ok(progress);
+    }
+    match res {
+      Ok(path) => {
+        progress.done += 1;
+        downloaded.push(path);
+      }
+      Err(e) => {
+        progress.failed += 1;
+        tracing::warn!(event = "dir_download_file_failed", file_id = file_id.as_str(), error = %e, "Не удалось скачать файл папки");
+      }
+    }
+  }
+  progress.current = None;
+
+  if job.zip && !downloaded.is_empty() {
+    progress.stage = DirDownloadStage::Archiving;
+    on_progress(progress.clone());
+    let base = paths.layout().downloads_dir().join(files::build_dir_path(pool, &job.dir_id).await?);
+    let archive = archive_path(paths, &base, &job.dir_id);
+    let control = job.control.clone();
+    let target = archive.clone();
+    let written = tokio::task::spawn_blocking(move || write_archive(&base, &downloaded, &target, &control.cancelled)).await??;
+    if !written {
+      progress.stage = DirDownloadStage::Cancelled;
+      return Ok(progress);
+    }
+    progress.archive_path = Some(archive.to_string_lossy().to_string());
+  }
+  progress.stage = DirDownloadStage::Done;
+  tracing::info!(
+    event = "dir_download_done",
+    dir_id = job.dir_id.as_str(),
+    done = progress.done,
+    failed = progress.failed,
+    "Папка скачана"
+  );
+  Ok(progress)
+}
+
+/// Архив называется по папке, а id папки в имени не дает столкнуться
+/// одноименным папкам из разных веток.
+fn archive_path(paths: &Paths, base: &Path, dir_id: &str) -> PathBuf {
+  let name = base
+    .file_name()
+    .and_then(|n| n.to_str())
+    .filter(|n| !n.is_empty())
+    .unwrap_or("Хранилище");
+  paths.layout().archive_path(&format!("{name}-{dir_id}"))
+}
+
+/// Пишет архив из файлов с путями относительно `base`. Возвращает `false`,
+/// если задачу отменили: недописанный архив удаляется.
+fn write_archive(base: &Path, sources: &[PathBuf], target: &Path, cancelled: &AtomicBool) -> anyhow::Result<bool> {
+  if let Some(parent) = target.parent() {
+    std::fs::create_dir_all(parent)?;
+  }
+  let partial = target.with_extension("zip.partial");
+  match fill_archive(base, sources, &partial, cancelled) {
+    Ok(true) => {
+      std::fs::rename(&partial, target)?;
+      Ok(true)
+    }
+    other => {
+      let _ = std::fs::remove_file(&partial);
+      other
+    }
+  }
+}
+
+fn fill_archive(base: &Path, sources: &[PathBuf], partial: &Path, cancelled: &AtomicBool) -> anyhow::Result<bool> {
+  use zip::write::SimpleFileOptions;
+
+  let mut writer = zip::ZipWriter::new(std::fs::File::create(partial)?);
+  for source in sources {
+    if cancelled.load(Ordering::Relaxed) {
+      return Ok(false);
+    }
+    let entry = source
+      .strip_prefix(base)
+      .ok()
+      .map(|rel| rel.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/"))
+      .or_else(|| source.file_name().map(|n| n.to_string_lossy().to_string()))
+      .unwrap_or_else(|| "файл".to_string());
+    let size = source.metadata()?.len();
+    let options = SimpleFileOptions::default().large_file(size >= u64::from(u32::MAX));
+    writer.start_file(entry, options)?;
+    std::io::copy(&mut std::fs::File::open(source)?, &mut writer)?;
+  }
+  writer.finish()?;
+  Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Read;
+
+  #[test]
+  fn archive_keeps_subfolder_layout_and_honors_cancel() -> anyhow::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let base = tmp.path().join("Фото");
+    std::fs::create_dir_all(base.join("Отпуск"))?;
+    let sources = vec![base.join("a.txt"), base.join("Отпуск").join("b.txt")];
+    std::fs::write(&sources[0], b"first")?;
+    std::fs::write(&sources[1], b"second")?;
+
+    let target = tmp.path().join("out").join("Фото.zip");
+    assert!(write_archive(&base, &sources, &target, &AtomicBool::new(false))?);
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(&target)?)?;
+    let mut body = String::new();
+    archive.by_name("Отпуск/b.txt")?.read_to_string(&mut body)?;
+    assert_eq!(body, "second");
+    assert_eq!(archive.len(), 2);
+
+    let cancelled_target = tmp.path().join("out").join("cancelled.zip");
+    assert!(!write_archive(&base, &sources, &cancelled_target, &AtomicBool::new(true))?);
+    assert!(!cancelled_target.exists());
+    assert!(!cancelled_target.with_extension("zip.partial").exists());
+    Ok(())
+  }
+
+  #[test]
+  fn archive_names_of_same_named_folders_differ() {
+    let tmp = tempfile::tempdir().expect("tempdir");
+    let paths = Paths::from_base(tmp.path().to_path_buf());
+    let first = archive_path(&paths, Path::new("Работа/Фото"), "d1");
+    let second = archive_path(&paths, Path::new("Отпуск/Фото"), "d2");
+    assert_ne!(first, second);
+    assert_eq!(first.file_name().and_then(|n| n.to_str()), Some("Фото-d1.zip"));
+  }
+
+  #[test]
+  fn cancel_reaches_running_job_only() {
+    let job = DirDownloadJob::start("d", false);
+    assert!(!job.is_cancelled());
+    assert!(cancel(&job.id));
+    assert!(job.is_cancelled());
+    let id = job.id.clone();
+    drop(job);
+    assert!(!cancel(&id));
+  }
+}
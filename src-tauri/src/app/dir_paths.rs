@@ -0,0 +1,188 @@
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+
+/// Глубже не поднимаемся: защита от цикла в `parent_id`.
+const MAX_DEPTH: usize = 64;
+
+/// Сбрасывает пути папок, которые триггер пометил после переименования или
+/// переноса, вместе со всеми подпапками. Без изменений стоит один запрос.
+pub async fn refresh(pool: &SqlitePool) -> anyhow::Result<usize> {
+  let stale: Vec<String> = sqlx::query("SELECT dir_id FROM dir_paths_stale")
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| row.get("dir_id"))
+    .collect();
+  for dir_id in &stale {
+    // Сброс и снятие отметки вместе: отметка, поставленная между ними,
+    // иначе потерялась бы.
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+      "UPDATE directories SET path = NULL WHERE id IN (
+         WITH RECURSIVE subtree(id) AS (
+           SELECT id FROM directories WHERE id = ?
+           UNION SELECT d.id FROM directories d JOIN subtree s ON d.parent_id = s.id
+         ) SELECT id FROM subtree)"
+    )
+      .bind(dir_id)
+      .execute(&mut *tx)
+      .await?;
+    sqlx::query("DELETE FROM dir_paths_stale WHERE dir_id = ?")
+      .bind(dir_id)
+      .execute(&mut *tx)
+      .await?;
+    tx.commit().await?;
+  }
+  Ok(stale.len())
+}
+
+/// Имена папок от корня до `dir_id` включительно, как они записаны в базе.
+/// Обычно это одно чтение столбца `path`; непосчитанный путь собирается
+/// подъемом до ближайшего предка с готовым путем и сохраняется для всей цепочки.
+pub async fn names(pool: &SqlitePool, dir_id: &str) -> anyhow::Result<Vec<String>> {
+  refresh(pool).await?;
+  // (id, имя, parent_id) в том виде, в каком они прочитаны.
+  let mut chain: Vec<(String, String, Option<String>)> = Vec::new();
+  let mut base: Vec<String> = Vec::new();
+  let mut current = Some(dir_id.to_string());
+  while let Some(id) = current.take() {
+    if chain.len() >= MAX_DEPTH || chain.iter().any(|(seen, _, _)| *seen == id) {
+      break;
+    }
+    let row = sqlx::query("SELECT name, parent_id, path FROM directories WHERE id = ?")
+      .bind(&id)
+      .fetch_optional(pool)
+      .await?;
+    let Some(row) = row else {
+      break;
+    };
+    if let Some(path) = row.try_get::<String,_>("path").ok().and_then(|p| decode(&p)) {
+      base = path;
+      break;
+    }
+    let parent_id: Option<String> = row.try_get("parent_id").ok().flatten();
+    current = parent_id.clone().filter(|p| !p.trim().is_empty() && p != "ROOT");
+    chain.push((id, row.get("name"), parent_id));
+  }
+
+  store_chain(pool, base, chain).await
+}
+
+/// Дописывает к `base` имена цепочки (от предка к `dir_id`) и сохраняет пути.
+/// Цепочка прочитана раньше: переименование или перенос после чтения сделали
+/// бы путь неверным навсегда — отметку уже сняли. Поэтому пути пишутся одной
+/// транзакцией и только пока папка не менялась и новых отметок нет; иначе путь
+/// посчитается при следующем чтении.
+async fn store_chain(
+  pool: &SqlitePool,
+  base: Vec<String>,
+  chain: Vec<(String, String, Option<String>)>
+) -> anyhow::Result<Vec<String>> {
+  let mut names = base;
+  let mut tx = pool.begin().await?;
+  let mut store = true;
+  for (id, name, parent_id) in chain.into_iter().rev() {
+    names.push(name);
+    if !store {
+      continue;
+    }
+    let res = sqlx::query(
+      "UPDATE directories SET path = ?
+       WHERE id = ? AND name = ? AND parent_id IS ?
+         AND NOT EXISTS (SELECT 1 FROM dir_paths_stale)"
+    )
+      .bind(serde_json::to_string(&names)?)
+      .bind(&id)
+      .bind(names.last())
+      .bind(&parent_id)
+      .execute(&mut *tx)
+      .await?;
+    store = res.rows_affected() > 0;
+  }
+  tx.commit().await?;
+  Ok(names)
+}
+
+fn decode(path: &str) -> Option<Vec<String>> {
+  serde_json::from_str(path).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::tempdir;
+  use crate::db::Db;
+
+  async fn stored_path(pool: &SqlitePool, dir_id: &str) -> anyhow::Result<Option<String>> {
+    Ok(sqlx::query("SELECT path FROM directories WHERE id = ?")
+      .bind(dir_id)
+      .fetch_one(pool)
+      .await?
+      .try_get::<String,_>("path")
+      .ok())
+  }
+
+  #[tokio::test]
+  async fn paths_follow_renames_and_moves() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    for (id, parent, name) in [("a", None, "Работа"), ("b", Some("a"), "Отчеты"), ("c", Some("b"), "2024")] {
+      sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES(?, ?, ?, NULL, 0)")
+        .bind(id)
+        .bind(parent)
+        .bind(name)
+        .execute(pool)
+        .await?;
+    }
+
+    assert_eq!(names(pool, "c").await?, vec!["Работа", "Отчеты", "2024"]);
+    assert_eq!(stored_path(pool, "b").await?.as_deref(), Some(r#"["Работа","Отчеты"]"#));
+
+    sqlx::query("UPDATE directories SET name = 'Архив' WHERE id = 'a'").execute(pool).await?;
+    assert_eq!(names(pool, "c").await?, vec!["Архив", "Отчеты", "2024"]);
+
+    sqlx::query("UPDATE directories SET parent_id = NULL WHERE id = 'c'").execute(pool).await?;
+    assert_eq!(names(pool, "c").await?, vec!["2024"]);
+    assert_eq!(names(pool, "b").await?, vec!["Архив", "Отчеты"]);
+    assert_eq!(refresh(pool).await?, 0);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn path_read_before_a_change_is_not_stored() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    for (id, parent, name) in [("a", None, "Работа"), ("b", Some("a"), "Отчеты")] {
+      sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES(?, ?, ?, NULL, 0)")
+        .bind(id)
+        .bind(parent)
+        .bind(name)
+        .execute(pool)
+        .await?;
+    }
+    let read = || vec![
+      ("b".to_string(), "Отчеты".to_string(), Some("a".to_string())),
+      ("a".to_string(), "Работа".to_string(), None)
+    ];
+
+    // Путь `b` собран от готового пути предка, а предка переименовали после
+    // чтения: пока стоит отметка, ничего не сохраняется.
+    sqlx::query("UPDATE directories SET name = 'Архив' WHERE id = 'a'").execute(pool).await?;
+    store_chain(pool, vec!["Работа".to_string()], read().into_iter().take(1).collect()).await?;
+    assert_eq!(stored_path(pool, "b").await?, None);
+
+    // Отметку уже снял другой читатель: старое имя не совпадает.
+    refresh(pool).await?;
+    store_chain(pool, Vec::new(), read()).await?;
+    assert_eq!(stored_path(pool, "a").await?, None);
+    assert_eq!(stored_path(pool, "b").await?, None);
+
+    assert_eq!(names(pool, "b").await?, vec!["Архив", "Отчеты"]);
+    assert_eq!(stored_path(pool, "b").await?.as_deref(), Some(r#"["Архив","Отчеты"]"#));
+    Ok(())
+  }
+}
@@ -0,0 +1,132 @@
+use chrono::Utc;
+use sqlx_sqlite::SqlitePool;
+
+use crate::app::files::SearchSort;
+use crate::sqlx::{self, Row};
+
+/// Настройки вида папки (сортировка, группировка, режим отображения).
+/// Хранятся в базе, а не в localStorage интерфейса, поэтому попадают в
+/// снимки бэкапа и переживают переустановку. `ROOT` — корень дерева.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DirPrefs {
+  #[serde(default)]
+  pub sort: SearchSort,
+  #[serde(default)]
+  pub grouping: Grouping,
+  #[serde(default)]
+  pub view_mode: ViewMode
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Grouping {
+  #[default]
+  None,
+  Type,
+  Date
+}
+
+impl Grouping {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      Grouping::None => "none",
+      Grouping::Type => "type",
+      Grouping::Date => "date"
+    }
+  }
+
+  pub fn parse(raw: &str) -> Option<Self> {
+    match raw {
+      "none" => Some(Grouping::None),
+      "type" => Some(Grouping::Type),
+      "date" => Some(Grouping::Date),
+      _ => None
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViewMode {
+  #[default]
+  List,
+  Grid
+}
+
+impl ViewMode {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      ViewMode::List => "list",
+      ViewMode::Grid => "grid"
+    }
+  }
+
+  pub fn parse(raw: &str) -> Option<Self> {
+    match raw {
+      "list" => Some(ViewMode::List),
+      "grid" => Some(ViewMode::Grid),
+      _ => None
+    }
+  }
+}
+
+/// Настройки папки; для папки без сохраненных настроек — значения по умолчанию.
+/// Неизвестные значения (например, от более новой версии) тоже заменяются ими.
+pub async fn get_prefs(pool: &SqlitePool, dir_id: &str) -> anyhow::Result<DirPrefs> {
+  let row = sqlx::query("SELECT sort, grouping, view_mode FROM dir_prefs WHERE dir_id = ?")
+    .bind(dir_id)
+    .fetch_optional(pool)
+    .await?;
+  let Some(row) = row else {
+    return Ok(DirPrefs::default());
+  };
+  Ok(DirPrefs {
+    sort: SearchSort::parse(&row.get::<String, _>("sort")).unwrap_or_default(),
+    grouping: Grouping::parse(&row.get::<String, _>("grouping")).unwrap_or_default(),
+    view_mode: ViewMode::parse(&row.get::<String, _>("view_mode")).unwrap_or_default()
+  })
+}
+
+pub async fn set_prefs(pool: &SqlitePool, dir_id: &str, prefs: DirPrefs) -> anyhow::Result<DirPrefs> {
+  if dir_id != "ROOT" && !crate::app::dirs::dir_exists(pool, dir_id).await? {
+    return Err(anyhow::anyhow!("Папка не найдена"));
+  }
+  sqlx::query(
+    "INSERT INTO dir_prefs(dir_id, sort, grouping, view_mode, updated_at) VALUES(?, ?, ?, ?, ?)
+     ON CONFLICT(dir_id) DO UPDATE SET
+       sort = excluded.sort, grouping = excluded.grouping, view_mode = excluded.view_mode, updated_at = excluded.updated_at"
+  )
+    .bind(dir_id)
+    .bind(prefs.sort.as_str())
+    .bind(prefs.grouping.as_str())
+    .bind(prefs.view_mode.as_str())
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await?;
+  Ok(prefs)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[tokio::test]
+  async fn prefs_round_trip_and_fall_back_to_defaults() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+
+    assert_eq!(get_prefs(pool, "ROOT").await?, DirPrefs::default());
+    let prefs = DirPrefs { sort: SearchSort::SizeDesc, grouping: Grouping::Type, view_mode: ViewMode::Grid };
+    set_prefs(pool, "ROOT", prefs).await?;
+    assert_eq!(get_prefs(pool, "ROOT").await?, prefs);
+    assert!(set_prefs(pool, "missing", prefs).await.is_err());
+
+    sqlx::query("UPDATE dir_prefs SET view_mode = 'carousel' WHERE dir_id = 'ROOT'").execute(pool).await?;
+    assert_eq!(get_prefs(pool, "ROOT").await?.view_mode, ViewMode::List);
+    Ok(())
+  }
+}
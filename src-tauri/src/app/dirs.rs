@@ -1,11 +1,13 @@
 use chrono::Utc;
+use std::collections::{HashSet, VecDeque};
 use crate::sqlx::{self, Row};
 use sqlx_sqlite::SqlitePool;
 use ulid::Ulid;
 
-use crate::fsmeta::{DirMeta, make_dir_message, parse_dir_message};
+use crate::fsmeta::{DirMeta, LinkKind, make_dir_message, parse_dir_message};
 use crate::telegram::{TelegramService, ChatId};
 
+use super::{files, links};
 use super::models::DirNode;
 
 pub async fn create_dir(
@@ -15,23 +17,15 @@ pub async fn create_dir(
   parent_id: Option<String>,
   name: String
 ) -> anyhow::Result<String> {
-  let id = Ulid::new().to_string();
-  let updated_at = Utc::now().timestamp();
   let parent_id = parent_id.filter(|p| !p.trim().is_empty() && p != "ROOT");
+  let id = create_local_dir(pool, parent_id.clone(), &name).await?;
+  let updated_at = Utc::now().timestamp();
 
-  sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES(?, ?, ?, NULL, ?)")
-    .bind(&id)
-    .bind(parent_id.as_deref())
-    .bind(&name)
-    .bind(updated_at)
-    .execute(pool)
-    .await?;
-
-  let parent_tag = parent_id.clone().unwrap_or_else(|| "ROOT".to_string());
+  let parent_tag = parent_id.unwrap_or_else(|| "ROOT".to_string());
   let msg = make_dir_message(&DirMeta { dir_id: id.clone(), parent_id: parent_tag, name });
   let uploaded = tg.send_dir_message(chat_id, msg).await?;
 
-  sqlx::query("UPDATE directories SET tg_msg_id = ?, updated_at = ?, is_broken = 0 WHERE id = ?")
+  sqlx::query("UPDATE directories SET tg_msg_id = ?, updated_at = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
     .bind(uploaded.message_id)
     .bind(updated_at)
     .bind(&id)
@@ -41,33 +35,84 @@ pub async fn create_dir(
   Ok(id)
 }
 
+/// Папка только в базе, без сообщения в канале: так create_dir начинает,
+/// а режим канала только для чтения так и оставляет.
+pub async fn create_local_dir(pool: &SqlitePool, parent_id: Option<String>, name: &str) -> anyhow::Result<String> {
+  let id = Ulid::new().to_string();
+  let parent_id = parent_id.filter(|p| !p.trim().is_empty() && p != "ROOT");
+  sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES(?, ?, ?, NULL, ?)")
+    .bind(&id)
+    .bind(parent_id.as_deref())
+    .bind(name)
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await?;
+  Ok(id)
+}
+
 pub async fn rename_dir(
   pool: &SqlitePool,
   tg: &dyn TelegramService,
   chat_id: ChatId,
   dir_id: &str,
   name: String
-) -> anyhow::Result<()> {
+) -> anyhow::Result<files::BatchOutcome> {
   let name = name.trim().to_string();
   if name.is_empty() {
     return Err(anyhow::anyhow!("Имя папки не может быть пустым"));
   }
   let mut dir = fetch_dir(pool, dir_id).await?;
   if dir.name == name && dir.tg_msg_id.is_some() {
-    return Ok(());
+    return Ok(files::BatchOutcome::default());
   }
   let msg_id = ensure_dir_message(tg, chat_id, &dir, dir.parent_id.clone(), &name).await?;
   let updated_at = Utc::now().timestamp();
-  sqlx::query("UPDATE directories SET name = ?, tg_msg_id = ?, updated_at = ?, is_broken = 0 WHERE id = ?")
+  sqlx::query("UPDATE directories SET name = ?, tg_msg_id = ?, updated_at = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
     .bind(&name)
     .bind(msg_id)
     .bind(updated_at)
     .bind(dir_id)
     .execute(pool)
     .await?;
+  let renamed = dir.name != name;
   dir.name = name;
   dir.tg_msg_id = Some(msg_id);
-  Ok(())
+  if !renamed {
+    return Ok(files::BatchOutcome::default());
+  }
+  retag_dir_files(pool, tg, dir_id).await
+}
+
+/// Обновляет хэштег папки в подписях ее файлов. Папка уже переименована,
+/// поэтому неудачные правки не откатывают переименование, а возвращаются
+/// в итоге: подпись с прежним тегом по-прежнему указывает на верную папку через `d=`.
+async fn retag_dir_files(pool: &SqlitePool, tg: &dyn TelegramService, dir_id: &str) -> anyhow::Result<files::BatchOutcome> {
+  let file_ids: Vec<String> = sqlx::query("SELECT id FROM files WHERE dir_id = ? ORDER BY id")
+    .bind(dir_id)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| r.get::<String,_>("id"))
+    .collect();
+  if file_ids.is_empty() {
+    return Ok(files::BatchOutcome::default());
+  }
+  let outcome = match files::rewrite_captions(pool, tg, &file_ids, None).await {
+    Ok(outcome) => outcome,
+    Err(e) => {
+      tracing::warn!(event = "dir_retag_failed", dir_id = dir_id, error = %e, "Не удалось обновить теги папки в подписях файлов");
+      let error = e.to_string();
+      files::BatchOutcome {
+        done: Vec::new(),
+        failed: file_ids.into_iter().map(|id| (id, error.clone())).collect()
+      }
+    }
+  };
+  for (file_id, error) in &outcome.failed {
+    tracing::warn!(event = "dir_retag_file_failed", dir_id = dir_id, file_id = file_id.as_str(), error = error.as_str(), "Не удалось обновить тег папки в подписи файла");
+  }
+  tracing::info!(event = "dir_retag_done", dir_id = dir_id, updated = outcome.done.len(), failed = outcome.failed.len(), "Теги папки в подписях файлов обновлены");
+  Ok(outcome)
 }
 
 pub async fn move_dir(
@@ -97,7 +142,7 @@ pub async fn move_dir(
   }
   let msg_id = ensure_dir_message(tg, chat_id, &dir, parent_id.clone(), &dir.name).await?;
   let updated_at = Utc::now().timestamp();
-  sqlx::query("UPDATE directories SET parent_id = ?, tg_msg_id = ?, updated_at = ?, is_broken = 0 WHERE id = ?")
+  sqlx::query("UPDATE directories SET parent_id = ?, tg_msg_id = ?, updated_at = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
     .bind(parent_id.as_deref())
     .bind(msg_id)
     .bind(updated_at)
@@ -109,6 +154,119 @@ pub async fn move_dir(
   Ok(())
 }
 
+/// Итог копирования папки: id копии, id скопированных файлов и исходные
+/// файлы, которые скопировать не удалось. Непустые `failed_dirs` значат, что
+/// копия неполная: этих подпапок (вместе с их содержимым) в ней нет.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct DirCopy {
+  pub dir_id: String,
+  pub created: Vec<String>,
+  pub failed: Vec<(String, String)>,
+  /// Исходные подпапки, копии которых не удалось создать, и причина.
+  pub failed_dirs: Vec<(String, String)>,
+  /// Исходные ссылки, которые не удалось повторить в копии, и причина.
+  pub failed_links: Vec<(String, String)>
+}
+
+/// Копирует папку со всем поддеревом в `parent_id`. Каждая подпапка получает
+/// новый id и свое сообщение, файлы копируются через `files::copy_files` без
+/// повторной загрузки, ссылки создаются заново с теми же целями. Прерывает
+/// копирование только ошибка создания самой копии; остальное попадает в итог.
+pub async fn copy_dir(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  chat_id: ChatId,
+  dir_id: &str,
+  parent_id: Option<String>
+) -> anyhow::Result<DirCopy> {
+  let dir = fetch_dir(pool, dir_id).await?;
+  let parent_id = normalize_parent_id(parent_id);
+  if let Some(pid) = parent_id.as_deref() {
+    if !dir_exists(pool, pid).await? {
+      return Err(anyhow::anyhow!("Родительская папка не найдена"));
+    }
+    if has_ancestor(pool, pid, dir_id).await? {
+      return Err(anyhow::anyhow!("Нельзя скопировать папку внутрь самой себя"));
+    }
+  }
+  let mut siblings: HashSet<String> = sqlx::query("SELECT name FROM directories WHERE parent_id IS ?")
+    .bind(parent_id.as_deref())
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| r.get::<String,_>("name"))
+    .collect();
+  let name = files::copy_name(&dir.name, &mut siblings);
+
+  let mut result = DirCopy::default();
+  let mut queue: VecDeque<(String, Option<String>, String)> = VecDeque::from([(dir.id, parent_id, name)]);
+  while let Some((source_id, parent_id, name)) = queue.pop_front() {
+    let copy_id = match create_dir(pool, tg, chat_id, parent_id, name).await {
+      Ok(id) => id,
+      Err(e) if result.dir_id.is_empty() => return Err(e),
+      Err(e) => {
+        tracing::warn!(event = "dir_copy_subdir_failed", dir_id = source_id.as_str(), error = %e, "Не удалось создать копию подпапки");
+        result.failed_dirs.push((source_id, e.to_string()));
+        continue;
+      }
+    };
+    if result.dir_id.is_empty() {
+      result.dir_id = copy_id.clone();
+    }
+    let file_ids: Vec<String> = sqlx::query("SELECT id FROM files WHERE dir_id = ? ORDER BY name")
+      .bind(&source_id)
+      .fetch_all(pool)
+      .await?
+      .into_iter()
+      .map(|r| r.get::<String,_>("id"))
+      .collect();
+    if !file_ids.is_empty() {
+      match files::copy_files(pool, tg, chat_id, &file_ids, &copy_id).await {
+        Ok(copied) => {
+          result.created.extend(copied.created);
+          result.failed.extend(copied.failed);
+        }
+        Err(e) => {
+          let error = e.to_string();
+          result.failed.extend(file_ids.into_iter().map(|id| (id, error.clone())));
+        }
+      }
+    }
+    let source_links = sqlx::query("SELECT id, target_kind, target_id FROM links WHERE dir_id = ? ORDER BY created_at, id")
+      .bind(&source_id)
+      .fetch_all(pool)
+      .await?;
+    for link in source_links {
+      let link_id: String = link.get("id");
+      let Some(kind) = LinkKind::parse(&link.get::<String,_>("target_kind")) else {
+        continue;
+      };
+      let target_id: String = link.get("target_id");
+      if let Err(e) = links::create_link(pool, tg, chat_id, &copy_id, kind, &target_id).await {
+        result.failed_links.push((link_id, e.to_string()));
+      }
+    }
+    let children = sqlx::query("SELECT id, name FROM directories WHERE parent_id = ? ORDER BY name")
+      .bind(&source_id)
+      .fetch_all(pool)
+      .await?;
+    for child in children {
+      queue.push_back((child.get("id"), Some(copy_id.clone()), child.get("name")));
+    }
+  }
+  tracing::info!(
+    event = "dir_copy_done",
+    dir_id = dir_id,
+    copy_id = result.dir_id.as_str(),
+    files = result.created.len(),
+    failed = result.failed.len(),
+    failed_dirs = result.failed_dirs.len(),
+    failed_links = result.failed_links.len(),
+    "Папка скопирована"
+  );
+  Ok(result)
+}
+
 pub async fn delete_dir(
   pool: &SqlitePool,
   tg: &dyn TelegramService,
@@ -127,11 +285,20 @@ pub async fn delete_dir(
     .await?
     .get::<i64,_>("cnt");
 
+  let link_count: i64 = sqlx::query("SELECT COUNT(1) as cnt FROM links WHERE dir_id = ?")
+    .bind(dir_id)
+    .fetch_one(pool)
+    .await?
+    .get::<i64,_>("cnt");
+
   if child_count > 0 || file_count > 0 {
     return Err(anyhow::anyhow!(
       "Папка не пустая: файлов={file_count}, подпапок={child_count}"
     ));
   }
+  if link_count > 0 {
+    return Err(anyhow::anyhow!("Папка не пустая: ссылок={link_count}"));
+  }
 
   let mut message_ids: Vec<i64> = Vec::new();
   if let Some(msg_id) = dir.tg_msg_id {
@@ -152,6 +319,10 @@ pub async fn delete_dir(
     .bind(dir_id)
     .execute(pool)
     .await?;
+  sqlx::query("DELETE FROM dir_prefs WHERE dir_id = ?")
+    .bind(dir_id)
+    .execute(pool)
+    .await?;
   Ok(())
 }
 
@@ -164,7 +335,7 @@ pub async fn repair_dir(
   let dir = fetch_dir(pool, dir_id).await?;
   let msg_id = ensure_dir_message(tg, chat_id, &dir, dir.parent_id.clone(), &dir.name).await?;
   let updated_at = Utc::now().timestamp();
-  sqlx::query("UPDATE directories SET tg_msg_id = ?, updated_at = ?, is_broken = 0 WHERE id = ?")
+  sqlx::query("UPDATE directories SET tg_msg_id = ?, updated_at = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
     .bind(msg_id)
     .bind(updated_at)
     .bind(dir_id)
@@ -179,7 +350,7 @@ pub async fn list_tree(pool: &SqlitePool) -> anyhow::Result<DirNode> {
     .await?;
 
   #[derive(Clone)]
-  struct RowItem { id: String, parent_id: Option<String>, name: String, is_broken: bool }
+  struct RowItem { id: String, parent_id: Option<String>, name: String, is_broken: bool, link_target_id: Option<String> }
 
   let mut items: Vec<RowItem> = Vec::with_capacity(rows.len());
   for r in rows {
@@ -189,10 +360,32 @@ pub async fn list_tree(pool: &SqlitePool) -> anyhow::Result<DirNode> {
       id: r.get::<String,_>("id"),
       parent_id,
       name: r.get::<String,_>("name"),
-      is_broken: r.get::<i64,_>("is_broken") != 0
+      is_broken: r.get::<i64,_>("is_broken") != 0,
+      link_target_id: None
     });
   }
 
+  // Ссылки на папки отображаются листьями дерева, чтобы не зацикливать обход.
+  let link_rows = sqlx::query(
+    "SELECT l.id, l.dir_id, l.target_id, d.name, d.is_broken
+     FROM links l JOIN directories d ON d.id = l.target_id
+     WHERE l.target_kind = 'dir'"
+  )
+    .fetch_all(pool)
+    .await?;
+  if !link_rows.is_empty() {
+    for r in link_rows {
+      items.push(RowItem {
+        id: r.get::<String,_>("id"),
+        parent_id: Some(r.get::<String,_>("dir_id")),
+        name: r.get::<String,_>("name"),
+        is_broken: r.get::<i64,_>("is_broken") != 0,
+        link_target_id: Some(r.get::<String,_>("target_id"))
+      });
+    }
+    items.sort_by(|a, b| a.name.cmp(&b.name));
+  }
+
   let mut map: std::collections::HashMap<String, DirNode> = std::collections::HashMap::new();
   for it in &items {
     map.insert(
@@ -202,6 +395,7 @@ pub async fn list_tree(pool: &SqlitePool) -> anyhow::Result<DirNode> {
         name: it.name.clone(),
         parent_id: it.parent_id.clone(),
         is_broken: it.is_broken,
+        link_target_id: it.link_target_id.clone(),
         children: vec![]
       }
     );
@@ -212,6 +406,7 @@ pub async fn list_tree(pool: &SqlitePool) -> anyhow::Result<DirNode> {
     name: "ROOT".to_string(),
     parent_id: None,
     is_broken: false,
+    link_target_id: None,
     children: vec![]
   };
 
@@ -242,6 +437,41 @@ pub async fn list_tree(pool: &SqlitePool) -> anyhow::Result<DirNode> {
   Ok(rebuilt_root)
 }
 
+/// Узел дерева для папки или ссылки на папку, без детей; `None`, если его нет.
+pub async fn get_node(pool: &SqlitePool, id: &str) -> anyhow::Result<Option<DirNode>> {
+  if let Some(r) = sqlx::query("SELECT id, parent_id, name, is_broken FROM directories WHERE id = ?")
+    .bind(id)
+    .fetch_optional(pool)
+    .await?
+  {
+    let parent_id = r.try_get::<String,_>("parent_id").ok().filter(|p| !p.trim().is_empty() && p != "ROOT");
+    return Ok(Some(DirNode {
+      id: r.get("id"),
+      name: r.get("name"),
+      parent_id,
+      is_broken: r.get::<i64,_>("is_broken") != 0,
+      link_target_id: None,
+      children: vec![]
+    }));
+  }
+  let link = sqlx::query(
+    "SELECT l.id, l.dir_id, l.target_id, d.name, d.is_broken
+     FROM links l JOIN directories d ON d.id = l.target_id
+     WHERE l.id = ? AND l.target_kind = 'dir'"
+  )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+  Ok(link.map(|r| DirNode {
+    id: r.get("id"),
+    name: r.get("name"),
+    parent_id: Some(r.get("dir_id")),
+    is_broken: r.get::<i64,_>("is_broken") != 0,
+    link_target_id: Some(r.get("target_id")),
+    children: vec![]
+  }))
+}
+
 #[derive(Clone)]
 struct DirRow {
   id: String,
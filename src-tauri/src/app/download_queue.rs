@@ -0,0 +1,847 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::{oneshot, Notify};
+use ulid::Ulid;
+
+use crate::events::{self, Change};
+use crate::settings;
+use crate::state::{AppState, AuthState};
+
+use super::{dirs, files, maintenance, sync};
+
+/// Сколько раз пробуем передачу, прежде чем оставить ее в очереди с ошибкой.
+const MAX_ATTEMPTS: i64 = 3;
+const IDLE_POLL: Duration = Duration::from_secs(30);
+const NOT_READY_POLL: Duration = Duration::from_secs(5);
+
+pub const DEFAULT_CONCURRENCY: usize = 2;
+pub const MAX_CONCURRENCY: usize = 8;
+
+static WAKE: Lazy<Notify> = Lazy::new(Notify::new);
+
+/// Занятые слоты передач: и задания очереди, и скачивания и загрузки из UI.
+static BUSY: Lazy<Mutex<usize>> = Lazy::new(|| Mutex::new(0));
+/// Передачи из UI, ждущие слота: очередь пропускает их вперед.
+static WAITING: AtomicUsize = AtomicUsize::new(0);
+static SLOT_FREED: Lazy<Notify> = Lazy::new(Notify::new);
+/// Идущие передачи по id. Пауза и отмена прерывают задачу через ее handle,
+/// а вызовы TDLib при сбросе задачи отменяют и саму передачу.
+static RUNNING: Lazy<Mutex<HashMap<String, JoinHandle<()>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+/// Передачи, начатые из UI: в базе их нет, в списке они видны отсюда.
+static INTERACTIVE: Lazy<Mutex<HashMap<String, Transfer>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Приоритет загрузки: отдельные файлы из UI идут раньше, папки «на потом» — последними.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+  High,
+  Normal,
+  Low
+}
+
+impl Priority {
+  fn as_i64(self) -> i64 {
+    match self {
+      Priority::High => 0,
+      Priority::Normal => 1,
+      Priority::Low => 2
+    }
+  }
+
+  fn from_i64(v: i64) -> Priority {
+    match v {
+      0 => Priority::High,
+      1 => Priority::Normal,
+      _ => Priority::Low
+    }
+  }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct QueueItem {
+  pub file_id: String,
+  pub name: String,
+  pub dir_id: String,
+  pub size: i64,
+  pub priority: Priority,
+  pub position: i64,
+  /// `queued`, `active`, `paused` или `failed`. Скачанные файлы из очереди удаляются.
+  pub status: String,
+  pub attempts: i64,
+  pub last_error: Option<String>,
+  pub enqueued_at: i64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferKind {
+  Upload,
+  Download
+}
+
+/// Передача в общем списке: загрузка из `upload_queue`, скачивание из
+/// `download_queue` (id совпадает с id файла) или вызов из UI.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Transfer {
+  pub id: String,
+  pub kind: TransferKind,
+  /// Для скачивания — какой файл.
+  pub file_id: Option<String>,
+  /// Для загрузки — в какую папку.
+  pub dir_id: Option<String>,
+  pub name: String,
+  /// `queued`, `active`, `paused` или `failed`. Завершенные передачи удаляются.
+  pub status: String,
+  pub attempts: i64,
+  pub last_error: Option<String>,
+  pub created_at: i64
+}
+
+impl Transfer {
+  /// Скачивание или загрузка, начатые из UI: их результат ждет вызывающий.
+  pub fn interactive(kind: TransferKind, name: &str, file_id: Option<&str>, dir_id: Option<&str>) -> Transfer {
+    Transfer {
+      id: Ulid::new().to_string(),
+      kind,
+      file_id: file_id.map(str::to_string),
+      dir_id: dir_id.map(str::to_string),
+      name: name.to_string(),
+      status: "queued".into(),
+      attempts: 0,
+      last_error: None,
+      created_at: Utc::now().timestamp()
+    }
+  }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TransferChanged {
+  pub id: String,
+  pub status: Option<String>
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct QueueChanged {
+  pub file_id: Option<String>,
+  pub status: Option<String>
+}
+
+/// Ставит файл в очередь. Если он уже там, приоритет только повышается,
+/// а упавшая загрузка начинается заново.
+pub async fn enqueue_file(pool: &SqlitePool, file_id: &str, priority: Priority) -> anyhow::Result<()> {
+  let exists = sqlx::query("SELECT 1 FROM files WHERE id = ?")
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?
+    .is_some();
+  if !exists {
+    return Err(anyhow::anyhow!("Файл не найден"));
+  }
+  let position = next_position(pool).await?;
+  sqlx::query(
+    "INSERT INTO download_queue(file_id, priority, position, status, attempts, last_error, enqueued_at)
+     VALUES(?, ?, ?, 'queued', 0, NULL, ?)
+     ON CONFLICT(file_id) DO UPDATE SET priority=MIN(download_queue.priority, excluded.priority),
+       status=CASE WHEN download_queue.status IN ('failed', 'paused') THEN 'queued' ELSE download_queue.status END,
+       attempts=CASE WHEN download_queue.status = 'failed' THEN 0 ELSE download_queue.attempts END"
+  )
+    .bind(file_id)
+    .bind(priority.as_i64())
+    .bind(position)
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await?;
+  wake();
+  Ok(())
+}
+
+/// Ставит в очередь все файлы папки и ее подпапок с низким приоритетом.
+/// Возвращает число добавленных файлов; уже стоящие в очереди не трогаются.
+pub async fn enqueue_dir(pool: &SqlitePool, dir_id: &str) -> anyhow::Result<usize> {
+  if !dirs::dir_exists(pool, dir_id).await? {
+    return Err(anyhow::anyhow!("Папка не найдена"));
+  }
+  let rows = sqlx::query(
+    "WITH RECURSIVE subtree(id, depth) AS (
+       SELECT ?, 0
+       UNION ALL
+       SELECT d.id, s.depth + 1 FROM directories d JOIN subtree s ON d.parent_id = s.id
+     )
+     SELECT f.id FROM files f JOIN subtree s ON s.id = f.dir_id
+     WHERE f.is_broken = 0 AND f.id NOT IN (SELECT file_id FROM download_queue)
+     ORDER BY s.depth, f.dir_id, f.name"
+  )
+    .bind(dir_id)
+    .fetch_all(pool)
+    .await?;
+
+  let mut position = next_position(pool).await?;
+  let now = Utc::now().timestamp();
+  for row in &rows {
+    sqlx::query(
+      "INSERT INTO download_queue(file_id, priority, position, status, attempts, last_error, enqueued_at)
+       VALUES(?, ?, ?, 'queued', 0, NULL, ?)
+       ON CONFLICT(file_id) DO NOTHING"
+    )
+      .bind(row.get::<String,_>("id"))
+      .bind(Priority::Low.as_i64())
+      .bind(position)
+      .bind(now)
+      .execute(pool)
+      .await?;
+    position += 1;
+  }
+  if !rows.is_empty() {
+    wake();
+  }
+  Ok(rows.len())
+}
+
+pub async fn list(pool: &SqlitePool) -> anyhow::Result<Vec<QueueItem>> {
+  let rows = sqlx::query(
+    "SELECT q.file_id, f.name, f.dir_id, f.size, q.priority, q.position, q.status, q.attempts, q.last_error, q.enqueued_at
+     FROM download_queue q JOIN files f ON f.id = q.file_id
+     ORDER BY (q.status = 'active') DESC, q.priority, q.position"
+  )
+    .fetch_all(pool)
+    .await?;
+  Ok(rows.into_iter().map(|r| QueueItem {
+    file_id: r.get("file_id"),
+    name: r.get("name"),
+    dir_id: r.get("dir_id"),
+    size: r.get("size"),
+    priority: Priority::from_i64(r.get("priority")),
+    position: r.get("position"),
+    status: r.get("status"),
+    attempts: r.get("attempts"),
+    last_error: r.try_get("last_error").ok(),
+    enqueued_at: r.get("enqueued_at")
+  }).collect())
+}
+
+pub async fn set_priority(pool: &SqlitePool, file_id: &str, priority: Priority) -> anyhow::Result<()> {
+  let res = sqlx::query("UPDATE download_queue SET priority = ? WHERE file_id = ?")
+    .bind(priority.as_i64())
+    .bind(file_id)
+    .execute(pool)
+    .await?;
+  if res.rows_affected() == 0 {
+    return Err(anyhow::anyhow!("Файла нет в очереди загрузок"));
+  }
+  wake();
+  Ok(())
+}
+
+/// Переставляет элементы в порядке `ids` внутри их приоритетов; остальные
+/// сохраняют взаимный порядок и идут следом.
+pub async fn reorder(pool: &SqlitePool, ids: &[String]) -> anyhow::Result<()> {
+  let current: Vec<String> = sqlx::query("SELECT file_id FROM download_queue ORDER BY priority, position")
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| r.get::<String,_>("file_id"))
+    .collect();
+  let mut ordered: Vec<&String> = ids.iter().filter(|id| current.contains(id)).collect();
+  for id in &current {
+    if !ordered.contains(&id) {
+      ordered.push(id);
+    }
+  }
+  for (position, id) in ordered.iter().enumerate() {
+    sqlx::query("UPDATE download_queue SET position = ? WHERE file_id = ?")
+      .bind(position as i64)
+      .bind(*id)
+      .execute(pool)
+      .await?;
+  }
+  Ok(())
+}
+
+/// Убирает файл из очереди; идущее скачивание прерывается.
+pub async fn remove(pool: &SqlitePool, file_id: &str) -> anyhow::Result<()> {
+  abort(file_id);
+  sqlx::query("DELETE FROM download_queue WHERE file_id = ?")
+    .bind(file_id)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+/// Ставит загрузку файла с диска в очередь передач и возвращает ее id.
+pub async fn enqueue_upload(pool: &SqlitePool, dir_id: &str, source: &Path) -> anyhow::Result<String> {
+  if !dirs::dir_exists(pool, dir_id).await? {
+    return Err(anyhow::anyhow!("Папка не найдена"));
+  }
+  let name = source
+    .file_name()
+    .map(|n| n.to_string_lossy().to_string())
+    .ok_or_else(|| anyhow::anyhow!("Некорректный путь файла"))?;
+  let id = Ulid::new().to_string();
+  sqlx::query(
+    "INSERT INTO upload_queue(id, dir_id, source_path, name, status, attempts, last_error, created_at)
+     VALUES(?, ?, ?, ?, 'queued', 0, NULL, ?)"
+  )
+    .bind(&id)
+    .bind(dir_id)
+    .bind(source.to_string_lossy().to_string())
+    .bind(&name)
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await?;
+  wake();
+  Ok(id)
+}
+
+/// Все передачи: загрузки, скачивания из очереди и идущие вызовы из UI.
+pub async fn list_transfers(pool: &SqlitePool) -> anyhow::Result<Vec<Transfer>> {
+  let rows = sqlx::query(
+    "SELECT * FROM (
+       SELECT id, 'upload' AS kind, NULL AS file_id, dir_id, name, status, attempts, last_error, created_at FROM upload_queue
+       UNION ALL
+       SELECT q.file_id, 'download', q.file_id, NULL, f.name, q.status, q.attempts, q.last_error, q.enqueued_at
+       FROM download_queue q JOIN files f ON f.id = q.file_id
+     ) ORDER BY (status = 'active') DESC, created_at, id"
+  )
+    .fetch_all(pool)
+    .await?;
+  let mut out: Vec<Transfer> = INTERACTIVE.lock().values().cloned().collect();
+  out.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+  out.extend(rows.into_iter().map(|r| Transfer {
+    id: r.get("id"),
+    kind: if r.get::<String,_>("kind") == "upload" { TransferKind::Upload } else { TransferKind::Download },
+    file_id: r.try_get("file_id").ok(),
+    dir_id: r.try_get("dir_id").ok(),
+    name: r.get("name"),
+    status: r.get("status"),
+    attempts: r.get("attempts"),
+    last_error: r.try_get("last_error").ok(),
+    created_at: r.get("created_at")
+  }));
+  Ok(out)
+}
+
+/// Ставит передачу на паузу. Идущая прерывается и потом продолжится заново.
+pub async fn pause(pool: &SqlitePool, id: &str) -> anyhow::Result<()> {
+  if INTERACTIVE.lock().contains_key(id) {
+    return Err(anyhow::anyhow!("Передачу из окна приложения можно только отменить"));
+  }
+  let mut affected = sqlx::query("UPDATE upload_queue SET status = 'paused' WHERE id = ? AND status IN ('queued', 'active')")
+    .bind(id)
+    .execute(pool)
+    .await?
+    .rows_affected();
+  if affected == 0 {
+    affected = sqlx::query("UPDATE download_queue SET status = 'paused' WHERE file_id = ? AND status IN ('queued', 'active')")
+      .bind(id)
+      .execute(pool)
+      .await?
+      .rows_affected();
+  }
+  if affected == 0 {
+    return Err(anyhow::anyhow!("Передача не найдена или уже на паузе"));
+  }
+  abort(id);
+  Ok(())
+}
+
+/// Возвращает в очередь передачу на паузе или упавшую.
+pub async fn resume(pool: &SqlitePool, id: &str) -> anyhow::Result<()> {
+  let mut affected = sqlx::query(
+    "UPDATE upload_queue SET status = 'queued',
+       attempts = CASE WHEN status = 'failed' THEN 0 ELSE attempts END
+     WHERE id = ? AND status IN ('paused', 'failed')"
+  )
+    .bind(id)
+    .execute(pool)
+    .await?
+    .rows_affected();
+  if affected == 0 {
+    affected = sqlx::query(
+      "UPDATE download_queue SET status = 'queued',
+         attempts = CASE WHEN status = 'failed' THEN 0 ELSE attempts END
+       WHERE file_id = ? AND status IN ('paused', 'failed')"
+    )
+      .bind(id)
+      .execute(pool)
+      .await?
+      .rows_affected();
+  }
+  if affected == 0 {
+    return Err(anyhow::anyhow!("Передача не найдена или уже идет"));
+  }
+  wake();
+  Ok(())
+}
+
+/// Отменяет передачу. Идущая прерывается вместе с передачей в TDLib.
+pub async fn cancel(pool: &SqlitePool, id: &str) -> anyhow::Result<()> {
+  abort(id);
+  sqlx::query("DELETE FROM upload_queue WHERE id = ?")
+    .bind(id)
+    .execute(pool)
+    .await?;
+  sqlx::query("DELETE FROM download_queue WHERE file_id = ?")
+    .bind(id)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+/// Прерывает идущую передачу по id, не трогая записи очереди. Ждущий
+/// вызов из UI получает ошибку отмены.
+pub fn abort(id: &str) {
+  if let Some(handle) = RUNNING.lock().remove(id) {
+    handle.abort();
+  }
+}
+
+/// Прерывает все идущие передачи, например при смене аккаунта. Записи
+/// остаются `active` и вернутся в очередь при следующем запуске обработчика
+/// для этой базы, а ждущие вызовы из UI получают ошибку отмены.
+pub fn abort_running() {
+  for (_, handle) in RUNNING.lock().drain() {
+    handle.abort();
+  }
+}
+
+pub fn wake() {
+  WAKE.notify_one();
+}
+
+/// Слот передачи; освобождается при завершении или прерывании задачи.
+struct Slot;
+
+impl Drop for Slot {
+  fn drop(&mut self) {
+    {
+      let mut busy = BUSY.lock();
+      *busy = busy.saturating_sub(1);
+    }
+    SLOT_FREED.notify_waiters();
+    wake();
+  }
+}
+
+fn try_take_slot(limit: usize) -> Option<Slot> {
+  let mut busy = BUSY.lock();
+  if *busy >= limit {
+    return None;
+  }
+  *busy += 1;
+  Some(Slot)
+}
+
+struct Waiting;
+
+impl Drop for Waiting {
+  fn drop(&mut self) {
+    WAITING.fetch_sub(1, Ordering::SeqCst);
+    wake();
+  }
+}
+
+/// Ждет свободного слота для передачи из UI.
+async fn wait_for_slot(limit: usize) -> Slot {
+  WAITING.fetch_add(1, Ordering::SeqCst);
+  let _waiting = Waiting;
+  loop {
+    let freed = SLOT_FREED.notified();
+    if let Some(slot) = try_take_slot(limit) {
+      return slot;
+    }
+    freed.await;
+  }
+}
+
+/// Выполняет скачивание или загрузку из UI в слоте общей очереди: они
+/// делят с ней лимит одновременных передач, видны в списке и отменяются
+/// так же, как задания очереди.
+pub async fn run_interactive<T, F>(pool: &SqlitePool, transfer: Transfer, job: F) -> anyhow::Result<T>
+where
+  T: Send + 'static,
+  F: Future<Output = anyhow::Result<T>> + Send + 'static
+{
+  let limit = settings::get_transfer_concurrency(pool).await?;
+  let id = transfer.id.clone();
+  INTERACTIVE.lock().insert(id.clone(), transfer);
+  let (tx, rx) = oneshot::channel();
+  {
+    // Блокировка держится до вставки, чтобы быстрая передача не закончилась
+    // раньше, чем ее запишут.
+    let mut running = RUNNING.lock();
+    let task_id = id.clone();
+    let handle = tauri::async_runtime::spawn(async move {
+      let _slot = wait_for_slot(limit).await;
+      if let Some(transfer) = INTERACTIVE.lock().get_mut(&task_id) {
+        transfer.status = "active".into();
+      }
+      let _ = tx.send(job.await);
+    });
+    running.insert(id.clone(), handle);
+  }
+  let res = rx.await;
+  RUNNING.lock().remove(&id);
+  INTERACTIVE.lock().remove(&id);
+  res.unwrap_or_else(|_| Err(anyhow::anyhow!("Передача отменена")))
+}
+
+async fn next_position(pool: &SqlitePool) -> anyhow::Result<i64> {
+  Ok(sqlx::query("SELECT COALESCE(MAX(position), -1) + 1 AS next FROM download_queue")
+    .fetch_one(pool)
+    .await?
+    .get("next"))
+}
+
+enum Job {
+  Download(String),
+  Upload { id: String, dir_id: String, source_path: String }
+}
+
+impl Job {
+  fn id(&self) -> &str {
+    match self {
+      Job::Download(file_id) => file_id,
+      Job::Upload { id, .. } => id
+    }
+  }
+}
+
+/// Следующая передача: сначала срочные скачивания, затем загрузки, обычные
+/// скачивания и, только когда разрешена фоновая работа, папки «на потом».
+async fn next_job(pool: &SqlitePool, background: bool) -> anyhow::Result<Option<Job>> {
+  let max_priority = if background { Priority::Low } else { Priority::Normal };
+  let row = sqlx::query(
+    "SELECT kind, id FROM (
+       SELECT 'download' AS kind, file_id AS id, priority * 2 AS rank, position AS pos
+       FROM download_queue WHERE status = 'queued' AND priority <= ?
+       UNION ALL
+       SELECT 'upload', id, 1, created_at FROM upload_queue WHERE status = 'queued'
+     ) ORDER BY rank, pos, id LIMIT 1"
+  )
+    .bind(max_priority.as_i64())
+    .fetch_optional(pool)
+    .await?;
+  let Some(row) = row else {
+    return Ok(None);
+  };
+  let id: String = row.get("id");
+  if row.get::<String,_>("kind") == "download" {
+    return Ok(Some(Job::Download(id)));
+  }
+  let row = sqlx::query("SELECT dir_id, source_path FROM upload_queue WHERE id = ?")
+    .bind(&id)
+    .fetch_one(pool)
+    .await?;
+  Ok(Some(Job::Upload { id, dir_id: row.get("dir_id"), source_path: row.get("source_path") }))
+}
+
+async fn set_status(pool: &SqlitePool, job: &Job, status: &str) -> anyhow::Result<()> {
+  let sql = match job {
+    Job::Download(_) => "UPDATE download_queue SET status = ? WHERE file_id = ?",
+    Job::Upload { .. } => "UPDATE upload_queue SET status = ? WHERE id = ?"
+  };
+  sqlx::query(sql)
+    .bind(status)
+    .bind(job.id())
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+/// Упавшая загрузка возвращается в конец своего приоритета, после
+/// `MAX_ATTEMPTS` попыток остается в очереди как `failed`. Если передачу
+/// успели поставить на паузу или отменить, ничего не меняется.
+async fn record_failure(pool: &SqlitePool, job: &Job, error: &str) -> anyhow::Result<Option<String>> {
+  let row = match job {
+    Job::Download(file_id) => {
+      let position = next_position(pool).await?;
+      sqlx::query(
+        "UPDATE download_queue SET attempts = attempts + 1, last_error = ?, position = ?,
+           status = CASE WHEN attempts + 1 >= ? THEN 'failed' ELSE 'queued' END
+         WHERE file_id = ? AND status = 'active'
+         RETURNING status"
+      )
+        .bind(error)
+        .bind(position)
+        .bind(MAX_ATTEMPTS)
+        .bind(file_id)
+        .fetch_optional(pool)
+        .await?
+    }
+    Job::Upload { id, .. } => {
+      sqlx::query(
+        "UPDATE upload_queue SET attempts = attempts + 1, last_error = ?,
+           status = CASE WHEN attempts + 1 >= ? THEN 'failed' ELSE 'queued' END
+         WHERE id = ? AND status = 'active'
+         RETURNING status"
+      )
+        .bind(error)
+        .bind(MAX_ATTEMPTS)
+        .bind(id)
+        .fetch_optional(pool)
+        .await?
+    }
+  };
+  Ok(row.map(|r| r.get("status")))
+}
+
+/// Фоновый обработчик очереди: держит не больше заданного числа одновременных
+/// передач, пока Telegram авторизован. Прерванные перезапуском передачи
+/// продолжаются.
+pub fn spawn_worker(app: AppHandle) -> JoinHandle<()> {
+  tauri::async_runtime::spawn(async move {
+    let state = app.state::<AppState>();
+    if let Ok(db) = state.db() {
+      for sql in [
+        "UPDATE download_queue SET status = 'queued' WHERE status = 'active'",
+        "UPDATE upload_queue SET status = 'queued' WHERE status = 'active'"
+      ] {
+        if let Err(e) = sqlx::query(sql).execute(db.pool()).await {
+          tracing::warn!(event = "download_queue_reset_failed", error = %e, "Не удалось вернуть прерванные передачи в очередь");
+        }
+      }
+    }
+    loop {
+      match start_next(&app).await {
+        Ok(true) => continue,
+        Ok(false) => {
+          let _ = tokio::time::timeout(IDLE_POLL, WAKE.notified()).await;
+        }
+        Err(e) => {
+          tracing::warn!(event = "download_queue_failed", error = %e, "Ошибка обработки очереди передач");
+          tokio::time::sleep(NOT_READY_POLL).await;
+        }
+      }
+    }
+  })
+}
+
+/// Возвращает `true`, если запущена еще одна передача.
+async fn start_next(app: &AppHandle) -> anyhow::Result<bool> {
+  let state = app.state::<AppState>();
+  if state.auth_state() != AuthState::Ready {
+    tokio::time::sleep(NOT_READY_POLL).await;
+    return Ok(false);
+  }
+  let db = state.db()?;
+  let pool = db.pool();
+  let limit = settings::get_transfer_concurrency(pool).await?;
+  // Передачи из UI, ждущие слота, идут раньше очереди.
+  if WAITING.load(Ordering::SeqCst) > 0 || *BUSY.lock() >= limit {
+    return Ok(false);
+  }
+  if sync::get_sync(pool, "storage_chat_id").await?.and_then(|v| v.parse::<i64>().ok()).is_none() {
+    tokio::time::sleep(NOT_READY_POLL).await;
+    return Ok(false);
+  }
+  let Some(job) = next_job(pool, maintenance::background_allowed()).await? else {
+    return Ok(false);
+  };
+  let Some(slot) = try_take_slot(limit) else {
+    return Ok(false);
+  };
+  set_status(pool, &job, "active").await?;
+  emit_job_changed(app, &job, Some("active"));
+
+  let mut running = RUNNING.lock();
+  let id = job.id().to_string();
+  let task_app = app.clone();
+  let handle = tauri::async_runtime::spawn(async move {
+    let _slot = slot;
+    let id = job.id().to_string();
+    run_job(&task_app, job).await;
+    RUNNING.lock().remove(&id);
+  });
+  running.insert(id, handle);
+  Ok(true)
+}
+
+async fn run_job(app: &AppHandle, job: Job) {
+  let state = app.state::<AppState>();
+  let res = execute(app, &state, &job).await;
+  let Ok(db) = state.db() else {
+    return;
+  };
+  let pool = db.pool();
+  let transfer = match job {
+    Job::Upload { .. } => crate::metrics::Transfer::Upload,
+    Job::Download(_) => crate::metrics::Transfer::Download
+  };
+  crate::metrics::record_transfer(transfer, res.is_ok());
+  match res {
+    Ok(()) => {
+      let sql = match job {
+        Job::Download(_) => "DELETE FROM download_queue WHERE file_id = ?",
+        Job::Upload { .. } => "DELETE FROM upload_queue WHERE id = ?"
+      };
+      if let Err(e) = sqlx::query(sql).bind(job.id()).execute(pool).await {
+        tracing::warn!(event = "transfer_cleanup_failed", id = job.id(), error = %e, "Не удалось убрать завершенную передачу");
+      }
+      emit_job_changed(app, &job, None);
+    }
+    Err(e) => {
+      tracing::warn!(event = "transfer_failed", id = job.id(), error = %e, "Передача из очереди не удалась");
+      match record_failure(pool, &job, &e.to_string()).await {
+        Ok(Some(status)) => emit_job_changed(app, &job, Some(&status)),
+        Ok(None) => {}
+        Err(e) => tracing::warn!(event = "transfer_record_failed", id = job.id(), error = %e, "Не удалось записать ошибку передачи")
+      }
+    }
+  }
+}
+
+async fn execute(app: &AppHandle, state: &AppState, job: &Job) -> anyhow::Result<()> {
+  let db = state.db()?;
+  let pool = db.pool();
+  let tg = state.telegram()?;
+  let storage_chat_id = sync::get_sync(pool, "storage_chat_id")
+    .await?
+    .and_then(|v| v.parse::<i64>().ok())
+    .ok_or_else(|| anyhow::anyhow!("Канал хранения не настроен"))?;
+  match job {
+    Job::Download(file_id) => {
+      let progress = events::download_progress_sink(app, file_id);
+      let paths = state.paths()?;
+      files::download_file(pool, tg.as_ref(), &paths, storage_chat_id, file_id, false, Some(progress)).await?;
+      state.invalidate_listings();
+    }
+    Job::Upload { dir_id, source_path, .. } => {
+      let file_id = files::upload_file(pool, tg.as_ref(), storage_chat_id, dir_id, Path::new(source_path)).await?;
+      state.invalidate_listings();
+      state.search_index_refresh_file(&db, &file_id).await;
+      events::file_changed(app, &file_id, Change::Created, Some(dir_id));
+    }
+  }
+  Ok(())
+}
+
+fn emit_job_changed(app: &AppHandle, job: &Job, status: Option<&str>) {
+  if let Job::Download(file_id) = job {
+    emit_changed(app, Some(file_id), status);
+  }
+  emit_transfer_changed(app, job.id(), status);
+}
+
+pub fn emit_transfer_changed(app: &AppHandle, id: &str, status: Option<&str>) {
+  let _ = app.emit("transfers_changed", TransferChanged { id: id.to_string(), status: status.map(str::to_string) });
+}
+
+pub fn emit_changed(app: &AppHandle, file_id: Option<&str>, status: Option<&str>) {
+  let _ = app.emit("download_queue_changed", QueueChanged {
+    file_id: file_id.map(str::to_string),
+    status: status.map(str::to_string)
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[tokio::test]
+  async fn folder_is_queued_after_single_files() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    for (id, parent) in [("root", None), ("child", Some("root")), ("other", None)] {
+      sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES(?, ?, ?, NULL, 0)")
+        .bind(id)
+        .bind(parent)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    }
+    for (msg_id, (id, dir)) in [("a", "root"), ("b", "child"), ("c", "other")].into_iter().enumerate() {
+      sqlx::query(
+        "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at) VALUES(?, ?, ?, 1, 'h', 1, ?, 0)"
+      )
+        .bind(id)
+        .bind(dir)
+        .bind(format!("{id}.bin"))
+        .bind(msg_id as i64)
+        .execute(pool)
+        .await?;
+    }
+
+    assert_eq!(enqueue_dir(pool, "root").await?, 2);
+    enqueue_file(pool, "c", Priority::High).await?;
+    let order: Vec<String> = list(pool).await?.into_iter().map(|i| i.file_id).collect();
+    assert_eq!(order, vec!["c", "a", "b"]);
+    assert_eq!(next_job(pool, true).await?.map(|j| j.id().to_string()).as_deref(), Some("c"));
+    remove(pool, "c").await?;
+    assert!(next_job(pool, false).await?.is_none());
+    enqueue_file(pool, "c", Priority::High).await?;
+
+    // Повторная постановка не дублирует и не понижает приоритет.
+    assert_eq!(enqueue_dir(pool, "root").await?, 0);
+    enqueue_file(pool, "c", Priority::Low).await?;
+    assert_eq!(list(pool).await?[0].priority, Priority::High);
+
+    reorder(pool, &["b".to_string()]).await?;
+    let order: Vec<String> = list(pool).await?.into_iter().map(|i| i.file_id).collect();
+    assert_eq!(order, vec!["c", "b", "a"]);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn transfers_survive_pause_resume_and_cancel() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d', NULL, 'd', NULL, 0)")
+      .execute(pool)
+      .await?;
+    for (msg_id, id) in ["f", "later"].into_iter().enumerate() {
+      sqlx::query(
+        "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at) VALUES(?, 'd', ?, 1, 'h', 1, ?, 0)"
+      )
+        .bind(id)
+        .bind(format!("{id}.bin"))
+        .bind(msg_id as i64)
+        .execute(pool)
+        .await?;
+    }
+
+    enqueue_file(pool, "f", Priority::Normal).await?;
+    enqueue_dir(pool, "d").await?;
+    let up = enqueue_upload(pool, "d", Path::new("/tmp/photo.jpg")).await?;
+    assert!(enqueue_upload(pool, "missing", Path::new("/tmp/x")).await.is_err());
+
+    // Загрузки идут раньше обычных скачиваний, папки «на потом» — только в окне.
+    assert_eq!(next_job(pool, false).await?.map(|j| j.id().to_string()), Some(up.clone()));
+    pause(pool, &up).await?;
+    assert!(pause(pool, &up).await.is_err());
+    assert_eq!(next_job(pool, false).await?.map(|j| j.id().to_string()).as_deref(), Some("f"));
+    pause(pool, "f").await?;
+    assert!(next_job(pool, false).await?.is_none());
+    assert_eq!(next_job(pool, true).await?.map(|j| j.id().to_string()).as_deref(), Some("later"));
+
+    resume(pool, "f").await?;
+    cancel(pool, &up).await?;
+    assert!(resume(pool, &up).await.is_err());
+    // Передачи из UI других тестов тоже попадают в список: смотрим только свои.
+    let items = list_transfers(pool).await?;
+    assert!(!items.iter().any(|t| t.id == up));
+    let summary: Vec<(TransferKind, &str, &str)> = items
+      .iter()
+      .filter(|t| t.id == "f" || t.id == "later")
+      .map(|t| (t.kind, t.id.as_str(), t.status.as_str()))
+      .collect();
+    assert_eq!(summary, vec![(TransferKind::Download, "f", "queued"), (TransferKind::Download, "later", "queued")]);
+    Ok(())
+  }
+}
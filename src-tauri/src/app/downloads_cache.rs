@@ -0,0 +1,204 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+
+use crate::paths::Paths;
+
+use crate::app::dirs::dir_exists;
+use crate::app::{files, local_copies};
+
+/// Сколько места на диске занимают скачанные файлы одной папки хранилища.
+/// Учитываются только файлы прямо в ее каталоге: вложенные папки идут
+/// отдельными строками.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FolderUsage {
+  pub dir_id: String,
+  /// Путь каталога относительно папки загрузок.
+  pub path: String,
+  pub bytes: u64,
+  pub file_count: i64,
+  /// Последнее обращение к файлам (unix-время); там, где ОС не ведет время
+  /// доступа, — время изменения.
+  pub last_access: Option<i64>
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct ClearReport {
+  pub removed_files: i64,
+  pub freed_bytes: u64,
+  /// Копии, измененные после скачивания: вытеснение их не удаляет.
+  pub kept_edited: i64
+}
+
+/// Каталоги загрузок всех папок. Разные папки с одинаковым путем делят один
+/// каталог — он считается один раз, за первой из них.
+async fn folder_dirs(pool: &SqlitePool) -> anyhow::Result<Vec<(String, PathBuf)>> {
+  let ids: Vec<String> = sqlx::query("SELECT id FROM directories ORDER BY name, id")
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| r.get("id"))
+    .collect();
+  let mut seen: HashSet<PathBuf> = HashSet::new();
+  let mut out = Vec::new();
+  for id in ids {
+    let path = files::build_dir_path(pool, &id).await?;
+    if path.as_os_str().is_empty() || !seen.insert(path.clone()) {
+      continue;
+    }
+    out.push((id, path));
+  }
+  Ok(out)
+}
+
+pub async fn downloads_usage(pool: &SqlitePool, paths: &Paths) -> anyhow::Result<Vec<FolderUsage>> {
+  let root = paths.layout().downloads_dir();
+  let folders = folder_dirs(pool).await?;
+  // Обход диска блокирующий и на больших загрузках долгий — не держим им
+  // поток асинхронного рантайма.
+  tokio::task::spawn_blocking(move || {
+    let mut out = Vec::new();
+    for (dir_id, rel) in folders {
+      let (bytes, file_count, last_access) = scan_dir(&root.join(&rel));
+      if file_count == 0 {
+        continue;
+      }
+      out.push(FolderUsage {
+        dir_id,
+        path: rel.to_string_lossy().to_string(),
+        bytes,
+        file_count,
+        last_access
+      });
+    }
+    out.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.path.cmp(&b.path)));
+    out
+  })
+  .await
+  .map_err(|e| anyhow::anyhow!("Не удалось посчитать загрузки: {e}"))
+}
+
+/// Удаляет скачанные файлы папки (без вложенных папок). Записи в базе и
+/// сообщения в канале не трогаются: файл просто снова станет «не скачан».
+pub async fn clear_downloads(pool: &SqlitePool, paths: &Paths, dir_id: &str) -> anyhow::Result<ClearReport> {
+  if !dir_exists(pool, dir_id).await? {
+    return Err(anyhow::anyhow!("Папка не найдена"));
+  }
+  let rel = files::build_dir_path(pool, dir_id).await?;
+  if rel.as_os_str().is_empty() {
+    return Err(anyhow::anyhow!("У этой папки нет своего каталога загрузок"));
+  }
+  let root = paths.layout().downloads_dir();
+  let dir = root.join(&rel);
+  let mut report = ClearReport::default();
+  let Ok(entries) = std::fs::read_dir(&dir) else {
+    return Ok(report);
+  };
+  for entry in entries.flatten() {
+    let path = entry.path();
+    let Ok(meta) = entry.metadata() else {
+      continue;
+    };
+    if !meta.is_file() {
+      continue;
+    }
+    match std::fs::remove_file(&path) {
+      Ok(()) => {
+        report.removed_files += 1;
+        report.freed_bytes += meta.len();
+        local_copies::forget_path(pool, paths, &path).await?;
+      }
+      Err(e) => {
+        tracing::warn!(event = "downloads_clear_file_failed", path = %path.display(), error = %e, "Не удалось удалить скачанный файл");
+      }
+    }
+  }
+  files::cleanup_empty_dirs(root, Some(&dir));
+  tracing::info!(
+    event = "downloads_cleared",
+    dir_id = dir_id,
+    removed = report.removed_files,
+    freed_bytes = report.freed_bytes,
+    "Скачанные файлы папки удалены"
+  );
+  Ok(report)
+}
+
+fn scan_dir(dir: &Path) -> (u64, i64, Option<i64>) {
+  let mut bytes = 0u64;
+  let mut count = 0i64;
+  let mut last: Option<i64> = None;
+  let Ok(entries) = std::fs::read_dir(dir) else {
+    return (0, 0, None);
+  };
+  for entry in entries.flatten() {
+    let Ok(meta) = entry.metadata() else {
+      continue;
+    };
+    if !meta.is_file() {
+      continue;
+    }
+    bytes += meta.len();
+    count += 1;
+    let touched = meta
+      .accessed()
+      .or_else(|_| meta.modified())
+      .ok()
+      .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+      .map(|d| d.as_secs() as i64);
+    last = last.max(touched);
+  }
+  (bytes, count, last)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[tokio::test]
+  async fn usage_is_per_folder_and_clear_keeps_subfolders() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    let paths = Paths {
+      base_dir: tmp.path().to_path_buf(),
+      data_dir: tmp.path().join("data"),
+      cache_dir: tmp.path().join("cache"),
+      logs_dir: tmp.path().join("logs"),
+      resource_dir: None,
+      portable: false
+    };
+    for (id, parent, name) in [("a", None, "Фото"), ("b", Some("a"), "Отпуск")] {
+      sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES(?, ?, ?, NULL, 0)")
+        .bind(id)
+        .bind(parent)
+        .bind(name)
+        .execute(pool)
+        .await?;
+    }
+    let photos = paths.layout().downloads_dir().join("Фото");
+    let vacation = photos.join("Отпуск");
+    std::fs::create_dir_all(&vacation)?;
+    std::fs::write(photos.join("1.jpg"), vec![0u8; 10])?;
+    std::fs::write(photos.join("2.jpg"), vec![0u8; 5])?;
+    std::fs::write(vacation.join("3.jpg"), vec![0u8; 100])?;
+
+    let usage = downloads_usage(pool, &paths).await?;
+    let shape: Vec<(&str, u64, i64)> = usage.iter().map(|u| (u.dir_id.as_str(), u.bytes, u.file_count)).collect();
+    assert_eq!(shape, vec![("b", 100, 1), ("a", 15, 2)]);
+
+    let report = clear_downloads(pool, &paths, "a").await?;
+    assert_eq!((report.removed_files, report.freed_bytes), (2, 15));
+    assert!(vacation.join("3.jpg").exists());
+    let usage = downloads_usage(pool, &paths).await?;
+    assert_eq!(usage.len(), 1);
+    assert_eq!(usage[0].dir_id, "b");
+    Ok(())
+  }
+}
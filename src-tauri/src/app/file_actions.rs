@@ -0,0 +1,64 @@
+use std::collections::BTreeMap;
+
+use crate::app::open_guard::extension;
+
+/// Что делает двойной клик по файлу.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileAction {
+  #[default]
+  Open,
+  OpenFolder,
+  Preview,
+  Ask
+}
+
+/// Действие по умолчанию и переопределения по расширению. Расширения
+/// хранятся в нижнем регистре без точки.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FileActions {
+  #[serde(default)]
+  pub default: FileAction,
+  #[serde(default)]
+  pub by_extension: BTreeMap<String, FileAction>
+}
+
+impl FileActions {
+  pub fn normalized(self) -> FileActions {
+    let by_extension = self
+      .by_extension
+      .into_iter()
+      .map(|(ext, action)| (ext.trim().trim_start_matches('.').to_lowercase(), action))
+      .filter(|(ext, _)| !ext.is_empty())
+      .collect();
+    FileActions { default: self.default, by_extension }
+  }
+
+  pub fn action_for(&self, file_name: &str) -> FileAction {
+    extension(file_name)
+      .and_then(|ext| self.by_extension.get(&ext).copied())
+      .unwrap_or(self.default)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn extension_overrides_default() {
+    let actions = FileActions {
+      default: FileAction::Preview,
+      by_extension: BTreeMap::from([(".MP4".to_string(), FileAction::OpenFolder), (" ".to_string(), FileAction::Ask)])
+    }
+    .normalized();
+    assert_eq!(actions.by_extension.len(), 1);
+    assert_eq!(actions.action_for("clip.mp4"), FileAction::OpenFolder);
+    assert_eq!(actions.action_for("notes.txt"), FileAction::Preview);
+    assert_eq!(actions.action_for("README"), FileAction::Preview);
+
+    let parsed: FileActions = serde_json::from_str(r#"{"by_extension":{"exe":"ask"}}"#).unwrap();
+    assert_eq!(parsed.default, FileAction::Open);
+    assert_eq!(parsed.action_for("setup.EXE"), FileAction::Ask);
+  }
+}
@@ -2,15 +2,23 @@ use chrono::Utc;
 use crate::sqlx::{self, QueryBuilder, Row};
 use sqlx_sqlite::SqlitePool;
 use ulid::Ulid;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 
-use crate::fsmeta::{FileMeta, make_file_caption, parse_file_caption};
-use crate::telegram::{TelegramService, ChatId};
+use crate::fsmeta::{FileMeta, PartMeta, make_file_caption, make_part_caption, mark_encrypted, parse_file_caption};
+use crate::telegram::{CaptionEdit, DownloadProgress, TelegramService, TgError, ChatId, MessageId, ProgressSink};
 use crate::app::dirs::dir_exists;
+use crate::app::{dir_paths, fulltext, hash_upgrade, indexer, local_cache, local_copies, local_names, partial_downloads, source_channels, storage_channels, vault};
+use crate::app::transcripts::fts_query;
+use crate::app::pending_uploads::{self, Retry, UploadKey};
 use crate::paths::Paths;
 
-fn hash_short(path: &Path) -> anyhow::Result<String> {
+pub(crate) fn hash_short(path: &Path) -> anyhow::Result<String> {
+  Ok(hash_full(path)?.chars().take(8).collect())
+}
+
+/// SHA-256 содержимого файла в hex.
+pub(crate) fn hash_full(path: &Path) -> anyhow::Result<String> {
   use sha2::{Digest, Sha256};
   use std::io::Read;
 
@@ -24,8 +32,7 @@ fn hash_short(path: &Path) -> anyhow::Result<String> {
     }
     hasher.update(&buf[..n]);
   }
-  let digest = hex::encode(hasher.finalize());
-  Ok(digest.chars().take(8).collect())
+  Ok(hex::encode(hasher.finalize()))
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -40,7 +47,46 @@ pub struct FileItem {
   pub tg_chat_id: i64,
   pub tg_msg_id: i64,
   pub created_at: i64,
-  pub is_broken: bool
+  pub is_broken: bool,
+  pub is_favorite: bool,
+  pub link_id: Option<String>,
+  pub media_group_id: Option<String>,
+  pub remote_status: RemoteStatus,
+  /// Фрагмент с подсвеченным совпадением при полнотекстовом поиске.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub snippet: Option<String>
+}
+
+/// Состояние копии файла в канале хранения для значков в списках. Считается
+/// из колонок той же строки, поэтому списку не нужны дополнительные запросы.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemoteStatus {
+  Ok,
+  /// Сообщение пропало или повреждено.
+  Broken,
+  /// Строка уже есть, а сообщение еще не отправлено.
+  PendingUpload,
+  /// Файл хранится несколькими частями.
+  Chunked,
+  /// Содержимое зашифровано на клиенте.
+  Encrypted
+}
+
+impl RemoteStatus {
+  pub fn from_columns(is_broken: bool, tg_msg_id: i64, part_count: i64, encrypted: bool) -> RemoteStatus {
+    if is_broken {
+      RemoteStatus::Broken
+    } else if tg_msg_id <= 0 {
+      RemoteStatus::PendingUpload
+    } else if part_count > 0 {
+      RemoteStatus::Chunked
+    } else if encrypted {
+      RemoteStatus::Encrypted
+    } else {
+      RemoteStatus::Ok
+    }
+  }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -51,18 +97,21 @@ pub enum RepairFileResult {
 
 pub async fn list_files(pool: &SqlitePool, paths: &Paths, dir_id: &str) -> anyhow::Result<Vec<FileItem>> {
   let rows = sqlx::query(
-    "SELECT id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, is_broken FROM files WHERE dir_id = ? ORDER BY name"
+    "SELECT id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, is_broken, is_favorite, media_group_id, part_count, enc_key_id,
+       (SELECT path FROM local_copies WHERE file_id = files.id) AS local_path
+     FROM files WHERE dir_id = ? ORDER BY name"
   )
     .bind(dir_id)
     .fetch_all(pool)
     .await?;
 
-  let dir_path = build_dir_path(pool, dir_id).await?;
   let mut out = Vec::with_capacity(rows.len());
   for row in rows {
     let name: String = row.get("name");
     let size: i64 = row.get("size");
-    let (is_downloaded, local_size) = local_download_info(paths, &dir_path, &name, size);
+    let (is_downloaded, local_size) = local_copies::check(paths, row.try_get::<String,_>("local_path").ok().as_deref());
+    let is_broken = row.get::<i64,_>("is_broken") != 0;
+    let tg_msg_id: i64 = row.get("tg_msg_id");
     out.push(FileItem {
       id: row.get::<String,_>("id"),
       dir_id: row.get::<String,_>("dir_id"),
@@ -72,39 +121,185 @@ pub async fn list_files(pool: &SqlitePool, paths: &Paths, dir_id: &str) -> anyho
       is_downloaded,
       hash: row.get::<String,_>("hash"),
       tg_chat_id: row.get::<i64,_>("tg_chat_id"),
-      tg_msg_id: row.get::<i64,_>("tg_msg_id"),
+      tg_msg_id,
       created_at: row.get::<i64,_>("created_at"),
-      is_broken: row.get::<i64,_>("is_broken") != 0
+      is_broken,
+      is_favorite: row.get::<i64,_>("is_favorite") != 0,
+      link_id: None,
+      media_group_id: row.try_get::<String,_>("media_group_id").ok(),
+      remote_status: RemoteStatus::from_columns(is_broken, tg_msg_id, row.get("part_count"), row.try_get::<String,_>("enc_key_id").is_ok()),
+      snippet: None
     });
   }
+
+  // Ссылки на файлы из других папок показываем рядом с обычными файлами.
+  let link_rows = sqlx::query(
+    "SELECT l.id AS link_id, f.id, f.dir_id, f.name, f.size, f.hash, f.tg_chat_id, f.tg_msg_id, f.created_at, f.is_broken, f.is_favorite, f.media_group_id, f.part_count, f.enc_key_id,
+       (SELECT path FROM local_copies WHERE file_id = f.id) AS local_path
+     FROM links l JOIN files f ON f.id = l.target_id
+     WHERE l.dir_id = ? AND l.target_kind = 'file'"
+  )
+    .bind(dir_id)
+    .fetch_all(pool)
+    .await?;
+  if !link_rows.is_empty() {
+    for row in link_rows {
+      let target_dir_id: String = row.get("dir_id");
+      let name: String = row.get("name");
+      let size: i64 = row.get("size");
+      let (is_downloaded, local_size) = local_copies::check(paths, row.try_get::<String,_>("local_path").ok().as_deref());
+      let is_broken = row.get::<i64,_>("is_broken") != 0;
+      let tg_msg_id: i64 = row.get("tg_msg_id");
+      out.push(FileItem {
+        id: row.get::<String,_>("id"),
+        dir_id: target_dir_id,
+        name,
+        size,
+        local_size,
+        is_downloaded,
+        hash: row.get::<String,_>("hash"),
+        tg_chat_id: row.get::<i64,_>("tg_chat_id"),
+        tg_msg_id,
+        created_at: row.get::<i64,_>("created_at"),
+        is_broken,
+        is_favorite: row.get::<i64,_>("is_favorite") != 0,
+        link_id: Some(row.get::<String,_>("link_id")),
+        media_group_id: row.try_get::<String,_>("media_group_id").ok(),
+        remote_status: RemoteStatus::from_columns(is_broken, tg_msg_id, row.get("part_count"), row.try_get::<String,_>("enc_key_id").is_ok()),
+        snippet: None
+      });
+    }
+    out.sort_by(|a, b| a.name.cmp(&b.name));
+  }
   Ok(out)
 }
 
-pub async fn search_files(
-  pool: &SqlitePool,
-  paths: &Paths,
-  dir_id: Option<&str>,
-  name: Option<&str>,
-  file_type: Option<&str>,
-  limit: Option<i64>
-) -> anyhow::Result<Vec<FileItem>> {
+/// Фильтры поиска. Все, кроме `is_downloaded`, проверяются в SQL; наличие
+/// локальной копии известно только по файловой системе, поэтому этот фильтр
+/// применяется после запроса, а лимит в таком случае считается уже по нему.
+/// Фильтры умной папки хранятся в базе как JSON этой структуры.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SearchFilters {
+  pub dir_id: Option<String>,
+  /// Расширяет `dir_id` на все вложенные папки.
+  pub recursive: bool,
+  pub name: Option<String>,
+  /// Запрос по имени, пути папки и подписи: подходит и подстрока, и слово
+  /// по префиксу из полнотекстового индекса.
+  pub text: Option<String>,
+  /// Файлы, в имени которых есть `text`, из индекса поиска в памяти. Без него
+  /// подстрока в имени ищется через LIKE.
+  #[serde(skip)]
+  pub name_hits: Option<Vec<String>>,
+  pub file_type: Option<String>,
+  pub is_downloaded: Option<bool>,
+  pub is_broken: Option<bool>,
+  pub is_favorite: Option<bool>,
+  pub min_size: Option<i64>,
+  pub max_size: Option<i64>,
+  pub created_from: Option<i64>,
+  pub created_to: Option<i64>,
+  pub sort: SearchSort,
+  pub limit: Option<i64>
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchSort {
+  #[default]
+  NameAsc,
+  NameDesc,
+  SizeAsc,
+  SizeDesc,
+  CreatedAsc,
+  CreatedDesc,
+  /// По релевантности полнотекстового запроса; без запроса — как `NameAsc`.
+  Relevance
+}
+
+impl SearchSort {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      SearchSort::NameAsc => "name_asc",
+      SearchSort::NameDesc => "name_desc",
+      SearchSort::SizeAsc => "size_asc",
+      SearchSort::SizeDesc => "size_desc",
+      SearchSort::CreatedAsc => "created_asc",
+      SearchSort::CreatedDesc => "created_desc",
+      SearchSort::Relevance => "relevance"
+    }
+  }
+
+  pub fn parse(raw: &str) -> Option<Self> {
+    match raw {
+      "name_asc" => Some(SearchSort::NameAsc),
+      "name_desc" => Some(SearchSort::NameDesc),
+      "size_asc" => Some(SearchSort::SizeAsc),
+      "size_desc" => Some(SearchSort::SizeDesc),
+      "created_asc" => Some(SearchSort::CreatedAsc),
+      "created_desc" => Some(SearchSort::CreatedDesc),
+      "relevance" => Some(SearchSort::Relevance),
+      _ => None
+    }
+  }
+
+  fn order_by(self) -> &'static str {
+    match self {
+      SearchSort::NameAsc => " ORDER BY name, id",
+      SearchSort::NameDesc => " ORDER BY name DESC, id",
+      SearchSort::SizeAsc => " ORDER BY size, name, id",
+      SearchSort::SizeDesc => " ORDER BY size DESC, name, id",
+      SearchSort::CreatedAsc => " ORDER BY created_at, name, id",
+      SearchSort::CreatedDesc => " ORDER BY created_at DESC, name, id",
+      // Совпадения только по подстроке ранга не имеют и идут после полнотекстовых.
+      SearchSort::Relevance => " ORDER BY fts_rank IS NULL, fts_rank, name, id"
+    }
+  }
+}
+
+pub async fn search_files(pool: &SqlitePool, paths: &Paths, filters: &SearchFilters) -> anyhow::Result<Vec<FileItem>> {
+  let text = filters.text.as_deref().map(str::trim).filter(|v| !v.is_empty());
+  let fts = text.and_then(fts_query);
   let mut builder = QueryBuilder::new(
-    "SELECT id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, is_broken FROM files"
+    "SELECT id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, is_broken, is_favorite, media_group_id, part_count, enc_key_id,
+       (SELECT path FROM local_copies WHERE file_id = files.id) AS local_path"
   );
-  let dir_id = dir_id.filter(|v| !v.trim().is_empty() && *v != "ROOT");
-  let name = name.map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
-  let file_type = file_type
-    .map(|v| v.trim().to_string())
-    .filter(|v| !v.is_empty())
-    .and_then(|v| {
-      let trimmed = v.trim_start_matches('.').trim().to_string();
-      if trimmed.is_empty() { None } else { Some(trimmed) }
-    });
+  if let Some(fts) = &fts {
+    fulltext::refresh_folders(pool).await?;
+    // Имя весит больше пути папки, путь — больше подписи и расшифровки.
+    builder
+      .push(
+        ", fts_snippet FROM files LEFT JOIN (
+           SELECT s.file_id AS fts_file_id, bm25(file_search_fts, 10.0, 3.0, 1.0, 1.0) AS fts_rank,
+             snippet(file_search_fts, -1, '[', ']', '…', 12) AS fts_snippet
+           FROM file_search_fts JOIN file_search s ON s.id = file_search_fts.rowid
+           WHERE file_search_fts MATCH "
+      )
+      .push_bind(fts.clone())
+      .push(") ON fts_file_id = files.id");
+  } else {
+    builder.push(" FROM files");
+  }
+  let dir_id = filters.dir_id.as_deref().filter(|v| !v.trim().is_empty() && *v != "ROOT");
+  let name = filters.name.as_deref().map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+  let file_type = filters
+    .file_type
+    .as_deref()
+    .map(|v| v.trim_start_matches('.').trim().to_string())
+    .filter(|v| !v.is_empty());
 
   builder.push(" WHERE 1=1");
 
   if let Some(dir_id) = dir_id {
-    builder.push(" AND dir_id = ").push_bind(dir_id);
+    if filters.recursive {
+      builder
+        .push(" AND dir_id IN (WITH RECURSIVE subtree(id) AS (SELECT id FROM directories WHERE id = ")
+        .push_bind(dir_id.to_string())
+        .push(" UNION SELECT d.id FROM directories d JOIN subtree s ON d.parent_id = s.id) SELECT id FROM subtree)");
+    } else {
+      builder.push(" AND dir_id = ").push_bind(dir_id.to_string());
+    }
   }
 
   if let Some(name) = name {
@@ -113,30 +308,80 @@ pub async fn search_files(
       .push_bind(format!("%{}%", name.to_lowercase()));
   }
 
+  // FTS находит слова только по началу, поэтому подстрока («порт» в «report»)
+  // проверяется отдельно.
+  if let Some(text) = text {
+    let pattern = format!("%{}%", text.to_lowercase());
+    builder.push(if fts.is_some() { " AND (fts_file_id IS NOT NULL OR " } else { " AND (" });
+    match &filters.name_hits {
+      Some(ids) if ids.is_empty() => {
+        builder.push("0");
+      }
+      Some(ids) => {
+        builder.push("files.id IN (");
+        let mut separated = builder.separated(", ");
+        for id in ids {
+          separated.push_bind(id.clone());
+        }
+        separated.push_unseparated(")");
+      }
+      None => {
+        builder.push("lower(name) LIKE ").push_bind(pattern.clone());
+      }
+    }
+    builder
+      .push(" OR files.id IN (SELECT file_id FROM file_search WHERE lower(folder) LIKE ")
+      .push_bind(pattern.clone())
+      .push(" OR lower(caption) LIKE ")
+      .push_bind(pattern)
+      .push("))");
+  }
+
   if let Some(file_type) = file_type {
     builder
       .push(" AND lower(name) LIKE ")
       .push_bind(format!("%.{file_type}", file_type = file_type.to_lowercase()));
   }
 
-  builder.push(" ORDER BY name");
-  builder.push(" LIMIT ").push_bind(limit.unwrap_or(500).max(1));
+  if let Some(is_broken) = filters.is_broken {
+    builder.push(" AND is_broken = ").push_bind(i64::from(is_broken));
+  }
+  if let Some(is_favorite) = filters.is_favorite {
+    builder.push(" AND is_favorite = ").push_bind(i64::from(is_favorite));
+  }
+  if let Some(is_downloaded) = filters.is_downloaded {
+    // Фильтр идет в SQL до LIMIT; записи о пропавших копиях сначала забываются,
+    // чтобы таблица совпадала с диском.
+    local_copies::prune(pool, paths).await?;
+    builder.push(if is_downloaded { " AND" } else { " AND NOT" });
+    builder.push(" EXISTS (SELECT 1 FROM local_copies c WHERE c.file_id = files.id)");
+  }
+  if let Some(min_size) = filters.min_size {
+    builder.push(" AND size >= ").push_bind(min_size);
+  }
+  if let Some(max_size) = filters.max_size {
+    builder.push(" AND size <= ").push_bind(max_size);
+  }
+  if let Some(from) = filters.created_from {
+    builder.push(" AND created_at >= ").push_bind(from);
+  }
+  if let Some(to) = filters.created_to {
+    builder.push(" AND created_at <= ").push_bind(to);
+  }
+
+  let sort = if filters.sort == SearchSort::Relevance && fts.is_none() { SearchSort::NameAsc } else { filters.sort };
+  builder.push(sort.order_by());
+  builder.push(" LIMIT ").push_bind(filters.limit.unwrap_or(500).max(1));
 
   let rows = builder.build().fetch_all(pool).await?;
   let mut out = Vec::with_capacity(rows.len());
-  let mut dir_paths: HashMap<String, PathBuf> = HashMap::new();
   for row in rows {
     let dir_id: String = row.get("dir_id");
     let name: String = row.get("name");
     let size: i64 = row.get("size");
-    let dir_path = if let Some(cached) = dir_paths.get(&dir_id) {
-      cached.clone()
-    } else {
-      let built = build_dir_path(pool, &dir_id).await?;
-      dir_paths.insert(dir_id.clone(), built.clone());
-      built
-    };
-    let (is_downloaded, local_size) = local_download_info(paths, &dir_path, &name, size);
+    let (is_downloaded, local_size) = local_copies::check(paths, row.try_get::<String,_>("local_path").ok().as_deref());
+    let is_broken = row.get::<i64,_>("is_broken") != 0;
+    let tg_msg_id: i64 = row.get("tg_msg_id");
     out.push(FileItem {
       id: row.get::<String,_>("id"),
       dir_id,
@@ -146,14 +391,95 @@ pub async fn search_files(
       is_downloaded,
       hash: row.get::<String,_>("hash"),
       tg_chat_id: row.get::<i64,_>("tg_chat_id"),
-      tg_msg_id: row.get::<i64,_>("tg_msg_id"),
+      tg_msg_id,
       created_at: row.get::<i64,_>("created_at"),
-      is_broken: row.get::<i64,_>("is_broken") != 0
+      is_broken,
+      is_favorite: row.get::<i64,_>("is_favorite") != 0,
+      link_id: None,
+      media_group_id: row.try_get::<String,_>("media_group_id").ok(),
+      remote_status: RemoteStatus::from_columns(is_broken, tg_msg_id, row.get("part_count"), row.try_get::<String,_>("enc_key_id").is_ok()),
+      snippet: row.try_get::<String,_>("fts_snippet").ok()
     });
   }
   Ok(out)
 }
 
+/// Все файлы одного альбома в порядке отправки. Id альбома уникален только
+/// внутри чата, поэтому альбом ищется вместе с чатом.
+pub async fn list_group(pool: &SqlitePool, paths: &Paths, chat_id: ChatId, group_id: &str) -> anyhow::Result<Vec<FileItem>> {
+  let ids: Vec<String> = sqlx::query("SELECT id FROM files WHERE tg_chat_id = ? AND media_group_id = ? ORDER BY tg_msg_id")
+    .bind(chat_id)
+    .bind(group_id)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| r.get::<String,_>("id"))
+    .collect();
+  files_by_ids(pool, paths, &ids).await
+}
+
+/// Папки, в которых лежат файлы из списка, без повторов.
+pub async fn dirs_of(pool: &SqlitePool, file_ids: &[String]) -> anyhow::Result<Vec<String>> {
+  if file_ids.is_empty() {
+    return Ok(Vec::new());
+  }
+  let mut builder = QueryBuilder::new("SELECT DISTINCT dir_id FROM files WHERE id IN (");
+  let mut separated = builder.separated(", ");
+  for id in file_ids {
+    separated.push_bind(id);
+  }
+  separated.push_unseparated(") ORDER BY dir_id");
+  Ok(builder.build().fetch_all(pool).await?.into_iter().map(|r| r.get::<String,_>("dir_id")).collect())
+}
+
+/// Загружает файлы по списку id, сохраняя порядок списка. Отсутствующие id пропускаются.
+pub async fn files_by_ids(pool: &SqlitePool, paths: &Paths, ids: &[String]) -> anyhow::Result<Vec<FileItem>> {
+  if ids.is_empty() {
+    return Ok(Vec::new());
+  }
+  let mut builder = QueryBuilder::new(
+    "SELECT id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, is_broken, is_favorite, media_group_id, part_count, enc_key_id,
+       (SELECT path FROM local_copies WHERE file_id = files.id) AS local_path
+     FROM files WHERE id IN ("
+  );
+  let mut separated = builder.separated(", ");
+  for id in ids {
+    separated.push_bind(id);
+  }
+  separated.push_unseparated(")");
+
+  let rows = builder.build().fetch_all(pool).await?;
+  let mut by_id: HashMap<String, FileItem> = HashMap::with_capacity(rows.len());
+  for row in rows {
+    let dir_id: String = row.get("dir_id");
+    let name: String = row.get("name");
+    let size: i64 = row.get("size");
+    let id: String = row.get("id");
+    let (is_downloaded, local_size) = local_copies::check(paths, row.try_get::<String,_>("local_path").ok().as_deref());
+    let is_broken = row.get::<i64,_>("is_broken") != 0;
+    let tg_msg_id: i64 = row.get("tg_msg_id");
+    by_id.insert(id.clone(), FileItem {
+      id,
+      dir_id,
+      name,
+      size,
+      local_size,
+      is_downloaded,
+      hash: row.get::<String,_>("hash"),
+      tg_chat_id: row.get::<i64,_>("tg_chat_id"),
+      tg_msg_id,
+      created_at: row.get::<i64,_>("created_at"),
+      is_broken,
+      is_favorite: row.get::<i64,_>("is_favorite") != 0,
+      link_id: None,
+      media_group_id: row.try_get::<String,_>("media_group_id").ok(),
+      remote_status: RemoteStatus::from_columns(is_broken, tg_msg_id, row.get("part_count"), row.try_get::<String,_>("enc_key_id").is_ok()),
+      snippet: None
+    });
+  }
+  Ok(ids.iter().filter_map(|id| by_id.remove(id)).collect())
+}
+
 pub async fn upload_file(
   pool: &SqlitePool,
   tg: &dyn TelegramService,
@@ -161,20 +487,219 @@ pub async fn upload_file(
   dir_id: &str,
   path: &Path
 ) -> anyhow::Result<String> {
+  upload_file_tagged(pool, tg, chat_id, dir_id, path, &[]).await
+}
+
+/// Как `upload_file`, но дописывает в подпись дополнительные хештеги
+/// (без `#`; недопустимые символы заменяются так же, как в теге папки).
+/// Уже загруженное содержимое не отправляется снова: запись ссылается на
+/// копию найденного сообщения.
+pub async fn upload_file_tagged(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  chat_id: ChatId,
+  dir_id: &str,
+  path: &Path,
+  extra_tags: &[String]
+) -> anyhow::Result<String> {
+  match upload_deduped(pool, tg, chat_id, dir_id, path, extra_tags, Dedup::Reference).await? {
+    DedupUpload::Uploaded(id) | DedupUpload::Referenced(id) => Ok(id),
+    DedupUpload::Duplicate(existing) => Err(anyhow::anyhow!("Файл с таким же содержимым уже есть: {}", existing.name))
+  }
+}
+
+async fn check_upload_source(pool: &SqlitePool, dir_id: &str, path: &Path) -> anyhow::Result<()> {
   if !dir_exists(pool, dir_id).await? {
     return Err(anyhow::anyhow!("Папка не найдена"));
   }
   if !path.is_file() {
     return Err(anyhow::anyhow!("Файл не найден"));
   }
+  Ok(())
+}
+
+/// Что делать, если файл с таким же содержимым уже есть в хранилище.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dedup {
+  /// Не загружать, а вернуть найденный файл, чтобы спросить пользователя.
+  #[default]
+  Ask,
+  /// Скопировать сообщение найденного файла с новой подписью.
+  Reference,
+  /// Загрузить заново.
+  Upload
+}
+
+/// Файл в хранилище с тем же содержимым, что и загружаемый.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Duplicate {
+  pub file_id: String,
+  pub dir_id: String,
+  pub name: String,
+  #[serde(skip)]
+  chat_id: ChatId,
+  #[serde(skip)]
+  message_id: MessageId,
+  #[serde(skip)]
+  enc_key_id: Option<String>
+}
+
+#[derive(Debug, Clone)]
+pub enum DedupUpload {
+  Uploaded(String),
+  /// Новая запись ссылается на копию сообщения уже загруженного файла.
+  Referenced(String),
+  Duplicate(Duplicate)
+}
+
+/// Загрузка с проверкой по полному SHA-256: одинаковое содержимое не
+/// отправляется второй раз. Файлы из частей копией не переиспользуются.
+pub async fn upload_file_dedup(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  chat_id: ChatId,
+  dir_id: &str,
+  path: &Path,
+  dedup: Dedup
+) -> anyhow::Result<DedupUpload> {
+  upload_deduped(pool, tg, chat_id, dir_id, path, &[], dedup).await
+}
+
+async fn upload_deduped(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  chat_id: ChatId,
+  dir_id: &str,
+  path: &Path,
+  extra_tags: &[String],
+  dedup: Dedup
+) -> anyhow::Result<DedupUpload> {
+  check_upload_source(pool, dir_id, path).await?;
+  let content_hash = hash_full(path)?;
+  if dedup != Dedup::Upload {
+    if let Some(existing) = find_duplicate(pool, &content_hash).await? {
+      if dedup == Dedup::Ask {
+        return Ok(DedupUpload::Duplicate(existing));
+      }
+      let id = upload_reference(pool, tg, chat_id, dir_id, path, extra_tags, &content_hash, &existing).await?;
+      return Ok(DedupUpload::Referenced(id));
+    }
+  }
+  let id = upload_hashed(pool, tg, chat_id, dir_id, path, extra_tags, &content_hash).await?;
+  Ok(DedupUpload::Uploaded(id))
+}
+
+/// Уже загруженный файл с тем же содержимым. Файлы каналов-источников не
+/// подходят: это чужие сообщения, на которые нельзя опираться.
+pub async fn find_duplicate(pool: &SqlitePool, content_hash: &str) -> anyhow::Result<Option<Duplicate>> {
+  let row = sqlx::query(
+    "SELECT id, dir_id, name, tg_chat_id, tg_msg_id, enc_key_id FROM files
+     WHERE content_hash = ? AND is_broken = 0 AND part_count = 0
+       AND tg_chat_id NOT IN (SELECT chat_id FROM source_channels)
+     ORDER BY created_at, id LIMIT 1"
+  )
+    .bind(content_hash)
+    .fetch_optional(pool)
+    .await?;
+  Ok(row.map(|r| Duplicate {
+    file_id: r.get("id"),
+    dir_id: r.get("dir_id"),
+    name: r.get("name"),
+    chat_id: r.get("tg_chat_id"),
+    message_id: r.get("tg_msg_id"),
+    enc_key_id: r.try_get("enc_key_id").ok()
+  }))
+}
+
+/// Новая запись без повторной загрузки: сообщение найденного файла
+/// копируется в канал хранения папки с подписью для новой записи.
+/// Зашифрованный оригинал остается зашифрованным тем же ключом.
+#[allow(clippy::too_many_arguments)]
+async fn upload_reference(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  chat_id: ChatId,
+  dir_id: &str,
+  path: &Path,
+  extra_tags: &[String],
+  content_hash: &str,
+  existing: &Duplicate
+) -> anyhow::Result<String> {
+  let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("file").to_string();
+  let size = path.metadata().map(|m| m.len() as i64).unwrap_or(0);
+  let hash_short: String = content_hash.chars().take(8).collect();
+  let id = Ulid::new().to_string();
+  let dir_name = fetch_dir_name(pool, dir_id).await?;
+  let mut caption = make_file_caption_with_tag(
+    &FileMeta { dir_id: dir_id.to_string(), file_id: id.clone(), name: file_name.clone(), hash_short: hash_short.clone() },
+    dir_name.as_deref()
+  );
+  push_extra_tags(&mut caption, extra_tags);
+  let caption = with_encryption(caption, existing.enc_key_id.as_deref());
+  // Папке или ее предку мог быть назначен отдельный канал хранения.
+  let target_chat_id = storage_channels::chat_for_dir(pool, dir_id, chat_id).await?;
+  let copied = tg.copy_messages(existing.chat_id, target_chat_id, vec![existing.message_id]).await?;
+  let Some(message_id) = copied.into_iter().next().flatten() else {
+    return Err(anyhow::anyhow!("Telegram не скопировал сообщение файла"));
+  };
+  if let Err(e) = tg.edit_message_caption(target_chat_id, message_id, caption).await {
+    // Копия с подписью оригинала выглядела бы вторым экземпляром старого файла.
+    if let Err(del) = tg.delete_messages(target_chat_id, vec![message_id], true).await {
+      tracing::warn!(event = "file_reference_cleanup_failed", error = %del, "Не удалось удалить копию сообщения без подписи");
+    }
+    return Err(e.into());
+  }
+  sqlx::query(
+    "INSERT INTO files(id, dir_id, name, size, hash, content_hash, tg_chat_id, tg_msg_id, created_at, is_broken, enc_key_id)
+     VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, 0, ?)"
+  )
+    .bind(&id)
+    .bind(dir_id)
+    .bind(&file_name)
+    .bind(size)
+    .bind(&hash_short)
+    .bind(content_hash)
+    .bind(target_chat_id)
+    .bind(message_id)
+    .bind(Utc::now().timestamp())
+    .bind(existing.enc_key_id.as_deref())
+    .execute(pool)
+    .await?;
+  tracing::info!(event = "file_upload_referenced", file_id = id.as_str(), source_id = existing.file_id.as_str(), "Файл добавлен копией уже загруженного");
+  Ok(id)
+}
 
+async fn upload_hashed(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  chat_id: ChatId,
+  dir_id: &str,
+  path: &Path,
+  extra_tags: &[String],
+  content_hash: &str
+) -> anyhow::Result<String> {
   let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("file").to_string();
   let size = path.metadata().map(|m| m.len() as i64).unwrap_or(0);
-  let hash_short = hash_short(path)?;
+  let hash_short: String = content_hash.chars().take(8).collect();
+  let key = UploadKey { dir_id, name: &file_name, size, hash: &hash_short };
+  match pending_uploads::check_retry(pool, key, Utc::now().timestamp()).await? {
+    Retry::Done(existing) => return Ok(existing),
+    Retry::InFlight => {
+      return Err(anyhow::anyhow!(
+        "Этот файл еще отправляется после прошлой попытки. Он появится в папке, когда Telegram подтвердит отправку."
+      ));
+    }
+    Retry::Fresh => {}
+  }
+  // Папке или ее предку мог быть назначен отдельный канал хранения.
+  let chat_id = storage_channels::chat_for_dir(pool, dir_id, chat_id).await?;
+  let payload = UploadPayload::prepare(pool, path).await?;
+  let enc_key_id = payload.enc_key_id.clone();
   let id = Ulid::new().to_string();
 
   let dir_name = fetch_dir_name(pool, dir_id).await?;
-  let caption = make_file_caption_with_tag(
+  let mut caption = make_file_caption_with_tag(
     &FileMeta {
       dir_id: dir_id.to_string(),
       file_id: id.clone(),
@@ -183,29 +708,404 @@ pub async fn upload_file(
     },
     dir_name.as_deref()
   );
-
-  let uploaded = tg.send_file(chat_id, path.to_path_buf(), caption).await?;
+  push_extra_tags(&mut caption, extra_tags);
+  let caption = with_encryption(caption, enc_key_id.as_deref());
+
+  pending_uploads::begin(pool, &id, key).await?;
+  let pending = pending_uploads::PendingGuard::new(pool, &id);
+  let payload_size = payload.size();
+  if part_count_for(payload_size) > 0 {
+    let meta = FileMeta { dir_id: dir_id.to_string(), file_id: id.clone(), name: file_name.clone(), hash_short: hash_short.clone() };
+    let parts = match upload_chunked(tg, chat_id, &payload.path, &meta, payload_size, CHUNK_SIZE, enc_key_id.as_deref()).await {
+      Ok(parts) => parts,
+      Err(e) => {
+        pending.disarm();
+        pending_uploads::finish(pool, &id).await?;
+        return Err(e);
+      }
+    };
+    save_chunked_file(pool, &meta, size, &parts, enc_key_id.as_deref()).await?;
+    sqlx::query("UPDATE files SET content_hash = ? WHERE id = ?")
+      .bind(content_hash)
+      .bind(&id)
+      .execute(pool)
+      .await?;
+    pending.disarm();
+    pending_uploads::finish(pool, &id).await?;
+    return Ok(id);
+  }
+  let uploaded = match tg.send_file(chat_id, payload.path.clone(), caption).await {
+    Ok(uploaded) => uploaded,
+    // Отметка остается: поздний результат подхватит индексатор, а повтор не отправит дубль.
+    // Зашифрованную копию TDLib еще дочитывает, ее не удаляем.
+    Err(e @ TgError::SendPending) => {
+      pending.disarm();
+      payload.keep();
+      return Err(e.into());
+    }
+    Err(e) => {
+      pending.disarm();
+      pending_uploads::finish(pool, &id).await?;
+      return Err(e.into());
+    }
+  };
+  pending.disarm();
   let created_at = Utc::now().timestamp();
 
   sqlx::query(
-    "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, is_broken)
-     VALUES(?, ?, ?, ?, ?, ?, ?, ?, 0)
-     ON CONFLICT(id) DO UPDATE SET dir_id=excluded.dir_id, name=excluded.name, size=excluded.size, hash=excluded.hash, tg_chat_id=excluded.tg_chat_id, tg_msg_id=excluded.tg_msg_id, is_broken=0"
+    "INSERT INTO files(id, dir_id, name, size, hash, content_hash, tg_chat_id, tg_msg_id, created_at, is_broken, enc_key_id)
+     VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, 0, ?)
+     ON CONFLICT(id) DO UPDATE SET dir_id=excluded.dir_id, name=excluded.name, size=excluded.size, hash=excluded.hash, content_hash=excluded.content_hash, tg_chat_id=excluded.tg_chat_id, tg_msg_id=excluded.tg_msg_id, is_broken=0, broken_reason=NULL, broken_since=NULL, enc_key_id=excluded.enc_key_id"
   )
     .bind(&id)
     .bind(dir_id)
     .bind(&file_name)
     .bind(size)
     .bind(hash_short)
+    .bind(content_hash)
     .bind(uploaded.chat_id)
     .bind(uploaded.message_id)
     .bind(created_at)
+    .bind(enc_key_id.as_deref())
     .execute(pool)
     .await?;
+  pending_uploads::finish(pool, &id).await?;
 
   Ok(id)
 }
 
+/// То, что уходит в канал: сам файл или, при включенном шифровании, его
+/// зашифрованная копия во временной папке. Копия удаляется вместе со значением.
+struct UploadPayload {
+  path: PathBuf,
+  enc_key_id: Option<String>,
+  sealed_dir: Option<PathBuf>
+}
+
+impl UploadPayload {
+  async fn prepare(pool: &SqlitePool, source: &Path) -> anyhow::Result<UploadPayload> {
+    let Some(key) = vault::upload_key(pool).await? else {
+      return Ok(UploadPayload { path: source.to_path_buf(), enc_key_id: None, sealed_dir: None });
+    };
+    let dir = std::env::temp_dir().join("cloudtg-sealed").join(Ulid::new().to_string());
+    std::fs::create_dir_all(&dir)?;
+    // Имя остается прежним: в канале документ выглядит так же, как без шифрования.
+    let name = source.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+    let payload = UploadPayload { path: dir.join(name), enc_key_id: Some(key.id.clone()), sealed_dir: Some(dir) };
+    vault::encrypt_to(&key, source, &payload.path).await?;
+    Ok(payload)
+  }
+
+  fn size(&self) -> u64 {
+    self.path.metadata().map(|m| m.len()).unwrap_or(0)
+  }
+
+  /// Оставляет копию на диске: TDLib еще может ее читать.
+  fn keep(mut self) {
+    self.sealed_dir = None;
+  }
+}
+
+impl Drop for UploadPayload {
+  fn drop(&mut self) {
+    if let Some(dir) = self.sealed_dir.take() {
+      let _ = std::fs::remove_dir_all(dir);
+    }
+  }
+}
+
+/// Файлы больше этого размера уходят частями: так они проходят под обычный
+/// лимит Telegram в 2 ГБ, а не только под премиальные 4 ГБ.
+pub const CHUNK_THRESHOLD: u64 = 2000 * 1024 * 1024;
+/// Размер одной части, с запасом до лимита.
+pub const CHUNK_SIZE: u64 = 1900 * 1024 * 1024;
+
+/// Одна часть файла, хранящегося в канале несколькими сообщениями.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilePart {
+  pub index: u32,
+  pub chat_id: ChatId,
+  pub message_id: MessageId,
+  pub size: i64
+}
+
+/// Сколько частей нужно файлу такого размера; 0 — файл уходит целиком.
+pub fn part_count_for(size: u64) -> u32 {
+  if size <= CHUNK_THRESHOLD {
+    0
+  } else {
+    size.div_ceil(CHUNK_SIZE) as u32
+  }
+}
+
+/// Копирует часть `index` (с единицы) исходного файла в `target` и
+/// возвращает ее размер.
+fn write_part(source: &Path, target: &Path, index: u32, chunk_size: u64) -> anyhow::Result<u64> {
+  use std::io::{Read, Seek, SeekFrom};
+
+  let mut input = std::fs::File::open(source)?;
+  input.seek(SeekFrom::Start(u64::from(index - 1) * chunk_size))?;
+  let mut output = std::fs::File::create(target)?;
+  let copied = std::io::copy(&mut input.take(chunk_size), &mut output)?;
+  output.sync_all()?;
+  Ok(copied)
+}
+
+/// Дописывает скачанную часть в конец собираемого файла.
+fn append_part(part: &Path, output: &mut std::fs::File) -> anyhow::Result<u64> {
+  let mut input = std::fs::File::open(part)?;
+  Ok(std::io::copy(&mut input, output)?)
+}
+
+/// Отправляет файл частями с подписями `#part i/N`. Если часть не ушла,
+/// уже отправленные удаляются, чтобы в канале не оставалось обрывков.
+async fn upload_chunked(
+  tg: &dyn TelegramService,
+  chat_id: ChatId,
+  source: &Path,
+  meta: &FileMeta,
+  size: u64,
+  chunk_size: u64,
+  enc_key_id: Option<&str>
+) -> anyhow::Result<Vec<FilePart>> {
+  let total = size.div_ceil(chunk_size).max(1) as u32;
+  let tmp = tempfile::tempdir()?;
+  let mut sent: Vec<FilePart> = Vec::with_capacity(total as usize);
+  for index in 1..=total {
+    let part_path = tmp.path().join(format!("{}.part{index}", sanitize_component(&meta.name)));
+    let part_size = write_part(source, &part_path, index, chunk_size)?;
+    let caption = with_encryption(make_part_caption(&PartMeta { file: meta.clone(), index, total }), enc_key_id);
+    tracing::info!(event = "file_part_upload", file_id = meta.file_id.as_str(), part = index, total = total, "Отправка части файла");
+    let res = tg.send_file(chat_id, part_path.clone(), caption).await;
+    let _ = std::fs::remove_file(&part_path);
+    match res {
+      Ok(uploaded) => sent.push(FilePart {
+        index,
+        chat_id: uploaded.chat_id,
+        message_id: uploaded.message_id,
+        size: part_size as i64
+      }),
+      Err(e) => {
+        if !sent.is_empty() {
+          let ids = sent.iter().map(|p| p.message_id).collect();
+          if let Err(del) = tg.delete_messages(chat_id, ids, true).await {
+            tracing::warn!(event = "file_part_cleanup_failed", file_id = meta.file_id.as_str(), error = %del, "Не удалось удалить уже отправленные части");
+          }
+        }
+        return Err(anyhow::anyhow!("Не удалось отправить часть {index} из {total}: {e}"));
+      }
+    }
+  }
+  Ok(sent)
+}
+
+/// Записывает файл из частей: строка файла ссылается на первую часть.
+async fn save_chunked_file(
+  pool: &SqlitePool,
+  meta: &FileMeta,
+  size: i64,
+  parts: &[FilePart],
+  enc_key_id: Option<&str>
+) -> anyhow::Result<()> {
+  let Some(first) = parts.first() else {
+    return Err(anyhow::anyhow!("Нет отправленных частей файла"));
+  };
+  sqlx::query(
+    "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, is_broken, part_count, enc_key_id)
+     VALUES(?, ?, ?, ?, ?, ?, ?, ?, 0, ?, ?)
+     ON CONFLICT(id) DO UPDATE SET dir_id=excluded.dir_id, name=excluded.name, size=excluded.size, hash=excluded.hash,
+       tg_chat_id=excluded.tg_chat_id, tg_msg_id=excluded.tg_msg_id, is_broken=0, broken_reason=NULL, broken_since=NULL, part_count=excluded.part_count,
+       enc_key_id=excluded.enc_key_id"
+  )
+    .bind(&meta.file_id)
+    .bind(&meta.dir_id)
+    .bind(&meta.name)
+    .bind(size)
+    .bind(&meta.hash_short)
+    .bind(first.chat_id)
+    .bind(first.message_id)
+    .bind(Utc::now().timestamp())
+    .bind(parts.len() as i64)
+    .bind(enc_key_id)
+    .execute(pool)
+    .await?;
+  for part in parts {
+    save_part(pool, &meta.file_id, part).await?;
+  }
+  Ok(())
+}
+
+pub(crate) async fn save_part(pool: &SqlitePool, file_id: &str, part: &FilePart) -> anyhow::Result<()> {
+  sqlx::query(
+    "INSERT INTO file_parts(file_id, part_index, tg_chat_id, tg_msg_id, size) VALUES(?, ?, ?, ?, ?)
+     ON CONFLICT(file_id, part_index) DO UPDATE SET tg_chat_id=excluded.tg_chat_id, tg_msg_id=excluded.tg_msg_id, size=excluded.size"
+  )
+    .bind(file_id)
+    .bind(part.index)
+    .bind(part.chat_id)
+    .bind(part.message_id)
+    .bind(part.size)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+/// Части файла по порядку; пусто, если файл хранится целиком.
+pub async fn file_parts(pool: &SqlitePool, file_id: &str) -> anyhow::Result<Vec<FilePart>> {
+  let rows = sqlx::query(
+    "SELECT part_index, tg_chat_id, tg_msg_id, size FROM file_parts WHERE file_id = ? ORDER BY part_index"
+  )
+    .bind(file_id)
+    .fetch_all(pool)
+    .await?;
+  Ok(rows
+    .into_iter()
+    .map(|r| FilePart {
+      index: r.get::<i64,_>("part_index") as u32,
+      chat_id: r.get("tg_chat_id"),
+      message_id: r.get("tg_msg_id"),
+      size: r.get("size")
+    })
+    .collect())
+}
+
+async fn part_count_of(pool: &SqlitePool, file_id: &str) -> anyhow::Result<i64> {
+  Ok(sqlx::query("SELECT part_count FROM files WHERE id = ?")
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?
+    .map(|r| r.get::<i64,_>("part_count"))
+    .unwrap_or(0))
+}
+
+/// Скачивает все части по порядку и склеивает их в `target`. Ход скачивания
+/// сообщается по файлу целиком, а не по частям.
+async fn download_chunked(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  file_id: &str,
+  part_count: i64,
+  target: &Path,
+  progress: Option<&ProgressSink>
+) -> anyhow::Result<PathBuf> {
+  let parts = file_parts(pool, file_id).await?;
+  let complete = parts.len() as i64 == part_count
+    && parts.iter().enumerate().all(|(i, p)| p.index as usize == i + 1);
+  if !complete {
+    return Err(anyhow::anyhow!("В канале найдены не все части файла: {} из {part_count}", parts.len()));
+  }
+  let total: u64 = parts.iter().map(|p| p.size.max(0) as u64).sum();
+  let file_name = target.file_name().and_then(|n| n.to_str()).unwrap_or("file").to_string();
+  let assembling = target.with_file_name(format!("{file_name}.assembling"));
+  let mut output = std::fs::File::create(&assembling)?;
+  let mut offset = 0u64;
+  for part in &parts {
+    let part_path = target.with_file_name(format!("{file_name}.part{}", part.index));
+    let sink = progress.map(|outer| {
+      let outer = outer.clone();
+      let sink: ProgressSink = std::sync::Arc::new(move |p: DownloadProgress| {
+        outer(DownloadProgress { downloaded: offset + p.downloaded, total: Some(total) })
+      });
+      sink
+    });
+    let res = download_message(tg, part.chat_id, part.message_id, part_path.clone(), sink.as_ref()).await;
+    let appended = res
+      .map_err(|e| anyhow::anyhow!("Не удалось скачать часть {} из {part_count}: {e}", part.index))
+      .and_then(|path| append_part(&path, &mut output));
+    let _ = std::fs::remove_file(&part_path);
+    match appended {
+      Ok(written) => offset += written,
+      Err(e) => {
+        drop(output);
+        let _ = std::fs::remove_file(&assembling);
+        return Err(e);
+      }
+    }
+  }
+  output.sync_all()?;
+  drop(output);
+  std::fs::rename(&assembling, target)?;
+  Ok(target.to_path_buf())
+}
+
+/// Файл из частей чинится только правкой подписей: переотправка или загрузка
+/// заново для него означала бы гигабайты трафика.
+async fn repair_chunked(pool: &SqlitePool, tg: &dyn TelegramService, file_id: &str) -> anyhow::Result<RepairFileResult> {
+  let edited = rewrite_captions(pool, tg, &[file_id.to_string()], None).await?;
+  if let Some((_, error)) = edited.failed.into_iter().next() {
+    return Err(anyhow::anyhow!("Не удалось восстановить подписи частей файла: {error}"));
+  }
+  sqlx::query("UPDATE files SET is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
+    .bind(file_id)
+    .execute(pool)
+    .await?;
+  Ok(RepairFileResult::Repaired)
+}
+
+/// Переносит части файла вслед за основным сообщением, уже скопированным в
+/// `new_chat_id` как `first_msg_id`. Части, которые не удалось скопировать,
+/// остаются ссылаться на старый канал — это видно при проверке переезда.
+pub async fn copy_parts(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  file_id: &str,
+  new_chat_id: ChatId,
+  first_msg_id: MessageId
+) -> anyhow::Result<()> {
+  let parts = file_parts(pool, file_id).await?;
+  for part in parts.into_iter().filter(|p| p.chat_id != new_chat_id) {
+    let message_id = if part.index == 1 {
+      Some(first_msg_id)
+    } else {
+      match tg.copy_messages(part.chat_id, new_chat_id, vec![part.message_id]).await {
+        Ok(ids) => ids.into_iter().next().flatten(),
+        Err(e) => {
+          tracing::warn!(event = "file_part_copy_failed", file_id = file_id, part = part.index, error = %e, "Не удалось скопировать часть файла в новый канал");
+          continue;
+        }
+      }
+    };
+    let Some(message_id) = message_id else {
+      tracing::warn!(event = "file_part_copy_failed", file_id = file_id, part = part.index, "Не удалось скопировать часть файла в новый канал");
+      continue;
+    };
+    save_part(pool, file_id, &FilePart { chat_id: new_chat_id, message_id, ..part }).await?;
+  }
+  Ok(())
+}
+
+/// В канале-источнике чинить нечего: файл либо еще лежит в канале, либо
+/// его удалил владелец канала.
+async fn repair_source_file(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  file_id: &str,
+  chat_id: ChatId,
+  msg_id: MessageId
+) -> anyhow::Result<RepairFileResult> {
+  if !tg.message_exists(chat_id, msg_id).await? {
+    return Err(anyhow::anyhow!("Сообщение удалено из канала-источника, восстановить файл нельзя"));
+  }
+  sqlx::query("UPDATE files SET is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
+    .bind(file_id)
+    .execute(pool)
+    .await?;
+  Ok(RepairFileResult::Repaired)
+}
+
+/// Подписи всех частей файла под папку из `meta`.
+fn part_caption_edits(meta: &FileMeta, parts: &[FilePart], enc_key_id: Option<&str>) -> Vec<CaptionEdit> {
+  let total = parts.len() as u32;
+  parts
+    .iter()
+    .map(|part| CaptionEdit {
+      chat_id: part.chat_id,
+      message_id: part.message_id,
+      caption: with_encryption(make_part_caption(&PartMeta { file: meta.clone(), index: part.index, total }), enc_key_id)
+    })
+    .collect()
+}
+
 pub async fn move_file(
   pool: &SqlitePool,
   tg: &dyn TelegramService,
@@ -216,7 +1116,7 @@ pub async fn move_file(
   if !dir_exists(pool, new_dir_id).await? {
     return Err(anyhow::anyhow!("Папка не найдена"));
   }
-  let row = sqlx::query("SELECT id, dir_id, name, hash, tg_chat_id, tg_msg_id FROM files WHERE id = ?")
+  let row = sqlx::query("SELECT id, dir_id, name, hash, tg_chat_id, tg_msg_id, part_count, enc_key_id FROM files WHERE id = ?")
     .bind(file_id)
     .fetch_optional(pool)
     .await?;
@@ -227,26 +1127,61 @@ pub async fn move_file(
   if current_dir == new_dir_id {
     return Ok(());
   }
+  if row.get::<i64,_>("part_count") > 0 || source_channels::is_source_chat(pool, row.get("tg_chat_id")).await? {
+    // Части не переотправляются: копия каждой стоила бы еще одной загрузки.
+    // Чужой канал-источник не трогаем вовсе, папка там живет только в базе.
+    let edited = rewrite_captions(pool, tg, &[file_id.to_string()], Some(new_dir_id)).await?;
+    if let Some((_, error)) = edited.failed.into_iter().next() {
+      return Err(anyhow::anyhow!("Не удалось обновить подписи файла: {error}"));
+    }
+    sqlx::query("UPDATE files SET dir_id = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
+      .bind(new_dir_id)
+      .bind(file_id)
+      .execute(pool)
+      .await?;
+    return Ok(());
+  }
   let name: String = row.get("name");
+  recaption_file(pool, tg, storage_chat_id, &row, new_dir_id, &name).await
+}
+
+/// Ставит файлу подпись под папку `new_dir_id` и имя `name` и записывает их в
+/// базу. Если подпись не правится, ищет сообщение заново, затем
+/// переотправляет файл и, наконец, копирует сообщение. `row` — строка
+/// `files` с `id`, `hash`, `tg_chat_id`, `tg_msg_id` и `enc_key_id`.
+async fn recaption_file(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  storage_chat_id: ChatId,
+  row: &sqlx_sqlite::SqliteRow,
+  new_dir_id: &str,
+  name: &str
+) -> anyhow::Result<()> {
+  let file_id: String = row.get("id");
+  let file_id = file_id.as_str();
   let hash: String = row.get("hash");
   let mut msg_id: i64 = row.get("tg_msg_id");
   let mut msg_chat_id: i64 = row.get("tg_chat_id");
   let dir_name = fetch_dir_name(pool, new_dir_id).await?;
 
-  let caption = make_file_caption_with_tag(
-    &FileMeta {
-      dir_id: new_dir_id.to_string(),
-      file_id: file_id.to_string(),
-      name: name.clone(),
-      hash_short: hash.clone()
-    },
-    dir_name.as_deref()
+  let caption = with_encryption(
+    make_file_caption_with_tag(
+      &FileMeta {
+        dir_id: new_dir_id.to_string(),
+        file_id: file_id.to_string(),
+        name: name.to_string(),
+        hash_short: hash.clone()
+      },
+      dir_name.as_deref()
+    ),
+    row.try_get::<String,_>("enc_key_id").ok().as_deref()
   );
 
   let mut edit_error = match tg.edit_message_caption(msg_chat_id, msg_id, caption.clone()).await {
     Ok(()) => {
-      sqlx::query("UPDATE files SET dir_id = ?, tg_chat_id = ?, tg_msg_id = ?, is_broken = 0 WHERE id = ?")
+      sqlx::query("UPDATE files SET dir_id = ?, name = ?, tg_chat_id = ?, tg_msg_id = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
         .bind(new_dir_id)
+        .bind(name)
         .bind(msg_chat_id)
         .bind(msg_id)
         .bind(file_id)
@@ -269,7 +1204,7 @@ pub async fn move_file(
     if found_chat_id != msg_chat_id || found_msg_id != msg_id {
       msg_chat_id = found_chat_id;
       msg_id = found_msg_id;
-      sqlx::query("UPDATE files SET tg_chat_id = ?, tg_msg_id = ?, is_broken = 0 WHERE id = ?")
+      sqlx::query("UPDATE files SET tg_chat_id = ?, tg_msg_id = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
         .bind(msg_chat_id)
         .bind(msg_id)
         .bind(file_id)
@@ -278,8 +1213,9 @@ pub async fn move_file(
     }
     match tg.edit_message_caption(msg_chat_id, msg_id, caption.clone()).await {
       Ok(()) => {
-        sqlx::query("UPDATE files SET dir_id = ?, tg_chat_id = ?, tg_msg_id = ?, is_broken = 0 WHERE id = ?")
+        sqlx::query("UPDATE files SET dir_id = ?, name = ?, tg_chat_id = ?, tg_msg_id = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
           .bind(new_dir_id)
+          .bind(name)
           .bind(msg_chat_id)
           .bind(msg_id)
           .bind(file_id)
@@ -287,77 +1223,620 @@ pub async fn move_file(
           .await?;
         return Ok(());
       }
-      Err(e) => {
-        edit_error = Some(e.to_string());
+      Err(e) => {
+        edit_error = Some(e.to_string());
+      }
+    }
+  }
+
+  let resend_error = match tg.send_file_from_message(msg_chat_id, msg_id, caption.clone()).await {
+    Ok(uploaded) => {
+      // Сначала база: удаление старого сообщения придет обновлением и не должно найти запись.
+      sqlx::query("UPDATE files SET dir_id = ?, name = ?, tg_chat_id = ?, tg_msg_id = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
+        .bind(new_dir_id)
+        .bind(name)
+        .bind(uploaded.chat_id)
+        .bind(uploaded.message_id)
+        .bind(file_id)
+        .execute(pool)
+        .await?;
+      let _ = tg.delete_messages(msg_chat_id, vec![msg_id], true).await;
+      return Ok(());
+    }
+    Err(e) => {
+      tracing::warn!(
+        event = "file_resend_for_move_failed",
+        file_id = file_id,
+        error = %e,
+        "Не удалось переотправить файл с новой подписью, пробую копирование"
+      );
+      Some(e.to_string())
+    }
+  };
+
+  let ids = tg
+    .copy_messages(msg_chat_id, msg_chat_id, vec![msg_id])
+    .await
+    .map_err(|e| anyhow::anyhow!("Не удалось скопировать файл для обновления подписи: {e}"))?;
+  let new_msg_id = ids
+    .into_iter()
+    .next()
+    .flatten()
+    .ok_or_else(|| {
+      let mut detail = String::new();
+      if let Some(err) = edit_error.as_deref() {
+        detail.push_str(&format!(" Ошибка редактирования подписи: {err}."));
+      }
+      if let Some(err) = resend_error.as_deref() {
+        detail.push_str(&format!(" Ошибка переотправки: {err}."));
+      }
+      anyhow::anyhow!(
+        "TDLib не вернул id скопированного сообщения.{detail} Возможно, в канале включена защита контента."
+      )
+    })?;
+
+  if let Err(e) = tg.edit_message_caption(msg_chat_id, new_msg_id, caption).await {
+    tracing::warn!(
+      event = "file_caption_update_failed",
+      file_id = file_id,
+      error = %e,
+      "Не удалось обновить подпись файла после копирования"
+    );
+    let _ = tg.delete_messages(msg_chat_id, vec![new_msg_id], true).await;
+    return Err(anyhow::anyhow!("Не удалось обновить подпись файла после копирования"));
+  }
+
+  sqlx::query("UPDATE files SET dir_id = ?, name = ?, tg_chat_id = ?, tg_msg_id = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
+    .bind(new_dir_id)
+    .bind(name)
+    .bind(msg_chat_id)
+    .bind(new_msg_id)
+    .bind(file_id)
+    .execute(pool)
+    .await?;
+  let _ = tg.delete_messages(msg_chat_id, vec![msg_id], true).await;
+  Ok(())
+}
+
+/// Переименовывает файл: подпись в Telegram, запись в базе и скачанную копию.
+/// Файлу из частей переписываются подписи всех частей, у файла из
+/// канала-источника имя меняется только в базе.
+pub async fn rename_file(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  paths: &Paths,
+  storage_chat_id: ChatId,
+  file_id: &str,
+  new_name: &str
+) -> anyhow::Result<()> {
+  let name = new_name.trim();
+  if name.is_empty() {
+    return Err(anyhow::anyhow!("Имя файла не может быть пустым"));
+  }
+  if name.contains(['/', '\\']) {
+    return Err(anyhow::anyhow!("Имя файла не может содержать / или \\"));
+  }
+  let row = sqlx::query("SELECT id, dir_id, name, hash, tg_chat_id, tg_msg_id, part_count, enc_key_id FROM files WHERE id = ?")
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?;
+  let Some(row) = row else {
+    return Err(anyhow::anyhow!("Файл не найден"));
+  };
+  let old_name: String = row.get("name");
+  if old_name == name {
+    return Ok(());
+  }
+  if row.get::<i64,_>("part_count") > 0 || source_channels::is_source_chat(pool, row.get("tg_chat_id")).await? {
+    // Подписи частей собираются из базы, поэтому имя меняется до них
+    // и возвращается, если подписи переписать не удалось.
+    sqlx::query("UPDATE files SET name = ? WHERE id = ?")
+      .bind(name)
+      .bind(file_id)
+      .execute(pool)
+      .await?;
+    let error = match rewrite_captions(pool, tg, &[file_id.to_string()], None).await {
+      Ok(edited) => edited.failed.into_iter().next().map(|(_, e)| e),
+      Err(e) => Some(e.to_string())
+    };
+    if let Some(error) = error {
+      sqlx::query("UPDATE files SET name = ? WHERE id = ?")
+        .bind(&old_name)
+        .bind(file_id)
+        .execute(pool)
+        .await?;
+      return Err(anyhow::anyhow!("Не удалось обновить подписи файла: {error}"));
+    }
+  } else {
+    let dir_id: String = row.get("dir_id");
+    recaption_file(pool, tg, storage_chat_id, &row, &dir_id, name).await?;
+  }
+
+  if let Err(e) = rename_local_download(pool, paths, file_id, name).await {
+    tracing::warn!(
+      event = "file_rename_local_failed",
+      file_id = file_id,
+      error = %e,
+      "Не удалось переименовать скачанную копию файла"
+    );
+  }
+  Ok(())
+}
+
+/// Отмечает файл избранным или снимает отметку. Отметка хранится только в базе.
+pub async fn set_favorite(pool: &SqlitePool, file_id: &str, favorite: bool) -> anyhow::Result<()> {
+  let updated = sqlx::query("UPDATE files SET is_favorite = ? WHERE id = ?")
+    .bind(i64::from(favorite))
+    .bind(file_id)
+    .execute(pool)
+    .await?
+    .rows_affected();
+  if updated == 0 {
+    return Err(anyhow::anyhow!("Файл не найден"));
+  }
+  Ok(())
+}
+
+/// Итог пакетной операции над файлами: какие прошли, какие нет и почему.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct BatchOutcome {
+  pub done: Vec<String>,
+  pub failed: Vec<(String, String)>
+}
+
+/// Переписывает подписи файлов одной пачкой через `edit_message_captions`.
+/// С `target_dir_id` подпись собирается под новую папку (перенос), иначе под
+/// текущую. У файла из частей правятся подписи всех частей, и он считается
+/// готовым, только если прошли все; файлы каналов-источников готовы сразу.
+/// БД не трогает: что делать с результатом, решает вызывающий.
+pub async fn rewrite_captions(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  file_ids: &[String],
+  target_dir_id: Option<&str>
+) -> anyhow::Result<BatchOutcome> {
+  let mut outcome = BatchOutcome::default();
+  let mut dir_names: HashMap<String, Option<String>> = HashMap::new();
+  let mut batch: Vec<(String, usize)> = Vec::new();
+  let mut edits: Vec<CaptionEdit> = Vec::new();
+  for file_id in file_ids {
+    let row = sqlx::query("SELECT dir_id, name, hash, tg_chat_id, tg_msg_id, part_count, enc_key_id FROM files WHERE id = ?")
+      .bind(file_id)
+      .fetch_optional(pool)
+      .await?;
+    let Some(row) = row else {
+      outcome.failed.push((file_id.clone(), "Файл не найден".into()));
+      continue;
+    };
+    let dir_id = target_dir_id.map(str::to_string).unwrap_or_else(|| row.get("dir_id"));
+    if !dir_names.contains_key(&dir_id) {
+      let name = fetch_dir_name(pool, &dir_id).await?;
+      dir_names.insert(dir_id.clone(), name);
+    }
+    let meta = FileMeta {
+      dir_id: dir_id.clone(),
+      file_id: file_id.clone(),
+      name: row.get("name"),
+      hash_short: row.get("hash")
+    };
+    if source_channels::is_source_chat(pool, row.get("tg_chat_id")).await? {
+      outcome.done.push(file_id.clone());
+      continue;
+    }
+    let enc_key_id = row.try_get::<String,_>("enc_key_id").ok();
+    if row.get::<i64,_>("part_count") > 0 {
+      let parts = file_parts(pool, file_id).await?;
+      if parts.is_empty() {
+        outcome.failed.push((file_id.clone(), "Части файла не найдены".into()));
+        continue;
+      }
+      batch.push((file_id.clone(), parts.len()));
+      edits.extend(part_caption_edits(&meta, &parts, enc_key_id.as_deref()));
+      continue;
+    }
+    let caption = with_encryption(make_file_caption_with_tag(&meta, dir_names[&dir_id].as_deref()), enc_key_id.as_deref());
+    batch.push((file_id.clone(), 1));
+    edits.push(CaptionEdit { chat_id: row.get("tg_chat_id"), message_id: row.get("tg_msg_id"), caption });
+  }
+  if edits.is_empty() {
+    return Ok(outcome);
+  }
+
+  let mut results = tg.edit_message_captions(edits).await?.into_iter();
+  for (file_id, count) in batch {
+    let file_results: Vec<_> = results.by_ref().take(count).collect();
+    match file_results.into_iter().find_map(Result::err) {
+      None => outcome.done.push(file_id),
+      Some(e) => outcome.failed.push((file_id, e.to_string()))
+    }
+  }
+  Ok(outcome)
+}
+
+/// Переносит файлы в папку: сначала правит подписи всех файлов пачкой; что так
+/// не прошло, копирует пачкой в том же чате с новой подписью, а оставшиеся
+/// проводит по одному через `move_file`. Записи перенесенных пачкой файлов
+/// обновляются одной транзакцией. Ошибка одного файла не останавливает остальные.
+pub async fn move_files(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  storage_chat_id: ChatId,
+  file_ids: &[String],
+  new_dir_id: &str
+) -> anyhow::Result<BatchOutcome> {
+  if !dir_exists(pool, new_dir_id).await? {
+    return Err(anyhow::anyhow!("Папка не найдена"));
+  }
+  let mut outcome = BatchOutcome::default();
+  let mut pending: Vec<String> = Vec::new();
+  for file_id in file_ids {
+    let current: Option<String> = sqlx::query("SELECT dir_id FROM files WHERE id = ?")
+      .bind(file_id)
+      .fetch_optional(pool)
+      .await?
+      .map(|r| r.get("dir_id"));
+    match current {
+      Some(dir_id) if dir_id == new_dir_id => outcome.done.push(file_id.clone()),
+      Some(_) => pending.push(file_id.clone()),
+      None => outcome.failed.push((file_id.clone(), "Файл не найден".into()))
+    }
+  }
+
+  let edited = rewrite_captions(pool, tg, &pending, Some(new_dir_id)).await?;
+  let copied = copy_with_captions(pool, tg, edited.failed, new_dir_id).await?;
+
+  // Сначала база: удаление старых сообщений придет обновлением и не должно найти записи.
+  let mut tx = pool.begin().await?;
+  for file_id in &edited.done {
+    sqlx::query("UPDATE files SET dir_id = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
+      .bind(new_dir_id)
+      .bind(file_id)
+      .execute(&mut *tx)
+      .await?;
+  }
+  for copy in &copied.relocated {
+    sqlx::query("UPDATE files SET dir_id = ?, tg_chat_id = ?, tg_msg_id = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
+      .bind(new_dir_id)
+      .bind(copy.chat_id)
+      .bind(copy.message_id)
+      .bind(&copy.file_id)
+      .execute(&mut *tx)
+      .await?;
+  }
+  tx.commit().await?;
+  for (chat_id, msg_ids) in copied.superseded {
+    let _ = tg.delete_messages(chat_id, msg_ids, true).await;
+  }
+  outcome.done.extend(edited.done);
+  outcome.done.extend(copied.relocated.into_iter().map(|copy| copy.file_id));
+
+  for (file_id, error) in copied.left {
+    tracing::warn!(
+      event = "file_caption_batch_update_failed",
+      file_id = file_id.as_str(),
+      error = error.as_str(),
+      "Пакетный перенос не прошел, переношу файл по одному"
+    );
+    match move_file(pool, tg, storage_chat_id, &file_id, new_dir_id).await {
+      Ok(()) => outcome.done.push(file_id),
+      Err(e) => outcome.failed.push((file_id, e.to_string()))
+    }
+  }
+  Ok(outcome)
+}
+
+/// Файл, перенесенный копией сообщения с новой подписью.
+struct RelocatedFile {
+  file_id: String,
+  chat_id: ChatId,
+  message_id: MessageId
+}
+
+#[derive(Default)]
+struct BatchCopy {
+  relocated: Vec<RelocatedFile>,
+  /// Старые сообщения перенесенных файлов; удаляются после записи в базу.
+  superseded: HashMap<ChatId, Vec<MessageId>>,
+  /// Файлы, которые не удалось скопировать пачкой, с последней ошибкой.
+  left: Vec<(String, String)>
+}
+
+/// Копирует сообщения файлов внутри их чатов одним `copy_messages` на чат и
+/// правит подписи копий одной пачкой. Файлы из частей и неудачные копии
+/// остаются в `left`; лишние копии удаляются. БД не трогает.
+async fn copy_with_captions(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  failed: Vec<(String, String)>,
+  new_dir_id: &str
+) -> anyhow::Result<BatchCopy> {
+  let mut result = BatchCopy::default();
+  if failed.is_empty() {
+    return Ok(result);
+  }
+  let dir_name = fetch_dir_name(pool, new_dir_id).await?;
+  let mut by_chat: HashMap<ChatId, Vec<(String, MessageId, String, String)>> = HashMap::new();
+  for (file_id, error) in failed {
+    let row = sqlx::query("SELECT name, hash, tg_chat_id, tg_msg_id, part_count, enc_key_id FROM files WHERE id = ?")
+      .bind(&file_id)
+      .fetch_optional(pool)
+      .await?;
+    let Some(row) = row.filter(|row| row.get::<i64,_>("part_count") == 0) else {
+      result.left.push((file_id, error));
+      continue;
+    };
+    let caption = with_encryption(
+      make_file_caption_with_tag(
+        &FileMeta {
+          dir_id: new_dir_id.to_string(),
+          file_id: file_id.clone(),
+          name: row.get("name"),
+          hash_short: row.get("hash")
+        },
+        dir_name.as_deref()
+      ),
+      row.try_get::<String,_>("enc_key_id").ok().as_deref()
+    );
+    by_chat.entry(row.get("tg_chat_id")).or_default().push((file_id, row.get("tg_msg_id"), caption, error));
+  }
+
+  let mut copies: Vec<(String, ChatId, MessageId, MessageId)> = Vec::new();
+  let mut edits: Vec<CaptionEdit> = Vec::new();
+  for (chat_id, files) in by_chat {
+    let msg_ids: Vec<MessageId> = files.iter().map(|(_, msg_id, _, _)| *msg_id).collect();
+    let copied = match tg.copy_messages(chat_id, chat_id, msg_ids).await {
+      Ok(copied) => copied,
+      Err(e) => {
+        result.left.extend(files.into_iter().map(|(file_id, _, _, _)| (file_id, e.to_string())));
+        continue;
+      }
+    };
+    let mut copied = copied.into_iter();
+    for (file_id, old_msg_id, caption, error) in files {
+      match copied.next().flatten() {
+        Some(new_msg_id) => {
+          copies.push((file_id, chat_id, old_msg_id, new_msg_id));
+          edits.push(CaptionEdit { chat_id, message_id: new_msg_id, caption });
+        }
+        None => result.left.push((file_id, error))
+      }
+    }
+  }
+  if edits.is_empty() {
+    return Ok(result);
+  }
+
+  let mut results = match tg.edit_message_captions(edits).await {
+    Ok(results) => results.into_iter().map(|res| res.map_err(|e| e.to_string())).collect::<Vec<_>>().into_iter(),
+    Err(e) => vec![Err(e.to_string()); copies.len()].into_iter()
+  };
+  let mut discarded: HashMap<ChatId, Vec<MessageId>> = HashMap::new();
+  for (file_id, chat_id, old_msg_id, new_msg_id) in copies {
+    match results.next().unwrap_or_else(|| Err("TDLib не вернул результат правки подписи".into())) {
+      Ok(()) => {
+        result.superseded.entry(chat_id).or_default().push(old_msg_id);
+        result.relocated.push(RelocatedFile { file_id, chat_id, message_id: new_msg_id });
+      }
+      Err(error) => {
+        discarded.entry(chat_id).or_default().push(new_msg_id);
+        result.left.push((file_id, error));
       }
     }
   }
+  for (chat_id, msg_ids) in discarded {
+    let _ = tg.delete_messages(chat_id, msg_ids, true).await;
+  }
+  Ok(result)
+}
 
-  let resend_error = match tg.send_file_from_message(msg_chat_id, msg_id, caption.clone()).await {
-    Ok(uploaded) => {
-      let _ = tg.delete_messages(msg_chat_id, vec![msg_id], true).await;
-      sqlx::query("UPDATE files SET dir_id = ?, tg_chat_id = ?, tg_msg_id = ?, is_broken = 0 WHERE id = ?")
-        .bind(new_dir_id)
-        .bind(uploaded.chat_id)
-        .bind(uploaded.message_id)
-        .bind(file_id)
-        .execute(pool)
-        .await?;
-      return Ok(());
-    }
-    Err(e) => {
-      tracing::warn!(
-        event = "file_resend_for_move_failed",
-        file_id = file_id,
-        error = %e,
-        "Не удалось переотправить файл с новой подписью, пробую копирование"
-      );
-      Some(e.to_string())
-    }
-  };
+/// Итог копирования: id новых файлов и исходные файлы, которые не скопировались.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct CopyOutcome {
+  pub created: Vec<String>,
+  pub failed: Vec<(String, String)>
+}
 
-  let ids = tg
-    .copy_messages(msg_chat_id, msg_chat_id, vec![msg_id])
-    .await
-    .map_err(|e| anyhow::anyhow!("Не удалось скопировать файл для обновления подписи: {e}"))?;
-  let new_msg_id = ids
+/// Сообщение исходного файла (целиком или часть) и его будущая копия.
+struct CopiedMessage {
+  chat_id: ChatId,
+  message_id: MessageId,
+  part: Option<FilePart>,
+  caption: String,
+  copy: Option<MessageId>
+}
+
+struct CopyJob {
+  source_id: String,
+  meta: FileMeta,
+  messages: Vec<CopiedMessage>,
+  error: Option<String>
+}
+
+/// Копирует файлы в папку без повторной загрузки: сообщения (и части)
+/// копируются в канал хранения одним `copy_messages` на исходный чат, копии
+/// получают подписи с новыми id одной пачкой, а записи создаются одной
+/// транзакцией. При совпадении имени в папке к копии добавляется «(копия)».
+pub async fn copy_files(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  storage_chat_id: ChatId,
+  file_ids: &[String],
+  target_dir_id: &str
+) -> anyhow::Result<CopyOutcome> {
+  if !dir_exists(pool, target_dir_id).await? {
+    return Err(anyhow::anyhow!("Папка не найдена"));
+  }
+  let dir_name = fetch_dir_name(pool, target_dir_id).await?;
+  let mut taken: HashSet<String> = sqlx::query("SELECT name FROM files WHERE dir_id = ?")
+    .bind(target_dir_id)
+    .fetch_all(pool)
+    .await?
     .into_iter()
-    .next()
-    .flatten()
-    .ok_or_else(|| {
-      let mut detail = String::new();
-      if let Some(err) = edit_error.as_deref() {
-        detail.push_str(&format!(" Ошибка редактирования подписи: {err}."));
+    .map(|r| r.get::<String,_>("name"))
+    .collect();
+  let mut outcome = CopyOutcome::default();
+  let mut jobs: Vec<CopyJob> = Vec::new();
+  for source_id in file_ids {
+    let row = sqlx::query("SELECT name, hash, tg_chat_id, tg_msg_id, part_count, enc_key_id FROM files WHERE id = ?")
+      .bind(source_id)
+      .fetch_optional(pool)
+      .await?;
+    let Some(row) = row else {
+      outcome.failed.push((source_id.clone(), "Файл не найден".into()));
+      continue;
+    };
+    let meta = FileMeta {
+      dir_id: target_dir_id.to_string(),
+      file_id: Ulid::new().to_string(),
+      name: copy_name(&row.get::<String,_>("name"), &mut taken),
+      hash_short: row.get("hash")
+    };
+    let enc_key_id = row.try_get::<String,_>("enc_key_id").ok();
+    let messages = if row.get::<i64,_>("part_count") > 0 {
+      let parts = file_parts(pool, source_id).await?;
+      if parts.is_empty() {
+        outcome.failed.push((source_id.clone(), "Части файла не найдены".into()));
+        continue;
       }
-      if let Some(err) = resend_error.as_deref() {
-        detail.push_str(&format!(" Ошибка переотправки: {err}."));
+      let edits = part_caption_edits(&meta, &parts, enc_key_id.as_deref());
+      parts
+        .into_iter()
+        .zip(edits)
+        .map(|(part, edit)| CopiedMessage {
+          chat_id: part.chat_id,
+          message_id: part.message_id,
+          part: Some(part),
+          caption: edit.caption,
+          copy: None
+        })
+        .collect()
+    } else {
+      vec![CopiedMessage {
+        chat_id: row.get("tg_chat_id"),
+        message_id: row.get("tg_msg_id"),
+        part: None,
+        caption: with_encryption(make_file_caption_with_tag(&meta, dir_name.as_deref()), enc_key_id.as_deref()),
+        copy: None
+      }]
+    };
+    jobs.push(CopyJob { source_id: source_id.clone(), meta, messages, error: None });
+  }
+
+  let mut by_chat: HashMap<ChatId, Vec<(usize, usize)>> = HashMap::new();
+  for (j, job) in jobs.iter().enumerate() {
+    for (m, msg) in job.messages.iter().enumerate() {
+      by_chat.entry(msg.chat_id).or_default().push((j, m));
+    }
+  }
+  for (chat_id, slots) in by_chat {
+    let msg_ids: Vec<MessageId> = slots.iter().map(|&(j, m)| jobs[j].messages[m].message_id).collect();
+    match tg.copy_messages(chat_id, storage_chat_id, msg_ids).await {
+      Ok(copied) => {
+        let mut copied = copied.into_iter();
+        for (j, m) in slots {
+          jobs[j].messages[m].copy = copied.next().flatten();
+        }
       }
-      anyhow::anyhow!(
-        "TDLib не вернул id скопированного сообщения.{detail} Возможно, в канале включена защита контента."
-      )
-    })?;
+      Err(e) => {
+        for (j, _) in slots {
+          jobs[j].error.get_or_insert_with(|| format!("Не удалось скопировать сообщение файла: {e}"));
+        }
+      }
+    }
+  }
 
-  if let Err(e) = tg.edit_message_caption(msg_chat_id, new_msg_id, caption).await {
-    tracing::warn!(
-      event = "file_caption_update_failed",
-      file_id = file_id,
-      error = %e,
-      "Не удалось обновить подпись файла после копирования"
-    );
-    let _ = tg.delete_messages(msg_chat_id, vec![new_msg_id], true).await;
-    return Err(anyhow::anyhow!("Не удалось обновить подпись файла после копирования"));
+  let mut edits: Vec<CaptionEdit> = Vec::new();
+  let mut edited: Vec<(usize, usize)> = Vec::new();
+  for (j, job) in jobs.iter_mut().enumerate() {
+    if job.error.is_none() && job.messages.iter().any(|msg| msg.copy.is_none()) {
+      job.error = Some("TDLib не вернул id скопированного сообщения. Возможно, в канале включена защита контента.".into());
+    }
+    if job.error.is_some() {
+      continue;
+    }
+    edited.push((j, job.messages.len()));
+    edits.extend(job.messages.iter().map(|msg| CaptionEdit {
+      chat_id: storage_chat_id,
+      message_id: msg.copy.unwrap_or_default(),
+      caption: msg.caption.clone()
+    }));
+  }
+  if !edits.is_empty() {
+    let total = edits.len();
+    let mut results = match tg.edit_message_captions(edits).await {
+      Ok(results) => results.into_iter().map(|res| res.map_err(|e| e.to_string())).collect::<Vec<_>>().into_iter(),
+      Err(e) => vec![Err(e.to_string()); total].into_iter()
+    };
+    for (j, count) in edited {
+      let job_results: Vec<_> = results.by_ref().take(count).collect();
+      let error = job_results.into_iter().find_map(Result::err);
+      jobs[j].error = error.map(|e| format!("Не удалось обновить подпись копии: {e}"));
+    }
   }
 
-  let _ = tg.delete_messages(msg_chat_id, vec![msg_id], true).await;
+  let mut tx = pool.begin().await?;
+  let mut discarded: Vec<MessageId> = Vec::new();
+  let created_at = Utc::now().timestamp();
+  for job in jobs {
+    if let Some(error) = job.error {
+      discarded.extend(job.messages.iter().filter_map(|msg| msg.copy));
+      outcome.failed.push((job.source_id, error));
+      continue;
+    }
+    // Строка файла из частей ссылается на первую часть, как при загрузке.
+    let first = job.messages.first().and_then(|msg| msg.copy).unwrap_or_default();
+    sqlx::query(
+      "INSERT INTO files(id, dir_id, name, size, hash, content_hash, tg_chat_id, tg_msg_id, created_at, is_broken,
+         part_count, enc_key_id, media_kind, remote_unique_id)
+       SELECT ?, ?, ?, size, hash, content_hash, ?, ?, ?, 0, part_count, enc_key_id, media_kind, remote_unique_id
+       FROM files WHERE id = ?"
+    )
+      .bind(&job.meta.file_id)
+      .bind(&job.meta.dir_id)
+      .bind(&job.meta.name)
+      .bind(storage_chat_id)
+      .bind(first)
+      .bind(created_at)
+      .bind(&job.source_id)
+      .execute(&mut *tx)
+      .await?;
+    for msg in &job.messages {
+      let Some(part) = msg.part.as_ref() else {
+        continue;
+      };
+      sqlx::query("INSERT INTO file_parts(file_id, part_index, tg_chat_id, tg_msg_id, size) VALUES(?, ?, ?, ?, ?)")
+        .bind(&job.meta.file_id)
+        .bind(part.index)
+        .bind(storage_chat_id)
+        .bind(msg.copy.unwrap_or_default())
+        .bind(part.size)
+        .execute(&mut *tx)
+        .await?;
+    }
+    outcome.created.push(job.meta.file_id);
+  }
+  tx.commit().await?;
+  if !discarded.is_empty() {
+    let _ = tg.delete_messages(storage_chat_id, discarded, true).await;
+  }
+  Ok(outcome)
+}
 
-  sqlx::query("UPDATE files SET dir_id = ?, tg_chat_id = ?, tg_msg_id = ?, is_broken = 0 WHERE id = ?")
-    .bind(new_dir_id)
-    .bind(msg_chat_id)
-    .bind(new_msg_id)
-    .bind(file_id)
-    .execute(pool)
-    .await?;
-  Ok(())
+/// Имя копии, не занятое в папке назначения: «отчет (копия).pdf»,
+/// затем «отчет (копия 2).pdf» и так далее. Выбранное имя становится занятым.
+pub(crate) fn copy_name(name: &str, taken: &mut HashSet<String>) -> String {
+  let mut candidate = name.to_string();
+  if taken.contains(&candidate) {
+    let (stem, ext) = split_name(name);
+    candidate = format!("{stem} (копия){ext}");
+    let mut i = 2;
+    while taken.contains(&candidate) {
+      candidate = format!("{stem} (копия {i}){ext}");
+      i += 1;
+    }
+  }
+  taken.insert(candidate.clone());
+  candidate
 }
 
 pub async fn delete_file(
@@ -366,7 +1845,7 @@ pub async fn delete_file(
   paths: &Paths,
   file_id: &str
 ) -> anyhow::Result<()> {
-  let row = sqlx::query("SELECT tg_msg_id, tg_chat_id, dir_id, name, size FROM files WHERE id = ?")
+  let row = sqlx::query("SELECT tg_msg_id, tg_chat_id FROM files WHERE id = ?")
     .bind(file_id)
     .fetch_optional(pool)
     .await?;
@@ -375,13 +1854,19 @@ pub async fn delete_file(
   };
   let msg_id: i64 = row.get("tg_msg_id");
   let msg_chat_id: i64 = row.get("tg_chat_id");
-  let dir_id: String = row.get("dir_id");
-  let name: String = row.get("name");
-  let size: i64 = row.get("size");
-  if let Err(e) = tg.delete_messages(msg_chat_id, vec![msg_id], true).await {
-    tracing::warn!(event = "file_delete_message_failed", file_id = file_id, error = %e, "Не удалось удалить сообщение файла в TG");
+  let mut grouped: HashMap<ChatId, Vec<MessageId>> = HashMap::new();
+  grouped.entry(msg_chat_id).or_default().push(msg_id);
+  add_part_messages(pool, file_id, &mut grouped).await?;
+  for (chat_id, msg_ids) in grouped {
+    // Из канала-источника файл убирается только из дерева.
+    if source_channels::is_source_chat(pool, chat_id).await? {
+      continue;
+    }
+    if let Err(e) = tg.delete_messages(chat_id, msg_ids, true).await {
+      tracing::warn!(event = "file_delete_message_failed", file_id = file_id, error = %e, "Не удалось удалить сообщение файла в TG");
+    }
   }
-  if let Err(e) = remove_local_download(pool, paths, &dir_id, &name, size).await {
+  if let Err(e) = remove_local_download(pool, paths, file_id).await {
     tracing::warn!(event = "file_delete_local_failed", file_id = file_id, error = %e, "Не удалось удалить локальный файл");
   }
   sqlx::query("DELETE FROM files WHERE id = ?")
@@ -400,56 +1885,94 @@ pub async fn delete_files(
   if file_ids.is_empty() {
     return Ok(());
   }
-  struct Row {
-    id: String,
-    dir_id: String,
-    name: String,
-    size: i64
-  }
-  let mut rows: Vec<Row> = Vec::new();
+  let mut ids: Vec<String> = Vec::new();
   let mut grouped: std::collections::HashMap<i64, Vec<i64>> = std::collections::HashMap::new();
   for id in file_ids {
-    if let Some(row) = sqlx::query("SELECT tg_msg_id, tg_chat_id, dir_id, name, size FROM files WHERE id = ?")
+    if let Some(row) = sqlx::query("SELECT tg_msg_id, tg_chat_id FROM files WHERE id = ?")
       .bind(id)
       .fetch_optional(pool)
       .await? {
       let msg_id = row.get::<i64,_>("tg_msg_id");
       let msg_chat_id = row.get::<i64,_>("tg_chat_id");
-      let dir_id = row.get::<String,_>("dir_id");
-      let name = row.get::<String,_>("name");
-      let size = row.get::<i64,_>("size");
       grouped.entry(msg_chat_id).or_default().push(msg_id);
-      rows.push(Row { id: id.clone(), dir_id, name, size });
+      add_part_messages(pool, id, &mut grouped).await?;
+      ids.push(id.clone());
     }
   }
   if !grouped.is_empty() {
     for (msg_chat_id, msg_ids) in grouped {
+      if source_channels::is_source_chat(pool, msg_chat_id).await? {
+        continue;
+      }
       if let Err(e) = tg.delete_messages(msg_chat_id, msg_ids, true).await {
         tracing::warn!(event = "file_delete_many_message_failed", count = file_ids.len(), error = %e, "Не удалось удалить сообщения файлов в TG");
       }
     }
   }
-  for row in rows {
-    if let Err(e) = remove_local_download(pool, paths, &row.dir_id, &row.name, row.size).await {
-      tracing::warn!(event = "file_delete_local_failed", file_id = row.id.as_str(), error = %e, "Не удалось удалить локальный файл");
+  for id in ids {
+    if let Err(e) = remove_local_download(pool, paths, &id).await {
+      tracing::warn!(event = "file_delete_local_failed", file_id = id.as_str(), error = %e, "Не удалось удалить локальный файл");
     }
     sqlx::query("DELETE FROM files WHERE id = ?")
-      .bind(&row.id)
+      .bind(&id)
       .execute(pool)
       .await?;
   }
   Ok(())
 }
 
+/// Добавляет к удаляемым сообщения частей файла; первая часть обычно уже
+/// есть в списке как основное сообщение.
+async fn add_part_messages(
+  pool: &SqlitePool,
+  file_id: &str,
+  grouped: &mut HashMap<ChatId, Vec<MessageId>>
+) -> anyhow::Result<()> {
+  for part in file_parts(pool, file_id).await? {
+    let ids = grouped.entry(part.chat_id).or_default();
+    if !ids.contains(&part.message_id) {
+      ids.push(part.message_id);
+    }
+  }
+  Ok(())
+}
+
 pub async fn download_file(
   pool: &SqlitePool,
   tg: &dyn TelegramService,
   paths: &Paths,
   storage_chat_id: ChatId,
   file_id: &str,
-  overwrite: bool
+  overwrite: bool,
+  progress: Option<ProgressSink>
+) -> anyhow::Result<PathBuf> {
+  let tracker = partial_downloads::Tracker::new(progress);
+  let res = download_file_tracked(pool, tg, paths, storage_chat_id, file_id, overwrite, &tracker).await;
+  let recorded = match &res {
+    Ok(_) => partial_downloads::finish(pool, file_id).await,
+    Err(e) => partial_downloads::interrupted(pool, file_id, tracker.downloaded(), &e.to_string()).await
+  };
+  if let Err(e) = recorded {
+    tracing::warn!(event = "partial_download_record_failed", file_id = file_id, error = %e, "Не удалось записать состояние скачивания");
+  }
+  if res.is_ok() {
+    if let Err(e) = local_cache::accessed(pool, paths, file_id).await {
+      tracing::warn!(event = "local_cache_update_failed", file_id = file_id, error = %e, "Не удалось обновить кэш загрузок");
+    }
+  }
+  res
+}
+
+async fn download_file_tracked(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  paths: &Paths,
+  storage_chat_id: ChatId,
+  file_id: &str,
+  overwrite: bool,
+  tracker: &partial_downloads::Tracker
 ) -> anyhow::Result<PathBuf> {
-  let row = sqlx::query("SELECT id, dir_id, name, size, tg_chat_id, tg_msg_id FROM files WHERE id = ?")
+  let row = sqlx::query("SELECT id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, part_count, enc_key_id FROM files WHERE id = ?")
     .bind(file_id)
     .fetch_optional(pool)
     .await?;
@@ -459,29 +1982,54 @@ pub async fn download_file(
   let dir_id: String = row.get("dir_id");
   let name: String = row.get("name");
   let size: i64 = row.get("size");
+  let hash: String = row.get("hash");
+  let part_count: i64 = row.get("part_count");
+  let enc_key_id = row.try_get::<String,_>("enc_key_id").ok();
   let mut msg_chat_id: i64 = row.get("tg_chat_id");
   let mut msg_id: i64 = row.get("tg_msg_id");
+  // Запоминаем до обновления размера и координат: от них считался хэш импорта.
+  let import_seed = indexer::is_import_hash(msg_chat_id, msg_id, &name, size, &hash);
 
   let dir_path = build_dir_path(pool, &dir_id).await?;
-  let base_dir = paths.cache_dir.join("downloads").join(&dir_path);
+  let base_dir = paths.layout().downloads_dir().join(&dir_path);
   std::fs::create_dir_all(&base_dir)?;
-  let existing = find_local_download(paths, &dir_path, &name, size);
+  let existing = local_copies::lookup(pool, paths, file_id).await?.map(|copy| copy.path);
   if let Some(existing_path) = existing.clone() {
     if !overwrite {
       return Ok(existing_path);
     }
   }
+  // Ключ проверяем до скачивания, чтобы не качать то, что нечем открыть.
+  if let Some(key_id) = enc_key_id.as_deref() {
+    vault::key_for(key_id)?;
+  }
+  partial_downloads::begin(pool, file_id, size).await?;
+  let progress = Some(tracker.sink());
   let target_path = if overwrite {
-    existing.unwrap_or_else(|| preferred_target_path(&base_dir, &name))
+    existing.unwrap_or_else(|| preferred_target_path(&base_dir, &name, file_id))
   } else {
-    resolve_target_path(&base_dir, &name, size)?
+    resolve_target_path(&base_dir, &name, file_id, size)?
   };
   if overwrite && target_path.exists() {
     let _ = std::fs::remove_file(&target_path);
   }
 
-  if let Ok(path) = tg.download_message_file(msg_chat_id, msg_id, target_path.clone()).await {
+  if part_count > 0 {
+    let path = download_chunked(pool, tg, file_id, part_count, &target_path, progress.as_ref()).await?;
+    open_downloaded(&path, enc_key_id.as_deref()).await?;
+    update_file_size_from_local(pool, file_id, &path).await?;
+    local_copies::record(pool, paths, file_id, &path).await?;
+    mark_downloaded(&path, msg_chat_id, msg_id);
+    hash_upgrade::enqueue(file_id, &path, &hash, false);
+    return Ok(path);
+  }
+
+  if let Ok(path) = download_message(tg, msg_chat_id, msg_id, target_path.clone(), progress.as_ref()).await {
+    open_downloaded(&path, enc_key_id.as_deref()).await?;
     update_file_size_from_local(pool, file_id, &path).await?;
+    local_copies::record(pool, paths, file_id, &path).await?;
+    mark_downloaded(&path, msg_chat_id, msg_id);
+    hash_upgrade::enqueue(file_id, &path, &hash, import_seed);
     return Ok(path);
   }
 
@@ -491,7 +2039,7 @@ pub async fn download_file(
     if found_chat_id != msg_chat_id || found_msg_id != msg_id {
       msg_chat_id = found_chat_id;
       msg_id = found_msg_id;
-      sqlx::query("UPDATE files SET tg_chat_id = ?, tg_msg_id = ?, is_broken = 0 WHERE id = ?")
+      sqlx::query("UPDATE files SET tg_chat_id = ?, tg_msg_id = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
         .bind(msg_chat_id)
         .bind(msg_id)
         .bind(file_id)
@@ -500,24 +2048,57 @@ pub async fn download_file(
     }
   }
 
-  let path = tg.download_message_file(msg_chat_id, msg_id, target_path.clone()).await?;
+  let path = download_message(tg, msg_chat_id, msg_id, target_path.clone(), progress.as_ref()).await?;
+  open_downloaded(&path, enc_key_id.as_deref()).await?;
   update_file_size_from_local(pool, file_id, &path).await?;
+  local_copies::record(pool, paths, file_id, &path).await?;
+  mark_downloaded(&path, msg_chat_id, msg_id);
+  hash_upgrade::enqueue(file_id, &path, &hash, import_seed);
   Ok(path)
 }
 
+/// Расшифровывает скачанный файл на месте. Если не вышло, шифротекст не
+/// оставляем: под именем файла он выглядел бы как испорченная копия.
+async fn open_downloaded(path: &Path, enc_key_id: Option<&str>) -> anyhow::Result<()> {
+  let Some(key_id) = enc_key_id else {
+    return Ok(());
+  };
+  if let Err(e) = vault::decrypt_in_place(key_id, path).await {
+    let _ = std::fs::remove_file(path);
+    return Err(e);
+  }
+  Ok(())
+}
+
+async fn download_message(
+  tg: &dyn TelegramService,
+  chat_id: ChatId,
+  message_id: MessageId,
+  target: PathBuf,
+  progress: Option<&ProgressSink>
+) -> Result<PathBuf, TgError> {
+  match progress {
+    Some(sink) => tg.download_message_file_with_progress(chat_id, message_id, target, sink.clone()).await,
+    None => tg.download_message_file(chat_id, message_id, target).await
+  }
+}
+
+/// Скачанное из канала помечается для защит ОС; ссылка на сообщение
+/// попадает в Mark-of-the-Web как источник.
+fn mark_downloaded(path: &Path, chat_id: ChatId, message_id: MessageId) {
+  let origin = build_message_link(chat_id, message_id).ok();
+  crate::quarantine::mark_downloaded(path, origin.as_deref());
+}
+
 pub async fn find_local_download_path(pool: &SqlitePool, paths: &Paths, file_id: &str) -> anyhow::Result<Option<PathBuf>> {
-  let row = sqlx::query("SELECT dir_id, name, size FROM files WHERE id = ?")
+  let row = sqlx::query("SELECT id FROM files WHERE id = ?")
     .bind(file_id)
     .fetch_optional(pool)
     .await?;
-  let Some(row) = row else {
+  if row.is_none() {
     return Err(anyhow::anyhow!("Файл не найден"));
-  };
-  let dir_id: String = row.get("dir_id");
-  let name: String = row.get("name");
-  let size: i64 = row.get("size");
-  let dir_path = build_dir_path(pool, &dir_id).await?;
-  Ok(find_local_download(paths, &dir_path, &name, size))
+  }
+  Ok(local_copies::lookup(pool, paths, file_id).await?.map(|copy| copy.path))
 }
 
 pub async fn repair_file(
@@ -528,22 +2109,27 @@ pub async fn repair_file(
   file_id: &str,
   upload_path: Option<&Path>
 ) -> anyhow::Result<RepairFileResult> {
-  let row = sqlx::query("SELECT id, dir_id, name, size, hash, tg_chat_id, tg_msg_id FROM files WHERE id = ?")
+  let row = sqlx::query("SELECT id, dir_id, name, hash, tg_chat_id, tg_msg_id, enc_key_id FROM files WHERE id = ?")
     .bind(file_id)
     .fetch_optional(pool)
     .await?;
   let Some(row) = row else {
     return Err(anyhow::anyhow!("Файл не найден"));
   };
+  if part_count_of(pool, file_id).await? > 0 {
+    return repair_chunked(pool, tg, file_id).await;
+  }
+  if source_channels::is_source_chat(pool, row.get("tg_chat_id")).await? {
+    return repair_source_file(pool, tg, file_id, row.get("tg_chat_id"), row.get("tg_msg_id")).await;
+  }
 
   let dir_id: String = row.get("dir_id");
   let name: String = row.get("name");
-  let size: i64 = row.get("size");
   let hash: String = row.get("hash");
   let mut msg_chat_id: i64 = row.get("tg_chat_id");
   let mut msg_id: i64 = row.get("tg_msg_id");
   let dir_name = fetch_dir_name(pool, &dir_id).await?;
-  let caption = make_file_caption_with_tag(
+  let base_caption = make_file_caption_with_tag(
     &FileMeta {
       dir_id: dir_id.clone(),
       file_id: file_id.to_string(),
@@ -552,9 +2138,10 @@ pub async fn repair_file(
     },
     dir_name.as_deref()
   );
+  let caption = with_encryption(base_caption.clone(), row.try_get::<String,_>("enc_key_id").ok().as_deref());
 
   if tg.edit_message_caption(msg_chat_id, msg_id, caption.clone()).await.is_ok() {
-    sqlx::query("UPDATE files SET tg_chat_id = ?, tg_msg_id = ?, is_broken = 0 WHERE id = ?")
+    sqlx::query("UPDATE files SET tg_chat_id = ?, tg_msg_id = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
       .bind(msg_chat_id)
       .bind(msg_id)
       .bind(file_id)
@@ -569,7 +2156,7 @@ pub async fn repair_file(
     msg_chat_id = found_chat_id;
     msg_id = found_msg_id;
     if tg.edit_message_caption(msg_chat_id, msg_id, caption.clone()).await.is_ok() {
-      sqlx::query("UPDATE files SET tg_chat_id = ?, tg_msg_id = ?, is_broken = 0 WHERE id = ?")
+      sqlx::query("UPDATE files SET tg_chat_id = ?, tg_msg_id = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
         .bind(msg_chat_id)
         .bind(msg_id)
         .bind(file_id)
@@ -582,8 +2169,7 @@ pub async fn repair_file(
   let source_path = if let Some(p) = upload_path {
     Some(p.to_path_buf())
   } else {
-    let dir_path = build_dir_path(pool, &dir_id).await?;
-    find_local_download(paths, &dir_path, &name, size)
+    local_copies::lookup(pool, paths, file_id).await?.map(|copy| copy.path)
   };
 
   let Some(source_path) = source_path else {
@@ -593,10 +2179,15 @@ pub async fn repair_file(
     return Err(anyhow::anyhow!("Файл не найден"));
   }
 
-  let uploaded = tg.send_file(storage_chat_id, source_path, caption).await?;
-  sqlx::query("UPDATE files SET tg_chat_id = ?, tg_msg_id = ?, is_broken = 0 WHERE id = ?")
+  // Локальная копия открыта, поэтому шифруется заново по текущим настройкам.
+  let payload = UploadPayload::prepare(pool, &source_path).await?;
+  let caption = with_encryption(base_caption, payload.enc_key_id.as_deref());
+  let target_chat_id = storage_channels::chat_for_dir(pool, &dir_id, storage_chat_id).await?;
+  let uploaded = tg.send_file(target_chat_id, payload.path.clone(), caption).await?;
+  sqlx::query("UPDATE files SET tg_chat_id = ?, tg_msg_id = ?, enc_key_id = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
     .bind(uploaded.chat_id)
     .bind(uploaded.message_id)
+    .bind(payload.enc_key_id.as_deref())
     .bind(file_id)
     .execute(pool)
     .await?;
@@ -604,6 +2195,122 @@ pub async fn repair_file(
   Ok(RepairFileResult::Repaired)
 }
 
+/// Отправляет файл заново из `source_path` и переводит запись на новое сообщение.
+/// Старое сообщение не трогается; возвращаются его chat_id и message_id.
+pub async fn reupload_file(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  storage_chat_id: ChatId,
+  file_id: &str,
+  source_path: &Path
+) -> anyhow::Result<(ChatId, i64)> {
+  let row = sqlx::query("SELECT dir_id, name, hash, tg_chat_id, tg_msg_id FROM files WHERE id = ?")
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?;
+  let Some(row) = row else {
+    return Err(anyhow::anyhow!("Файл не найден"));
+  };
+  if !source_path.is_file() {
+    return Err(anyhow::anyhow!("Файл не найден"));
+  }
+  let dir_id: String = row.get("dir_id");
+  let dir_name = fetch_dir_name(pool, &dir_id).await?;
+  let target_chat_id = storage_channels::chat_for_dir(pool, &dir_id, storage_chat_id).await?;
+  let payload = UploadPayload::prepare(pool, source_path).await?;
+  let caption = with_encryption(
+    make_file_caption_with_tag(
+      &FileMeta {
+        dir_id,
+        file_id: file_id.to_string(),
+        name: row.get("name"),
+        hash_short: row.get("hash")
+      },
+      dir_name.as_deref()
+    ),
+    payload.enc_key_id.as_deref()
+  );
+
+  let uploaded = tg.send_file(target_chat_id, payload.path.clone(), caption).await?;
+  sqlx::query("UPDATE files SET tg_chat_id = ?, tg_msg_id = ?, enc_key_id = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
+    .bind(uploaded.chat_id)
+    .bind(uploaded.message_id)
+    .bind(payload.enc_key_id.as_deref())
+    .bind(file_id)
+    .execute(pool)
+    .await?;
+  Ok((row.get("tg_chat_id"), row.get("tg_msg_id")))
+}
+
+/// Прежнее сообщение файла после замены содержимого.
+#[derive(Debug, Clone)]
+pub struct PreviousMessage {
+  pub chat_id: ChatId,
+  pub message_id: i64,
+  pub hash: String,
+  pub size: i64
+}
+
+/// Отправляет новое содержимое файла и переводит запись на новое сообщение
+/// с пересчитанными хэшем и размером. Старое сообщение не трогается.
+pub async fn replace_file_content(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  storage_chat_id: ChatId,
+  file_id: &str,
+  source_path: &Path
+) -> anyhow::Result<PreviousMessage> {
+  let row = sqlx::query("SELECT dir_id, name, size, hash, tg_chat_id, tg_msg_id FROM files WHERE id = ?")
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?;
+  let Some(row) = row else {
+    return Err(anyhow::anyhow!("Файл не найден"));
+  };
+  if !source_path.is_file() {
+    return Err(anyhow::anyhow!("Файл не найден"));
+  }
+  let dir_id: String = row.get("dir_id");
+  let size = source_path.metadata().map(|m| m.len() as i64).unwrap_or(0);
+  let content_hash = hash_full(source_path)?;
+  let hash_short: String = content_hash.chars().take(8).collect();
+  let dir_name = fetch_dir_name(pool, &dir_id).await?;
+  let target_chat_id = storage_channels::chat_for_dir(pool, &dir_id, storage_chat_id).await?;
+  let payload = UploadPayload::prepare(pool, source_path).await?;
+  let caption = with_encryption(
+    make_file_caption_with_tag(
+      &FileMeta {
+        dir_id,
+        file_id: file_id.to_string(),
+        name: row.get("name"),
+        hash_short: hash_short.clone()
+      },
+      dir_name.as_deref()
+    ),
+    payload.enc_key_id.as_deref()
+  );
+
+  let uploaded = tg.send_file(target_chat_id, payload.path.clone(), caption).await?;
+  sqlx::query(
+    "UPDATE files SET tg_chat_id = ?, tg_msg_id = ?, size = ?, hash = ?, content_hash = ?, enc_key_id = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?"
+  )
+    .bind(uploaded.chat_id)
+    .bind(uploaded.message_id)
+    .bind(size)
+    .bind(&hash_short)
+    .bind(&content_hash)
+    .bind(payload.enc_key_id.as_deref())
+    .bind(file_id)
+    .execute(pool)
+    .await?;
+  Ok(PreviousMessage {
+    chat_id: row.get("tg_chat_id"),
+    message_id: row.get("tg_msg_id"),
+    hash: row.get("hash"),
+    size: row.get("size")
+  })
+}
+
 pub fn build_message_link(chat_id: i64, message_id: i64) -> anyhow::Result<String> {
   if chat_id >= 0 {
     return Err(anyhow::anyhow!("Ссылка доступна только для сообщений каналов"));
@@ -616,6 +2323,31 @@ pub fn build_message_link(chat_id: i64, message_id: i64) -> anyhow::Result<Strin
   Ok(format!("https://t.me/c/{internal}/{message_id}"))
 }
 
+/// Подпись файла по данным из базы, но с другим хэшем.
+pub(crate) async fn caption_with_hash(pool: &SqlitePool, file_id: &str, hash_short: &str) -> anyhow::Result<String> {
+  let row = sqlx::query("SELECT dir_id, name, enc_key_id FROM files WHERE id = ?")
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?;
+  let Some(row) = row else {
+    return Err(anyhow::anyhow!("Файл не найден"));
+  };
+  let dir_id: String = row.get("dir_id");
+  let dir_name = fetch_dir_name(pool, &dir_id).await?;
+  Ok(with_encryption(
+    make_file_caption_with_tag(
+      &FileMeta {
+        dir_id,
+        file_id: file_id.to_string(),
+        name: row.get("name"),
+        hash_short: hash_short.to_string()
+      },
+      dir_name.as_deref()
+    ),
+    row.try_get::<String,_>("enc_key_id").ok().as_deref()
+  ))
+}
+
 fn make_file_caption_with_tag(meta: &FileMeta, dir_name: Option<&str>) -> String {
   let base = make_file_caption(meta);
   if let Some(tag) = dir_name.and_then(folder_hashtag) {
@@ -625,6 +2357,23 @@ fn make_file_caption_with_tag(meta: &FileMeta, dir_name: Option<&str>) -> String
   }
 }
 
+/// Без отметки `#enc` индексатор принял бы шифротекст за обычный файл.
+fn with_encryption(caption: String, enc_key_id: Option<&str>) -> String {
+  match enc_key_id {
+    Some(key_id) => mark_encrypted(&caption, key_id),
+    None => caption
+  }
+}
+
+fn push_extra_tags(caption: &mut String, extra_tags: &[String]) {
+  for tag in extra_tags.iter().filter_map(|t| folder_hashtag(t)) {
+    if !caption.split_whitespace().any(|w| w == tag) {
+      caption.push(' ');
+      caption.push_str(&tag);
+    }
+  }
+}
+
 fn folder_hashtag(name: &str) -> Option<String> {
   let trimmed = name.trim();
   if trimmed.is_empty() {
@@ -649,7 +2398,7 @@ fn folder_hashtag(name: &str) -> Option<String> {
   }
 }
 
-async fn fetch_dir_name(pool: &SqlitePool, dir_id: &str) -> anyhow::Result<Option<String>> {
+pub(crate) async fn fetch_dir_name(pool: &SqlitePool, dir_id: &str) -> anyhow::Result<Option<String>> {
   let row = sqlx::query("SELECT name FROM directories WHERE id = ?")
     .bind(dir_id)
     .fetch_optional(pool)
@@ -657,35 +2406,23 @@ async fn fetch_dir_name(pool: &SqlitePool, dir_id: &str) -> anyhow::Result<Optio
   Ok(row.map(|r| r.get::<String,_>("name")))
 }
 
-async fn build_dir_path(pool: &SqlitePool, dir_id: &str) -> anyhow::Result<PathBuf> {
-  let mut names: Vec<String> = Vec::new();
-  let mut current = Some(dir_id.to_string());
-  let mut guard = 0;
-  while let Some(id) = current {
-    guard += 1;
-    if guard > 64 {
-      break;
-    }
-    let row = sqlx::query("SELECT name, parent_id FROM directories WHERE id = ?")
-      .bind(&id)
-      .fetch_optional(pool)
-      .await?;
-    let Some(row) = row else {
-      break;
-    };
-    let name: String = row.get("name");
-    if name.trim().is_empty() || name == "Неизвестная папка" {
-      // пропускаем
-    } else {
-      names.push(sanitize_component(&name));
-    }
-    let parent = row.try_get::<String,_>("parent_id").ok();
-    current = parent
-      .filter(|p| !p.trim().is_empty() && p != "ROOT");
-  }
-  names.reverse();
+pub(crate) async fn fetch_file_name(pool: &SqlitePool, file_id: &str) -> anyhow::Result<Option<String>> {
+  let row = sqlx::query("SELECT name FROM files WHERE id = ?")
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?;
+  Ok(row.map(|r| r.get::<String,_>("name")))
+}
+
+/// Локальный путь папки относительно каталога загрузок. Имена берутся из
+/// сохраненного пути `dir_paths` и проходят через текущие правила имен.
+pub(crate) async fn build_dir_path(pool: &SqlitePool, dir_id: &str) -> anyhow::Result<PathBuf> {
   let mut path = PathBuf::new();
-  for n in names {
+  for name in dir_paths::names(pool, dir_id).await? {
+    if name.trim().is_empty() || crate::app::system_dirs::is_placeholder_name(&name) {
+      continue;
+    }
+    let n = sanitize_component(&name);
     if !n.is_empty() {
       path.push(n);
     }
@@ -693,29 +2430,12 @@ async fn build_dir_path(pool: &SqlitePool, dir_id: &str) -> anyhow::Result<PathB
   Ok(path)
 }
 
-fn sanitize_component(name: &str) -> String {
-  let mut out = String::new();
-  for ch in name.chars() {
-    if ch == '/' || ch == '\\' || ch == ':' || ch == '\0' || ch.is_control() {
-      out.push('_');
-    } else {
-      out.push(ch);
-    }
-  }
-  let cleaned = out.trim().to_string();
-  // Не допускаем спец-сегменты пути, чтобы исключить выход за пределы cache/downloads.
-  if cleaned == "." || cleaned == ".." {
-    "_".to_string()
-  } else {
-    cleaned
-  }
+pub(crate) fn sanitize_component(name: &str) -> String {
+  local_names::sanitize(name, &local_names::active())
 }
 
-fn resolve_target_path(base_dir: &Path, name: &str, size: i64) -> anyhow::Result<PathBuf> {
-  let mut safe = sanitize_component(name);
-  if safe.is_empty() {
-    safe = "файл".to_string();
-  }
+fn resolve_target_path(base_dir: &Path, name: &str, file_id: &str, size: i64) -> anyhow::Result<PathBuf> {
+  let safe = local_file_name(name, file_id);
   let mut candidate = base_dir.join(&safe);
   if candidate.exists() {
     if size > 0 {
@@ -737,12 +2457,12 @@ fn resolve_target_path(base_dir: &Path, name: &str, size: i64) -> anyhow::Result
   Ok(candidate)
 }
 
-fn preferred_target_path(base_dir: &Path, name: &str) -> PathBuf {
-  let mut safe = sanitize_component(name);
-  if safe.is_empty() {
-    safe = "файл".to_string();
-  }
-  base_dir.join(safe)
+fn preferred_target_path(base_dir: &Path, name: &str, file_id: &str) -> PathBuf {
+  base_dir.join(local_file_name(name, file_id))
+}
+
+fn local_file_name(name: &str, file_id: &str) -> String {
+  local_names::download_name(name, file_id, &local_names::active())
 }
 
 fn split_name(name: &str) -> (String, String) {
@@ -771,15 +2491,19 @@ fn is_name_variant(base_stem: &str, candidate_stem: &str) -> bool {
   !num.is_empty() && num.chars().all(|c| c.is_ascii_digit())
 }
 
-fn find_local_download(paths: &Paths, dir_path: &Path, name: &str, size: i64) -> Option<PathBuf> {
-  let base_dir = paths.cache_dir.join("downloads").join(dir_path);
+/// Прежний поиск копии по имени в каталоге загрузок; нужен только для
+/// переноса старых загрузок в `local_copies`.
+pub(crate) fn scan_local_download(paths: &Paths, dir_path: &Path, name: &str, file_id: &str, size: i64) -> Option<PathBuf> {
+  let base_dir = paths.layout().downloads_dir().join(dir_path);
   if !base_dir.exists() {
     return None;
   }
 
-  let mut safe = sanitize_component(name);
-  if safe.is_empty() {
-    safe = "файл".to_string();
+  let safe = local_file_name(name, file_id);
+  // Имя с фрагментом file_id принадлежит ровно одному файлу.
+  if local_names::active().file_id_suffix {
+    let path = base_dir.join(&safe);
+    return path.is_file().then_some(path);
   }
   let (stem, ext) = split_name(&safe);
 
@@ -811,16 +2535,6 @@ fn find_local_download(paths: &Paths, dir_path: &Path, name: &str, size: i64) ->
   first_match
 }
 
-fn local_download_info(paths: &Paths, dir_path: &Path, name: &str, size: i64) -> (bool, Option<i64>) {
-  let Some(path) = find_local_download(paths, dir_path, name, size) else {
-    return (false, None);
-  };
-  let local_size = std::fs::metadata(&path)
-    .ok()
-    .map(|meta| meta.len().min(i64::MAX as u64) as i64);
-  (true, local_size)
-}
-
 async fn update_file_size_from_local(pool: &SqlitePool, file_id: &str, path: &Path) -> anyhow::Result<()> {
   let local_size = std::fs::metadata(path)
     .map(|meta| meta.len().min(i64::MAX as u64) as i64)
@@ -895,56 +2609,40 @@ pub async fn find_file_message(
   Ok(None)
 }
 
-async fn remove_local_download(
-  pool: &SqlitePool,
-  paths: &Paths,
-  dir_id: &str,
-  name: &str,
-  _size: i64
-) -> anyhow::Result<()> {
-  let dir_path = build_dir_path(pool, dir_id).await?;
-  let base_dir = paths.cache_dir.join("downloads").join(dir_path);
-  if !base_dir.exists() {
-    return Ok(());
-  }
-
-  let mut safe = sanitize_component(name);
-  if safe.is_empty() {
-    safe = "файл".to_string();
-  }
-  let (stem, ext) = split_name(&safe);
-  let mut removed = false;
+/// Удаляет скачанную копию файла. Возвращает освобожденные байты; `None`,
+/// если копии на диске уже не было (ее запись при этом забывается).
+pub(crate) async fn remove_local_download(pool: &SqlitePool, paths: &Paths, file_id: &str) -> anyhow::Result<Option<u64>> {
+  let Some(copy) = local_copies::lookup(pool, paths, file_id).await? else {
+    return Ok(None);
+  };
+  std::fs::remove_file(&copy.path)?;
+  local_copies::forget(pool, file_id).await?;
+  cleanup_empty_dirs(paths.layout().downloads_dir(), copy.path.parent());
+  Ok(Some(copy.size.max(0) as u64))
+}
 
-  let entries = match std::fs::read_dir(&base_dir) {
-    Ok(v) => v,
-    Err(_) => return Ok(())
+/// Переносит скачанную копию под новое имя файла. Хэш копии сохраняется:
+/// содержимое не менялось.
+async fn rename_local_download(pool: &SqlitePool, paths: &Paths, file_id: &str, name: &str) -> anyhow::Result<()> {
+  let Some(copy) = local_copies::lookup(pool, paths, file_id).await? else {
+    return Ok(());
   };
-  for entry in entries.flatten() {
-    let path = entry.path();
-    if !path.is_file() {
-      continue;
-    }
-    let file_name = match path.file_name().and_then(|n| n.to_str()) {
-      Some(v) => v,
-      None => continue
-    };
-    let (cand_stem, cand_ext) = split_name(file_name);
-    if cand_ext != ext || !is_name_variant(&stem, &cand_stem) {
-      continue;
-    }
-    if std::fs::remove_file(&path).is_ok() {
-      removed = true;
-    }
+  let Some(dir) = copy.path.parent() else {
+    return Ok(());
+  };
+  if preferred_target_path(dir, name, file_id) == copy.path {
+    return Ok(());
   }
-
-  let should_cleanup = removed || std::fs::read_dir(&base_dir).map(|mut it| it.next().is_none()).unwrap_or(false);
-  if should_cleanup {
-    cleanup_empty_dirs(paths.cache_dir.join("downloads"), Some(&base_dir));
+  let target = resolve_target_path(dir, name, file_id, 0)?;
+  std::fs::rename(&copy.path, &target)?;
+  local_copies::record(pool, paths, file_id, &target).await?;
+  if let Some(hash) = copy.hash.as_deref() {
+    local_copies::set_hash(pool, file_id, hash).await?;
   }
   Ok(())
 }
 
-fn cleanup_empty_dirs(root: PathBuf, start: Option<&Path>) {
+pub(crate) fn cleanup_empty_dirs(root: PathBuf, start: Option<&Path>) {
   let mut current = start.map(|p| p.to_path_buf());
   while let Some(dir) = current {
     if !dir.starts_with(&root) || dir == root {
@@ -963,18 +2661,23 @@ fn cleanup_empty_dirs(root: PathBuf, start: Option<&Path>) {
 #[cfg(test)]
 mod tests {
   use super::*;
-  use std::collections::HashMap;
+  use std::collections::{HashMap, HashSet};
   use std::io::Write;
   use std::sync::{Arc, Mutex};
   use tempfile::tempdir;
+  use crate::app::broken::{self, BrokenReason};
   use crate::db::Db;
+  use crate::fsmeta::{LinkKind, LinkMeta};
   use crate::sqlx;
   use crate::telegram::{
     ChatId,
+    ChatFolder,
     ChatInfo,
+    ChatRef,
     HistoryMessage,
     MessageId,
     SearchMessagesResult,
+    StickerSetInfo,
     TelegramService,
     TgError,
     UploadedMessage
@@ -991,7 +2694,22 @@ mod tests {
     fail_once_for: Option<(ChatId, MessageId)>,
     failed_once: bool,
     download_payloads: HashMap<(ChatId, MessageId), Vec<u8>>,
-    search_results: HashMap<(ChatId, String, MessageId), SearchMessagesResult>
+    search_results: HashMap<(ChatId, String, MessageId), SearchMessagesResult>,
+    editable_captions: Vec<MessageId>,
+    edited_captions: Vec<(MessageId, String)>,
+    copied_messages: Vec<(ChatId, MessageId, String)>,
+    /// Откуда и куда копировались сообщения через `copy_messages`.
+    copied_between: Vec<(ChatId, ChatId, MessageId)>,
+    /// Копия сообщения получает id `смещение + id` и допускает правку подписи.
+    copy_offset: Option<MessageId>,
+    /// Отправленный файл получает id `смещение + номер отправки`.
+    send_offset: Option<MessageId>,
+    sent_captions: Vec<String>,
+    /// Служебное сообщение получает id `смещение + номер отправки`.
+    dir_message_offset: Option<MessageId>,
+    /// Сообщения папок с этими именами отклоняются.
+    rejected_dir_names: Vec<String>,
+    dir_messages: Vec<String>
   }
 
   impl MockTelegram {
@@ -1002,6 +2720,33 @@ mod tests {
       self
     }
 
+    fn allow_caption_edit(self, message_id: MessageId) -> Self {
+      let mut guard = self.state.lock().expect("mock lock");
+      guard.editable_captions.push(message_id);
+      drop(guard);
+      self
+    }
+
+    fn allow_send(self, offset: MessageId) -> Self {
+      self.state.lock().expect("mock lock").send_offset = Some(offset);
+      self
+    }
+
+    fn allow_copy(self, offset: MessageId) -> Self {
+      self.state.lock().expect("mock lock").copy_offset = Some(offset);
+      self
+    }
+
+    fn allow_dir_messages(self, offset: MessageId) -> Self {
+      self.state.lock().expect("mock lock").dir_message_offset = Some(offset);
+      self
+    }
+
+    fn reject_dir_message(self, name: &str) -> Self {
+      self.state.lock().expect("mock lock").rejected_dir_names.push(name.to_string());
+      self
+    }
+
     fn fail_once(self, chat_id: ChatId, message_id: MessageId) -> Self {
       let mut guard = self.state.lock().expect("mock lock");
       guard.fail_once_for = Some((chat_id, message_id));
@@ -1078,6 +2823,14 @@ mod tests {
       Err(TgError::NotImplemented)
     }
 
+    async fn storage_chat_ref(&self, _chat_id: ChatId) -> Result<ChatRef, TgError> {
+      Ok(ChatRef::default())
+    }
+
+    async fn storage_resolve_chat_ref(&self, _chat_ref: &ChatRef) -> Result<Option<ChatId>, TgError> {
+      Ok(None)
+    }
+
     async fn backup_check_channel(&self, _chat_id: ChatId) -> Result<bool, TgError> {
       Ok(false)
     }
@@ -1135,39 +2888,68 @@ mod tests {
       Err(TgError::NotImplemented)
     }
 
-    async fn send_dir_message(&self, _chat_id: ChatId, _text: String) -> Result<UploadedMessage, TgError> {
-      Err(TgError::NotImplemented)
+    async fn send_dir_message(&self, chat_id: ChatId, text: String) -> Result<UploadedMessage, TgError> {
+      let mut guard = self.state.lock().expect("mock lock");
+      let Some(offset) = guard.dir_message_offset else {
+        return Err(TgError::NotImplemented);
+      };
+      if guard.rejected_dir_names.iter().any(|name| text.ends_with(&format!("name={name}"))) {
+        return Err(TgError::Other("MESSAGE_REJECTED".into()));
+      }
+      guard.dir_messages.push(text.clone());
+      let message_id = offset + guard.dir_messages.len() as MessageId;
+      Ok(UploadedMessage { chat_id, message_id, caption_or_text: text })
     }
 
-    async fn edit_message_text(&self, _chat_id: ChatId, _message_id: MessageId, _text: String) -> Result<(), TgError> {
+    async fn edit_message_text(&self, _chat_id: ChatId, message_id: MessageId, _text: String) -> Result<(), TgError> {
+      if self.state.lock().expect("mock lock").editable_captions.contains(&message_id) {
+        Ok(())
+      } else {
+        Err(TgError::NotImplemented)
+      }
+    }
+
+    async fn pin_message(&self, _chat_id: ChatId, _message_id: MessageId) -> Result<(), TgError> {
       Err(TgError::NotImplemented)
     }
 
     async fn edit_message_caption(
       &self,
       _chat_id: ChatId,
-      _message_id: MessageId,
-      _caption: String
+      message_id: MessageId,
+      caption: String
     ) -> Result<(), TgError> {
-      Err(TgError::NotImplemented)
+      let mut guard = self.state.lock().expect("mock lock");
+      if !guard.editable_captions.contains(&message_id) {
+        return Err(TgError::NotImplemented);
+      }
+      guard.edited_captions.push((message_id, caption));
+      Ok(())
     }
 
     async fn send_file(
       &self,
-      _chat_id: ChatId,
+      chat_id: ChatId,
       _path: PathBuf,
-      _caption: String
+      caption: String
     ) -> Result<UploadedMessage, TgError> {
-      Err(TgError::NotImplemented)
+      let mut guard = self.state.lock().expect("mock lock");
+      let Some(offset) = guard.send_offset else {
+        return Err(TgError::NotImplemented);
+      };
+      guard.sent_captions.push(caption.clone());
+      Ok(UploadedMessage { chat_id, message_id: offset + guard.sent_captions.len() as i64, caption_or_text: caption })
     }
 
     async fn send_file_from_message(
       &self,
-      _chat_id: ChatId,
-      _message_id: MessageId,
-      _caption: String
+      chat_id: ChatId,
+      message_id: MessageId,
+      caption: String
     ) -> Result<UploadedMessage, TgError> {
-      Err(TgError::NotImplemented)
+      let mut guard = self.state.lock().expect("mock lock");
+      guard.copied_messages.push((chat_id, message_id, caption.clone()));
+      Ok(UploadedMessage { chat_id, message_id: 9000 + guard.copied_messages.len() as i64, caption_or_text: caption })
     }
 
     async fn forward_message(
@@ -1181,11 +2963,22 @@ mod tests {
 
     async fn copy_messages(
       &self,
-      _from_chat_id: ChatId,
-      _to_chat_id: ChatId,
-      _message_ids: Vec<MessageId>
+      from_chat_id: ChatId,
+      to_chat_id: ChatId,
+      message_ids: Vec<MessageId>
     ) -> Result<Vec<Option<MessageId>>, TgError> {
-      Err(TgError::NotImplemented)
+      let mut guard = self.state.lock().expect("mock lock");
+      let Some(offset) = guard.copy_offset else {
+        return Err(TgError::NotImplemented);
+      };
+      // Повторная копия того же сообщения получает новый id, как в Telegram.
+      let copies: Vec<MessageId> = message_ids
+        .iter()
+        .map(|id| offset + id + 1000 * guard.copied_between.iter().filter(|c| c.2 == *id).count() as MessageId)
+        .collect();
+      guard.copied_between.extend(message_ids.iter().map(|id| (from_chat_id, to_chat_id, *id)));
+      guard.editable_captions.extend(copies.iter().copied());
+      Ok(copies.into_iter().map(Some).collect())
     }
 
     async fn delete_messages(
@@ -1224,6 +3017,26 @@ mod tests {
     async fn message_exists(&self, _chat_id: ChatId, _message_id: MessageId) -> Result<bool, TgError> {
       Ok(false)
     }
+
+    async fn recognize_speech(&self, _chat_id: ChatId, _message_id: MessageId) -> Result<String, TgError> {
+      Err(TgError::NotImplemented)
+    }
+
+    async fn sticker_set_info(&self, _set_id: String) -> Result<StickerSetInfo, TgError> {
+      Err(TgError::NotImplemented)
+    }
+
+    async fn saved_messages_chat(&self) -> Result<ChatId, TgError> {
+      Err(TgError::NotImplemented)
+    }
+
+    async fn chat_folders(&self) -> Result<Vec<ChatFolder>, TgError> {
+      Ok(Vec::new())
+    }
+
+    async fn folder_chats(&self, _folder_id: i32, _limit: i32) -> Result<Vec<ChatInfo>, TgError> {
+      Ok(Vec::new())
+    }
   }
 
   async fn setup_db_and_paths() -> anyhow::Result<(tempfile::TempDir, Db, Paths)> {
@@ -1270,51 +3083,89 @@ mod tests {
   }
 
   #[test]
-  fn find_local_download_returns_existing_when_size_unknown() {
+  fn scan_local_download_returns_existing_when_size_unknown() {
     let tmp = tempdir().expect("tempdir");
     let paths = Paths::from_base(tmp.path().to_path_buf());
     let dir_path = PathBuf::from("docs");
-    let base_dir = paths.cache_dir.join("downloads").join(&dir_path);
+    let base_dir = paths.layout().downloads_dir().join(&dir_path);
     std::fs::create_dir_all(&base_dir).expect("create dirs");
     let file_path = base_dir.join("report.txt");
     let mut file = std::fs::File::create(&file_path).expect("create file");
     writeln!(file, "hello").expect("write");
 
-    let found = find_local_download(&paths, &dir_path, "report.txt", 0);
+    let found = scan_local_download(&paths, &dir_path, "report.txt", "f1", 0);
     assert_eq!(found, Some(file_path));
   }
 
   #[test]
-  fn find_local_download_falls_back_when_size_mismatch() {
+  fn scan_local_download_falls_back_when_size_mismatch() {
     let tmp = tempdir().expect("tempdir");
     let paths = Paths::from_base(tmp.path().to_path_buf());
     let dir_path = PathBuf::from("docs");
-    let base_dir = paths.cache_dir.join("downloads").join(&dir_path);
+    let base_dir = paths.layout().downloads_dir().join(&dir_path);
     std::fs::create_dir_all(&base_dir).expect("create dirs");
     let file_path = base_dir.join("report.txt");
     let mut file = std::fs::File::create(&file_path).expect("create file");
     writeln!(file, "hello").expect("write");
 
     // Размер в БД мог устареть, но локальную копию все равно нужно переиспользовать.
-    let found = find_local_download(&paths, &dir_path, "report.txt", 1024);
+    let found = scan_local_download(&paths, &dir_path, "report.txt", "f1", 1024);
     assert_eq!(found, Some(file_path));
   }
 
   #[test]
-  fn local_download_info_returns_actual_local_size() {
+  fn local_copy_check_returns_actual_local_size() {
     let tmp = tempdir().expect("tempdir");
     let paths = Paths::from_base(tmp.path().to_path_buf());
-    let dir_path = PathBuf::from("docs");
-    let base_dir = paths.cache_dir.join("downloads").join(&dir_path);
+    let base_dir = paths.layout().downloads_dir().join("docs");
     std::fs::create_dir_all(&base_dir).expect("create dirs");
-    let file_path = base_dir.join("report.txt");
-    std::fs::write(&file_path, b"hello world").expect("write");
+    std::fs::write(base_dir.join("report.txt"), b"hello world").expect("write");
 
-    let (downloaded, local_size) = local_download_info(&paths, &dir_path, "report.txt", 0);
+    let (downloaded, local_size) = local_copies::check(&paths, Some("docs/report.txt"));
     assert!(downloaded);
     assert_eq!(local_size, Some(11));
   }
 
+  #[test]
+  fn parts_split_and_reassemble_to_the_original() {
+    let tmp = tempdir().expect("tempdir");
+    let source = tmp.path().join("big.bin");
+    let data: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+    std::fs::write(&source, &data).expect("write");
+
+    let target = tmp.path().join("assembled.bin");
+    let mut output = std::fs::File::create(&target).expect("create");
+    let mut sizes = Vec::new();
+    for index in 1..=3 {
+      let part = tmp.path().join(format!("big.part{index}"));
+      sizes.push(write_part(&source, &part, index, 400).expect("write part"));
+      append_part(&part, &mut output).expect("append part");
+    }
+    drop(output);
+
+    assert_eq!(sizes, vec![400, 400, 200]);
+    assert_eq!(std::fs::read(&target).expect("read"), data);
+  }
+
+  #[test]
+  fn part_count_only_for_files_over_threshold() {
+    assert_eq!(part_count_for(0), 0);
+    assert_eq!(part_count_for(CHUNK_THRESHOLD), 0);
+    assert_eq!(part_count_for(CHUNK_THRESHOLD + 1), 2);
+    assert_eq!(part_count_for(CHUNK_SIZE * 3), 3);
+  }
+
+  #[test]
+  fn remote_status_prefers_broken_over_pending() {
+    assert_eq!(RemoteStatus::from_columns(false, 10, 0, false), RemoteStatus::Ok);
+    assert_eq!(RemoteStatus::from_columns(false, 0, 0, false), RemoteStatus::PendingUpload);
+    assert_eq!(RemoteStatus::from_columns(true, 0, 0, false), RemoteStatus::Broken);
+    assert_eq!(RemoteStatus::from_columns(false, 10, 3, false), RemoteStatus::Chunked);
+    assert_eq!(RemoteStatus::from_columns(true, 10, 3, false), RemoteStatus::Broken);
+    assert_eq!(RemoteStatus::from_columns(false, 10, 0, true), RemoteStatus::Encrypted);
+    assert_eq!(RemoteStatus::from_columns(true, 10, 0, true), RemoteStatus::Broken);
+  }
+
   #[test]
   fn sanitize_component_rejects_relative_segments() {
     assert_eq!(sanitize_component("."), "_");
@@ -1328,18 +3179,329 @@ mod tests {
     assert_eq!(sanitize_component("name\0with\rbad\nchars"), "name_with_bad_chars");
   }
 
+  #[tokio::test]
+  async fn move_files_batches_captions_and_keeps_failed_in_place() -> anyhow::Result<()> {
+    let (_tmp, db, _paths) = setup_db_and_paths().await?;
+    seed_one_file(db.pool(), "f1", "d1", "a.txt", 1, -1001, 100).await?;
+    seed_one_file(db.pool(), "f2", "d2", "b.txt", 1, -1001, 200).await?;
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at, is_broken) VALUES('d3', NULL, 'Архив', NULL, 0, 0)")
+      .execute(db.pool())
+      .await?;
+    broken::mark_file_broken(db.pool(), "f1", BrokenReason::CaptionUnparsable).await?;
+    let tg = MockTelegram::default().allow_caption_edit(100);
+
+    let outcome = move_files(db.pool(), &tg, -1001, &["f1".to_string(), "f2".to_string()], "d3").await?;
+    assert_eq!(outcome.done, vec!["f1".to_string()]);
+    assert_eq!(outcome.failed.len(), 1);
+    assert_eq!(outcome.failed[0].0, "f2");
+
+    // Перенесенный файл с исправленной подписью больше не битый, причина тоже снята.
+    let row = sqlx::query("SELECT is_broken, broken_reason FROM files WHERE id = 'f1'")
+      .fetch_one(db.pool())
+      .await?;
+    assert_eq!(row.get::<i64,_>("is_broken"), 0);
+    assert!(row.try_get::<String,_>("broken_reason").is_err());
+
+    for (file_id, expected) in [("f1", "d3"), ("f2", "d2")] {
+      let dir_id: String = sqlx::query("SELECT dir_id FROM files WHERE id = ?")
+        .bind(file_id)
+        .fetch_one(db.pool())
+        .await?
+        .get("dir_id");
+      assert_eq!(dir_id, expected);
+    }
+
+    let edited = tg.state.lock().expect("mock lock").edited_captions.clone();
+    assert_eq!(edited.len(), 1);
+    assert!(edited[0].1.contains("d=d3"));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn move_files_copies_messages_whose_caption_cannot_be_edited() -> anyhow::Result<()> {
+    let (_tmp, db, _paths) = setup_db_and_paths().await?;
+    seed_one_file(db.pool(), "f1", "d1", "a.txt", 1, -1001, 100).await?;
+    seed_one_file(db.pool(), "f2", "d2", "b.txt", 1, -1001, 200).await?;
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at, is_broken) VALUES('d3', NULL, 'Архив', NULL, 0, 0)")
+      .execute(db.pool())
+      .await?;
+    let tg = MockTelegram::default().allow_caption_edit(100).allow_copy(7000);
+
+    let outcome = move_files(db.pool(), &tg, -1001, &["f1".to_string(), "f2".to_string()], "d3").await?;
+    assert_eq!(outcome.done, vec!["f1".to_string(), "f2".to_string()]);
+    assert!(outcome.failed.is_empty());
+
+    for (file_id, expected_msg) in [("f1", 100), ("f2", 7200)] {
+      let row = sqlx::query("SELECT dir_id, tg_msg_id FROM files WHERE id = ?")
+        .bind(file_id)
+        .fetch_one(db.pool())
+        .await?;
+      assert_eq!(row.get::<String,_>("dir_id"), "d3");
+      assert_eq!(row.get::<i64,_>("tg_msg_id"), expected_msg);
+    }
+
+    let edited = tg.state.lock().expect("mock lock").edited_captions.clone();
+    assert_eq!(edited.len(), 2);
+    assert_eq!(edited[1].0, 7200);
+    assert!(edited[1].1.contains("d=d3"));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn dir_rename_reports_files_whose_tag_was_not_updated() -> anyhow::Result<()> {
+    let (_tmp, db, _paths) = setup_db_and_paths().await?;
+    seed_one_file(db.pool(), "f1", "d1", "a.txt", 1, -1001, 100).await?;
+    sqlx::query(
+      "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, is_broken)
+       VALUES('f2', 'd1', 'b.txt', 1, 'deadbeef', -1001, 200, 0, 0)"
+    )
+      .execute(db.pool())
+      .await?;
+    sqlx::query("UPDATE directories SET tg_msg_id = 50 WHERE id = 'd1'")
+      .execute(db.pool())
+      .await?;
+    let tg = MockTelegram::default().allow_caption_edit(50).allow_caption_edit(100);
+
+    let outcome = super::super::dirs::rename_dir(db.pool(), &tg, -1001, "d1", "Архив".into()).await?;
+    assert_eq!(outcome.done, vec!["f1".to_string()]);
+    assert_eq!(outcome.failed.len(), 1);
+    assert_eq!(outcome.failed[0].0, "f2");
+
+    // Переименование не откатывается из-за файлов со старым тегом.
+    let name: String = sqlx::query("SELECT name FROM directories WHERE id = 'd1'")
+      .fetch_one(db.pool())
+      .await?
+      .get("name");
+    assert_eq!(name, "Архив");
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn rename_file_updates_caption_row_and_local_copy() -> anyhow::Result<()> {
+    let (_tmp, db, paths) = setup_db_and_paths().await?;
+    seed_one_file(db.pool(), "f1", "d1", "a.txt", 7, -1001, 100).await?;
+    let base_dir = paths.layout().downloads_dir().join("Документы");
+    std::fs::create_dir_all(&base_dir)?;
+    let local = base_dir.join(local_file_name("a.txt", "f1"));
+    std::fs::write(&local, b"payload")?;
+    local_copies::record(db.pool(), &paths, "f1", &local).await?;
+    local_copies::set_hash(db.pool(), "f1", "cafe").await?;
+    let tg = MockTelegram::default().allow_caption_edit(100);
+
+    rename_file(db.pool(), &tg, &paths, -1001, "f1", "  отчет.txt ").await?;
+
+    let name: String = sqlx::query("SELECT name FROM files WHERE id = 'f1'")
+      .fetch_one(db.pool())
+      .await?
+      .get("name");
+    assert_eq!(name, "отчет.txt");
+    let edited = tg.state.lock().expect("mock lock").edited_captions.clone();
+    assert_eq!(edited.len(), 1);
+    assert!(edited[0].1.contains("d=d1"));
+    assert!(edited[0].1.contains("n=отчет.txt"));
+
+    let copy = local_copies::lookup(db.pool(), &paths, "f1").await?.expect("local copy");
+    assert_eq!(copy.path, base_dir.join(local_file_name("отчет.txt", "f1")));
+    assert_eq!(copy.hash.as_deref(), Some("cafe"));
+    assert!(!local.exists());
+
+    assert!(rename_file(db.pool(), &tg, &paths, -1001, "f1", "a/b.txt").await.is_err());
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn copy_files_duplicates_message_under_new_id() -> anyhow::Result<()> {
+    let (_tmp, db, _paths) = setup_db_and_paths().await?;
+    seed_one_file(db.pool(), "f1", "d1", "a.txt", 7, -1001, 100).await?;
+    let tg = MockTelegram::default().allow_copy(7000);
+
+    let outcome = copy_files(db.pool(), &tg, -1001, &["f1".to_string()], "d1").await?;
+    assert!(outcome.failed.is_empty());
+    assert_eq!(outcome.created.len(), 1);
+    let copy_id = &outcome.created[0];
+    assert_ne!(copy_id, "f1");
+
+    let row = sqlx::query("SELECT dir_id, name, size, tg_chat_id, tg_msg_id FROM files WHERE id = ?")
+      .bind(copy_id)
+      .fetch_one(db.pool())
+      .await?;
+    assert_eq!(row.get::<String,_>("dir_id"), "d1");
+    assert_eq!(row.get::<String,_>("name"), "a (копия).txt");
+    assert_eq!(row.get::<i64,_>("size"), 7);
+    assert_eq!(row.get::<i64,_>("tg_msg_id"), 7100);
+    let source_msg: i64 = sqlx::query("SELECT tg_msg_id FROM files WHERE id = 'f1'")
+      .fetch_one(db.pool())
+      .await?
+      .get("tg_msg_id");
+    assert_eq!(source_msg, 100);
+
+    let edited = tg.state.lock().expect("mock lock").edited_captions.clone();
+    assert_eq!(edited.len(), 1);
+    assert_eq!(edited[0].0, 7100);
+    assert!(edited[0].1.contains(&format!("f={copy_id}")));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn copy_dir_copies_subtree_and_links_and_reports_missing_subfolders() -> anyhow::Result<()> {
+    let (_tmp, db, _paths) = setup_db_and_paths().await?;
+    let pool = db.pool();
+    seed_one_file(pool, "f1", "d1", "a.txt", 7, -1001, 100).await?;
+    seed_one_file(pool, "f2", "d2", "b.txt", 9, -1001, 200).await?;
+    for (id, parent, name) in [("s1", "d1", "Сканы"), ("s2", "d1", "Черновики"), ("s3", "s2", "Старые")] {
+      sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES(?, ?, ?, NULL, 0)")
+        .bind(id)
+        .bind(parent)
+        .bind(name)
+        .execute(pool)
+        .await?;
+    }
+    sqlx::query("UPDATE files SET dir_id = 's1' WHERE id = 'f2'").execute(pool).await?;
+    let link = LinkMeta { link_id: "l1".into(), dir_id: "d1".into(), kind: LinkKind::Dir, target_id: "d2".into() };
+    crate::app::links::upsert_link(pool, &link, 60, 0).await?;
+    let tg = MockTelegram::default().allow_dir_messages(9000).allow_copy(7000).reject_dir_message("Черновики");
+
+    let copy = super::super::dirs::copy_dir(pool, &tg, -1001, "d1", None).await?;
+    assert_eq!(copy.created.len(), 2);
+    assert!(copy.failed.is_empty());
+    assert!(copy.failed_links.is_empty());
+    // Подпапка без копии попадает в итог вместе со своим поддеревом.
+    assert_eq!(copy.failed_dirs.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>(), vec!["s2"]);
+
+    let copied_link = sqlx::query("SELECT target_kind, target_id, tg_msg_id FROM links WHERE dir_id = ?")
+      .bind(&copy.dir_id)
+      .fetch_one(pool)
+      .await?;
+    assert_eq!(copied_link.get::<String,_>("target_kind"), "dir");
+    assert_eq!(copied_link.get::<String,_>("target_id"), "d2");
+    assert!(copied_link.try_get::<i64,_>("tg_msg_id").is_ok());
+
+    let scans: String = sqlx::query("SELECT id FROM directories WHERE parent_id = ? AND name = 'Сканы'")
+      .bind(&copy.dir_id)
+      .fetch_one(pool)
+      .await?
+      .get("id");
+    let scan_files: i64 = sqlx::query("SELECT COUNT(1) AS cnt FROM files WHERE dir_id = ?")
+      .bind(&scans)
+      .fetch_one(pool)
+      .await?
+      .get("cnt");
+    assert_eq!(scan_files, 1);
+    let old_copies: i64 = sqlx::query("SELECT COUNT(1) AS cnt FROM directories WHERE name = 'Старые'")
+      .fetch_one(pool)
+      .await?
+      .get("cnt");
+    assert_eq!(old_copies, 1);
+    Ok(())
+  }
+
+  #[test]
+  fn copy_name_skips_taken_names() {
+    let mut taken: HashSet<String> = ["a.txt".to_string(), "a (копия).txt".to_string()].into_iter().collect();
+    assert_eq!(copy_name("b.txt", &mut taken), "b.txt");
+    assert_eq!(copy_name("a.txt", &mut taken), "a (копия 2).txt");
+    assert_eq!(copy_name("a.txt", &mut taken), "a (копия 3).txt");
+  }
+
+  #[tokio::test]
+  async fn upload_dedup_asks_then_references_existing_message() -> anyhow::Result<()> {
+    let (tmp, db, _paths) = setup_db_and_paths().await?;
+    seed_one_file(db.pool(), "f1", "d1", "disk.iso", 7, -1001, 100).await?;
+    let local = tmp.path().join("copy.iso");
+    std::fs::write(&local, b"payload")?;
+    sqlx::query("UPDATE files SET content_hash = ? WHERE id = 'f1'")
+      .bind(hash_full(&local)?)
+      .execute(db.pool())
+      .await?;
+    let tg = MockTelegram::default().allow_copy(9000);
+
+    let asked = upload_file_dedup(db.pool(), &tg, -1001, "d1", &local, Dedup::Ask).await?;
+    assert!(matches!(asked, DedupUpload::Duplicate(ref d) if d.file_id == "f1"));
+
+    let DedupUpload::Referenced(id) = upload_file_dedup(db.pool(), &tg, -1001, "d1", &local, Dedup::Reference).await? else {
+      panic!("ожидалась ссылка на существующий файл");
+    };
+    let copied = tg.state.lock().expect("mock lock").copied_between.clone();
+    assert_eq!(copied, vec![(-1001, -1001, 100)]);
+    let edited = tg.state.lock().expect("mock lock").edited_captions.clone();
+    assert_eq!(edited.len(), 1);
+    assert_eq!(edited[0].0, 9100);
+    assert!(edited[0].1.contains(&format!("f={id}")));
+    let row = sqlx::query("SELECT name, tg_msg_id, content_hash FROM files WHERE id = ?")
+      .bind(&id)
+      .fetch_one(db.pool())
+      .await?;
+    assert_eq!(row.get::<String, _>("name"), "copy.iso");
+    assert_eq!(row.get::<i64, _>("tg_msg_id"), 9100);
+    assert_eq!(row.get::<String, _>("content_hash"), hash_full(&local)?);
+
+    // Загрузки из очереди, «Входящих» и заметок тоже не отправляют содержимое повторно.
+    let tagged = upload_file_tagged(db.pool(), &tg, -1001, "d1", &local, &["inbox".to_string()]).await?;
+    let edited = tg.state.lock().expect("mock lock").edited_captions.clone();
+    assert_eq!(edited.len(), 2);
+    assert!(edited[1].1.contains(&format!("f={tagged}")));
+    assert!(edited[1].1.contains("#inbox"));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn upload_reference_targets_dir_channel_and_skips_source_channels() -> anyhow::Result<()> {
+    let (tmp, db, _paths) = setup_db_and_paths().await?;
+    let pool = db.pool();
+    seed_one_file(pool, "f1", "d1", "disk.iso", 7, -1001, 100).await?;
+    seed_one_file(pool, "f_src", "d_src", "foreign.iso", 5, -4004, 400).await?;
+    let local = tmp.path().join("copy.iso");
+    std::fs::write(&local, b"payload")?;
+    let foreign = tmp.path().join("foreign.iso");
+    std::fs::write(&foreign, b"other")?;
+    for (id, file) in [("f1", &local), ("f_src", &foreign)] {
+      sqlx::query("UPDATE files SET content_hash = ? WHERE id = ?")
+        .bind(hash_full(file)?)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    }
+    sqlx::query("INSERT INTO source_channels(chat_id, title, target_dir_id, created_at) VALUES(-4004, 'Чужой', 'd_src', 0)")
+      .execute(pool)
+      .await?;
+    sqlx::query("INSERT INTO storage_channels(chat_id, title, created_at) VALUES(-3003, 'Архив', 0)")
+      .execute(pool)
+      .await?;
+    sqlx::query("INSERT INTO dir_storage_channels(dir_id, chat_id) VALUES('d1', -3003)")
+      .execute(pool)
+      .await?;
+    let tg = MockTelegram::default().allow_copy(9000);
+
+    // Копия ложится в канал папки, а не в канал найденного файла.
+    let DedupUpload::Referenced(id) = upload_file_dedup(pool, &tg, -1001, "d1", &local, Dedup::Reference).await? else {
+      panic!("ожидалась ссылка на существующий файл");
+    };
+    assert_eq!(tg.state.lock().expect("mock lock").copied_between.clone(), vec![(-1001, -3003, 100)]);
+    let chat: i64 = sqlx::query("SELECT tg_chat_id FROM files WHERE id = ?")
+      .bind(&id)
+      .fetch_one(pool)
+      .await?
+      .get("tg_chat_id");
+    assert_eq!(chat, -3003);
+
+    // Совпадение с файлом канала-источника дубликатом не считается.
+    assert!(find_duplicate(pool, &hash_full(&foreign)?).await?.is_none());
+    Ok(())
+  }
+
   #[tokio::test]
   async fn download_file_returns_existing_without_redownload_when_overwrite_disabled() -> anyhow::Result<()> {
     let (_tmp, db, paths) = setup_db_and_paths().await?;
     seed_one_file(db.pool(), "f1", "d1", "report.txt", 0, -1001, 100).await?;
 
-    let existing_dir = paths.cache_dir.join("downloads").join("Документы");
+    let existing_dir = paths.layout().downloads_dir().join("Документы");
     std::fs::create_dir_all(&existing_dir)?;
     let existing_path = existing_dir.join("report.txt");
     std::fs::write(&existing_path, b"cached")?;
+    local_copies::record(db.pool(), &paths, "f1", &existing_path).await?;
 
     let tg = MockTelegram::default();
-    let out = download_file(db.pool(), &tg, &paths, -2002, "f1", false).await?;
+    let out = download_file(db.pool(), &tg, &paths, -2002, "f1", false, None).await?;
 
     assert_eq!(out, existing_path);
     assert_eq!(tg.download_attempts().len(), 0);
@@ -1351,13 +3513,14 @@ mod tests {
     let (_tmp, db, paths) = setup_db_and_paths().await?;
     seed_one_file(db.pool(), "f2", "d2", "video.mp4", 6, -3001, 200).await?;
 
-    let existing_dir = paths.cache_dir.join("downloads").join("Документы");
+    let existing_dir = paths.layout().downloads_dir().join("Документы");
     std::fs::create_dir_all(&existing_dir)?;
     let existing_path = existing_dir.join("video.mp4");
     std::fs::write(&existing_path, b"oldold")?;
+    local_copies::record(db.pool(), &paths, "f2", &existing_path).await?;
 
     let tg = MockTelegram::default().with_payload(-3001, 200, b"new payload bytes");
-    let out = download_file(db.pool(), &tg, &paths, -3001, "f2", true).await?;
+    let out = download_file(db.pool(), &tg, &paths, -3001, "f2", true, None).await?;
 
     assert_eq!(out, existing_path);
     assert_eq!(std::fs::read(&out)?, b"new payload bytes");
@@ -1372,6 +3535,56 @@ mod tests {
     Ok(())
   }
 
+  #[tokio::test]
+  async fn healed_file_keeps_new_message_when_old_caption_edit_fails() -> anyhow::Result<()> {
+    let (_tmp, db, paths) = setup_db_and_paths().await?;
+    let pool = db.pool();
+    seed_one_file(pool, "f_heal", "d_heal", "photo.jpg", 7, -4001, 300).await?;
+    let local = paths.layout().downloads_dir().join("photo.jpg");
+    std::fs::write(&local, b"healthy")?;
+    let hash = hash_short(&local)?;
+    sqlx::query("UPDATE files SET hash = ? WHERE id = 'f_heal'").bind(&hash).execute(pool).await?;
+    local_copies::record(pool, &paths, "f_heal", &local).await?;
+    broken::mark_file_broken(pool, "f_heal", BrokenReason::ChecksumMismatch).await?;
+
+    // Подпись старого сообщения править нельзя: fsmeta на нем остается.
+    let tg = MockTelegram::default().allow_send(9500);
+    assert!(crate::app::verify::heal_from_local(pool, &tg, &paths, -4001, "f_heal", Some("badbad")).await?);
+    let row = sqlx::query("SELECT tg_msg_id, is_broken, broken_reason FROM files WHERE id = 'f_heal'")
+      .fetch_one(pool)
+      .await?;
+    assert_eq!(row.get::<i64,_>("tg_msg_id"), 9501);
+    assert_eq!(row.get::<i64,_>("is_broken"), 0);
+    assert_eq!(row.try_get::<String,_>("broken_reason").ok(), None);
+
+    let stale = HistoryMessage {
+      id: 300,
+      date: 0,
+      text: None,
+      caption: Some(make_file_caption(&FileMeta {
+        dir_id: "d_heal".to_string(),
+        file_id: "f_heal".to_string(),
+        name: "photo.jpg".to_string(),
+        hash_short: hash.clone()
+      })),
+      file_size: Some(7),
+      file_name: Some("photo.jpg".to_string()),
+      sender_id: None,
+      media_group_id: None,
+      content_type: Some("messageDocument".to_string()),
+      sticker_set_id: None,
+      file_unique_id: None
+    };
+    let mut unassigned = None;
+    let outcomes = indexer::index_storage_batch(pool, &tg, -4001, std::slice::from_ref(&stale), &mut unassigned).await?;
+    assert!(outcomes[0].skipped && !outcomes[0].file);
+    let outcome = indexer::index_storage_message(pool, &tg, -4001, &stale, &mut unassigned).await?;
+    assert!(outcome.skipped);
+    let msg_id: i64 = sqlx::query("SELECT tg_msg_id FROM files WHERE id = 'f_heal'").fetch_one(pool).await?.get("tg_msg_id");
+    assert_eq!(msg_id, 9501);
+    Ok(())
+  }
+
   #[tokio::test]
   async fn download_file_fallback_finds_new_message_and_updates_db() -> anyhow::Result<()> {
     let (_tmp, db, paths) = setup_db_and_paths().await?;
@@ -1394,7 +3607,12 @@ mod tests {
         text: None,
         caption: Some(caption),
         file_size: Some(0),
-        file_name: Some("archive.zip".to_string())
+        file_name: Some("archive.zip".to_string()),
+        sender_id: None,
+        media_group_id: None,
+        content_type: Some("messageDocument".to_string()),
+        sticker_set_id: None,
+        file_unique_id: None
       }]
     };
 
@@ -1403,7 +3621,7 @@ mod tests {
       .with_payload(-5002, 555, b"fallback payload")
       .with_search_result(-5002, query, 0, search_hit);
 
-    let out = download_file(db.pool(), &tg, &paths, -5002, file_id, false).await?;
+    let out = download_file(db.pool(), &tg, &paths, -5002, file_id, false, None).await?;
     assert_eq!(std::fs::read(&out)?, b"fallback payload");
     assert_eq!(tg.download_attempts(), vec![(-4001, 300), (-5002, 555)]);
 
@@ -1425,10 +3643,11 @@ mod tests {
     let (_tmp, db, paths) = setup_db_and_paths().await?;
     seed_one_file(db.pool(), "f_del", "d_del", "report.txt", 9999, -7001, 701).await?;
 
-    let local_dir = paths.cache_dir.join("downloads").join("Документы");
+    let local_dir = paths.layout().downloads_dir().join("Документы");
     std::fs::create_dir_all(&local_dir)?;
     let local_path = local_dir.join("report.txt");
     std::fs::write(&local_path, b"local copy")?;
+    local_copies::record(db.pool(), &paths, "f_del", &local_path).await?;
 
     let tg = MockTelegram::default();
     delete_file(db.pool(), &tg, &paths, "f_del").await?;
@@ -1442,4 +3661,131 @@ mod tests {
     assert!(row.is_none());
     Ok(())
   }
+
+  #[tokio::test]
+  async fn search_files_recursive_includes_subfolders() -> anyhow::Result<()> {
+    let (_tmp, db, paths) = setup_db_and_paths().await?;
+    let pool = db.pool();
+    seed_one_file(pool, "f_top", "d_work", "invoices-2024.pdf", 10, -1, 1).await?;
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at, is_broken) VALUES('d_sub', 'd_work', 'Sub', NULL, 0, 0)")
+      .execute(pool)
+      .await?;
+    sqlx::query(
+      "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, is_broken)
+       VALUES('f_sub', 'd_sub', 'invoices-2025.pdf', 10, 'deadbeef', -1, 2, 0, 0)"
+    )
+      .execute(pool)
+      .await?;
+
+    let mut filters = SearchFilters { dir_id: Some("d_work".into()), name: Some("invoices".into()), ..Default::default() };
+    let flat = search_files(pool, &paths, &filters).await?;
+    assert_eq!(flat.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(), vec!["f_top"]);
+    filters.recursive = true;
+    let deep = search_files(pool, &paths, &filters).await?;
+    assert_eq!(deep.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(), vec!["f_top", "f_sub"]);
+
+    sqlx::query("UPDATE files SET is_broken = 1, size = 500 WHERE id = 'f_sub'").execute(pool).await?;
+    filters.sort = SearchSort::SizeDesc;
+    let sorted = search_files(pool, &paths, &filters).await?;
+    assert_eq!(sorted.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(), vec!["f_sub", "f_top"]);
+    filters.is_broken = Some(false);
+    filters.min_size = Some(5);
+    let healthy = search_files(pool, &paths, &filters).await?;
+    assert_eq!(healthy.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(), vec!["f_top"]);
+    filters.is_broken = None;
+    filters.is_downloaded = Some(true);
+    assert!(search_files(pool, &paths, &filters).await?.is_empty());
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn search_files_full_text_ranks_names_above_captions() -> anyhow::Result<()> {
+    let (_tmp, db, paths) = setup_db_and_paths().await?;
+    let pool = db.pool();
+    seed_one_file(pool, "f_name", "d_a", "Годовой отчет.pdf", 10, -1, 1).await?;
+    seed_one_file(pool, "f_caption", "d_b", "scan-0001.jpg", 10, -1, 2).await?;
+    fulltext::remember_caption(pool, "f_caption", "Черновик отчета для бухгалтерии").await?;
+
+    let filters = SearchFilters { text: Some("отч".into()), sort: SearchSort::Relevance, ..Default::default() };
+    let found = search_files(pool, &paths, &filters).await?;
+    assert_eq!(found.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(), vec!["f_name", "f_caption"]);
+    assert_eq!(found[0].snippet.as_deref(), Some("Годовой [отчет].pdf"));
+    assert_eq!(found[1].snippet.as_deref(), Some("Черновик [отчета] для бухгалтерии"));
+
+    // Путь папки тоже ищется.
+    let filters = SearchFilters { text: Some("документы scan".into()), ..Default::default() };
+    let found = search_files(pool, &paths, &filters).await?;
+    assert_eq!(found.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(), vec!["f_caption"]);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn search_files_text_matches_substrings() -> anyhow::Result<()> {
+    let (_tmp, db, paths) = setup_db_and_paths().await?;
+    let pool = db.pool();
+    seed_one_file(pool, "f_report", "d_a", "report.pdf", 10, -1, 1).await?;
+    seed_one_file(pool, "f_port", "d_b", "port plan.txt", 10, -1, 2).await?;
+    seed_one_file(pool, "f_other", "d_c", "notes.txt", 10, -1, 3).await?;
+    fulltext::remember_caption(pool, "f_other", "export for accounting").await?;
+
+    // Слово целиком ранжируется выше совпадения внутри слова.
+    let filters = SearchFilters { text: Some("port".into()), sort: SearchSort::Relevance, ..Default::default() };
+    let found = search_files(pool, &paths, &filters).await?;
+    assert_eq!(found.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(), vec!["f_port", "f_other", "f_report"]);
+
+    // Совпадения по имени из индекса в памяти заменяют LIKE.
+    let filters = SearchFilters {
+      text: Some("port".into()),
+      name_hits: Some(vec!["f_report".into()]),
+      ..Default::default()
+    };
+    let found = search_files(pool, &paths, &filters).await?;
+    assert_eq!(found.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(), vec!["f_other", "f_port", "f_report"]);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn list_group_returns_album_of_one_chat_in_send_order() -> anyhow::Result<()> {
+    let (_tmp, db, paths) = setup_db_and_paths().await?;
+    let pool = db.pool();
+    for (id, chat, msg) in [("second", -1001, 11), ("first", -1001, 10), ("other_chat", -2002, 12), ("single", -1001, 13)] {
+      seed_one_file(pool, id, &format!("d_{id}"), &format!("{id}.jpg"), 1, chat, msg).await?;
+    }
+    sqlx::query("UPDATE files SET media_group_id = 'g1' WHERE id IN ('first', 'second', 'other_chat')")
+      .execute(pool)
+      .await?;
+
+    let album = list_group(pool, &paths, -1001, "g1").await?;
+    assert_eq!(album.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(), vec!["first", "second"]);
+    assert!(album.iter().all(|f| f.media_group_id.as_deref() == Some("g1")));
+    assert!(list_group(pool, &paths, -1001, "missing").await?.is_empty());
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn search_files_downloaded_filter_fills_the_limit() -> anyhow::Result<()> {
+    let (_tmp, db, paths) = setup_db_and_paths().await?;
+    let pool = db.pool();
+    let dir = paths.layout().downloads_dir();
+    std::fs::create_dir_all(&dir)?;
+    for (i, id) in ["a", "b", "c", "d", "e"].into_iter().enumerate() {
+      seed_one_file(pool, id, &format!("d_{id}"), &format!("{id}.txt"), 1, -1, i as i64 + 1).await?;
+    }
+    // Скачаны «b», «d» и «e»; запись о копии «c» устарела — файла на диске нет.
+    for id in ["b", "c", "d", "e"] {
+      let path = dir.join(format!("{id}.txt"));
+      std::fs::write(&path, b"x")?;
+      local_copies::record(pool, &paths, id, &path).await?;
+    }
+    std::fs::remove_file(dir.join("c.txt"))?;
+
+    let filters = SearchFilters { is_downloaded: Some(true), limit: Some(2), ..Default::default() };
+    let found = search_files(pool, &paths, &filters).await?;
+    assert_eq!(found.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(), vec!["b", "d"]);
+
+    let filters = SearchFilters { is_downloaded: Some(false), limit: Some(2), ..Default::default() };
+    let found = search_files(pool, &paths, &filters).await?;
+    assert_eq!(found.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(), vec!["a", "c"]);
+    Ok(())
+  }
 }
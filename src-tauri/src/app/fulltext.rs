@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::{SqliteConnection, SqlitePool};
+
+use crate::app::files::build_dir_path;
+
+/// Слова подписи, которые пишет само приложение: метки fsmeta и пары
+/// `ключ=значение`. В поиск идет только текст пользователя.
+fn is_service_word(word: &str) -> bool {
+  matches!(word, "#ocltg" | "#v1" | "#file" | "#part" | "#enc")
+    || word
+      .split_once('=')
+      .is_some_and(|(key, _)| !key.is_empty() && key.len() <= 4 && key.chars().all(|c| c.is_ascii_lowercase()))
+}
+
+/// Текст подписи Telegram без служебной разметки.
+pub fn caption_text(caption: &str) -> String {
+  caption
+    .split_whitespace()
+    .filter(|word| !is_service_word(word))
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+/// Запоминает подпись сообщения для полнотекстового поиска. Подпись
+/// сохраняется только один раз: при импорте приложение переписывает
+/// сообщение своей разметкой, а искать нужно по исходному тексту.
+pub async fn remember_caption(pool: &SqlitePool, file_id: &str, caption: &str) -> anyhow::Result<()> {
+  let mut conn = pool.acquire().await?;
+  remember_captions(&mut conn, &[(file_id, caption)]).await
+}
+
+/// `remember_caption` для пачки файлов на уже открытом соединении, например
+/// внутри транзакции синхронизации.
+pub async fn remember_captions(conn: &mut SqliteConnection, captions: &[(&str, &str)]) -> anyhow::Result<()> {
+  for (file_id, caption) in captions {
+    let text = caption_text(caption);
+    if text.is_empty() {
+      continue;
+    }
+    sqlx::query("UPDATE file_search SET caption = ? WHERE file_id = ? AND caption = ''")
+      .bind(&text)
+      .bind(*file_id)
+      .execute(&mut *conn)
+      .await?;
+  }
+  Ok(())
+}
+
+/// Пересчитывает пути папок у строк, которые триггеры пометили устаревшими.
+/// Вызывается перед поиском; без изменений стоит один запрос.
+pub async fn refresh_folders(pool: &SqlitePool) -> anyhow::Result<usize> {
+  let stale_dirs: Vec<String> = sqlx::query("SELECT dir_id FROM file_search_stale_dirs")
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| row.get("dir_id"))
+    .collect();
+  for dir_id in &stale_dirs {
+    sqlx::query(
+      "UPDATE file_search SET stale = 1 WHERE file_id IN (SELECT id FROM files WHERE dir_id IN (
+         WITH RECURSIVE subtree(id) AS (
+           SELECT id FROM directories WHERE id = ?
+           UNION SELECT d.id FROM directories d JOIN subtree s ON d.parent_id = s.id
+         ) SELECT id FROM subtree))"
+    )
+      .bind(dir_id)
+      .execute(pool)
+      .await?;
+    sqlx::query("DELETE FROM file_search_stale_dirs WHERE dir_id = ?")
+      .bind(dir_id)
+      .execute(pool)
+      .await?;
+  }
+
+  let rows = sqlx::query("SELECT s.file_id, f.dir_id FROM file_search s JOIN files f ON f.id = s.file_id WHERE s.stale = 1")
+    .fetch_all(pool)
+    .await?;
+  let mut folders: HashMap<String, String> = HashMap::new();
+  for row in &rows {
+    let dir_id: String = row.get("dir_id");
+    let folder = match folders.get(&dir_id) {
+      Some(folder) => folder.clone(),
+      None => {
+        let path = build_dir_path(pool, &dir_id).await?;
+        let folder = path
+          .components()
+          .map(|c| c.as_os_str().to_string_lossy().into_owned())
+          .collect::<Vec<_>>()
+          .join("/");
+        folders.insert(dir_id, folder.clone());
+        folder
+      }
+    };
+    sqlx::query("UPDATE file_search SET folder = ?, stale = 0 WHERE file_id = ?")
+      .bind(folder)
+      .bind(row.get::<String,_>("file_id"))
+      .execute(pool)
+      .await?;
+  }
+  if !rows.is_empty() {
+    tracing::debug!(event = "file_search_folders_refreshed", files = rows.len(), "Пути папок в поисковом индексе обновлены");
+  }
+  Ok(rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[test]
+  fn caption_text_drops_service_markup() {
+    assert_eq!(
+      caption_text("#ocltg #v1 #file d=D1 f=F1 n=Отчет.pdf h=abc #Работа #enc k=key"),
+      "#Работа"
+    );
+    assert_eq!(caption_text("Сканы договора за март"), "Сканы договора за март");
+  }
+
+  #[tokio::test]
+  async fn folders_follow_directory_renames() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query(
+      "INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES
+         ('d1', NULL, 'Работа', NULL, 0), ('d2', 'd1', 'Отчеты', NULL, 0)"
+    )
+      .execute(pool)
+      .await?;
+    sqlx::query(
+      "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at)
+       VALUES('f1', 'd2', 'q1.pdf', 1, 'h', 1, 1, 0)"
+    )
+      .execute(pool)
+      .await?;
+    remember_caption(pool, "f1", "Итоги квартала").await?;
+    remember_caption(pool, "f1", "#ocltg #v1 #file d=d2 f=f1 n=q1.pdf h=h #Отчеты").await?;
+
+    assert_eq!(refresh_folders(pool).await?, 1);
+    assert_eq!(refresh_folders(pool).await?, 0);
+    let row = sqlx::query("SELECT folder, caption FROM file_search WHERE file_id = 'f1'").fetch_one(pool).await?;
+    assert_eq!(row.get::<String,_>("folder"), "Работа/Отчеты");
+    assert_eq!(row.get::<String,_>("caption"), "Итоги квартала");
+
+    sqlx::query("UPDATE directories SET name = 'Архив' WHERE id = 'd1'").execute(pool).await?;
+    assert_eq!(refresh_folders(pool).await?, 1);
+    let folder: String = sqlx::query("SELECT folder FROM file_search WHERE file_id = 'f1'").fetch_one(pool).await?.get("folder");
+    assert_eq!(folder, "Архив/Отчеты");
+    Ok(())
+  }
+}
@@ -0,0 +1,301 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Manager};
+use tokio::sync::Notify;
+
+use crate::events::{self, Change};
+use crate::paths::Paths;
+use crate::settings;
+use crate::state::AppState;
+use crate::telegram::TelegramService;
+
+use super::broken::{self, BrokenReason};
+use super::{files, local_copies, maintenance, source_channels};
+
+/// Очередь не копится бесконечно: пропущенный файл проверится при следующем скачивании.
+const MAX_PENDING: usize = 256;
+/// Сколько скачанных копий без полного хэша досчитывается за один запуск.
+const BACKFILL_BATCH: i64 = 200;
+
+static QUEUE: Lazy<Mutex<VecDeque<HashJob>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+static WAKE: Lazy<Notify> = Lazy::new(Notify::new);
+
+/// Скачанный файл, хэш которого нужно досчитать.
+#[derive(Debug, Clone)]
+pub struct HashJob {
+  pub file_id: String,
+  pub path: PathBuf,
+  /// Хэш в базе на момент скачивания.
+  pub recorded_hash: String,
+  /// Записанный хэш получен при импорте из координат сообщения.
+  pub import_seed: bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashCheck {
+  /// Хэш совпал с записанным.
+  Matched,
+  /// Вместо хэша импорта записан настоящий, подпись обновлена.
+  Upgraded,
+  /// Содержимое не совпало с записанным хэшем, файл помечен поврежденным.
+  Mismatch,
+  /// Запись успела измениться или пропасть, проверка не нужна.
+  Skipped
+}
+
+/// Что делать с файлом, зная записанный и настоящий хэш.
+fn classify(recorded: &str, import_seed: bool, actual: &str) -> HashCheck {
+  if actual == recorded {
+    HashCheck::Matched
+  } else if import_seed {
+    HashCheck::Upgraded
+  } else {
+    HashCheck::Mismatch
+  }
+}
+
+/// Ставит скачанный файл в очередь на проверку хэша. Хэш считается в фоне,
+/// чтобы не задерживать открытие больших файлов.
+pub fn enqueue(file_id: &str, path: &Path, recorded_hash: &str, import_seed: bool) {
+  let job = HashJob {
+    file_id: file_id.to_string(),
+    path: path.to_path_buf(),
+    recorded_hash: recorded_hash.to_string(),
+    import_seed
+  };
+  {
+    let mut queue = QUEUE.lock();
+    queue.retain(|j| j.file_id != job.file_id);
+    if queue.len() >= MAX_PENDING {
+      tracing::debug!(event = "hash_upgrade_queue_full", file_id = file_id, "Очередь проверки хэшей заполнена, файл пропущен");
+      return;
+    }
+    queue.push_back(job);
+  }
+  WAKE.notify_one();
+}
+
+/// Очередь принадлежит базе активного аккаунта; при переключении она
+/// сбрасывается, пропущенные файлы проверятся при следующем скачивании.
+pub fn clear_queue() {
+  QUEUE.lock().clear();
+}
+
+pub fn spawn_worker(app: AppHandle) -> JoinHandle<()> {
+  tauri::async_runtime::spawn(async move {
+    maintenance::wait_for_window().await;
+    let state = app.state::<AppState>();
+    if let (Ok(db), Ok(paths)) = (state.db(), state.paths()) {
+      match backfill_content_hashes(db.pool(), &paths).await {
+        Ok(0) => {}
+        Ok(filled) => tracing::info!(event = "content_hash_backfilled", files = filled, "Полные хэши скачанных файлов досчитаны"),
+        Err(e) => tracing::warn!(event = "content_hash_backfill_failed", error = %e, "Не удалось досчитать полные хэши скачанных файлов")
+      }
+    }
+    loop {
+      let next = QUEUE.lock().pop_front();
+      let Some(job) = next else {
+        WAKE.notified().await;
+        continue;
+      };
+      maintenance::wait_for_window().await;
+      if let Err(e) = process(&app, &job).await {
+        tracing::warn!(event = "hash_upgrade_failed", file_id = job.file_id.as_str(), error = %e, "Не удалось проверить хэш скачанного файла");
+      }
+    }
+  })
+}
+
+async fn process(app: &AppHandle, job: &HashJob) -> anyhow::Result<()> {
+  let state = app.state::<AppState>();
+  let db = state.db()?;
+  let tg = state.telegram()?;
+  let outcome = check_downloaded(db.pool(), tg.as_ref(), job).await?;
+  if matches!(outcome, HashCheck::Upgraded | HashCheck::Mismatch) {
+    state.invalidate_listings();
+    let dir_id = sqlx::query("SELECT dir_id FROM files WHERE id = ?")
+      .bind(&job.file_id)
+      .fetch_optional(db.pool())
+      .await?
+      .map(|r| r.get::<String, _>("dir_id"));
+    events::file_changed(app, &job.file_id, Change::Updated, dir_id.as_deref());
+  }
+  Ok(())
+}
+
+/// Считает хэш скачанного файла и сверяет с записанным. Хэш импорта заменяется
+/// настоящим: сначала в подписи, затем в базе, чтобы синхронизация не вернула
+/// старое значение. Расхождение с настоящим хэшем помечает файл поврежденным.
+pub async fn check_downloaded(pool: &SqlitePool, tg: &dyn TelegramService, job: &HashJob) -> anyhow::Result<HashCheck> {
+  let path = job.path.clone();
+  let content_hash = tokio::task::spawn_blocking(move || files::hash_full(&path)).await??;
+  let actual: String = content_hash.chars().take(8).collect();
+  local_copies::set_hash(pool, &job.file_id, &content_hash).await?;
+
+  let row = sqlx::query("SELECT hash, tg_chat_id, tg_msg_id, broken_reason FROM files WHERE id = ?")
+    .bind(&job.file_id)
+    .fetch_optional(pool)
+    .await?;
+  let Some(row) = row else {
+    return Ok(HashCheck::Skipped);
+  };
+  if row.get::<String, _>("hash") != job.recorded_hash {
+    return Ok(HashCheck::Skipped);
+  }
+  let broken_reason: Option<String> = row.try_get("broken_reason").ok();
+
+  let outcome = classify(&job.recorded_hash, job.import_seed, &actual);
+  match outcome {
+    HashCheck::Matched => {
+      if broken_reason.as_deref() == Some(BrokenReason::ChecksumMismatch.as_str()) {
+        sqlx::query("UPDATE files SET is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
+          .bind(&job.file_id)
+          .execute(pool)
+          .await?;
+      }
+    }
+    HashCheck::Upgraded => {
+      // Подпись в канале только для чтения и в канале-источнике не правим: без
+      // fsmeta файл и так узнается по координатам, и хэш из базы ничем не перезапишется.
+      let chat_id: i64 = row.get("tg_chat_id");
+      if !settings::get_read_only_channel(pool).await? && !source_channels::is_source_chat(pool, chat_id).await? {
+        let caption = files::caption_with_hash(pool, &job.file_id, &actual).await?;
+        tg.edit_message_caption(row.get("tg_chat_id"), row.get("tg_msg_id"), caption).await?;
+      }
+      sqlx::query("UPDATE files SET hash = ? WHERE id = ? AND hash = ?")
+        .bind(&actual)
+        .bind(&job.file_id)
+        .bind(&job.recorded_hash)
+        .execute(pool)
+        .await?;
+      tracing::info!(event = "file_hash_upgraded", file_id = job.file_id.as_str(), "Хэш импортированного файла заменен настоящим");
+    }
+    HashCheck::Mismatch => {
+      tracing::warn!(
+        event = "file_hash_mismatch",
+        file_id = job.file_id.as_str(),
+        expected = job.recorded_hash.as_str(),
+        actual = actual.as_str(),
+        "Скачанный файл не совпадает с контрольной суммой"
+      );
+      broken::mark_file_broken(pool, &job.file_id, BrokenReason::ChecksumMismatch).await?;
+    }
+    HashCheck::Skipped => {}
+  }
+  // Полный хэш нужен для поиска дублей; у старых записей он появляется так.
+  if matches!(outcome, HashCheck::Matched | HashCheck::Upgraded) {
+    sqlx::query("UPDATE files SET content_hash = ? WHERE id = ?")
+      .bind(&content_hash)
+      .bind(&job.file_id)
+      .execute(pool)
+      .await?;
+  }
+  Ok(outcome)
+}
+
+/// Полный хэш нужен для поиска дублей, а у файлов, загруженных до его
+/// появления, его нет. Там, где есть скачанная копия, хэш берется из нее:
+/// сначала уже посчитанный при проверке, затем досчитывается по диску.
+/// Копия, не совпавшая с записанным коротким хэшем, пропускается.
+pub async fn backfill_content_hashes(pool: &SqlitePool, paths: &Paths) -> anyhow::Result<u64> {
+  let mut filled = sqlx::query(
+    "UPDATE files SET content_hash = (SELECT c.hash FROM local_copies c WHERE c.file_id = files.id)
+     WHERE content_hash IS NULL AND part_count = 0 AND id IN (
+       SELECT c.file_id FROM local_copies c WHERE c.hash IS NOT NULL AND substr(c.hash, 1, 8) = files.hash
+     )"
+  )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+  let ids: Vec<String> = sqlx::query(
+    "SELECT f.id FROM files f JOIN local_copies c ON c.file_id = f.id
+     WHERE f.content_hash IS NULL AND f.part_count = 0 AND c.hash IS NULL
+     ORDER BY f.id LIMIT ?"
+  )
+    .bind(BACKFILL_BATCH)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| r.get("id"))
+    .collect();
+  for file_id in ids {
+    let Some(copy) = local_copies::lookup(pool, paths, &file_id).await? else {
+      continue;
+    };
+    let path = copy.path.clone();
+    let content_hash = tokio::task::spawn_blocking(move || files::hash_full(&path)).await??;
+    local_copies::set_hash(pool, &file_id, &content_hash).await?;
+    let short: String = content_hash.chars().take(8).collect();
+    filled += sqlx::query("UPDATE files SET content_hash = ? WHERE id = ? AND hash = ? AND content_hash IS NULL")
+      .bind(&content_hash)
+      .bind(&file_id)
+      .bind(&short)
+      .execute(pool)
+      .await?
+      .rows_affected();
+  }
+  Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn import_hash_is_upgraded_and_real_hash_is_checked() {
+    assert_eq!(classify("abcd1234", true, "abcd1234"), HashCheck::Matched);
+    assert_eq!(classify("seed0000", true, "abcd1234"), HashCheck::Upgraded);
+    assert_eq!(classify("abcd1234", false, "ffff0000"), HashCheck::Mismatch);
+  }
+
+  #[tokio::test]
+  async fn backfill_takes_full_hashes_from_matching_local_copies() -> anyhow::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let paths = Paths::from_base(tmp.path().to_path_buf());
+    let db = crate::db::Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d1', NULL, 'docs', NULL, 0)")
+      .execute(pool)
+      .await?;
+    let dir = paths.layout().downloads_dir().join("docs");
+    std::fs::create_dir_all(&dir)?;
+    let mut expected = Vec::new();
+    for (msg_id, (id, body)) in [("checked", "one"), ("fresh", "two"), ("other", "three")].into_iter().enumerate() {
+      let path = dir.join(format!("{id}.txt"));
+      std::fs::write(&path, body)?;
+      let full = files::hash_full(&path)?;
+      // У «other» на диске лежит чужое содержимое: короткий хэш не совпадет.
+      let short: String = if id == "other" { "00000000".to_string() } else { full.chars().take(8).collect() };
+      sqlx::query("INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at) VALUES(?, 'd1', ?, 3, ?, 1, ?, 0)")
+        .bind(id)
+        .bind(format!("{id}.txt"))
+        .bind(&short)
+        .bind(msg_id as i64)
+        .execute(pool)
+        .await?;
+      local_copies::record(pool, &paths, id, &path).await?;
+      expected.push((id, full));
+    }
+    local_copies::set_hash(pool, "checked", &expected[0].1).await?;
+
+    assert_eq!(backfill_content_hashes(pool, &paths).await?, 2);
+    for (id, full) in &expected {
+      let stored: Option<String> = sqlx::query("SELECT content_hash FROM files WHERE id = ?")
+        .bind(id)
+        .fetch_one(pool)
+        .await?
+        .try_get("content_hash")?;
+      assert_eq!(stored.as_deref(), (*id != "other").then_some(full.as_str()));
+    }
+    assert_eq!(backfill_content_hashes(pool, &paths).await?, 0);
+    Ok(())
+  }
+}
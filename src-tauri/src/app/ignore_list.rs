@@ -0,0 +1,148 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+
+use crate::telegram::{content_kind, HistoryMessage};
+
+/// Что realtime-индексатор пропускает, не открывая БД: болтовня в канале хранения,
+/// стикеры, голосовые. Сообщения с fsmeta (`#ocltg`) не отбрасываются никогда.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IgnoreList {
+  #[serde(default)]
+  pub senders: Vec<i64>,
+  #[serde(default)]
+  pub text_prefixes: Vec<String>,
+  /// Типы содержимого TDLib без префикса `message`: `sticker`, `voice_note`, `animation`...
+  #[serde(default)]
+  pub content_types: Vec<String>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IgnoreReason {
+  Sender,
+  TextPrefix,
+  ContentType
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Serialize)]
+pub struct IgnoreCounters {
+  pub sender: u64,
+  pub text_prefix: u64,
+  pub content_type: u64
+}
+
+static ACTIVE: Lazy<RwLock<IgnoreList>> = Lazy::new(|| RwLock::new(IgnoreList::default()));
+static IGNORED_SENDER: AtomicU64 = AtomicU64::new(0);
+static IGNORED_TEXT_PREFIX: AtomicU64 = AtomicU64::new(0);
+static IGNORED_CONTENT_TYPE: AtomicU64 = AtomicU64::new(0);
+
+impl IgnoreList {
+  pub fn normalized(self) -> IgnoreList {
+    let mut senders = self.senders;
+    senders.sort_unstable();
+    senders.dedup();
+    let clean = |values: Vec<String>, lower: bool| {
+      let mut out: Vec<String> = Vec::new();
+      for v in values {
+        let v = if lower { v.trim().to_lowercase() } else { v.trim().to_string() };
+        if !v.is_empty() && !out.contains(&v) {
+          out.push(v);
+        }
+      }
+      out
+    };
+    IgnoreList {
+      senders,
+      text_prefixes: clean(self.text_prefixes, false),
+      content_types: clean(self.content_types, true)
+    }
+  }
+
+  pub fn check(&self, msg: &HistoryMessage) -> Option<IgnoreReason> {
+    let text = msg.text.as_deref().unwrap_or("");
+    let caption = msg.caption.as_deref().unwrap_or("");
+    if text.contains("#ocltg") || caption.contains("#ocltg") {
+      return None;
+    }
+    if msg.sender_id.map(|id| self.senders.contains(&id)).unwrap_or(false) {
+      return Some(IgnoreReason::Sender);
+    }
+    let kind = msg.content_type.as_deref().map(content_kind).unwrap_or_default();
+    if self.content_types.iter().any(|t| *t == kind) {
+      return Some(IgnoreReason::ContentType);
+    }
+    if !text.is_empty() && self.text_prefixes.iter().any(|p| text.starts_with(p.as_str())) {
+      return Some(IgnoreReason::TextPrefix);
+    }
+    None
+  }
+}
+
+pub fn set_active(list: IgnoreList) {
+  *ACTIVE.write() = list.normalized();
+}
+
+/// Проверка для realtime-обновлений; считает пропуски по причинам.
+pub fn should_ignore(msg: &HistoryMessage) -> bool {
+  let reason = {
+    let list = ACTIVE.read();
+    list.check(msg)
+  };
+  let Some(reason) = reason else {
+    return false;
+  };
+  let counter = match reason {
+    IgnoreReason::Sender => &IGNORED_SENDER,
+    IgnoreReason::TextPrefix => &IGNORED_TEXT_PREFIX,
+    IgnoreReason::ContentType => &IGNORED_CONTENT_TYPE
+  };
+  counter.fetch_add(1, Ordering::Relaxed);
+  true
+}
+
+pub fn counters() -> IgnoreCounters {
+  IgnoreCounters {
+    sender: IGNORED_SENDER.load(Ordering::Relaxed),
+    text_prefix: IGNORED_TEXT_PREFIX.load(Ordering::Relaxed),
+    content_type: IGNORED_CONTENT_TYPE.load(Ordering::Relaxed)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn msg(text: Option<&str>, sender_id: Option<i64>, content_type: &str) -> HistoryMessage {
+    HistoryMessage {
+      id: 1,
+      date: 0,
+      text: text.map(str::to_string),
+      caption: None,
+      file_size: None,
+      file_name: None,
+      sender_id,
+      media_group_id: None,
+      content_type: Some(content_type.to_string()),
+      sticker_set_id: None,
+      file_unique_id: None
+    }
+  }
+
+  #[test]
+  fn check_matches_each_rule_but_keeps_fsmeta() {
+    let list = IgnoreList {
+      senders: vec![7],
+      text_prefixes: vec!["!".into()],
+      content_types: vec!["Sticker".into(), "voice_note".into()]
+    }
+    .normalized();
+
+    assert_eq!(list.check(&msg(Some("привет"), Some(7), "messageText")), Some(IgnoreReason::Sender));
+    assert_eq!(list.check(&msg(Some("!ping"), None, "messageText")), Some(IgnoreReason::TextPrefix));
+    assert_eq!(list.check(&msg(None, None, "messageVoiceNote")), Some(IgnoreReason::ContentType));
+    assert_eq!(list.check(&msg(None, None, "messageSticker")), Some(IgnoreReason::ContentType));
+    assert_eq!(list.check(&msg(Some("заметка"), None, "messageText")), None);
+    assert_eq!(list.check(&msg(Some("#ocltg #v1 #dir d=1"), Some(7), "messageText")), None);
+  }
+}
@@ -0,0 +1,326 @@
+use chrono::Utc;
+use regex::{Regex, RegexBuilder};
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+use ulid::Ulid;
+
+use crate::app::dirs::dir_exists;
+use crate::telegram::HistoryMessage;
+
+/// Пользовательское правило разбора файлов без fsmeta. Правила проверяются
+/// по возрастанию `priority`, срабатывает первое подходящее; пустые условия
+/// считаются выполненными.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ImportRule {
+  pub id: String,
+  pub name: String,
+  pub priority: i64,
+  pub enabled: bool,
+  pub extensions: Vec<String>,
+  pub sender_id: Option<i64>,
+  pub name_pattern: Option<String>,
+  pub min_size: Option<i64>,
+  pub max_size: Option<i64>,
+  pub target_dir_id: String,
+  pub tags: Vec<String>
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct ImportRuleInput {
+  pub name: String,
+  #[serde(default = "default_enabled")]
+  pub enabled: bool,
+  #[serde(default)]
+  pub extensions: Vec<String>,
+  pub sender_id: Option<i64>,
+  pub name_pattern: Option<String>,
+  pub min_size: Option<i64>,
+  pub max_size: Option<i64>,
+  pub target_dir_id: String,
+  #[serde(default)]
+  pub tags: Vec<String>
+}
+
+fn default_enabled() -> bool {
+  true
+}
+
+/// Правило с заранее собранным регулярным выражением — для разбора пачки сообщений.
+pub struct CompiledRule {
+  pub rule: ImportRule,
+  pattern: Option<Regex>
+}
+
+impl CompiledRule {
+  pub fn matches(&self, msg: &HistoryMessage) -> bool {
+    let rule = &self.rule;
+    let name = msg.file_name.as_deref().unwrap_or("");
+    if !rule.extensions.is_empty() {
+      let ext = file_extension(name);
+      if !rule.extensions.iter().any(|e| Some(e.as_str()) == ext.as_deref()) {
+        return false;
+      }
+    }
+    if let Some(sender) = rule.sender_id {
+      if msg.sender_id != Some(sender) {
+        return false;
+      }
+    }
+    if let Some(re) = self.pattern.as_ref() {
+      if !re.is_match(name) {
+        return false;
+      }
+    }
+    let size = msg.file_size.unwrap_or(0);
+    if rule.min_size.map(|min| size < min).unwrap_or(false) {
+      return false;
+    }
+    if rule.max_size.map(|max| size > max).unwrap_or(false) {
+      return false;
+    }
+    true
+  }
+}
+
+pub fn first_match<'a>(rules: &'a [CompiledRule], msg: &HistoryMessage) -> Option<&'a ImportRule> {
+  rules.iter().find(|r| r.matches(msg)).map(|r| &r.rule)
+}
+
+pub async fn list_rules(pool: &SqlitePool) -> anyhow::Result<Vec<ImportRule>> {
+  let rows = sqlx::query(
+    "SELECT id, name, priority, enabled, extensions, sender_id, name_pattern, min_size, max_size, target_dir_id, tags
+     FROM import_rules ORDER BY priority, created_at"
+  )
+    .fetch_all(pool)
+    .await?;
+  Ok(rows.into_iter().map(|r| ImportRule {
+    id: r.get("id"),
+    name: r.get("name"),
+    priority: r.get("priority"),
+    enabled: r.get::<i64,_>("enabled") != 0,
+    extensions: split_list(&r.get::<String,_>("extensions")),
+    sender_id: r.try_get("sender_id").ok(),
+    name_pattern: r.try_get("name_pattern").ok(),
+    min_size: r.try_get("min_size").ok(),
+    max_size: r.try_get("max_size").ok(),
+    target_dir_id: r.get("target_dir_id"),
+    tags: split_list(&r.get::<String,_>("tags"))
+  }).collect())
+}
+
+/// Включенные правила в порядке приоритета. Правило с испорченным выражением
+/// пропускается с предупреждением, чтобы не остановить весь импорт.
+pub async fn load_compiled(pool: &SqlitePool) -> anyhow::Result<Vec<CompiledRule>> {
+  let mut out = Vec::new();
+  for rule in list_rules(pool).await? {
+    if !rule.enabled {
+      continue;
+    }
+    let pattern = match rule.name_pattern.as_deref() {
+      Some(p) => match compile_pattern(p) {
+        Ok(re) => Some(re),
+        Err(e) => {
+          tracing::warn!(event = "import_rule_invalid_pattern", rule_id = rule.id.as_str(), error = %e, "Правило импорта пропущено: некорректное выражение");
+          continue;
+        }
+      },
+      None => None
+    };
+    out.push(CompiledRule { rule, pattern });
+  }
+  Ok(out)
+}
+
+pub async fn create_rule(pool: &SqlitePool, input: ImportRuleInput) -> anyhow::Result<ImportRule> {
+  let input = validate(pool, input).await?;
+  let id = Ulid::new().to_string();
+  let priority: i64 = sqlx::query("SELECT COALESCE(MAX(priority), -1) + 1 AS next FROM import_rules")
+    .fetch_one(pool)
+    .await?
+    .get("next");
+  sqlx::query(
+    "INSERT INTO import_rules(id, name, priority, enabled, extensions, sender_id, name_pattern, min_size, max_size, target_dir_id, tags, created_at)
+     VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
+  )
+    .bind(&id)
+    .bind(&input.name)
+    .bind(priority)
+    .bind(if input.enabled { 1 } else { 0 })
+    .bind(input.extensions.join(","))
+    .bind(input.sender_id)
+    .bind(&input.name_pattern)
+    .bind(input.min_size)
+    .bind(input.max_size)
+    .bind(&input.target_dir_id)
+    .bind(input.tags.join(","))
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await?;
+  fetch_rule(pool, &id).await
+}
+
+pub async fn update_rule(pool: &SqlitePool, id: &str, input: ImportRuleInput) -> anyhow::Result<ImportRule> {
+  let input = validate(pool, input).await?;
+  let res = sqlx::query(
+    "UPDATE import_rules SET name = ?, enabled = ?, extensions = ?, sender_id = ?, name_pattern = ?,
+       min_size = ?, max_size = ?, target_dir_id = ?, tags = ?
+     WHERE id = ?"
+  )
+    .bind(&input.name)
+    .bind(if input.enabled { 1 } else { 0 })
+    .bind(input.extensions.join(","))
+    .bind(input.sender_id)
+    .bind(&input.name_pattern)
+    .bind(input.min_size)
+    .bind(input.max_size)
+    .bind(&input.target_dir_id)
+    .bind(input.tags.join(","))
+    .bind(id)
+    .execute(pool)
+    .await?;
+  if res.rows_affected() == 0 {
+    return Err(anyhow::anyhow!("Правило не найдено"));
+  }
+  fetch_rule(pool, id).await
+}
+
+pub async fn delete_rule(pool: &SqlitePool, id: &str) -> anyhow::Result<()> {
+  sqlx::query("DELETE FROM import_rules WHERE id = ?")
+    .bind(id)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+/// Переставляет правила в порядке `ids`; не упомянутые уходят в конец, сохраняя порядок.
+pub async fn reorder_rules(pool: &SqlitePool, ids: &[String]) -> anyhow::Result<Vec<ImportRule>> {
+  let current = list_rules(pool).await?;
+  let mut ordered: Vec<String> = ids
+    .iter()
+    .filter(|id| current.iter().any(|r| &r.id == *id))
+    .cloned()
+    .collect();
+  for rule in &current {
+    if !ordered.contains(&rule.id) {
+      ordered.push(rule.id.clone());
+    }
+  }
+
+  for (priority, id) in ordered.iter().enumerate() {
+    sqlx::query("UPDATE import_rules SET priority = ? WHERE id = ?")
+      .bind(priority as i64)
+      .bind(id)
+      .execute(pool)
+      .await?;
+  }
+  list_rules(pool).await
+}
+
+async fn fetch_rule(pool: &SqlitePool, id: &str) -> anyhow::Result<ImportRule> {
+  list_rules(pool)
+    .await?
+    .into_iter()
+    .find(|r| r.id == id)
+    .ok_or_else(|| anyhow::anyhow!("Правило не найдено"))
+}
+
+async fn validate(pool: &SqlitePool, mut input: ImportRuleInput) -> anyhow::Result<ImportRuleInput> {
+  input.name = input.name.trim().to_string();
+  if input.name.is_empty() {
+    return Err(anyhow::anyhow!("Название правила не может быть пустым"));
+  }
+  if !dir_exists(pool, &input.target_dir_id).await? {
+    return Err(anyhow::anyhow!("Папка не найдена"));
+  }
+  input.extensions = normalize_list(&input.extensions, |e| e.trim_start_matches('.').to_lowercase());
+  input.tags = normalize_list(&input.tags, |t| t.trim_start_matches('#').to_string());
+  input.name_pattern = input.name_pattern.map(|p| p.trim().to_string()).filter(|p| !p.is_empty());
+  if let Some(p) = input.name_pattern.as_deref() {
+    compile_pattern(p).map_err(|e| anyhow::anyhow!("Некорректное выражение для имени: {e}"))?;
+  }
+  if let (Some(min), Some(max)) = (input.min_size, input.max_size) {
+    if min > max {
+      return Err(anyhow::anyhow!("Минимальный размер больше максимального"));
+    }
+  }
+  Ok(input)
+}
+
+fn compile_pattern(pattern: &str) -> Result<Regex, regex::Error> {
+  RegexBuilder::new(pattern).case_insensitive(true).size_limit(1 << 20).build()
+}
+
+fn normalize_list(values: &[String], map: impl Fn(&str) -> String) -> Vec<String> {
+  let mut out: Vec<String> = Vec::new();
+  for v in values {
+    let v = map(v.trim()).replace(',', "");
+    if !v.is_empty() && !out.contains(&v) {
+      out.push(v);
+    }
+  }
+  out
+}
+
+fn split_list(raw: &str) -> Vec<String> {
+  raw.split(',').map(str::trim).filter(|v| !v.is_empty()).map(str::to_string).collect()
+}
+
+fn file_extension(name: &str) -> Option<String> {
+  std::path::Path::new(name)
+    .extension()
+    .and_then(|e| e.to_str())
+    .map(|e| e.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn rule(id: &str, extensions: &[&str], pattern: Option<&str>, min_size: Option<i64>) -> CompiledRule {
+    CompiledRule {
+      rule: ImportRule {
+        id: id.into(),
+        name: id.into(),
+        priority: 0,
+        enabled: true,
+        extensions: extensions.iter().map(|e| e.to_string()).collect(),
+        sender_id: None,
+        name_pattern: pattern.map(str::to_string),
+        min_size,
+        max_size: None,
+        target_dir_id: "d".into(),
+        tags: Vec::new()
+      },
+      pattern: pattern.map(|p| compile_pattern(p).unwrap())
+    }
+  }
+
+  fn msg(name: &str, size: i64) -> HistoryMessage {
+    HistoryMessage {
+      id: 1,
+      date: 0,
+      text: None,
+      caption: None,
+      file_size: Some(size),
+      file_name: Some(name.into()),
+      sender_id: Some(42),
+      media_group_id: None,
+      content_type: Some("messageDocument".to_string()),
+      sticker_set_id: None,
+      file_unique_id: None
+    }
+  }
+
+  #[test]
+  fn first_matching_rule_wins() {
+    let rules = vec![
+      rule("big-video", &["mp4"], None, Some(1000)),
+      rule("scans", &[], Some(r"^scan_\d+"), None),
+      rule("video", &["mp4", "mkv"], None, None)
+    ];
+    assert_eq!(first_match(&rules, &msg("clip.MP4", 10)).map(|r| r.id.as_str()), Some("video"));
+    assert_eq!(first_match(&rules, &msg("clip.mp4", 5000)).map(|r| r.id.as_str()), Some("big-video"));
+    assert_eq!(first_match(&rules, &msg("SCAN_001.pdf", 1)).map(|r| r.id.as_str()), Some("scans"));
+    assert!(first_match(&rules, &msg("notes.txt", 1)).is_none());
+  }
+}
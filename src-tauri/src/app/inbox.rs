@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Local};
+use sqlx_sqlite::SqlitePool;
+
+use crate::paths::{Paths, Scratch};
+use crate::telegram::{ChatId, TelegramService};
+
+use super::files;
+use super::system_dirs::{self, SystemDir};
+
+/// Что отправляется во «Входящие»: текст заметки или готовый файл.
+#[derive(Debug, Clone)]
+pub enum Capture {
+  Text(String),
+  File(PathBuf)
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Captured {
+  pub file_id: String,
+  pub dir_id: String,
+  pub dir_name: String
+}
+
+/// Хештег даты захвата: `captured_2024_05_31` (решетку добавит подпись).
+fn capture_tag(at: DateTime<Local>) -> String {
+  at.format("captured_%Y_%m_%d").to_string()
+}
+
+/// Имя файла для текстовой заметки; двоеточия не используются, чтобы имя
+/// было допустимым на всех системах.
+fn note_file_name(at: DateTime<Local>) -> String {
+  at.format("Заметка %Y-%m-%d %H-%M-%S.txt").to_string()
+}
+
+/// Сохраняет заметку или файл во «Входящие» одним вызовом. Папка создается
+/// при первом захвате, запись помечается тегом даты захвата.
+pub async fn capture(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  paths: &Paths,
+  storage_chat_id: ChatId,
+  capture: Capture
+) -> anyhow::Result<Captured> {
+  let now = Local::now();
+  let tags = [capture_tag(now)];
+  let (dir_id, dir_name) = system_dirs::ensure(pool, tg, storage_chat_id, SystemDir::Inbox).await?;
+
+  let file_id = match capture {
+    Capture::File(path) => files::upload_file_tagged(pool, tg, storage_chat_id, &dir_id, &path, &tags).await?,
+    Capture::Text(text) => {
+      let text = text.trim();
+      if text.is_empty() {
+        return Err(anyhow::anyhow!("Заметка пустая"));
+      }
+      let tmp_dir = paths.layout().scratch_dir(Scratch::Inbox);
+      std::fs::create_dir_all(&tmp_dir)?;
+      let tmp_path = tmp_dir.join(note_file_name(now));
+      std::fs::write(&tmp_path, format!("{text}\n"))?;
+      let result = files::upload_file_tagged(pool, tg, storage_chat_id, &dir_id, &tmp_path, &tags).await;
+      let _ = std::fs::remove_file(&tmp_path);
+      result?
+    }
+  };
+
+  tracing::info!(event = "inbox_captured", file_id = file_id.as_str(), dir_id = dir_id.as_str(), "Запись сохранена во Входящие");
+  Ok(Captured { file_id, dir_id, dir_name })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::TimeZone;
+
+  #[test]
+  fn tag_and_note_name_use_capture_date() {
+    let at = Local.with_ymd_and_hms(2024, 5, 31, 9, 7, 3).unwrap();
+    assert_eq!(capture_tag(at), "captured_2024_05_31");
+    assert_eq!(note_file_name(at), "Заметка 2024-05-31 09-07-03.txt");
+  }
+}
@@ -1,21 +1,27 @@
+use std::collections::{HashMap, HashSet};
+
 use chrono::Utc;
-use crate::sqlx::{self, Row};
+use crate::sqlx::{self, QueryBuilder, Row};
 use sqlx_sqlite::SqlitePool;
 use ulid::Ulid;
 use tokio::time::{sleep, Duration};
 
-use crate::fsmeta::{FileMeta, parse_dir_message, parse_file_caption, make_file_caption};
-use crate::telegram::{TelegramService, ChatId, HistoryMessage};
-
-use super::dirs;
+use crate::fsmeta::{DirMeta, FileMeta, PartMeta, encryption_key_id, is_caption_form_of, parse_dir_message, parse_file_caption, parse_link_message, parse_part_caption, make_file_caption};
+use crate::settings;
+use crate::telegram::{content_kind, TelegramService, ChatId, HistoryMessage};
 
-pub const UNASSIGNED_DIR_NAME: &str = "Неразобранное";
+use super::{collections, dirs, files, fulltext, import_rules, links, pending_uploads, system_dirs, vault};
+use super::system_dirs::SystemDir;
 
 #[derive(Default, Debug, Clone)]
 pub struct IndexOutcome {
   pub dir: bool,
   pub file: bool,
+  pub link: bool,
   pub imported: bool,
+  /// Пересланная копия уже известного файла: записана к нему, а не импортирована.
+  pub linked: bool,
+  pub file_id: Option<String>,
   pub skipped: bool,
   pub failed: bool
 }
@@ -35,12 +41,31 @@ pub async fn index_storage_message(
       out.dir = true;
       return Ok(out);
     }
+    if let Ok(meta) = parse_link_message(text) {
+      ensure_dir_placeholder(pool, &meta.dir_id, msg.date).await?;
+      links::upsert_link(pool, &meta, msg.id, msg.date).await?;
+      out.link = true;
+      return Ok(out);
+    }
   }
 
   if let Some(caption) = msg.caption.as_deref() {
     if let Ok(meta) = parse_file_caption(caption) {
-      upsert_file(pool, &meta, storage_chat_id, msg.id, msg.date, msg.file_size.unwrap_or(0)).await?;
+      if !previous_versions(pool, storage_chat_id, &[msg.id]).await?.is_empty() {
+        out.skipped = true;
+        return Ok(out);
+      }
+      upsert_file(pool, &meta, storage_chat_id, msg).await?;
+      remember_sticker_pack(pool, tg, msg).await;
+      out.file = true;
+      out.file_id = Some(meta.file_id.clone());
+      return Ok(out);
+    }
+    // Часть большого файла нельзя импортировать как отдельный файл без подписи.
+    if let Ok(part) = parse_part_caption(caption) {
+      upsert_part(pool, &part, storage_chat_id, msg).await?;
       out.file = true;
+      out.file_id = Some(part.file.file_id.clone());
       return Ok(out);
     }
   }
@@ -52,10 +77,14 @@ pub async fn index_storage_message(
     return Ok(out);
   }
 
-  match import_untagged_file(pool, tg, storage_chat_id, msg, unassigned_cache).await? {
-    ImportAction::Imported => {
+  match import_untagged_file(pool, tg, storage_chat_id, msg, None, unassigned_cache).await? {
+    ImportAction::Imported(file_id) => {
       out.imported = true;
       out.file = true;
+      out.file_id = Some(file_id);
+    }
+    ImportAction::Linked(_) => {
+      out.linked = true;
     }
     ImportAction::Skipped => {
       out.skipped = true;
@@ -65,8 +94,197 @@ pub async fn index_storage_message(
   Ok(out)
 }
 
+/// Индексирует страницу истории канала хранения, результаты — в порядке
+/// сообщений. Папки и файлы с разметкой пишутся многострочными upsert'ами в
+/// одной транзакции; ссылки, части и сообщения без разметки идут по одному
+/// через `index_storage_message`.
+pub async fn index_storage_batch(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  storage_chat_id: ChatId,
+  msgs: &[HistoryMessage],
+  unassigned_cache: &mut Option<(String, String)>
+) -> anyhow::Result<Vec<IndexOutcome>> {
+  let mut outcomes = vec![IndexOutcome::default(); msgs.len()];
+  let mut dirs: Vec<(&HistoryMessage, DirMeta)> = Vec::new();
+  let mut files: Vec<(&HistoryMessage, FileMeta)> = Vec::new();
+  let mut rest: Vec<usize> = Vec::new();
+  for (i, msg) in msgs.iter().enumerate() {
+    let text = msg.text.as_deref();
+    if let Some(meta) = text.and_then(|t| parse_dir_message(t).ok()) {
+      outcomes[i].dir = true;
+      dirs.push((msg, meta));
+    } else if text.is_some_and(|t| parse_link_message(t).is_ok()) {
+      rest.push(i);
+    } else if let Some(meta) = msg.caption.as_deref().and_then(|c| parse_file_caption(c).ok()) {
+      outcomes[i].file = true;
+      outcomes[i].file_id = Some(meta.file_id.clone());
+      files.push((msg, meta));
+    } else {
+      rest.push(i);
+    }
+  }
+  let ids: Vec<i64> = files.iter().map(|(msg, _)| msg.id).collect();
+  let versions = previous_versions(pool, storage_chat_id, &ids).await?;
+  if !versions.is_empty() {
+    files.retain(|(msg, _)| !versions.contains(&msg.id));
+    for (i, msg) in msgs.iter().enumerate() {
+      if versions.contains(&msg.id) {
+        outcomes[i] = IndexOutcome { skipped: true, ..IndexOutcome::default() };
+      }
+    }
+  }
+
+  upsert_tagged(pool, storage_chat_id, &dirs, &files).await?;
+  for (msg, _) in &files {
+    remember_sticker_pack(pool, tg, msg).await;
+  }
+  for i in rest {
+    outcomes[i] = index_storage_message(pool, tg, storage_chat_id, &msgs[i], unassigned_cache).await?;
+  }
+  Ok(outcomes)
+}
+
+/// Сообщения, которые записаны прежними версиями файлов. Если при замене не
+/// удалось снять fsmeta со старого сообщения, его подпись все еще указывает
+/// на файл, и без этой проверки синхронизация вернула бы запись на него.
+async fn previous_versions(pool: &SqlitePool, chat_id: ChatId, msg_ids: &[i64]) -> anyhow::Result<HashSet<i64>> {
+  if msg_ids.is_empty() {
+    return Ok(HashSet::new());
+  }
+  let mut builder = QueryBuilder::new("SELECT tg_msg_id FROM file_versions WHERE tg_chat_id = ");
+  builder.push_bind(chat_id).push(" AND tg_msg_id IN (");
+  let mut separated = builder.separated(", ");
+  for id in msg_ids {
+    separated.push_bind(*id);
+  }
+  separated.push_unseparated(")");
+  Ok(builder.build().fetch_all(pool).await?.into_iter().map(|r| r.get::<i64,_>("tg_msg_id")).collect())
+}
+
+/// Пакетный `upsert_dir` + `upsert_file`: заглушки папок, папки и файлы —
+/// по одному многострочному запросу, все в одной транзакции.
+async fn upsert_tagged(
+  pool: &SqlitePool,
+  chat_id: ChatId,
+  dirs: &[(&HistoryMessage, DirMeta)],
+  files: &[(&HistoryMessage, FileMeta)]
+) -> anyhow::Result<()> {
+  if dirs.is_empty() && files.is_empty() {
+    return Ok(());
+  }
+  let dir_names = stored_names(pool, "directories", dirs.iter().map(|(_, m)| m.dir_id.as_str())).await?;
+  let file_names = stored_names(pool, "files", files.iter().map(|(_, m)| m.file_id.as_str())).await?;
+  let parent_of = |meta: &DirMeta| -> Option<String> {
+    if meta.parent_id == "ROOT" || meta.parent_id.trim().is_empty() { None } else { Some(meta.parent_id.clone()) }
+  };
+  let placeholders: Vec<(String, i64)> = dirs
+    .iter()
+    .filter_map(|(msg, meta)| parent_of(meta).map(|pid| (pid, msg.date)))
+    .chain(files.iter().map(|(msg, meta)| (meta.dir_id.clone(), msg.date)))
+    .filter(|(id, _)| !id.trim().is_empty())
+    .collect();
+
+  let mut tx = pool.begin().await?;
+  if !placeholders.is_empty() {
+    let placeholder = system_dirs::name(SystemDir::Unknown);
+    let mut builder = QueryBuilder::new("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) ");
+    builder.push_values(&placeholders, |mut b, (id, date)| {
+      b.push_bind(id).push("NULL").push_bind(&placeholder).push("NULL").push_bind(*date);
+    });
+    builder.push(" ON CONFLICT(id) DO NOTHING");
+    let inserted = builder.build().execute(&mut *tx).await?.rows_affected();
+    if inserted > 0 {
+      tracing::debug!(event = "storage_sync_dir_placeholder", count = inserted, "Добавлены заглушки директорий");
+    }
+  }
+  if !dirs.is_empty() {
+    let mut builder = QueryBuilder::new(DIR_UPSERT);
+    builder.push_values(dirs, |mut b, (msg, meta)| {
+      b.push_bind(meta.dir_id.clone())
+        .push_bind(parent_of(meta))
+        .push_bind(pick_name(&meta.name, dir_names.get(&meta.dir_id).cloned()))
+        .push_bind(msg.id)
+        .push_bind(msg.date)
+        .push("0");
+    });
+    builder.push(DIR_UPSERT_CONFLICT);
+    builder.build().execute(&mut *tx).await?;
+  }
+  if !files.is_empty() {
+    // Прежние записи сообщений, чья подпись теперь называет другой id.
+    let mut builder = QueryBuilder::new("DELETE FROM files WHERE tg_chat_id = ");
+    builder.push_bind(chat_id).push(" AND (");
+    let mut separated = builder.separated(" OR ");
+    for (msg, meta) in files {
+      separated
+        .push("(tg_msg_id = ")
+        .push_bind_unseparated(msg.id)
+        .push_unseparated(" AND id <> ")
+        .push_bind_unseparated(meta.file_id.as_str())
+        .push_unseparated(")");
+    }
+    separated.push_unseparated(")");
+    builder.build().execute(&mut *tx).await?;
+
+    let mut builder = QueryBuilder::new(FILE_UPSERT);
+    builder.push_values(files, |mut b, (msg, meta)| {
+      let (size, enc_key_id) = stored_size(msg);
+      b.push_bind(meta.file_id.clone())
+        .push_bind(meta.dir_id.clone())
+        .push_bind(pick_name(&meta.name, file_names.get(&meta.file_id).cloned()))
+        .push_bind(size)
+        .push_bind(meta.hash_short.clone())
+        .push_bind(chat_id)
+        .push_bind(msg.id)
+        .push_bind(msg.date)
+        .push("0")
+        .push_bind(msg.media_group_id.clone())
+        .push_bind(media_kind(msg))
+        .push_bind(msg.sticker_set_id.clone())
+        .push_bind(msg.file_unique_id.clone())
+        .push_bind(enc_key_id);
+    });
+    builder.push(FILE_UPSERT_CONFLICT);
+    builder.build().execute(&mut *tx).await?;
+
+    let captions: Vec<(&str, &str)> = files
+      .iter()
+      .filter_map(|(msg, meta)| msg.caption.as_deref().map(|c| (meta.file_id.as_str(), c)))
+      .collect();
+    fulltext::remember_captions(&mut tx, &captions).await?;
+    // Файлы могли прийти уже после таймаута отправки.
+    let mut builder = QueryBuilder::new("DELETE FROM pending_uploads WHERE file_id IN (");
+    let mut separated = builder.separated(", ");
+    for (_, meta) in files {
+      separated.push_bind(meta.file_id.as_str());
+    }
+    separated.push_unseparated(")");
+    builder.build().execute(&mut *tx).await?;
+  }
+  tx.commit().await?;
+  Ok(())
+}
+
+/// Импортирует сообщение без fsmeta в указанную папку (или по тегам / в «Неразобранное»,
+/// если папка не задана). Возвращает id файла, если импорт состоялся.
+pub async fn import_untagged_message(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  storage_chat_id: ChatId,
+  msg: &HistoryMessage,
+  target_dir: Option<(String, String)>,
+  unassigned_cache: &mut Option<(String, String)>
+) -> anyhow::Result<Option<String>> {
+  match import_untagged_file(pool, tg, storage_chat_id, msg, target_dir, unassigned_cache).await? {
+    ImportAction::Imported(file_id) => Ok(Some(file_id)),
+    ImportAction::Linked(_) | ImportAction::Skipped => Ok(None)
+  }
+}
+
 enum ImportAction {
-  Imported,
+  Imported(String),
+  Linked(String),
   Skipped
 }
 
@@ -75,6 +293,7 @@ async fn import_untagged_file(
   tg: &dyn TelegramService,
   storage_chat_id: ChatId,
   msg: &HistoryMessage,
+  forced_target: Option<(String, String)>,
   unassigned_cache: &mut Option<(String, String)>
 ) -> anyhow::Result<ImportAction> {
   if let Some(row) = sqlx::query("SELECT id FROM files WHERE tg_chat_id = ? AND tg_msg_id = ?")
@@ -85,11 +304,20 @@ async fn import_untagged_file(
     let _existing: String = row.get("id");
     return Ok(ImportAction::Skipped);
   }
+  // Прежние версии переотправленных файлов остаются в канале без fsmeta.
+  if !previous_versions(pool, storage_chat_id, &[msg.id]).await?.is_empty() {
+    return Ok(ImportAction::Skipped);
+  }
+  if let Some(existing) = link_forwarded_copy(pool, storage_chat_id, msg).await? {
+    return Ok(ImportAction::Linked(existing));
+  }
 
+  let read_only = settings::get_read_only_channel(pool).await?;
   let caption_text = msg.caption.clone().unwrap_or_default();
   let mut preferred: Option<String> = None;
   let mut target: Option<(String, String)> = None;
-  for tag in extract_folder_tags(&caption_text) {
+  let tags = if forced_target.is_some() { Vec::new() } else { extract_folder_tags(&caption_text) };
+  for tag in tags {
     let Some(name) = normalize_tag_name(&tag) else { continue; };
     if preferred.is_none() {
       preferred = Some(name.clone());
@@ -100,13 +328,23 @@ async fn import_untagged_file(
     }
   }
 
-  let target = if let Some(found) = target {
+  let mut auto_tags: Vec<String> = Vec::new();
+  let target = if let Some(forced) = forced_target {
+    forced
+  } else if let Some(found) = target {
     found
   } else if let Some(name) = preferred {
-    ensure_dir_by_name(pool, tg, storage_chat_id, &name).await?
+    ensure_import_dir(pool, tg, storage_chat_id, &name, read_only).await?
+  } else if let Some(sibling) = album_sibling_dir(pool, storage_chat_id, msg).await? {
+    sibling
+  } else if let Some((rule_target, tags)) = match_import_rule(pool, msg).await? {
+    auto_tags = tags;
+    rule_target
+  } else if collections::is_collection_kind(media_kind(msg).as_deref()) {
+    ensure_system_dir(pool, tg, storage_chat_id, SystemDir::Collections, read_only).await?
   } else {
     if unassigned_cache.is_none() {
-      *unassigned_cache = Some(ensure_dir_by_name(pool, tg, storage_chat_id, UNASSIGNED_DIR_NAME).await?);
+      *unassigned_cache = Some(ensure_system_dir(pool, tg, storage_chat_id, SystemDir::Unassigned, read_only).await?);
     }
     unassigned_cache.clone().unwrap()
   };
@@ -115,7 +353,7 @@ async fn import_untagged_file(
   let file_name = msg.file_name.clone().filter(|v| !v.trim().is_empty())
     .unwrap_or_else(|| format!("файл_{}", msg.id));
   let size = msg.file_size.unwrap_or(0);
-  let hash_short = hash_short_from_seed(&format!("{storage_chat_id}:{msg_id}:{file_name}:{size}", msg_id = msg.id));
+  let hash_short = import_hash(storage_chat_id, msg.id, &file_name, size);
   let caption = make_file_caption_with_tag(
     &FileMeta {
       dir_id: target.0.clone(),
@@ -125,8 +363,13 @@ async fn import_untagged_file(
     },
     Some(target.1.as_str())
   );
+  let caption = append_auto_tags(caption, &auto_tags);
 
-  if let Err(e) = edit_caption_with_retry(tg, storage_chat_id, msg.id, &caption).await {
+  // В канале только для чтения подпись не трогаем: папка, имя и хэш живут
+  // только в базе, а при пересинхронизации файл узнается по координатам.
+  if read_only {
+    tracing::debug!(event = "storage_import_read_only", message_id = msg.id, "Канал только для чтения, подпись не меняю");
+  } else if let Err(e) = edit_caption_with_retry(tg, storage_chat_id, msg.id, &caption).await {
     tracing::warn!(
       event = "storage_import_edit_failed",
       message_id = msg.id,
@@ -146,8 +389,8 @@ async fn import_untagged_file(
 
   let created_at = if msg.date > 0 { msg.date } else { Utc::now().timestamp() };
   let inserted = sqlx::query(
-    "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, is_broken)
-     VALUES(?, ?, ?, ?, ?, ?, ?, ?, 0)"
+    "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, is_broken, media_group_id, media_kind, pack_id, remote_unique_id)
+     VALUES(?, ?, ?, ?, ?, ?, ?, ?, 0, ?, ?, ?, ?)"
   )
     .bind(&file_id)
     .bind(&target.0)
@@ -157,11 +400,26 @@ async fn import_untagged_file(
     .bind(storage_chat_id)
     .bind(msg.id)
     .bind(created_at)
+    .bind(msg.media_group_id.as_deref())
+    .bind(media_kind(msg).as_deref())
+    .bind(msg.sticker_set_id.as_deref())
+    .bind(msg.file_unique_id.as_deref())
     .execute(pool)
     .await;
 
   match inserted {
-    Ok(_) => Ok(ImportAction::Imported),
+    Ok(_) => {
+      // Подпись уже переписана разметкой, в поиск идет исходный текст.
+      fulltext::remember_caption(pool, &file_id, &caption_text).await?;
+      remember_sticker_pack(pool, tg, msg).await;
+      if let Some(group) = msg.media_group_id.as_deref() {
+        let unassigned_id = system_dirs::resolve_id(pool, SystemDir::Unassigned).await?;
+        if unassigned_id.as_deref() != Some(target.0.as_str()) {
+          gather_album(pool, tg, storage_chat_id, group, &target, read_only).await?;
+        }
+      }
+      Ok(ImportAction::Imported(file_id))
+    }
     Err(e) => {
       tracing::warn!(
         event = "storage_import_db_failed",
@@ -174,6 +432,110 @@ async fn import_untagged_file(
   }
 }
 
+/// Участники альбома держатся вместе: подпись с тегами обычно только у первого,
+/// остальные идут в ту же папку.
+async fn album_sibling_dir(
+  pool: &SqlitePool,
+  storage_chat_id: ChatId,
+  msg: &HistoryMessage
+) -> anyhow::Result<Option<(String, String)>> {
+  let Some(group) = msg.media_group_id.as_deref() else {
+    return Ok(None);
+  };
+  let unassigned_id = system_dirs::resolve_id(pool, SystemDir::Unassigned).await?.unwrap_or_default();
+  let row = sqlx::query(
+    "SELECT d.id, d.name FROM files f JOIN directories d ON d.id = f.dir_id
+     WHERE f.tg_chat_id = ? AND f.media_group_id = ?
+     ORDER BY (d.id = ?) ASC, f.tg_msg_id ASC
+     LIMIT 1"
+  )
+    .bind(storage_chat_id)
+    .bind(group)
+    .bind(unassigned_id)
+    .fetch_optional(pool)
+    .await?;
+  Ok(row.map(|r| (r.get::<String,_>("id"), r.get::<String,_>("name"))))
+}
+
+/// История читается от новых к старым, поэтому участники альбома без подписи
+/// могут попасть в «Неразобранное» раньше, чем найдется подпись с тегом.
+/// Когда она нашлась, подтягиваем их в ту же папку.
+async fn gather_album(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  storage_chat_id: ChatId,
+  group: &str,
+  target: &(String, String),
+  read_only: bool
+) -> anyhow::Result<()> {
+  let Some(unassigned_id) = system_dirs::resolve_id(pool, SystemDir::Unassigned).await? else {
+    return Ok(());
+  };
+  let rows = sqlx::query(
+    "SELECT f.id, f.name, f.hash, f.tg_msg_id FROM files f
+     WHERE f.tg_chat_id = ? AND f.media_group_id = ? AND f.dir_id = ? AND f.dir_id != ?"
+  )
+    .bind(storage_chat_id)
+    .bind(group)
+    .bind(unassigned_id)
+    .bind(&target.0)
+    .fetch_all(pool)
+    .await?;
+  for row in rows {
+    let meta = FileMeta {
+      dir_id: target.0.clone(),
+      file_id: row.get("id"),
+      name: row.get("name"),
+      hash_short: row.get("hash")
+    };
+    let msg_id: i64 = row.get("tg_msg_id");
+    let caption = make_file_caption_with_tag(&meta, Some(target.1.as_str()));
+    if !read_only {
+      if let Err(e) = edit_caption_with_retry(tg, storage_chat_id, msg_id, &caption).await {
+        tracing::warn!(event = "storage_album_regroup_edit_failed", message_id = msg_id, error = %e, "Не удалось обновить подпись участника альбома");
+        continue;
+      }
+    }
+    sqlx::query("UPDATE files SET dir_id = ? WHERE id = ?")
+      .bind(&target.0)
+      .bind(&meta.file_id)
+      .execute(pool)
+      .await?;
+  }
+  Ok(())
+}
+
+/// Папка и авто-теги первого подходящего пользовательского правила.
+async fn match_import_rule(
+  pool: &SqlitePool,
+  msg: &HistoryMessage
+) -> anyhow::Result<Option<((String, String), Vec<String>)>> {
+  let rules = import_rules::load_compiled(pool).await?;
+  let Some(rule) = import_rules::first_match(&rules, msg) else {
+    return Ok(None);
+  };
+  let row = sqlx::query("SELECT name FROM directories WHERE id = ?")
+    .bind(&rule.target_dir_id)
+    .fetch_optional(pool)
+    .await?;
+  let Some(row) = row else {
+    return Ok(None);
+  };
+  tracing::debug!(event = "storage_import_rule_matched", rule_id = rule.id.as_str(), message_id = msg.id, "Сработало правило импорта");
+  Ok(Some(((rule.target_dir_id.clone(), row.get::<String,_>("name")), rule.tags.clone())))
+}
+
+fn append_auto_tags(caption: String, tags: &[String]) -> String {
+  let mut out = caption;
+  for tag in tags.iter().filter_map(|t| folder_hashtag(t)) {
+    if !out.split_whitespace().any(|w| w == tag) {
+      out.push(' ');
+      out.push_str(&tag);
+    }
+  }
+  out
+}
+
 async fn edit_caption_with_retry(
   tg: &dyn TelegramService,
   chat_id: ChatId,
@@ -218,7 +580,74 @@ async fn message_exists_with_retry(
   false
 }
 
-pub async fn upsert_dir(pool: &SqlitePool, meta: &crate::fsmeta::DirMeta, msg_id: i64, date: i64) -> anyhow::Result<()> {
+/// Подпись несет упрощенное имя (см. `fsmeta::caption_name`). Если в базе
+/// уже лежит исходное имя, из которого оно получено, остается исходное.
+async fn true_name(pool: &SqlitePool, query: &str, id: &str, caption_name: &str) -> anyhow::Result<String> {
+  let existing = sqlx::query(query)
+    .bind(id)
+    .fetch_optional(pool)
+    .await?
+    .map(|row| row.get::<String,_>("name"));
+  Ok(pick_name(caption_name, existing))
+}
+
+fn pick_name(caption_name: &str, existing: Option<String>) -> String {
+  match existing {
+    Some(name) if is_caption_form_of(caption_name, &name) => name,
+    _ => caption_name.to_string()
+  }
+}
+
+/// Имена уже известных записей `table` одним запросом, для `pick_name`.
+async fn stored_names<'a>(
+  pool: &SqlitePool,
+  table: &str,
+  ids: impl Iterator<Item = &'a str>
+) -> anyhow::Result<HashMap<String, String>> {
+  let mut builder = QueryBuilder::new(format!("SELECT id, name FROM {table} WHERE id IN ("));
+  let mut separated = builder.separated(", ");
+  let mut any = false;
+  for id in ids {
+    separated.push_bind(id);
+    any = true;
+  }
+  separated.push_unseparated(")");
+  if !any {
+    return Ok(HashMap::new());
+  }
+  Ok(builder
+    .build()
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.get("id"), row.get("name")))
+    .collect())
+}
+
+/// Размер файла в базе и ключ шифрования из подписи: у зашифрованного файла
+/// хранится исходный размер, а не размер шифротекста.
+fn stored_size(msg: &HistoryMessage) -> (i64, Option<String>) {
+  let enc_key_id = msg.caption.as_deref().and_then(encryption_key_id);
+  let size = msg.file_size.unwrap_or(0);
+  let size = if enc_key_id.is_some() { vault::plain_size(size) } else { size };
+  (size, enc_key_id)
+}
+
+const DIR_UPSERT: &str = "INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at, is_broken) ";
+const DIR_UPSERT_CONFLICT: &str =
+  " ON CONFLICT(id) DO UPDATE SET parent_id=excluded.parent_id, name=excluded.name, tg_msg_id=excluded.tg_msg_id, updated_at=excluded.updated_at, is_broken=0, broken_reason=NULL, broken_since=NULL";
+const FILE_UPSERT: &str =
+  "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, is_broken, media_group_id, media_kind, pack_id, remote_unique_id, enc_key_id) ";
+// updateMessageContent приходит без media_album_id — не затираем уже известный альбом.
+const FILE_UPSERT_CONFLICT: &str =
+  " ON CONFLICT(id) DO UPDATE SET dir_id=excluded.dir_id, name=excluded.name, size=excluded.size, hash=excluded.hash, tg_chat_id=excluded.tg_chat_id, tg_msg_id=excluded.tg_msg_id, is_broken=0, broken_reason=NULL, broken_since=NULL,
+     media_group_id=COALESCE(excluded.media_group_id, files.media_group_id),
+     media_kind=COALESCE(excluded.media_kind, files.media_kind),
+     pack_id=COALESCE(excluded.pack_id, files.pack_id),
+     remote_unique_id=COALESCE(excluded.remote_unique_id, files.remote_unique_id),
+     enc_key_id=excluded.enc_key_id";
+
+pub async fn upsert_dir(pool: &SqlitePool, meta: &DirMeta, msg_id: i64, date: i64) -> anyhow::Result<()> {
   let parent_id = if meta.parent_id == "ROOT" || meta.parent_id.trim().is_empty() {
     None
   } else {
@@ -227,13 +656,11 @@ pub async fn upsert_dir(pool: &SqlitePool, meta: &crate::fsmeta::DirMeta, msg_id
   if let Some(pid) = parent_id {
     ensure_dir_placeholder(pool, pid, date).await?;
   }
-  sqlx::query(
-    "INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at, is_broken) VALUES(?, ?, ?, ?, ?, 0)
-     ON CONFLICT(id) DO UPDATE SET parent_id=excluded.parent_id, name=excluded.name, tg_msg_id=excluded.tg_msg_id, updated_at=excluded.updated_at, is_broken=0"
-  )
+  let name = true_name(pool, "SELECT name FROM directories WHERE id = ?", &meta.dir_id, &meta.name).await?;
+  sqlx::query(&format!("{DIR_UPSERT}VALUES(?, ?, ?, ?, ?, 0){DIR_UPSERT_CONFLICT}"))
     .bind(&meta.dir_id)
     .bind(parent_id)
-    .bind(&meta.name)
+    .bind(&name)
     .bind(msg_id)
     .bind(date)
     .execute(pool)
@@ -245,42 +672,167 @@ pub async fn upsert_file(
   pool: &SqlitePool,
   meta: &FileMeta,
   chat_id: i64,
-  msg_id: i64,
-  date: i64,
-  size: i64
+  msg: &HistoryMessage
 ) -> anyhow::Result<()> {
-  ensure_dir_placeholder(pool, &meta.dir_id, date).await?;
+  ensure_dir_placeholder(pool, &meta.dir_id, msg.date).await?;
+  let (size, enc_key_id) = stored_size(msg);
+  let name = true_name(pool, "SELECT name FROM files WHERE id = ?", &meta.file_id, &meta.name).await?;
+  // Сообщение принадлежит одному файлу: если подпись теперь называет другой
+  // id, прежняя запись этого сообщения устарела и мешала бы вставке.
+  sqlx::query("DELETE FROM files WHERE tg_chat_id = ? AND tg_msg_id = ? AND id <> ?")
+    .bind(chat_id)
+    .bind(msg.id)
+    .bind(&meta.file_id)
+    .execute(pool)
+    .await?;
+
+  sqlx::query(&format!("{FILE_UPSERT}VALUES(?, ?, ?, ?, ?, ?, ?, ?, 0, ?, ?, ?, ?, ?){FILE_UPSERT_CONFLICT}"))
+    .bind(&meta.file_id)
+    .bind(&meta.dir_id)
+    .bind(&name)
+    .bind(size)
+    .bind(&meta.hash_short)
+    .bind(chat_id)
+    .bind(msg.id)
+    .bind(msg.date)
+    .bind(msg.media_group_id.as_deref())
+    .bind(media_kind(msg).as_deref())
+    .bind(msg.sticker_set_id.as_deref())
+    .bind(msg.file_unique_id.as_deref())
+    .bind(enc_key_id.as_deref())
+    .execute(pool)
+    .await?;
+  if let Some(caption) = msg.caption.as_deref() {
+    fulltext::remember_caption(pool, &meta.file_id, caption).await?;
+  }
+  // Файл мог прийти уже после таймаута отправки.
+  pending_uploads::finish(pool, &meta.file_id).await?;
+  Ok(())
+}
+
+/// Если в сообщении лежит уже известный файл (переслали из канала хранения
+/// или из канала-источника), сообщение записывается как его копия и
+/// возвращается id файла. Так пересылка не плодит дубль в «Неразобранном».
+pub async fn link_forwarded_copy(pool: &SqlitePool, chat_id: ChatId, msg: &HistoryMessage) -> anyhow::Result<Option<String>> {
+  let Some(unique_id) = msg.file_unique_id.as_deref() else {
+    return Ok(None);
+  };
+  let existing = sqlx::query(
+    "SELECT id FROM files WHERE remote_unique_id = ? AND NOT (tg_chat_id = ? AND tg_msg_id = ?) ORDER BY created_at LIMIT 1"
+  )
+    .bind(unique_id)
+    .bind(chat_id)
+    .bind(msg.id)
+    .fetch_optional(pool)
+    .await?;
+  let Some(row) = existing else {
+    return Ok(None);
+  };
+  let file_id: String = row.get("id");
+  sqlx::query(
+    "INSERT INTO file_copies(tg_chat_id, tg_msg_id, file_id, created_at) VALUES(?, ?, ?, ?)
+     ON CONFLICT(tg_chat_id, tg_msg_id) DO UPDATE SET file_id=excluded.file_id"
+  )
+    .bind(chat_id)
+    .bind(msg.id)
+    .bind(&file_id)
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await?;
+  tracing::info!(
+    event = "storage_forwarded_copy_linked",
+    chat_id = chat_id,
+    message_id = msg.id,
+    file_id = file_id.as_str(),
+    "Пересланная копия привязана к существующему файлу"
+  );
+  Ok(Some(file_id))
+}
+
+/// Запоминает часть файла. Строка файла создается по первой пришедшей части,
+/// а координаты в ней всегда указывают на первую; размер — сумма известных частей.
+pub async fn upsert_part(
+  pool: &SqlitePool,
+  part: &PartMeta,
+  chat_id: i64,
+  msg: &HistoryMessage
+) -> anyhow::Result<()> {
+  let meta = &part.file;
+  ensure_dir_placeholder(pool, &meta.dir_id, msg.date).await?;
+  let name = true_name(pool, "SELECT name FROM files WHERE id = ?", &meta.file_id, &meta.name).await?;
 
   sqlx::query(
-    "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, is_broken)
-     VALUES(?, ?, ?, ?, ?, ?, ?, ?, 0)
-     ON CONFLICT(id) DO UPDATE SET dir_id=excluded.dir_id, name=excluded.name, size=excluded.size, hash=excluded.hash, tg_chat_id=excluded.tg_chat_id, tg_msg_id=excluded.tg_msg_id, is_broken=0"
+    "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, is_broken, part_count, enc_key_id)
+     VALUES(?, ?, ?, 0, ?, ?, ?, ?, 0, ?, ?)
+     ON CONFLICT(id) DO UPDATE SET dir_id=excluded.dir_id, name=excluded.name, hash=excluded.hash, is_broken=0, broken_reason=NULL, broken_since=NULL,
+       part_count=excluded.part_count, enc_key_id=excluded.enc_key_id,
+       tg_chat_id=CASE WHEN ? = 1 THEN excluded.tg_chat_id ELSE files.tg_chat_id END,
+       tg_msg_id=CASE WHEN ? = 1 THEN excluded.tg_msg_id ELSE files.tg_msg_id END"
   )
     .bind(&meta.file_id)
     .bind(&meta.dir_id)
-    .bind(&meta.name)
-    .bind(size)
+    .bind(&name)
     .bind(&meta.hash_short)
     .bind(chat_id)
-    .bind(msg_id)
-    .bind(date)
+    .bind(msg.id)
+    .bind(msg.date)
+    .bind(part.total)
+    .bind(msg.caption.as_deref().and_then(encryption_key_id))
+    .bind(part.index)
+    .bind(part.index)
     .execute(pool)
     .await?;
+  files::save_part(pool, &meta.file_id, &files::FilePart {
+    index: part.index,
+    chat_id,
+    message_id: msg.id,
+    size: msg.file_size.unwrap_or(0)
+  }).await?;
+  // Части зашифрованного файла — куски одного шифротекста, исходный размер
+  // считается от их суммы.
+  let sealed_size: i64 = sqlx::query("SELECT COALESCE(SUM(size), 0) AS total FROM file_parts WHERE file_id = ?")
+    .bind(&meta.file_id)
+    .fetch_one(pool)
+    .await?
+    .get("total");
+  let size = if msg.caption.as_deref().and_then(encryption_key_id).is_some() { vault::plain_size(sealed_size) } else { sealed_size };
+  sqlx::query("UPDATE files SET size = ? WHERE id = ?")
+    .bind(size)
+    .bind(&meta.file_id)
+    .execute(pool)
+    .await?;
+  pending_uploads::finish(pool, &meta.file_id).await?;
   Ok(())
 }
 
+/// Название набора нужно только для группировки в коллекциях, поэтому ошибка
+/// не должна мешать индексации.
+async fn remember_sticker_pack(pool: &SqlitePool, tg: &dyn TelegramService, msg: &HistoryMessage) {
+  let Some(set_id) = msg.sticker_set_id.as_deref() else {
+    return;
+  };
+  if let Err(e) = collections::remember_pack(pool, tg, set_id).await {
+    tracing::debug!(event = "sticker_pack_store_failed", set_id = set_id, error = %e, "Не удалось сохранить набор стикеров");
+  }
+}
+
+/// Вид содержимого без префикса `message` (`document`, `voice_note`, `video_note`...).
+pub(crate) fn media_kind(msg: &HistoryMessage) -> Option<String> {
+  msg.content_type.as_deref().map(content_kind).filter(|k| !k.is_empty())
+}
+
 async fn ensure_dir_placeholder(pool: &SqlitePool, dir_id: &str, date: i64) -> anyhow::Result<()> {
   if dir_id.trim().is_empty() {
     return Ok(());
   }
-  let placeholder = "Неизвестная папка";
+  let placeholder = system_dirs::name(SystemDir::Unknown);
   let inserted = sqlx::query(
     "INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at)
      VALUES(?, NULL, ?, NULL, ?)
      ON CONFLICT(id) DO NOTHING"
   )
     .bind(dir_id)
-    .bind(placeholder)
+    .bind(&placeholder)
     .bind(date)
     .execute(pool)
     .await?;
@@ -294,7 +846,7 @@ async fn ensure_dir_placeholder(pool: &SqlitePool, dir_id: &str, date: i64) -> a
   Ok(())
 }
 
-async fn find_dir_by_name(pool: &SqlitePool, name: &str) -> anyhow::Result<Option<(String, String)>> {
+pub async fn find_dir_by_name(pool: &SqlitePool, name: &str) -> anyhow::Result<Option<(String, String)>> {
   let row = sqlx::query(
     "SELECT id, name FROM directories
      WHERE lower(name) = lower(?)
@@ -307,7 +859,7 @@ async fn find_dir_by_name(pool: &SqlitePool, name: &str) -> anyhow::Result<Optio
   Ok(row.map(|r| (r.get::<String,_>("id"), r.get::<String,_>("name"))))
 }
 
-async fn ensure_dir_by_name(
+pub async fn ensure_dir_by_name(
   pool: &SqlitePool,
   tg: &dyn TelegramService,
   storage_chat_id: ChatId,
@@ -320,6 +872,50 @@ async fn ensure_dir_by_name(
   Ok((id, name.to_string()))
 }
 
+/// Папка для импорта по тегу. В канале только для чтения новая папка
+/// создается лишь в базе.
+async fn ensure_import_dir(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  storage_chat_id: ChatId,
+  name: &str,
+  read_only: bool
+) -> anyhow::Result<(String, String)> {
+  if !read_only {
+    return ensure_dir_by_name(pool, tg, storage_chat_id, name).await;
+  }
+  if let Some(found) = find_dir_by_name(pool, name).await? {
+    return Ok(found);
+  }
+  let id = dirs::create_local_dir(pool, None, name).await?;
+  Ok((id, name.to_string()))
+}
+
+async fn ensure_system_dir(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  storage_chat_id: ChatId,
+  dir: SystemDir,
+  read_only: bool
+) -> anyhow::Result<(String, String)> {
+  if read_only {
+    system_dirs::ensure_local(pool, dir).await
+  } else {
+    system_dirs::ensure(pool, tg, storage_chat_id, dir).await
+  }
+}
+
+/// Хэш импортированного файла считается от его координат, а не содержимого,
+/// поэтому сверять с ним скачанные байты бессмысленно.
+pub fn is_import_hash(chat_id: ChatId, msg_id: i64, name: &str, size: i64, hash: &str) -> bool {
+  import_hash(chat_id, msg_id, name, size) == hash
+}
+
+/// Хэш файла, импортированного без fsmeta: настоящий хэш до скачивания неизвестен.
+pub fn import_hash(chat_id: ChatId, msg_id: i64, name: &str, size: i64) -> String {
+  hash_short_from_seed(&format!("{chat_id}:{msg_id}:{name}:{size}"))
+}
+
 fn hash_short_from_seed(seed: &str) -> String {
   use sha2::{Digest, Sha256};
   let mut hasher = Sha256::new();
@@ -362,7 +958,7 @@ fn folder_hashtag(name: &str) -> Option<String> {
 }
 
 fn is_reserved_tag(tag: &str) -> bool {
-  matches!(tag, "ocltg" | "v1" | "file" | "dir")
+  matches!(tag, "ocltg" | "v1" | "file" | "dir" | "link" | "summary")
 }
 
 fn extract_folder_tags(caption: &str) -> Vec<String> {
@@ -411,3 +1007,158 @@ fn normalize_tag_name(tag: &str) -> Option<String> {
   let cleaned = out.trim().to_string();
   if cleaned.is_empty() { None } else { Some(cleaned) }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use crate::fsmeta::make_dir_message;
+  use tempfile::tempdir;
+
+  fn msg(id: i64, text: Option<String>, caption: Option<String>) -> HistoryMessage {
+    HistoryMessage {
+      id,
+      date: 100 + id,
+      text,
+      caption,
+      file_size: Some(42),
+      file_name: None,
+      sender_id: None,
+      media_group_id: None,
+      content_type: Some("messageDocument".to_string()),
+      sticker_set_id: None,
+      file_unique_id: None
+    }
+  }
+
+  #[tokio::test]
+  async fn tagged_batch_upserts_dirs_and_files_in_one_pass() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d0', NULL, 'Корень', NULL, 0)")
+      .execute(pool)
+      .await?;
+    sqlx::query("INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, is_broken) VALUES('f1', 'd0', 'a\tb.txt', 1, 'h1', -1001, 5, 0, 0)")
+      .execute(pool)
+      .await?;
+    sqlx::query("INSERT INTO pending_uploads(file_id, dir_id, name, size, hash, created_at) VALUES('f2', 'd9', 'c.txt', 42, 'h2', 0)")
+      .execute(pool)
+      .await?;
+
+    let dir = DirMeta { dir_id: "d1".into(), parent_id: "d0".into(), name: "Фото".into() };
+    let file = |file_id: &str, dir_id: &str, name: &str| {
+      make_file_caption(&FileMeta { dir_id: dir_id.into(), file_id: file_id.into(), name: name.into(), hash_short: "h".into() })
+    };
+    let msgs = [
+      msg(3, Some(make_dir_message(&dir)), None),
+      msg(2, None, Some(format!("отпуск {}", file("f1", "d1", "a\tb.txt")))),
+      msg(1, None, Some(file("f2", "d9", "c.txt")))
+    ];
+    let dirs = vec![(&msgs[0], dir.clone())];
+    let files: Vec<_> = msgs[1..]
+      .iter()
+      .map(|m| (m, parse_file_caption(m.caption.as_deref().unwrap()).unwrap()))
+      .collect();
+    upsert_tagged(pool, -1001, &dirs, &files).await?;
+
+    let dirs: Vec<(String, Option<String>, String)> = sqlx::query("SELECT id, parent_id, name FROM directories ORDER BY id")
+      .fetch_all(pool)
+      .await?
+      .into_iter()
+      .map(|r| (r.get("id"), r.get("parent_id"), r.get("name")))
+      .collect();
+    assert_eq!(dirs, vec![
+      ("d0".into(), None, "Корень".into()),
+      ("d1".into(), Some("d0".into()), "Фото".into()),
+      ("d9".into(), None, system_dirs::name(SystemDir::Unknown))
+    ]);
+
+    let f1 = sqlx::query("SELECT dir_id, name, tg_msg_id FROM files WHERE id = 'f1'").fetch_one(pool).await?;
+    assert_eq!(f1.get::<String,_>("dir_id"), "d1");
+    assert_eq!(f1.get::<String,_>("name"), "a\tb.txt");
+    assert_eq!(f1.get::<i64,_>("tg_msg_id"), 2);
+    let caption: String = sqlx::query("SELECT caption FROM file_search WHERE file_id = 'f1'").fetch_one(pool).await?.get("caption");
+    assert_eq!(caption, "отпуск");
+    let pending: i64 = sqlx::query("SELECT COUNT(1) AS cnt FROM pending_uploads").fetch_one(pool).await?.get("cnt");
+    assert_eq!(pending, 0);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn forwarded_copy_links_to_known_file_but_not_to_itself() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d0', NULL, 'Корень', NULL, 0)")
+      .execute(pool)
+      .await?;
+    sqlx::query(
+      "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, remote_unique_id)
+       VALUES('orig', 'd0', 'a.pdf', 1, 'h', -1001, 5, 0, 'U1')"
+    )
+      .execute(pool)
+      .await?;
+
+    let mut forwarded = msg(9, None, None);
+    forwarded.file_unique_id = Some("U1".to_string());
+    assert_eq!(link_forwarded_copy(pool, -2002, &forwarded).await?, Some("orig".to_string()));
+    // Повторная индексация того же сообщения не плодит копии.
+    assert_eq!(link_forwarded_copy(pool, -2002, &forwarded).await?, Some("orig".to_string()));
+    let copies: i64 = sqlx::query("SELECT COUNT(*) AS n FROM file_copies WHERE tg_chat_id = -2002 AND tg_msg_id = 9 AND file_id = 'orig'")
+      .fetch_one(pool)
+      .await?
+      .get("n");
+    assert_eq!(copies, 1);
+
+    // Сообщение самого файла — не копия.
+    let mut own = msg(5, None, None);
+    own.file_unique_id = Some("U1".to_string());
+    assert_eq!(link_forwarded_copy(pool, -1001, &own).await?, None);
+
+    let mut unknown = msg(10, None, None);
+    unknown.file_unique_id = Some("U2".to_string());
+    assert_eq!(link_forwarded_copy(pool, -2002, &unknown).await?, None);
+    assert_eq!(link_forwarded_copy(pool, -2002, &msg(11, None, None)).await?, None);
+    let total: i64 = sqlx::query("SELECT COUNT(*) AS n FROM file_copies").fetch_one(pool).await?.get("n");
+    assert_eq!(total, 1);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn album_member_follows_sibling_of_the_same_chat() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    let (unassigned, _) = system_dirs::ensure_local(pool, SystemDir::Unassigned).await?;
+    sqlx::query(
+      "INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES
+         ('trip', NULL, 'Поездка', NULL, 0), ('elsewhere', NULL, 'Другой канал', NULL, 0)"
+    )
+      .execute(pool)
+      .await?;
+    sqlx::query(
+      "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, media_group_id) VALUES
+         ('loose', ?, 'a.jpg', 1, 'h', -1001, 9, 0, 'g1'),
+         ('tagged', 'trip', 'b.jpg', 1, 'h', -1001, 10, 0, 'g1'),
+         ('foreign', 'elsewhere', 'c.jpg', 1, 'h', -2002, 5, 0, 'g1')"
+    )
+      .bind(&unassigned)
+      .execute(pool)
+      .await?;
+
+    let mut member = msg(11, None, None);
+    member.media_group_id = Some("g1".to_string());
+    // Разобранная папка альбома важнее «Неразобранного», чужой чат не учитывается.
+    let sibling = album_sibling_dir(pool, -1001, &member).await?;
+    assert_eq!(sibling, Some(("trip".to_string(), "Поездка".to_string())));
+    let sibling = album_sibling_dir(pool, -3003, &member).await?;
+    assert_eq!(sibling, None);
+    member.media_group_id = None;
+    assert_eq!(album_sibling_dir(pool, -1001, &member).await?, None);
+    Ok(())
+  }
+}
@@ -0,0 +1,200 @@
+use chrono::Utc;
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+use ulid::Ulid;
+
+use crate::fsmeta::{LinkKind, LinkMeta, make_link_message};
+use crate::telegram::{TelegramService, ChatId};
+use crate::app::dirs::dir_exists;
+
+#[derive(Debug, Clone)]
+pub struct LinkRow {
+  pub id: String,
+  pub dir_id: String,
+  pub kind: LinkKind,
+  pub target_id: String,
+  pub tg_msg_id: Option<i64>
+}
+
+pub async fn create_link(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  chat_id: ChatId,
+  dir_id: &str,
+  kind: LinkKind,
+  target_id: &str
+) -> anyhow::Result<String> {
+  if !dir_exists(pool, dir_id).await? {
+    return Err(anyhow::anyhow!("Папка не найдена"));
+  }
+  match kind {
+    LinkKind::File => {
+      let row = sqlx::query("SELECT dir_id FROM files WHERE id = ?")
+        .bind(target_id)
+        .fetch_optional(pool)
+        .await?;
+      let Some(row) = row else {
+        return Err(anyhow::anyhow!("Файл не найден"));
+      };
+      if row.get::<String,_>("dir_id") == dir_id {
+        return Err(anyhow::anyhow!("Файл уже находится в этой папке"));
+      }
+    }
+    LinkKind::Dir => {
+      if target_id == dir_id {
+        return Err(anyhow::anyhow!("Нельзя создать ссылку папки внутри самой себя"));
+      }
+      if !dir_exists(pool, target_id).await? {
+        return Err(anyhow::anyhow!("Папка не найдена"));
+      }
+    }
+  }
+
+  let existing = sqlx::query("SELECT id FROM links WHERE dir_id = ? AND target_kind = ? AND target_id = ?")
+    .bind(dir_id)
+    .bind(kind.as_str())
+    .bind(target_id)
+    .fetch_optional(pool)
+    .await?;
+  if let Some(row) = existing {
+    return Ok(row.get::<String,_>("id"));
+  }
+
+  let id = Ulid::new().to_string();
+  let created_at = Utc::now().timestamp();
+  sqlx::query("INSERT INTO links(id, dir_id, target_kind, target_id, tg_msg_id, created_at) VALUES(?, ?, ?, ?, NULL, ?)")
+    .bind(&id)
+    .bind(dir_id)
+    .bind(kind.as_str())
+    .bind(target_id)
+    .bind(created_at)
+    .execute(pool)
+    .await?;
+
+  let msg = make_link_message(&LinkMeta {
+    link_id: id.clone(),
+    dir_id: dir_id.to_string(),
+    kind,
+    target_id: target_id.to_string()
+  });
+  let uploaded = match tg.send_dir_message(chat_id, msg).await {
+    Ok(v) => v,
+    Err(e) => {
+      sqlx::query("DELETE FROM links WHERE id = ?")
+        .bind(&id)
+        .execute(pool)
+        .await?;
+      return Err(e.into());
+    }
+  };
+
+  sqlx::query("UPDATE links SET tg_msg_id = ? WHERE id = ?")
+    .bind(uploaded.message_id)
+    .bind(&id)
+    .execute(pool)
+    .await?;
+
+  Ok(id)
+}
+
+pub async fn delete_link(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  chat_id: ChatId,
+  link_id: &str
+) -> anyhow::Result<()> {
+  let Some(link) = fetch_link(pool, link_id).await? else {
+    return Err(anyhow::anyhow!("Ссылка не найдена"));
+  };
+  if let Some(msg_id) = link.tg_msg_id {
+    if let Err(e) = tg.delete_messages(chat_id, vec![msg_id], true).await {
+      tracing::warn!(event = "link_delete_message_failed", link_id = link_id, error = %e, "Не удалось удалить сообщение ссылки");
+    }
+  }
+  sqlx::query("DELETE FROM links WHERE id = ?")
+    .bind(link_id)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+pub async fn fetch_link(pool: &SqlitePool, link_id: &str) -> anyhow::Result<Option<LinkRow>> {
+  let row = sqlx::query("SELECT id, dir_id, target_kind, target_id, tg_msg_id FROM links WHERE id = ?")
+    .bind(link_id)
+    .fetch_optional(pool)
+    .await?;
+  let Some(row) = row else {
+    return Ok(None);
+  };
+  let raw_kind: String = row.get("target_kind");
+  let Some(kind) = LinkKind::parse(&raw_kind) else {
+    return Ok(None);
+  };
+  Ok(Some(LinkRow {
+    id: row.get::<String,_>("id"),
+    dir_id: row.get::<String,_>("dir_id"),
+    kind,
+    target_id: row.get::<String,_>("target_id"),
+    tg_msg_id: row.try_get::<i64,_>("tg_msg_id").ok()
+  }))
+}
+
+/// Если `dir_id` указывает на ссылку на папку, возвращает id целевой папки.
+pub async fn resolve_dir_id(pool: &SqlitePool, dir_id: &str) -> anyhow::Result<String> {
+  match fetch_link(pool, dir_id).await? {
+    Some(link) if link.kind == LinkKind::Dir => Ok(link.target_id),
+    _ => Ok(dir_id.to_string())
+  }
+}
+
+/// Папка ссылки должна уже существовать (индексатор заводит заглушку заранее).
+pub async fn upsert_link(pool: &SqlitePool, meta: &LinkMeta, msg_id: i64, date: i64) -> anyhow::Result<()> {
+  sqlx::query(
+    "INSERT INTO links(id, dir_id, target_kind, target_id, tg_msg_id, created_at) VALUES(?, ?, ?, ?, ?, ?)
+     ON CONFLICT(id) DO UPDATE SET dir_id=excluded.dir_id, target_kind=excluded.target_kind, target_id=excluded.target_id, tg_msg_id=excluded.tg_msg_id"
+  )
+    .bind(&meta.link_id)
+    .bind(&meta.dir_id)
+    .bind(meta.kind.as_str())
+    .bind(&meta.target_id)
+    .bind(msg_id)
+    .bind(date)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::tempdir;
+  use crate::db::Db;
+
+  #[tokio::test]
+  async fn resolve_dir_id_follows_dir_links_only() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+
+    for (id, name) in [("d1", "Клиенты"), ("d2", "2024")] {
+      sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES(?, NULL, ?, NULL, 0)")
+        .bind(id)
+        .bind(name)
+        .execute(pool)
+        .await?;
+    }
+    let meta = LinkMeta { link_id: "l1".into(), dir_id: "d2".into(), kind: LinkKind::Dir, target_id: "d1".into() };
+    upsert_link(pool, &meta, 10, 0).await?;
+
+    assert_eq!(resolve_dir_id(pool, "l1").await?, "d1");
+    assert_eq!(resolve_dir_id(pool, "d2").await?, "d2");
+
+    sqlx::query("DELETE FROM directories WHERE id = ?")
+      .bind("d1")
+      .execute(pool)
+      .await?;
+    assert!(fetch_link(pool, "l1").await?.is_none());
+    Ok(())
+  }
+}
@@ -0,0 +1,283 @@
+use chrono::Utc;
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+
+use crate::paths::Paths;
+use crate::settings;
+
+use super::downloads_cache::{self, ClearReport};
+use super::{files, local_copies};
+use super::local_copies::CopyState;
+
+pub const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+pub const MIN_MAX_BYTES: u64 = 100 * 1024 * 1024;
+
+/// Предел места под скачанные файлы. При превышении удаляются копии, которые
+/// дольше всего не открывали; записи в базе и сообщения в канале остаются.
+/// Измененные после скачивания копии не удаляются.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CacheLimit {
+  #[serde(default)]
+  pub enabled: bool,
+  pub max_bytes: u64
+}
+
+impl Default for CacheLimit {
+  fn default() -> Self {
+    Self { enabled: false, max_bytes: DEFAULT_MAX_BYTES }
+  }
+}
+
+impl CacheLimit {
+  pub fn sanitized(self) -> CacheLimit {
+    CacheLimit { enabled: self.enabled, max_bytes: self.max_bytes.max(MIN_MAX_BYTES) }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct CacheUsage {
+  pub bytes: u64,
+  pub file_count: i64,
+  pub limit: CacheLimit
+}
+
+/// Отмечает обращение к скачанному файлу.
+pub async fn touch(pool: &SqlitePool, file_id: &str) -> anyhow::Result<()> {
+  sqlx::query(
+    "INSERT INTO local_cache(file_id, last_access) VALUES(?, ?)
+     ON CONFLICT(file_id) DO UPDATE SET last_access = excluded.last_access"
+  )
+    .bind(file_id)
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+/// Объем по записанным локальным копиям, без обхода каталога загрузок.
+pub async fn usage(pool: &SqlitePool) -> anyhow::Result<CacheUsage> {
+  let row = sqlx::query("SELECT COUNT(1) AS cnt, COALESCE(SUM(size), 0) AS total FROM local_copies")
+    .fetch_one(pool)
+    .await?;
+  Ok(CacheUsage {
+    bytes: row.get::<i64, _>("total").max(0) as u64,
+    file_count: row.get("cnt"),
+    limit: settings::get_cache_limit(pool).await?
+  })
+}
+
+/// Копии от давно открытых к недавним. Без отметки обращения считается
+/// время, когда копию записали.
+async fn lru_order(pool: &SqlitePool) -> anyhow::Result<Vec<String>> {
+  let rows = sqlx::query(
+    "SELECT c.file_id FROM local_copies c LEFT JOIN local_cache a ON a.file_id = c.file_id
+     ORDER BY COALESCE(a.last_access, c.recorded_at), c.file_id"
+  )
+    .fetch_all(pool)
+    .await?;
+  Ok(rows.into_iter().map(|r| r.get("file_id")).collect())
+}
+
+/// Освобождает место до предела, не трогая `keep` — только что открытый файл.
+pub async fn enforce(pool: &SqlitePool, paths: &Paths, keep: Option<&str>) -> anyhow::Result<ClearReport> {
+  let limit = settings::get_cache_limit(pool).await?;
+  if !limit.enabled {
+    return Ok(ClearReport::default());
+  }
+  evict(pool, paths, limit.max_bytes, keep).await
+}
+
+async fn evict(pool: &SqlitePool, paths: &Paths, max_bytes: u64, keep: Option<&str>) -> anyhow::Result<ClearReport> {
+  let mut report = ClearReport::default();
+  if usage(pool).await?.bytes <= max_bytes {
+    return Ok(report);
+  }
+  // Записи о пропавших копиях завышают объем: сначала забываем их, и
+  // освобождать приходится только то, что действительно лежит на диске.
+  local_copies::prune(pool, paths).await?;
+  let current = usage(pool).await?.bytes;
+  if current <= max_bytes {
+    return Ok(report);
+  }
+  let mut excess = current - max_bytes;
+  for file_id in lru_order(pool).await? {
+    if excess == 0 {
+      break;
+    }
+    if Some(file_id.as_str()) == keep {
+      continue;
+    }
+    match local_copies::state(pool, paths, &file_id).await {
+      Ok(CopyState::Unchanged) => {}
+      Ok(CopyState::Missing) => continue,
+      Ok(CopyState::Edited) => {
+        report.kept_edited += 1;
+        continue;
+      }
+      Err(e) => {
+        tracing::warn!(event = "local_cache_check_failed", file_id = file_id.as_str(), error = %e, "Не удалось сверить скачанный файл");
+        continue;
+      }
+    }
+    match files::remove_local_download(pool, paths, &file_id).await {
+      Ok(Some(freed)) => {
+        excess = excess.saturating_sub(freed);
+        report.removed_files += 1;
+        report.freed_bytes += freed;
+      }
+      Ok(None) => {}
+      Err(e) => {
+        tracing::warn!(event = "local_cache_evict_failed", file_id = file_id.as_str(), error = %e, "Не удалось удалить скачанный файл");
+      }
+    }
+  }
+  tracing::info!(
+    event = "local_cache_evicted",
+    removed = report.removed_files,
+    freed_bytes = report.freed_bytes,
+    kept_edited = report.kept_edited,
+    "Кэш загрузок уменьшен до предела"
+  );
+  Ok(report)
+}
+
+/// Удаляет скачанные файлы: все или, если задана `dir_id`, только файлы
+/// этой папки (без вложенных) через `downloads_cache`.
+pub async fn clear(pool: &SqlitePool, paths: &Paths, dir_id: Option<&str>) -> anyhow::Result<ClearReport> {
+  if let Some(dir_id) = dir_id {
+    return downloads_cache::clear_downloads(pool, paths, dir_id).await;
+  }
+  let mut report = ClearReport::default();
+  for file_id in lru_order(pool).await? {
+    match files::remove_local_download(pool, paths, &file_id).await {
+      Ok(Some(freed)) => {
+        report.removed_files += 1;
+        report.freed_bytes += freed;
+      }
+      Ok(None) => {}
+      Err(e) => {
+        tracing::warn!(event = "local_cache_clear_failed", file_id = file_id.as_str(), error = %e, "Не удалось удалить скачанный файл");
+      }
+    }
+  }
+  tracing::info!(event = "local_cache_cleared", removed = report.removed_files, freed_bytes = report.freed_bytes, "Кэш загрузок очищен");
+  Ok(report)
+}
+
+/// После открытия файла: отметить обращение и уложиться в предел.
+pub async fn accessed(pool: &SqlitePool, paths: &Paths, file_id: &str) -> anyhow::Result<()> {
+  touch(pool, file_id).await?;
+  enforce(pool, paths, Some(file_id)).await?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[tokio::test]
+  async fn evicts_least_recently_used_over_limit() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let paths = Paths::from_base(tmp.path().to_path_buf());
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d1', NULL, 'docs', NULL, 0)")
+      .execute(pool)
+      .await?;
+    let dir = paths.layout().downloads_dir().join("docs");
+    std::fs::create_dir_all(&dir)?;
+    let chunk = vec![0u8; 8];
+    std::fs::write(dir.join("probe.bin"), &chunk)?;
+    let short_hash: String = files::hash_full(&dir.join("probe.bin"))?.chars().take(8).collect();
+    std::fs::remove_file(dir.join("probe.bin"))?;
+    for (msg_id, (id, name)) in [("old", "old.bin"), ("mid", "mid.bin"), ("new", "new.bin")].into_iter().enumerate() {
+      sqlx::query("INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at) VALUES(?, 'd1', ?, ?, ?, 1, ?, 0)")
+        .bind(id)
+        .bind(name)
+        .bind(chunk.len() as i64)
+        .bind(&short_hash)
+        .bind(msg_id as i64)
+        .execute(pool)
+        .await?;
+      std::fs::write(dir.join(name), &chunk)?;
+      local_copies::record(pool, &paths, id, &dir.join(name)).await?;
+    }
+    for (id, at) in [("old", 10), ("mid", 20), ("new", 30)] {
+      sqlx::query("INSERT INTO local_cache(file_id, last_access) VALUES(?, ?)").bind(id).bind(at).execute(pool).await?;
+    }
+
+    // Предел выключен по умолчанию.
+    assert_eq!(enforce(pool, &paths, None).await?.removed_files, 0);
+    assert_eq!(usage(pool).await?.bytes, 24);
+    // Открыли самый старый — уходит следующий по давности.
+    let report = evict(pool, &paths, 16, Some("old")).await?;
+    assert_eq!(report.removed_files, 1);
+    assert!(dir.join("old.bin").exists());
+    assert!(!dir.join("mid.bin").exists());
+    assert_eq!(usage(pool).await?.file_count, 2);
+
+    // Пропавшая копия не считается освобожденной и не останавливает
+    // вытеснение раньше времени.
+    std::fs::remove_file(dir.join("old.bin"))?;
+    let report = evict(pool, &paths, 0, None).await?;
+    assert_eq!((report.removed_files, report.freed_bytes), (1, 8));
+    assert!(!dir.join("new.bin").exists());
+    assert_eq!(usage(pool).await?.bytes, 0);
+
+    std::fs::write(dir.join("mid.bin"), &chunk)?;
+    local_copies::record(pool, &paths, "mid", &dir.join("mid.bin")).await?;
+    let report = clear(pool, &paths, None).await?;
+    assert_eq!((report.removed_files, report.freed_bytes), (1, 8));
+    assert_eq!(usage(pool).await?.bytes, 0);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn eviction_keeps_copies_edited_after_download() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let paths = Paths::from_base(tmp.path().to_path_buf());
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d1', NULL, 'docs', NULL, 0)")
+      .execute(pool)
+      .await?;
+    let dir = paths.layout().downloads_dir().join("docs");
+    std::fs::create_dir_all(&dir)?;
+    let original = b"original".to_vec();
+    std::fs::write(dir.join("probe.bin"), &original)?;
+    let full_hash = files::hash_full(&dir.join("probe.bin"))?;
+    std::fs::remove_file(dir.join("probe.bin"))?;
+    for (msg_id, id) in ["edited", "resized", "clean"].into_iter().enumerate() {
+      sqlx::query(
+        "INSERT INTO files(id, dir_id, name, size, hash, content_hash, tg_chat_id, tg_msg_id, created_at)
+         VALUES(?, 'd1', ?, 8, ?, ?, 1, ?, 0)"
+      )
+        .bind(id)
+        .bind(format!("{id}.txt"))
+        .bind(&full_hash[..8])
+        .bind(&full_hash)
+        .bind(msg_id as i64)
+        .execute(pool)
+        .await?;
+      std::fs::write(dir.join(format!("{id}.txt")), &original)?;
+      local_copies::record(pool, &paths, id, &dir.join(format!("{id}.txt"))).await?;
+    }
+
+    // Правка того же размера, уже перезаписанная `lookup` без хэша, и правка
+    // с другим размером, которую еще никто не видел.
+    std::fs::write(dir.join("edited.txt"), b"modified")?;
+    local_copies::lookup(pool, &paths, "edited").await?;
+    std::fs::write(dir.join("resized.txt"), b"modified and longer")?;
+
+    let report = evict(pool, &paths, 0, None).await?;
+    assert_eq!((report.removed_files, report.kept_edited), (1, 2));
+    assert_eq!(std::fs::read(dir.join("edited.txt"))?, b"modified");
+    assert!(dir.join("resized.txt").exists());
+    assert!(!dir.join("clean.txt").exists());
+    Ok(())
+  }
+}
@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use chrono::Utc;
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+
+use crate::paths::Paths;
+
+use super::{files, sync};
+
+/// Где лежит скачанный файл и каким он был, когда его записали. «Скачан ли
+/// файл» проверяется по этой таблице, а не перебором каталога загрузок;
+/// запись сверяется с диском при чтении. Пути внутри каталога загрузок
+/// хранятся относительными, чтобы пережить перенос данных приложения.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalCopy {
+  pub path: PathBuf,
+  pub size: i64,
+  pub mtime: i64,
+  pub hash: Option<String>
+}
+
+fn stored_path(paths: &Paths, path: &Path) -> String {
+  let rel = path.strip_prefix(paths.layout().downloads_dir()).unwrap_or(path);
+  rel.to_string_lossy().into_owned()
+}
+
+/// Абсолютный путь из сохраненного.
+pub fn resolve_path(paths: &Paths, stored: &str) -> PathBuf {
+  let path = Path::new(stored);
+  if path.is_absolute() {
+    path.to_path_buf()
+  } else {
+    paths.layout().downloads_dir().join(path)
+  }
+}
+
+/// Размер и время изменения файла; `None`, если файла нет.
+fn stat(path: &Path) -> Option<(i64, i64)> {
+  let meta = std::fs::metadata(path).ok().filter(|m| m.is_file())?;
+  let mtime = meta
+    .modified()
+    .ok()
+    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+    .map(|d| d.as_secs() as i64)
+    .unwrap_or(0);
+  Some((meta.len().min(i64::MAX as u64) as i64, mtime))
+}
+
+/// Запоминает локальную копию после скачивания.
+pub async fn record(pool: &SqlitePool, paths: &Paths, file_id: &str, path: &Path) -> anyhow::Result<()> {
+  let Some((size, mtime)) = stat(path) else {
+    return forget(pool, file_id).await;
+  };
+  sqlx::query(
+    "INSERT INTO local_copies(file_id, path, size, mtime, hash, recorded_at) VALUES(?, ?, ?, ?, NULL, ?)
+     ON CONFLICT(file_id) DO UPDATE SET path = excluded.path, size = excluded.size, mtime = excluded.mtime,
+       hash = NULL, recorded_at = excluded.recorded_at"
+  )
+    .bind(file_id)
+    .bind(stored_path(paths, path))
+    .bind(size)
+    .bind(mtime)
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+/// Полный хэш содержимого, когда его посчитала проверка после скачивания.
+pub async fn set_hash(pool: &SqlitePool, file_id: &str, hash: &str) -> anyhow::Result<()> {
+  sqlx::query("UPDATE local_copies SET hash = ? WHERE file_id = ?")
+    .bind(hash)
+    .bind(file_id)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+pub async fn forget(pool: &SqlitePool, file_id: &str) -> anyhow::Result<()> {
+  sqlx::query("DELETE FROM local_copies WHERE file_id = ?")
+    .bind(file_id)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+/// Забывает копию по пути, например после очистки каталога загрузок.
+pub async fn forget_path(pool: &SqlitePool, paths: &Paths, path: &Path) -> anyhow::Result<()> {
+  sqlx::query("DELETE FROM local_copies WHERE path = ?")
+    .bind(stored_path(paths, path))
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+/// Забывает записи о копиях, которых больше нет на диске. Возвращает,
+/// сколько записей убрано.
+pub async fn prune(pool: &SqlitePool, paths: &Paths) -> anyhow::Result<u64> {
+  let rows = sqlx::query("SELECT file_id, path FROM local_copies")
+    .fetch_all(pool)
+    .await?;
+  let mut removed = 0u64;
+  for row in rows {
+    if stat(&resolve_path(paths, &row.get::<String, _>("path"))).is_none() {
+      forget(pool, &row.get::<String, _>("file_id")).await?;
+      removed += 1;
+    }
+  }
+  Ok(removed)
+}
+
+/// Локальная копия файла, если она все еще на месте. Пропавшая копия
+/// забывается, а измененная вне приложения записывается заново без хэша.
+pub async fn lookup(pool: &SqlitePool, paths: &Paths, file_id: &str) -> anyhow::Result<Option<LocalCopy>> {
+  let Some(row) = sqlx::query("SELECT path, size, mtime, hash FROM local_copies WHERE file_id = ?")
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?
+  else {
+    return Ok(None);
+  };
+  let path = resolve_path(paths, &row.get::<String, _>("path"));
+  let Some((size, mtime)) = stat(&path) else {
+    forget(pool, file_id).await?;
+    return Ok(None);
+  };
+  if size != row.get::<i64, _>("size") || mtime != row.get::<i64, _>("mtime") {
+    record(pool, paths, file_id, &path).await?;
+    return Ok(Some(LocalCopy { path, size, mtime, hash: None }));
+  }
+  Ok(Some(LocalCopy { path, size, mtime, hash: row.try_get::<String, _>("hash").ok() }))
+}
+
+/// Сверка копии с тем, что скачивали: вытеснять можно только нетронутую.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyState {
+  Missing,
+  /// Копию меняли после скачивания: правки пользователя есть только в ней.
+  Edited,
+  Unchanged
+}
+
+/// Нетронута ли копия: размер и время изменения совпадают с записанными, а
+/// хэш содержимого — с хэшем файла в хранилище. Без записанного хэша он
+/// считается заново: `lookup` перезаписывает измененную копию без хэша, и по
+/// одним размеру и времени правку уже не отличить.
+pub async fn state(pool: &SqlitePool, paths: &Paths, file_id: &str) -> anyhow::Result<CopyState> {
+  let Some(row) = sqlx::query(
+    "SELECT c.path, c.size, c.mtime, c.hash, f.hash AS file_hash, f.content_hash
+     FROM local_copies c JOIN files f ON f.id = c.file_id WHERE c.file_id = ?"
+  )
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?
+  else {
+    return Ok(CopyState::Missing);
+  };
+  let path = resolve_path(paths, &row.get::<String, _>("path"));
+  let Some((size, mtime)) = stat(&path) else {
+    forget(pool, file_id).await?;
+    return Ok(CopyState::Missing);
+  };
+  if size != row.get::<i64, _>("size") || mtime != row.get::<i64, _>("mtime") {
+    return Ok(CopyState::Edited);
+  }
+  let recorded: Option<String> = row.try_get("hash").ok().flatten();
+  let actual = match recorded.clone() {
+    Some(hash) => hash,
+    None => tokio::task::spawn_blocking(move || files::hash_full(&path)).await??
+  };
+  let expected_full: Option<String> = row.try_get("content_hash").ok().flatten();
+  let expected_short: String = row.try_get::<Option<String>, _>("file_hash").ok().flatten().unwrap_or_default();
+  let matches = match expected_full {
+    Some(full) => full == actual,
+    None => !expected_short.is_empty() && actual.starts_with(&expected_short)
+  };
+  if !matches {
+    return Ok(CopyState::Edited);
+  }
+  if recorded.is_none() {
+    set_hash(pool, file_id, &actual).await?;
+  }
+  Ok(CopyState::Unchanged)
+}
+
+/// Проверка для списков: сохраненный путь из `LEFT JOIN local_copies`
+/// сверяется с диском одним `stat`. Возвращает признак «скачан» и размер копии.
+pub fn check(paths: &Paths, stored: Option<&str>) -> (bool, Option<i64>) {
+  match stored.map(|s| resolve_path(paths, s)).as_deref().and_then(stat) {
+    Some((size, _)) => (true, Some(size)),
+    None => (false, None)
+  }
+}
+
+/// Один раз после обновления находит копии, скачанные до появления таблицы,
+/// прежним поиском по имени в каталоге загрузок.
+pub async fn backfill(pool: &SqlitePool, paths: &Paths) -> anyhow::Result<usize> {
+  if sync::get_sync(pool, "local_copies_indexed").await?.is_some() {
+    return Ok(0);
+  }
+  let rows = sqlx::query(
+    "SELECT id, dir_id, name, size FROM files WHERE id NOT IN (SELECT file_id FROM local_copies)"
+  )
+    .fetch_all(pool)
+    .await?;
+  let mut dir_paths: HashMap<String, PathBuf> = HashMap::new();
+  let mut found = 0;
+  for row in rows {
+    let dir_id: String = row.get("dir_id");
+    let dir_path = match dir_paths.get(&dir_id) {
+      Some(cached) => cached.clone(),
+      None => {
+        let built = files::build_dir_path(pool, &dir_id).await?;
+        dir_paths.insert(dir_id, built.clone());
+        built
+      }
+    };
+    let file_id: String = row.get("id");
+    if let Some(path) = files::scan_local_download(paths, &dir_path, &row.get::<String, _>("name"), &file_id, row.get("size")) {
+      record(pool, paths, &file_id, &path).await?;
+      found += 1;
+    }
+  }
+  sync::set_sync(pool, "local_copies_indexed", &Utc::now().to_rfc3339()).await?;
+  tracing::info!(event = "local_copies_indexed", found = found, "Локальные копии файлов занесены в базу");
+  Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[tokio::test]
+  async fn lookup_validates_copies_lazily() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let paths = Paths::from_base(tmp.path().to_path_buf());
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d1', NULL, 'docs', NULL, 0)")
+      .execute(pool)
+      .await?;
+    sqlx::query(
+      "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at) VALUES
+         ('f1', 'd1', 'report.txt', 5, 'h', 1, 1, 0), ('f2', 'd1', 'old.txt', 3, 'h', 1, 2, 0)"
+    )
+      .execute(pool)
+      .await?;
+    let dir = paths.layout().downloads_dir().join("docs");
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join("report.txt"), b"hello")?;
+    std::fs::write(dir.join("old.txt"), b"old")?;
+
+    // Копии, скачанные до таблицы, находятся один раз.
+    assert_eq!(backfill(pool, &paths).await?, 2);
+    assert_eq!(backfill(pool, &paths).await?, 0);
+    let stored: String = sqlx::query("SELECT path FROM local_copies WHERE file_id = 'f1'").fetch_one(pool).await?.get("path");
+    assert_eq!(Path::new(&stored), Path::new("docs").join("report.txt"));
+
+    // Переименованная приложением копия находится по записи, а не по имени.
+    let moved = dir.join("report (1).txt");
+    std::fs::rename(dir.join("report.txt"), &moved)?;
+    record(pool, &paths, "f1", &moved).await?;
+    set_hash(pool, "f1", "abc").await?;
+    let copy = lookup(pool, &paths, "f1").await?.expect("copy");
+    assert_eq!(copy.path, moved);
+    assert_eq!(copy.hash.as_deref(), Some("abc"));
+    assert_eq!(check(&paths, Some(&stored_path(&paths, &moved))), (true, Some(5)));
+
+    std::fs::remove_file(dir.join("old.txt"))?;
+    assert_eq!(lookup(pool, &paths, "f2").await?, None);
+    let left: i64 = sqlx::query("SELECT COUNT(1) AS cnt FROM local_copies").fetch_one(pool).await?.get("cnt");
+    assert_eq!(left, 1);
+    Ok(())
+  }
+}
@@ -0,0 +1,289 @@
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use sha2::{Digest, Sha256};
+
+/// Имена, которые Windows резервирует за устройствами, в том числе с
+/// любым расширением (`NUL.txt`).
+const WINDOWS_RESERVED: &[&str] = &[
+  "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+  "COM¹", "COM²", "COM³", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9", "LPT¹",
+  "LPT²", "LPT³"
+];
+/// Предел длины имени в файловых системах.
+const FS_NAME_LIMIT: usize = 255;
+/// Самый длинный суффикс, который добавляется при совпадении имен.
+const DEDUPE_SUFFIX_RESERVE: usize = " (100)".len();
+const MAX_NAME_LEN: usize = FS_NAME_LIMIT - DEDUPE_SUFFIX_RESERVE;
+/// Длинное «расширение» скорее часть имени, его можно укорачивать.
+const MAX_KEPT_EXTENSION: usize = 16;
+/// Сколько hex-символов хэша file_id попадает в имя локальной копии. Берется
+/// хэш, а не начало id: первые символы ULID — время, и у файлов одного
+/// импорта они совпадают.
+const FILE_ID_FRAGMENT: usize = 8;
+/// Имя локальной копии, если от имени из канала ничего не осталось.
+pub const DEFAULT_NAME: &str = "файл";
+
+/// Чьим правилам должны подчиняться локальные имена файлов и папок.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NameProfile {
+  /// Правила ОС, на которой запущено приложение.
+  #[default]
+  Auto,
+  Windows,
+  Macos,
+  Linux,
+  /// Правила Windows на любой ОС: имена переносятся на флешки с exFAT и
+  /// сетевые диски без потерь.
+  Portable
+}
+
+impl NameProfile {
+  pub fn resolved(self) -> NameProfile {
+    match self {
+      NameProfile::Auto if cfg!(target_os = "windows") => NameProfile::Windows,
+      NameProfile::Auto if cfg!(target_os = "macos") => NameProfile::Macos,
+      NameProfile::Auto => NameProfile::Linux,
+      other => other
+    }
+  }
+
+  fn strict(self) -> bool {
+    matches!(self.resolved(), NameProfile::Windows | NameProfile::Portable)
+  }
+}
+
+/// Как имя из канала превращается в имя файла на диске. `/`, `\`, `:` и
+/// управляющие символы заменяются всегда, чтобы прежние загрузки находились
+/// по тем же именам; Windows дополнительно запрещает `<>"|?*`, имена
+/// устройств и точки с пробелами в конце. Смена правил меняет ожидаемые
+/// имена, и уже скачанные файлы с другими именами будут скачаны заново.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct LocalNameRules {
+  #[serde(default)]
+  pub profile: NameProfile,
+  #[serde(default = "default_replacement")]
+  pub replacement: char,
+  /// Добавлять к имени начало file_id: `Отчет [1f3a9c0e].pdf`. Одноименные
+  /// файлы одной папки тогда не делят локальную копию, а поиск копии идет по
+  /// точному имени без подбора суффикса ` (N)` по размеру.
+  #[serde(default)]
+  pub file_id_suffix: bool
+}
+
+fn default_replacement() -> char {
+  '_'
+}
+
+impl Default for LocalNameRules {
+  fn default() -> Self {
+    Self { profile: NameProfile::Auto, replacement: default_replacement(), file_id_suffix: false }
+  }
+}
+
+impl LocalNameRules {
+  /// Замена сама должна быть допустимой на любой ОС.
+  pub fn sanitized(self) -> LocalNameRules {
+    let replacement = self.replacement;
+    let valid = !is_forbidden(replacement, true) && !replacement.is_whitespace() && replacement != '.';
+    LocalNameRules { replacement: if valid { replacement } else { default_replacement() }, ..self }
+  }
+}
+
+static ACTIVE: Lazy<RwLock<LocalNameRules>> = Lazy::new(|| RwLock::new(LocalNameRules::default()));
+
+pub fn set_active(rules: LocalNameRules) {
+  *ACTIVE.write() = rules.sanitized();
+}
+
+pub fn active() -> LocalNameRules {
+  *ACTIVE.read()
+}
+
+/// Что пришлось поменять в имени — для предпросмотра в настройках.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Adjustment {
+  ForbiddenChars,
+  RelativeSegment,
+  TrailingDots,
+  ReservedName,
+  Truncated
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct LocalNamePreview {
+  pub name: String,
+  /// Пустая строка значит, что от имени ничего не осталось и файл получит
+  /// имя по умолчанию.
+  pub local: String,
+  pub adjustments: Vec<Adjustment>
+}
+
+pub fn preview(name: &str, rules: &LocalNameRules) -> LocalNamePreview {
+  let (local, adjustments) = apply(name, &rules.sanitized(), 0);
+  LocalNamePreview { name: name.to_string(), local, adjustments }
+}
+
+/// Один сегмент пути для имени файла или папки из канала.
+pub fn sanitize(name: &str, rules: &LocalNameRules) -> String {
+  apply(name, rules, 0).0
+}
+
+/// Имя локальной копии файла с учетом `file_id_suffix`.
+pub fn download_name(name: &str, file_id: &str, rules: &LocalNameRules) -> String {
+  let suffix = rules.file_id_suffix.then(|| {
+    let digest = hex::encode(Sha256::digest(file_id.as_bytes()));
+    format!(" [{}]", &digest[..FILE_ID_FRAGMENT])
+  });
+  let (mut safe, _) = apply(name, rules, suffix.as_ref().map_or(0, String::len));
+  if safe.is_empty() {
+    safe = DEFAULT_NAME.to_string();
+  }
+  match suffix {
+    Some(suffix) => {
+      let (stem, ext) = safe.split_at(extension_start(&safe).unwrap_or(safe.len()));
+      format!("{stem}{suffix}{ext}")
+    }
+    None => safe
+  }
+}
+
+fn extension_start(name: &str) -> Option<usize> {
+  name.rfind('.').filter(|&pos| pos > 0)
+}
+
+fn is_forbidden(ch: char, strict: bool) -> bool {
+  matches!(ch, '/' | '\\' | ':') || ch.is_control() || (strict && matches!(ch, '<' | '>' | '"' | '|' | '?' | '*'))
+}
+
+/// `reserve` — сколько места оставить под то, что добавят к имени потом.
+fn apply(name: &str, rules: &LocalNameRules, reserve: usize) -> (String, Vec<Adjustment>) {
+  let strict = rules.profile.strict();
+  let mut adjustments = Vec::new();
+  let replaced: String = name
+    .chars()
+    .map(|ch| if is_forbidden(ch, strict) { rules.replacement } else { ch })
+    .collect();
+  if replaced != name {
+    adjustments.push(Adjustment::ForbiddenChars);
+  }
+  let trimmed = replaced.trim();
+  // Не допускаем спец-сегменты пути, чтобы исключить выход за пределы cache/downloads.
+  if trimmed == "." || trimmed == ".." {
+    adjustments.push(Adjustment::RelativeSegment);
+    return (rules.replacement.to_string(), adjustments);
+  }
+  let mut out = trimmed.to_string();
+  if strict {
+    let without_dots = out.trim_end_matches(['.', ' ']);
+    if without_dots.len() != out.len() {
+      adjustments.push(Adjustment::TrailingDots);
+      out = without_dots.to_string();
+    }
+    let stem_len = out.find('.').unwrap_or(out.len());
+    let stem = out[..stem_len].trim_end().to_ascii_uppercase();
+    if WINDOWS_RESERVED.contains(&stem.as_str()) {
+      adjustments.push(Adjustment::ReservedName);
+      out.insert(stem_len, rules.replacement);
+    }
+  }
+  let measure: fn(&str) -> usize = if rules.profile.resolved() == NameProfile::Windows {
+    |s| s.encode_utf16().count()
+  } else {
+    str::len
+  };
+  let limit = MAX_NAME_LEN - reserve;
+  if measure(&out) > limit {
+    adjustments.push(Adjustment::Truncated);
+    out = truncate(&out, measure, limit);
+  }
+  (out, adjustments)
+}
+
+/// Укорачивает основу имени, сохраняя короткое расширение.
+fn truncate(name: &str, measure: fn(&str) -> usize, limit: usize) -> String {
+  let ext_start = extension_start(name).filter(|&pos| name.len() - pos <= MAX_KEPT_EXTENSION);
+  let (stem, ext) = name.split_at(ext_start.unwrap_or(name.len()));
+  let budget = limit - measure(ext);
+  let mut cut = String::new();
+  for ch in stem.chars() {
+    let mut buf = [0u8; 4];
+    if measure(&cut) + measure(ch.encode_utf8(&mut buf)) > budget {
+      break;
+    }
+    cut.push(ch);
+  }
+  format!("{}{ext}", cut.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn rules(profile: NameProfile) -> LocalNameRules {
+    LocalNameRules { profile, replacement: '_', file_id_suffix: false }
+  }
+
+  #[test]
+  fn windows_rules_cover_chars_reserved_names_and_trailing_dots() {
+    let win = rules(NameProfile::Windows);
+    assert_eq!(sanitize("a<b>c\"d|e?f*g.txt", &win), "a_b_c_d_e_f_g.txt");
+    assert_eq!(sanitize("con", &win), "con_");
+    assert_eq!(sanitize("NUL.tar.gz", &win), "NUL_.tar.gz");
+    assert_eq!(sanitize("COM1 .txt", &win), "COM1 _.txt");
+    assert_eq!(sanitize("console.txt", &win), "console.txt");
+    assert_eq!(sanitize("отчет. . ", &win), "отчет");
+    assert_eq!(sanitize("..", &win), "_");
+
+    let linux = rules(NameProfile::Linux);
+    assert_eq!(sanitize("a<b>?.txt", &linux), "a<b>?.txt");
+    assert_eq!(sanitize("CON", &linux), "CON");
+    assert_eq!(sanitize("a:b\\c", &linux), "a_b_c");
+  }
+
+  #[test]
+  fn long_names_keep_extension() {
+    let name = format!("{}.pdf", "я".repeat(200));
+    let linux = sanitize(&name, &rules(NameProfile::Linux));
+    assert!(linux.len() <= MAX_NAME_LEN);
+    assert!(linux.ends_with("я.pdf"));
+    // Даже с суффиксом совпадения имя укладывается в предел файловой системы.
+    let (stem, ext) = linux.split_at(extension_start(&linux).unwrap_or(linux.len()));
+    assert!(format!("{stem} (100){ext}").len() <= FS_NAME_LIMIT);
+    // В Windows предел в UTF-16: кириллица занимает одну единицу, а не два байта.
+    assert_eq!(sanitize(&name, &rules(NameProfile::Windows)), name);
+  }
+
+  #[test]
+  fn preview_lists_adjustments_and_rejects_bad_replacement() {
+    let bad = LocalNameRules { profile: NameProfile::Portable, replacement: '?', file_id_suffix: false };
+    let shown = preview("aux?.txt", &bad);
+    assert_eq!(shown.local, "aux_.txt");
+    assert_eq!(shown.adjustments, vec![Adjustment::ForbiddenChars]);
+    let shown = preview("aux.txt", &bad);
+    assert_eq!(shown.local, "aux_.txt");
+    assert_eq!(shown.adjustments, vec![Adjustment::ReservedName]);
+    assert!(preview("notes.txt", &bad).adjustments.is_empty());
+  }
+
+  #[test]
+  fn download_name_appends_file_id_fragment() {
+    let plain = rules(NameProfile::Linux);
+    assert_eq!(download_name("Отчет.pdf", "1f3a9c0e-77aa", &plain), "Отчет.pdf");
+    assert_eq!(download_name(" .. ", "1f3a9c0e-77aa", &plain), "_");
+    assert_eq!(download_name("", "1f3a9c0e-77aa", &plain), DEFAULT_NAME);
+
+    let tagged = LocalNameRules { file_id_suffix: true, ..plain };
+    assert_eq!(download_name("Отчет.pdf", "1f3a9c0e-77aa", &tagged), "Отчет [2328f7f7].pdf");
+    assert_eq!(download_name("README", "ab-cd", &tagged), "README [5db3d84c]");
+    let long = download_name(&format!("{}.pdf", "я".repeat(200)), "1f3a9c0e", &tagged);
+    assert!(long.len() <= MAX_NAME_LEN);
+    assert!(long.ends_with("я [a5f07c58].pdf"));
+    // ULID одного импорта различаются только хвостом.
+    assert_ne!(
+      download_name("IMG.jpg", "01HZX3Q8J6ZP2V1T0M4R5K7N9A", &tagged),
+      download_name("IMG.jpg", "01HZX3Q8J6ZP2V1T0M4R5K7N9B", &tagged)
+    );
+  }
+}
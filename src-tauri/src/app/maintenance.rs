@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use chrono::{Local, Timelike};
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+
+/// Окно обслуживания: фоновая работа, которую пользователь не запускал сам,
+/// идет только внутри него — загрузки папок «на потом», автосинхронизация,
+/// проверка хэшей скачанных файлов, пересчет размеров папок и прогрев после
+/// запуска. Загрузки, передачи и скачивание папок, начатые пользователем,
+/// окно не ограничивает. Время — минуты от полуночи по локальным часам;
+/// `start > end` означает окно через полночь (например, 23:00–07:00).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MaintenanceWindow {
+  #[serde(default)]
+  pub enabled: bool,
+  pub start_minute: u32,
+  pub end_minute: u32
+}
+
+impl Default for MaintenanceWindow {
+  fn default() -> Self {
+    Self { enabled: false, start_minute: 60, end_minute: 6 * 60 }
+  }
+}
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+/// Как часто фоновая задача проверяет, не открылось ли окно.
+const WINDOW_POLL: Duration = Duration::from_secs(60);
+
+static ACTIVE: Lazy<RwLock<MaintenanceWindow>> = Lazy::new(|| RwLock::new(MaintenanceWindow::default()));
+
+impl MaintenanceWindow {
+  pub fn sanitized(self) -> MaintenanceWindow {
+    MaintenanceWindow {
+      enabled: self.enabled,
+      start_minute: self.start_minute % MINUTES_PER_DAY,
+      end_minute: self.end_minute % MINUTES_PER_DAY
+    }
+  }
+
+  /// Выключенное окно разрешает фон всегда; пустое окно (start == end) — никогда.
+  pub fn allows(&self, minute_of_day: u32) -> bool {
+    if !self.enabled {
+      return true;
+    }
+    let (start, end) = (self.start_minute, self.end_minute);
+    if start <= end {
+      minute_of_day >= start && minute_of_day < end
+    } else {
+      minute_of_day >= start || minute_of_day < end
+    }
+  }
+
+  pub fn allows_now(&self) -> bool {
+    let now = Local::now();
+    #[cfg(feature = "dev")]
+    let now = now + chrono::Duration::seconds(crate::dev::clock_offset_secs());
+    self.allows(now.hour() * 60 + now.minute())
+  }
+}
+
+pub fn set_active(window: MaintenanceWindow) {
+  *ACTIVE.write() = window.sanitized();
+}
+
+pub fn active() -> MaintenanceWindow {
+  *ACTIVE.read()
+}
+
+/// Можно ли сейчас запускать фоновую работу.
+pub fn background_allowed() -> bool {
+  active().allows_now()
+}
+
+/// Ждет открытия окна; без окна возвращается сразу.
+pub async fn wait_for_window() {
+  while !background_allowed() {
+    tokio::time::sleep(WINDOW_POLL).await;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn window_wraps_over_midnight() {
+    let night = MaintenanceWindow { enabled: true, start_minute: 23 * 60, end_minute: 7 * 60 };
+    assert!(night.allows(23 * 60 + 30));
+    assert!(night.allows(3 * 60));
+    assert!(!night.allows(7 * 60));
+    assert!(!night.allows(12 * 60));
+
+    let day = MaintenanceWindow { enabled: true, start_minute: 9 * 60, end_minute: 18 * 60 };
+    assert!(day.allows(9 * 60));
+    assert!(!day.allows(18 * 60));
+
+    let off = MaintenanceWindow { enabled: false, ..night };
+    assert!(off.allows(12 * 60));
+  }
+}
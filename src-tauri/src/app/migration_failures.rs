@@ -0,0 +1,196 @@
+use chrono::Utc;
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+
+use crate::paths::Paths;
+use crate::telegram::{ChatId, TelegramService};
+
+use super::files;
+
+/// Файл, который не удалось скопировать при переносе канала хранения.
+/// Запись остается, пока файл не перенесут повтором или из локальной копии.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MigrationFailure {
+  pub file_id: String,
+  pub dir_id: String,
+  pub name: String,
+  pub size: i64,
+  pub old_chat_id: ChatId,
+  pub old_msg_id: i64,
+  pub reason: String,
+  pub attempts: i64,
+  pub created_at: i64,
+  pub has_local_copy: bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryOutcome {
+  /// Сообщение скопировано из старого канала.
+  Copied,
+  /// Старое сообщение недоступно, файл отправлен из локальной копии.
+  Reuploaded,
+  Failed
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RetryResult {
+  pub file_id: String,
+  pub outcome: RetryOutcome,
+  pub error: Option<String>
+}
+
+pub async fn record(
+  pool: &SqlitePool,
+  file_id: &str,
+  old_chat_id: ChatId,
+  old_msg_id: i64,
+  new_chat_id: ChatId,
+  reason: &str
+) -> anyhow::Result<()> {
+  sqlx::query(
+    "INSERT INTO migration_failures(file_id, old_chat_id, old_msg_id, new_chat_id, reason, attempts, created_at)
+     VALUES(?, ?, ?, ?, ?, 0, ?)
+     ON CONFLICT(file_id) DO UPDATE SET
+       old_chat_id = excluded.old_chat_id, old_msg_id = excluded.old_msg_id,
+       new_chat_id = excluded.new_chat_id, reason = excluded.reason"
+  )
+    .bind(file_id)
+    .bind(old_chat_id)
+    .bind(old_msg_id)
+    .bind(new_chat_id)
+    .bind(reason)
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+pub async fn list(pool: &SqlitePool, paths: &Paths) -> anyhow::Result<Vec<MigrationFailure>> {
+  let rows = sqlx::query(
+    "SELECT m.file_id, f.dir_id, f.name, f.size, m.old_chat_id, m.old_msg_id, m.reason, m.attempts, m.created_at
+     FROM migration_failures m JOIN files f ON f.id = m.file_id
+     ORDER BY f.name"
+  )
+    .fetch_all(pool)
+    .await?;
+  let mut out = Vec::with_capacity(rows.len());
+  for r in rows {
+    let file_id: String = r.get("file_id");
+    let has_local_copy = files::find_local_download_path(pool, paths, &file_id).await?.is_some();
+    out.push(MigrationFailure {
+      file_id,
+      dir_id: r.get("dir_id"),
+      name: r.get("name"),
+      size: r.get("size"),
+      old_chat_id: r.get("old_chat_id"),
+      old_msg_id: r.get("old_msg_id"),
+      reason: r.get("reason"),
+      attempts: r.get("attempts"),
+      created_at: r.get("created_at"),
+      has_local_copy
+    });
+  }
+  Ok(out)
+}
+
+/// Повторяет перенос файла: сначала копированием из старого канала, затем
+/// отправкой локальной копии. Неудача увеличивает счетчик попыток.
+pub async fn retry(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  paths: &Paths,
+  storage_chat_id: ChatId,
+  file_id: &str
+) -> anyhow::Result<RetryResult> {
+  let row = sqlx::query("SELECT old_chat_id, old_msg_id FROM migration_failures WHERE file_id = ?")
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?;
+  let Some(row) = row else {
+    return Err(anyhow::anyhow!("Файл не найден среди непереносенных"));
+  };
+  let old_chat_id: ChatId = row.get("old_chat_id");
+  let old_msg_id: i64 = row.get("old_msg_id");
+
+  let copy_error = match tg.copy_messages(old_chat_id, storage_chat_id, vec![old_msg_id]).await {
+    Ok(ids) => match ids.into_iter().next().flatten() {
+      Some(new_id) => {
+        sqlx::query("UPDATE files SET tg_chat_id = ?, tg_msg_id = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
+          .bind(storage_chat_id)
+          .bind(new_id)
+          .bind(file_id)
+          .execute(pool)
+          .await?;
+        resolve(pool, file_id).await?;
+        return Ok(RetryResult { file_id: file_id.to_string(), outcome: RetryOutcome::Copied, error: None });
+      }
+      None => "TDLib не скопировал сообщение".to_string()
+    },
+    Err(e) => e.to_string()
+  };
+
+  let error = match files::find_local_download_path(pool, paths, file_id).await? {
+    Some(local) => match files::reupload_file(pool, tg, storage_chat_id, file_id, &local).await {
+      Ok(_) => {
+        resolve(pool, file_id).await?;
+        return Ok(RetryResult { file_id: file_id.to_string(), outcome: RetryOutcome::Reuploaded, error: None });
+      }
+      Err(e) => format!("{copy_error}; локальная копия не отправилась: {e}")
+    },
+    None => format!("{copy_error}; локальной копии нет")
+  };
+  sqlx::query("UPDATE migration_failures SET attempts = attempts + 1, reason = ? WHERE file_id = ?")
+    .bind(&error)
+    .bind(file_id)
+    .execute(pool)
+    .await?;
+  tracing::warn!(event = "migration_failure_retry_failed", file_id = file_id, error = error.as_str(), "Повторный перенос файла не удался");
+  Ok(RetryResult { file_id: file_id.to_string(), outcome: RetryOutcome::Failed, error: Some(error) })
+}
+
+async fn resolve(pool: &SqlitePool, file_id: &str) -> anyhow::Result<()> {
+  sqlx::query("DELETE FROM migration_failures WHERE file_id = ?")
+    .bind(file_id)
+    .execute(pool)
+    .await?;
+  tracing::info!(event = "migration_failure_resolved", file_id = file_id, "Файл перенесен в канал хранения");
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[tokio::test]
+  async fn recorded_failures_are_listed_with_file_names() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let paths = Paths::from_base(tmp.path().to_path_buf());
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d', NULL, 'Док', NULL, 0)")
+      .execute(pool)
+      .await?;
+    sqlx::query(
+      "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at)
+       VALUES('f', 'd', 'a.pdf', 10, 'h', -1, 7, 0)"
+    )
+      .execute(pool)
+      .await?;
+
+    record(pool, "f", -1, 7, -2, "TDLib не скопировал сообщение").await?;
+    record(pool, "f", -1, 7, -3, "повтор").await?;
+    let items = list(pool, &paths).await?;
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].name, "a.pdf");
+    assert_eq!(items[0].reason, "повтор");
+    assert!(!items[0].has_local_copy);
+
+    sqlx::query("DELETE FROM files WHERE id = 'f'").execute(pool).await?;
+    assert!(list(pool, &paths).await?.is_empty());
+    Ok(())
+  }
+}
@@ -1,9 +1,53 @@
 pub mod models;
 pub mod sync;
 pub mod dirs;
+pub mod dir_download;
+pub mod dir_paths;
+pub mod dir_prefs;
 pub mod files;
+pub mod fulltext;
+pub mod file_actions;
+pub mod hash_upgrade;
+pub mod inbox;
+pub mod links;
+pub mod local_cache;
+pub mod local_copies;
+pub mod local_names;
+pub mod migration_failures;
+pub mod notes;
+pub mod partial_downloads;
+pub mod pending_uploads;
 pub mod indexer;
 pub mod reconcile;
+pub mod reseed;
 pub mod backup;
+pub mod bootstrap;
+pub mod broken;
+pub mod clipboard;
+pub mod chat_resync;
+pub mod search_index;
+pub mod summary;
+pub mod system_dirs;
+pub mod unindexed;
+pub mod import_rules;
+pub mod source_channels;
+pub mod storage_channels;
+pub mod storage_stats;
+pub mod streaming;
+pub mod ignore_list;
+pub mod thumbnails;
+pub mod transcripts;
+pub mod collections;
+pub mod auto_sort;
+pub mod auto_sync;
+pub mod download_queue;
+pub mod downloads_cache;
+pub mod verify;
+pub mod maintenance;
+pub mod open_guard;
+pub mod preview;
+pub mod virus_scan;
+pub mod vault;
+pub mod virtual_folders;
 
 pub use models::*;
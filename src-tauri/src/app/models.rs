@@ -4,5 +4,6 @@ pub struct DirNode {
   pub name: String,
   pub parent_id: Option<String>,
   pub is_broken: bool,
+  pub link_target_id: Option<String>,
   pub children: Vec<DirNode>
 }
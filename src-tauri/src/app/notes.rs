@@ -0,0 +1,312 @@
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+use ulid::Ulid;
+
+use crate::paths::{Paths, Scratch};
+use crate::telegram::{ChatId, TelegramService};
+
+use super::{files, vault};
+use super::transcripts::fts_query;
+
+/// Заметки — небольшие текстовые файлы, их целиком держим в базе.
+pub const MAX_NOTE_BYTES: usize = 1024 * 1024;
+
+const NOTE_EXT: &str = ".md";
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Note {
+  pub file_id: String,
+  pub dir_id: String,
+  pub name: String,
+  pub body: String,
+  /// Хэш содержимого, с которым открыт редактор; передается обратно при сохранении.
+  pub hash: String,
+  pub updated_at: i64
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct NoteSaved {
+  pub note: Note,
+  /// Заметку успели изменить в другом месте: та версия сохранена в истории.
+  pub conflict: bool
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct NoteHit {
+  pub file_id: String,
+  pub name: String,
+  pub snippet: String
+}
+
+/// Причина записи в `file_versions` при правке заметки.
+fn version_reason(conflict: bool) -> &'static str {
+  if conflict { "note_conflict" } else { "note_edit" }
+}
+
+pub fn is_note_name(name: &str) -> bool {
+  name.to_lowercase().ends_with(NOTE_EXT)
+}
+
+fn note_file_name(title: &str) -> anyhow::Result<String> {
+  let title = title.trim();
+  let title = if is_note_name(title) { &title[..title.len() - NOTE_EXT.len()] } else { title };
+  let safe = files::sanitize_component(title);
+  if safe.is_empty() || safe == "_" {
+    return Err(anyhow::anyhow!("Укажи название заметки"));
+  }
+  Ok(format!("{safe}{NOTE_EXT}"))
+}
+
+fn check_body(body: &str) -> anyhow::Result<()> {
+  if body.len() > MAX_NOTE_BYTES {
+    return Err(anyhow::anyhow!("Заметка слишком большая (больше {} КБ)", MAX_NOTE_BYTES / 1024));
+  }
+  Ok(())
+}
+
+/// Пишет текст во временный файл с нужным именем: имя файла уходит в Telegram.
+fn write_temp(paths: &Paths, name: &str, body: &str) -> anyhow::Result<(PathBuf, PathBuf)> {
+  let tmp_dir = paths.layout().scratch_dir(Scratch::Notes).join(Ulid::new().to_string());
+  std::fs::create_dir_all(&tmp_dir)?;
+  let path = tmp_dir.join(name);
+  std::fs::write(&path, body)?;
+  Ok((tmp_dir, path))
+}
+
+async fn store_body(pool: &SqlitePool, file_id: &str, body: &str) -> anyhow::Result<i64> {
+  // Индекс FTS обновляют триггеры таблицы.
+  let now = Utc::now().timestamp();
+  sqlx::query(
+    "INSERT INTO notes(file_id, body, updated_at) VALUES(?, ?, ?)
+     ON CONFLICT(file_id) DO UPDATE SET body=excluded.body, updated_at=excluded.updated_at"
+  )
+    .bind(file_id)
+    .bind(body)
+    .bind(now)
+    .execute(pool)
+    .await?;
+  Ok(now)
+}
+
+async fn load_note(pool: &SqlitePool, file_id: &str) -> anyhow::Result<Option<Note>> {
+  let row = sqlx::query(
+    "SELECT f.id, f.dir_id, f.name, f.hash, n.body, n.updated_at
+     FROM files f JOIN notes n ON n.file_id = f.id
+     WHERE f.id = ?"
+  )
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?;
+  Ok(row.map(|r| Note {
+    file_id: r.get("id"),
+    dir_id: r.get("dir_id"),
+    name: r.get("name"),
+    body: r.get("body"),
+    hash: r.get("hash"),
+    updated_at: r.get("updated_at")
+  }))
+}
+
+pub async fn create_note(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  paths: &Paths,
+  storage_chat_id: ChatId,
+  dir_id: &str,
+  title: &str,
+  body: &str
+) -> anyhow::Result<Note> {
+  check_body(body)?;
+  let name = note_file_name(title)?;
+  let (tmp_dir, path) = write_temp(paths, &name, body)?;
+  let uploaded = files::upload_file(pool, tg, storage_chat_id, dir_id, &path).await;
+  let _ = std::fs::remove_dir_all(&tmp_dir);
+  let file_id = uploaded?;
+  store_body(pool, &file_id, body).await?;
+  tracing::info!(event = "note_created", file_id = file_id.as_str(), "Заметка создана");
+  load_note(pool, &file_id)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("Заметка не найдена"))
+}
+
+/// Текст заметки. Если заметка пришла из канала и еще не открывалась,
+/// файл скачивается и его текст сохраняется в базе.
+pub async fn get_note(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  paths: &Paths,
+  file_id: &str
+) -> anyhow::Result<Note> {
+  if let Some(note) = load_note(pool, file_id).await? {
+    return Ok(note);
+  }
+  let row = sqlx::query("SELECT name, size, tg_chat_id, tg_msg_id, enc_key_id FROM files WHERE id = ?")
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?;
+  let Some(row) = row else {
+    return Err(anyhow::anyhow!("Файл не найден"));
+  };
+  let name: String = row.get("name");
+  if !is_note_name(&name) {
+    return Err(anyhow::anyhow!("Это не заметка"));
+  }
+  if row.get::<i64, _>("size") as usize > MAX_NOTE_BYTES {
+    return Err(anyhow::anyhow!("Заметка слишком большая для редактора"));
+  }
+  let tmp_dir = paths.layout().scratch_dir(Scratch::Notes).join(Ulid::new().to_string());
+  std::fs::create_dir_all(&tmp_dir)?;
+  let downloaded = tg
+    .download_message_file(row.get("tg_chat_id"), row.get("tg_msg_id"), tmp_dir.join(file_id))
+    .await;
+  let enc_key_id = row.try_get::<String, _>("enc_key_id").ok();
+  let bytes = match downloaded {
+    Ok(p) => match enc_key_id.as_deref() {
+      Some(key_id) => vault::decrypt_in_place(key_id, &p).await.and_then(|_| std::fs::read(&p).map_err(anyhow::Error::from)),
+      None => std::fs::read(p).map_err(anyhow::Error::from)
+    },
+    Err(e) => Err(e.into())
+  };
+  let _ = std::fs::remove_dir_all(&tmp_dir);
+  let body = String::from_utf8(bytes?).map_err(|_| anyhow::anyhow!("Заметка не в кодировке UTF-8"))?;
+  store_body(pool, file_id, &body).await?;
+  load_note(pool, file_id)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("Заметка не найдена"))
+}
+
+/// Сохраняет новый текст как новую версию файла. `base_hash` — хэш, с которым
+/// открывался редактор: если с тех пор заметку изменили, правка все равно
+/// сохраняется, а перезаписанный вариант остается в истории версий как конфликт.
+pub async fn update_note(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  paths: &Paths,
+  storage_chat_id: ChatId,
+  file_id: &str,
+  body: &str,
+  base_hash: Option<&str>
+) -> anyhow::Result<NoteSaved> {
+  check_body(body)?;
+  let current = get_note(pool, tg, paths, file_id).await?;
+  let conflict = base_hash.is_some_and(|h| h != current.hash);
+  if current.body == body {
+    return Ok(NoteSaved { note: current, conflict: false });
+  }
+
+  let (tmp_dir, path) = write_temp(paths, &current.name, body)?;
+  let replaced = replace_with_version(pool, tg, storage_chat_id, &current, &path, conflict).await;
+  let _ = std::fs::remove_dir_all(&tmp_dir);
+  replaced?;
+  store_body(pool, file_id, body).await?;
+  if conflict {
+    tracing::warn!(event = "note_conflict", file_id = file_id, "Заметка изменена в другом месте, прежний текст сохранен в истории");
+  }
+  let note = load_note(pool, file_id)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("Заметка не найдена"))?;
+  Ok(NoteSaved { note, conflict })
+}
+
+async fn replace_with_version(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  storage_chat_id: ChatId,
+  current: &Note,
+  path: &Path,
+  conflict: bool
+) -> anyhow::Result<()> {
+  let previous = files::replace_file_content(pool, tg, storage_chat_id, &current.file_id, path).await?;
+  sqlx::query(
+    "INSERT INTO file_versions(id, file_id, tg_chat_id, tg_msg_id, hash, size, reason, created_at)
+     VALUES(?, ?, ?, ?, ?, ?, ?, ?)"
+  )
+    .bind(Ulid::new().to_string())
+    .bind(&current.file_id)
+    .bind(previous.chat_id)
+    .bind(previous.message_id)
+    .bind(&previous.hash)
+    .bind(previous.size)
+    .bind(version_reason(conflict))
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await?;
+  // Убираем fsmeta со старого сообщения, иначе синхронизация вернет запись на него.
+  if let Err(e) = tg
+    .edit_message_caption(previous.chat_id, previous.message_id, format!("Прежняя версия файла {}", current.name))
+    .await
+  {
+    tracing::warn!(event = "file_version_caption_failed", file_id = current.file_id.as_str(), error = %e, "Не удалось обновить подпись прежней версии");
+  }
+  Ok(())
+}
+
+/// Полнотекстовый поиск по тексту заметок.
+pub async fn search_notes(pool: &SqlitePool, query: &str, limit: i64) -> anyhow::Result<Vec<NoteHit>> {
+  let Some(fts) = fts_query(query) else {
+    return Ok(Vec::new());
+  };
+  let rows = sqlx::query(
+    "SELECT n.file_id, f.name, snippet(notes_fts, 0, '[', ']', '…', 12) AS snippet
+     FROM notes_fts
+     JOIN notes n ON n.rowid = notes_fts.rowid
+     JOIN files f ON f.id = n.file_id
+     WHERE notes_fts MATCH ?
+     ORDER BY rank
+     LIMIT ?"
+  )
+    .bind(&fts)
+    .bind(limit.clamp(1, 200))
+    .fetch_all(pool)
+    .await?;
+  Ok(rows.into_iter().map(|r| NoteHit {
+    file_id: r.get("file_id"),
+    name: r.get("name"),
+    snippet: r.get("snippet")
+  }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[test]
+  fn note_names_get_markdown_extension() {
+    assert_eq!(note_file_name("  Список покупок ").unwrap(), "Список покупок.md");
+    assert_eq!(note_file_name("plan.MD").unwrap(), "plan.md");
+    assert_eq!(note_file_name("a/b").unwrap(), "a_b.md");
+    assert!(note_file_name("   ").is_err());
+  }
+
+  #[tokio::test]
+  async fn stored_note_is_searchable_and_replaced() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d', NULL, 'Заметки', NULL, 0)")
+      .execute(pool)
+      .await?;
+    sqlx::query(
+      "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at)
+       VALUES('n', 'd', 'plan.md', 1, 'h', 1, 1, 0)"
+    )
+      .execute(pool)
+      .await?;
+
+    store_body(pool, "n", "купить молоко").await?;
+    let hits = search_notes(pool, "молок", 10).await?;
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].name, "plan.md");
+
+    store_body(pool, "n", "позвонить маме").await?;
+    assert!(search_notes(pool, "молоко", 10).await?.is_empty());
+    assert_eq!(load_note(pool, "n").await?.map(|n| n.body).as_deref(), Some("позвонить маме"));
+    Ok(())
+  }
+}
@@ -0,0 +1,96 @@
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+
+/// Расширения, которые при открытии запускают код: исполняемые файлы,
+/// скрипты, установщики и ярлыки.
+const RISKY_EXTENSIONS: &[&str] = &[
+  "exe", "com", "scr", "pif", "msi", "msix", "msp", "bat", "cmd", "ps1", "psm1", "vbs", "vbe", "js", "jse",
+  "wsf", "wsh", "hta", "cpl", "lnk", "reg", "jar", "sh", "bash", "zsh", "command", "run", "bin",
+  "app", "pkg", "dmg", "appimage", "deb", "rpm", "apk", "py", "pl", "rb", "desktop"
+];
+
+/// Защита `file_open` от случайного запуска программ из канала. Файлы с
+/// опасным расширением открываются только после подтверждения.
+/// `deny` добавляет расширения к встроенному списку, `allow` убирает.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct OpenGuard {
+  pub enabled: bool,
+  #[serde(default)]
+  pub allow: Vec<String>,
+  #[serde(default)]
+  pub deny: Vec<String>
+}
+
+impl Default for OpenGuard {
+  fn default() -> Self {
+    Self { enabled: true, allow: Vec::new(), deny: Vec::new() }
+  }
+}
+
+static ACTIVE: Lazy<RwLock<OpenGuard>> = Lazy::new(|| RwLock::new(OpenGuard::default()));
+
+impl OpenGuard {
+  /// Расширения в нижнем регистре без точки, без пустых и повторов.
+  pub fn normalized(self) -> OpenGuard {
+    let clean = |list: Vec<String>| {
+      let mut out: Vec<String> = list
+        .into_iter()
+        .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+        .filter(|e| !e.is_empty())
+        .collect();
+      out.sort();
+      out.dedup();
+      out
+    };
+    OpenGuard { enabled: self.enabled, allow: clean(self.allow), deny: clean(self.deny) }
+  }
+
+  pub fn needs_confirmation(&self, file_name: &str) -> bool {
+    if !self.enabled {
+      return false;
+    }
+    let Some(ext) = extension(file_name) else {
+      return false;
+    };
+    if self.allow.contains(&ext) {
+      return false;
+    }
+    RISKY_EXTENSIONS.contains(&ext.as_str()) || self.deny.contains(&ext)
+  }
+}
+
+pub fn extension(file_name: &str) -> Option<String> {
+  std::path::Path::new(file_name)
+    .extension()
+    .and_then(|e| e.to_str())
+    .map(|e| e.to_lowercase())
+}
+
+pub fn set_active(guard: OpenGuard) {
+  *ACTIVE.write() = guard.normalized();
+}
+
+pub fn active() -> OpenGuard {
+  ACTIVE.read().clone()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn risky_extensions_need_confirmation_unless_allowed() {
+    let guard = OpenGuard::default();
+    assert!(guard.needs_confirmation("setup.EXE"));
+    assert!(guard.needs_confirmation("install.sh"));
+    assert!(!guard.needs_confirmation("report.pdf"));
+    assert!(!guard.needs_confirmation("README"));
+
+    let custom = OpenGuard { enabled: true, allow: vec![".PY".into()], deny: vec!["docm".into()] }.normalized();
+    assert!(!custom.needs_confirmation("tool.py"));
+    assert!(custom.needs_confirmation("macro.docm"));
+
+    let off = OpenGuard { enabled: false, ..OpenGuard::default() };
+    assert!(!off.needs_confirmation("setup.exe"));
+  }
+}
@@ -0,0 +1,158 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use chrono::Utc;
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+
+use crate::telegram::{DownloadProgress, ProgressSink};
+
+/// Скачивание, которое началось и не закончилось: оборвалось с ошибкой или
+/// приложение закрыли. Недокачанное начало лежит в кеше TDLib, и повторное
+/// скачивание продолжается с него.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PartialDownload {
+  pub file_id: String,
+  pub name: String,
+  pub dir_id: String,
+  pub downloaded: i64,
+  pub total: i64,
+  pub last_error: Option<String>,
+  pub started_at: i64,
+  pub updated_at: i64
+}
+
+/// Запоминает, сколько уже скачано, и передает ход дальше.
+pub struct Tracker {
+  downloaded: Arc<AtomicU64>,
+  outer: Option<ProgressSink>
+}
+
+impl Tracker {
+  pub fn new(outer: Option<ProgressSink>) -> Tracker {
+    Tracker { downloaded: Arc::new(AtomicU64::new(0)), outer }
+  }
+
+  pub fn sink(&self) -> ProgressSink {
+    let downloaded = self.downloaded.clone();
+    let outer = self.outer.clone();
+    Arc::new(move |p: DownloadProgress| {
+      downloaded.fetch_max(p.downloaded, Ordering::Relaxed);
+      if let Some(outer) = &outer {
+        outer(p);
+      }
+    })
+  }
+
+  pub fn downloaded(&self) -> u64 {
+    self.downloaded.load(Ordering::Relaxed)
+  }
+}
+
+/// Отмечает начало скачивания. Повторная попытка сохраняет время первой.
+pub async fn begin(pool: &SqlitePool, file_id: &str, total: i64) -> anyhow::Result<()> {
+  let now = Utc::now().timestamp();
+  sqlx::query(
+    "INSERT INTO partial_downloads(file_id, downloaded, total, last_error, started_at, updated_at)
+     VALUES(?, 0, ?, NULL, ?, ?)
+     ON CONFLICT(file_id) DO UPDATE SET total=excluded.total, last_error=NULL, updated_at=excluded.updated_at"
+  )
+    .bind(file_id)
+    .bind(total)
+    .bind(now)
+    .bind(now)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+pub async fn interrupted(pool: &SqlitePool, file_id: &str, downloaded: u64, error: &str) -> anyhow::Result<()> {
+  sqlx::query(
+    "UPDATE partial_downloads SET downloaded = MAX(downloaded, ?), last_error = ?, updated_at = ? WHERE file_id = ?"
+  )
+    .bind(downloaded as i64)
+    .bind(error)
+    .bind(Utc::now().timestamp())
+    .bind(file_id)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+pub async fn finish(pool: &SqlitePool, file_id: &str) -> anyhow::Result<()> {
+  sqlx::query("DELETE FROM partial_downloads WHERE file_id = ?")
+    .bind(file_id)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+pub async fn exists(pool: &SqlitePool, file_id: &str) -> anyhow::Result<bool> {
+  Ok(
+    sqlx::query("SELECT 1 FROM partial_downloads WHERE file_id = ?")
+      .bind(file_id)
+      .fetch_optional(pool)
+      .await?
+      .is_some()
+  )
+}
+
+pub async fn list(pool: &SqlitePool) -> anyhow::Result<Vec<PartialDownload>> {
+  let rows = sqlx::query(
+    "SELECT p.file_id, f.name, f.dir_id, p.downloaded, p.total, p.last_error, p.started_at, p.updated_at
+     FROM partial_downloads p JOIN files f ON f.id = p.file_id
+     ORDER BY p.updated_at DESC"
+  )
+    .fetch_all(pool)
+    .await?;
+  Ok(rows.into_iter().map(|r| PartialDownload {
+    file_id: r.get("file_id"),
+    name: r.get("name"),
+    dir_id: r.get("dir_id"),
+    downloaded: r.get("downloaded"),
+    total: r.get("total"),
+    last_error: r.try_get("last_error").ok(),
+    started_at: r.get("started_at"),
+    updated_at: r.get("updated_at")
+  }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[tokio::test]
+  async fn interrupted_download_is_listed_until_finished() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d', NULL, 'd', NULL, 0)")
+      .execute(pool)
+      .await?;
+    sqlx::query(
+      "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at) VALUES('f', 'd', 'big.iso', 100, 'h', 1, 1, 0)"
+    )
+      .execute(pool)
+      .await?;
+
+    let tracker = Tracker::new(None);
+    begin(pool, "f", 100).await?;
+    (tracker.sink())(DownloadProgress { downloaded: 40, total: Some(100) });
+    (tracker.sink())(DownloadProgress { downloaded: 30, total: Some(100) });
+    interrupted(pool, "f", tracker.downloaded(), "network").await?;
+
+    let items = list(pool).await?;
+    assert_eq!(items.len(), 1);
+    assert_eq!((items[0].downloaded, items[0].last_error.as_deref()), (40, Some("network")));
+
+    // Повторная попытка не сбрасывает уже скачанное.
+    begin(pool, "f", 100).await?;
+    assert_eq!(list(pool).await?[0].downloaded, 40);
+    finish(pool, "f").await?;
+    assert!(!exists(pool, "f").await?);
+    Ok(())
+  }
+}
@@ -0,0 +1,174 @@
+use chrono::Utc;
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+
+use crate::telegram::ChatId;
+
+/// Сколько ждем позднего подтверждения, прежде чем разрешить отправить файл заново.
+pub const PENDING_TTL_SECS: i64 = 6 * 60 * 60;
+
+/// Файл, который пользователь загружает в папку.
+#[derive(Debug, Clone, Copy)]
+pub struct UploadKey<'a> {
+  pub dir_id: &'a str,
+  pub name: &'a str,
+  pub size: i64,
+  pub hash: &'a str
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Retry {
+  /// Прежняя отправка все-таки завершилась, запись уже в базе.
+  Done(String),
+  /// Прежняя отправка еще идет, повторять нельзя.
+  InFlight,
+  /// Незавершенных отправок нет, можно отправлять.
+  Fresh
+}
+
+/// Решает, что делать с повторной загрузкой того же файла в ту же папку.
+pub async fn check_retry(pool: &SqlitePool, key: UploadKey<'_>, now: i64) -> anyhow::Result<Retry> {
+  let rows = sqlx::query(
+    "SELECT p.file_id, p.created_at, f.id AS existing
+     FROM pending_uploads p LEFT JOIN files f ON f.id = p.file_id
+     WHERE p.dir_id = ? AND p.name = ? AND p.size = ? AND p.hash = ?
+     ORDER BY p.created_at DESC"
+  )
+    .bind(key.dir_id)
+    .bind(key.name)
+    .bind(key.size)
+    .bind(key.hash)
+    .fetch_all(pool)
+    .await?;
+  let mut in_flight = false;
+  for row in rows {
+    let file_id: String = row.get("file_id");
+    if row.try_get::<String, _>("existing").is_ok() {
+      finish(pool, &file_id).await?;
+      return Ok(Retry::Done(file_id));
+    }
+    if now - row.get::<i64, _>("created_at") < PENDING_TTL_SECS {
+      in_flight = true;
+    } else {
+      finish(pool, &file_id).await?;
+    }
+  }
+  Ok(if in_flight { Retry::InFlight } else { Retry::Fresh })
+}
+
+/// Запоминает отправку до вызова TDLib: `file_id` попадает в подпись как f=
+/// и по нему поздний результат сопоставляется с записью.
+pub async fn begin(pool: &SqlitePool, file_id: &str, key: UploadKey<'_>) -> anyhow::Result<()> {
+  sqlx::query(
+    "INSERT INTO pending_uploads(file_id, dir_id, name, size, hash, created_at) VALUES(?, ?, ?, ?, ?, ?)
+     ON CONFLICT(file_id) DO NOTHING"
+  )
+    .bind(file_id)
+    .bind(key.dir_id)
+    .bind(key.name)
+    .bind(key.size)
+    .bind(key.hash)
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+pub async fn finish(pool: &SqlitePool, file_id: &str) -> anyhow::Result<()> {
+  sqlx::query("DELETE FROM pending_uploads WHERE file_id = ?")
+    .bind(file_id)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+/// Снимает отметку, если загрузку прервали на середине (отмена или пауза в
+/// очереди передач): иначе повтор того же файла считался бы еще идущим.
+pub struct PendingGuard {
+  pool: SqlitePool,
+  file_id: String,
+  armed: bool
+}
+
+impl PendingGuard {
+  pub fn new(pool: &SqlitePool, file_id: &str) -> PendingGuard {
+    PendingGuard { pool: pool.clone(), file_id: file_id.to_string(), armed: true }
+  }
+
+  /// Отметкой дальше распоряжается вызывающий.
+  pub fn disarm(mut self) {
+    self.armed = false;
+  }
+}
+
+impl Drop for PendingGuard {
+  fn drop(&mut self) {
+    if !self.armed {
+      return;
+    }
+    let pool = self.pool.clone();
+    let file_id = std::mem::take(&mut self.file_id);
+    tauri::async_runtime::spawn(async move {
+      if let Err(e) = finish(&pool, &file_id).await {
+        tracing::warn!(event = "pending_upload_cleanup_failed", file_id = file_id.as_str(), error = %e, "Не удалось снять отметку прерванной загрузки");
+      }
+    });
+  }
+}
+
+/// TDLib окончательно не смог отправить файл. Запись, проиндексированная
+/// по временному сообщению, удаляется вместе с отметкой.
+pub async fn fail(pool: &SqlitePool, file_id: &str, chat_id: ChatId, temp_message_id: i64) -> anyhow::Result<()> {
+  let removed = sqlx::query("DELETE FROM files WHERE id = ? AND tg_chat_id = ? AND tg_msg_id = ?")
+    .bind(file_id)
+    .bind(chat_id)
+    .bind(temp_message_id)
+    .execute(pool)
+    .await?
+    .rows_affected();
+  let pending = sqlx::query("DELETE FROM pending_uploads WHERE file_id = ?")
+    .bind(file_id)
+    .execute(pool)
+    .await?
+    .rows_affected();
+  if removed > 0 || pending > 0 {
+    tracing::warn!(event = "pending_upload_failed", file_id = file_id, "TDLib не смог отправить файл после таймаута");
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[tokio::test]
+  async fn retry_waits_for_pending_send_and_reuses_late_result() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d', NULL, 'Папка', NULL, 0)")
+      .execute(pool)
+      .await?;
+    let key = UploadKey { dir_id: "d", name: "a.txt", size: 3, hash: "abcd1234" };
+    let now = Utc::now().timestamp();
+
+    assert_eq!(check_retry(pool, key, now).await?, Retry::Fresh);
+    begin(pool, "f1", key).await?;
+    assert_eq!(check_retry(pool, key, now).await?, Retry::InFlight);
+    assert_eq!(check_retry(pool, key, now + PENDING_TTL_SECS + 1).await?, Retry::Fresh);
+
+    begin(pool, "f2", key).await?;
+    sqlx::query(
+      "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at)
+       VALUES('f2', 'd', 'a.txt', 3, 'abcd1234', -100, 42, 0)"
+    )
+      .execute(pool)
+      .await?;
+    assert_eq!(check_retry(pool, key, now).await?, Retry::Done("f2".into()));
+    assert_eq!(check_retry(pool, key, now).await?, Retry::Fresh);
+    Ok(())
+  }
+}
@@ -0,0 +1,164 @@
+use std::io::Read;
+use std::path::Path;
+
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+
+use crate::paths::Paths;
+use crate::telegram::{ChatId, TelegramService};
+
+use crate::app::files;
+
+pub const DEFAULT_PREVIEW_BYTES: u64 = 64 * 1024;
+const MIN_PREVIEW_BYTES: u64 = 1024;
+const MAX_PREVIEW_BYTES: u64 = 1024 * 1024;
+
+/// Начало текстового файла для просмотра внутри приложения.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TextPreview {
+  pub text: String,
+  /// `utf-8`, `utf-16le`, `utf-16be` или `windows-1251`.
+  pub encoding: &'static str,
+  /// Файл длиннее показанного.
+  pub truncated: bool,
+  pub size: i64
+}
+
+/// Первые `max_bytes` файла как текст. Для файла из нескольких частей без
+/// локальной копии скачивается только первая часть; остальные файлы
+/// скачиваются целиком, как при открытии.
+pub async fn preview_text(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  paths: &Paths,
+  storage_chat_id: ChatId,
+  file_id: &str,
+  max_bytes: u64
+) -> anyhow::Result<TextPreview> {
+  let row = sqlx::query("SELECT size, part_count, enc_key_id FROM files WHERE id = ?")
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("Файл не найден"))?;
+  let size: i64 = row.get("size");
+  let part_count: i64 = row.get("part_count");
+  let encrypted = row.try_get::<String,_>("enc_key_id").is_ok();
+  let max_bytes = max_bytes.clamp(MIN_PREVIEW_BYTES, MAX_PREVIEW_BYTES);
+
+  let mut bytes = if let Some(local) = files::find_local_download_path(pool, paths, file_id).await? {
+    read_head(&local, max_bytes)?
+  } else if part_count > 0 && !encrypted {
+    first_part_head(pool, tg, paths, file_id, max_bytes).await?
+  } else {
+    let local = files::download_file(pool, tg, paths, storage_chat_id, file_id, false, None).await?;
+    read_head(&local, max_bytes)?
+  };
+  let truncated = bytes.len() as u64 > max_bytes;
+  bytes.truncate(max_bytes as usize);
+  let (text, encoding) = decode(&bytes, truncated)?;
+  Ok(TextPreview { text, encoding, truncated, size })
+}
+
+/// Скачивает во временный каталог только первую часть файла.
+async fn first_part_head(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  paths: &Paths,
+  file_id: &str,
+  max_bytes: u64
+) -> anyhow::Result<Vec<u8>> {
+  let part = sqlx::query("SELECT tg_chat_id, tg_msg_id FROM file_parts WHERE file_id = ? ORDER BY part_index LIMIT 1")
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("Части файла не найдены"))?;
+  let previews = paths.layout().previews_dir();
+  std::fs::create_dir_all(&previews)?;
+  let tmp = tempfile::tempdir_in(&previews)?;
+  let path = tg
+    .download_message_file(part.get("tg_chat_id"), part.get("tg_msg_id"), tmp.path().join("part"))
+    .await?;
+  read_head(&path, max_bytes)
+}
+
+/// Читает на байт больше `max_bytes`, чтобы понять, обрезан ли текст.
+fn read_head(path: &Path, max_bytes: u64) -> anyhow::Result<Vec<u8>> {
+  let mut out = Vec::new();
+  std::fs::File::open(path)?.take(max_bytes + 1).read_to_end(&mut out)?;
+  Ok(out)
+}
+
+/// Определяет кодировку по BOM, затем проверяет UTF-8. Остальное без нулевых
+/// байтов считается windows-1251. Обрезанный на середине символ отбрасывается.
+fn decode(bytes: &[u8], truncated: bool) -> anyhow::Result<(String, &'static str)> {
+  if let Some(rest) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+    return Ok((utf8_lossy_tail(rest, truncated), "utf-8"));
+  }
+  if let Some(rest) = bytes.strip_prefix(&[0xFF, 0xFE]) {
+    return Ok((utf16(rest, u16::from_le_bytes), "utf-16le"));
+  }
+  if let Some(rest) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+    return Ok((utf16(rest, u16::from_be_bytes), "utf-16be"));
+  }
+  if bytes.contains(&0) {
+    return Err(anyhow::anyhow!("Файл не похож на текст"));
+  }
+  match std::str::from_utf8(bytes) {
+    Ok(text) => Ok((text.to_string(), "utf-8")),
+    Err(e) if truncated && e.error_len().is_none() => Ok((utf8_lossy_tail(bytes, truncated), "utf-8")),
+    Err(_) => Ok((bytes.iter().map(|&b| cp1251(b)).collect(), "windows-1251"))
+  }
+}
+
+fn utf8_lossy_tail(bytes: &[u8], truncated: bool) -> String {
+  let end = match std::str::from_utf8(bytes) {
+    Err(e) if truncated && e.error_len().is_none() => e.valid_up_to(),
+    _ => bytes.len()
+  };
+  String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> String {
+  let units = bytes.chunks_exact(2).map(|c| unit([c[0], c[1]]));
+  char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect()
+}
+
+/// Верхняя половина windows-1251 до кириллических букв.
+const CP1251_HIGH: [u16; 64] = [
+  0x0402, 0x0403, 0x201A, 0x0453, 0x201E, 0x2026, 0x2020, 0x2021, 0x20AC, 0x2030, 0x0409, 0x2039, 0x040A, 0x040C, 0x040B, 0x040F,
+  0x0452, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014, 0xFFFD, 0x2122, 0x0459, 0x203A, 0x045A, 0x045C, 0x045B, 0x045F,
+  0x00A0, 0x040E, 0x045E, 0x0408, 0x00A4, 0x0490, 0x00A6, 0x00A7, 0x0401, 0x00A9, 0x0404, 0x00AB, 0x00AC, 0x00AD, 0x00AE, 0x0407,
+  0x00B0, 0x00B1, 0x0406, 0x0456, 0x0491, 0x00B5, 0x00B6, 0x00B7, 0x0451, 0x2116, 0x0454, 0x00BB, 0x0458, 0x0405, 0x0455, 0x0457
+];
+
+fn cp1251(b: u8) -> char {
+  let code = match b {
+    0x00..=0x7F => u32::from(b),
+    0x80..=0xBF => u32::from(CP1251_HIGH[usize::from(b - 0x80)]),
+    _ => 0x0410 + u32::from(b - 0xC0)
+  };
+  char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn detects_common_encodings() -> anyhow::Result<()> {
+    assert_eq!(decode("привет".as_bytes(), false)?, ("привет".to_string(), "utf-8"));
+
+    // Обрезка посреди двухбайтовой «т» не портит текст.
+    let cut = &"привет".as_bytes()[..11];
+    assert_eq!(decode(cut, true)?, ("приве".to_string(), "utf-8"));
+
+    let utf16: Vec<u8> = [0xFF, 0xFE].into_iter().chain("Ёж".encode_utf16().flat_map(u16::to_le_bytes)).collect();
+    assert_eq!(decode(&utf16, false)?, ("Ёж".to_string(), "utf-16le"));
+
+    let cp = [0xCF, 0xF0, 0xE8, 0xE2, 0xE5, 0xF2, 0x20, 0xA8, 0xB9];
+    assert_eq!(decode(&cp, false)?, ("Привет Ё№".to_string(), "windows-1251"));
+
+    assert!(decode(&[0x89, b'P', b'N', b'G', 0, 0], false).is_err());
+    Ok(())
+  }
+}
@@ -5,7 +5,8 @@ use crate::sqlx::{self, Row};
 use sqlx_sqlite::SqlitePool;
 
 use crate::telegram::{TelegramService, ChatId, HistoryMessage};
-use crate::app::{indexer, sync};
+use crate::app::{indexer, storage_channels, sync};
+use crate::app::broken::{self, BrokenReason};
 
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct ReconcileOutcome {
@@ -66,16 +67,17 @@ pub async fn reconcile_recent(
     }
   }
 
+  let present: HashSet<i64> = messages.iter().map(|m| m.id).collect();
   let min_id = messages.iter().map(|m| m.id).min().unwrap_or(0);
   let max_id = messages.iter().map(|m| m.id).max().unwrap_or(0);
 
   let (marked_dirs, cleared_dirs) = if min_id > 0 {
-    mark_broken_dirs(pool, min_id, max_id, &seen_dirs).await?
+    mark_broken_dirs(pool, min_id, max_id, &seen_dirs, &present).await?
   } else {
     (0, 0)
   };
   let (marked_files, cleared_files) = if min_id > 0 {
-    mark_broken_files(pool, storage_chat_id, min_id, max_id, &seen_files).await?
+    mark_broken_files(pool, storage_chat_id, min_id, max_id, &seen_files, &present).await?
   } else {
     (0, 0)
   };
@@ -89,9 +91,7 @@ pub async fn reconcile_recent(
       sync::set_sync(pool, "storage_last_message_id", &max_id.to_string()).await?;
     }
   }
-  let _ = sync::set_sync(pool, "storage_reconcile_done", &Utc::now().to_rfc3339()).await;
-
-  Ok(ReconcileOutcome {
+  let mut outcome = ReconcileOutcome {
     scanned: messages.len() as i64,
     dir_seen,
     file_seen,
@@ -102,7 +102,54 @@ pub async fn reconcile_recent(
     cleared_files,
     min_message_id: min_id,
     max_message_id: max_id
-  })
+  };
+  // В дополнительных каналах только файлы; недоступный канал не должен
+  // ломать сверку основного.
+  for chat_id in storage_channels::chat_ids(pool).await? {
+    if let Err(e) = reconcile_extra_channel(pool, tg, chat_id, limit, &mut outcome).await {
+      tracing::warn!(event = "storage_channel_reconcile_failed", chat_id = chat_id, error = %e, "Не удалось сверить дополнительный канал хранения");
+    }
+  }
+  let _ = sync::set_sync(pool, "storage_reconcile_done", &Utc::now().to_rfc3339()).await;
+
+  Ok(outcome)
+}
+
+/// Сверяет последние сообщения дополнительного канала хранения и добавляет
+/// счетчики к `outcome`. Диапазон id в итоге остается от основного канала.
+async fn reconcile_extra_channel(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  chat_id: ChatId,
+  limit: i64,
+  outcome: &mut ReconcileOutcome
+) -> anyhow::Result<()> {
+  let messages = fetch_recent_messages(tg, chat_id, limit).await?;
+  if messages.is_empty() {
+    return Ok(());
+  }
+  let mut seen_files: HashSet<i64> = HashSet::new();
+  let mut unassigned_dir: Option<(String, String)> = None;
+  for msg in &messages {
+    let indexed = indexer::index_storage_message(pool, tg, chat_id, msg, &mut unassigned_dir).await?;
+    if indexed.file {
+      seen_files.insert(msg.id);
+      outcome.file_seen += 1;
+    }
+    if indexed.imported {
+      outcome.imported += 1;
+    }
+  }
+  let present: HashSet<i64> = messages.iter().map(|m| m.id).collect();
+  let min_id = messages.iter().map(|m| m.id).min().unwrap_or(0);
+  let max_id = messages.iter().map(|m| m.id).max().unwrap_or(0);
+  if min_id > 0 {
+    let (marked, cleared) = mark_broken_files(pool, chat_id, min_id, max_id, &seen_files, &present).await?;
+    outcome.marked_files += marked;
+    outcome.cleared_files += cleared;
+  }
+  outcome.scanned += messages.len() as i64;
+  Ok(())
 }
 
 async fn fetch_recent_messages(
@@ -143,7 +190,8 @@ async fn mark_broken_dirs(
   pool: &SqlitePool,
   min_message_id: i64,
   max_message_id: i64,
-  seen: &HashSet<i64>
+  seen: &HashSet<i64>,
+  present: &HashSet<i64>
 ) -> anyhow::Result<(i64, i64)> {
   let rows = sqlx::query(
     "SELECT id, tg_msg_id, is_broken
@@ -167,13 +215,10 @@ async fn mark_broken_dirs(
     }
     let should_broken = !seen.contains(&msg_id);
     if should_broken && is_broken == 0 {
-      sqlx::query("UPDATE directories SET is_broken = 1 WHERE id = ?")
-        .bind(&id)
-        .execute(pool)
-        .await?;
+      broken::mark_dir_broken(pool, &id, broken_reason_for(msg_id, present)).await?;
       marked += 1;
     } else if !should_broken && is_broken != 0 {
-      sqlx::query("UPDATE directories SET is_broken = 0 WHERE id = ?")
+      sqlx::query("UPDATE directories SET is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
         .bind(&id)
         .execute(pool)
         .await?;
@@ -189,7 +234,8 @@ async fn mark_broken_files(
   storage_chat_id: ChatId,
   min_message_id: i64,
   max_message_id: i64,
-  seen: &HashSet<i64>
+  seen: &HashSet<i64>,
+  present: &HashSet<i64>
 ) -> anyhow::Result<(i64, i64)> {
   let rows = sqlx::query(
     "SELECT id, tg_msg_id, is_broken
@@ -214,13 +260,10 @@ async fn mark_broken_files(
     }
     let should_broken = !seen.contains(&msg_id);
     if should_broken && is_broken == 0 {
-      sqlx::query("UPDATE files SET is_broken = 1 WHERE id = ?")
-        .bind(&id)
-        .execute(pool)
-        .await?;
+      broken::mark_file_broken(pool, &id, broken_reason_for(msg_id, present)).await?;
       marked += 1;
     } else if !should_broken && is_broken != 0 {
-      sqlx::query("UPDATE files SET is_broken = 0 WHERE id = ?")
+      sqlx::query("UPDATE files SET is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
         .bind(&id)
         .execute(pool)
         .await?;
@@ -230,3 +273,150 @@ async fn mark_broken_files(
 
   Ok((marked, cleared))
 }
+
+fn broken_reason_for(msg_id: i64, present: &HashSet<i64>) -> BrokenReason {
+  // Сообщение есть в истории, но индексатор его не узнал — значит, испорчена подпись.
+  if present.contains(&msg_id) {
+    BrokenReason::CaptionUnparsable
+  } else {
+    BrokenReason::MessageDeleted
+  }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeletedMessages {
+  pub files: Vec<String>,
+  pub dirs: Vec<String>,
+  pub links: i64
+}
+
+impl DeletedMessages {
+  pub fn is_empty(&self) -> bool {
+    self.files.is_empty() && self.dirs.is_empty() && self.links == 0
+  }
+}
+
+/// Отражает удаление сообщений канала хранения (`updateDeleteMessages`) сразу,
+/// не дожидаясь реконсайла: файлы, у которых пропало основное сообщение или
+/// часть, и папки помечаются битыми, как это сделал бы реконсайл. Ссылки
+/// состоят из одного сообщения и удаляются вместе с ним. Папки и ссылки
+/// живут только в основном канале (`main_chat`), в дополнительных — файлы.
+pub async fn mark_deleted_messages(
+  pool: &SqlitePool,
+  storage_chat_id: ChatId,
+  main_chat: bool,
+  message_ids: &[i64]
+) -> anyhow::Result<DeletedMessages> {
+  let mut out = DeletedMessages::default();
+  for &msg_id in message_ids {
+    let files: Vec<String> = sqlx::query(
+      "SELECT id FROM files WHERE is_broken = 0 AND (
+         (tg_chat_id = ? AND tg_msg_id = ?)
+         OR id IN (SELECT file_id FROM file_parts WHERE tg_chat_id = ? AND tg_msg_id = ?)
+       )"
+    )
+      .bind(storage_chat_id)
+      .bind(msg_id)
+      .bind(storage_chat_id)
+      .bind(msg_id)
+      .fetch_all(pool)
+      .await?
+      .into_iter()
+      .map(|row| row.get("id"))
+      .collect();
+    for file_id in files {
+      broken::mark_file_broken(pool, &file_id, BrokenReason::MessageDeleted).await?;
+      if !out.files.contains(&file_id) {
+        out.files.push(file_id);
+      }
+    }
+
+    if !main_chat {
+      continue;
+    }
+    let dirs: Vec<String> = sqlx::query("SELECT id FROM directories WHERE is_broken = 0 AND tg_msg_id = ?")
+      .bind(msg_id)
+      .fetch_all(pool)
+      .await?
+      .into_iter()
+      .map(|row| row.get("id"))
+      .collect();
+    for dir_id in dirs {
+      broken::mark_dir_broken(pool, &dir_id, BrokenReason::MessageDeleted).await?;
+      out.dirs.push(dir_id);
+    }
+
+    // У ссылок нет колонки чата: они пишутся только в основной канал, и
+    // проверка `main_chat` выше и есть фильтр по чату.
+    out.links += sqlx::query("DELETE FROM links WHERE tg_msg_id = ?")
+      .bind(msg_id)
+      .execute(pool)
+      .await?
+      .rows_affected() as i64;
+  }
+  Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[tokio::test]
+  async fn deleted_messages_mark_files_and_dirs_broken() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query(
+      "INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES
+         ('docs', NULL, 'Документы', 10, 0), ('other', NULL, 'Другое', 11, 0)"
+    )
+      .execute(pool)
+      .await?;
+    sqlx::query(
+      "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at) VALUES
+         ('gone', 'docs', 'a.txt', 1, 'h', 5, 20, 0),
+         ('kept', 'docs', 'b.txt', 1, 'h', 5, 21, 0),
+         ('other_chat', 'docs', 'c.txt', 1, 'h', 6, 20, 0),
+         ('chunked', 'other', 'big.bin', 2, 'h', 5, 30, 0)"
+    )
+      .execute(pool)
+      .await?;
+    sqlx::query(
+      "INSERT INTO file_parts(file_id, part_index, tg_chat_id, tg_msg_id, size) VALUES
+         ('chunked', 0, 5, 30, 1), ('chunked', 1, 5, 31, 1)"
+    )
+      .execute(pool)
+      .await?;
+
+    sqlx::query(
+      "INSERT INTO links(id, dir_id, target_kind, target_id, tg_msg_id, created_at) VALUES('l', 'other', 'file', 'kept', 40, 0)"
+    )
+      .execute(pool)
+      .await?;
+
+    // В дополнительном канале те же id сообщений не задевают папки и ссылки основного.
+    let extra = mark_deleted_messages(pool, 6, false, &[10, 20, 40]).await?;
+    assert_eq!(extra.files, vec!["other_chat".to_string()]);
+    assert!(extra.dirs.is_empty());
+    assert_eq!(extra.links, 0);
+
+    let deleted = mark_deleted_messages(pool, 5, true, &[10, 20, 31, 40]).await?;
+    assert_eq!(deleted.files, vec!["gone".to_string(), "chunked".to_string()]);
+    assert_eq!(deleted.dirs, vec!["docs".to_string()]);
+    assert_eq!(deleted.links, 1);
+
+    let broken: Vec<String> = sqlx::query("SELECT id FROM files WHERE is_broken = 1 AND broken_reason = 'message_deleted' ORDER BY id")
+      .fetch_all(pool)
+      .await?
+      .into_iter()
+      .map(|row| row.get("id"))
+      .collect();
+    assert_eq!(broken, vec!["chunked".to_string(), "gone".to_string(), "other_chat".to_string()]);
+    // Повторное удаление уже помеченных записей ничего не меняет.
+    assert!(mark_deleted_messages(pool, 5, true, &[10, 20]).await?.is_empty());
+    Ok(())
+  }
+}
@@ -0,0 +1,333 @@
+use chrono::Utc;
+use sqlx_sqlite::SqlitePool;
+
+use crate::app::{broken, files, verify};
+use crate::fsmeta::{DirMeta, LinkKind, LinkMeta, make_dir_message, make_link_message};
+use crate::paths::Paths;
+use crate::sqlx::{self, Row};
+use crate::telegram::{ChatId, TelegramService};
+
+/// Частичный reseed: восстанавливает в канале хранения только одно
+/// поддерево (например, после случайного массового удаления сообщений).
+/// Живые сообщения не трогаются; пропавшие папки и ссылки отправляются
+/// заново, пропавшие файлы ищутся в канале или переотправляются из
+/// локальной копии, остальные помечаются поврежденными.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct SubtreeReseedReport {
+  pub dirs_checked: u64,
+  pub dirs_resent: u64,
+  pub links_resent: u64,
+  pub files_checked: u64,
+  pub files_restored: u64,
+  pub files_broken: u64
+}
+
+pub async fn reseed_subtree(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  paths: &Paths,
+  storage_chat_id: ChatId,
+  dir_id: &str
+) -> anyhow::Result<SubtreeReseedReport> {
+  let dir_ids = subtree_dir_ids(pool, dir_id).await?;
+  if dir_ids.is_empty() {
+    return Err(anyhow::anyhow!("Папка не найдена"));
+  }
+  tracing::info!(event = "storage_subtree_reseed_start", dir_id = dir_id, dirs = dir_ids.len(), "Частичное восстановление канала");
+  let mut report = SubtreeReseedReport::default();
+  let now = Utc::now().timestamp();
+
+  for id in &dir_ids {
+    let row = sqlx::query("SELECT parent_id, name, tg_msg_id FROM directories WHERE id = ?")
+      .bind(id)
+      .fetch_one(pool)
+      .await?;
+    report.dirs_checked += 1;
+    let msg_id: Option<i64> = row.try_get("tg_msg_id").ok();
+    if message_alive(tg, storage_chat_id, msg_id).await? {
+      continue;
+    }
+    let parent_id = row
+      .try_get::<String, _>("parent_id")
+      .ok()
+      .filter(|p| !p.trim().is_empty() && p != "ROOT")
+      .unwrap_or_else(|| "ROOT".to_string());
+    let msg = make_dir_message(&DirMeta { dir_id: id.clone(), parent_id, name: row.get("name") });
+    let uploaded = tg.send_dir_message(storage_chat_id, msg).await?;
+    sqlx::query("UPDATE directories SET tg_msg_id = ?, updated_at = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
+      .bind(uploaded.message_id)
+      .bind(now)
+      .bind(id)
+      .execute(pool)
+      .await?;
+    report.dirs_resent += 1;
+  }
+
+  for id in &dir_ids {
+    let links = sqlx::query("SELECT id, target_kind, target_id, tg_msg_id FROM links WHERE dir_id = ? ORDER BY created_at")
+      .bind(id)
+      .fetch_all(pool)
+      .await?;
+    for link in links {
+      let msg_id: Option<i64> = link.try_get("tg_msg_id").ok();
+      if message_alive(tg, storage_chat_id, msg_id).await? {
+        continue;
+      }
+      let Some(kind) = LinkKind::parse(&link.get::<String, _>("target_kind")) else {
+        continue;
+      };
+      let link_id: String = link.get("id");
+      let msg = make_link_message(&LinkMeta {
+        link_id: link_id.clone(),
+        dir_id: id.clone(),
+        kind,
+        target_id: link.get("target_id")
+      });
+      let uploaded = tg.send_dir_message(storage_chat_id, msg).await?;
+      sqlx::query("UPDATE links SET tg_msg_id = ? WHERE id = ?")
+        .bind(uploaded.message_id)
+        .bind(&link_id)
+        .execute(pool)
+        .await?;
+      report.links_resent += 1;
+    }
+  }
+
+  for id in &dir_ids {
+    let rows = sqlx::query("SELECT id, tg_chat_id, tg_msg_id FROM files WHERE dir_id = ? ORDER BY name")
+      .bind(id)
+      .fetch_all(pool)
+      .await?;
+    for row in rows {
+      report.files_checked += 1;
+      let file_id: String = row.get("id");
+      let chat_id: i64 = row.get("tg_chat_id");
+      let msg_id: i64 = row.get("tg_msg_id");
+      if tg.message_exists(chat_id, msg_id).await.unwrap_or(false) {
+        continue;
+      }
+      match files::repair_file(pool, tg, paths, storage_chat_id, &file_id, None).await {
+        Ok(files::RepairFileResult::Repaired) => report.files_restored += 1,
+        Ok(files::RepairFileResult::NeedFile) => {
+          broken::mark_file_broken(pool, &file_id, broken::BrokenReason::MessageDeleted).await?;
+          report.files_broken += 1;
+        }
+        Err(e) => {
+          tracing::warn!(event = "storage_subtree_reseed_file_failed", file_id = file_id.as_str(), error = %e, "Не удалось восстановить файл");
+          broken::mark_file_broken(pool, &file_id, broken::BrokenReason::MessageDeleted).await?;
+          report.files_broken += 1;
+        }
+      }
+    }
+  }
+
+  tracing::info!(
+    event = "storage_subtree_reseed_done",
+    dir_id = dir_id,
+    dirs_resent = report.dirs_resent,
+    links_resent = report.links_resent,
+    files_restored = report.files_restored,
+    files_broken = report.files_broken,
+    "Частичное восстановление канала завершено"
+  );
+  Ok(report)
+}
+
+/// Файл, который не удалось перенести в новый канал хранения.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct NotMigrated {
+  pub file_id: String,
+  pub name: String,
+  pub reason: String
+}
+
+/// Итог переноса всего канала. Старый канал удаляется, только если перенос
+/// прошел проверку, иначе он остается источником для повторной попытки.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct ChannelMigrationReport {
+  pub old_chat_id: Option<ChatId>,
+  pub new_chat_id: ChatId,
+  pub files_copied: u64,
+  pub files_checked: u64,
+  pub spot_checked: u64,
+  pub old_deleted: bool,
+  pub not_migrated: Vec<NotMigrated>
+}
+
+impl ChannelMigrationReport {
+  pub fn safe_to_delete_old(&self) -> bool {
+    self.not_migrated.is_empty()
+  }
+}
+
+/// Сколько файлов скачивается для выборочной сверки и до какого размера.
+const SPOT_CHECK_FILES: i64 = 3;
+const SPOT_CHECK_MAX_BYTES: i64 = 20 * 1024 * 1024;
+/// Сколько сообщений проверяется одним запросом.
+const EXISTS_BATCH: usize = 100;
+
+/// Проверяет новый канал перед удалением старого: ни один файл не должен
+/// остаться в старом канале, а у каждого перенесенного должно быть сообщение.
+/// Сообщения проверяются пачками, без скачивания.
+pub async fn verify_migrated(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  report: &mut ChannelMigrationReport
+) -> anyhow::Result<()> {
+  let new_chat_id = report.new_chat_id;
+  if let Some(old) = report.old_chat_id.filter(|id| *id != new_chat_id) {
+    // Файлы с удаленным сообщением переносить неоткуда, они уже помечены битыми.
+    let left = sqlx::query(
+      "SELECT id, name FROM files
+       WHERE tg_chat_id = ? AND NOT (is_broken != 0 AND broken_reason IS 'message_deleted')
+       ORDER BY name"
+    )
+      .bind(old)
+      .fetch_all(pool)
+      .await?;
+    for row in left {
+      report.not_migrated.push(NotMigrated {
+        file_id: row.get("id"),
+        name: row.get("name"),
+        reason: "не скопирован в новый канал".into()
+      });
+    }
+  }
+
+  if let Some(old) = report.old_chat_id.filter(|id| *id != new_chat_id) {
+    let left = sqlx::query(
+      "SELECT DISTINCT f.id, f.name FROM file_parts p JOIN files f ON f.id = p.file_id
+       WHERE p.tg_chat_id = ? AND f.tg_chat_id <> ? ORDER BY f.name"
+    )
+      .bind(old)
+      .bind(old)
+      .fetch_all(pool)
+      .await?;
+    for row in left {
+      report.not_migrated.push(NotMigrated {
+        file_id: row.get("id"),
+        name: row.get("name"),
+        reason: "не все части скопированы в новый канал".into()
+      });
+    }
+  }
+
+  let rows = sqlx::query("SELECT id, name, tg_msg_id FROM files WHERE tg_chat_id = ? ORDER BY name")
+    .bind(new_chat_id)
+    .fetch_all(pool)
+    .await?;
+  for chunk in rows.chunks(EXISTS_BATCH) {
+    report.files_checked += chunk.len() as u64;
+    let ids: Vec<i64> = chunk.iter().map(|r| r.get("tg_msg_id")).collect();
+    let found = tg.messages_exist(new_chat_id, ids).await;
+    for (idx, row) in chunk.iter().enumerate() {
+      let reason = match &found {
+        Ok(found) if found.get(idx).copied().unwrap_or(false) => continue,
+        Ok(_) => "нет сообщения в новом канале".to_string(),
+        Err(e) => format!("не удалось проверить сообщение: {e}")
+      };
+      report.not_migrated.push(NotMigrated { file_id: row.get("id"), name: row.get("name"), reason });
+    }
+  }
+
+  tracing::info!(
+    event = "storage_channel_migration_verified",
+    files_checked = report.files_checked,
+    not_migrated = report.not_migrated.len(),
+    "Проверка нового канала завершена"
+  );
+  Ok(())
+}
+
+/// Выборочная сверка: несколько небольших файлов из нового канала
+/// скачиваются и сравниваются с хэшем. Идет уже без блокировки канала
+/// хранения, чтобы скачивание не задерживало остальные операции.
+pub async fn spot_check_migrated(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  paths: &Paths,
+  report: &mut ChannelMigrationReport
+) -> anyhow::Result<()> {
+  let new_chat_id = report.new_chat_id;
+  let sample = sqlx::query(
+    "SELECT id, name FROM files WHERE tg_chat_id = ? AND size > 0 AND size <= ? ORDER BY RANDOM() LIMIT ?"
+  )
+    .bind(new_chat_id)
+    .bind(SPOT_CHECK_MAX_BYTES)
+    .bind(SPOT_CHECK_FILES)
+    .fetch_all(pool)
+    .await?;
+  for row in sample {
+    let file_id: String = row.get("id");
+    if report.not_migrated.iter().any(|n| n.file_id == file_id) {
+      continue;
+    }
+    let reason = match verify::verify_file(pool, tg, paths, new_chat_id, &file_id, false).await {
+      Ok(res) if res.status == verify::VerifyStatus::Mismatch => "содержимое не совпадает с контрольной суммой".to_string(),
+      Ok(res) => {
+        if res.status == verify::VerifyStatus::Ok {
+          report.spot_checked += 1;
+        }
+        continue;
+      }
+      Err(e) => format!("не удалось скачать для проверки: {e}")
+    };
+    report.not_migrated.push(NotMigrated { file_id, name: row.get("name"), reason });
+  }
+
+  tracing::info!(
+    event = "storage_channel_migration_spot_checked",
+    spot_checked = report.spot_checked,
+    not_migrated = report.not_migrated.len(),
+    "Выборочная сверка нового канала завершена"
+  );
+  Ok(())
+}
+
+/// Папки поддерева от корня вглубь, чтобы родитель уходил в канал раньше детей.
+pub async fn subtree_dir_ids(pool: &SqlitePool, dir_id: &str) -> anyhow::Result<Vec<String>> {
+  let rows = sqlx::query(
+    "WITH RECURSIVE subtree(id, depth) AS (
+       SELECT id, 0 FROM directories WHERE id = ?
+       UNION ALL
+       SELECT d.id, s.depth + 1 FROM directories d JOIN subtree s ON d.parent_id = s.id
+     )
+     SELECT id FROM subtree ORDER BY depth, id"
+  )
+    .bind(dir_id)
+    .fetch_all(pool)
+    .await?;
+  Ok(rows.into_iter().map(|r| r.get("id")).collect())
+}
+
+async fn message_alive(tg: &dyn TelegramService, chat_id: ChatId, msg_id: Option<i64>) -> anyhow::Result<bool> {
+  match msg_id {
+    Some(id) if id > 0 => Ok(tg.message_exists(chat_id, id).await?),
+    _ => Ok(false)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[tokio::test]
+  async fn subtree_lists_parents_before_children() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    for (id, parent) in [("a", None), ("b", Some("a")), ("c", Some("b")), ("x", None)] {
+      sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES(?, ?, ?, NULL, 0)")
+        .bind(id)
+        .bind(parent)
+        .bind(id.to_uppercase())
+        .execute(db.pool())
+        .await?;
+    }
+    assert_eq!(subtree_dir_ids(db.pool(), "a").await?, vec!["a", "b", "c"]);
+    assert!(subtree_dir_ids(db.pool(), "missing").await?.is_empty());
+    Ok(())
+  }
+}
@@ -0,0 +1,215 @@
+use std::collections::{HashMap, HashSet};
+
+use parking_lot::RwLock;
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+
+/// Индекс по триграммам имен файлов для мгновенного поиска по мере ввода.
+/// Хранит только id и имя: папку и остальные поля поиск берет из БД,
+/// поэтому перемещения файлов индекс обновлять не нужно.
+#[derive(Default)]
+pub struct SearchIndex {
+  inner: RwLock<IndexInner>
+}
+
+#[derive(Default)]
+struct IndexInner {
+  ready: bool,
+  /// Файлы, удаленные во время прогрева: его выборка могла застать их в БД.
+  removed: HashSet<String>,
+  names: HashMap<String, String>,
+  trigrams: HashMap<String, HashSet<String>>
+}
+
+impl SearchIndex {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn is_ready(&self) -> bool {
+    self.inner.read().ready
+  }
+
+  pub fn len(&self) -> usize {
+    self.inner.read().names.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Заполняет индекс из БД. Обновления, пришедшие во время прогрева,
+  /// применяются сразу и не теряются: индекс устанавливается в состояние до сборки.
+  pub async fn warm(&self, pool: &SqlitePool) -> anyhow::Result<()> {
+    let rows = sqlx::query("SELECT id, name FROM files")
+      .fetch_all(pool)
+      .await?;
+    {
+      let mut inner = self.inner.write();
+      for row in rows {
+        let id: String = row.get("id");
+        if inner.names.contains_key(&id) || inner.removed.contains(&id) {
+          continue;
+        }
+        let name: String = row.get("name");
+        inner.insert(id, &name);
+      }
+      inner.removed.clear();
+      inner.ready = true;
+    }
+    tracing::info!(event = "search_index_ready", files = self.len(), "Поисковый индекс готов");
+    Ok(())
+  }
+
+  pub fn upsert(&self, file_id: &str, name: &str) {
+    let mut inner = self.inner.write();
+    inner.remove(file_id);
+    inner.removed.remove(file_id);
+    inner.insert(file_id.to_string(), name);
+  }
+
+  pub fn remove(&self, file_id: &str) {
+    let mut inner = self.inner.write();
+    inner.remove(file_id);
+    if !inner.ready {
+      inner.removed.insert(file_id.to_string());
+    }
+  }
+
+  /// Перечитывает имя файла из БД; если файла больше нет, убирает его из индекса.
+  pub async fn refresh_file(&self, pool: &SqlitePool, file_id: &str) -> anyhow::Result<()> {
+    let row = sqlx::query("SELECT name FROM files WHERE id = ?")
+      .bind(file_id)
+      .fetch_optional(pool)
+      .await?;
+    match row {
+      Some(row) => self.upsert(file_id, &row.get::<String,_>("name")),
+      None => self.remove(file_id)
+    }
+    Ok(())
+  }
+
+  /// Возвращает id файлов, в имени которых есть `query`, отсортированные по имени.
+  /// `None`, пока индекс не прогрет — тогда нужно искать через SQL.
+  pub fn query(&self, query: &str, limit: usize) -> Option<Vec<String>> {
+    let inner = self.inner.read();
+    if !inner.ready {
+      return None;
+    }
+    let needle = query.trim().to_lowercase();
+    if needle.is_empty() {
+      return Some(Vec::new());
+    }
+
+    let grams = trigrams(&needle);
+    let mut found: Vec<(&String, &String)> = if grams.is_empty() {
+      inner
+        .names
+        .iter()
+        .filter(|(_, name)| name.contains(&needle))
+        .collect()
+    } else {
+      let mut postings: Vec<&HashSet<String>> = Vec::with_capacity(grams.len());
+      for gram in &grams {
+        match inner.trigrams.get(gram) {
+          Some(ids) => postings.push(ids),
+          None => return Some(Vec::new())
+        }
+      }
+      postings.sort_by_key(|ids| ids.len());
+      let (first, rest) = postings.split_first()?;
+      first
+        .iter()
+        .filter(|id| rest.iter().all(|ids| ids.contains(*id)))
+        .filter_map(|id| inner.names.get_key_value(id))
+        .filter(|(_, name)| name.contains(&needle))
+        .collect()
+    };
+
+    found.sort_by(|a, b| a.1.cmp(b.1).then_with(|| a.0.cmp(b.0)));
+    Some(found.into_iter().take(limit.max(1)).map(|(id, _)| id.clone()).collect())
+  }
+}
+
+impl IndexInner {
+  fn insert(&mut self, file_id: String, name: &str) {
+    let lower = name.to_lowercase();
+    for gram in trigrams(&lower) {
+      self.trigrams.entry(gram).or_default().insert(file_id.clone());
+    }
+    self.names.insert(file_id, lower);
+  }
+
+  fn remove(&mut self, file_id: &str) {
+    let Some(lower) = self.names.remove(file_id) else {
+      return;
+    };
+    for gram in trigrams(&lower) {
+      if let Some(ids) = self.trigrams.get_mut(&gram) {
+        ids.remove(file_id);
+        if ids.is_empty() {
+          self.trigrams.remove(&gram);
+        }
+      }
+    }
+  }
+}
+
+fn trigrams(value: &str) -> HashSet<String> {
+  let chars: Vec<char> = value.chars().collect();
+  chars.windows(3).map(|w| w.iter().collect()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn query_matches_substrings_and_tracks_updates() {
+    let index = SearchIndex::new();
+    assert!(index.query("отч", 10).is_none());
+
+    index.upsert("f1", "Отчет 2024.pdf");
+    index.upsert("f2", "Договор.docx");
+    index.upsert("f3", "отчет_черновик.txt");
+    index.inner.write().ready = true;
+
+    assert_eq!(index.query("ОТЧЕТ", 10), Some(vec!["f1".to_string(), "f3".to_string()]));
+    assert_eq!(index.query(".d", 10), Some(vec!["f2".to_string()]));
+    assert_eq!(index.query("отчет", 1), Some(vec!["f1".to_string()]));
+
+    index.upsert("f1", "Акт.pdf");
+    index.remove("f3");
+    assert_eq!(index.query("отчет", 10), Some(Vec::new()));
+    assert_eq!(index.query("акт", 10), Some(vec!["f1".to_string()]));
+  }
+
+  #[tokio::test]
+  async fn warm_skips_files_removed_while_warming() -> anyhow::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let db = crate::db::Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d', NULL, 'Docs', NULL, 0)")
+      .execute(pool)
+      .await?;
+    sqlx::query(
+      "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at) VALUES
+         ('gone', 'd', 'report-old.pdf', 1, 'h', 1, 1, 0), ('kept', 'd', 'report.pdf', 1, 'h', 1, 2, 0)"
+    )
+      .execute(pool)
+      .await?;
+
+    // Удаление пришло раньше, чем прогрев дочитал выборку со старой строкой.
+    let index = SearchIndex::new();
+    index.remove("gone");
+    index.warm(pool).await?;
+    assert_eq!(index.query("report", 10), Some(vec!["kept".to_string()]));
+
+    // После прогрева удаления не копятся, а вернувшийся файл снова ищется.
+    index.upsert("gone", "report-old.pdf");
+    assert_eq!(index.query("report", 10), Some(vec!["gone".to_string(), "kept".to_string()]));
+    assert!(index.inner.read().removed.is_empty());
+    Ok(())
+  }
+}
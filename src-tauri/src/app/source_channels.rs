@@ -0,0 +1,275 @@
+use chrono::Utc;
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+use ulid::Ulid;
+
+use crate::app::dirs::dir_exists;
+use crate::app::indexer;
+use crate::telegram::{content_kind, ChatId, HistoryMessage, TelegramService};
+
+/// Дополнительный канал, из которого файлы только читаются: его сообщения
+/// попадают в назначенную папку, но подписи в нем не правятся и сообщения
+/// не удаляются.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SourceChannel {
+  pub chat_id: ChatId,
+  pub title: String,
+  pub target_dir_id: String,
+  pub enabled: bool,
+  /// Брать только документы, без фото, видео и голосовых.
+  pub documents_only: bool,
+  pub last_message_id: i64,
+  pub file_count: i64
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct SourceChannelInput {
+  pub chat_id: ChatId,
+  pub title: String,
+  pub target_dir_id: String,
+  #[serde(default = "default_true")]
+  pub enabled: bool,
+  #[serde(default = "default_true")]
+  pub documents_only: bool
+}
+
+fn default_true() -> bool {
+  true
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct SourceSyncReport {
+  pub processed: i64,
+  pub imported: i64,
+  pub file_ids: Vec<String>
+}
+
+impl SourceChannel {
+  /// Подходит ли сообщение канала для индексации.
+  pub fn accepts(&self, msg: &HistoryMessage) -> bool {
+    let has_file = msg.file_size.is_some()
+      || msg.file_name.as_ref().map(|v| !v.trim().is_empty()).unwrap_or(false);
+    if !has_file {
+      return false;
+    }
+    !self.documents_only || msg.content_type.as_deref().map(content_kind).as_deref() == Some("document")
+  }
+}
+
+pub async fn list_sources(pool: &SqlitePool) -> anyhow::Result<Vec<SourceChannel>> {
+  let rows = sqlx::query(
+    "SELECT s.chat_id, s.title, s.target_dir_id, s.enabled, s.documents_only, s.last_message_id,
+       (SELECT COUNT(1) FROM files f WHERE f.tg_chat_id = s.chat_id) AS file_count
+     FROM source_channels s ORDER BY s.title, s.chat_id"
+  )
+    .fetch_all(pool)
+    .await?;
+  Ok(rows.into_iter().map(|r| SourceChannel {
+    chat_id: r.get("chat_id"),
+    title: r.get("title"),
+    target_dir_id: r.get("target_dir_id"),
+    enabled: r.get::<i64,_>("enabled") != 0,
+    documents_only: r.get::<i64,_>("documents_only") != 0,
+    last_message_id: r.get("last_message_id"),
+    file_count: r.get("file_count")
+  }).collect())
+}
+
+/// Добавляет канал или меняет его настройки. Уже проиндексированные файлы при
+/// смене папки переезжают в новую — они живут только в базе.
+pub async fn save_source(pool: &SqlitePool, mut input: SourceChannelInput) -> anyhow::Result<SourceChannel> {
+  input.title = input.title.trim().to_string();
+  if input.title.is_empty() {
+    input.title = input.chat_id.to_string();
+  }
+  if !dir_exists(pool, &input.target_dir_id).await? {
+    return Err(anyhow::anyhow!("Папка не найдена"));
+  }
+  let previous_dir: Option<String> = sqlx::query("SELECT target_dir_id FROM source_channels WHERE chat_id = ?")
+    .bind(input.chat_id)
+    .fetch_optional(pool)
+    .await?
+    .map(|r| r.get("target_dir_id"));
+  sqlx::query(
+    "INSERT INTO source_channels(chat_id, title, target_dir_id, enabled, documents_only, last_message_id, created_at)
+     VALUES(?, ?, ?, ?, ?, 0, ?)
+     ON CONFLICT(chat_id) DO UPDATE SET title=excluded.title, target_dir_id=excluded.target_dir_id,
+       enabled=excluded.enabled, documents_only=excluded.documents_only"
+  )
+    .bind(input.chat_id)
+    .bind(&input.title)
+    .bind(&input.target_dir_id)
+    .bind(if input.enabled { 1 } else { 0 })
+    .bind(if input.documents_only { 1 } else { 0 })
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await?;
+  if let Some(previous) = previous_dir.filter(|d| *d != input.target_dir_id) {
+    sqlx::query("UPDATE files SET dir_id = ? WHERE tg_chat_id = ? AND dir_id = ?")
+      .bind(&input.target_dir_id)
+      .bind(input.chat_id)
+      .bind(&previous)
+      .execute(pool)
+      .await?;
+  }
+  fetch_source(pool, input.chat_id).await
+}
+
+/// Убирает канал и его файлы из дерева, возвращает id убранных файлов.
+/// Сообщения в самом канале остаются.
+pub async fn delete_source(pool: &SqlitePool, chat_id: ChatId) -> anyhow::Result<Vec<String>> {
+  let removed: Vec<String> = sqlx::query("SELECT id FROM files WHERE tg_chat_id = ?")
+    .bind(chat_id)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| r.get("id"))
+    .collect();
+  sqlx::query("DELETE FROM files WHERE tg_chat_id = ?")
+    .bind(chat_id)
+    .execute(pool)
+    .await?;
+  sqlx::query("DELETE FROM source_channels WHERE chat_id = ?")
+    .bind(chat_id)
+    .execute(pool)
+    .await?;
+  Ok(removed)
+}
+
+pub async fn fetch_source(pool: &SqlitePool, chat_id: ChatId) -> anyhow::Result<SourceChannel> {
+  list_sources(pool)
+    .await?
+    .into_iter()
+    .find(|s| s.chat_id == chat_id)
+    .ok_or_else(|| anyhow::anyhow!("Канал-источник не найден"))
+}
+
+pub async fn is_source_chat(pool: &SqlitePool, chat_id: ChatId) -> anyhow::Result<bool> {
+  Ok(sqlx::query("SELECT 1 FROM source_channels WHERE chat_id = ?")
+    .bind(chat_id)
+    .fetch_optional(pool)
+    .await?
+    .is_some())
+}
+
+/// Читает новые сообщения канала до последнего уже виденного и заносит
+/// подходящие файлы в папку канала. Подписи не трогает.
+pub async fn sync_source(pool: &SqlitePool, tg: &dyn TelegramService, source: &SourceChannel) -> anyhow::Result<SourceSyncReport> {
+  let mut report = SourceSyncReport::default();
+  let mut from_message_id: i64 = 0;
+  let mut newest_seen: Option<i64> = None;
+  'pages: loop {
+    let batch = tg.chat_history(source.chat_id, from_message_id, 100).await?;
+    if batch.messages.is_empty() {
+      break;
+    }
+    for msg in batch.messages {
+      if source.last_message_id > 0 && msg.id <= source.last_message_id {
+        break 'pages;
+      }
+      report.processed += 1;
+      if newest_seen.is_none() {
+        newest_seen = Some(msg.id);
+      }
+      if !source.accepts(&msg) {
+        continue;
+      }
+      if indexer::link_forwarded_copy(pool, source.chat_id, &msg).await?.is_some() {
+        continue;
+      }
+      if let Some(file_id) = index_message(pool, source, &msg).await? {
+        report.imported += 1;
+        report.file_ids.push(file_id);
+      }
+    }
+    if batch.next_from_message_id == 0 || batch.next_from_message_id == from_message_id {
+      break;
+    }
+    from_message_id = batch.next_from_message_id;
+  }
+  if let Some(latest) = newest_seen {
+    sqlx::query("UPDATE source_channels SET last_message_id = ? WHERE chat_id = ?")
+      .bind(latest)
+      .bind(source.chat_id)
+      .execute(pool)
+      .await?;
+  }
+  tracing::info!(
+    event = "source_channel_sync_done",
+    chat_id = source.chat_id,
+    processed = report.processed,
+    imported = report.imported,
+    "Канал-источник синхронизирован"
+  );
+  Ok(report)
+}
+
+async fn index_message(pool: &SqlitePool, source: &SourceChannel, msg: &HistoryMessage) -> anyhow::Result<Option<String>> {
+  let file_id = Ulid::new().to_string();
+  let file_name = msg.file_name.clone().filter(|v| !v.trim().is_empty())
+    .unwrap_or_else(|| format!("файл_{}", msg.id));
+  let size = msg.file_size.unwrap_or(0);
+  let created_at = if msg.date > 0 { msg.date } else { Utc::now().timestamp() };
+  let inserted = sqlx::query(
+    "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, is_broken, media_group_id, media_kind, remote_unique_id)
+     SELECT ?, ?, ?, ?, ?, ?, ?, ?, 0, ?, ?, ?
+     WHERE NOT EXISTS (SELECT 1 FROM files WHERE tg_chat_id = ? AND tg_msg_id = ?)"
+  )
+    .bind(&file_id)
+    .bind(&source.target_dir_id)
+    .bind(&file_name)
+    .bind(size)
+    .bind(indexer::import_hash(source.chat_id, msg.id, &file_name, size))
+    .bind(source.chat_id)
+    .bind(msg.id)
+    .bind(created_at)
+    .bind(msg.media_group_id.as_deref())
+    .bind(indexer::media_kind(msg).as_deref())
+    .bind(msg.file_unique_id.as_deref())
+    .bind(source.chat_id)
+    .bind(msg.id)
+    .execute(pool)
+    .await?;
+  Ok((inserted.rows_affected() > 0).then_some(file_id))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn source(documents_only: bool) -> SourceChannel {
+    SourceChannel {
+      chat_id: -100,
+      title: "Курс".into(),
+      target_dir_id: "d".into(),
+      enabled: true,
+      documents_only,
+      last_message_id: 0,
+      file_count: 0
+    }
+  }
+
+  fn msg(content_type: &str, file_name: Option<&str>) -> HistoryMessage {
+    HistoryMessage {
+      id: 1,
+      date: 0,
+      text: None,
+      caption: None,
+      file_size: file_name.map(|_| 10),
+      file_name: file_name.map(str::to_string),
+      sender_id: None,
+      media_group_id: None,
+      content_type: Some(content_type.to_string()),
+      sticker_set_id: None,
+      file_unique_id: None
+    }
+  }
+
+  #[test]
+  fn accepts_only_files_and_respects_documents_only() {
+    assert!(source(true).accepts(&msg("messageDocument", Some("lesson1.pdf"))));
+    assert!(!source(true).accepts(&msg("messageVideo", Some("lesson1.mp4"))));
+    assert!(source(false).accepts(&msg("messageVideo", Some("lesson1.mp4"))));
+    assert!(!source(false).accepts(&msg("messageText", None)));
+  }
+}
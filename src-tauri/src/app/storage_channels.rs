@@ -0,0 +1,249 @@
+use chrono::Utc;
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+
+use crate::app::dirs::dir_exists;
+use crate::app::indexer;
+use crate::telegram::{ChatId, TelegramService};
+
+const MAX_TITLE_LEN: usize = 64;
+
+/// Дополнительный канал хранения. Файлы, загруженные в назначенные ему
+/// папки и их подпапки, уходят в него, а не в основной канал: так история
+/// и поиск каждого канала остаются в пределах лимитов Telegram. Сообщения
+/// папок и ссылок по-прежнему живут в основном канале.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct StorageChannel {
+  pub chat_id: ChatId,
+  pub title: String,
+  pub last_message_id: i64,
+  pub created_at: i64,
+  /// Папки, назначенные каналу напрямую (без унаследованных подпапок).
+  pub dir_ids: Vec<String>,
+  pub file_count: i64
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct StorageChannelSyncReport {
+  pub processed: i64,
+  pub file_ids: Vec<String>
+}
+
+pub async fn list_channels(pool: &SqlitePool) -> anyhow::Result<Vec<StorageChannel>> {
+  let rows = sqlx::query(
+    "SELECT s.chat_id, s.title, s.last_message_id, s.created_at,
+       (SELECT COUNT(1) FROM files f WHERE f.tg_chat_id = s.chat_id) AS file_count
+     FROM storage_channels s ORDER BY s.created_at, s.chat_id"
+  )
+    .fetch_all(pool)
+    .await?;
+  let assigned = sqlx::query("SELECT dir_id, chat_id FROM dir_storage_channels ORDER BY dir_id")
+    .fetch_all(pool)
+    .await?;
+  Ok(rows.into_iter().map(|r| {
+    let chat_id: ChatId = r.get("chat_id");
+    StorageChannel {
+      chat_id,
+      title: r.get("title"),
+      last_message_id: r.get("last_message_id"),
+      created_at: r.get("created_at"),
+      dir_ids: assigned
+        .iter()
+        .filter(|a| a.get::<ChatId,_>("chat_id") == chat_id)
+        .map(|a| a.get("dir_id"))
+        .collect(),
+      file_count: r.get("file_count")
+    }
+  }).collect())
+}
+
+pub async fn fetch_channel(pool: &SqlitePool, chat_id: ChatId) -> anyhow::Result<StorageChannel> {
+  list_channels(pool)
+    .await?
+    .into_iter()
+    .find(|c| c.chat_id == chat_id)
+    .ok_or_else(|| anyhow::anyhow!("Канал хранения не найден"))
+}
+
+/// Запоминает уже созданный в Telegram канал под понятным названием.
+pub async fn add_channel(pool: &SqlitePool, chat_id: ChatId, title: &str) -> anyhow::Result<StorageChannel> {
+  let title = title.trim();
+  if title.chars().count() > MAX_TITLE_LEN {
+    return Err(anyhow::anyhow!("Название канала длиннее {MAX_TITLE_LEN} символов"));
+  }
+  let title = if title.is_empty() { chat_id.to_string() } else { title.to_string() };
+  sqlx::query(
+    "INSERT INTO storage_channels(chat_id, title, last_message_id, created_at) VALUES(?, ?, 0, ?)
+     ON CONFLICT(chat_id) DO UPDATE SET title=excluded.title"
+  )
+    .bind(chat_id)
+    .bind(&title)
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await?;
+  fetch_channel(pool, chat_id).await
+}
+
+/// Назначает папке канал хранения; `None` возвращает ее к каналу родителя.
+/// Уже загруженные файлы остаются в прежних каналах.
+pub async fn assign_dir(pool: &SqlitePool, dir_id: &str, chat_id: Option<ChatId>) -> anyhow::Result<()> {
+  if !dir_exists(pool, dir_id).await? {
+    return Err(anyhow::anyhow!("Папка не найдена"));
+  }
+  let Some(chat_id) = chat_id else {
+    sqlx::query("DELETE FROM dir_storage_channels WHERE dir_id = ?")
+      .bind(dir_id)
+      .execute(pool)
+      .await?;
+    return Ok(());
+  };
+  if !is_extra_chat(pool, chat_id).await? {
+    return Err(anyhow::anyhow!("Канал хранения не найден"));
+  }
+  sqlx::query(
+    "INSERT INTO dir_storage_channels(dir_id, chat_id) VALUES(?, ?)
+     ON CONFLICT(dir_id) DO UPDATE SET chat_id=excluded.chat_id"
+  )
+    .bind(dir_id)
+    .bind(chat_id)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+/// Id всех дополнительных каналов хранения.
+pub async fn chat_ids(pool: &SqlitePool) -> anyhow::Result<Vec<ChatId>> {
+  Ok(
+    sqlx::query("SELECT chat_id FROM storage_channels ORDER BY created_at, chat_id")
+      .fetch_all(pool)
+      .await?
+      .into_iter()
+      .map(|r| r.get("chat_id"))
+      .collect()
+  )
+}
+
+pub async fn is_extra_chat(pool: &SqlitePool, chat_id: ChatId) -> anyhow::Result<bool> {
+  Ok(
+    sqlx::query("SELECT 1 FROM storage_channels WHERE chat_id = ?")
+      .bind(chat_id)
+      .fetch_optional(pool)
+      .await?
+      .is_some()
+  )
+}
+
+/// Канал для загрузки в папку: назначенный ближайшему предку (включая саму
+/// папку) или основной канал, если назначений на пути нет.
+pub async fn chat_for_dir(pool: &SqlitePool, dir_id: &str, default_chat_id: ChatId) -> anyhow::Result<ChatId> {
+  let row = sqlx::query(
+    "WITH RECURSIVE ancestors(id, parent_id, depth) AS (
+       SELECT id, parent_id, 0 FROM directories WHERE id = ?
+       UNION ALL SELECT d.id, d.parent_id, a.depth + 1 FROM directories d JOIN ancestors a ON d.id = a.parent_id
+     )
+     SELECT m.chat_id FROM ancestors a JOIN dir_storage_channels m ON m.dir_id = a.id
+     ORDER BY a.depth LIMIT 1"
+  )
+    .bind(dir_id)
+    .fetch_optional(pool)
+    .await?;
+  Ok(row.map(|r| r.get("chat_id")).unwrap_or(default_chat_id))
+}
+
+/// Читает новые сообщения канала до последнего уже виденного и заносит
+/// файлы с подписью CloudTG в базу так же, как из основного канала.
+pub async fn sync_channel(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  channel: &StorageChannel
+) -> anyhow::Result<StorageChannelSyncReport> {
+  let mut report = StorageChannelSyncReport::default();
+  let mut from_message_id: i64 = 0;
+  let mut newest_seen: Option<i64> = None;
+  let mut unassigned_dir: Option<(String, String)> = None;
+  loop {
+    let batch = tg.chat_history(channel.chat_id, from_message_id, 100).await?;
+    if batch.messages.is_empty() {
+      break;
+    }
+    let mut messages = batch.messages;
+    let seen = messages.iter().position(|msg| channel.last_message_id > 0 && msg.id <= channel.last_message_id);
+    if let Some(seen) = seen {
+      messages.truncate(seen);
+    }
+    report.processed += messages.len() as i64;
+    if newest_seen.is_none() {
+      newest_seen = messages.first().map(|msg| msg.id);
+    }
+    let outcomes = indexer::index_storage_batch(pool, tg, channel.chat_id, &messages, &mut unassigned_dir).await?;
+    report.file_ids.extend(outcomes.into_iter().filter_map(|outcome| outcome.file_id));
+    if seen.is_some() || batch.next_from_message_id == 0 || batch.next_from_message_id == from_message_id {
+      break;
+    }
+    from_message_id = batch.next_from_message_id;
+  }
+  if let Some(latest) = newest_seen {
+    sqlx::query("UPDATE storage_channels SET last_message_id = ? WHERE chat_id = ?")
+      .bind(latest)
+      .bind(channel.chat_id)
+      .execute(pool)
+      .await?;
+  }
+  tracing::info!(
+    event = "storage_channel_sync_done",
+    chat_id = channel.chat_id,
+    processed = report.processed,
+    files = report.file_ids.len(),
+    "Дополнительный канал хранения синхронизирован"
+  );
+  Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[tokio::test]
+  async fn uploads_follow_nearest_assigned_ancestor() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query(
+      "INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES
+         ('root', NULL, 'root', NULL, 0), ('photos', 'root', 'photos', NULL, 0),
+         ('trips', 'photos', 'trips', NULL, 0), ('docs', 'root', 'docs', NULL, 0)"
+    )
+      .execute(pool)
+      .await?;
+
+    assert_eq!(chat_for_dir(pool, "trips", 1).await?, 1);
+    assert!(assign_dir(pool, "photos", Some(-200)).await.is_err());
+
+    add_channel(pool, -200, " Фото ").await?;
+    add_channel(pool, -300, "").await?;
+    assign_dir(pool, "photos", Some(-200)).await?;
+    assert_eq!(chat_for_dir(pool, "photos", 1).await?, -200);
+    assert_eq!(chat_for_dir(pool, "trips", 1).await?, -200);
+    assert_eq!(chat_for_dir(pool, "docs", 1).await?, 1);
+
+    assign_dir(pool, "trips", Some(-300)).await?;
+    assert_eq!(chat_for_dir(pool, "trips", 1).await?, -300);
+    assign_dir(pool, "trips", None).await?;
+    assert_eq!(chat_for_dir(pool, "trips", 1).await?, -200);
+
+    let channels = list_channels(pool).await?;
+    assert_eq!(channels.len(), 2);
+    let mut ids = chat_ids(pool).await?;
+    ids.sort();
+    assert_eq!(ids, vec![-300, -200]);
+    assert!(is_extra_chat(pool, -300).await? && !is_extra_chat(pool, 1).await?);
+    let photos = channels.iter().find(|c| c.chat_id == -200).expect("channel");
+    assert_eq!(photos.title, "Фото");
+    assert_eq!(photos.dir_ids, vec!["photos".to_string()]);
+    assert_eq!(channels.iter().find(|c| c.chat_id == -300).expect("channel").title, "-300");
+    Ok(())
+  }
+}
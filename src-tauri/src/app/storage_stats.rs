@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Manager};
+use tokio::sync::Notify;
+
+use crate::paths::Paths;
+use crate::state::AppState;
+
+use super::{downloads_cache, maintenance};
+
+/// Изменения идут пачками (загрузка папки, синхронизация), поэтому свертки
+/// пересчитываются не на каждое, а после короткой паузы.
+const DEBOUNCE: Duration = Duration::from_secs(3);
+const LARGEST_LIMIT: i64 = 20;
+
+static WAKE: Lazy<Notify> = Lazy::new(Notify::new);
+
+/// Размеры папки: свои файлы и все поддерево, плюс скачанное на диск.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DirStats {
+  pub dir_id: String,
+  pub parent_id: Option<String>,
+  pub name: String,
+  pub own_files: i64,
+  pub own_size: i64,
+  pub total_files: i64,
+  pub total_size: i64,
+  /// Скачанные файлы прямо в каталоге папки.
+  pub cached_bytes: u64
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct LargeFile {
+  pub id: String,
+  pub dir_id: String,
+  pub name: String,
+  pub size: i64
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct CacheStats {
+  pub bytes: u64,
+  pub file_count: i64
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct StorageStats {
+  pub total_size: i64,
+  pub file_count: i64,
+  pub dir_count: i64,
+  pub broken_count: i64,
+  pub dirs: Vec<DirStats>,
+  pub largest: Vec<LargeFile>,
+  pub cache: CacheStats,
+  /// Когда свертки по папкам пересчитывались в последний раз.
+  pub rollups_updated_at: Option<i64>
+}
+
+/// Пересчитывает свертки размеров по всем папкам одним запросом.
+pub async fn refresh_rollups(pool: &SqlitePool) -> anyhow::Result<()> {
+  let now = Utc::now().timestamp();
+  sqlx::query(
+    "WITH RECURSIVE own(id, files, size) AS (
+       SELECT d.id, COUNT(f.id), COALESCE(SUM(f.size), 0)
+       FROM directories d LEFT JOIN files f ON f.dir_id = d.id GROUP BY d.id
+     ),
+     tree(ancestor, id) AS (
+       SELECT id, id FROM directories
+       UNION SELECT t.ancestor, d.id FROM directories d JOIN tree t ON d.parent_id = t.id
+     )
+     INSERT INTO dir_stats(dir_id, own_files, own_size, total_files, total_size, updated_at)
+     SELECT t.ancestor, a.files, a.size, SUM(o.files), SUM(o.size), ?
+     FROM tree t JOIN own o ON o.id = t.id JOIN own a ON a.id = t.ancestor
+     WHERE true GROUP BY t.ancestor
+     ON CONFLICT(dir_id) DO UPDATE SET own_files=excluded.own_files, own_size=excluded.own_size,
+       total_files=excluded.total_files, total_size=excluded.total_size, updated_at=excluded.updated_at"
+  )
+    .bind(now)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+pub async fn collect(pool: &SqlitePool, paths: &Paths) -> anyhow::Result<StorageStats> {
+  if rollups_updated_at(pool).await?.is_none() {
+    refresh_rollups(pool).await?;
+  }
+  let totals = sqlx::query(
+    "SELECT COUNT(1) AS cnt, COALESCE(SUM(size), 0) AS total, COALESCE(SUM(is_broken != 0), 0) AS broken,
+       (SELECT COUNT(1) FROM directories) AS dirs
+     FROM files"
+  )
+    .fetch_one(pool)
+    .await?;
+
+  let usage = downloads_cache::downloads_usage(pool, paths).await?;
+  let cache = CacheStats {
+    bytes: usage.iter().map(|u| u.bytes).sum(),
+    file_count: usage.iter().map(|u| u.file_count).sum()
+  };
+  let cached: HashMap<String, u64> = usage.into_iter().map(|u| (u.dir_id, u.bytes)).collect();
+
+  let dirs: Vec<DirStats> = sqlx::query(
+    "SELECT d.id, d.parent_id, d.name, s.own_files, s.own_size, s.total_files, s.total_size
+     FROM dir_stats s JOIN directories d ON d.id = s.dir_id
+     ORDER BY s.total_size DESC, d.name"
+  )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| {
+      let dir_id: String = r.get("id");
+      DirStats {
+        cached_bytes: cached.get(&dir_id).copied().unwrap_or(0),
+        dir_id,
+        parent_id: r.try_get("parent_id").ok(),
+        name: r.get("name"),
+        own_files: r.get("own_files"),
+        own_size: r.get("own_size"),
+        total_files: r.get("total_files"),
+        total_size: r.get("total_size")
+      }
+    })
+    .collect();
+
+  let largest = sqlx::query("SELECT id, dir_id, name, size FROM files ORDER BY size DESC, name LIMIT ?")
+    .bind(LARGEST_LIMIT)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| LargeFile { id: r.get("id"), dir_id: r.get("dir_id"), name: r.get("name"), size: r.get("size") })
+    .collect();
+
+  Ok(StorageStats {
+    total_size: totals.get("total"),
+    file_count: totals.get("cnt"),
+    dir_count: totals.get("dirs"),
+    broken_count: totals.get("broken"),
+    dirs,
+    largest,
+    cache,
+    rollups_updated_at: rollups_updated_at(pool).await?
+  })
+}
+
+async fn rollups_updated_at(pool: &SqlitePool) -> anyhow::Result<Option<i64>> {
+  Ok(sqlx::query("SELECT MIN(updated_at) AS at FROM dir_stats")
+    .fetch_one(pool)
+    .await?
+    .try_get::<i64, _>("at")
+    .ok())
+}
+
+/// Отмечает, что файлы или папки изменились и свертки пора пересчитать.
+pub fn mark_dirty() {
+  WAKE.notify_one();
+}
+
+pub fn spawn_worker(app: AppHandle) -> JoinHandle<()> {
+  let handle = tauri::async_runtime::spawn(async move {
+    loop {
+      WAKE.notified().await;
+      tokio::time::sleep(DEBOUNCE).await;
+      maintenance::wait_for_window().await;
+      let state = app.state::<AppState>();
+      let res = match state.db() {
+        Ok(db) => refresh_rollups(db.pool()).await,
+        Err(e) => Err(e)
+      };
+      if let Err(e) = res {
+        tracing::warn!(event = "storage_stats_refresh_failed", error = %e, "Не удалось пересчитать размеры папок");
+      }
+    }
+  });
+  mark_dirty();
+  handle
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[tokio::test]
+  async fn rollups_sum_subtrees() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    let paths = Paths::from_base(tmp.path().to_path_buf());
+    sqlx::query(
+      "INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES
+         ('root', NULL, 'root', NULL, 0), ('docs', 'root', 'docs', NULL, 0),
+         ('old', 'docs', 'old', NULL, 0), ('empty', 'root', 'empty', NULL, 0)"
+    )
+      .execute(pool)
+      .await?;
+    sqlx::query(
+      "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at) VALUES
+         ('f1', 'root', 'a.txt', 5, 'h', 1, 1, 0), ('f2', 'docs', 'b.pdf', 100, 'h', 1, 2, 0),
+         ('f3', 'old', 'c.zip', 1000, 'h', 1, 3, 0)"
+    )
+      .execute(pool)
+      .await?;
+
+    let stats = collect(pool, &paths).await?;
+    assert_eq!(stats.total_size, 1105);
+    assert_eq!(stats.file_count, 3);
+    assert_eq!(stats.largest.first().map(|f| f.id.as_str()), Some("f3"));
+    let dir = |id: &str| stats.dirs.iter().find(|d| d.dir_id == id).cloned().expect("dir");
+    assert_eq!((dir("root").own_size, dir("root").total_size, dir("root").total_files), (5, 1105, 3));
+    assert_eq!((dir("docs").own_files, dir("docs").total_size), (1, 1100));
+    assert_eq!((dir("empty").own_files, dir("empty").total_size), (0, 0));
+
+    sqlx::query("DELETE FROM files WHERE id = 'f3'").execute(pool).await?;
+    refresh_rollups(pool).await?;
+    let stats = collect(pool, &paths).await?;
+    assert_eq!(stats.dirs.iter().find(|d| d.dir_id == "root").map(|d| d.total_size), Some(105));
+
+    // Число папок берется из самих папок, а не из сверток, которые еще не
+    // пересчитаны.
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('new', 'root', 'new', NULL, 0)")
+      .execute(pool)
+      .await?;
+    assert_eq!(collect(pool, &paths).await?.dir_count, 5);
+    Ok(())
+  }
+}
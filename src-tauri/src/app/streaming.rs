@@ -0,0 +1,148 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+
+use crate::paths::Paths;
+use crate::stream_server::{media_mime, Available, StreamControl};
+use crate::telegram::{StreamingFile, TelegramService};
+
+use super::{files, local_copies, sync};
+
+/// Сколько начала файла нужно скачать, прежде чем отдавать его плееру:
+/// заголовков контейнера обычно хватает, чтобы плеер начал воспроизведение.
+const START_BYTES: u64 = 1024 * 1024;
+const START_TIMEOUT: Duration = Duration::from_secs(30);
+const START_POLL: Duration = Duration::from_millis(100);
+/// Как часто проверять, докачан ли поток целиком.
+const KEEP_POLL: Duration = Duration::from_secs(2);
+
+/// Файл, готовый к передаче плееру.
+pub struct StreamSource {
+  pub path: PathBuf,
+  pub name: String,
+  pub total: u64,
+  pub available: Available,
+  /// Файл уже скачан целиком и отдается с диска.
+  pub local: bool,
+  /// Скачивание за потоком; у локального файла его нет.
+  pub control: Option<Arc<TelegramStream>>
+}
+
+/// Потоковое скачивание TDLib: перематывается за плеером и останавливается,
+/// когда сервер убирает поток.
+pub struct TelegramStream {
+  tg: Arc<dyn TelegramService>,
+  file: StreamingFile,
+  released: AtomicBool
+}
+
+impl TelegramStream {
+  pub fn is_released(&self) -> bool {
+    self.released.load(Ordering::Relaxed)
+  }
+}
+
+impl StreamControl for TelegramStream {
+  fn seek(&self, offset: u64) {
+    let (tg, file) = (self.tg.clone(), self.file.clone());
+    tauri::async_runtime::spawn(async move {
+      if let Err(e) = tg.stream_seek(&file, offset).await {
+        tracing::debug!(event = "stream_seek_failed", offset = offset, error = %e, "Не удалось перемотать потоковое скачивание");
+      }
+    });
+  }
+
+  fn release(&self) {
+    if self.released.swap(true, Ordering::Relaxed) {
+      return;
+    }
+    let (tg, file) = (self.tg.clone(), self.file.clone());
+    tauri::async_runtime::spawn(async move {
+      if let Err(e) = tg.stream_stop(&file).await {
+        tracing::debug!(event = "stream_stop_failed", error = %e, "Не удалось остановить потоковое скачивание");
+      }
+    });
+  }
+}
+
+/// Находит локальную копию или начинает скачивание и ждет, пока будет
+/// готово начало файла.
+pub async fn prepare(
+  pool: &SqlitePool,
+  tg: Arc<dyn TelegramService>,
+  paths: &Paths,
+  file_id: &str
+) -> anyhow::Result<StreamSource> {
+  let row = sqlx::query("SELECT name, size, tg_chat_id, tg_msg_id, part_count, enc_key_id FROM files WHERE id = ?")
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("Файл не найден"))?;
+  let name: String = row.get("name");
+  let size: i64 = row.get("size");
+  if media_mime(&name).is_none() {
+    return Err(anyhow::anyhow!("Потоком открываются только видео и аудио"));
+  }
+  let total = size.max(0) as u64;
+
+  if let Some(copy) = local_copies::lookup(pool, paths, file_id).await? {
+    return Ok(StreamSource { path: copy.path, name, total, available: Available::complete(total), local: true, control: None });
+  }
+  // Части и зашифрованное содержимое нельзя читать до полного скачивания.
+  if row.get::<i64, _>("part_count") > 1 {
+    return Err(anyhow::anyhow!("Файл загружен частями, его можно открыть только после скачивания"));
+  }
+  if row.try_get::<String, _>("enc_key_id").is_ok() {
+    return Err(anyhow::anyhow!("Зашифрованный файл можно открыть только после скачивания"));
+  }
+
+  let available = Available::default();
+  let file = tg
+    .stream_message_file(row.get("tg_chat_id"), row.get("tg_msg_id"), available.sink())
+    .await?;
+  let total = file.total.unwrap_or(total);
+  let path = file.path.clone();
+  let control = Arc::new(TelegramStream { tg, file, released: AtomicBool::new(false) });
+  let ready = total.min(START_BYTES);
+  let started = Instant::now();
+  while available.end_from(0) < ready {
+    if started.elapsed() >= START_TIMEOUT {
+      control.release();
+      return Err(anyhow::anyhow!("Начало файла не скачалось вовремя"));
+    }
+    tokio::time::sleep(START_POLL).await;
+  }
+  Ok(StreamSource { path, name, total, available, local: false, control: Some(control) })
+}
+
+/// Когда поток докачан целиком, кладет файл в каталог загрузок обычным
+/// скачиванием: TDLib отдает его из своего кеша, а копия попадает в
+/// local_copies и под предел кеша загрузок. Возвращает `true`, если копия
+/// записана, и `false`, если плеер закрыли раньше.
+pub async fn keep_completed(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  paths: &Paths,
+  file_id: &str,
+  source: &StreamSource
+) -> anyhow::Result<bool> {
+  let Some(control) = source.control.as_ref() else {
+    return Ok(false);
+  };
+  while source.available.end_from(0) < source.total {
+    if control.is_released() {
+      return Ok(false);
+    }
+    tokio::time::sleep(KEEP_POLL).await;
+  }
+  let storage_chat_id = sync::get_sync(pool, "storage_chat_id")
+    .await?
+    .and_then(|v| v.parse::<i64>().ok())
+    .ok_or_else(|| anyhow::anyhow!("Канал хранения не настроен"))?;
+  files::download_file(pool, tg, paths, storage_chat_id, file_id, false, None).await?;
+  Ok(true)
+}
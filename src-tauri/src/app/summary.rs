@@ -0,0 +1,132 @@
+use chrono::{DateTime, Utc};
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+
+use crate::telegram::{TelegramService, ChatId};
+use super::sync;
+
+pub const SUMMARY_TAG: &str = "#ocltg #summary";
+const SUMMARY_MSG_KEY: &str = "vault_summary_msg_id";
+pub const BACKUP_LAST_AT_KEY: &str = "backup_last_at";
+
+#[derive(Debug, Clone)]
+pub struct VaultSummary {
+  pub files: i64,
+  pub dirs: i64,
+  pub total_size: i64,
+  pub broken: i64,
+  pub last_backup_at: Option<String>,
+  pub app_version: String
+}
+
+pub async fn collect_summary(pool: &SqlitePool, app_version: &str) -> anyhow::Result<VaultSummary> {
+  let row = sqlx::query(
+    "SELECT COUNT(1) AS cnt, COALESCE(SUM(size), 0) AS total, COALESCE(SUM(is_broken != 0), 0) AS broken FROM files"
+  )
+    .fetch_one(pool)
+    .await?;
+  let dirs: i64 = sqlx::query("SELECT COUNT(1) AS cnt FROM directories")
+    .fetch_one(pool)
+    .await?
+    .get("cnt");
+  Ok(VaultSummary {
+    files: row.get("cnt"),
+    dirs,
+    total_size: row.get("total"),
+    broken: row.get("broken"),
+    last_backup_at: sync::get_sync(pool, BACKUP_LAST_AT_KEY).await?,
+    app_version: app_version.to_string()
+  })
+}
+
+/// Текст сводки. Тег в начале нужен, чтобы индексатор и люди отличали ее от файлов.
+pub fn render_summary(summary: &VaultSummary) -> String {
+  let last_backup = summary
+    .last_backup_at
+    .as_deref()
+    .and_then(|v| DateTime::parse_from_rfc3339(v).ok())
+    .map(|v| v.with_timezone(&Utc).format("%Y-%m-%d %H:%M UTC").to_string())
+    .unwrap_or_else(|| "еще не было".to_string());
+  let mut lines = vec![
+    SUMMARY_TAG.to_string(),
+    "Сводка хранилища CloudTG".to_string(),
+    format!("Файлов: {}", summary.files),
+    format!("Папок: {}", summary.dirs),
+    format!("Общий размер: {}", format_size(summary.total_size))
+  ];
+  if summary.broken > 0 {
+    lines.push(format!("Требуют восстановления: {}", summary.broken));
+  }
+  lines.push(format!("Последний бэкап: {last_backup}"));
+  lines.push(format!("Версия приложения: {}", summary.app_version));
+  lines.push(format!("Обновлено: {}", Utc::now().format("%Y-%m-%d %H:%M UTC")));
+  lines.join("\n")
+}
+
+/// Обновляет закрепленную сводку в канале хранения или публикует новую,
+/// если прежнее сообщение удалено или канал пересоздан.
+pub async fn publish_summary(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  chat_id: ChatId,
+  app_version: &str
+) -> anyhow::Result<()> {
+  let text = render_summary(&collect_summary(pool, app_version).await?);
+  let key = format!("{SUMMARY_MSG_KEY}:{chat_id}");
+  let existing = sync::get_sync(pool, &key)
+    .await?
+    .and_then(|v| v.parse::<i64>().ok());
+
+  if let Some(message_id) = existing {
+    match tg.edit_message_text(chat_id, message_id, text.clone()).await {
+      Ok(()) => return Ok(()),
+      Err(e) => {
+        tracing::info!(event = "vault_summary_edit_failed", message_id = message_id, error = %e, "Сводка не обновилась, публикую заново");
+      }
+    }
+  }
+
+  let sent = tg.send_text_message(chat_id, text).await?;
+  sync::set_sync(pool, &key, &sent.message_id.to_string()).await?;
+  if let Err(e) = tg.pin_message(chat_id, sent.message_id).await {
+    tracing::warn!(event = "vault_summary_pin_failed", message_id = sent.message_id, error = %e, "Не удалось закрепить сводку");
+  }
+  Ok(())
+}
+
+fn format_size(bytes: i64) -> String {
+  const UNITS: [&str; 5] = ["Б", "КБ", "МБ", "ГБ", "ТБ"];
+  let mut value = bytes.max(0) as f64;
+  let mut unit = 0;
+  while value >= 1024.0 && unit < UNITS.len() - 1 {
+    value /= 1024.0;
+    unit += 1;
+  }
+  if unit == 0 {
+    format!("{} {}", bytes.max(0), UNITS[0])
+  } else {
+    format!("{value:.1} {}", UNITS[unit])
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn render_includes_counts_and_backup_time() {
+    let text = render_summary(&VaultSummary {
+      files: 12,
+      dirs: 3,
+      total_size: 5 * 1024 * 1024 + 512 * 1024,
+      broken: 0,
+      last_backup_at: Some("2024-05-01T10:30:00+00:00".to_string()),
+      app_version: "1.2.3".to_string()
+    });
+    assert!(text.starts_with(SUMMARY_TAG));
+    assert!(text.contains("Файлов: 12"));
+    assert!(text.contains("Общий размер: 5.5 МБ"));
+    assert!(text.contains("Последний бэкап: 2024-05-01 10:30 UTC"));
+    assert!(!text.contains("Требуют восстановления"));
+  }
+}
@@ -17,3 +17,11 @@ pub async fn set_sync(pool: &SqlitePool, key: &str, value: &str) -> anyhow::Resu
     .await?;
   Ok(())
 }
+
+pub async fn delete_sync(pool: &SqlitePool, key: &str) -> anyhow::Result<()> {
+  sqlx::query("DELETE FROM sync_state WHERE key = ?")
+    .bind(key)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
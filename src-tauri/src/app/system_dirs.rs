@@ -0,0 +1,220 @@
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use sqlx_sqlite::SqlitePool;
+
+use crate::app::{dirs, indexer, sync};
+use crate::sqlx::{self, Row};
+use crate::telegram::{ChatId, TelegramService};
+
+/// Служебные папки, которые приложение создает само. Имена настраиваются
+/// (локализация, «мягкое» переименование), а папка находится по
+/// сохраненному id, поэтому смена имени не плодит дубликаты.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemDir {
+  /// Куда импортируются файлы без тегов и правил.
+  Unassigned,
+  /// Стикеры, GIF и кружки.
+  Collections,
+  /// Быстрые заметки и файлы, отправленные «себе».
+  Inbox,
+  /// Заглушка для папки, чье сообщение еще не найдено в канале.
+  Unknown
+}
+
+impl SystemDir {
+  pub fn key(self) -> &'static str {
+    match self {
+      SystemDir::Unassigned => "unassigned",
+      SystemDir::Collections => "collections",
+      SystemDir::Inbox => "inbox",
+      SystemDir::Unknown => "unknown"
+    }
+  }
+
+  pub fn default_name(self) -> &'static str {
+    match self {
+      SystemDir::Unassigned => "Неразобранное",
+      SystemDir::Collections => "Стикеры и GIF",
+      SystemDir::Inbox => "Входящие",
+      SystemDir::Unknown => "Неизвестная папка"
+    }
+  }
+
+  /// Папки с сообщением в канале; заглушки живут только в базе.
+  pub const RENAMABLE: [SystemDir; 3] = [SystemDir::Unassigned, SystemDir::Collections, SystemDir::Inbox];
+
+  fn id_key(self) -> String {
+    format!("system_dir_id:{}", self.key())
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SystemDirNames {
+  pub unassigned: String,
+  pub collections: String,
+  pub inbox: String,
+  pub unknown: String
+}
+
+impl Default for SystemDirNames {
+  fn default() -> Self {
+    Self {
+      unassigned: SystemDir::Unassigned.default_name().to_string(),
+      collections: SystemDir::Collections.default_name().to_string(),
+      inbox: SystemDir::Inbox.default_name().to_string(),
+      unknown: SystemDir::Unknown.default_name().to_string()
+    }
+  }
+}
+
+impl SystemDirNames {
+  /// Пустые имена заменяются стандартными.
+  pub fn sanitized(self) -> SystemDirNames {
+    let pick = |value: String, dir: SystemDir| {
+      let trimmed = value.trim();
+      if trimmed.is_empty() { dir.default_name().to_string() } else { trimmed.to_string() }
+    };
+    SystemDirNames {
+      unassigned: pick(self.unassigned, SystemDir::Unassigned),
+      collections: pick(self.collections, SystemDir::Collections),
+      inbox: pick(self.inbox, SystemDir::Inbox),
+      unknown: pick(self.unknown, SystemDir::Unknown)
+    }
+  }
+
+  pub fn get(&self, dir: SystemDir) -> &str {
+    match dir {
+      SystemDir::Unassigned => &self.unassigned,
+      SystemDir::Collections => &self.collections,
+      SystemDir::Inbox => &self.inbox,
+      SystemDir::Unknown => &self.unknown
+    }
+  }
+}
+
+static ACTIVE: Lazy<RwLock<SystemDirNames>> = Lazy::new(|| RwLock::new(SystemDirNames::default()));
+
+pub fn set_active(names: SystemDirNames) {
+  *ACTIVE.write() = names.sanitized();
+}
+
+pub fn name(dir: SystemDir) -> String {
+  ACTIVE.read().get(dir).to_string()
+}
+
+/// Имя заглушки: текущее или стандартное (заглушки могли появиться до смены).
+pub fn is_placeholder_name(candidate: &str) -> bool {
+  candidate == SystemDir::Unknown.default_name() || ACTIVE.read().unknown == candidate
+}
+
+/// id служебной папки. Для баз до появления настройки папка ищется по
+/// текущему или стандартному имени и запоминается.
+pub async fn resolve_id(pool: &SqlitePool, dir: SystemDir) -> anyhow::Result<Option<String>> {
+  if let Some(id) = sync::get_sync(pool, &dir.id_key()).await? {
+    if dirs::dir_exists(pool, &id).await? {
+      return Ok(Some(id));
+    }
+  }
+  for candidate in [name(dir), dir.default_name().to_string()] {
+    if let Some((id, _)) = indexer::find_dir_by_name(pool, &candidate).await? {
+      sync::set_sync(pool, &dir.id_key(), &id).await?;
+      return Ok(Some(id));
+    }
+  }
+  Ok(None)
+}
+
+/// Находит или создает служебную папку; возвращает id и текущее имя.
+pub async fn ensure(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  storage_chat_id: ChatId,
+  dir: SystemDir
+) -> anyhow::Result<(String, String)> {
+  if let Some(id) = resolve_id(pool, dir).await? {
+    let row = sqlx::query("SELECT name FROM directories WHERE id = ?")
+      .bind(&id)
+      .fetch_one(pool)
+      .await?;
+    return Ok((id, row.get("name")));
+  }
+  let current = name(dir);
+  let id = dirs::create_dir(pool, tg, storage_chat_id, None, current.clone()).await?;
+  sync::set_sync(pool, &dir.id_key(), &id).await?;
+  Ok((id, current))
+}
+
+/// Как `ensure`, но новая папка создается только в базе, без сообщения в канале.
+pub async fn ensure_local(pool: &SqlitePool, dir: SystemDir) -> anyhow::Result<(String, String)> {
+  if let Some(id) = resolve_id(pool, dir).await? {
+    let row = sqlx::query("SELECT name FROM directories WHERE id = ?")
+      .bind(&id)
+      .fetch_one(pool)
+      .await?;
+    return Ok((id, row.get("name")));
+  }
+  let current = name(dir);
+  let id = dirs::create_local_dir(pool, None, &current).await?;
+  sync::set_sync(pool, &dir.id_key(), &id).await?;
+  Ok((id, current))
+}
+
+/// Применяет новые имена к существующим папкам: служебные переименовываются
+/// вместе с сообщением в канале, заглушки — только в базе.
+pub async fn rename_existing(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  storage_chat_id: ChatId,
+  old: &SystemDirNames,
+  new: &SystemDirNames
+) -> anyhow::Result<u64> {
+  let mut renamed = 0;
+  for dir in SystemDir::RENAMABLE {
+    if old.get(dir) == new.get(dir) {
+      continue;
+    }
+    if let Some(id) = resolve_id(pool, dir).await? {
+      dirs::rename_dir(pool, tg, storage_chat_id, &id, new.get(dir).to_string()).await?;
+      renamed += 1;
+    }
+  }
+  if old.unknown != new.unknown {
+    renamed += sqlx::query("UPDATE directories SET name = ? WHERE tg_msg_id IS NULL AND (name = ? OR name = ?)")
+      .bind(&new.unknown)
+      .bind(&old.unknown)
+      .bind(SystemDir::Unknown.default_name())
+      .execute(pool)
+      .await?
+      .rows_affected();
+  }
+  Ok(renamed)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[tokio::test]
+  async fn legacy_folder_is_found_by_name_and_kept_by_id() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('u1', NULL, 'Неразобранное', 5, 0)")
+      .execute(pool)
+      .await?;
+
+    assert_eq!(resolve_id(pool, SystemDir::Unassigned).await?.as_deref(), Some("u1"));
+    sqlx::query("UPDATE directories SET name = 'Unsorted' WHERE id = 'u1'").execute(pool).await?;
+    assert_eq!(resolve_id(pool, SystemDir::Unassigned).await?.as_deref(), Some("u1"));
+
+    let names = SystemDirNames { unassigned: "  ".into(), collections: "Stickers".into(), inbox: "Inbox".into(), unknown: "".into() }.sanitized();
+    assert_eq!(names.unassigned, "Неразобранное");
+    assert_eq!(names.collections, "Stickers");
+    assert_eq!(names.unknown, "Неизвестная папка");
+    Ok(())
+  }
+}
@@ -0,0 +1,540 @@
+use std::path::{Path, PathBuf};
+
+use crate::sqlx::{self, Row};
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use sqlx_sqlite::SqlitePool;
+
+use crate::paths::Paths;
+use crate::telegram::{ChatId, TelegramService};
+
+use crate::app::{files, local_copies};
+use crate::app::open_guard::extension;
+
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "bmp"];
+/// Больше этого PDF ради миниатюры не скачиваем: первая страница
+/// рисуется, только если файл уже лежит локально.
+const MAX_REMOTE_PDF_BYTES: i64 = 32 * 1024 * 1024;
+/// То же для HEIC: снимки с телефона обычно укладываются в несколько
+/// мегабайт, крупнее бывают серии и панорамы.
+const MAX_REMOTE_HEIC_BYTES: i64 = 16 * 1024 * 1024;
+/// Сколько недавно скачанных файлов просматривает прогрев после запуска.
+const WARM_LIMIT: i64 = 64;
+
+/// Размер миниатюры: для сетки файлов и для просмотра во весь экран.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbnailSize {
+  #[default]
+  Grid,
+  Lightbox
+}
+
+impl ThumbnailSize {
+  /// Сторона квадрата, в который вписывается миниатюра.
+  pub fn max_side(self) -> u32 {
+    match self {
+      ThumbnailSize::Grid => 256,
+      ThumbnailSize::Lightbox => 1280
+    }
+  }
+
+  fn as_str(self) -> &'static str {
+    match self {
+      ThumbnailSize::Grid => "grid",
+      ThumbnailSize::Lightbox => "lightbox"
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SourceKind {
+  Image,
+  Heic,
+  Pdf
+}
+
+impl SourceKind {
+  /// Предел размера, до которого файл можно скачать ради миниатюры.
+  fn download_limit(self) -> Option<i64> {
+    match self {
+      SourceKind::Pdf => Some(MAX_REMOTE_PDF_BYTES),
+      SourceKind::Heic => Some(MAX_REMOTE_HEIC_BYTES),
+      SourceKind::Image => None
+    }
+  }
+}
+
+fn source_kind(name: &str) -> Option<SourceKind> {
+  let ext = extension(name)?;
+  if IMAGE_EXTENSIONS.contains(&ext.as_str()) {
+    Some(SourceKind::Image)
+  } else if (ext == "heic" || ext == "heif") && heic::available() {
+    Some(SourceKind::Heic)
+  } else if ext == "pdf" && pdf::available() {
+    Some(SourceKind::Pdf)
+  } else {
+    None
+  }
+}
+
+/// Имя миниатюры включает хэш содержимого: после замены файла старая
+/// миниатюра просто перестает находиться и заменяется новой.
+fn thumbnail_name(size: ThumbnailSize, hash: &str) -> String {
+  format!("{}-{hash}.png", size.as_str())
+}
+
+/// PNG-миниатюра файла в `cache_dir/thumbnails/<file_id>`. Для картинок —
+/// уменьшенная копия с учетом поворота из EXIF, для PDF — первая страница.
+/// `None`, если для такого файла миниатюр не бывает или он слишком велик,
+/// чтобы скачивать его ради миниатюры. Файл при необходимости скачивается.
+pub async fn file_thumbnail(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  paths: &Paths,
+  storage_chat_id: ChatId,
+  file_id: &str,
+  size: ThumbnailSize
+) -> anyhow::Result<Option<PathBuf>> {
+  let row = sqlx::query("SELECT name, size, hash, content_hash FROM files WHERE id = ?")
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("Файл не найден"))?;
+  let name: String = row.get("name");
+  let Some(kind) = source_kind(&name) else {
+    return Ok(None);
+  };
+  let hash = row.try_get::<String,_>("content_hash").unwrap_or_else(|_| row.get("hash"));
+  let dir = paths.layout().thumbnails_dir(file_id);
+  let target = dir.join(thumbnail_name(size, &hash));
+  if target.exists() {
+    return Ok(Some(target));
+  }
+  let source = match local_copies::lookup(pool, paths, file_id).await? {
+    Some(copy) => copy.path,
+    None if kind.download_limit().is_some_and(|limit| row.get::<i64, _>("size") > limit) => return Ok(None),
+    None => files::download_file(pool, tg, paths, storage_chat_id, file_id, false, None).await?
+  };
+  render_into(&dir, &target, kind, source, size).await?;
+  Ok(Some(target))
+}
+
+/// Заранее рисует миниатюры сетки для недавно скачанных файлов, у которых их
+/// еще нет. Только из локальных копий: ради прогрева ничего не скачивается.
+/// Возвращает число нарисованных миниатюр.
+pub async fn warm_local(pool: &SqlitePool, paths: &Paths) -> anyhow::Result<usize> {
+  let rows = sqlx::query(
+    "SELECT f.id, f.name, f.hash, f.content_hash FROM local_copies l JOIN files f ON f.id = l.file_id
+     ORDER BY l.recorded_at DESC LIMIT ?"
+  )
+    .bind(WARM_LIMIT)
+    .fetch_all(pool)
+    .await?;
+  let size = ThumbnailSize::Grid;
+  let mut rendered = 0;
+  for row in rows {
+    let file_id: String = row.get("id");
+    let Some(kind) = source_kind(&row.get::<String, _>("name")) else {
+      continue;
+    };
+    let hash = row.try_get::<String,_>("content_hash").unwrap_or_else(|_| row.get("hash"));
+    let dir = paths.layout().thumbnails_dir(&file_id);
+    let target = dir.join(thumbnail_name(size, &hash));
+    if target.exists() {
+      continue;
+    }
+    let Some(copy) = local_copies::lookup(pool, paths, &file_id).await? else {
+      continue;
+    };
+    match render_into(&dir, &target, kind, copy.path, size).await {
+      Ok(()) => rendered += 1,
+      Err(e) => tracing::debug!(event = "thumbnail_warm_failed", file_id = file_id.as_str(), error = %e, "Не удалось заранее нарисовать миниатюру")
+    }
+  }
+  Ok(rendered)
+}
+
+async fn render_into(dir: &Path, target: &Path, kind: SourceKind, source: PathBuf, size: ThumbnailSize) -> anyhow::Result<()> {
+  std::fs::create_dir_all(dir)?;
+  remove_stale(dir, size);
+  let out = target.to_path_buf();
+  tokio::task::spawn_blocking(move || render(kind, &source, &out, size.max_side())).await?
+}
+
+/// Удаляет миниатюры файла, например после его удаления.
+pub fn forget(paths: &Paths, file_id: &str) {
+  let _ = std::fs::remove_dir_all(paths.layout().thumbnails_dir(file_id));
+}
+
+/// Миниатюры того же размера от прежнего содержимого.
+fn remove_stale(dir: &Path, size: ThumbnailSize) {
+  let prefix = format!("{}-", size.as_str());
+  let Ok(entries) = std::fs::read_dir(dir) else {
+    return;
+  };
+  for entry in entries.flatten() {
+    if entry.file_name().to_string_lossy().starts_with(&prefix) {
+      let _ = std::fs::remove_file(entry.path());
+    }
+  }
+}
+
+fn render(kind: SourceKind, source: &Path, target: &Path, max_side: u32) -> anyhow::Result<()> {
+  let partial = target.with_extension("png.partial");
+  let res = match kind {
+    SourceKind::Image => render_image(source, &partial, max_side),
+    SourceKind::Heic => heic::render(source, &partial, max_side),
+    SourceKind::Pdf => pdf::render_first_page(source, &partial, max_side)
+  };
+  match res {
+    Ok(()) => {
+      std::fs::rename(&partial, target)?;
+      Ok(())
+    }
+    Err(e) => {
+      let _ = std::fs::remove_file(&partial);
+      Err(e)
+    }
+  }
+}
+
+fn render_image(source: &Path, target: &Path, max_side: u32) -> anyhow::Result<()> {
+  let image = decode_oriented(source).map_err(|e| anyhow::anyhow!("Не удалось прочитать картинку: {e}"))?;
+  // Маленькие картинки не растягиваем.
+  let image = if image.width() > max_side || image.height() > max_side {
+    image.thumbnail(max_side, max_side)
+  } else {
+    image
+  };
+  image.save_with_format(target, ImageFormat::Png)?;
+  Ok(())
+}
+
+/// Декодирует картинку и поворачивает ее так, как записано в EXIF.
+fn decode_oriented(source: &Path) -> image::ImageResult<DynamicImage> {
+  let mut decoder = ImageReader::open(source)?.with_guessed_format()?.into_decoder()?;
+  let orientation = decoder.orientation()?;
+  let mut image = DynamicImage::from_decoder(decoder)?;
+  image.apply_orientation(orientation);
+  Ok(image)
+}
+
+/// Запуск внешних конвертеров. Им достаются недоверенные файлы из
+/// хранилища, поэтому процесс ограничен по времени, памяти, процессорному
+/// времени и размеру записываемых файлов, а в Windows запускается без окна.
+#[cfg_attr(not(any(feature = "pdf_preview", feature = "heic")), allow(dead_code))]
+mod converter {
+  use std::path::Path;
+  use std::process::{Command, Stdio};
+  use std::time::{Duration, Instant};
+
+  const TIMEOUT: Duration = Duration::from_secs(20);
+  const POLL: Duration = Duration::from_millis(50);
+  /// Сколько stderr показывать в ошибке.
+  const MAX_STDERR: usize = 4096;
+  #[cfg(unix)]
+  const MAX_MEMORY_BYTES: u64 = 1024 * 1024 * 1024;
+  #[cfg(unix)]
+  const MAX_CPU_SECS: u64 = 20;
+  #[cfg(unix)]
+  const MAX_OUTPUT_BYTES: u64 = 128 * 1024 * 1024;
+
+  /// Есть ли конвертер: явный путь проверяется как есть, имя ищется в PATH.
+  /// Без него у файлов просто нет миниатюр, а не ошибка на каждом файле.
+  pub fn installed(bin: &str) -> bool {
+    let path = Path::new(bin);
+    if path.components().count() > 1 {
+      return path.is_file();
+    }
+    let Some(dirs) = std::env::var_os("PATH") else {
+      return false;
+    };
+    std::env::split_paths(&dirs).any(|dir| {
+      let candidate = dir.join(bin);
+      candidate.is_file() || (cfg!(windows) && candidate.with_extension("exe").is_file())
+    })
+  }
+
+  /// Запускает конвертер и ждет его не дольше `TIMEOUT`. `work_dir` —
+  /// временный каталог конвертации, туда же пишется stderr.
+  pub fn run(cmd: Command, name: &str, work_dir: &Path) -> anyhow::Result<()> {
+    run_for(cmd, name, work_dir, TIMEOUT)
+  }
+
+  pub(super) fn run_for(mut cmd: Command, name: &str, work_dir: &Path, timeout: Duration) -> anyhow::Result<()> {
+    let stderr_path = work_dir.join("stderr.log");
+    cmd
+      .stdin(Stdio::null())
+      .stdout(Stdio::null())
+      .stderr(std::fs::File::create(&stderr_path)?);
+    restrict(&mut cmd);
+    let mut child = cmd.spawn().map_err(|e| anyhow::anyhow!("Не удалось запустить {name}: {e}"))?;
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+      if let Some(status) = child.try_wait()? {
+        break status;
+      }
+      if Instant::now() >= deadline {
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err(anyhow::anyhow!("{name} не уложился в {} с", timeout.as_secs()));
+      }
+      std::thread::sleep(POLL);
+    };
+    if !status.success() {
+      let stderr = std::fs::read(&stderr_path).unwrap_or_default();
+      let stderr = String::from_utf8_lossy(&stderr[..stderr.len().min(MAX_STDERR)]);
+      return Err(anyhow::anyhow!("{name} завершился с ошибкой: {}", stderr.trim()));
+    }
+    Ok(())
+  }
+
+  #[cfg(unix)]
+  fn restrict(cmd: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    // SAFETY: между fork и exec вызываются только getrlimit/setrlimit,
+    // они безопасны для асинхронных сигналов и ничего не выделяют.
+    unsafe {
+      cmd.pre_exec(|| {
+        let limits = [
+          (libc::RLIMIT_AS, MAX_MEMORY_BYTES),
+          (libc::RLIMIT_CPU, MAX_CPU_SECS),
+          (libc::RLIMIT_FSIZE, MAX_OUTPUT_BYTES)
+        ];
+        for (resource, value) in limits {
+          let mut current = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+          if libc::getrlimit(resource, &mut current) != 0 {
+            return Err(std::io::Error::last_os_error());
+          }
+          let value = (value as libc::rlim_t).min(current.rlim_max);
+          let limit = libc::rlimit { rlim_cur: value, rlim_max: value };
+          if libc::setrlimit(resource, &limit) != 0 {
+            return Err(std::io::Error::last_os_error());
+          }
+        }
+        Ok(())
+      });
+    }
+  }
+
+  #[cfg(windows)]
+  fn restrict(cmd: &mut Command) {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+    cmd.creation_flags(CREATE_NO_WINDOW);
+  }
+
+  #[cfg(not(any(unix, windows)))]
+  fn restrict(_cmd: &mut Command) {}
+}
+
+#[cfg(feature = "heic")]
+mod heic {
+  use std::path::Path;
+  use std::process::Command;
+
+  use once_cell::sync::Lazy;
+
+  use super::{converter, render_image};
+
+  static INSTALLED: Lazy<bool> = Lazy::new(|| {
+    let installed = converter::installed(&bin());
+    if !installed {
+      tracing::info!(event = "heic_converter_missing", bin = %bin(), "heif-convert не найден, миниатюр HEIC не будет");
+    }
+    installed
+  });
+
+  /// Переводит HEIC в PNG через `heif-convert` из libheif (пакет
+  /// libheif-examples). Путь к бинарнику можно задать через
+  /// CLOUDTG_HEIF_CONVERT_BIN. Без него миниатюр HEIC нет.
+  pub fn available() -> bool {
+    *INSTALLED
+  }
+
+  fn bin() -> String {
+    std::env::var("CLOUDTG_HEIF_CONVERT_BIN").unwrap_or_else(|_| "heif-convert".to_string())
+  }
+
+  pub fn render(source: &Path, target: &Path, max_side: u32) -> anyhow::Result<()> {
+    let bin = bin();
+    let tmp = tempfile::tempdir()?;
+    let converted = tmp.path().join("image.png");
+    let mut cmd = Command::new(&bin);
+    cmd.arg(source).arg(&converted);
+    converter::run(cmd, "heif-convert", tmp.path())?;
+    render_image(&converted, target, max_side)
+  }
+}
+
+#[cfg(not(feature = "heic"))]
+mod heic {
+  use std::path::Path;
+
+  pub fn available() -> bool {
+    false
+  }
+
+  pub fn render(_source: &Path, _target: &Path, _max_side: u32) -> anyhow::Result<()> {
+    Err(anyhow::anyhow!("HEIC недоступен в этой сборке"))
+  }
+}
+
+#[cfg(feature = "pdf_preview")]
+mod pdf {
+  use std::path::Path;
+  use std::process::Command;
+
+  use once_cell::sync::Lazy;
+
+  use super::{converter, render_image};
+
+  static INSTALLED: Lazy<bool> = Lazy::new(|| {
+    let installed = converter::installed(&bin());
+    if !installed {
+      tracing::info!(event = "pdf_renderer_missing", bin = %bin(), "pdftoppm не найден, миниатюр PDF не будет");
+    }
+    installed
+  });
+
+  /// Рендерит через `pdftoppm` из poppler (пакет poppler-utils). Путь к
+  /// бинарнику можно задать через CLOUDTG_PDFTOPPM_BIN. Без него миниатюр
+  /// PDF нет. Рисуется только первая страница и не крупнее двойного
+  /// размера миниатюры.
+  pub fn available() -> bool {
+    *INSTALLED
+  }
+
+  fn bin() -> String {
+    std::env::var("CLOUDTG_PDFTOPPM_BIN").unwrap_or_else(|_| "pdftoppm".to_string())
+  }
+
+  pub fn render_first_page(source: &Path, target: &Path, max_side: u32) -> anyhow::Result<()> {
+    let bin = bin();
+    let tmp = tempfile::tempdir()?;
+    let prefix = tmp.path().join("page");
+    let mut cmd = Command::new(&bin);
+    cmd
+      .args(["-png", "-f", "1", "-l", "1", "-singlefile", "-scale-to"])
+      .arg((max_side * 2).to_string())
+      .arg(source)
+      .arg(&prefix);
+    converter::run(cmd, "pdftoppm", tmp.path())?;
+    render_image(&prefix.with_extension("png"), target, max_side)
+  }
+}
+
+#[cfg(not(feature = "pdf_preview"))]
+mod pdf {
+  use std::path::Path;
+
+  pub fn available() -> bool {
+    false
+  }
+
+  pub fn render_first_page(_source: &Path, _target: &Path, _max_side: u32) -> anyhow::Result<()> {
+    Err(anyhow::anyhow!("Миниатюры PDF недоступны в этой сборке"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn image_thumbnail_fits_the_box() -> anyhow::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let source = tmp.path().join("wide.png");
+    image::RgbaImage::from_pixel(1024, 512, image::Rgba([10, 20, 30, 255])).save(&source)?;
+    let grid = ThumbnailSize::Grid.max_side();
+    let target = tmp.path().join("thumb.png");
+    render(SourceKind::Image, &source, &target, grid)?;
+    let thumb = image::open(&target)?;
+    assert_eq!((thumb.width(), thumb.height()), (grid, grid / 2));
+    assert!(!target.with_extension("png.partial").exists());
+
+    // Картинка меньше просмотра остается своего размера.
+    let lightbox = tmp.path().join("lightbox.png");
+    render(SourceKind::Image, &source, &lightbox, ThumbnailSize::Lightbox.max_side())?;
+    assert_eq!(image::open(&lightbox)?.width(), 1024);
+
+    std::fs::write(&source, b"not an image")?;
+    assert!(render(SourceKind::Image, &source, &tmp.path().join("bad.png"), grid).is_err());
+    assert!(!tmp.path().join("bad.png.partial").exists());
+
+    assert_eq!(source_kind("Фото.JPG"), Some(SourceKind::Image));
+    assert_eq!(source_kind("notes.txt"), None);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn warm_renders_missing_grid_thumbnails_from_local_copies() -> anyhow::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let paths = Paths::from_base(tmp.path().to_path_buf());
+    paths.ensure_dirs()?;
+    let db = crate::db::Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d', NULL, 'Фото', NULL, 0)")
+      .execute(pool)
+      .await?;
+    for (msg_id, (id, name)) in [("img", "cat.png"), ("remote", "dog.png"), ("doc", "notes.txt")].into_iter().enumerate() {
+      sqlx::query(
+        "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at) VALUES(?, 'd', ?, 1, 'h', 1, ?, 0)"
+      )
+        .bind(id)
+        .bind(name)
+        .bind(msg_id as i64)
+        .execute(pool)
+        .await?;
+    }
+    let dir = paths.layout().downloads_dir().join("Фото");
+    std::fs::create_dir_all(&dir)?;
+    let image = dir.join("cat.png");
+    image::RgbaImage::from_pixel(512, 512, image::Rgba([1, 2, 3, 255])).save(&image)?;
+    local_copies::record(pool, &paths, "img", &image).await?;
+    let text = dir.join("notes.txt");
+    std::fs::write(&text, b"text")?;
+    local_copies::record(pool, &paths, "doc", &text).await?;
+
+    assert_eq!(warm_local(pool, &paths).await?, 1);
+    assert!(paths.layout().thumbnails_dir("img").join(thumbnail_name(ThumbnailSize::Grid, "h")).exists());
+    assert!(!paths.layout().thumbnails_dir("remote").exists());
+    assert_eq!(warm_local(pool, &paths).await?, 0);
+    Ok(())
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn converter_is_killed_after_timeout_and_reports_failures() -> anyhow::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let started = std::time::Instant::now();
+    let mut slow = std::process::Command::new("sh");
+    slow.args(["-c", "sleep 60"]);
+    let err = converter::run_for(slow, "sh", tmp.path(), std::time::Duration::from_millis(200)).unwrap_err();
+    assert!(err.to_string().contains("не уложился"));
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+
+    let mut failing = std::process::Command::new("sh");
+    failing.args(["-c", "echo broken >&2; exit 3"]);
+    let err = converter::run(failing, "sh", tmp.path()).unwrap_err();
+    assert!(err.to_string().contains("broken"));
+
+    // Отсутствующий конвертер означает «без миниатюр», а не ошибку запуска.
+    assert!(converter::installed("sh"));
+    assert!(!converter::installed("cloudtg-no-such-converter"));
+    assert!(!converter::installed(&tmp.path().join("pdftoppm").to_string_lossy()));
+    Ok(())
+  }
+
+  #[test]
+  fn stale_thumbnails_of_the_same_size_are_removed() -> anyhow::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    std::fs::write(tmp.path().join(thumbnail_name(ThumbnailSize::Grid, "old")), b"x")?;
+    std::fs::write(tmp.path().join(thumbnail_name(ThumbnailSize::Lightbox, "old")), b"x")?;
+    remove_stale(tmp.path(), ThumbnailSize::Grid);
+    assert!(!tmp.path().join("grid-old.png").exists());
+    assert!(tmp.path().join("lightbox-old.png").exists());
+    Ok(())
+  }
+}
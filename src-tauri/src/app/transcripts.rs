@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::Utc;
+use crate::sqlx::{self, Row};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use sqlx_sqlite::SqlitePool;
+
+use crate::paths::Paths;
+use crate::telegram::{ChatId, TelegramService};
+
+/// Откуда взят текст: распознавание Telegram (Premium) или локальный whisper.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptSource {
+  Telegram,
+  Local
+}
+
+impl TranscriptSource {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      TranscriptSource::Telegram => "telegram",
+      TranscriptSource::Local => "local"
+    }
+  }
+
+  pub fn parse(raw: &str) -> Option<TranscriptSource> {
+    match raw.trim() {
+      "telegram" => Some(TranscriptSource::Telegram),
+      "local" => Some(TranscriptSource::Local),
+      _ => None
+    }
+  }
+
+  /// Локальное распознавание есть только в сборке с фичей `local_whisper`.
+  pub fn is_available(self) -> bool {
+    match self {
+      TranscriptSource::Telegram => true,
+      TranscriptSource::Local => cfg!(feature = "local_whisper")
+    }
+  }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Transcript {
+  pub file_id: String,
+  pub source: String,
+  pub text: String,
+  pub created_at: i64
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TranscriptHit {
+  pub file_id: String,
+  pub snippet: String
+}
+
+/// Идущие расшифровки по id файла: повторный запрос ждет первый и берет его
+/// результат, а не распознает файл еще раз.
+static IN_FLIGHT: Lazy<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Расшифровывать имеет смысл только голосовые и видеосообщения.
+pub fn is_transcribable(media_kind: Option<&str>) -> bool {
+  matches!(media_kind, Some("voice_note") | Some("video_note"))
+}
+
+pub async fn transcribe_file(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  paths: &Paths,
+  storage_chat_id: ChatId,
+  file_id: &str,
+  source: TranscriptSource
+) -> anyhow::Result<Transcript> {
+  let started = Utc::now().timestamp();
+  let slot = IN_FLIGHT.lock().entry(file_id.to_string()).or_default().clone();
+  let result = {
+    let _guard = slot.lock().await;
+    match get_transcript(pool, file_id).await? {
+      Some(existing) if existing.source == source.as_str() && existing.created_at >= started => Ok(existing),
+      _ => transcribe_uncached(pool, tg, paths, storage_chat_id, file_id, source).await
+    }
+  };
+  let mut in_flight = IN_FLIGHT.lock();
+  if Arc::strong_count(&slot) <= 2 {
+    in_flight.remove(file_id);
+  }
+  result
+}
+
+async fn transcribe_uncached(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  paths: &Paths,
+  storage_chat_id: ChatId,
+  file_id: &str,
+  source: TranscriptSource
+) -> anyhow::Result<Transcript> {
+  let row = sqlx::query("SELECT tg_chat_id, tg_msg_id, media_kind FROM files WHERE id = ?")
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?;
+  let Some(row) = row else {
+    return Err(anyhow::anyhow!("Файл не найден"));
+  };
+  let media_kind: Option<String> = row.try_get("media_kind").ok();
+  if !is_transcribable(media_kind.as_deref()) {
+    return Err(anyhow::anyhow!("Расшифровка доступна только для голосовых и видеосообщений"));
+  }
+
+  let text = match source {
+    TranscriptSource::Telegram => {
+      let chat_id: i64 = row.get("tg_chat_id");
+      let msg_id: i64 = row.get("tg_msg_id");
+      tg.recognize_speech(chat_id, msg_id)
+        .await
+        .map_err(|e| anyhow::anyhow!("Не удалось получить расшифровку от Telegram: {e}"))?
+    }
+    TranscriptSource::Local => {
+      let path = super::files::download_file(pool, tg, paths, storage_chat_id, file_id, false, None).await?;
+      local_whisper::transcribe(path).await?
+    }
+  };
+
+  let text = text.trim().to_string();
+  if text.is_empty() {
+    return Err(anyhow::anyhow!("Речь не распознана"));
+  }
+  store_transcript(pool, file_id, source, &text).await?;
+  tracing::info!(event = "transcript_stored", file_id = file_id, source = source.as_str(), "Расшифровка сохранена");
+  get_transcript(pool, file_id)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("Расшифровка не найдена"))
+}
+
+pub async fn store_transcript(
+  pool: &SqlitePool,
+  file_id: &str,
+  source: TranscriptSource,
+  text: &str
+) -> anyhow::Result<()> {
+  // Текст попадает в file_search (и в его FTS) через триггеры таблицы.
+  sqlx::query(
+    "INSERT INTO file_transcripts(file_id, source, text, created_at) VALUES(?, ?, ?, ?)
+     ON CONFLICT(file_id) DO UPDATE SET source=excluded.source, text=excluded.text, created_at=excluded.created_at"
+  )
+    .bind(file_id)
+    .bind(source.as_str())
+    .bind(text)
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+pub async fn get_transcript(pool: &SqlitePool, file_id: &str) -> anyhow::Result<Option<Transcript>> {
+  let row = sqlx::query("SELECT file_id, source, text, created_at FROM file_transcripts WHERE file_id = ?")
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?;
+  Ok(row.map(|r| Transcript {
+    file_id: r.get("file_id"),
+    source: r.get("source"),
+    text: r.get("text"),
+    created_at: r.get("created_at")
+  }))
+}
+
+/// Полнотекстовый поиск по расшифровкам; каждое слово запроса ищется по префиксу.
+/// Ищет в общем индексе file_search, только по колонке расшифровки.
+pub async fn search_transcripts(pool: &SqlitePool, query: &str, limit: i64) -> anyhow::Result<Vec<TranscriptHit>> {
+  let Some(fts) = fts_query(query) else {
+    return Ok(Vec::new());
+  };
+  let rows = sqlx::query(
+    "SELECT s.file_id, snippet(file_search_fts, 3, '[', ']', '…', 12) AS snippet
+     FROM file_search_fts
+     JOIN file_search s ON s.id = file_search_fts.rowid
+     WHERE file_search_fts MATCH ?
+     ORDER BY rank
+     LIMIT ?"
+  )
+    .bind(format!("transcript : ({fts})"))
+    .bind(limit.clamp(1, 200))
+    .fetch_all(pool)
+    .await?;
+  Ok(rows.into_iter().map(|r| TranscriptHit {
+    file_id: r.get("file_id"),
+    snippet: r.get("snippet")
+  }).collect())
+}
+
+/// Пользовательский ввод не должен попадать в синтаксис FTS5 как есть:
+/// слова берутся в кавычки, операторы и скобки теряют смысл.
+pub(crate) fn fts_query(raw: &str) -> Option<String> {
+  let terms: Vec<String> = raw
+    .split_whitespace()
+    .map(|w| w.replace('"', ""))
+    .filter(|w| !w.is_empty())
+    .map(|w| format!("\"{w}\"*"))
+    .collect();
+  if terms.is_empty() {
+    None
+  } else {
+    Some(terms.join(" "))
+  }
+}
+
+#[cfg(feature = "local_whisper")]
+mod local_whisper {
+  use std::path::PathBuf;
+  use std::process::Command;
+
+  /// Запускает whisper.cpp (`whisper-cli`) на скачанном файле. Бинарник и модель
+  /// задаются через CLOUDTG_WHISPER_BIN и CLOUDTG_WHISPER_MODEL.
+  pub async fn transcribe(path: PathBuf) -> anyhow::Result<String> {
+    let bin = std::env::var("CLOUDTG_WHISPER_BIN").unwrap_or_else(|_| "whisper-cli".to_string());
+    let model = std::env::var("CLOUDTG_WHISPER_MODEL")
+      .map_err(|_| anyhow::anyhow!("Не задана модель whisper (CLOUDTG_WHISPER_MODEL)"))?;
+    tokio::task::spawn_blocking(move || {
+      let output = Command::new(&bin)
+        .arg("-m").arg(&model)
+        .arg("-f").arg(&path)
+        .args(["-l", "auto", "-nt", "-np"])
+        .output()
+        .map_err(|e| anyhow::anyhow!("Не удалось запустить {bin}: {e}"))?;
+      if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow::anyhow!("whisper завершился с ошибкой: {}", stderr.trim()));
+      }
+      Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    })
+    .await?
+  }
+}
+
+#[cfg(not(feature = "local_whisper"))]
+mod local_whisper {
+  use std::path::PathBuf;
+
+  pub async fn transcribe(_path: PathBuf) -> anyhow::Result<String> {
+    Err(anyhow::anyhow!("Локальное распознавание недоступно в этой сборке"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[test]
+  fn fts_query_quotes_user_input() {
+    assert_eq!(fts_query("  купить \"молоко\" OR"), Some("\"купить\"* \"молоко\"* \"OR\"*".to_string()));
+    assert_eq!(fts_query("   "), None);
+  }
+
+  #[tokio::test]
+  async fn stored_transcript_is_searchable_and_replaced() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d', NULL, 'Голос', NULL, 0)")
+      .execute(pool)
+      .await?;
+    sqlx::query(
+      "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at, media_kind)
+       VALUES('f', 'd', 'voice.ogg', 1, 'h', 1, 1, 0, 'voice_note')"
+    )
+      .execute(pool)
+      .await?;
+
+    store_transcript(pool, "f", TranscriptSource::Telegram, "Не забыть купить молоко").await?;
+    let hits = search_transcripts(pool, "молок", 10).await?;
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].file_id, "f");
+
+    store_transcript(pool, "f", TranscriptSource::Local, "Позвонить врачу").await?;
+    assert!(search_transcripts(pool, "молоко", 10).await?.is_empty());
+    assert_eq!(search_transcripts(pool, "врачу", 10).await?.len(), 1);
+    Ok(())
+  }
+}
@@ -0,0 +1,228 @@
+use std::collections::HashSet;
+
+use crate::sqlx::{self, QueryBuilder, Row};
+use sqlx_sqlite::SqlitePool;
+
+use crate::fsmeta::{parse_dir_message, parse_file_caption, parse_link_message, parse_part_caption};
+use crate::telegram::{TelegramService, ChatId, HistoryMessage};
+use super::{indexer, sync};
+
+const SCAN_CURSOR_KEY: &str = "unindexed_scan_cursor";
+const PAGE_SIZE: i32 = 100;
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct UnindexedItem {
+  pub message_id: i64,
+  pub date: i64,
+  pub file_name: Option<String>,
+  pub file_size: Option<i64>,
+  pub caption: Option<String>
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct UnindexedPage {
+  pub items: Vec<UnindexedItem>,
+  pub scanned: i64,
+  /// С этого сообщения продолжать скан; 0 — канал просмотрен до конца.
+  pub next_from_message_id: i64,
+  pub done: bool
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct UnindexedImportResult {
+  pub imported: i64,
+  pub skipped: i64,
+  pub file_ids: Vec<String>
+}
+
+/// Просматривает до `limit` сообщений канала, начиная с `from_message_id`
+/// (0 — с последней сохраненной точки или с начала), и возвращает файлы,
+/// которых нет в БД и у которых нет разбираемой подписи. Точка продолжения
+/// сохраняется после каждой страницы, чтобы скан огромного канала можно было прервать.
+pub async fn scan_unindexed(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  storage_chat_id: ChatId,
+  from_message_id: Option<i64>,
+  limit: i64
+) -> anyhow::Result<UnindexedPage> {
+  let cursor_key = format!("{SCAN_CURSOR_KEY}:{storage_chat_id}");
+  let mut from = match from_message_id {
+    Some(id) => id.max(0),
+    None => sync::get_sync(pool, &cursor_key)
+      .await?
+      .and_then(|v| v.parse::<i64>().ok())
+      .unwrap_or(0)
+  };
+  let mut remaining = limit.max(1);
+  let mut scanned = 0i64;
+  let mut items = Vec::new();
+  let mut seen: HashSet<i64> = HashSet::new();
+  let mut done = false;
+
+  while remaining > 0 {
+    let batch = tg.chat_history(storage_chat_id, from, remaining.min(PAGE_SIZE as i64) as i32).await?;
+    let fresh: Vec<HistoryMessage> = batch
+      .messages
+      .into_iter()
+      .filter(|m| seen.insert(m.id))
+      .collect();
+    if fresh.is_empty() {
+      done = true;
+      break;
+    }
+    scanned += fresh.len() as i64;
+    remaining -= fresh.len() as i64;
+
+    let candidates: Vec<&HistoryMessage> = fresh.iter().filter(|m| is_untagged_file(m)).collect();
+    let known = known_message_ids(pool, storage_chat_id, &candidates).await?;
+    items.extend(
+      candidates
+        .into_iter()
+        .filter(|m| !known.contains(&m.id))
+        .map(|m| UnindexedItem {
+          message_id: m.id,
+          date: m.date,
+          file_name: m.file_name.clone(),
+          file_size: m.file_size,
+          caption: m.caption.clone()
+        })
+    );
+
+    if batch.next_from_message_id == 0 || batch.next_from_message_id == from {
+      done = true;
+      break;
+    }
+    from = batch.next_from_message_id;
+  }
+
+  let next_from_message_id = if done { 0 } else { from };
+  sync::set_sync(pool, &cursor_key, &next_from_message_id.to_string()).await?;
+
+  Ok(UnindexedPage { items, scanned, next_from_message_id, done })
+}
+
+/// Импортирует найденные сканом сообщения в папку `dir_id` или, если она не задана,
+/// по тегам подписи и в «Неразобранное».
+pub async fn import_unindexed(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  storage_chat_id: ChatId,
+  message_ids: &[i64],
+  dir_id: Option<&str>
+) -> anyhow::Result<UnindexedImportResult> {
+  let target = match dir_id.filter(|v| !v.trim().is_empty() && *v != "ROOT") {
+    Some(id) => {
+      let row = sqlx::query("SELECT id, name FROM directories WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+      let Some(row) = row else {
+        return Err(anyhow::anyhow!("Папка не найдена"));
+      };
+      Some((row.get::<String,_>("id"), row.get::<String,_>("name")))
+    }
+    None => None
+  };
+
+  let mut unassigned = None;
+  let mut file_ids = Vec::new();
+  let mut skipped = 0i64;
+  for &message_id in message_ids {
+    let Some(msg) = fetch_message(tg, storage_chat_id, message_id).await? else {
+      skipped += 1;
+      continue;
+    };
+    if !is_untagged_file(&msg) {
+      skipped += 1;
+      continue;
+    }
+    match indexer::import_untagged_message(pool, tg, storage_chat_id, &msg, target.clone(), &mut unassigned).await? {
+      Some(file_id) => file_ids.push(file_id),
+      None => skipped += 1
+    }
+  }
+
+  Ok(UnindexedImportResult { imported: file_ids.len() as i64, skipped, file_ids })
+}
+
+fn is_untagged_file(msg: &HistoryMessage) -> bool {
+  let has_file = msg.file_size.is_some()
+    || msg.file_name.as_ref().map(|v| !v.trim().is_empty()).unwrap_or(false);
+  if !has_file {
+    return false;
+  }
+  if msg.caption.as_deref().map(|c| parse_file_caption(c).is_ok() || parse_part_caption(c).is_ok()).unwrap_or(false) {
+    return false;
+  }
+  !msg
+    .text
+    .as_deref()
+    .map(|t| parse_dir_message(t).is_ok() || parse_link_message(t).is_ok())
+    .unwrap_or(false)
+}
+
+async fn known_message_ids(
+  pool: &SqlitePool,
+  storage_chat_id: ChatId,
+  messages: &[&HistoryMessage]
+) -> anyhow::Result<HashSet<i64>> {
+  if messages.is_empty() {
+    return Ok(HashSet::new());
+  }
+  let mut builder = QueryBuilder::new(
+    "SELECT tg_msg_id FROM (
+       SELECT tg_chat_id, tg_msg_id FROM files
+       UNION ALL
+       SELECT tg_chat_id, tg_msg_id FROM file_versions
+       UNION ALL
+       SELECT tg_chat_id, tg_msg_id FROM file_copies
+     ) WHERE tg_chat_id = "
+  );
+  builder.push_bind(storage_chat_id).push(" AND tg_msg_id IN (");
+  let mut separated = builder.separated(", ");
+  for msg in messages {
+    separated.push_bind(msg.id);
+  }
+  separated.push_unseparated(")");
+  let rows = builder.build().fetch_all(pool).await?;
+  Ok(rows.into_iter().map(|r| r.get::<i64,_>("tg_msg_id")).collect())
+}
+
+async fn fetch_message(
+  tg: &dyn TelegramService,
+  chat_id: ChatId,
+  message_id: i64
+) -> anyhow::Result<Option<HistoryMessage>> {
+  // getChatHistory с offset=-1 отдает и само сообщение, и одно более новое.
+  let batch = tg.chat_history(chat_id, message_id, 2).await?;
+  Ok(batch.messages.into_iter().find(|m| m.id == message_id))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn msg(caption: Option<&str>, file_name: Option<&str>) -> HistoryMessage {
+    HistoryMessage {
+      id: 1,
+      date: 0,
+      text: None,
+      caption: caption.map(str::to_string),
+      file_size: file_name.map(|_| 10),
+      file_name: file_name.map(str::to_string),
+      sender_id: None,
+      media_group_id: None,
+      content_type: Some("messageDocument".to_string()),
+      sticker_set_id: None,
+      file_unique_id: None
+    }
+  }
+
+  #[test]
+  fn untagged_file_requires_file_without_fsmeta() {
+    assert!(is_untagged_file(&msg(None, Some("photo.jpg"))));
+    assert!(is_untagged_file(&msg(Some("#отпуск"), Some("photo.jpg"))));
+    assert!(!is_untagged_file(&msg(None, None)));
+  }
+}
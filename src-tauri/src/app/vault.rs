@@ -0,0 +1,294 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use sha2::{Digest, Sha256};
+use sqlx_sqlite::SqlitePool;
+
+use crate::secrets;
+use crate::settings;
+
+/// Заголовок зашифрованного файла; за ним 16 байт префикса nonce и блоки.
+const MAGIC: &[u8; 8] = b"CTGENC1\n";
+const NONCE_PREFIX_LEN: usize = 16;
+const TAG_LEN: usize = 16;
+/// Содержимое шифруется блоками, чтобы не держать гигабайты в памяти.
+const BLOCK_SIZE: usize = 1024 * 1024;
+const MIN_PASSPHRASE_CHARS: usize = 8;
+
+/// Ключ хранилища. Сам ключ случайный и не меняется при смене пароля:
+/// пароль только шифрует его, поэтому старые файлы остаются читаемыми.
+#[derive(Clone)]
+pub struct VaultKey {
+  pub id: String,
+  key: [u8; 32]
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct VaultStatus {
+  pub configured: bool,
+  pub enabled: bool,
+  pub unlocked: bool,
+  pub key_id: Option<String>
+}
+
+static UNLOCKED: Lazy<RwLock<Option<VaultKey>>> = Lazy::new(|| RwLock::new(None));
+
+impl VaultKey {
+  fn from_bytes(key: [u8; 32]) -> VaultKey {
+    let digest = hex::encode(Sha256::digest(key));
+    VaultKey { id: digest.chars().take(16).collect(), key }
+  }
+
+  fn generate() -> anyhow::Result<VaultKey> {
+    let mut key = [0u8; 32];
+    getrandom::fill(&mut key).map_err(|e| anyhow::anyhow!("Не удалось получить случайные байты: {e}"))?;
+    Ok(VaultKey::from_bytes(key))
+  }
+}
+
+pub async fn status(pool: &SqlitePool) -> anyhow::Result<VaultStatus> {
+  let configured = settings::get_vault_key(pool).await?.is_some();
+  let unlocked = UNLOCKED.read().as_ref().map(|k| k.id.clone());
+  Ok(VaultStatus {
+    configured,
+    enabled: settings::get_vault_encryption(pool).await?,
+    unlocked: unlocked.is_some(),
+    key_id: unlocked
+  })
+}
+
+/// Задает пароль хранилища или меняет его. При первой настройке создается
+/// новый ключ; при смене пароля тот же ключ перешифровывается новым паролем.
+pub async fn set_passphrase(pool: &SqlitePool, current: Option<String>, new: String) -> anyhow::Result<VaultStatus> {
+  if new.chars().count() < MIN_PASSPHRASE_CHARS {
+    return Err(anyhow::anyhow!("Пароль шифрования должен быть не короче {MIN_PASSPHRASE_CHARS} символов"));
+  }
+  let sealed = settings::get_vault_key(pool).await?;
+  let key = match (sealed, current) {
+    (Some(sealed), Some(current)) => open_key(sealed, current).await?,
+    (Some(_), None) => return Err(anyhow::anyhow!("Укажите текущий пароль шифрования")),
+    (None, _) => VaultKey::generate()?
+  };
+  let plain = key.key;
+  let sealed = tokio::task::spawn_blocking(move || secrets::seal_blob(&plain, &new)).await??;
+  settings::set_vault_key(pool, &String::from_utf8(sealed)?).await?;
+  tracing::info!(event = "vault_passphrase_set", key_id = key.id.as_str(), "Пароль шифрования хранилища задан");
+  *UNLOCKED.write() = Some(key);
+  status(pool).await
+}
+
+pub async fn unlock(pool: &SqlitePool, passphrase: String) -> anyhow::Result<VaultStatus> {
+  let Some(sealed) = settings::get_vault_key(pool).await? else {
+    return Err(anyhow::anyhow!("Шифрование хранилища не настроено"));
+  };
+  let key = open_key(sealed, passphrase).await?;
+  *UNLOCKED.write() = Some(key);
+  status(pool).await
+}
+
+pub fn lock() {
+  *UNLOCKED.write() = None;
+}
+
+pub async fn set_enabled(pool: &SqlitePool, enabled: bool) -> anyhow::Result<VaultStatus> {
+  if enabled && settings::get_vault_key(pool).await?.is_none() {
+    return Err(anyhow::anyhow!("Сначала задайте пароль шифрования"));
+  }
+  settings::set_vault_encryption(pool, enabled).await?;
+  status(pool).await
+}
+
+async fn open_key(sealed: String, passphrase: String) -> anyhow::Result<VaultKey> {
+  let plain = tokio::task::spawn_blocking(move || secrets::open_blob(sealed.as_bytes(), &passphrase)).await??;
+  let key: [u8; 32] = plain
+    .try_into()
+    .map_err(|_| anyhow::anyhow!("Некорректный ключ хранилища"))?;
+  Ok(VaultKey::from_bytes(key))
+}
+
+/// Ключ для новых загрузок: `None`, если шифрование выключено.
+pub async fn upload_key(pool: &SqlitePool) -> anyhow::Result<Option<VaultKey>> {
+  if !settings::get_vault_encryption(pool).await? {
+    return Ok(None);
+  }
+  unlocked_key().map(Some)
+}
+
+fn unlocked_key() -> anyhow::Result<VaultKey> {
+  UNLOCKED
+    .read()
+    .clone()
+    .ok_or_else(|| anyhow::anyhow!("Хранилище заблокировано: введите пароль шифрования"))
+}
+
+/// Ключ, которым зашифрован файл с отпечатком `key_id`.
+pub fn key_for(key_id: &str) -> anyhow::Result<VaultKey> {
+  let key = unlocked_key()?;
+  if key.id != key_id {
+    return Err(anyhow::anyhow!("Файл зашифрован другим ключом ({key_id})"));
+  }
+  Ok(key)
+}
+
+/// Расшифровывает скачанный файл на месте.
+pub async fn decrypt_in_place(key_id: &str, path: &Path) -> anyhow::Result<()> {
+  let key = key_for(key_id)?;
+  let path = path.to_path_buf();
+  tokio::task::spawn_blocking(move || {
+    let plain = sibling(&path, "decrypting");
+    if let Err(e) = decrypt_file(&key, &path, &plain) {
+      let _ = std::fs::remove_file(&plain);
+      return Err(e);
+    }
+    std::fs::rename(&plain, &path)?;
+    Ok(())
+  })
+  .await?
+}
+
+/// Шифрует `source` в `target` ключом хранилища.
+pub async fn encrypt_to(key: &VaultKey, source: &Path, target: &Path) -> anyhow::Result<()> {
+  let (key, source, target) = (key.clone(), source.to_path_buf(), target.to_path_buf());
+  tokio::task::spawn_blocking(move || encrypt_file(&key, &source, &target)).await?
+}
+
+/// Размер исходного файла по размеру зашифрованного: индексатор видит только
+/// сообщение, а локальная копия ищется по исходному размеру.
+pub fn plain_size(sealed: i64) -> i64 {
+  let body = sealed - (MAGIC.len() + NONCE_PREFIX_LEN) as i64;
+  if body < TAG_LEN as i64 {
+    return 0;
+  }
+  let blocks = (body as u64).div_ceil((BLOCK_SIZE + TAG_LEN) as u64) as i64;
+  body - blocks * TAG_LEN as i64
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+  let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+  path.with_file_name(format!("{name}.{suffix}"))
+}
+
+fn block_nonce(prefix: &[u8], index: u64) -> XNonce {
+  let mut nonce = [0u8; 24];
+  nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+  nonce[NONCE_PREFIX_LEN..].copy_from_slice(&index.to_be_bytes());
+  *XNonce::from_slice(&nonce)
+}
+
+/// Читает до `buf.len()` байт; меньше — только в конце файла.
+fn read_block(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+  let mut filled = 0;
+  while filled < buf.len() {
+    let n = reader.read(&mut buf[filled..])?;
+    if n == 0 {
+      break;
+    }
+    filled += n;
+  }
+  Ok(filled)
+}
+
+/// Каждый блок шифруется со своим nonce (префикс + номер), а последний
+/// помечается в AAD: так ни переставить, ни обрезать блоки незаметно нельзя.
+fn encrypt_file(key: &VaultKey, source: &Path, target: &Path) -> anyhow::Result<()> {
+  let cipher = XChaCha20Poly1305::new((&key.key).into());
+  let mut prefix = [0u8; NONCE_PREFIX_LEN];
+  getrandom::fill(&mut prefix).map_err(|e| anyhow::anyhow!("Не удалось получить случайные байты: {e}"))?;
+  let mut input = BufReader::new(std::fs::File::open(source)?);
+  let mut output = std::io::BufWriter::new(std::fs::File::create(target)?);
+  output.write_all(MAGIC)?;
+  output.write_all(&prefix)?;
+  let mut buf = vec![0u8; BLOCK_SIZE];
+  let mut index = 0u64;
+  loop {
+    let n = read_block(&mut input, &mut buf)?;
+    let last = input.fill_buf()?.is_empty();
+    let sealed = cipher
+      .encrypt(&block_nonce(&prefix, index), Payload { msg: &buf[..n], aad: &[last as u8] })
+      .map_err(|_| anyhow::anyhow!("Не удалось зашифровать файл"))?;
+    output.write_all(&sealed)?;
+    if last {
+      break;
+    }
+    index += 1;
+  }
+  output.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+  Ok(())
+}
+
+fn decrypt_file(key: &VaultKey, source: &Path, target: &Path) -> anyhow::Result<()> {
+  let cipher = XChaCha20Poly1305::new((&key.key).into());
+  let mut input = BufReader::new(std::fs::File::open(source)?);
+  let mut header = [0u8; MAGIC.len() + NONCE_PREFIX_LEN];
+  if read_block(&mut input, &mut header)? != header.len() || &header[..MAGIC.len()] != MAGIC {
+    return Err(anyhow::anyhow!("Файл не похож на зашифрованный CloudTG"));
+  }
+  let prefix = &header[MAGIC.len()..];
+  let mut output = std::io::BufWriter::new(std::fs::File::create(target)?);
+  let mut buf = vec![0u8; BLOCK_SIZE + TAG_LEN];
+  let mut index = 0u64;
+  loop {
+    let n = read_block(&mut input, &mut buf)?;
+    let last = input.fill_buf()?.is_empty();
+    let plain = cipher
+      .decrypt(&block_nonce(prefix, index), Payload { msg: &buf[..n], aad: &[last as u8] })
+      .map_err(|_| anyhow::anyhow!("Не удалось расшифровать файл: неверный ключ или поврежденные данные"))?;
+    output.write_all(&plain)?;
+    if last {
+      break;
+    }
+    index += 1;
+  }
+  output.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn file_roundtrip_and_tamper_detection() {
+    let tmp = tempfile::tempdir().expect("tempdir");
+    let key = VaultKey::generate().expect("key");
+    let source = tmp.path().join("plain.bin");
+    let data: Vec<u8> = (0..BLOCK_SIZE * 2 + 123).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&source, &data).expect("write");
+
+    let sealed = tmp.path().join("sealed.bin");
+    encrypt_file(&key, &source, &sealed).expect("encrypt");
+    let opened = tmp.path().join("opened.bin");
+    decrypt_file(&key, &sealed, &opened).expect("decrypt");
+    assert_eq!(std::fs::read(&opened).expect("read"), data);
+    let sealed_size = std::fs::metadata(&sealed).expect("meta").len() as i64;
+    assert_eq!(plain_size(sealed_size), data.len() as i64);
+
+    // Обрезанный по границе блока файл не должен расшифроваться целиком.
+    let mut bytes = std::fs::read(&sealed).expect("read");
+    bytes.truncate(MAGIC.len() + NONCE_PREFIX_LEN + BLOCK_SIZE + TAG_LEN);
+    std::fs::write(&sealed, &bytes).expect("write");
+    assert!(decrypt_file(&key, &sealed, &opened).is_err());
+
+    let other = VaultKey::generate().expect("key");
+    encrypt_file(&key, &source, &sealed).expect("encrypt");
+    assert!(decrypt_file(&other, &sealed, &opened).is_err());
+  }
+
+  #[test]
+  fn empty_file_roundtrip() {
+    let tmp = tempfile::tempdir().expect("tempdir");
+    let key = VaultKey::generate().expect("key");
+    let source = tmp.path().join("empty");
+    std::fs::write(&source, b"").expect("write");
+    let sealed = tmp.path().join("sealed");
+    let opened = tmp.path().join("opened");
+    encrypt_file(&key, &source, &sealed).expect("encrypt");
+    decrypt_file(&key, &sealed, &opened).expect("decrypt");
+    assert!(std::fs::read(&opened).expect("read").is_empty());
+    assert_eq!(plain_size(std::fs::metadata(&sealed).expect("meta").len() as i64), 0);
+  }
+}
@@ -0,0 +1,274 @@
+use chrono::Utc;
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+use ulid::Ulid;
+
+use crate::paths::{Paths, Scratch};
+use crate::telegram::{ChatId, TelegramService};
+
+use super::broken::{self, BrokenReason};
+use super::{dirs, files, indexer, vault};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerifyStatus {
+  Ok,
+  /// Копия в канале повреждена, надежной локальной копии нет.
+  Mismatch,
+  /// Копия в канале была повреждена и переотправлена из локальной.
+  Healed,
+  /// Хэш получен при импорте, сверять не с чем.
+  Unverifiable
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct VerifyResult {
+  pub file_id: String,
+  pub name: String,
+  pub status: VerifyStatus,
+  pub expected_hash: String,
+  pub remote_hash: Option<String>
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct RepairAllReport {
+  pub repaired: i64,
+  pub healed: i64,
+  pub need_file: i64,
+  pub failed: i64,
+  pub healed_names: Vec<String>
+}
+
+/// Скачивает копию из канала во временный файл и сверяет ее хэш с записанным.
+/// При расхождении файл помечается `checksum_mismatch`; если `heal` и есть
+/// локальная копия с верным хэшем, файл переотправляется.
+pub async fn verify_file(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  paths: &Paths,
+  storage_chat_id: ChatId,
+  file_id: &str,
+  heal: bool
+) -> anyhow::Result<VerifyResult> {
+  let row = sqlx::query("SELECT name, size, hash, tg_chat_id, tg_msg_id, broken_reason, part_count, enc_key_id FROM files WHERE id = ?")
+    .bind(file_id)
+    .fetch_optional(pool)
+    .await?;
+  let Some(row) = row else {
+    return Err(anyhow::anyhow!("Файл не найден"));
+  };
+  let name: String = row.get("name");
+  let size: i64 = row.get("size");
+  let expected_hash: String = row.get("hash");
+  let chat_id: i64 = row.get("tg_chat_id");
+  let msg_id: i64 = row.get("tg_msg_id");
+  let broken_reason: Option<String> = row.try_get("broken_reason").ok();
+  let mut result = VerifyResult {
+    file_id: file_id.to_string(),
+    name,
+    status: VerifyStatus::Unverifiable,
+    expected_hash,
+    remote_hash: None
+  };
+  if indexer::is_import_hash(chat_id, msg_id, &result.name, size, &result.expected_hash) {
+    return Ok(result);
+  }
+  // Файл из частей сверяется при обычном скачивании; ради проверки качать
+  // гигабайты во временную папку не стоит.
+  if row.get::<i64, _>("part_count") > 0 {
+    return Ok(result);
+  }
+  // Хэш записан от исходного содержимого: без ключа сверять не с чем.
+  let enc_key_id = row.try_get::<String,_>("enc_key_id").ok();
+  if enc_key_id.as_deref().is_some_and(|key_id| vault::key_for(key_id).is_err()) {
+    return Ok(result);
+  }
+
+  let tmp_dir = paths.layout().scratch_dir(Scratch::Verify);
+  std::fs::create_dir_all(&tmp_dir)?;
+  let target = tmp_dir.join(file_id);
+  let downloaded = tg.download_message_file(chat_id, msg_id, target.clone()).await?;
+  if let Some(key_id) = enc_key_id.as_deref() {
+    // Если не расшифровалось, копия повреждена: хэш шифротекста даст расхождение.
+    if let Err(e) = vault::decrypt_in_place(key_id, &downloaded).await {
+      tracing::warn!(event = "file_verify_decrypt_failed", file_id = file_id, error = %e, "Не удалось расшифровать копию файла");
+    }
+  }
+  let remote_hash = files::hash_short(&downloaded);
+  let _ = std::fs::remove_file(&downloaded);
+  let _ = std::fs::remove_file(&target);
+  let remote_hash = remote_hash?;
+  result.remote_hash = Some(remote_hash.clone());
+
+  if remote_hash == result.expected_hash {
+    if broken_reason.as_deref() == Some(BrokenReason::ChecksumMismatch.as_str()) {
+      sqlx::query("UPDATE files SET is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
+        .bind(file_id)
+        .execute(pool)
+        .await?;
+    }
+    result.status = VerifyStatus::Ok;
+    return Ok(result);
+  }
+
+  tracing::warn!(
+    event = "file_verify_mismatch",
+    file_id = file_id,
+    expected = result.expected_hash.as_str(),
+    actual = remote_hash.as_str(),
+    "Копия файла в канале не совпадает с контрольной суммой"
+  );
+  broken::mark_file_broken(pool, file_id, BrokenReason::ChecksumMismatch).await?;
+  result.status = if heal && heal_from_local(pool, tg, paths, storage_chat_id, file_id, Some(&remote_hash)).await? {
+    VerifyStatus::Healed
+  } else {
+    VerifyStatus::Mismatch
+  };
+  Ok(result)
+}
+
+/// Переотправляет файл из локальной копии, если ее хэш совпадает с записанным.
+/// Поврежденное сообщение остается в канале как прежняя версия без fsmeta.
+pub async fn heal_from_local(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  paths: &Paths,
+  storage_chat_id: ChatId,
+  file_id: &str,
+  remote_hash: Option<&str>
+) -> anyhow::Result<bool> {
+  let Some(local) = files::find_local_download_path(pool, paths, file_id).await? else {
+    return Ok(false);
+  };
+  let row = sqlx::query("SELECT name, size, hash FROM files WHERE id = ?")
+    .bind(file_id)
+    .fetch_one(pool)
+    .await?;
+  let name: String = row.get("name");
+  let expected_hash: String = row.get("hash");
+  if files::hash_short(&local)? != expected_hash {
+    return Ok(false);
+  }
+
+  let (old_chat_id, old_msg_id) = files::reupload_file(pool, tg, storage_chat_id, file_id, &local).await?;
+  sqlx::query(
+    "INSERT INTO file_versions(id, file_id, tg_chat_id, tg_msg_id, hash, size, reason, created_at)
+     VALUES(?, ?, ?, ?, ?, ?, ?, ?)"
+  )
+    .bind(Ulid::new().to_string())
+    .bind(file_id)
+    .bind(old_chat_id)
+    .bind(old_msg_id)
+    .bind(remote_hash.unwrap_or(""))
+    .bind(row.get::<i64,_>("size"))
+    .bind(BrokenReason::ChecksumMismatch.as_str())
+    .bind(Utc::now().timestamp())
+    .execute(pool)
+    .await?;
+  // Снимаем fsmeta со старого сообщения. Если правка не прошла, синхронизация
+  // все равно пропустит его: оно уже записано в file_versions.
+  if let Err(e) = tg
+    .edit_message_caption(old_chat_id, old_msg_id, format!("Прежняя версия файла {name}"))
+    .await
+  {
+    tracing::warn!(event = "file_version_caption_failed", file_id = file_id, error = %e, "Не удалось обновить подпись прежней версии");
+  }
+  tracing::info!(event = "file_healed", file_id = file_id, "Файл переотправлен из локальной копии");
+  Ok(true)
+}
+
+/// Восстанавливает все поврежденные папки и файлы, для которых не нужен выбор
+/// пользователя. Файлы с расхождением хэша лечатся из локальных копий.
+pub async fn repair_all(
+  pool: &SqlitePool,
+  tg: &dyn TelegramService,
+  paths: &Paths,
+  storage_chat_id: ChatId
+) -> anyhow::Result<RepairAllReport> {
+  let mut report = RepairAllReport::default();
+
+  let dir_ids: Vec<String> = sqlx::query("SELECT id FROM directories WHERE is_broken != 0")
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| r.get::<String,_>("id"))
+    .collect();
+  for dir_id in dir_ids {
+    match dirs::repair_dir(pool, tg, storage_chat_id, &dir_id).await {
+      Ok(()) => report.repaired += 1,
+      Err(e) => {
+        report.failed += 1;
+        tracing::warn!(event = "repair_all_dir_failed", dir_id = dir_id.as_str(), error = %e, "Не удалось восстановить папку");
+      }
+    }
+  }
+
+  let rows = sqlx::query("SELECT id, name, broken_reason FROM files WHERE is_broken != 0 ORDER BY name")
+    .fetch_all(pool)
+    .await?;
+  let broken: Vec<(String, String, bool)> = rows
+    .into_iter()
+    .map(|row| {
+      let reason = row.try_get::<String,_>("broken_reason").ok().as_deref().and_then(BrokenReason::parse);
+      (row.get("id"), row.get("name"), reason == Some(BrokenReason::ChecksumMismatch))
+    })
+    .collect();
+
+  // Большинство файлов чинится правкой подписи на месте: сначала пробуем это
+  // одной пачкой, а по одному через `repair_file` идут только оставшиеся.
+  let caption_ids: Vec<String> = broken
+    .iter()
+    .filter(|(_, _, mismatch)| !mismatch)
+    .map(|(id, _, _)| id.clone())
+    .collect();
+  let mut fixed = std::collections::HashSet::new();
+  match files::rewrite_captions(pool, tg, &caption_ids, None).await {
+    Ok(outcome) => {
+      for file_id in outcome.done {
+        sqlx::query("UPDATE files SET is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
+          .bind(&file_id)
+          .execute(pool)
+          .await?;
+        report.repaired += 1;
+        fixed.insert(file_id);
+      }
+    }
+    Err(e) => {
+      tracing::warn!(event = "repair_all_caption_batch_failed", error = %e, "Пакетная правка подписей не удалась, чиню файлы по одному");
+    }
+  }
+
+  for (file_id, name, mismatch) in broken {
+    if fixed.contains(&file_id) {
+      continue;
+    }
+    let res = if mismatch {
+      heal_from_local(pool, tg, paths, storage_chat_id, &file_id, None).await.map(|healed| {
+        if healed {
+          report.healed += 1;
+          report.healed_names.push(name);
+        } else {
+          report.need_file += 1;
+        }
+      })
+    } else {
+      files::repair_file(pool, tg, paths, storage_chat_id, &file_id, None).await.map(|outcome| match outcome {
+        files::RepairFileResult::Repaired => report.repaired += 1,
+        files::RepairFileResult::NeedFile => report.need_file += 1
+      })
+    };
+    if let Err(e) = res {
+      report.failed += 1;
+      tracing::warn!(event = "repair_all_file_failed", file_id = file_id.as_str(), error = %e, "Не удалось восстановить файл");
+    }
+  }
+  tracing::info!(
+    event = "repair_all_done",
+    repaired = report.repaired,
+    healed = report.healed,
+    need_file = report.need_file,
+    failed = report.failed,
+    "Массовое восстановление завершено"
+  );
+  Ok(report)
+}
@@ -0,0 +1,131 @@
+use chrono::Utc;
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+use ulid::Ulid;
+
+use crate::paths::Paths;
+use super::files::{self, FileItem, SearchFilters};
+
+/// Умная папка: сохраненный поиск. Файлы не переносятся, содержимое
+/// считается заново при каждом открытии.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct VirtualFolder {
+  pub id: String,
+  pub name: String,
+  pub query: SearchFilters,
+  pub created_at: i64
+}
+
+pub async fn create(pool: &SqlitePool, name: &str, query: SearchFilters) -> anyhow::Result<VirtualFolder> {
+  let name = name.trim();
+  if name.is_empty() {
+    return Err(anyhow::anyhow!("Имя умной папки не может быть пустым"));
+  }
+  let folder = VirtualFolder {
+    id: Ulid::new().to_string(),
+    name: name.to_string(),
+    query,
+    created_at: Utc::now().timestamp()
+  };
+  sqlx::query("INSERT INTO virtual_folders(id, name, query, created_at) VALUES(?, ?, ?, ?)")
+    .bind(&folder.id)
+    .bind(&folder.name)
+    .bind(serde_json::to_string(&folder.query)?)
+    .bind(folder.created_at)
+    .execute(pool)
+    .await?;
+  Ok(folder)
+}
+
+/// Все умные папки по имени. Папка с нечитаемым запросом пропускается.
+pub async fn list(pool: &SqlitePool) -> anyhow::Result<Vec<VirtualFolder>> {
+  let rows = sqlx::query("SELECT id, name, query, created_at FROM virtual_folders ORDER BY name, id")
+    .fetch_all(pool)
+    .await?;
+  let mut out = Vec::with_capacity(rows.len());
+  for row in rows {
+    let id: String = row.get("id");
+    match serde_json::from_str::<SearchFilters>(&row.get::<String,_>("query")) {
+      Ok(query) => out.push(VirtualFolder { id, name: row.get("name"), query, created_at: row.get("created_at") }),
+      Err(e) => {
+        tracing::warn!(event = "virtual_folder_query_invalid", folder_id = id.as_str(), error = %e, "Не удалось прочитать запрос умной папки");
+      }
+    }
+  }
+  Ok(out)
+}
+
+pub async fn delete(pool: &SqlitePool, id: &str) -> anyhow::Result<()> {
+  sqlx::query("DELETE FROM virtual_folders WHERE id = ?")
+    .bind(id)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+/// Текущее содержимое умной папки.
+pub async fn list_files(pool: &SqlitePool, paths: &Paths, id: &str) -> anyhow::Result<Vec<FileItem>> {
+  let query = sqlx::query("SELECT query FROM virtual_folders WHERE id = ?")
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+  let Some(query) = query else {
+    return Err(anyhow::anyhow!("Умная папка не найдена"));
+  };
+  let filters: SearchFilters = serde_json::from_str(&query.get::<String,_>("query"))?;
+  files::search_files(pool, paths, &filters).await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::tempdir;
+  use crate::db::Db;
+
+  #[tokio::test]
+  async fn saved_search_lists_matching_files() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let paths = Paths::from_base(tmp.path().to_path_buf());
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let pool = db.pool();
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d', NULL, 'Документы', NULL, 0)")
+      .execute(pool)
+      .await?;
+    // 2024-03-01, 2023-06-01 и 2024-05-01.
+    for (id, name, msg_id, created_at) in [("f1", "a.pdf", 1, 1709251200), ("f2", "b.pdf", 2, 1685577600), ("f3", "c.txt", 3, 1714521600)] {
+      sqlx::query(
+        "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at) VALUES(?, 'd', ?, 1, 'h', 1, ?, ?)"
+      )
+        .bind(id)
+        .bind(name)
+        .bind(msg_id)
+        .bind(created_at)
+        .execute(pool)
+        .await?;
+    }
+
+    let query = SearchFilters {
+      file_type: Some("pdf".into()),
+      created_from: Some(1704067200),
+      created_to: Some(1735689599),
+      ..Default::default()
+    };
+    let folder = create(pool, " PDF за 2024 ", query).await?;
+    assert_eq!(folder.name, "PDF за 2024");
+    let found = list_files(pool, &paths, &folder.id).await?;
+    assert_eq!(found.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(), vec!["f1"]);
+
+    files::set_favorite(pool, "f3", true).await?;
+    let starred = create(pool, "Избранное", SearchFilters { is_favorite: Some(true), ..Default::default() }).await?;
+    let found = list_files(pool, &paths, &starred.id).await?;
+    assert_eq!(found.iter().map(|f| (f.id.as_str(), f.is_favorite)).collect::<Vec<_>>(), vec![("f3", true)]);
+
+    let listed = list(pool).await?;
+    assert_eq!(listed.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(), vec!["PDF за 2024", "Избранное"]);
+    delete(pool, &folder.id).await?;
+    assert_eq!(list(pool).await?.len(), 1);
+    assert!(files::set_favorite(pool, "missing", true).await.is_err());
+    Ok(())
+  }
+}
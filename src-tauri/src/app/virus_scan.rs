@@ -0,0 +1,249 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use sqlx_sqlite::SqlitePool;
+
+use crate::app::files::hash_full;
+use crate::sqlx;
+
+/// Проверка скачанного файла внешним антивирусом перед `file_open`.
+/// Команда запускается без оболочки: `{path}` в аргументах заменяется путем
+/// к файлу, без плейсхолдера путь добавляется последним аргументом. Код 0 —
+/// файл чист, коды из `infected_exit_codes` — угроза (clamscan возвращает 1,
+/// MpCmdRun — 2), остальные — сбой проверки. Чистые вердикты кешируются
+/// по SHA-256 содержимого и самой команде.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ScannerConfig {
+  pub enabled: bool,
+  #[serde(default)]
+  pub command: String,
+  #[serde(default)]
+  pub args: Vec<String>,
+  #[serde(default = "default_infected_exit_codes")]
+  pub infected_exit_codes: Vec<i32>,
+  #[serde(default = "default_timeout_secs")]
+  pub timeout_secs: u64
+}
+
+fn default_infected_exit_codes() -> Vec<i32> {
+  vec![1, 2]
+}
+
+fn default_timeout_secs() -> u64 {
+  120
+}
+
+impl Default for ScannerConfig {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      command: String::new(),
+      args: Vec::new(),
+      infected_exit_codes: default_infected_exit_codes(),
+      timeout_secs: default_timeout_secs()
+    }
+  }
+}
+
+const PATH_PLACEHOLDER: &str = "{path}";
+const MAX_TIMEOUT_SECS: u64 = 30 * 60;
+const MAX_REPORT_CHARS: usize = 300;
+
+static ACTIVE: Lazy<RwLock<ScannerConfig>> = Lazy::new(|| RwLock::new(ScannerConfig::default()));
+
+impl ScannerConfig {
+  pub fn sanitized(self) -> ScannerConfig {
+    let command = self.command.trim().to_string();
+    ScannerConfig {
+      enabled: self.enabled && !command.is_empty(),
+      command,
+      args: self.args.into_iter().map(|a| a.trim().to_string()).filter(|a| !a.is_empty()).collect(),
+      infected_exit_codes: self.infected_exit_codes.into_iter().filter(|c| *c != 0).collect(),
+      timeout_secs: self.timeout_secs.clamp(1, MAX_TIMEOUT_SECS)
+    }
+  }
+
+  /// Ключ кеша: при смене сканера или его аргументов вердикты пересчитываются.
+  fn signature(&self) -> String {
+    let mut parts = vec![self.command.clone()];
+    parts.extend(self.args.iter().cloned());
+    parts.join("\u{1f}")
+  }
+
+  fn build_args(&self, path: &Path) -> Vec<String> {
+    let path_str = path.to_string_lossy();
+    let mut has_placeholder = false;
+    let mut args: Vec<String> = self
+      .args
+      .iter()
+      .map(|a| {
+        if a.contains(PATH_PLACEHOLDER) {
+          has_placeholder = true;
+          a.replace(PATH_PLACEHOLDER, &path_str)
+        } else {
+          a.clone()
+        }
+      })
+      .collect();
+    if !has_placeholder {
+      args.push(path_str.to_string());
+    }
+    args
+  }
+}
+
+pub fn set_active(config: ScannerConfig) {
+  *ACTIVE.write() = config.sanitized();
+}
+
+pub fn active() -> ScannerConfig {
+  ACTIVE.read().clone()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "verdict", rename_all = "snake_case")]
+pub enum Verdict {
+  /// Сканер не настроен.
+  Skipped,
+  Clean { cached: bool },
+  Infected { report: String },
+  Failed { reason: String }
+}
+
+/// Проверяет файл активным сканером.
+pub async fn scan(pool: &SqlitePool, path: &Path) -> anyhow::Result<Verdict> {
+  scan_with(pool, &active(), path).await
+}
+
+pub async fn scan_with(pool: &SqlitePool, config: &ScannerConfig, path: &Path) -> anyhow::Result<Verdict> {
+  if !config.enabled {
+    return Ok(Verdict::Skipped);
+  }
+  let owned = path.to_path_buf();
+  let sha256 = tokio::task::spawn_blocking(move || hash_full(&owned)).await??;
+  let signature = config.signature();
+  let cached = sqlx::query("SELECT 1 FROM scan_verdicts WHERE sha256 = ? AND scanner = ?")
+    .bind(&sha256)
+    .bind(&signature)
+    .fetch_optional(pool)
+    .await?
+    .is_some();
+  if cached {
+    return Ok(Verdict::Clean { cached: true });
+  }
+
+  let runner = config.clone();
+  let target = path.to_path_buf();
+  let verdict = tokio::task::spawn_blocking(move || run_scanner(&runner, target)).await?;
+  if verdict == (Verdict::Clean { cached: false }) {
+    sqlx::query(
+      "INSERT INTO scan_verdicts(sha256, scanner, scanned_at) VALUES(?, ?, ?)
+       ON CONFLICT(sha256, scanner) DO UPDATE SET scanned_at = excluded.scanned_at"
+    )
+      .bind(&sha256)
+      .bind(&signature)
+      .bind(Utc::now().timestamp())
+      .execute(pool)
+      .await?;
+  }
+  Ok(verdict)
+}
+
+fn run_scanner(config: &ScannerConfig, path: PathBuf) -> Verdict {
+  let started = Instant::now();
+  let mut child = match Command::new(&config.command)
+    .args(config.build_args(&path))
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+  {
+    Ok(child) => child,
+    Err(e) => return Verdict::Failed { reason: format!("Не удалось запустить сканер {}: {e}", config.command) }
+  };
+  let deadline = started + Duration::from_secs(config.timeout_secs);
+  loop {
+    match child.try_wait() {
+      Ok(Some(_)) => break,
+      Ok(None) if Instant::now() >= deadline => {
+        let _ = child.kill();
+        let _ = child.wait();
+        return Verdict::Failed { reason: format!("Сканер не ответил за {} с", config.timeout_secs) };
+      }
+      Ok(None) => std::thread::sleep(Duration::from_millis(100)),
+      Err(e) => return Verdict::Failed { reason: format!("Ошибка ожидания сканера: {e}") }
+    }
+  }
+  let output = match child.wait_with_output() {
+    Ok(output) => output,
+    Err(e) => return Verdict::Failed { reason: format!("Не удалось прочитать вывод сканера: {e}") }
+  };
+  let code = output.status.code();
+  tracing::info!(
+    event = "virus_scan_finished",
+    path = %path.display(),
+    exit_code = ?code,
+    elapsed_ms = started.elapsed().as_millis() as u64,
+    "Проверка файла антивирусом завершена"
+  );
+  match code {
+    Some(0) => Verdict::Clean { cached: false },
+    Some(c) if config.infected_exit_codes.contains(&c) => Verdict::Infected { report: summarize_output(&output.stdout) },
+    Some(c) => Verdict::Failed { reason: format!("Сканер завершился с кодом {c}: {}", summarize_output(&output.stderr)) },
+    None => Verdict::Failed { reason: "Сканер прерван сигналом".into() }
+  }
+}
+
+fn summarize_output(raw: &[u8]) -> String {
+  let text = String::from_utf8_lossy(raw);
+  let trimmed = text.trim();
+  if trimmed.chars().count() <= MAX_REPORT_CHARS {
+    return trimmed.to_string();
+  }
+  let mut out: String = trimmed.chars().take(MAX_REPORT_CHARS).collect();
+  out.push('…');
+  out
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  fn config(script: &str) -> ScannerConfig {
+    ScannerConfig {
+      enabled: true,
+      command: "sh".into(),
+      args: vec!["-c".into(), script.into(), "scan".into(), "{path}".into()],
+      ..ScannerConfig::default()
+    }
+    .sanitized()
+  }
+
+  #[tokio::test]
+  async fn clean_verdicts_are_cached_and_detections_block() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+    let file = tmp.path().join("doc.txt");
+    std::fs::write(&file, b"hello")?;
+
+    let clean = config("test -f \"$1\"");
+    assert_eq!(scan_with(db.pool(), &clean, &file).await?, Verdict::Clean { cached: false });
+    assert_eq!(scan_with(db.pool(), &clean, &file).await?, Verdict::Clean { cached: true });
+
+    let infected = config("echo \"$1: Eicar FOUND\"; exit 1");
+    match scan_with(db.pool(), &infected, &file).await? {
+      Verdict::Infected { report } => assert!(report.contains("Eicar FOUND")),
+      other => panic!("unexpected verdict {other:?}")
+    }
+    assert!(matches!(scan_with(db.pool(), &config("exit 7"), &file).await?, Verdict::Failed { .. }));
+    assert_eq!(scan_with(db.pool(), &ScannerConfig::default(), &file).await?, Verdict::Skipped);
+    Ok(())
+  }
+}
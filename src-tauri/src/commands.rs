@@ -1,22 +1,45 @@
-use tauri::{Emitter, State, AppHandle};
+use tauri::{Emitter, Manager, State, AppHandle};
 use std::collections::HashSet;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use crate::sqlx::{self, Row};
 use sqlx_sqlite::SqlitePool;
 use chrono::Utc;
 use serde::Deserialize;
 use ureq::Agent;
-use crate::state::{AppState, AuthState};
-use crate::app::{backup, dirs, sync, files, indexer, reconcile};
+use crate::state::{AppState, AuthState, SessionGuard, RECENT_CHATS_LIMIT};
+use crate::app::{auto_sort, auto_sync, backup, bootstrap, broken, chat_resync, clipboard, collections, dir_download, dir_prefs, dirs, download_queue, downloads_cache, file_actions, maintenance, open_guard, preview, virus_scan, sync, files, ignore_list, inbox, import_rules, indexer, links, local_cache, local_names, migration_failures, notes, partial_downloads, reconcile, reseed, source_channels, storage_channels, storage_stats, streaming, summary, system_dirs, thumbnails, transcripts, unindexed, vault, verify, virtual_folders};
+use crate::accounts;
 use crate::settings;
+use crate::metrics;
+use crate::startup;
+use crate::diagnostics;
+use crate::recent_errors;
+use crate::status_page;
+use crate::deep_link::{self, DeepLink};
+use crate::updater;
+use crate::stream_server::StreamControl;
+use crate::events::{self, Change};
+use crate::telegram::{limits, ChatFolder, ChatInfo, ProgressSink};
+use crate::telegram::timeouts::{self, TimeoutPreset, TimeoutProfile};
 use crate::secrets::{self, CredentialsSource};
 use crate::paths::Paths;
-use crate::fsmeta::{DirMeta, make_dir_message};
+use crate::fsmeta::{DirMeta, LinkKind, LinkMeta, make_dir_message, make_link_message};
 use tracing::info;
 
 #[derive(serde::Serialize)]
 pub struct AuthStatus { pub state: String }
 
+#[derive(serde::Serialize)]
+pub struct AccountInfo {
+  pub id: String,
+  pub label: String,
+  pub created_at: i64,
+  pub active: bool,
+  /// Состояние авторизации; `None`, если сессия в этом запуске не открывалась.
+  pub auth_state: Option<String>
+}
+
 #[derive(Clone, serde::Serialize)]
 pub struct TgSyncStatus {
   pub state: String,
@@ -31,7 +54,8 @@ pub struct TgCredentialsView {
   pub source: Option<String>,
   pub keychain_available: bool,
   pub encrypted_present: bool,
-  pub locked: bool
+  pub locked: bool,
+  pub password_hint: Option<String>
 }
 
 #[derive(serde::Serialize)]
@@ -51,7 +75,30 @@ pub struct ChatView {
   pub id: i64,
   pub title: String,
   pub kind: String,
-  pub username: Option<String>
+  pub username: Option<String>,
+  pub photo_path: Option<String>,
+  /// Аватарка как data URL: интерфейс не читает локальные файлы напрямую.
+  pub photo: Option<String>
+}
+
+/// Аватарки маленькие (около 160×160), поэтому больший файл не встраиваем.
+const MAX_CHAT_PHOTO_BYTES: u64 = 64 * 1024;
+
+impl From<ChatInfo> for ChatView {
+  fn from(c: ChatInfo) -> Self {
+    let photo = c.photo_path.as_deref().and_then(chat_photo_data_url);
+    ChatView { id: c.id, title: c.title, kind: c.kind, username: c.username, photo_path: c.photo_path, photo }
+  }
+}
+
+fn chat_photo_data_url(path: &str) -> Option<String> {
+  use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+  let meta = std::fs::metadata(path).ok()?;
+  if !meta.is_file() || meta.len() > MAX_CHAT_PHOTO_BYTES {
+    return None;
+  }
+  let bytes = std::fs::read(path).ok()?;
+  Some(format!("data:image/jpeg;base64,{}", BASE64.encode(bytes)))
 }
 
 #[derive(serde::Serialize)]
@@ -73,15 +120,6 @@ pub struct BackupResult {
   pub message: String
 }
 
-#[derive(serde::Serialize)]
-pub struct AppUpdateInfo {
-  pub current_version: String,
-  pub latest_version: Option<String>,
-  pub has_update: bool,
-  pub download_url: Option<String>,
-  pub release_url: Option<String>
-}
-
 #[derive(serde::Serialize)]
 pub struct RepairResult {
   pub ok: bool,
@@ -106,6 +144,9 @@ pub struct TdlibCacheClearResult {
 
 const RECONCILE_SYNC_REQUIRED: &str = "RECONCILE_SYNC_REQUIRED";
 const REPAIR_NEED_FILE: &str = "REPAIR_NEED_FILE";
+const OPEN_NEEDS_CONFIRMATION: &str = "NEEDS_CONFIRMATION";
+const UPLOAD_DUPLICATE: &str = "DUPLICATE";
+const OPEN_BLOCKED: &str = "OPEN_BLOCKED";
 const APP_HELP_TEXT: &str = include_str!("../../docs/HELP.md");
 
 #[derive(Deserialize)]
@@ -116,6 +157,7 @@ pub struct TgSettingsInput {
   pub remember: Option<bool>,
   pub storage_mode: Option<String>,
   pub password: Option<String>,
+  pub password_hint: Option<String>,
   pub tdlib_path: Option<String>
 }
 
@@ -123,59 +165,51 @@ pub struct TgSettingsInput {
 #[serde(rename_all = "camelCase")]
 pub struct FileSearchInput {
   pub dir_id: Option<String>,
+  #[serde(default)]
+  pub recursive: bool,
   pub name: Option<String>,
+  pub text: Option<String>,
   pub file_type: Option<String>,
+  pub is_downloaded: Option<bool>,
+  pub is_broken: Option<bool>,
+  pub is_favorite: Option<bool>,
+  pub min_size: Option<i64>,
+  pub max_size: Option<i64>,
+  pub created_from: Option<i64>,
+  pub created_to: Option<i64>,
+  pub sort: Option<files::SearchSort>,
   pub limit: Option<i64>
 }
 
-#[derive(Deserialize)]
-struct GithubReleaseAsset {
-  name: String,
-  browser_download_url: String
-}
-
-#[derive(Deserialize)]
-struct GithubRelease {
-  tag_name: String,
-  html_url: String,
-  assets: Vec<GithubReleaseAsset>
-}
-
-fn map_err(e: anyhow::Error) -> String { format!("{e:#}") }
-
-fn parse_github_repo_slug(url: &str) -> Option<String> {
-  let normalized = url.trim().trim_end_matches('/').trim_end_matches(".git");
-  let path = normalized
-    .strip_prefix("https://github.com/")
-    .or_else(|| normalized.strip_prefix("http://github.com/"))
-    .or_else(|| normalized.strip_prefix("git@github.com:"))?;
-  let mut parts = path.split('/').filter(|s| !s.is_empty());
-  let owner = parts.next()?;
-  let repo = parts.next()?;
-  Some(format!("{owner}/{repo}"))
-}
-
-fn parse_semver_triplet(version: &str) -> Option<(u64, u64, u64)> {
-  let core = version
-    .trim()
-    .trim_start_matches(['v', 'V'])
-    .split('+')
-    .next()?
-    .split('-')
-    .next()?;
-  let mut parts = core.split('.');
-  let major = parts.next()?.parse::<u64>().ok()?;
-  let minor = parts.next().unwrap_or("0").parse::<u64>().ok()?;
-  let patch = parts.next().unwrap_or("0").parse::<u64>().ok()?;
-  Some((major, minor, patch))
+impl FileSearchInput {
+  fn into_filters(self) -> files::SearchFilters {
+    let has_text = self.text.as_deref().is_some_and(|t| !t.trim().is_empty());
+    files::SearchFilters {
+      dir_id: self.dir_id,
+      recursive: self.recursive,
+      name: self.name,
+      text: self.text,
+      name_hits: None,
+      file_type: self.file_type,
+      is_downloaded: self.is_downloaded,
+      is_broken: self.is_broken,
+      is_favorite: self.is_favorite,
+      min_size: self.min_size,
+      max_size: self.max_size,
+      created_from: self.created_from,
+      created_to: self.created_to,
+      sort: self.sort.unwrap_or(if has_text { files::SearchSort::Relevance } else { files::SearchSort::default() }),
+      limit: self.limit
+    }
+  }
 }
 
-fn is_newer_version(candidate: &str, current: &str) -> bool {
-  match (parse_semver_triplet(candidate), parse_semver_triplet(current)) {
-    (Some(c), Some(cur)) => c > cur,
-    (Some(_), None) => true,
-    _ => false
-  }
+fn map_err(e: anyhow::Error) -> String {
+  metrics::record_command_error();
+  diagnostics::record_command_error(&e);
+  let message = format!("{e:#}");
+  recent_errors::report(diagnostics::error_code(&e), &message);
+  message
 }
 
 fn is_strict_https_url(url: &str) -> bool {
@@ -183,32 +217,12 @@ fn is_strict_https_url(url: &str) -> bool {
   trimmed.len() > "https://".len() && trimmed.starts_with("https://")
 }
 
-fn preferred_asset_download_url(assets: &[GithubReleaseAsset]) -> Option<String> {
-  #[cfg(target_os = "windows")]
-  const PREFERRED_SUFFIXES: &[&str] = &[".msi", ".exe", ".zip"];
-  #[cfg(target_os = "macos")]
-  const PREFERRED_SUFFIXES: &[&str] = &[".dmg", ".pkg", ".zip"];
-  #[cfg(all(not(target_os = "windows"), not(target_os = "macos")))]
-  const PREFERRED_SUFFIXES: &[&str] = &[".AppImage", ".deb", ".rpm", ".tar.gz"];
-
-  for suffix in PREFERRED_SUFFIXES {
-    if let Some(asset) = assets.iter().find(|a| a.name.ends_with(suffix)) {
-      return Some(asset.browser_download_url.clone());
-    }
-  }
-  assets.first().map(|a| a.browser_download_url.clone())
-}
-
 fn github_api_agent() -> Agent {
-  ureq::Agent::config_builder()
-    .timeout_connect(Some(std::time::Duration::from_secs(10)))
-    .timeout_recv_body(Some(std::time::Duration::from_secs(20)))
-    .build()
-    .into()
+  crate::github::agent(std::time::Duration::from_secs(20))
 }
 
 fn tdlib_cache_root(paths: &Paths) -> PathBuf {
-  paths.cache_dir.join("tdlib_files")
+  paths.layout().tdlib_files_dir()
 }
 
 fn dir_size_bytes(root: &Path) -> anyhow::Result<u64> {
@@ -364,15 +378,71 @@ fn emit_sync(app: &AppHandle, state: &str, message: &str, processed: i64, total:
     processed,
     total
   };
+  status_page::record_sync_status(state, message, processed, total);
+  if matches!(state, "success" | "error") {
+    status_page::record_activity(message);
+  }
   let _ = app.emit("tg_sync_status", payload);
 }
 
-async fn download_file_path(state: &AppState, file_id: &str, overwrite: bool) -> anyhow::Result<PathBuf> {
+/// Отказывает в операциях, которые пишут в канал хранения, если он
+/// подключен только для чтения. Возвращенный guard держится до конца
+/// команды: смена аккаунта дождется ее завершения.
+async fn ensure_channel_writable(state: &AppState) -> anyhow::Result<SessionGuard> {
+  let guard = state.session_guard().await;
+  let db = state.db()?;
+  if settings::get_read_only_channel(db.pool()).await? {
+    return Err(anyhow::anyhow!("Канал хранения подключен только для чтения: изменения в нем отключены"));
+  }
+  Ok(guard)
+}
+
+/// Обновляет закрепленную сводку в фоне, если она включена в настройках.
+fn refresh_vault_summary(state: &AppState) {
+  let state = state.clone();
+  tauri::async_runtime::spawn(async move {
+    let res: anyhow::Result<()> = async {
+      let db = state.db()?;
+      if !settings::get_vault_summary_enabled(db.pool()).await? || settings::get_read_only_channel(db.pool()).await? {
+        return Ok(());
+      }
+      let tg = state.telegram()?;
+      let chat_id = ensure_storage_chat_id(&state).await?;
+      summary::publish_summary(db.pool(), tg.as_ref(), chat_id, env!("CARGO_PKG_VERSION")).await
+    }
+    .await;
+    if let Err(e) = res {
+      tracing::warn!(event = "vault_summary_failed", error = %e, "Не удалось обновить сводку хранилища");
+    }
+  });
+}
+
+async fn download_file_path(
+  state: &AppState,
+  file_id: &str,
+  overwrite: bool,
+  progress: Option<ProgressSink>
+) -> anyhow::Result<PathBuf> {
   let db = state.db()?;
   let tg = state.telegram()?;
   let paths = state.paths()?;
   let storage_chat_id = ensure_storage_chat_id(state).await?;
-  files::download_file(db.pool(), tg.as_ref(), &paths, storage_chat_id, file_id, overwrite).await
+  let name = files::fetch_file_name(db.pool(), file_id).await?.unwrap_or_else(|| file_id.to_string());
+  let transfer = download_queue::Transfer::interactive(download_queue::TransferKind::Download, &name, Some(file_id), None);
+  let pool = db.pool().clone();
+  let id = file_id.to_string();
+  let res = download_queue::run_interactive(db.pool(), transfer, async move {
+    files::download_file(&pool, tg.as_ref(), &paths, storage_chat_id, &id, overwrite, progress).await
+  })
+    .await;
+  metrics::record_transfer(metrics::Transfer::Download, res.is_ok());
+  let path = res?;
+  status_page::record_activity_link(
+    format!("Скачан файл {}", path.file_name().unwrap_or_default().to_string_lossy()),
+    &DeepLink::File(file_id.to_string())
+  );
+  state.invalidate_listings();
+  Ok(path)
 }
 
 async fn local_file_path(state: &AppState, file_id: &str) -> anyhow::Result<Option<PathBuf>> {
@@ -458,13 +528,27 @@ fn open_folder_for_file(path: &Path) -> anyhow::Result<()> {
   }
 }
 
-async fn ensure_storage_chat_id(state: &AppState) -> anyhow::Result<i64> {
+/// Возвращает проверенный канал хранения, при необходимости находя или
+/// пересоздавая его. Одновременные вызовы ждут друг друга, поэтому канал не
+/// создается и не наполняется дважды.
+pub(crate) async fn ensure_storage_chat_id(state: &AppState) -> anyhow::Result<i64> {
   let db = state.db()?;
   let pool = db.pool();
+  let mut slot = state.lock_storage_chat().await;
+  let saved = sync::get_sync(pool, "storage_chat_id").await?;
+  if let Some(id) = slot.fresh(saved.as_deref().and_then(|v| v.parse::<i64>().ok())) {
+    return Ok(id);
+  }
+  let chat_id = resolve_storage_chat_id(state, pool, saved).await?;
+  slot.store(chat_id);
+  Ok(chat_id)
+}
+
+async fn resolve_storage_chat_id(state: &AppState, pool: &SqlitePool, saved: Option<String>) -> anyhow::Result<i64> {
   let tg = state.telegram()?;
   let mut previous_id: Option<i64> = None;
 
-  if let Some(v) = sync::get_sync(pool, "storage_chat_id").await? {
+  if let Some(v) = saved {
     if let Ok(id) = v.parse::<i64>() {
       if id == 777 {
         info!(event = "storage_chat_id_invalid", value = v, "Обнаружен mock chat_id, пересоздаю");
@@ -479,8 +563,20 @@ async fn ensure_storage_chat_id(state: &AppState) -> anyhow::Result<i64> {
   if let Some(id) = previous_id {
     if tg.storage_check_channel(id).await.unwrap_or(false) {
       info!(event = "storage_chat_id_cached", chat_id = id, "Использую сохраненный storage_chat_id");
+      if let Err(e) = chat_resync::remember_storage_ref(pool, tg.as_ref(), id).await {
+        tracing::debug!(event = "storage_chat_ref_save_failed", error = %e, "Не удалось запомнить признаки канала хранения");
+      }
       return Ok(id);
     }
+    // После потери базы TDLib канал может быть жив, но еще не «увиден».
+    match chat_resync::resync_storage_chat(pool, tg.as_ref(), id).await {
+      Ok(Some(res)) => {
+        state.invalidate_listings();
+        return Ok(res.new_chat_id);
+      }
+      Ok(None) => {}
+      Err(e) => tracing::warn!(event = "storage_chat_resync_failed", chat_id = id, error = %e, "Не удалось найти канал хранения после прогрева")
+    }
     info!(event = "storage_chat_id_invalid", chat_id = id, "Канал хранения недоступен, создаю новый");
   }
 
@@ -488,23 +584,135 @@ async fn ensure_storage_chat_id(state: &AppState) -> anyhow::Result<i64> {
   sync::set_sync(pool, "storage_chat_id", &chat_id.to_string()).await?;
   info!(event = "storage_chat_id_saved", chat_id = chat_id, "storage_chat_id сохранен");
 
-  let mut reseed_ok = true;
   if previous_id.filter(|id| *id != chat_id).is_some() || previous_id.is_none() {
-    if let Err(e) = reseed_storage_channel(pool, tg.as_ref(), previous_id, chat_id).await {
-      reseed_ok = false;
-      tracing::error!(event = "storage_channel_reseed_failed", error = %e, "Не удалось пересоздать содержимое канала");
+    match migrate_storage_channel(pool, tg.as_ref(), previous_id, chat_id).await {
+      Ok(report) => {
+        // Сверка со скачиванием идет уже после того, как вызывающий отпустит
+        // блокировку канала хранения.
+        let (db, paths) = (state.db()?, state.paths()?);
+        tauri::async_runtime::spawn(async move {
+          match finish_storage_migration(db.pool(), tg.as_ref(), &paths, report).await {
+            Ok(report) if !report.safe_to_delete_old() => {
+              status_page::record_activity(format!(
+                "Старый канал хранения сохранен: не перенесено файлов — {}",
+                report.not_migrated.len()
+              ));
+            }
+            Ok(_) => {}
+            Err(e) => tracing::error!(event = "storage_channel_verify_failed", error = %e, "Не удалось проверить новый канал")
+          }
+        });
+      }
+      Err(e) => tracing::error!(event = "storage_channel_reseed_failed", error = %e, "Не удалось пересоздать содержимое канала")
     }
   }
 
-  if reseed_ok {
-    if let Some(old_id) = previous_id.filter(|id| *id != chat_id) {
-      if let Err(e) = tg.storage_delete_channel(old_id).await {
-        tracing::warn!(event = "storage_channel_delete_failed", chat_id = old_id, error = %e, "Не удалось удалить старый канал");
-      }
+  Ok(chat_id)
+}
+
+/// Переносит содержимое в новый канал и проверяет, что у каждого файла есть
+/// сообщение. Вызывается под блокировкой канала хранения.
+async fn migrate_storage_channel(
+  pool: &SqlitePool,
+  tg: &dyn crate::telegram::TelegramService,
+  old_chat_id: Option<i64>,
+  new_chat_id: i64
+) -> anyhow::Result<reseed::ChannelMigrationReport> {
+  let mut report = reseed::ChannelMigrationReport { old_chat_id, new_chat_id, ..Default::default() };
+  report.files_copied = reseed_storage_channel(pool, tg, old_chat_id, new_chat_id).await?;
+  reseed::verify_migrated(pool, tg, &mut report).await?;
+  Ok(report)
+}
+
+/// Выборочная сверка и судьба старого канала. Он удаляется, только если все
+/// файлы перенесены и сверка прошла; иначе запоминается, чтобы пользователь
+/// мог удалить его сам после разбора непереносенных файлов.
+async fn finish_storage_migration(
+  pool: &SqlitePool,
+  tg: &dyn crate::telegram::TelegramService,
+  paths: &crate::paths::Paths,
+  mut report: reseed::ChannelMigrationReport
+) -> anyhow::Result<reseed::ChannelMigrationReport> {
+  reseed::spot_check_migrated(pool, tg, paths, &mut report).await?;
+
+  let Some(old_id) = report.old_chat_id.filter(|id| *id != report.new_chat_id) else {
+    return Ok(report);
+  };
+  if !report.safe_to_delete_old() {
+    let names: Vec<&str> = report.not_migrated.iter().take(10).map(|f| f.name.as_str()).collect();
+    tracing::warn!(
+      event = "storage_channel_delete_skipped",
+      chat_id = old_id,
+      not_migrated = report.not_migrated.len(),
+      files = names.join(", "),
+      "Перенос не прошел проверку, старый канал сохранен"
+    );
+    sync::set_sync(pool, OLD_STORAGE_CHAT_KEY, &old_id.to_string()).await?;
+    return Ok(report);
+  }
+  match tg.storage_delete_channel(old_id).await {
+    Ok(()) => {
+      report.old_deleted = true;
+      sync::delete_sync(pool, OLD_STORAGE_CHAT_KEY).await?;
+    }
+    Err(e) => {
+      tracing::warn!(event = "storage_channel_delete_failed", chat_id = old_id, error = %e, "Не удалось удалить старый канал");
+      sync::set_sync(pool, OLD_STORAGE_CHAT_KEY, &old_id.to_string()).await?;
     }
   }
+  Ok(report)
+}
 
-  Ok(chat_id)
+/// Старый канал хранения, оставленный после неполного переноса.
+const OLD_STORAGE_CHAT_KEY: &str = "storage_old_chat_id";
+
+/// Запоминает старый канал до переключения на новый: если перенос оборвется
+/// ошибкой, файлы, оставшиеся только в нем, не потеряются из виду. После
+/// удачного удаления канала запись стирается.
+async fn remember_old_storage_channel(pool: &SqlitePool, old_chat_id: Option<i64>, new_chat_id: i64) -> anyhow::Result<()> {
+  match old_chat_id.filter(|id| *id != new_chat_id) {
+    Some(old_id) => sync::set_sync(pool, OLD_STORAGE_CHAT_KEY, &old_id.to_string()).await,
+    None => Ok(())
+  }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct OldStorageChannel {
+  pub chat_id: i64,
+  /// Сколько файлов все еще хранится только в старом канале.
+  pub files_left: i64
+}
+
+/// После удаления старого канала не перенесенные из него файлы помечаются битыми.
+async fn forget_old_storage_channel(pool: &SqlitePool, chat_id: i64) -> anyhow::Result<()> {
+  let left = sqlx::query(
+    "SELECT id FROM files WHERE tg_chat_id = ? OR id IN (SELECT file_id FROM file_parts WHERE tg_chat_id = ?)"
+  )
+    .bind(chat_id)
+    .bind(chat_id)
+    .fetch_all(pool)
+    .await?;
+  for row in left {
+    let file_id: String = row.get("id");
+    broken::mark_file_broken(pool, &file_id, broken::BrokenReason::MessageDeleted).await?;
+  }
+  sync::delete_sync(pool, OLD_STORAGE_CHAT_KEY).await
+}
+
+async fn old_storage_channel(pool: &SqlitePool) -> anyhow::Result<Option<OldStorageChannel>> {
+  let Some(chat_id) = sync::get_sync(pool, OLD_STORAGE_CHAT_KEY).await?.and_then(|v| v.parse::<i64>().ok()) else {
+    return Ok(None);
+  };
+  let files_left: i64 = sqlx::query(
+    "SELECT COUNT(*) AS cnt FROM files
+     WHERE tg_chat_id = ? OR id IN (SELECT file_id FROM file_parts WHERE tg_chat_id = ?)"
+  )
+    .bind(chat_id)
+    .bind(chat_id)
+    .fetch_one(pool)
+    .await?
+    .get("cnt");
+  Ok(Some(OldStorageChannel { chat_id, files_left }))
 }
 
 async fn ensure_backup_chat_id(state: &AppState) -> anyhow::Result<i64> {
@@ -530,61 +738,55 @@ async fn ensure_backup_chat_id(state: &AppState) -> anyhow::Result<i64> {
   Ok(chat_id)
 }
 
+/// Ручной прогрев после сброса сессии TDLib: находит канал хранения и
+/// переписывает chat_id в базе, если он изменился.
 #[tauri::command]
-pub async fn auth_status(state: State<'_, AppState>) -> Result<AuthStatus, String> {
-  let s = match state.auth_state() {
-    AuthState::Unknown => "unknown",
-    AuthState::WaitConfig => "wait_config",
-    AuthState::WaitPhone => "wait_phone",
-    AuthState::WaitCode => "wait_code",
-    AuthState::WaitPassword => "wait_password",
-    AuthState::Ready => "ready",
-    AuthState::Closed => "closed"
-  };
-  Ok(AuthStatus { state: s.to_string() })
+pub async fn storage_resync_chat(state: State<'_, AppState>) -> Result<Option<chat_resync::ResyncResult>, String> {
+  info!(event = "storage_resync_chat", "Поиск канала хранения после сброса TDLib");
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let mut slot = state.lock_storage_chat().await;
+  let stale = sync::get_sync(db.pool(), "storage_chat_id")
+    .await
+    .map_err(map_err)?
+    .and_then(|v| v.parse::<i64>().ok())
+    .ok_or_else(|| "Канал хранения еще не выбран".to_string())?;
+  slot.forget();
+  let res = chat_resync::resync_storage_chat(db.pool(), tg.as_ref(), stale).await.map_err(map_err)?;
+  if res.is_some() {
+    state.invalidate_listings();
+  }
+  Ok(res)
 }
 
 #[tauri::command]
-pub async fn app_check_update() -> Result<AppUpdateInfo, String> {
-  tauri::async_runtime::spawn_blocking(|| {
-    let current_version = env!("CARGO_PKG_VERSION").to_string();
-    let repo_url = env!("CARGO_PKG_REPOSITORY");
-    let repo_slug = parse_github_repo_slug(repo_url)
-      .ok_or_else(|| "Не удалось определить репозиторий приложения".to_string())?;
-    let api_url = format!("https://api.github.com/repos/{repo_slug}/releases/latest");
+pub async fn auth_status(state: State<'_, AppState>) -> Result<AuthStatus, String> {
+  Ok(AuthStatus { state: state.auth_state().as_str().to_string() })
+}
 
-    let response = github_api_agent()
-      .get(&api_url)
-      .header("User-Agent", "cloudtg")
-      .header("Accept", "application/vnd.github+json")
-      .call()
-      .map_err(|e| format!("Не удалось проверить обновления: {e}"))?;
-    let body = response
-      .into_body()
-      .read_to_string()
-      .map_err(|e| format!("Не удалось прочитать ответ сервера обновлений: {e}"))?;
-    let release: GithubRelease = serde_json::from_str(&body)
-      .map_err(|e| format!("Некорректный ответ сервера обновлений: {e}"))?;
-
-    let latest_version = release.tag_name.trim().to_string();
-    let has_update = is_newer_version(&latest_version, &current_version);
-    let release_url = if release.html_url.trim().is_empty() {
-      None
-    } else {
-      Some(release.html_url)
-    };
-    let download_url = preferred_asset_download_url(&release.assets).or_else(|| release_url.clone());
-
-    Ok(AppUpdateInfo {
-      current_version,
-      latest_version: Some(latest_version),
-      has_update,
-      download_url,
-      release_url
-    })
-  })
+/// Проверяет релизы на GitHub. При новой версии установщик скачивается
+/// в фоне, а UI получает событие `update_available` с описанием релиза.
+#[tauri::command]
+pub async fn app_update_check(app: AppHandle) -> Result<updater::AppUpdateInfo, String> {
+  let check = tauri::async_runtime::spawn_blocking(updater::check)
     .await
     .map_err(|e| format!("Не удалось выполнить проверку обновлений: {e}"))?
+    .map_err(|e| format!("{e:#}"))?;
+  let info = check.info.clone();
+  updater::prepare(&app, check);
+  Ok(info)
+}
+
+/// Запускает скачанный установщик обновления после повторной проверки
+/// хеша и подписи.
+#[tauri::command]
+pub async fn app_update_install() -> Result<(), String> {
+  let path = tauri::async_runtime::spawn_blocking(updater::verified_installer)
+    .await
+    .map_err(|e| format!("Не удалось проверить установщик обновления: {e}"))?
+    .map_err(|e| format!("{e:#}"))?;
+  info!(event = "app_update_install", path = %path.display(), "Запуск установщика обновления");
+  open_file_in_os(&path).map_err(map_err)
 }
 
 #[tauri::command]
@@ -649,6 +851,40 @@ pub async fn auth_logout(state: State<'_, AppState>) -> Result<(), String> {
   Ok(())
 }
 
+fn account_info(state: &AppState, entry: accounts::AccountEntry) -> AccountInfo {
+  AccountInfo {
+    active: state.active_account() == entry.id,
+    auth_state: state.account_auth_state(&entry.id).map(|s| s.as_str().to_string()),
+    id: entry.id,
+    label: entry.label,
+    created_at: entry.created_at
+  }
+}
+
+#[tauri::command]
+pub async fn account_list(state: State<'_, AppState>) -> Result<Vec<AccountInfo>, String> {
+  let root = state.root_paths().map_err(map_err)?;
+  let registry = accounts::load(&root).map_err(map_err)?;
+  Ok(registry.accounts.into_iter().map(|entry| account_info(&state, entry)).collect())
+}
+
+/// Новый аккаунт получает свою базу и сессию TDLib при первом переключении на него.
+#[tauri::command]
+pub async fn account_add(state: State<'_, AppState>, label: String) -> Result<AccountInfo, String> {
+  info!(event = "account_add", "Добавление аккаунта");
+  let root = state.root_paths().map_err(map_err)?;
+  let mut registry = accounts::load(&root).map_err(map_err)?;
+  let entry = registry.add(&label).map_err(map_err)?;
+  accounts::save(&root, &registry).map_err(map_err)?;
+  Ok(account_info(&state, entry))
+}
+
+#[tauri::command]
+pub async fn account_switch(app: AppHandle, state: State<'_, AppState>, account_id: String) -> Result<(), String> {
+  info!(event = "account_switch", account = account_id.as_str(), "Переключение аккаунта");
+  state.switch_account(&app, &account_id).await.map_err(map_err)
+}
+
 #[tauri::command]
 pub async fn storage_get_or_create_channel(state: State<'_, AppState>) -> Result<i64, String> {
   info!(event = "storage_get_or_create_channel", "Запрос storage канала");
@@ -658,17 +894,20 @@ pub async fn storage_get_or_create_channel(state: State<'_, AppState>) -> Result
 #[tauri::command]
 pub async fn dir_create(app: AppHandle, state: State<'_, AppState>, parent_id: Option<String>, name: String) -> Result<String, String> {
   info!(event = "dir_create", parent_id = parent_id.as_deref().unwrap_or("ROOT"), "Создание директории");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
   let db = state.db().map_err(map_err)?;
   let tg = state.telegram().map_err(map_err)?;
   let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
-  let id = dirs::create_dir(db.pool(), tg.as_ref(), chat_id, parent_id, name).await.map_err(map_err)?;
-  let _ = app.emit("tree_updated", ());
+  let id = dirs::create_dir(db.pool(), tg.as_ref(), chat_id, parent_id.clone(), name).await.map_err(map_err)?;
+  state.invalidate_listings();
+  events::dir_changed(&app, &id, Change::Created, parent_id.as_deref());
   Ok(id)
 }
 
 #[tauri::command]
-pub async fn dir_rename(app: AppHandle, state: State<'_, AppState>, dir_id: String, name: String) -> Result<(), String> {
+pub async fn dir_rename(app: AppHandle, state: State<'_, AppState>, dir_id: String, name: String) -> Result<files::BatchOutcome, String> {
   info!(event = "dir_rename", dir_id = dir_id.as_str(), "Переименование директории");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
   if dir_id == "ROOT" {
     return Err("Нельзя переименовать корневую папку".into());
   }
@@ -678,42 +917,78 @@ pub async fn dir_rename(app: AppHandle, state: State<'_, AppState>, dir_id: Stri
   let db = state.db().map_err(map_err)?;
   let tg = state.telegram().map_err(map_err)?;
   let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
-  dirs::rename_dir(db.pool(), tg.as_ref(), chat_id, &dir_id, name).await.map_err(map_err)?;
-  let _ = app.emit("tree_updated", ());
-  Ok(())
+  let retag = dirs::rename_dir(db.pool(), tg.as_ref(), chat_id, &dir_id, name).await.map_err(map_err)?;
+  state.invalidate_listings();
+  events::dir_changed(&app, &dir_id, Change::Updated, None);
+  Ok(retag)
 }
 
 #[tauri::command]
 pub async fn dir_move(app: AppHandle, state: State<'_, AppState>, dir_id: String, parent_id: Option<String>) -> Result<(), String> {
   info!(event = "dir_move", dir_id = dir_id.as_str(), parent_id = parent_id.as_deref().unwrap_or("ROOT"), "Перемещение директории");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
   if dir_id == "ROOT" {
     return Err("Нельзя перемещать корневую папку".into());
   }
   let db = state.db().map_err(map_err)?;
   let tg = state.telegram().map_err(map_err)?;
   let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
-  dirs::move_dir(db.pool(), tg.as_ref(), chat_id, &dir_id, parent_id).await.map_err(map_err)?;
-  let _ = app.emit("tree_updated", ());
+  dirs::move_dir(db.pool(), tg.as_ref(), chat_id, &dir_id, parent_id.clone()).await.map_err(map_err)?;
+  state.invalidate_listings();
+  events::dir_changed(&app, &dir_id, Change::Moved, parent_id.as_deref());
   Ok(())
 }
 
+/// Копирует папку с подпапками и файлами; байты заново не загружаются.
+#[tauri::command]
+pub async fn dir_copy(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  dir_id: String,
+  parent_id: Option<String>
+) -> Result<dirs::DirCopy, String> {
+  info!(event = "dir_copy", dir_id = dir_id.as_str(), parent_id = parent_id.as_deref().unwrap_or("ROOT"), "Копирование директории");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  if dir_id == "ROOT" {
+    return Err("Нельзя скопировать корневую папку".into());
+  }
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let copied = dirs::copy_dir(db.pool(), tg.as_ref(), chat_id, &dir_id, parent_id).await.map_err(map_err)?;
+  state.invalidate_listings();
+  for file_id in &copied.created {
+    state.search_index_refresh_file(&db, file_id).await;
+  }
+  events::tree_updated(&app);
+  Ok(copied)
+}
+
 #[tauri::command]
 pub async fn dir_delete(app: AppHandle, state: State<'_, AppState>, dir_id: String) -> Result<(), String> {
   info!(event = "dir_delete", dir_id = dir_id.as_str(), "Удаление директории");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
   if dir_id == "ROOT" {
     return Err("Нельзя удалить корневую папку".into());
   }
   let db = state.db().map_err(map_err)?;
   let tg = state.telegram().map_err(map_err)?;
   let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
-  dirs::delete_dir(db.pool(), tg.as_ref(), chat_id, &dir_id).await.map_err(map_err)?;
-  let _ = app.emit("tree_updated", ());
+  // Для ссылки на папку удаляем только саму ссылку.
+  if links::fetch_link(db.pool(), &dir_id).await.map_err(map_err)?.is_some() {
+    links::delete_link(db.pool(), tg.as_ref(), chat_id, &dir_id).await.map_err(map_err)?;
+  } else {
+    dirs::delete_dir(db.pool(), tg.as_ref(), chat_id, &dir_id).await.map_err(map_err)?;
+  }
+  state.invalidate_listings();
+  events::dir_changed(&app, &dir_id, Change::Deleted, None);
   Ok(())
 }
 
 #[tauri::command]
 pub async fn dir_repair(app: AppHandle, state: State<'_, AppState>, dir_id: String) -> Result<RepairResult, String> {
   info!(event = "dir_repair", dir_id = dir_id.as_str(), "Восстановление директории");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
   if dir_id == "ROOT" {
     return Err("Нельзя восстановить корневую папку".into());
   }
@@ -721,7 +996,8 @@ pub async fn dir_repair(app: AppHandle, state: State<'_, AppState>, dir_id: Stri
   let tg = state.telegram().map_err(map_err)?;
   let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
   dirs::repair_dir(db.pool(), tg.as_ref(), chat_id, &dir_id).await.map_err(map_err)?;
-  let _ = app.emit("tree_updated", ());
+  state.invalidate_listings();
+  events::dir_changed(&app, &dir_id, Change::Updated, None);
   Ok(RepairResult { ok: true, message: "Папка восстановлена.".to_string(), code: None })
 }
 
@@ -731,387 +1007,1787 @@ pub async fn dir_list_tree(state: State<'_, AppState>) -> Result<crate::app::mod
   dirs::list_tree(db.pool()).await.map_err(map_err)
 }
 
+/// Один узел дерева без детей: по нему UI точечно обновляет дерево после `dir_changed`.
 #[tauri::command]
-pub async fn file_list(state: State<'_, AppState>, dir_id: String) -> Result<Vec<files::FileItem>, String> {
+pub async fn dir_get_node(state: State<'_, AppState>, dir_id: String) -> Result<Option<crate::app::models::DirNode>, String> {
   let db = state.db().map_err(map_err)?;
-  let paths = state.paths().map_err(map_err)?;
-  files::list_files(db.pool(), &paths, &dir_id).await.map_err(map_err)
+  dirs::get_node(db.pool(), &dir_id).await.map_err(map_err)
 }
 
 #[tauri::command]
-pub async fn file_search(state: State<'_, AppState>, input: FileSearchInput) -> Result<Vec<files::FileItem>, String> {
+pub async fn link_create(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  dir_id: String,
+  target_kind: String,
+  target_id: String
+) -> Result<String, String> {
+  info!(event = "link_create", dir_id = dir_id.as_str(), target_kind = target_kind.as_str(), target_id = target_id.as_str(), "Создание ссылки");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let Some(kind) = LinkKind::parse(target_kind.trim()) else {
+    return Err("Неизвестный тип ссылки".into());
+  };
+  if dir_id == "ROOT" {
+    return Err("Ссылки в корневой папке не поддерживаются".into());
+  }
   let db = state.db().map_err(map_err)?;
-  let paths = state.paths().map_err(map_err)?;
-  files::search_files(
-    db.pool(),
-    &paths,
-    input.dir_id.as_deref(),
-    input.name.as_deref(),
-    input.file_type.as_deref(),
-    input.limit
-  )
-  .await
-  .map_err(map_err)
+  let tg = state.telegram().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let id = links::create_link(db.pool(), tg.as_ref(), chat_id, &dir_id, kind, &target_id)
+    .await
+    .map_err(map_err)?;
+  state.invalidate_listings();
+  match kind {
+    LinkKind::Dir => events::dir_changed(&app, &id, Change::Created, Some(&dir_id)),
+    LinkKind::File => events::file_changed(&app, &target_id, Change::Created, Some(&dir_id))
+  }
+  Ok(id)
 }
 
 #[tauri::command]
-pub async fn file_pick() -> Result<Vec<String>, String> {
-  let files = rfd::FileDialog::new().pick_files().unwrap_or_default();
-  Ok(files
-    .into_iter()
-    .map(|p| p.to_string_lossy().to_string())
-    .collect())
+pub async fn link_delete(app: AppHandle, state: State<'_, AppState>, link_id: String) -> Result<(), String> {
+  info!(event = "link_delete", link_id = link_id.as_str(), "Удаление ссылки");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let link = links::fetch_link(db.pool(), &link_id).await.map_err(map_err)?;
+  links::delete_link(db.pool(), tg.as_ref(), chat_id, &link_id).await.map_err(map_err)?;
+  state.invalidate_listings();
+  match link {
+    Some(link) if link.kind == LinkKind::File => {
+      events::file_changed(&app, &link.target_id, Change::Deleted, Some(&link.dir_id));
+    }
+    Some(link) => events::dir_changed(&app, &link_id, Change::Deleted, Some(&link.dir_id)),
+    None => events::tree_updated(&app)
+  }
+  Ok(())
 }
 
-#[tauri::command]
-pub async fn file_pick_upload(state: State<'_, AppState>) -> Result<Vec<String>, String> {
-  let files = rfd::FileDialog::new().pick_files().unwrap_or_default();
-  Ok(state.register_upload_paths(files))
+#[derive(serde::Serialize)]
+pub struct AppHealth {
+  pub status: String,
+  pub tdlib_connected: bool,
+  pub warnings: Vec<limits::LimitWarning>,
+  pub startup: startup::StartupReport
 }
 
 #[tauri::command]
-pub async fn file_prepare_upload_paths(state: State<'_, AppState>, paths: Vec<String>) -> Result<Vec<String>, String> {
-  let parsed = normalize_upload_candidate_paths(paths);
-  if parsed.is_empty() {
-    return Ok(Vec::new());
-  }
-  if !confirm_upload_paths(&parsed) {
-    return Err("Загрузка отменена пользователем.".into());
-  }
-  Ok(state.register_upload_paths(parsed))
+pub async fn app_health(state: State<'_, AppState>) -> Result<AppHealth, String> {
+  let warnings = limits::active_warnings(&state.active_account());
+  let status = if warnings.iter().any(|w| w.severity == "critical") {
+    "critical"
+  } else if warnings.is_empty() {
+    "ok"
+  } else {
+    "warning"
+  };
+  Ok(AppHealth {
+    status: status.to_string(),
+    tdlib_connected: metrics::snapshot().tdlib_connected,
+    warnings,
+    startup: startup::report()
+  })
 }
 
+/// Интерфейс сообщает о первой отрисовке окна; после нее начинается
+/// отложенный прогрев.
 #[tauri::command]
-pub async fn tdlib_pick() -> Result<Option<String>, String> {
-  let dialog = rfd::FileDialog::new();
-
-  #[cfg(target_os = "windows")]
-  let dialog = dialog.add_filter("TDLib", &["dll"]);
-
-  #[cfg(target_os = "macos")]
-  let dialog = dialog.add_filter("TDLib", &["dylib"]);
+pub async fn app_first_paint() -> Result<(), String> {
+  startup::first_paint();
+  Ok(())
+}
 
-  // On Linux, TDLib is often `libtdjson.so.1`, and filtering by extension can hide it.
-  let file = dialog.pick_file();
-  Ok(file.map(|p| p.to_string_lossy().to_string()))
+#[tauri::command]
+pub async fn db_schema_info(state: State<'_, AppState>) -> Result<crate::db::SchemaInfo, String> {
+  let db = state.db().map_err(map_err)?;
+  db.schema_info().await.map_err(map_err)
 }
 
+/// План частого запроса по его id — проверить, что он идет по индексу.
 #[tauri::command]
-pub async fn tdlib_cache_size(state: State<'_, AppState>) -> Result<TdlibCacheInfo, String> {
-  let paths = state.paths().map_err(map_err)?;
-  let root = tdlib_cache_root(&paths);
-  let bytes = dir_size_bytes(&root).map_err(map_err)?;
-  Ok(TdlibCacheInfo {
-    bytes,
-    megabytes: (bytes as f64) / (1024_f64 * 1024_f64)
-  })
+pub async fn db_explain(state: State<'_, AppState>, query_id: String) -> Result<crate::db::QueryPlan, String> {
+  let db = state.db().map_err(map_err)?;
+  db.explain(&query_id).await.map_err(map_err)
 }
 
+/// Последние ошибки команд для панели проблем, от новых к старым.
 #[tauri::command]
-pub async fn tdlib_cache_clear(state: State<'_, AppState>) -> Result<TdlibCacheClearResult, String> {
-  let paths = state.paths().map_err(map_err)?;
-  let root = tdlib_cache_root(&paths);
-  tauri::async_runtime::spawn_blocking(move || -> anyhow::Result<TdlibCacheClearResult> {
-    let before_bytes = dir_size_bytes(&root)?;
-    let failures = clear_dir_contents(&root);
-    let after_bytes = dir_size_bytes(&root)?;
-    let freed_bytes = before_bytes.saturating_sub(after_bytes);
-    let message = if failures == 0 {
-      format!(
-        "Кеш TDLib очищен. Освобождено {:.1} МБ.",
-        (freed_bytes as f64) / (1024_f64 * 1024_f64)
-      )
-    } else {
-      format!(
-        "Кеш TDLib очищен частично. Освобождено {:.1} МБ, ошибок удаления: {}.",
-        (freed_bytes as f64) / (1024_f64 * 1024_f64),
-        failures
-      )
-    };
-    Ok(TdlibCacheClearResult {
-      message,
-      before_bytes,
-      after_bytes,
-      freed_bytes,
-      failures
-    })
-  })
-    .await
-    .map_err(|e| format!("Не удалось очистить кеш TDLib: {e}"))?
-    .map_err(map_err)
+pub async fn errors_recent(state: State<'_, AppState>, limit: Option<usize>) -> Result<Vec<recent_errors::RecentError>, String> {
+  Ok(state.recent_errors().list(limit.unwrap_or(recent_errors::MAX_RECENT)))
 }
 
+/// Интерфейс сообщает, какая команда вернула ошибку; запись дополняется
+/// именем команды и рассылается событием `error_occurred`.
 #[tauri::command]
-pub async fn file_upload(state: State<'_, AppState>, dir_id: String, upload_token: String) -> Result<String, String> {
-  info!(event = "file_upload", dir_id = dir_id.as_str(), "Загрузка файла");
-  let db = state.db().map_err(map_err)?;
-  let tg = state.telegram().map_err(map_err)?;
-  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
-  let Some(path) = state.consume_upload_path(&upload_token) else {
-    return Err("Файл не подтвержден. Выбери файл через кнопку «Выбрать и загрузить» и повтори попытку.".into());
-  };
-  let id = files::upload_file(db.pool(), tg.as_ref(), chat_id, &dir_id, path.as_path()).await.map_err(map_err)?;
-  Ok(id)
+pub async fn errors_report(app: AppHandle, state: State<'_, AppState>, command: String, message: String) -> Result<(), String> {
+  if let Some(entry) = state.recent_errors().attach_command(&command, &message) {
+    let _ = app.emit("error_occurred", entry);
+  }
+  Ok(())
 }
 
 #[tauri::command]
-pub async fn file_move(state: State<'_, AppState>, file_id: String, dir_id: String) -> Result<(), String> {
-  info!(event = "file_move", file_id = file_id.as_str(), dir_id = dir_id.as_str(), "Перемещение файла");
-  let db = state.db().map_err(map_err)?;
-  let tg = state.telegram().map_err(map_err)?;
-  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
-  files::move_file(db.pool(), tg.as_ref(), chat_id, &file_id, &dir_id).await.map_err(map_err)?;
+pub async fn errors_clear(state: State<'_, AppState>) -> Result<(), String> {
+  state.recent_errors().clear();
   Ok(())
 }
 
 #[tauri::command]
-pub async fn file_delete(state: State<'_, AppState>, file_id: String) -> Result<(), String> {
-  info!(event = "file_delete", file_id = file_id.as_str(), "Удаление файла");
+pub async fn metrics_dump(format: Option<String>) -> Result<String, String> {
+  let snapshot = metrics::snapshot();
+  match format.as_deref().map(str::trim).unwrap_or("json") {
+    "prometheus" => Ok(metrics::render_prometheus(&snapshot)),
+    "json" => serde_json::to_string(&snapshot).map_err(|e| e.to_string()),
+    other => Err(format!("Неизвестный формат метрик: {other}"))
+  }
+}
+
+#[derive(serde::Serialize)]
+pub struct DiagnosticsInfo {
+  pub enabled: bool,
+  pub submit_available: bool,
+  pub report: diagnostics::DiagnosticsReport
+}
+
+#[tauri::command]
+pub async fn diagnostics_get(state: State<'_, AppState>) -> Result<DiagnosticsInfo, String> {
   let db = state.db().map_err(map_err)?;
-  let tg = state.telegram().map_err(map_err)?;
-  let paths = state.paths().map_err(map_err)?;
-  files::delete_file(db.pool(), tg.as_ref(), &paths, &file_id).await.map_err(map_err)?;
+  Ok(DiagnosticsInfo {
+    enabled: diagnostics::is_enabled(),
+    submit_available: diagnostics::submit_url().is_some(),
+    report: diagnostics::report(db.pool()).await.map_err(map_err)?
+  })
+}
+
+#[tauri::command]
+pub async fn diagnostics_set(state: State<'_, AppState>, enabled: bool) -> Result<(), String> {
+  info!(event = "diagnostics_set", enabled = enabled, "Изменение согласия на диагностику");
+  let db = state.db().map_err(map_err)?;
+  settings::set_diagnostics_enabled(db.pool(), enabled).await.map_err(map_err)?;
+  diagnostics::set_enabled(enabled);
+  if !enabled {
+    diagnostics::clear(db.pool()).await.map_err(map_err)?;
+  }
   Ok(())
 }
 
+/// Отчет в JSON для сохранения в файл или отправки вручную.
 #[tauri::command]
-pub async fn file_repair(
-  state: State<'_, AppState>,
-  file_id: String,
-  upload_token: Option<String>
-) -> Result<RepairResult, String> {
-  info!(event = "file_repair", file_id = file_id.as_str(), "Восстановление файла");
+pub async fn diagnostics_export(state: State<'_, AppState>) -> Result<String, String> {
   let db = state.db().map_err(map_err)?;
-  let tg = state.telegram().map_err(map_err)?;
-  let paths = state.paths().map_err(map_err)?;
-  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
-  let selected_path = if let Some(token) = upload_token {
-    Some(state.consume_upload_path(&token).ok_or_else(|| {
-      "Файл не подтвержден. Выбери файл через кнопку «Выбрать» и повтори попытку.".to_string()
-    })?)
-  } else {
-    None
+  let report = diagnostics::report(db.pool()).await.map_err(map_err)?;
+  serde_json::to_string_pretty(&report).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn diagnostics_submit(state: State<'_, AppState>) -> Result<(), String> {
+  if !diagnostics::is_enabled() {
+    return Err("Диагностика выключена".into());
+  }
+  let Some(url) = diagnostics::submit_url() else {
+    return Err("Адрес отправки диагностики не настроен. Воспользуйся экспортом".into());
   };
-  let outcome = files::repair_file(
-    db.pool(),
-    tg.as_ref(),
-    &paths,
-    chat_id,
-    &file_id,
-    selected_path.as_deref()
-  )
+  let db = state.db().map_err(map_err)?;
+  let report = diagnostics::report(db.pool()).await.map_err(map_err)?;
+  let body = serde_json::to_string(&report).map_err(|e| e.to_string())?;
+  tauri::async_runtime::spawn_blocking(move || {
+    github_api_agent()
+      .post(&url)
+      .header("User-Agent", "cloudtg")
+      .header("Content-Type", "application/json")
+      .send(body.as_str())
+      .map(|_| ())
+      .map_err(|e| format!("Не удалось отправить диагностику: {e}"))
+  })
     .await
-    .map_err(map_err)?;
-  match outcome {
-    files::RepairFileResult::Repaired => Ok(RepairResult {
-      ok: true,
-      message: "Файл восстановлен.".to_string(),
-      code: None
-    }),
-    files::RepairFileResult::NeedFile => Ok(RepairResult {
-      ok: false,
-      message: "Не удалось восстановить файл: нужно выбрать файл для переотправки.".to_string(),
-      code: Some(REPAIR_NEED_FILE.to_string())
-    })
-  }
+    .map_err(|e| format!("Не удалось отправить диагностику: {e}"))??;
+  info!(event = "diagnostics_submitted", counters = report.counters.len(), "Диагностика отправлена");
+  Ok(())
 }
 
+/// Внедряет синтетическую историю канала; без `chat_id` — в канал хранения.
+#[cfg(feature = "dev")]
 #[tauri::command]
-pub async fn file_delete_many(state: State<'_, AppState>, file_ids: Vec<String>) -> Result<(), String> {
-  info!(event = "file_delete_many", count = file_ids.len(), "Удаление нескольких файлов");
-  let db = state.db().map_err(map_err)?;
-  let tg = state.telegram().map_err(map_err)?;
-  let paths = state.paths().map_err(map_err)?;
-  files::delete_files(db.pool(), tg.as_ref(), &paths, &file_ids).await.map_err(map_err)?;
+pub async fn dev_inject_history(
+  state: State<'_, AppState>,
+  chat_id: Option<i64>,
+  messages: Vec<crate::dev::SyntheticMessage>
+) -> Result<Vec<i64>, String> {
+  let chat_id = match chat_id {
+    Some(id) => id,
+    None => ensure_storage_chat_id(&state).await.map_err(map_err)?
+  };
+  info!(event = "dev_inject_history", chat_id = chat_id, count = messages.len(), "Внедрение синтетической истории");
+  Ok(crate::dev::inject_history(chat_id, messages))
+}
+
+#[cfg(feature = "dev")]
+#[tauri::command]
+pub async fn dev_simulate_error(method: String, error: crate::dev::SimulatedError, count: Option<u32>) -> Result<(), String> {
+  info!(event = "dev_simulate_error", method = method.as_str(), "Симуляция ошибки Telegram");
+  crate::dev::add_fault(&method, error, count.unwrap_or(1));
   Ok(())
 }
 
-fn resolve_download_overwrite(overwrite: Option<bool>) -> bool {
-  overwrite.unwrap_or(false)
+/// Переводит часы планировщиков и сразу будит очередь загрузок.
+#[cfg(feature = "dev")]
+#[tauri::command]
+pub async fn dev_fast_forward(seconds: i64) -> Result<i64, String> {
+  let offset = crate::dev::fast_forward(seconds);
+  info!(event = "dev_fast_forward", seconds = seconds, offset = offset, "Перевод часов планировщиков");
+  download_queue::wake();
+  Ok(offset)
 }
 
-async fn file_download_impl(state: &AppState, file_id: &str, overwrite: Option<bool>) -> Result<String, String> {
-  let path = download_file_path(state, file_id, resolve_download_overwrite(overwrite))
-    .await
-    .map_err(map_err)?;
-  Ok(path.to_string_lossy().to_string())
+#[cfg(feature = "dev")]
+#[tauri::command]
+pub async fn dev_simulation_state() -> Result<crate::dev::SimulationState, String> {
+  Ok(crate::dev::state())
 }
 
-async fn resolve_file_open_path(state: &AppState, file_id: &str) -> Result<PathBuf, String> {
-  match local_file_path(state, file_id).await.map_err(map_err)? {
-    Some(path) => Ok(path),
-    None => download_file_path(state, file_id, false).await.map_err(map_err)
-  }
+#[cfg(feature = "dev")]
+#[tauri::command]
+pub async fn dev_simulation_reset() -> Result<(), String> {
+  crate::dev::reset();
+  Ok(())
 }
 
-async fn resolve_file_open_folder_path(state: &AppState, file_id: &str) -> Result<PathBuf, String> {
-  let Some(path) = local_file_path(state, file_id).await.map_err(map_err)? else {
-    return Err("Файл еще не скачан.".to_string());
-  };
-  Ok(path)
+#[tauri::command]
+pub async fn broken_report(state: State<'_, AppState>) -> Result<broken::BrokenReport, String> {
+  let db = state.db().map_err(map_err)?;
+  broken::broken_report(db.pool()).await.map_err(map_err)
 }
 
 #[tauri::command]
-pub async fn file_download(state: State<'_, AppState>, file_id: String, overwrite: Option<bool>) -> Result<String, String> {
-  info!(event = "file_download", file_id = file_id.as_str(), "Скачивание файла");
-  file_download_impl(&state, &file_id, overwrite).await
+pub async fn file_list(state: State<'_, AppState>, dir_id: String) -> Result<Vec<files::FileItem>, String> {
+  let db = state.db().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  if let Some(items) = state.cached_listing(&dir_id) {
+    return Ok(items);
+  }
+  let generation = state.listing_generation();
+  let resolved = links::resolve_dir_id(db.pool(), &dir_id).await.map_err(map_err)?;
+  let items = files::list_files(db.pool(), &paths, &resolved).await.map_err(map_err)?;
+  state.store_listing(&dir_id, generation, items.clone());
+  Ok(items)
 }
 
 #[tauri::command]
-pub async fn file_open(state: State<'_, AppState>, file_id: String) -> Result<(), String> {
-  let path = resolve_file_open_path(&state, &file_id).await?;
-  open_file_in_os(&path).map_err(map_err)?;
-  Ok(())
+pub async fn file_search(state: State<'_, AppState>, input: FileSearchInput) -> Result<Vec<files::FileItem>, String> {
+  let db = state.db().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let mut filters = input.into_filters();
+  // Подстроку в имени быстрее найти индексом в памяти, если он включен и
+  // прогрет. При слишком большом числе совпадений остается LIKE.
+  if let (Some(text), Some(index)) = (filters.text.as_deref(), state.search_index()) {
+    filters.name_hits = index.query(text, SEARCH_NAME_HITS_MAX).filter(|ids| ids.len() < SEARCH_NAME_HITS_MAX);
+  }
+  files::search_files(db.pool(), &paths, &filters)
+    .await
+    .map_err(map_err)
 }
 
+/// Сколько совпадений из индекса в памяти передается в запрос поиска.
+const SEARCH_NAME_HITS_MAX: usize = 2000;
+
 #[tauri::command]
-pub async fn file_open_folder(state: State<'_, AppState>, file_id: String) -> Result<(), String> {
-  let path = resolve_file_open_folder_path(&state, &file_id).await?;
-  open_folder_for_file(&path).map_err(map_err)?;
+pub async fn file_set_favorite(app: AppHandle, state: State<'_, AppState>, file_id: String, favorite: bool) -> Result<(), String> {
+  info!(event = "file_set_favorite", file_id = file_id.as_str(), favorite = favorite, "Отметка избранного");
+  let db = state.db().map_err(map_err)?;
+  files::set_favorite(db.pool(), &file_id, favorite).await.map_err(map_err)?;
+  state.invalidate_listings();
+  events::file_changed(&app, &file_id, Change::Updated, None);
   Ok(())
 }
 
+/// Сохраняет поиск как умную папку, например «все PDF за 2024 год».
 #[tauri::command]
-pub async fn file_share_link(state: State<'_, AppState>, file_id: String) -> Result<String, String> {
+pub async fn virtual_folder_create(
+  state: State<'_, AppState>,
+  name: String,
+  query: FileSearchInput
+) -> Result<virtual_folders::VirtualFolder, String> {
+  info!(event = "virtual_folder_create", "Создание умной папки");
   let db = state.db().map_err(map_err)?;
-  let row = sqlx::query("SELECT tg_chat_id, tg_msg_id FROM files WHERE id = ?")
-    .bind(&file_id)
-    .fetch_optional(db.pool())
-    .await
-    .map_err(|e| map_err(e.into()))?;
-  let Some(row) = row else {
-    return Err("Файл не найден".into());
-  };
-  let chat_id: i64 = row.get("tg_chat_id");
-  let msg_id: i64 = row.get("tg_msg_id");
-  files::build_message_link(chat_id, msg_id).map_err(map_err)
+  virtual_folders::create(db.pool(), &name, query.into_filters()).await.map_err(map_err)
 }
 
 #[tauri::command]
-pub async fn tg_search_chats(state: State<'_, AppState>, query: String) -> Result<Vec<ChatView>, String> {
-  let tg = state.telegram().map_err(map_err)?;
-  let items = tg.search_chats(query, 20).await.map_err(|e| e.to_string())?;
-  Ok(items
-    .into_iter()
-    .map(|c| ChatView {
-      id: c.id,
-      title: c.title,
-      kind: c.kind,
-      username: c.username
-    })
-    .collect())
+pub async fn virtual_folder_list(state: State<'_, AppState>) -> Result<Vec<virtual_folders::VirtualFolder>, String> {
+  let db = state.db().map_err(map_err)?;
+  virtual_folders::list(db.pool()).await.map_err(map_err)
 }
 
 #[tauri::command]
-pub async fn tg_recent_chats(state: State<'_, AppState>) -> Result<Vec<ChatView>, String> {
-  let tg = state.telegram().map_err(map_err)?;
-  let items = tg.recent_chats(12).await.map_err(|e| e.to_string())?;
-  Ok(items
-    .into_iter()
-    .map(|c| ChatView {
-      id: c.id,
-      title: c.title,
-      kind: c.kind,
-      username: c.username
-    })
-    .collect())
+pub async fn virtual_folder_files(state: State<'_, AppState>, folder_id: String) -> Result<Vec<files::FileItem>, String> {
+  let db = state.db().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  virtual_folders::list_files(db.pool(), &paths, &folder_id).await.map_err(map_err)
 }
 
 #[tauri::command]
-pub async fn file_share_to_chat(state: State<'_, AppState>, file_id: String, chat_id: i64) -> Result<ShareResult, String> {
+pub async fn virtual_folder_delete(state: State<'_, AppState>, folder_id: String) -> Result<(), String> {
+  info!(event = "virtual_folder_delete", folder_id = folder_id.as_str(), "Удаление умной папки");
   let db = state.db().map_err(map_err)?;
-  let tg = state.telegram().map_err(map_err)?;
-  let row = sqlx::query("SELECT tg_chat_id, tg_msg_id FROM files WHERE id = ?")
-    .bind(&file_id)
-    .fetch_optional(db.pool())
-    .await
-    .map_err(|e| map_err(e.into()))?;
-  let Some(row) = row else {
-    return Err("Файл не найден".into());
-  };
-  let mut from_chat_id: i64 = row.get("tg_chat_id");
-  let mut msg_id: i64 = row.get("tg_msg_id");
+  virtual_folders::delete(db.pool(), &folder_id).await.map_err(map_err)
+}
 
-  if tg.forward_message(from_chat_id, chat_id, msg_id).await.is_ok() {
-    return Ok(ShareResult { message: "Сообщение переслано.".into() });
+#[tauri::command]
+pub async fn quick_search(state: State<'_, AppState>, query: String, limit: Option<i64>) -> Result<Vec<files::FileItem>, String> {
+  let db = state.db().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let limit = limit.unwrap_or(50).clamp(1, 500);
+  if let Some(ids) = state.search_index().and_then(|index| index.query(&query, limit as usize)) {
+    return files::files_by_ids(db.pool(), &paths, &ids).await.map_err(map_err);
   }
+  let filters = files::SearchFilters { name: Some(query), limit: Some(limit), ..Default::default() };
+  files::search_files(db.pool(), &paths, &filters)
+    .await
+    .map_err(map_err)
+}
 
-  {
-    let storage_chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
-    if let Ok(Some((found_chat_id, found_msg_id))) =
-      files::find_file_message(tg.as_ref(), from_chat_id, storage_chat_id, &file_id).await
-    {
-      if found_chat_id != from_chat_id || found_msg_id != msg_id {
-        from_chat_id = found_chat_id;
-        msg_id = found_msg_id;
-        sqlx::query("UPDATE files SET tg_chat_id = ?, tg_msg_id = ?, is_broken = 0 WHERE id = ?")
-          .bind(from_chat_id)
-          .bind(msg_id)
-          .bind(&file_id)
-          .execute(db.pool())
-          .await
-          .map_err(|e| map_err(e.into()))?;
-      }
-    }
-  }
+#[tauri::command]
+pub async fn file_group_list(
+  state: State<'_, AppState>,
+  chat_id: i64,
+  group_id: String
+) -> Result<Vec<files::FileItem>, String> {
+  let db = state.db().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  files::list_group(db.pool(), &paths, chat_id, group_id.trim()).await.map_err(map_err)
+}
 
-  tg.forward_message(from_chat_id, chat_id, msg_id)
+#[tauri::command]
+pub async fn collections_browse(
+  state: State<'_, AppState>,
+  kind: collections::CollectionKind
+) -> Result<Vec<collections::CollectionGroup>, String> {
+  let db = state.db().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  collections::browse(db.pool(), &paths, kind).await.map_err(map_err)
+}
+
+#[tauri::command]
+pub async fn file_transcribe(
+  state: State<'_, AppState>,
+  file_id: String,
+  source: Option<transcripts::TranscriptSource>
+) -> Result<transcripts::Transcript, String> {
+  let db = state.db().map_err(map_err)?;
+  let source = match source {
+    Some(source) => source,
+    None => settings::get_transcription_mode(db.pool())
+      .await
+      .map_err(map_err)?
+      .unwrap_or(transcripts::TranscriptSource::Telegram)
+  };
+  info!(event = "file_transcribe", file_id = file_id.as_str(), source = source.as_str(), "Расшифровка голосового сообщения");
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let storage_chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  transcripts::transcribe_file(db.pool(), tg.as_ref(), &paths, storage_chat_id, &file_id, source)
     .await
-    .map_err(|e| e.to_string())?;
+    .map_err(map_err)
+}
 
-  Ok(ShareResult { message: "Сообщение переслано.".into() })
+#[tauri::command]
+pub async fn file_transcript_get(state: State<'_, AppState>, file_id: String) -> Result<Option<transcripts::Transcript>, String> {
+  let db = state.db().map_err(map_err)?;
+  transcripts::get_transcript(db.pool(), &file_id).await.map_err(map_err)
 }
 
 #[tauri::command]
-pub async fn tg_test_message(state: State<'_, AppState>) -> Result<(), String> {
-  info!(event = "tg_test_message", "Проверка связи с Telegram");
-  let tg = state.telegram().map_err(map_err)?;
-  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
-  let ts = Utc::now().to_rfc3339();
-  let text = format!("CloudTG: тестовое сообщение ({ts})");
-  tg.send_text_message(chat_id, text).await.map_err(|e| e.to_string())?;
-  Ok(())
+pub async fn transcripts_search(
+  state: State<'_, AppState>,
+  query: String,
+  limit: Option<i64>
+) -> Result<Vec<transcripts::TranscriptHit>, String> {
+  let db = state.db().map_err(map_err)?;
+  transcripts::search_transcripts(db.pool(), &query, limit.unwrap_or(50)).await.map_err(map_err)
 }
 
 #[tauri::command]
-pub async fn tg_create_channel(state: State<'_, AppState>) -> Result<(), String> {
-  info!(event = "tg_create_channel", "Создание нового канала хранения");
+pub async fn note_create(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  dir_id: String,
+  title: String,
+  body: String
+) -> Result<notes::Note, String> {
+  info!(event = "note_create", dir_id = dir_id.as_str(), "Создание заметки");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
   let db = state.db().map_err(map_err)?;
-  let pool = db.pool();
-  let old_id = sync::get_sync(pool, "storage_chat_id")
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let note = notes::create_note(db.pool(), tg.as_ref(), &paths, chat_id, &dir_id, &title, &body)
     .await
-    .map_err(map_err)?
-    .and_then(|v| v.parse::<i64>().ok())
-    .filter(|id| *id != 777);
+    .map_err(map_err)?;
+  state.invalidate_listings();
+  state.search_index_refresh_file(&db, &note.file_id).await;
+  events::file_changed(&app, &note.file_id, Change::Created, Some(&dir_id));
+  Ok(note)
+}
 
+#[tauri::command]
+pub async fn note_get(state: State<'_, AppState>, file_id: String) -> Result<notes::Note, String> {
+  let db = state.db().map_err(map_err)?;
   let tg = state.telegram().map_err(map_err)?;
-  let new_id = tg.storage_create_channel().await.map_err(|e| e.to_string())?;
-  sync::set_sync(pool, "storage_chat_id", &new_id.to_string()).await.map_err(map_err)?;
-
-  if let Err(e) = reseed_storage_channel(pool, tg.as_ref(), old_id, new_id).await {
-    tracing::error!(event = "storage_channel_reseed_failed", error = %e, "Не удалось пересоздать содержимое канала");
-    return Err(format!("Не удалось перенести данные: {e}"));
-  }
-
-  if let Some(old) = old_id.filter(|id| *id != new_id) {
-    if let Err(e) = tg.storage_delete_channel(old).await {
-      tracing::warn!(event = "storage_channel_delete_failed", chat_id = old, error = %e, "Не удалось удалить старый канал");
-    }
-  }
+  let paths = state.paths().map_err(map_err)?;
+  notes::get_note(db.pool(), tg.as_ref(), &paths, &file_id).await.map_err(map_err)
+}
 
-  Ok(())
+#[tauri::command]
+pub async fn note_update(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  file_id: String,
+  body: String,
+  base_hash: Option<String>
+) -> Result<notes::NoteSaved, String> {
+  info!(event = "note_update", file_id = file_id.as_str(), "Сохранение заметки");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let saved = notes::update_note(db.pool(), tg.as_ref(), &paths, chat_id, &file_id, &body, base_hash.as_deref())
+    .await
+    .map_err(map_err)?;
+  state.invalidate_listings();
+  events::file_changed(&app, &file_id, Change::Updated, Some(&saved.note.dir_id));
+  Ok(saved)
 }
 
 #[tauri::command]
-pub async fn tg_sync_storage(app: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
-  let res: Result<(), String> = async {
-    info!(event = "storage_sync_start", "Синхронизация данных из Telegram");
-    emit_sync(&app, "start", "Ищу сообщения в канале хранения", 0, None);
+pub async fn notes_search(
+  state: State<'_, AppState>,
+  query: String,
+  limit: Option<i64>
+) -> Result<Vec<notes::NoteHit>, String> {
+  let db = state.db().map_err(map_err)?;
+  notes::search_notes(db.pool(), &query, limit.unwrap_or(50)).await.map_err(map_err)
+}
 
-    let db = state.db().map_err(map_err)?;
-    let pool = db.pool();
-    let existing_dirs: i64 = sqlx::query("SELECT COUNT(1) as cnt FROM directories")
-      .fetch_one(pool)
+#[tauri::command]
+pub async fn file_pick() -> Result<Vec<String>, String> {
+  let files = rfd::FileDialog::new().pick_files().unwrap_or_default();
+  Ok(files
+    .into_iter()
+    .map(|p| p.to_string_lossy().to_string())
+    .collect())
+}
+
+#[tauri::command]
+pub async fn file_pick_upload(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+  let files = rfd::FileDialog::new().pick_files().unwrap_or_default();
+  Ok(state.register_upload_paths(files))
+}
+
+#[tauri::command]
+pub async fn file_prepare_upload_paths(state: State<'_, AppState>, paths: Vec<String>) -> Result<Vec<String>, String> {
+  let parsed = normalize_upload_candidate_paths(paths);
+  if parsed.is_empty() {
+    return Ok(Vec::new());
+  }
+  if !confirm_upload_paths(&parsed) {
+    return Err("Загрузка отменена пользователем.".into());
+  }
+  Ok(state.register_upload_paths(parsed))
+}
+
+#[tauri::command]
+pub async fn tdlib_pick() -> Result<Option<String>, String> {
+  let dialog = rfd::FileDialog::new();
+
+  #[cfg(target_os = "windows")]
+  let dialog = dialog.add_filter("TDLib", &["dll"]);
+
+  #[cfg(target_os = "macos")]
+  let dialog = dialog.add_filter("TDLib", &["dylib"]);
+
+  // On Linux, TDLib is often `libtdjson.so.1`, and filtering by extension can hide it.
+  let file = dialog.pick_file();
+  Ok(file.map(|p| p.to_string_lossy().to_string()))
+}
+
+#[tauri::command]
+pub async fn tdlib_cache_size(state: State<'_, AppState>) -> Result<TdlibCacheInfo, String> {
+  let paths = state.paths().map_err(map_err)?;
+  let root = tdlib_cache_root(&paths);
+  let bytes = dir_size_bytes(&root).map_err(map_err)?;
+  Ok(TdlibCacheInfo {
+    bytes,
+    megabytes: (bytes as f64) / (1024_f64 * 1024_f64)
+  })
+}
+
+#[tauri::command]
+pub async fn tdlib_cache_clear(state: State<'_, AppState>) -> Result<TdlibCacheClearResult, String> {
+  let paths = state.paths().map_err(map_err)?;
+  let root = tdlib_cache_root(&paths);
+  tauri::async_runtime::spawn_blocking(move || -> anyhow::Result<TdlibCacheClearResult> {
+    let before_bytes = dir_size_bytes(&root)?;
+    let failures = clear_dir_contents(&root);
+    let after_bytes = dir_size_bytes(&root)?;
+    let freed_bytes = before_bytes.saturating_sub(after_bytes);
+    let message = if failures == 0 {
+      format!(
+        "Кеш TDLib очищен. Освобождено {:.1} МБ.",
+        (freed_bytes as f64) / (1024_f64 * 1024_f64)
+      )
+    } else {
+      format!(
+        "Кеш TDLib очищен частично. Освобождено {:.1} МБ, ошибок удаления: {}.",
+        (freed_bytes as f64) / (1024_f64 * 1024_f64),
+        failures
+      )
+    };
+    Ok(TdlibCacheClearResult {
+      message,
+      before_bytes,
+      after_bytes,
+      freed_bytes,
+      failures
+    })
+  })
+    .await
+    .map_err(|e| format!("Не удалось очистить кеш TDLib: {e}"))?
+    .map_err(map_err)
+}
+
+/// Загрузка файла. Если такое же содержимое уже есть в хранилище, без
+/// `dedup` возвращается ошибка `DUPLICATE:<token>: ...` с новым токеном
+/// загрузки: с ним можно повторить вызов с `reference` или `upload`.
+#[tauri::command]
+pub async fn file_upload(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  dir_id: String,
+  upload_token: String,
+  dedup: Option<files::Dedup>
+) -> Result<String, String> {
+  info!(event = "file_upload", dir_id = dir_id.as_str(), "Загрузка файла");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let Some(path) = state.consume_upload_path(&upload_token) else {
+    return Err("Файл не подтвержден. Выбери файл через кнопку «Выбрать и загрузить» и повтори попытку.".into());
+  };
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let res = upload_confirmed(&app, &db, tg, chat_id, &dir_id, &path, dedup.unwrap_or_default()).await;
+  match res.map_err(map_err)? {
+    files::DedupUpload::Duplicate(existing) => {
+      let token = state.register_upload_paths(vec![path.clone()]).pop().unwrap_or_default();
+      Err(format!(
+        "{UPLOAD_DUPLICATE}:{token}: Файл с таким же содержимым уже есть: {}. Добавить ссылку на него вместо повторной загрузки?",
+        existing.name
+      ))
+    }
+    files::DedupUpload::Uploaded(id) | files::DedupUpload::Referenced(id) => Ok(id)
+  }
+}
+
+/// Загружает подтвержденный файл и сообщает о нем дереву, поиску и ленте.
+/// База и клиент передаются явно: фоновая загрузка держит их с момента
+/// запуска и не пишет в другой аккаунт после переключения.
+async fn upload_confirmed(
+  app: &AppHandle,
+  db: &crate::db::Db,
+  tg: Arc<dyn crate::telegram::TelegramService>,
+  chat_id: i64,
+  dir_id: &str,
+  path: &Path,
+  dedup: files::Dedup
+) -> anyhow::Result<files::DedupUpload> {
+  let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+  let transfer = download_queue::Transfer::interactive(download_queue::TransferKind::Upload, &name, None, Some(dir_id));
+  let pool = db.pool().clone();
+  let (target, source) = (dir_id.to_string(), path.to_path_buf());
+  let res = download_queue::run_interactive(db.pool(), transfer, async move {
+    files::upload_file_dedup(&pool, tg.as_ref(), chat_id, &target, &source, dedup).await
+  })
+    .await;
+  let id = match &res {
+    Ok(files::DedupUpload::Duplicate(_)) => return res,
+    Ok(files::DedupUpload::Uploaded(id)) => {
+      metrics::record_transfer(metrics::Transfer::Upload, true);
+      id.clone()
+    }
+    Ok(files::DedupUpload::Referenced(id)) => id.clone(),
+    Err(_) => {
+      metrics::record_transfer(metrics::Transfer::Upload, false);
+      return res;
+    }
+  };
+  status_page::record_activity_link(
+    format!("Загружен файл {}", path.file_name().unwrap_or_default().to_string_lossy()),
+    &DeepLink::File(id.clone())
+  );
+  let state = app.state::<AppState>();
+  state.invalidate_listings();
+  state.search_index_refresh_file(db, &id).await;
+  events::file_changed(app, &id, Change::Created, Some(dir_id));
+  res
+}
+
+#[derive(serde::Serialize)]
+pub struct DropUpload {
+  /// Пустой, если среди перетащенного не нашлось файлов.
+  pub batch_id: String,
+  pub total: usize
+}
+
+/// Загрузка перетащенных в окно файлов в папку `dir_id`. Пути проходят то же
+/// подтверждение, что и выбранные вручную, загрузка идет в фоне через очередь
+/// передач, а ход приходит событиями `upload_progress`. Папки среди
+/// перетащенного пропускаются. База и клиент берутся один раз: если аккаунт
+/// сменится, оставшиеся файлы не загружаются.
+#[tauri::command]
+pub async fn file_drop_upload(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  dir_id: String,
+  paths: Vec<String>,
+  dedup: Option<files::Dedup>
+) -> Result<DropUpload, String> {
+  let parsed = normalize_upload_candidate_paths(paths);
+  if parsed.is_empty() {
+    return Ok(DropUpload { batch_id: String::new(), total: 0 });
+  }
+  info!(event = "file_drop_upload", dir_id = dir_id.as_str(), count = parsed.len(), "Загрузка перетащенных файлов");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let account = state.active_account();
+  if !dirs::dir_exists(db.pool(), &dir_id).await.map_err(map_err)? {
+    return Err("Папка не найдена".into());
+  }
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  if !confirm_upload_paths(&parsed) {
+    return Err("Загрузка отменена пользователем.".into());
+  }
+  let batch_id = ulid::Ulid::new().to_string();
+  let total = parsed.len();
+  let dedup = dedup.unwrap_or_default();
+
+  let task_app = app.clone();
+  let task_batch = batch_id.clone();
+  tauri::async_runtime::spawn(async move {
+    let mut progress = events::UploadProgress {
+      batch_id: task_batch,
+      dir_id,
+      done: 0,
+      total,
+      name: None,
+      file_id: None,
+      error: None,
+      finished: false
+    };
+    for path in parsed {
+      progress.name = Some(path.file_name().unwrap_or_default().to_string_lossy().to_string());
+      progress.file_id = None;
+      progress.error = None;
+      if task_app.state::<AppState>().active_account() != account {
+        progress.error = Some("Аккаунт сменился, загрузка остановлена".to_string());
+        progress.done += 1;
+        events::upload_progress(&task_app, &progress);
+        continue;
+      }
+      match upload_confirmed(&task_app, &db, tg.clone(), chat_id, &progress.dir_id, &path, dedup).await {
+        Ok(files::DedupUpload::Uploaded(id) | files::DedupUpload::Referenced(id)) => progress.file_id = Some(id),
+        Ok(files::DedupUpload::Duplicate(existing)) => {
+          progress.error = Some(format!("Такой файл уже есть: {}", existing.name));
+        }
+        Err(e) => {
+          tracing::warn!(event = "file_drop_upload_failed", error = %e, "Не удалось загрузить перетащенный файл");
+          progress.error = Some(map_err(e));
+        }
+      }
+      progress.done += 1;
+      events::upload_progress(&task_app, &progress);
+    }
+    progress.name = None;
+    progress.file_id = None;
+    progress.error = None;
+    progress.finished = true;
+    events::upload_progress(&task_app, &progress);
+  });
+  Ok(DropUpload { batch_id, total })
+}
+
+/// Быстрый захват во «Входящие»: текст заметки или файл, подтвержденный
+/// через `upload_token`. Нужно передать ровно одно из двух.
+#[tauri::command]
+pub async fn inbox_capture(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  text: Option<String>,
+  upload_token: Option<String>
+) -> Result<inbox::Captured, String> {
+  info!(event = "inbox_capture", "Захват во Входящие");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let capture = match (text, upload_token) {
+    (Some(text), None) => inbox::Capture::Text(text),
+    (None, Some(token)) => {
+      let Some(path) = state.consume_upload_path(&token) else {
+        return Err("Файл не подтвержден. Выбери файл через кнопку «Выбрать и загрузить» и повтори попытку.".into());
+      };
+      inbox::Capture::File(path)
+    }
+    _ => return Err("Передай либо текст заметки, либо файл".into())
+  };
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let res = inbox::capture(db.pool(), tg.as_ref(), &paths, chat_id, capture).await;
+  metrics::record_transfer(metrics::Transfer::Upload, res.is_ok());
+  let captured = res.map_err(map_err)?;
+  status_page::record_activity(format!("Сохранено во «{}»", captured.dir_name));
+  state.invalidate_listings();
+  state.search_index_refresh_file(&db, &captured.file_id).await;
+  // Папка могла появиться только что.
+  events::tree_updated(&app);
+  events::file_changed(&app, &captured.file_id, Change::Created, Some(&captured.dir_id));
+  Ok(captured)
+}
+
+#[tauri::command]
+pub async fn file_move(app: AppHandle, state: State<'_, AppState>, file_id: String, dir_id: String) -> Result<(), String> {
+  info!(event = "file_move", file_id = file_id.as_str(), dir_id = dir_id.as_str(), "Перемещение файла");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let from_dir_id = files::dirs_of(db.pool(), std::slice::from_ref(&file_id)).await.map_err(map_err)?.pop();
+  files::move_file(db.pool(), tg.as_ref(), chat_id, &file_id, &dir_id).await.map_err(map_err)?;
+  state.invalidate_listings();
+  events::file_moved(&app, &file_id, from_dir_id.as_deref(), &dir_id);
+  Ok(())
+}
+
+/// Переносит много файлов разом: подписи правятся пачками, а интерфейс
+/// получает одно событие `tree_updated` вместо события на каждый файл.
+#[tauri::command]
+pub async fn file_move_many(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  file_ids: Vec<String>,
+  dir_id: String
+) -> Result<files::BatchOutcome, String> {
+  info!(event = "file_move_many", count = file_ids.len(), dir_id = dir_id.as_str(), "Перемещение нескольких файлов");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let outcome = files::move_files(db.pool(), tg.as_ref(), chat_id, &file_ids, &dir_id).await.map_err(map_err)?;
+  state.invalidate_listings();
+  events::tree_updated(&app);
+  Ok(outcome)
+}
+
+#[tauri::command]
+pub async fn file_copy(app: AppHandle, state: State<'_, AppState>, file_id: String, dir_id: String) -> Result<String, String> {
+  info!(event = "file_copy", file_id = file_id.as_str(), dir_id = dir_id.as_str(), "Копирование файла");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let outcome = files::copy_files(db.pool(), tg.as_ref(), chat_id, &[file_id], &dir_id).await.map_err(map_err)?;
+  if let Some((_, error)) = outcome.failed.into_iter().next() {
+    return Err(error);
+  }
+  let Some(copy_id) = outcome.created.into_iter().next() else {
+    return Err("Копия файла не создана".into());
+  };
+  state.invalidate_listings();
+  state.search_index_refresh_file(&db, &copy_id).await;
+  events::file_changed(&app, &copy_id, Change::Created, Some(&dir_id));
+  Ok(copy_id)
+}
+
+#[tauri::command]
+pub async fn file_rename(app: AppHandle, state: State<'_, AppState>, file_id: String, name: String) -> Result<(), String> {
+  info!(event = "file_rename", file_id = file_id.as_str(), "Переименование файла");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  files::rename_file(db.pool(), tg.as_ref(), &paths, chat_id, &file_id, &name).await.map_err(map_err)?;
+  state.invalidate_listings();
+  state.search_index_refresh_file(&db, &file_id).await;
+  events::file_changed(&app, &file_id, Change::Updated, None);
+  Ok(())
+}
+
+#[tauri::command]
+pub async fn file_delete(app: AppHandle, state: State<'_, AppState>, file_id: String) -> Result<(), String> {
+  info!(event = "file_delete", file_id = file_id.as_str(), "Удаление файла");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let dir_id = files::dirs_of(db.pool(), std::slice::from_ref(&file_id)).await.map_err(map_err)?.pop();
+  files::delete_file(db.pool(), tg.as_ref(), &paths, &file_id).await.map_err(map_err)?;
+  thumbnails::forget(&paths, &file_id);
+  state.invalidate_listings();
+  state.search_index_remove_file(&file_id);
+  status_page::record_activity("Удален файл");
+  events::file_changed(&app, &file_id, Change::Deleted, dir_id.as_deref());
+  Ok(())
+}
+
+#[tauri::command]
+pub async fn file_repair(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  file_id: String,
+  upload_token: Option<String>
+) -> Result<RepairResult, String> {
+  info!(event = "file_repair", file_id = file_id.as_str(), "Восстановление файла");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let selected_path = if let Some(token) = upload_token {
+    Some(state.consume_upload_path(&token).ok_or_else(|| {
+      "Файл не подтвержден. Выбери файл через кнопку «Выбрать» и повтори попытку.".to_string()
+    })?)
+  } else {
+    None
+  };
+  let outcome = files::repair_file(
+    db.pool(),
+    tg.as_ref(),
+    &paths,
+    chat_id,
+    &file_id,
+    selected_path.as_deref()
+  )
+    .await
+    .map_err(map_err)?;
+  state.invalidate_listings();
+  if outcome == files::RepairFileResult::Repaired {
+    thumbnails::forget(&paths, &file_id);
+    events::file_changed(&app, &file_id, Change::Updated, None);
+  }
+  match outcome {
+    files::RepairFileResult::Repaired => Ok(RepairResult {
+      ok: true,
+      message: "Файл восстановлен.".to_string(),
+      code: None
+    }),
+    files::RepairFileResult::NeedFile => Ok(RepairResult {
+      ok: false,
+      message: "Не удалось восстановить файл: нужно выбрать файл для переотправки.".to_string(),
+      code: Some(REPAIR_NEED_FILE.to_string())
+    })
+  }
+}
+
+#[tauri::command]
+pub async fn file_verify(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  file_id: String,
+  heal: Option<bool>
+) -> Result<verify::VerifyResult, String> {
+  info!(event = "file_verify", file_id = file_id.as_str(), "Проверка целостности файла");
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let res = verify::verify_file(db.pool(), tg.as_ref(), &paths, chat_id, &file_id, heal.unwrap_or(true))
+    .await
+    .map_err(map_err)?;
+  match res.status {
+    verify::VerifyStatus::Healed => {
+      status_page::record_activity(format!("Файл {} переотправлен из локальной копии", res.name));
+      state.invalidate_listings();
+      events::file_changed(&app, &file_id, Change::Updated, None);
+    }
+    verify::VerifyStatus::Mismatch => {
+      state.invalidate_listings();
+      events::file_changed(&app, &file_id, Change::Updated, None);
+    }
+    _ => {}
+  }
+  Ok(res)
+}
+
+#[tauri::command]
+pub async fn repair_all(app: AppHandle, state: State<'_, AppState>) -> Result<verify::RepairAllReport, String> {
+  info!(event = "repair_all", "Массовое восстановление");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let report = verify::repair_all(db.pool(), tg.as_ref(), &paths, chat_id).await.map_err(map_err)?;
+  for name in &report.healed_names {
+    status_page::record_activity(format!("Файл {name} переотправлен из локальной копии"));
+  }
+  if report.repaired > 0 || report.healed > 0 {
+    state.invalidate_listings();
+    events::tree_updated(&app);
+  }
+  Ok(report)
+}
+
+#[tauri::command]
+pub async fn file_delete_many(app: AppHandle, state: State<'_, AppState>, file_ids: Vec<String>) -> Result<(), String> {
+  info!(event = "file_delete_many", count = file_ids.len(), "Удаление нескольких файлов");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let dir_ids = files::dirs_of(db.pool(), &file_ids).await.map_err(map_err)?;
+  files::delete_files(db.pool(), tg.as_ref(), &paths, &file_ids).await.map_err(map_err)?;
+  state.invalidate_listings();
+  for file_id in &file_ids {
+    thumbnails::forget(&paths, file_id);
+    state.search_index_remove_file(file_id);
+  }
+  // Одно событие на папку, а не на каждый удаленный файл.
+  for dir_id in &dir_ids {
+    events::dir_changed(&app, dir_id, Change::Updated, None);
+  }
+  refresh_vault_summary(&state);
+  Ok(())
+}
+
+fn resolve_download_overwrite(overwrite: Option<bool>) -> bool {
+  overwrite.unwrap_or(false)
+}
+
+async fn file_download_impl(
+  state: &AppState,
+  file_id: &str,
+  overwrite: Option<bool>,
+  progress: Option<ProgressSink>
+) -> Result<String, String> {
+  let path = download_file_path(state, file_id, resolve_download_overwrite(overwrite), progress)
+    .await
+    .map_err(map_err)?;
+  Ok(path.to_string_lossy().to_string())
+}
+
+async fn resolve_file_open_path(state: &AppState, file_id: &str) -> Result<PathBuf, String> {
+  match local_file_path(state, file_id).await.map_err(map_err)? {
+    Some(path) => Ok(path),
+    None => download_file_path(state, file_id, false, None).await.map_err(map_err)
+  }
+}
+
+async fn resolve_file_open_folder_path(state: &AppState, file_id: &str) -> Result<PathBuf, String> {
+  let Some(path) = local_file_path(state, file_id).await.map_err(map_err)? else {
+    return Err("Файл еще не скачан.".to_string());
+  };
+  Ok(path)
+}
+
+#[tauri::command]
+pub async fn file_download(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  file_id: String,
+  overwrite: Option<bool>
+) -> Result<String, String> {
+  info!(event = "file_download", file_id = file_id.as_str(), "Скачивание файла");
+  let progress = events::download_progress_sink(&app, &file_id);
+  file_download_impl(&state, &file_id, overwrite, Some(progress)).await
+}
+
+/// Докачивает прерванное скачивание с того места, где оно оборвалось.
+#[tauri::command]
+pub async fn file_download_resume(app: AppHandle, state: State<'_, AppState>, file_id: String) -> Result<String, String> {
+  info!(event = "file_download_resume", file_id = file_id.as_str(), "Продолжение скачивания файла");
+  let db = state.db().map_err(map_err)?;
+  if !partial_downloads::exists(db.pool(), &file_id).await.map_err(map_err)? {
+    return Err("У этого файла нет прерванного скачивания".into());
+  }
+  let progress = events::download_progress_sink(&app, &file_id);
+  file_download_impl(&state, &file_id, Some(false), Some(progress)).await
+}
+
+#[tauri::command]
+pub async fn downloads_interrupted(state: State<'_, AppState>) -> Result<Vec<partial_downloads::PartialDownload>, String> {
+  let db = state.db().map_err(map_err)?;
+  partial_downloads::list(db.pool()).await.map_err(map_err)
+}
+
+#[tauri::command]
+pub async fn file_queue_download(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  file_id: String,
+  priority: Option<download_queue::Priority>
+) -> Result<(), String> {
+  let priority = priority.unwrap_or(download_queue::Priority::Normal);
+  info!(event = "file_queue_download", file_id = file_id.as_str(), priority = ?priority, "Файл поставлен в очередь загрузок");
+  let db = state.db().map_err(map_err)?;
+  download_queue::enqueue_file(db.pool(), &file_id, priority).await.map_err(map_err)?;
+  download_queue::emit_changed(&app, Some(&file_id), Some("queued"));
+  Ok(())
+}
+
+#[tauri::command]
+pub async fn dir_queue_download(app: AppHandle, state: State<'_, AppState>, dir_id: String) -> Result<usize, String> {
+  info!(event = "dir_queue_download", dir_id = dir_id.as_str(), "Папка поставлена в очередь загрузок");
+  let db = state.db().map_err(map_err)?;
+  let added = download_queue::enqueue_dir(db.pool(), &dir_id).await.map_err(map_err)?;
+  if added > 0 {
+    download_queue::emit_changed(&app, None, None);
+  }
+  Ok(added)
+}
+
+/// Скачивает папку со всеми вложенными в фоне и возвращает id задачи.
+/// Ход и итог приходят событиями `dir_download_progress`.
+#[tauri::command]
+pub async fn dir_download(app: AppHandle, state: State<'_, AppState>, dir_id: String, zip: Option<bool>) -> Result<String, String> {
+  info!(event = "dir_download", dir_id = dir_id.as_str(), zip = zip.unwrap_or(false), "Скачивание папки целиком");
+  let storage_chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let job = dir_download::DirDownloadJob::start(&dir_id, zip.unwrap_or(false));
+  let job_id = job.id.clone();
+  let state = state.inner().clone();
+  tauri::async_runtime::spawn(async move {
+    let res: anyhow::Result<dir_download::DirDownloadProgress> = async {
+      let db = state.db()?;
+      let tg = state.telegram()?;
+      let paths = state.paths()?;
+      let emit = |progress: dir_download::DirDownloadProgress| {
+        let _ = app.emit("dir_download_progress", progress);
+      };
+      dir_download::run(db.pool(), tg, &paths, storage_chat_id, &job, &emit).await
+    }
+    .await;
+    let last = res.unwrap_or_else(|e| {
+      tracing::warn!(event = "dir_download_failed", dir_id = job.dir_id.as_str(), error = %e, "Не удалось скачать папку");
+      dir_download::DirDownloadProgress::failed(&job, e.to_string())
+    });
+    state.invalidate_listings();
+    events::dir_changed(&app, &job.dir_id, Change::Updated, None);
+    let _ = app.emit("dir_download_progress", last);
+  });
+  Ok(job_id)
+}
+
+#[tauri::command]
+pub async fn dir_download_cancel(job_id: String) -> Result<bool, String> {
+  info!(event = "dir_download_cancel", job_id = job_id.as_str(), "Отмена скачивания папки");
+  Ok(dir_download::cancel(&job_id))
+}
+
+/// Ставит загрузку файла в очередь передач и возвращает id передачи.
+#[tauri::command]
+pub async fn transfer_queue_upload(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  dir_id: String,
+  upload_token: String
+) -> Result<String, String> {
+  info!(event = "transfer_queue_upload", dir_id = dir_id.as_str(), "Загрузка поставлена в очередь передач");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  let Some(path) = state.consume_upload_path(&upload_token) else {
+    return Err("Файл не подтвержден. Выбери файл через кнопку «Выбрать и загрузить» и повтори попытку.".into());
+  };
+  let id = download_queue::enqueue_upload(db.pool(), &dir_id, &path).await.map_err(map_err)?;
+  download_queue::emit_transfer_changed(&app, &id, Some("queued"));
+  Ok(id)
+}
+
+/// Ставит скачивание в очередь передач; id передачи совпадает с id файла.
+#[tauri::command]
+pub async fn transfer_queue_download(app: AppHandle, state: State<'_, AppState>, file_id: String) -> Result<String, String> {
+  info!(event = "transfer_queue_download", file_id = file_id.as_str(), "Скачивание поставлено в очередь передач");
+  let db = state.db().map_err(map_err)?;
+  download_queue::enqueue_file(db.pool(), &file_id, download_queue::Priority::Normal).await.map_err(map_err)?;
+  download_queue::emit_changed(&app, Some(&file_id), Some("queued"));
+  download_queue::emit_transfer_changed(&app, &file_id, Some("queued"));
+  Ok(file_id)
+}
+
+#[tauri::command]
+pub async fn transfer_list(state: State<'_, AppState>) -> Result<Vec<download_queue::Transfer>, String> {
+  let db = state.db().map_err(map_err)?;
+  download_queue::list_transfers(db.pool()).await.map_err(map_err)
+}
+
+#[tauri::command]
+pub async fn transfer_pause(app: AppHandle, state: State<'_, AppState>, id: String) -> Result<(), String> {
+  info!(event = "transfer_pause", id = id.as_str(), "Передача на паузе");
+  let db = state.db().map_err(map_err)?;
+  download_queue::pause(db.pool(), &id).await.map_err(map_err)?;
+  download_queue::emit_transfer_changed(&app, &id, Some("paused"));
+  Ok(())
+}
+
+#[tauri::command]
+pub async fn transfer_resume(app: AppHandle, state: State<'_, AppState>, id: String) -> Result<(), String> {
+  info!(event = "transfer_resume", id = id.as_str(), "Передача возобновлена");
+  let db = state.db().map_err(map_err)?;
+  download_queue::resume(db.pool(), &id).await.map_err(map_err)?;
+  download_queue::emit_transfer_changed(&app, &id, Some("queued"));
+  Ok(())
+}
+
+#[tauri::command]
+pub async fn transfer_cancel(app: AppHandle, state: State<'_, AppState>, id: String) -> Result<(), String> {
+  info!(event = "transfer_cancel", id = id.as_str(), "Передача отменена");
+  let db = state.db().map_err(map_err)?;
+  download_queue::cancel(db.pool(), &id).await.map_err(map_err)?;
+  download_queue::emit_transfer_changed(&app, &id, None);
+  Ok(())
+}
+
+#[tauri::command]
+pub async fn settings_get_transfer_concurrency(state: State<'_, AppState>) -> Result<usize, String> {
+  let db = state.db().map_err(map_err)?;
+  settings::get_transfer_concurrency(db.pool()).await.map_err(map_err)
+}
+
+#[tauri::command]
+pub async fn settings_set_transfer_concurrency(state: State<'_, AppState>, limit: usize) -> Result<usize, String> {
+  info!(event = "settings_set_transfer_concurrency", limit = limit, "Изменение числа одновременных передач");
+  let db = state.db().map_err(map_err)?;
+  let limit = settings::set_transfer_concurrency(db.pool(), limit).await.map_err(map_err)?;
+  download_queue::wake();
+  Ok(limit)
+}
+
+#[tauri::command]
+pub async fn download_queue_list(state: State<'_, AppState>) -> Result<Vec<download_queue::QueueItem>, String> {
+  let db = state.db().map_err(map_err)?;
+  download_queue::list(db.pool()).await.map_err(map_err)
+}
+
+#[tauri::command]
+pub async fn download_queue_set_priority(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  file_id: String,
+  priority: download_queue::Priority
+) -> Result<(), String> {
+  let db = state.db().map_err(map_err)?;
+  download_queue::set_priority(db.pool(), &file_id, priority).await.map_err(map_err)?;
+  download_queue::emit_changed(&app, Some(&file_id), None);
+  Ok(())
+}
+
+#[tauri::command]
+pub async fn download_queue_reorder(app: AppHandle, state: State<'_, AppState>, file_ids: Vec<String>) -> Result<(), String> {
+  let db = state.db().map_err(map_err)?;
+  download_queue::reorder(db.pool(), &file_ids).await.map_err(map_err)?;
+  download_queue::emit_changed(&app, None, None);
+  Ok(())
+}
+
+#[tauri::command]
+pub async fn download_queue_remove(app: AppHandle, state: State<'_, AppState>, file_id: String) -> Result<(), String> {
+  let db = state.db().map_err(map_err)?;
+  download_queue::remove(db.pool(), &file_id).await.map_err(map_err)?;
+  download_queue::emit_changed(&app, Some(&file_id), None);
+  Ok(())
+}
+
+/// Проверка перед запуском исполняемого файла. Без действующего токена
+/// возвращает ошибку `NEEDS_CONFIRMATION:<token>: ...`; UI спрашивает
+/// пользователя и повторяет вызов с этим токеном. `Some(name)` означает
+/// подтвержденный запуск, который нужно записать в ленту активности.
+async fn check_open_guard(state: &AppState, file_id: &str, confirm_token: Option<&str>) -> Result<Option<String>, String> {
+  let db = state.db().map_err(map_err)?;
+  let name: Option<String> = sqlx::query("SELECT name FROM files WHERE id = ?")
+    .bind(file_id)
+    .fetch_optional(db.pool())
+    .await
+    .map_err(|e| map_err(e.into()))?
+    .map(|row| row.get("name"));
+  let Some(name) = name else {
+    return Err("Файл не найден".into());
+  };
+  if !open_guard::active().needs_confirmation(&name) {
+    return Ok(None);
+  }
+  if let Some(token) = confirm_token {
+    if state.consume_open_confirmation(token, file_id) {
+      return Ok(Some(name));
+    }
+  }
+  let token = state.issue_open_confirmation(file_id);
+  Err(format!(
+    "{OPEN_NEEDS_CONFIRMATION}:{token}: Файл {name} может запустить программу. Подтверди открытие."
+  ))
+}
+
+/// Антивирусная проверка перед открытием. Угроза или сбой проверки
+/// блокируют открытие ошибкой `OPEN_BLOCKED: ...`.
+async fn scan_before_open(state: &AppState, path: &Path) -> Result<(), String> {
+  let db = state.db().map_err(map_err)?;
+  let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+  match virus_scan::scan(db.pool(), path).await.map_err(map_err)? {
+    virus_scan::Verdict::Skipped | virus_scan::Verdict::Clean { .. } => Ok(()),
+    virus_scan::Verdict::Infected { report } => {
+      tracing::warn!(event = "file_open_blocked", path = %path.display(), report = report.as_str(), "Антивирус обнаружил угрозу");
+      status_page::record_activity(format!("Открытие {name} заблокировано антивирусом"));
+      Err(format!("{OPEN_BLOCKED}: Антивирус обнаружил угрозу в файле {name}. {report}"))
+    }
+    virus_scan::Verdict::Failed { reason } => {
+      tracing::warn!(event = "file_open_scan_failed", path = %path.display(), reason = reason.as_str(), "Проверка файла не удалась");
+      Err(format!("{OPEN_BLOCKED}: Не удалось проверить файл {name}: {reason}"))
+    }
+  }
+}
+
+#[tauri::command]
+pub async fn file_open(state: State<'_, AppState>, file_id: String, confirm_token: Option<String>) -> Result<(), String> {
+  open_file(&state, &file_id, confirm_token.as_deref()).await
+}
+
+async fn open_file(state: &AppState, file_id: &str, confirm_token: Option<&str>) -> Result<(), String> {
+  let confirmed = check_open_guard(state, file_id, confirm_token).await?;
+  let path = resolve_file_open_path(state, file_id).await?;
+  scan_before_open(state, &path).await?;
+  open_file_in_os(&path).map_err(map_err)?;
+  if let Some(name) = confirmed {
+    info!(event = "file_open_executable", file_id = file_id, name = name.as_str(), "Запущен исполняемый файл");
+    status_page::record_activity(format!("Запущен исполняемый файл {name}"));
+  }
+  Ok(())
+}
+
+/// Двойной клик по файлу. Действие берется из настроек по расширению;
+/// `open` и `open_folder` выполняются здесь, а `preview` и `ask` возвращаются
+/// интерфейсу, который показывает просмотр или меню выбора.
+#[tauri::command]
+pub async fn file_activate(
+  state: State<'_, AppState>,
+  file_id: String,
+  confirm_token: Option<String>
+) -> Result<file_actions::FileAction, String> {
+  let db = state.db().map_err(map_err)?;
+  let name: String = sqlx::query("SELECT name FROM files WHERE id = ?")
+    .bind(&file_id)
+    .fetch_optional(db.pool())
+    .await
+    .map_err(|e| map_err(e.into()))?
+    .map(|row| row.get("name"))
+    .ok_or_else(|| "Файл не найден".to_string())?;
+  let action = settings::get_file_actions(db.pool()).await.map_err(map_err)?.action_for(&name);
+  match action {
+    file_actions::FileAction::Open => open_file(&state, &file_id, confirm_token.as_deref()).await?,
+    file_actions::FileAction::OpenFolder => {
+      let path = resolve_file_open_path(&state, &file_id).await?;
+      open_folder_for_file(&path).map_err(map_err)?;
+    }
+    file_actions::FileAction::Preview | file_actions::FileAction::Ask => {}
+  }
+  Ok(action)
+}
+
+/// Начало текстового файла для просмотра без внешней программы.
+#[tauri::command]
+pub async fn file_preview_text(
+  state: State<'_, AppState>,
+  file_id: String,
+  max_bytes: Option<u64>
+) -> Result<preview::TextPreview, String> {
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let max_bytes = max_bytes.unwrap_or(preview::DEFAULT_PREVIEW_BYTES);
+  preview::preview_text(db.pool(), tg.as_ref(), &paths, chat_id, &file_id, max_bytes).await.map_err(map_err)
+}
+
+/// Миниатюра картинки или первой страницы PDF как data URL; `None`, если
+/// для такого файла миниатюр нет.
+#[tauri::command]
+pub async fn file_thumbnail(
+  state: State<'_, AppState>,
+  file_id: String,
+  size: Option<thumbnails::ThumbnailSize>
+) -> Result<Option<String>, String> {
+  use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let Some(path) = thumbnails::file_thumbnail(db.pool(), tg.as_ref(), &paths, chat_id, &file_id, size.unwrap_or_default()).await.map_err(map_err)? else {
+    return Ok(None);
+  };
+  let bytes = std::fs::read(&path).map_err(|e| map_err(e.into()))?;
+  Ok(Some(format!("data:image/png;base64,{}", BASE64.encode(bytes))))
+}
+
+#[tauri::command]
+pub async fn file_open_folder(state: State<'_, AppState>, file_id: String) -> Result<(), String> {
+  let path = resolve_file_open_folder_path(&state, &file_id).await?;
+  open_folder_for_file(&path).map_err(map_err)?;
+  Ok(())
+}
+
+/// Файлы для перетаскивания и картинка под курсором: плагин перетаскивания
+/// принимает только пути на диске.
+#[derive(serde::Serialize)]
+pub struct DragPayload {
+  pub paths: Vec<String>,
+  pub icon: String
+}
+
+/// Готовит файлы к перетаскиванию из приложения: недостающие скачиваются с
+/// прогрессом, в ответе — локальные пути в том же порядке, что и `file_ids`.
+#[tauri::command]
+pub async fn file_prepare_drag(app: AppHandle, state: State<'_, AppState>, file_ids: Vec<String>) -> Result<DragPayload, String> {
+  info!(event = "file_prepare_drag", count = file_ids.len(), "Подготовка файлов к перетаскиванию");
+  let paths = prepare_drag_paths(&state, &file_ids, |file_id| Some(events::download_progress_sink(&app, file_id))).await?;
+  let icon = ensure_drag_icon(&state.paths().map_err(map_err)?).map_err(map_err)?;
+  Ok(DragPayload {
+    paths: paths.into_iter().map(|path| path.to_string_lossy().to_string()).collect(),
+    icon: icon.to_string_lossy().to_string()
+  })
+}
+
+fn ensure_drag_icon(paths: &Paths) -> anyhow::Result<PathBuf> {
+  let path = paths.layout().drag_icon_path();
+  if !path.exists() {
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, include_bytes!("../icons/32x32.png"))?;
+  }
+  Ok(path)
+}
+
+async fn prepare_drag_paths(
+  state: &AppState,
+  file_ids: &[String],
+  progress: impl Fn(&str) -> Option<ProgressSink>
+) -> Result<Vec<PathBuf>, String> {
+  let mut out = Vec::with_capacity(file_ids.len());
+  for file_id in file_ids {
+    let path = match local_file_path(state, file_id).await.map_err(map_err)? {
+      Some(path) => path,
+      None => download_file_path(state, file_id, false, progress(file_id)).await.map_err(map_err)?
+    };
+    out.push(path);
+  }
+  Ok(out)
+}
+
+#[derive(serde::Serialize)]
+pub struct FileStream {
+  pub url: String,
+  /// Файл уже был скачан и отдается с диска.
+  pub local: bool
+}
+
+/// Открывает видео или аудио в плеере ОС, не дожидаясь полного скачивания:
+/// плеер получает адрес на 127.0.0.1 и перематывает запросами Range.
+/// Докачанный до конца файл остается в каталоге загрузок.
+#[tauri::command]
+pub async fn file_stream(app: AppHandle, state: State<'_, AppState>, file_id: String) -> Result<FileStream, String> {
+  info!(event = "file_stream", file_id = file_id.as_str(), "Потоковое открытие файла");
+  let db = state.db().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let source = streaming::prepare(db.pool(), tg.clone(), &paths, &file_id).await.map_err(map_err)?;
+  let local = source.local;
+  let control = source.control.clone().map(|c| c as Arc<dyn StreamControl>);
+  let url = state
+    .register_stream(source.path.clone(), &source.name, source.total, source.available.clone(), control)
+    .map_err(map_err)?;
+  open_url_in_os(&url).map_err(map_err)?;
+  if !local {
+    tauri::async_runtime::spawn(async move {
+      match streaming::keep_completed(db.pool(), tg.as_ref(), &paths, &file_id, &source).await {
+        Ok(true) => app.state::<AppState>().invalidate_listings(),
+        Ok(false) => {}
+        Err(e) => tracing::warn!(event = "stream_keep_failed", file_id = file_id.as_str(), error = %e, "Не удалось сохранить докачанный поток")
+      }
+    });
+  }
+  Ok(FileStream { url, local })
+}
+
+/// Кладет файл в буфер обмена ОС, при необходимости сначала скачав его.
+#[tauri::command]
+pub async fn file_copy_to_clipboard(state: State<'_, AppState>, file_id: String) -> Result<(), String> {
+  info!(event = "file_copy_to_clipboard", file_id = file_id.as_str(), "Копирование файла в буфер обмена");
+  let path = resolve_file_open_path(&state, &file_id).await?;
+  tauri::async_runtime::spawn_blocking(move || clipboard::copy_file(&path))
+    .await
+    .map_err(|e| map_err(e.into()))?
+    .map_err(map_err)
+}
+
+/// Переход по ссылке cloudtg://, пришедший до того, как окно подписалось на события.
+#[tauri::command]
+pub async fn deep_link_pending() -> Result<Option<deep_link::Navigation>, String> {
+  Ok(deep_link::take_pending())
+}
+
+#[tauri::command]
+pub async fn window_open_folder(app: AppHandle, state: State<'_, AppState>, dir_id: String) -> Result<(), String> {
+  info!(event = "window_open_folder", dir_id = dir_id.as_str(), "Открытие папки в отдельном окне");
+  let db = state.db().map_err(map_err)?;
+  let name = files::fetch_dir_name(db.pool(), &dir_id)
+    .await
+    .map_err(map_err)?
+    .ok_or_else(|| "Папка не найдена".to_string())?;
+  deep_link::open_folder_window(&app, &dir_id, &name).map_err(map_err)
+}
+
+/// Сводка для панели хранилища: общий объем, размеры папок, крупные файлы
+/// и место под скачанное.
+#[tauri::command]
+pub async fn storage_stats(state: State<'_, AppState>) -> Result<storage_stats::StorageStats, String> {
+  let db = state.db().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  storage_stats::collect(db.pool(), &paths).await.map_err(map_err)
+}
+
+#[tauri::command]
+pub async fn cache_usage(state: State<'_, AppState>) -> Result<local_cache::CacheUsage, String> {
+  let db = state.db().map_err(map_err)?;
+  local_cache::usage(db.pool()).await.map_err(map_err)
+}
+
+/// Удаляет скачанные файлы — все или только папки `dir_id` (без вложенных);
+/// записи в базе и сообщения остаются.
+#[tauri::command]
+pub async fn cache_clear(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  dir_id: Option<String>
+) -> Result<downloads_cache::ClearReport, String> {
+  info!(event = "cache_clear", dir_id = dir_id.as_deref().unwrap_or(""), "Очистка кэша загрузок");
+  let db = state.db().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let report = local_cache::clear(db.pool(), &paths, dir_id.as_deref()).await.map_err(map_err)?;
+  if report.removed_files > 0 {
+    state.invalidate_listings();
+    match dir_id.as_deref() {
+      Some(dir_id) => events::dir_changed(&app, dir_id, Change::Updated, None),
+      None => events::tree_updated(&app)
+    }
+  }
+  Ok(report)
+}
+
+#[tauri::command]
+pub async fn settings_get_cache_limit(state: State<'_, AppState>) -> Result<local_cache::CacheLimit, String> {
+  let db = state.db().map_err(map_err)?;
+  settings::get_cache_limit(db.pool()).await.map_err(map_err)
+}
+
+/// Сохраняет предел и сразу укладывает в него уже скачанное.
+#[tauri::command]
+pub async fn settings_set_cache_limit(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  limit: local_cache::CacheLimit
+) -> Result<local_cache::CacheLimit, String> {
+  info!(event = "settings_set_cache_limit", enabled = limit.enabled, max_bytes = limit.max_bytes, "Изменение предела кэша загрузок");
+  let db = state.db().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let limit = settings::set_cache_limit(db.pool(), limit).await.map_err(map_err)?;
+  let report = local_cache::enforce(db.pool(), &paths, None).await.map_err(map_err)?;
+  if report.removed_files > 0 {
+    state.invalidate_listings();
+    events::tree_updated(&app);
+  }
+  Ok(limit)
+}
+
+/// Место на диске под скачанные файлы по папкам хранилища.
+#[tauri::command]
+pub async fn downloads_usage(state: State<'_, AppState>) -> Result<Vec<downloads_cache::FolderUsage>, String> {
+  let db = state.db().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  downloads_cache::downloads_usage(db.pool(), &paths).await.map_err(map_err)
+}
+
+#[tauri::command]
+pub async fn file_share_link(state: State<'_, AppState>, file_id: String) -> Result<String, String> {
+  let db = state.db().map_err(map_err)?;
+  let row = sqlx::query("SELECT tg_chat_id, tg_msg_id FROM files WHERE id = ?")
+    .bind(&file_id)
+    .fetch_optional(db.pool())
+    .await
+    .map_err(|e| map_err(e.into()))?;
+  let Some(row) = row else {
+    return Err("Файл не найден".into());
+  };
+  let chat_id: i64 = row.get("tg_chat_id");
+  let msg_id: i64 = row.get("tg_msg_id");
+  files::build_message_link(chat_id, msg_id).map_err(map_err)
+}
+
+#[tauri::command]
+pub async fn tg_search_chats(state: State<'_, AppState>, query: String) -> Result<Vec<ChatView>, String> {
+  let key = format!("search:{}", query.trim().to_lowercase());
+  let items = match state.cached_chats(&key) {
+    Some(items) => items,
+    None => {
+      let tg = state.telegram().map_err(map_err)?;
+      let items = tg.search_chats(query, 20).await.map_err(|e| e.to_string())?;
+      state.store_chats(&key, items.clone());
+      items
+    }
+  };
+  Ok(items.into_iter().map(ChatView::from).collect())
+}
+
+#[tauri::command]
+pub async fn tg_recent_chats(state: State<'_, AppState>) -> Result<Vec<ChatView>, String> {
+  let items = match state.cached_chats("recent") {
+    Some(items) => items,
+    None => {
+      let tg = state.telegram().map_err(map_err)?;
+      let items = tg.recent_chats(RECENT_CHATS_LIMIT).await.map_err(|e| e.to_string())?;
+      state.store_chats("recent", items.clone());
+      items
+    }
+  };
+  Ok(items.into_iter().map(ChatView::from).collect())
+}
+
+#[tauri::command]
+pub async fn tg_chat_folders(state: State<'_, AppState>) -> Result<Vec<ChatFolder>, String> {
+  let tg = state.telegram().map_err(map_err)?;
+  tg.chat_folders().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn tg_folder_chats(state: State<'_, AppState>, folder_id: i32) -> Result<Vec<ChatView>, String> {
+  let key = format!("folder:{folder_id}");
+  let items = match state.cached_chats(&key) {
+    Some(items) => items,
+    None => {
+      let tg = state.telegram().map_err(map_err)?;
+      let items = tg.folder_chats(folder_id, 50).await.map_err(|e| e.to_string())?;
+      state.store_chats(&key, items.clone());
+      items
+    }
+  };
+  Ok(items.into_iter().map(ChatView::from).collect())
+}
+
+#[tauri::command]
+pub async fn file_share_to_chat(state: State<'_, AppState>, file_id: String, chat_id: i64) -> Result<ShareResult, String> {
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let row = sqlx::query("SELECT tg_chat_id, tg_msg_id FROM files WHERE id = ?")
+    .bind(&file_id)
+    .fetch_optional(db.pool())
+    .await
+    .map_err(|e| map_err(e.into()))?;
+  let Some(row) = row else {
+    return Err("Файл не найден".into());
+  };
+  let mut from_chat_id: i64 = row.get("tg_chat_id");
+  let mut msg_id: i64 = row.get("tg_msg_id");
+
+  if tg.forward_message(from_chat_id, chat_id, msg_id).await.is_ok() {
+    return Ok(ShareResult { message: "Сообщение переслано.".into() });
+  }
+
+  {
+    let storage_chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+    if let Ok(Some((found_chat_id, found_msg_id))) =
+      files::find_file_message(tg.as_ref(), from_chat_id, storage_chat_id, &file_id).await
+    {
+      if found_chat_id != from_chat_id || found_msg_id != msg_id {
+        from_chat_id = found_chat_id;
+        msg_id = found_msg_id;
+        sqlx::query("UPDATE files SET tg_chat_id = ?, tg_msg_id = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
+          .bind(from_chat_id)
+          .bind(msg_id)
+          .bind(&file_id)
+          .execute(db.pool())
+          .await
+          .map_err(|e| map_err(e.into()))?;
+      }
+    }
+  }
+
+  tg.forward_message(from_chat_id, chat_id, msg_id)
+    .await
+    .map_err(|e| e.to_string())?;
+
+  Ok(ShareResult { message: "Сообщение переслано.".into() })
+}
+
+#[tauri::command]
+pub async fn tg_test_message(state: State<'_, AppState>) -> Result<(), String> {
+  info!(event = "tg_test_message", "Проверка связи с Telegram");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let ts = Utc::now().to_rfc3339();
+  let text = format!("CloudTG: тестовое сообщение ({ts})");
+  tg.send_text_message(chat_id, text).await.map_err(|e| e.to_string())?;
+  Ok(())
+}
+
+/// Создает новый канал хранения и переносит в него данные. Если перенос
+/// не прошел проверку, старый канал остается, а в отчете — непереносенные файлы.
+#[tauri::command]
+pub async fn tg_create_channel(state: State<'_, AppState>) -> Result<reseed::ChannelMigrationReport, String> {
+  info!(event = "tg_create_channel", "Создание нового канала хранения");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  let pool = db.pool();
+  // Пересоздание — одно на приложение: остальные вызовы ждут нового канала.
+  let mut slot = state.lock_storage_chat().await;
+  slot.forget();
+  let old_id = sync::get_sync(pool, "storage_chat_id")
+    .await
+    .map_err(map_err)?
+    .and_then(|v| v.parse::<i64>().ok())
+    .filter(|id| *id != 777);
+
+  let tg = state.telegram().map_err(map_err)?;
+  let new_id = tg.storage_create_channel().await.map_err(|e| e.to_string())?;
+  remember_old_storage_channel(pool, old_id, new_id).await.map_err(map_err)?;
+  sync::set_sync(pool, "storage_chat_id", &new_id.to_string()).await.map_err(map_err)?;
+
+  let report = match migrate_storage_channel(pool, tg.as_ref(), old_id, new_id).await {
+    Ok(report) => report,
+    Err(e) => {
+      tracing::error!(event = "storage_channel_reseed_failed", error = %e, "Не удалось пересоздать содержимое канала");
+      return Err(format!("Не удалось перенести данные: {e}"));
+    }
+  };
+  slot.store(new_id);
+  drop(slot);
+  state.invalidate_listings();
+
+  let paths = state.paths().map_err(map_err)?;
+  finish_storage_migration(pool, tg.as_ref(), &paths, report).await.map_err(map_err)
+}
+
+/// Старый канал хранения, который остался после неполного переноса.
+#[tauri::command]
+pub async fn tg_old_channel(state: State<'_, AppState>) -> Result<Option<OldStorageChannel>, String> {
+  let db = state.db().map_err(map_err)?;
+  old_storage_channel(db.pool()).await.map_err(map_err)
+}
+
+/// Удаляет оставленный старый канал по решению пользователя. Файлы, которые
+/// так и не перенеслись, помечаются битыми: их сообщений больше нет.
+#[tauri::command]
+pub async fn tg_delete_old_channel(state: State<'_, AppState>) -> Result<(), String> {
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  let pool = db.pool();
+  let Some(old) = old_storage_channel(pool).await.map_err(map_err)? else {
+    return Ok(());
+  };
+  info!(event = "tg_delete_old_channel", chat_id = old.chat_id, files_left = old.files_left, "Удаление старого канала хранения");
+  let tg = state.telegram().map_err(map_err)?;
+  tg.storage_delete_channel(old.chat_id).await.map_err(|e| e.to_string())?;
+  forget_old_storage_channel(pool, old.chat_id).await.map_err(map_err)?;
+  state.invalidate_listings();
+  Ok(())
+}
+
+#[tauri::command]
+pub async fn migration_failures_list(state: State<'_, AppState>) -> Result<Vec<migration_failures::MigrationFailure>, String> {
+  let db = state.db().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  migration_failures::list(db.pool(), &paths).await.map_err(map_err)
+}
+
+/// Повторяет перенос выбранных файлов (или всех, если список не передан).
+#[tauri::command]
+pub async fn migration_failures_retry(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  file_ids: Option<Vec<String>>
+) -> Result<Vec<migration_failures::RetryResult>, String> {
+  info!(event = "migration_failures_retry", "Повторный перенос файлов в канал хранения");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let ids = match file_ids {
+    Some(ids) => ids,
+    None => migration_failures::list(db.pool(), &paths)
+      .await
+      .map_err(map_err)?
+      .into_iter()
+      .map(|f| f.file_id)
+      .collect()
+  };
+  let mut results = Vec::with_capacity(ids.len());
+  for id in ids {
+    let res = migration_failures::retry(db.pool(), tg.as_ref(), &paths, chat_id, &id).await.map_err(map_err)?;
+    if res.outcome != migration_failures::RetryOutcome::Failed {
+      events::file_changed(&app, &id, Change::Updated, None);
+    }
+    results.push(res);
+  }
+  state.invalidate_listings();
+  Ok(results)
+}
+
+#[tauri::command]
+pub async fn storage_reseed_subtree(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  dir_id: String
+) -> Result<reseed::SubtreeReseedReport, String> {
+  info!(event = "storage_reseed_subtree", dir_id = dir_id.as_str(), "Частичное восстановление канала хранения");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let storage_chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let report = reseed::reseed_subtree(db.pool(), tg.as_ref(), &paths, storage_chat_id, &dir_id)
+    .await
+    .map_err(map_err)?;
+  state.invalidate_listings();
+  events::tree_updated(&app);
+  status_page::record_activity(format!(
+    "Папка восстановлена в канале: папок {}, файлов {}",
+    report.dirs_resent, report.files_restored
+  ));
+  Ok(report)
+}
+
+#[tauri::command]
+pub async fn tg_sync_storage(app: AppHandle, state: State<'_, AppState>) -> Result<(), String> {
+  run_storage_sync(&app, &state, false).await.map(|_| ())
+}
+
+/// Инкрементальная синхронизация канала хранения и включенных источников.
+/// Общая для команды и фоновой автосинхронизации (`quiet`); одновременно идет
+/// только один проход. Возвращает число прочитанных сообщений.
+pub(crate) async fn run_storage_sync(app: &AppHandle, state: &AppState, quiet: bool) -> Result<i64, String> {
+  let _guard = auto_sync::lock_sync().await;
+  // Фоновый проход не показывает баннеры синхронизации, только пишет в лог.
+  let report = |stage: &str, message: &str, processed: i64, total: Option<i64>| {
+    if !quiet {
+      emit_sync(app, stage, message, processed, total);
+    }
+  };
+  let started = std::time::Instant::now();
+  let res: Result<i64, String> = async {
+    info!(event = "storage_sync_start", "Синхронизация данных из Telegram");
+    report("start", "Ищу сообщения в канале хранения", 0, None);
+
+    let db = state.db().map_err(map_err)?;
+    let pool = db.pool();
+    let existing_dirs: i64 = sqlx::query("SELECT COUNT(1) as cnt FROM directories")
+      .fetch_one(pool)
       .await
       .map_err(|e| e.to_string())?
       .get::<i64,_>("cnt");
@@ -1119,438 +2795,1403 @@ pub async fn tg_sync_storage(app: AppHandle, state: State<'_, AppState>) -> Resu
       .fetch_one(pool)
       .await
       .map_err(|e| e.to_string())?
-      .get::<i64,_>("cnt");
-    if existing_dirs > 0 || existing_files > 0 {
-      info!(
-        event = "storage_sync_incremental",
-        dirs = existing_dirs,
-        files = existing_files,
-        "Локальные данные уже есть, проверяю новые сообщения"
-      );
-      emit_sync(&app, "progress", "Проверяю новые сообщения канала", 0, None);
+      .get::<i64,_>("cnt");
+    if existing_dirs > 0 || existing_files > 0 {
+      info!(
+        event = "storage_sync_incremental",
+        dirs = existing_dirs,
+        files = existing_files,
+        "Локальные данные уже есть, проверяю новые сообщения"
+      );
+      report("progress", "Проверяю новые сообщения канала", 0, None);
+    }
+
+    let tg = state.telegram().map_err(map_err)?;
+    let chat_id = ensure_storage_chat_id(state).await.map_err(map_err)?;
+
+    let mut from_message_id: i64 = 0;
+    let mut processed: i64 = 0;
+    let total: Option<i64> = None;
+    let mut dir_count: i64 = 0;
+    let mut file_count: i64 = 0;
+    let mut imported_count: i64 = 0;
+    let mut failed_count: i64 = 0;
+    let mut unassigned_dir: Option<(String, String)> = None;
+
+    let last_seen: i64 = sync::get_sync(pool, "storage_last_message_id")
+      .await
+      .map_err(|e| e.to_string())?
+      .and_then(|v| v.parse::<i64>().ok())
+      .unwrap_or(0);
+    let mut newest_seen: Option<i64> = None;
+    let mut stop = false;
+
+    loop {
+      let batch = tg
+        .chat_history(chat_id, from_message_id, 100)
+        .await
+        .map_err(|e| e.to_string())?;
+
+      if batch.messages.is_empty() {
+        break;
+      }
+
+      let mut messages = batch.messages;
+      if let Some(seen) = messages.iter().position(|msg| last_seen > 0 && msg.id <= last_seen) {
+        messages.truncate(seen);
+        stop = true;
+      }
+      processed += messages.len() as i64;
+      if newest_seen.is_none() {
+        newest_seen = messages.first().map(|msg| msg.id);
+      }
+      let outcomes = indexer::index_storage_batch(pool, tg.as_ref(), chat_id, &messages, &mut unassigned_dir)
+        .await
+        .map_err(map_err)?;
+      for outcome in outcomes {
+        if outcome.dir {
+          dir_count += 1;
+        }
+        if outcome.file {
+          file_count += 1;
+        }
+        if outcome.imported {
+          imported_count += 1;
+        }
+        if outcome.failed {
+          failed_count += 1;
+        }
+        if let Some(file_id) = outcome.file_id.as_deref() {
+          state.search_index_refresh_file(&db, file_id).await;
+        }
+      }
+
+      report("progress", "Читаю сообщения канала", processed, total);
+      info!(
+        event = "storage_sync_batch",
+        processed = processed,
+        dirs = dir_count,
+        files = file_count,
+        imported = imported_count,
+        failed = failed_count,
+        next_from_message_id = batch.next_from_message_id,
+        "Обработан пакет сообщений"
+      );
+
+      if stop || batch.next_from_message_id == 0 || batch.next_from_message_id == from_message_id {
+        break;
+      }
+      from_message_id = batch.next_from_message_id;
+    }
+
+    if let Some(latest) = newest_seen {
+      sync::set_sync(pool, "storage_last_message_id", &latest.to_string()).await.map_err(map_err)?;
+    }
+
+    report("progress", "Проверяю дополнительные каналы хранения", processed, total);
+    sync_storage_channels(state, &db, tg.as_ref()).await.map_err(map_err)?;
+
+    report("progress", "Проверяю каналы-источники", processed, total);
+    sync_enabled_sources(state, &db, tg.as_ref()).await.map_err(map_err)?;
+
+    sync::set_sync(pool, "storage_sync_done", &Utc::now().to_rfc3339()).await.map_err(map_err)?;
+    report("success", "Синхронизация завершена", processed, total);
+    info!(
+      event = "storage_sync_done",
+      processed = processed,
+      dirs = dir_count,
+      files = file_count,
+      imported = imported_count,
+      failed = failed_count,
+      "Синхронизация завершена"
+    );
+
+    Ok(processed)
+  }.await;
+
+  metrics::record_sync(started.elapsed(), *res.as_ref().unwrap_or(&0) as u64, res.is_ok());
+  state.invalidate_listings();
+  if res.is_ok() {
+    refresh_vault_summary(state);
+  }
+  if let Err(err) = res.as_ref() {
+    report("error", "Синхронизация не удалась", 0, None);
+    tracing::error!(event = "storage_sync_error", error = err, "Ошибка синхронизации");
+  }
+
+  res
+}
+
+#[tauri::command]
+pub async fn tg_reconcile_recent(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  limit: Option<i64>,
+  force: Option<bool>
+) -> Result<TgReconcileResult, String> {
+  let res: Result<TgReconcileResult, String> = async {
+    let limit = limit.unwrap_or(100).max(1);
+    let db = state.db().map_err(map_err)?;
+    let force = force.unwrap_or(false);
+
+    let sync_done = sync::get_sync(db.pool(), "storage_sync_done").await.map_err(map_err)?;
+    if sync_done.is_none() && !force {
+      return Err(format!(
+        "{RECONCILE_SYNC_REQUIRED}: Сначала запусти импорт из канала хранения или подтверди запуск без него."
+      ));
+    }
+
+    let _guard = auto_sync::lock_sync().await;
+    emit_sync(&app, "start", &format!("Реконсайл последних {limit} сообщений"), 0, Some(limit));
+
+    let tg = state.telegram().map_err(map_err)?;
+    let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+
+    let outcome = reconcile::reconcile_recent(db.pool(), tg.as_ref(), chat_id, limit)
+      .await
+      .map_err(map_err)?;
+
+    let marked = outcome.marked_dirs + outcome.marked_files;
+    let cleared = outcome.cleared_dirs + outcome.cleared_files;
+    let message = format!(
+      "Готово: просмотрено {}, битых отмечено {}, восстановлено {}, импортировано {}.",
+      outcome.scanned, marked, cleared, outcome.imported
+    );
+
+    emit_sync(&app, "success", "Реконсайл завершен", outcome.scanned, Some(limit));
+    if outcome.scanned > 0 && (marked > 0 || cleared > 0 || outcome.imported > 0) {
+      state.invalidate_listings();
+      events::tree_updated(&app);
+      refresh_vault_summary(&state);
+    }
+
+    Ok(TgReconcileResult {
+      message,
+      scanned: outcome.scanned,
+      marked,
+      cleared,
+      imported: outcome.imported
+    })
+  }
+  .await;
+
+  if let Err(err) = res.as_ref() {
+    emit_sync(&app, "error", "Реконсайл не удался", 0, None);
+    tracing::error!(event = "storage_reconcile_error", error = err, "Ошибка реконсайла");
+  }
+
+  res
+}
+
+#[tauri::command]
+pub async fn sync_auto_enable(
+  state: State<'_, AppState>,
+  enabled: bool,
+  interval_secs: Option<u64>
+) -> Result<auto_sync::AutoSyncStatus, String> {
+  let interval_secs = interval_secs.unwrap_or(auto_sync::active().interval_secs);
+  info!(event = "sync_auto_enable", enabled = enabled, interval_secs = interval_secs, "Изменение автосинхронизации");
+  let db = state.db().map_err(map_err)?;
+  let config = settings::set_auto_sync(db.pool(), auto_sync::AutoSyncConfig { enabled, interval_secs })
+    .await
+    .map_err(map_err)?;
+  auto_sync::set_active(config);
+  Ok(auto_sync::status())
+}
+
+#[tauri::command]
+pub async fn sync_auto_status() -> Result<auto_sync::AutoSyncStatus, String> {
+  Ok(auto_sync::status())
+}
+
+#[tauri::command]
+pub async fn storage_unindexed_scan(
+  state: State<'_, AppState>,
+  from_message_id: Option<i64>,
+  limit: Option<i64>
+) -> Result<unindexed::UnindexedPage, String> {
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let limit = limit.unwrap_or(500).clamp(1, 5000);
+  let page = unindexed::scan_unindexed(db.pool(), tg.as_ref(), chat_id, from_message_id, limit)
+    .await
+    .map_err(map_err)?;
+  info!(
+    event = "storage_unindexed_scan",
+    scanned = page.scanned,
+    found = page.items.len(),
+    done = page.done,
+    "Поиск файлов канала без записи в базе"
+  );
+  Ok(page)
+}
+
+#[tauri::command]
+pub async fn storage_unindexed_import(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  message_ids: Vec<i64>,
+  dir_id: Option<String>
+) -> Result<unindexed::UnindexedImportResult, String> {
+  info!(event = "storage_unindexed_import", count = message_ids.len(), "Импорт файлов канала без записи в базе");
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let res = unindexed::import_unindexed(db.pool(), tg.as_ref(), chat_id, &message_ids, dir_id.as_deref())
+    .await
+    .map_err(map_err)?;
+  if res.imported > 0 {
+    for file_id in &res.file_ids {
+      state.search_index_refresh_file(&db, file_id).await;
+    }
+    state.invalidate_listings();
+    events::tree_updated(&app);
+    refresh_vault_summary(&state);
+  }
+  Ok(res)
+}
+
+#[tauri::command]
+pub async fn unassigned_auto_sort(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  dry_run: bool
+) -> Result<auto_sort::AutoSortReport, String> {
+  info!(event = "unassigned_auto_sort", dry_run = dry_run, "Авторазбор «Неразобранного»");
+  let _session = if dry_run {
+    None
+  } else {
+    Some(ensure_channel_writable(&state).await.map_err(map_err)?)
+  };
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let report = auto_sort::auto_sort_unassigned(db.pool(), tg.as_ref(), chat_id, dry_run)
+    .await
+    .map_err(map_err)?;
+  if report.moved > 0 {
+    state.invalidate_listings();
+    events::tree_updated(&app);
+  }
+  Ok(report)
+}
+
+#[derive(serde::Serialize)]
+pub struct BackupCreateResult {
+  pub message: String,
+  pub retention: backup::RetentionPlan
+}
+
+#[tauri::command]
+pub async fn backup_create(state: State<'_, AppState>) -> Result<BackupCreateResult, String> {
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let chat_id = ensure_backup_chat_id(&state).await.map_err(map_err)?;
+
+  let snapshot = backup::create_backup_snapshot(db.pool(), &paths).await.map_err(map_err)?;
+  let caption = backup::build_backup_caption(env!("CARGO_PKG_VERSION"));
+  let res = tg.send_file(chat_id, snapshot.clone(), caption).await.map_err(|e| e.to_string())?;
+  let _ = std::fs::remove_file(&snapshot);
+  sync::set_sync(db.pool(), summary::BACKUP_LAST_AT_KEY, &Utc::now().to_rfc3339()).await.map_err(map_err)?;
+  sync::set_sync(db.pool(), bootstrap::BACKUP_LAST_MESSAGE_KEY, &res.message_id.to_string()).await.map_err(map_err)?;
+  refresh_vault_summary(&state);
+  status_page::record_activity("Создан бэкап базы");
+
+  info!(event = "backup_created", chat_id = res.chat_id, message_id = res.message_id, "Бэкап отправлен в канал");
+
+  let retention = settings::get_backup_retention(db.pool()).await.map_err(map_err)?;
+  let retention = match backup::prune_backups(tg.as_ref(), chat_id, retention).await {
+    Ok(plan) => plan,
+    Err(e) => {
+      tracing::warn!(event = "backup_prune_failed", error = %e, "Не удалось удалить старые бэкапы");
+      backup::RetentionPlan { error: Some(e.to_string()), ..Default::default() }
+    }
+  };
+  let mut message = "Бэкап создан и отправлен в канал CloudTG Backups.".to_string();
+  if !retention.delete.is_empty() {
+    message.push_str(&format!(" Удалено старых бэкапов: {}, осталось: {}.", retention.delete.len(), retention.keep.len()));
+  }
+  Ok(BackupCreateResult { message, retention })
+}
+
+#[tauri::command]
+pub async fn settings_get_backup_retention(state: State<'_, AppState>) -> Result<backup::BackupRetention, String> {
+  let db = state.db().map_err(map_err)?;
+  settings::get_backup_retention(db.pool()).await.map_err(map_err)
+}
+
+#[tauri::command]
+pub async fn settings_set_backup_retention(
+  state: State<'_, AppState>,
+  retention: backup::BackupRetention
+) -> Result<backup::BackupRetention, String> {
+  info!(
+    event = "settings_set_backup_retention",
+    keep_daily = retention.keep_daily,
+    keep_weekly = retention.keep_weekly,
+    "Изменение политики хранения бэкапов"
+  );
+  let db = state.db().map_err(map_err)?;
+  settings::set_backup_retention(db.pool(), retention).await.map_err(map_err)?;
+  Ok(retention)
+}
+
+#[tauri::command]
+pub async fn backup_restore(app: AppHandle, state: State<'_, AppState>) -> Result<BackupResult, String> {
+  let res = prepare_restore(&app, &state).await;
+  if let Err(e) = &res {
+    backup::emit_restore_progress(&app, backup::RestoreStage::Failed, None, e.clone());
+  }
+  res
+}
+
+/// Скачивает или пересобирает базу во временный файл, проверяет ее и только
+/// после этого кладет на место `pending_restore_path`, откуда ее подхватит запуск.
+async fn prepare_restore(app: &AppHandle, state: &AppState) -> Result<BackupResult, String> {
+  use backup::RestoreStage;
+
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  backup::emit_restore_progress(app, RestoreStage::Searching, None, "Ищу последний бэкап");
+  let backup_chat_id = ensure_backup_chat_id(state).await.map_err(map_err)?;
+  let storage_chat_id = ensure_storage_chat_id(state).await.map_err(map_err)?;
+
+  let backup_msg = tg
+    .search_chat_messages(backup_chat_id, backup::BACKUP_TAG.to_string(), 0, 1)
+    .await
+    .map_err(|e| e.to_string())?
+    .messages
+    .into_iter()
+    .next();
+
+  let latest_storage_date = tg
+    .chat_history(storage_chat_id, 0, 1)
+    .await
+    .map_err(|e| e.to_string())?
+    .messages
+    .first()
+    .map(|m| m.date)
+    .unwrap_or(0);
+
+  let pending_path = paths.layout().pending_restore_path();
+  if pending_path.exists() {
+    let _ = std::fs::remove_file(&pending_path);
+  }
+  let candidate_dir = paths.layout().backup_dir();
+  std::fs::create_dir_all(&candidate_dir).map_err(|e| e.to_string())?;
+  let candidate = candidate_dir.join("restore-candidate.sqlite");
+  let _ = std::fs::remove_file(&candidate);
+
+  if let Some(msg) = backup_msg {
+    if latest_storage_date == 0 || msg.date >= latest_storage_date {
+      backup::emit_restore_progress(app, RestoreStage::Downloading, Some(0), "Скачиваю бэкап");
+      let downloaded = tg
+        .download_message_file(backup_chat_id, msg.id, candidate.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+      backup::emit_restore_progress(app, RestoreStage::Downloading, Some(100), "Бэкап скачан");
+      mark_restore_pending(app, &downloaded, &pending_path).await?;
+      return Ok(BackupResult {
+        message: "Бэкап найден. Перезапусти приложение, чтобы применить восстановление.".into()
+      });
+    }
+  }
+
+  let tdlib_path = match state.db() {
+    Ok(db) => settings::get_tdlib_path(db.pool()).await.ok().flatten(),
+    Err(_) => None
+  };
+  let tdlib_effective = resolve_tdlib_path_effective(&paths, tdlib_path.as_deref())
+    .map(|p| p.to_string_lossy().to_string());
+
+  backup::emit_restore_progress(app, RestoreStage::Rebuilding, None, "Актуального бэкапа нет, собираю базу из канала хранения");
+  backup::rebuild_storage_to_path(
+    &candidate,
+    tg.as_ref(),
+    storage_chat_id,
+    tdlib_effective.as_deref()
+  )
+    .await
+    .map_err(map_err)?;
+  mark_restore_pending(app, &candidate, &pending_path).await?;
+  Ok(BackupResult {
+    message: "Актуальный бэкап не найден. Подготовлена новая база из канала хранения. Перезапусти приложение.".into()
+  })
+}
+
+async fn mark_restore_pending(app: &AppHandle, candidate: &Path, pending_path: &Path) -> Result<(), String> {
+  use backup::RestoreStage;
+
+  backup::emit_restore_progress(app, RestoreStage::Validating, None, "Проверяю целостность и версию схемы");
+  if let Err(e) = backup::validate_restore_snapshot(candidate).await {
+    let _ = std::fs::remove_file(candidate);
+    return Err(format!("Бэкап не прошел проверку: {e}"));
+  }
+  std::fs::rename(candidate, pending_path)
+    .or_else(|_| std::fs::copy(candidate, pending_path).map(|_| ()))
+    .map_err(|e| e.to_string())?;
+  let _ = std::fs::remove_file(candidate);
+  info!(event = "backup_restore_pending", path = %pending_path.display(), "Восстановление подготовлено");
+  backup::emit_restore_progress(app, RestoreStage::Pending, Some(100), "Восстановление подготовлено, база будет заменена при перезапуске");
+  Ok(())
+}
+
+#[derive(serde::Serialize)]
+pub struct BootstrapImportResult {
+  pub message: String,
+  pub storage_chat_id: i64,
+  pub restore_pending: bool
+}
+
+#[tauri::command]
+pub async fn bootstrap_export(state: State<'_, AppState>, password: String) -> Result<BackupResult, String> {
+  info!(event = "bootstrap_export", "Экспорт данных для подключения устройства");
+  if password.trim().is_empty() {
+    return Err("Укажи пароль для данных подключения.".into());
+  }
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let storage_chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let data = bootstrap::collect(db.pool(), storage_chat_id, tg.as_ref()).await.map_err(map_err)?;
+  let sealed = bootstrap::seal(&data, &password).map_err(map_err)?;
+  let message_id = bootstrap::upload(tg.as_ref(), &paths, &sealed).await.map_err(map_err)?;
+  info!(event = "bootstrap_exported", message_id = message_id, "Данные для подключения устройства отправлены в Избранное");
+  status_page::record_activity("Данные для подключения устройства отправлены в Избранное");
+  let mut message = "Данные для подключения устройства сохранены в «Избранном».".to_string();
+  if data.last_backup_message_id.is_none() {
+    message.push_str(" Бэкапов пока нет: второе устройство получит каналы, но базу придется собрать из истории.");
+  }
+  Ok(BackupResult { message })
+}
+
+/// Подключение нового устройства: каналы и настройки пишутся в текущую базу,
+/// а последний бэкап готовится к восстановлению при перезапуске.
+#[tauri::command]
+pub async fn bootstrap_import(app: AppHandle, state: State<'_, AppState>, password: String) -> Result<BootstrapImportResult, String> {
+  info!(event = "bootstrap_import", "Импорт данных для подключения устройства");
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let raw = bootstrap::download(tg.as_ref(), &paths).await.map_err(map_err)?;
+  let data = bootstrap::open(&raw, &password).map_err(map_err)?;
+  if !tg.storage_check_channel(data.storage_chat_id).await.unwrap_or(false) {
+    return Err("Канал хранения из данных подключения недоступен этому аккаунту.".into());
+  }
+  bootstrap::apply(db.pool(), &data).await.map_err(map_err)?;
+  state.invalidate_listings();
+  status_page::record_activity("Устройство подключено к хранилищу");
+
+  let (Some(backup_chat_id), Some(message_id)) = (data.backup_chat_id, data.last_backup_message_id) else {
+    return Ok(BootstrapImportResult {
+      message: "Каналы подключены. Бэкапа нет, запусти синхронизацию, чтобы собрать базу из истории.".into(),
+      storage_chat_id: data.storage_chat_id,
+      restore_pending: false
+    });
+  };
+
+  use backup::RestoreStage;
+  let pending_path = paths.layout().pending_restore_path();
+  let _ = std::fs::remove_file(&pending_path);
+  let candidate = paths.layout().backup_dir().join("restore-candidate.sqlite");
+  let _ = std::fs::remove_file(&candidate);
+  backup::emit_restore_progress(&app, RestoreStage::Downloading, Some(0), "Скачиваю бэкап");
+  let downloaded = match tg.download_message_file(backup_chat_id, message_id, candidate).await {
+    Ok(path) => path,
+    Err(e) => {
+      backup::emit_restore_progress(&app, RestoreStage::Failed, None, e.to_string());
+      return Err(format!("Каналы подключены, но бэкап скачать не удалось: {e}"));
+    }
+  };
+  backup::emit_restore_progress(&app, RestoreStage::Downloading, Some(100), "Бэкап скачан");
+  if let Err(e) = mark_restore_pending(&app, &downloaded, &pending_path).await {
+    backup::emit_restore_progress(&app, RestoreStage::Failed, None, e.clone());
+    return Err(e);
+  }
+  Ok(BootstrapImportResult {
+    message: "Устройство подключено. Перезапусти приложение, чтобы применить бэкап.".into(),
+    storage_chat_id: data.storage_chat_id,
+    restore_pending: true
+  })
+}
+
+#[derive(serde::Serialize)]
+pub struct BackupExtractResult {
+  pub message: String,
+  pub restored: i64,
+  pub skipped: i64,
+  pub broken: i64
+}
+
+#[tauri::command]
+pub async fn backup_extract(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  message_id: i64,
+  file_selector: backup::BackupFileSelector,
+  target_dir_id: Option<String>
+) -> Result<BackupExtractResult, String> {
+  info!(event = "backup_extract", message_id = message_id, "Восстановление файлов из бэкапа");
+  let db = state.db().map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  let backup_chat_id = ensure_backup_chat_id(&state).await.map_err(map_err)?;
+  let storage_chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+
+  let dir = paths.layout().backup_dir();
+  std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+  let snapshot_path = dir.join(format!("extract-{message_id}.sqlite"));
+  tg.download_message_file(backup_chat_id, message_id, snapshot_path.clone())
+    .await
+    .map_err(|e| e.to_string())?;
+
+  let res = async {
+    let snapshot = crate::db::Db::connect(snapshot_path.clone()).await?;
+    let stats = backup::extract_files_from_snapshot(
+      db.pool(),
+      snapshot.pool(),
+      tg.as_ref(),
+      storage_chat_id,
+      &file_selector,
+      target_dir_id.as_deref()
+    )
+      .await;
+    snapshot.pool().close().await;
+    stats
+  }
+  .await;
+  for suffix in ["", "-wal", "-shm"] {
+    let _ = std::fs::remove_file(format!("{}{suffix}", snapshot_path.to_string_lossy()));
+  }
+  let stats = res.map_err(map_err)?;
+
+  if stats.restored > 0 {
+    for file_id in &stats.file_ids {
+      state.search_index_refresh_file(&db, file_id).await;
+    }
+    state.invalidate_listings();
+    events::tree_updated(&app);
+  }
+  let message = if stats.broken > 0 {
+    format!(
+      "Восстановлено файлов: {}, из них без сообщения в канале: {}. Пропущено: {}.",
+      stats.restored, stats.broken, stats.skipped
+    )
+  } else {
+    format!("Восстановлено файлов: {}. Пропущено: {}.", stats.restored, stats.skipped)
+  };
+  Ok(BackupExtractResult { message, restored: stats.restored, skipped: stats.skipped, broken: stats.broken })
+}
+
+#[tauri::command]
+pub async fn backup_open_channel(state: State<'_, AppState>) -> Result<BackupResult, String> {
+  let tg = state.telegram().map_err(map_err)?;
+  let backup_chat_id = ensure_backup_chat_id(&state).await.map_err(map_err)?;
+
+  let backup_msg = tg
+    .search_chat_messages(backup_chat_id, backup::BACKUP_TAG.to_string(), 0, 1)
+    .await
+    .map_err(|e| e.to_string())?
+    .messages
+    .into_iter()
+    .next();
+
+  let msg_id = if let Some(msg) = backup_msg {
+    Some(msg.id)
+  } else {
+    tg.chat_history(backup_chat_id, 0, 1)
+      .await
+      .map_err(|e| e.to_string())?
+      .messages
+      .first()
+      .map(|m| m.id)
+  };
+
+  let Some(msg_id) = msg_id else {
+    return Ok(BackupResult {
+      message: format!("Канал бэкапов пуст. chat_id={backup_chat_id}")
+    });
+  };
+
+  let url = files::build_message_link(backup_chat_id, msg_id).map_err(map_err)?;
+  open_url_in_os(&url).map_err(map_err)?;
+  Ok(BackupResult { message: format!("Открываю канал: {url}") })
+}
+
+#[tauri::command]
+pub async fn settings_get_tg(state: State<'_, AppState>) -> Result<TgSettingsView, String> {
+  info!(event = "settings_get_tg", "Чтение настроек Telegram");
+  let db = state.db().map_err(map_err)?;
+  let mut tdlib_path = settings::get_tdlib_path(db.pool()).await.map_err(map_err)?;
+  let paths = state.paths().map_err(map_err)?;
+  if let Some(p) = resolve_tdlib_path_effective(&paths, tdlib_path.as_deref()) {
+    tdlib_path = Some(p.to_string_lossy().to_string());
+  }
+  let runtime = state.tg_credentials().map(|(creds, _)| creds);
+  let (_, status) = secrets::resolve_credentials(&paths, runtime.as_ref());
+
+  Ok(TgSettingsView {
+    tdlib_path,
+    credentials: TgCredentialsView {
+      available: status.available,
+      source: status.source.map(|s| s.as_str().to_string()),
+      keychain_available: status.keychain_available,
+      encrypted_present: status.encrypted_present,
+      locked: status.locked,
+      password_hint: secrets::encrypted_hint(&paths)
     }
+  })
+}
 
-    let tg = state.telegram().map_err(map_err)?;
-    let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
-
-    let mut from_message_id: i64 = 0;
-    let mut processed: i64 = 0;
-    let total: Option<i64> = None;
-    let mut dir_count: i64 = 0;
-    let mut file_count: i64 = 0;
-    let mut imported_count: i64 = 0;
-    let mut failed_count: i64 = 0;
-    let mut unassigned_dir: Option<(String, String)> = None;
+#[tauri::command]
+pub async fn settings_set_tg(state: State<'_, AppState>, input: TgSettingsInput) -> Result<TgSettingsSaveResult, String> {
+  info!(
+    event = "settings_set_tg",
+    api_id = input.api_id.unwrap_or(0),
+    api_hash_len = input.api_hash.as_ref().map(|v| v.len()).unwrap_or(0),
+    tdlib_path_present = input.tdlib_path.as_ref().map(|p| !p.is_empty()).unwrap_or(false),
+    remember = input.remember.unwrap_or(true),
+    "Сохранение настроек Telegram"
+  );
 
-    let last_seen: i64 = sync::get_sync(pool, "storage_last_message_id")
-      .await
-      .map_err(|e| e.to_string())?
-      .and_then(|v| v.parse::<i64>().ok())
-      .unwrap_or(0);
-    let mut newest_seen: Option<i64> = None;
-    let mut stop = false;
+  let db = state.db().map_err(map_err)?;
+  if let Some(p) = input.tdlib_path.as_ref().map(|p| p.trim().to_string()).filter(|p| !p.is_empty()) {
+    let path = std::path::Path::new(&p);
+    if !path.exists() {
+      return Err("Указанный путь к TDLib не существует".into());
+    }
+    if !path.is_file() {
+      return Err("Указанный путь к TDLib должен указывать на файл библиотеки".into());
+    }
+  }
 
-    loop {
-      let batch = tg
-        .chat_history(chat_id, from_message_id, 100)
-        .await
-        .map_err(|e| e.to_string())?;
+  settings::set_tdlib_path(db.pool(), input.tdlib_path.clone()).await.map_err(map_err)?;
 
-      if batch.messages.is_empty() {
-        break;
-      }
+  let paths = state.paths().map_err(map_err)?;
+  let remember = input.remember.unwrap_or(true);
+  let storage_mode = input.storage_mode.as_deref().unwrap_or("keychain");
+  let mut storage: Option<String> = None;
 
-      for msg in batch.messages {
-        if last_seen > 0 && msg.id <= last_seen {
-          stop = true;
-          break;
-        }
-        processed += 1;
-        if newest_seen.is_none() {
-          newest_seen = Some(msg.id);
-        }
-        let outcome = indexer::index_storage_message(pool, tg.as_ref(), chat_id, &msg, &mut unassigned_dir)
-          .await
-          .map_err(map_err)?;
-        if outcome.dir {
-          dir_count += 1;
+  let configured_creds = if let (Some(id), Some(hash)) = (input.api_id, input.api_hash.clone()) {
+    let creds = secrets::normalize_credentials(id, hash).map_err(map_err)?;
+    if !remember {
+      state.set_tg_credentials(creds.clone(), CredentialsSource::Runtime);
+      let _ = secrets::keychain_clear(&paths);
+      let _ = secrets::encrypted_clear(&paths);
+      storage = Some(CredentialsSource::Runtime.as_str().to_string());
+      Some(creds)
+    } else {
+      match storage_mode {
+        "encrypted" => {
+          let password = input.password.clone().unwrap_or_default();
+          if password.trim().is_empty() {
+            return Err("Нужен пароль для шифрования.".into());
+          }
+          secrets::encrypted_save(&paths, &creds, &password, input.password_hint.as_deref()).map_err(map_err)?;
+          let _ = secrets::keychain_clear(&paths);
+          state.set_tg_credentials(creds.clone(), CredentialsSource::EncryptedFile);
+          storage = Some(CredentialsSource::EncryptedFile.as_str().to_string());
         }
-        if outcome.file {
-          file_count += 1;
+        "keychain" | "auto" => {
+          match secrets::keychain_set(&paths, &creds) {
+            Ok(_) => {
+              let _ = secrets::encrypted_clear(&paths);
+              state.set_tg_credentials(creds.clone(), CredentialsSource::Keychain);
+              storage = Some(CredentialsSource::Keychain.as_str().to_string());
+            }
+            Err(_) => {
+              let password = input.password.clone().unwrap_or_default();
+              if password.trim().is_empty() {
+                return Err("Системное хранилище недоступно. Укажи пароль для шифрования.".into());
+              }
+              secrets::encrypted_save(&paths, &creds, &password, input.password_hint.as_deref()).map_err(map_err)?;
+              let _ = secrets::keychain_clear(&paths);
+              state.set_tg_credentials(creds.clone(), CredentialsSource::EncryptedFile);
+              storage = Some(CredentialsSource::EncryptedFile.as_str().to_string());
+            }
+          }
         }
-        if outcome.imported {
-          imported_count += 1;
+        "runtime" => {
+          state.set_tg_credentials(creds.clone(), CredentialsSource::Runtime);
+          let _ = secrets::keychain_clear(&paths);
+          let _ = secrets::encrypted_clear(&paths);
+          storage = Some(CredentialsSource::Runtime.as_str().to_string());
         }
-        if outcome.failed {
-          failed_count += 1;
+        _ => {
+          return Err("Некорректный способ хранения ключей".into());
         }
       }
-
-      emit_sync(&app, "progress", "Читаю сообщения канала", processed, total);
-      info!(
-        event = "storage_sync_batch",
-        processed = processed,
-        dirs = dir_count,
-        files = file_count,
-        imported = imported_count,
-        failed = failed_count,
-        next_from_message_id = batch.next_from_message_id,
-        "Обработан пакет сообщений"
-      );
-
-      if stop || batch.next_from_message_id == 0 || batch.next_from_message_id == from_message_id {
-        break;
-      }
-      from_message_id = batch.next_from_message_id;
+      Some(creds)
     }
+  } else {
+    let runtime = state.tg_credentials().map(|(creds, _)| creds);
+    let (existing, _) = secrets::resolve_credentials(&paths, runtime.as_ref());
+    existing
+  };
 
-    if let Some(latest) = newest_seen {
-      sync::set_sync(pool, "storage_last_message_id", &latest.to_string()).await.map_err(map_err)?;
+  if let Some(creds) = configured_creds {
+    let tg = state.telegram().map_err(map_err)?;
+    tg.configure(creds.api_id, creds.api_hash, input.tdlib_path).await.map_err(|e| e.to_string())?;
+    if !matches!(state.auth_state(), AuthState::Ready) {
+      state.set_auth_state(AuthState::Unknown);
     }
+  } else {
+    state.set_auth_state(AuthState::WaitConfig);
+  }
+
+  info!(event = "settings_set_tg_done", storage = storage.as_deref().unwrap_or("none"), "Настройки Telegram сохранены");
+  let message = match storage.as_deref() {
+    Some("keychain") => "Ключи сохранены в системном хранилище.".to_string(),
+    Some("encrypted") => "Ключи сохранены в зашифрованном файле.".to_string(),
+    Some("runtime") => "Ключи действуют только в текущем запуске.".to_string(),
+    _ => "Настройки сохранены.".to_string()
+  };
+  Ok(TgSettingsSaveResult { storage, message })
+}
+
+/// Перешифровывает файл ключей новым паролем. Шифрования файлов на клиенте
+/// пока нет, поэтому ротировать больше нечего.
+#[tauri::command]
+pub async fn secrets_rotate(
+  state: State<'_, AppState>,
+  old_password: String,
+  new_password: String,
+  new_hint: Option<String>
+) -> Result<(), String> {
+  info!(event = "secrets_rotate", new_password_len = new_password.len(), "Смена пароля зашифрованных ключей");
+  let paths = state.paths().map_err(map_err)?;
+  if !secrets::encrypted_exists(&paths) {
+    return Err("Зашифрованные ключи не найдены".into());
+  }
+  secrets::encrypted_rotate(&paths, &old_password, &new_password, new_hint.as_deref()).map_err(map_err)
+}
+
+#[tauri::command]
+pub async fn password_strength(password: String) -> Result<secrets::PasswordStrength, String> {
+  Ok(secrets::password_strength(&password))
+}
+
+#[tauri::command]
+pub async fn settings_unlock_tg(state: State<'_, AppState>, password: String) -> Result<(), String> {
+  info!(event = "settings_unlock_tg", password_len = password.len(), "Разблокировка ключей");
+  if password.trim().is_empty() {
+    return Err("Нужен пароль для расшифровки".into());
+  }
+  let paths = state.paths().map_err(map_err)?;
+  if !secrets::encrypted_exists(&paths) {
+    return Err("Зашифрованные ключи не найдены".into());
+  }
+  // С привязанным аппаратным ключом разблокировка ждет его касания.
+  let creds = tauri::async_runtime::spawn_blocking(move || secrets::encrypted_load(&paths, &password))
+    .await
+    .map_err(|e| format!("Не удалось разблокировать ключи: {e}"))?
+    .map_err(map_err)?;
+  apply_unlocked_credentials(&state, creds).await
+}
+
+async fn apply_unlocked_credentials(state: &AppState, creds: secrets::TgCredentials) -> Result<(), String> {
+  state.set_tg_credentials(creds.clone(), CredentialsSource::EncryptedFile);
+
+  let db = state.db().map_err(map_err)?;
+  let tdlib_path = settings::get_tdlib_path(db.pool()).await.map_err(map_err)?;
+  let tg = state.telegram().map_err(map_err)?;
+  tg.configure(creds.api_id, creds.api_hash, tdlib_path).await.map_err(|e| e.to_string())?;
+  if !matches!(state.auth_state(), AuthState::Ready) {
+    state.set_auth_state(AuthState::Unknown);
+  }
+  Ok(())
+}
+
+#[derive(serde::Serialize)]
+pub struct Fido2Status {
+  pub supported: bool,
+  pub enrolled: bool
+}
+
+#[tauri::command]
+pub async fn secrets_fido2_status(state: State<'_, AppState>) -> Result<Fido2Status, String> {
+  let paths = state.paths().map_err(map_err)?;
+  let supported = tauri::async_runtime::spawn_blocking(secrets::fido2_supported).await.unwrap_or(false);
+  Ok(Fido2Status { supported, enrolled: secrets::fido2_enrolled(&paths) })
+}
+
+#[tauri::command]
+pub async fn secrets_fido2_enroll(state: State<'_, AppState>, password: String) -> Result<(), String> {
+  info!(event = "secrets_fido2_enroll", "Привязка аппаратного ключа");
+  let paths = state.paths().map_err(map_err)?;
+  if !secrets::encrypted_exists(&paths) {
+    return Err("Зашифрованные ключи не найдены".into());
+  }
+  tauri::async_runtime::spawn_blocking(move || secrets::fido2_enroll(&paths, &password))
+    .await
+    .map_err(|e| format!("Не удалось привязать аппаратный ключ: {e}"))?
+    .map_err(map_err)
+}
+
+#[tauri::command]
+pub async fn secrets_fido2_remove(state: State<'_, AppState>, password: String) -> Result<(), String> {
+  info!(event = "secrets_fido2_remove", "Отвязка аппаратного ключа");
+  let paths = state.paths().map_err(map_err)?;
+  tauri::async_runtime::spawn_blocking(move || secrets::fido2_remove(&paths, &password))
+    .await
+    .map_err(|e| format!("Не удалось отвязать аппаратный ключ: {e}"))?
+    .map_err(map_err)
+}
+
+#[derive(serde::Serialize)]
+pub struct SearchIndexStatus {
+  pub enabled: bool,
+  pub ready: bool,
+  pub files: usize
+}
+
+fn search_index_status(state: &AppState) -> SearchIndexStatus {
+  match state.search_index() {
+    Some(index) => SearchIndexStatus { enabled: true, ready: index.is_ready(), files: index.len() },
+    None => SearchIndexStatus { enabled: false, ready: false, files: 0 }
+  }
+}
+
+#[tauri::command]
+pub async fn settings_get_search_index(state: State<'_, AppState>) -> Result<SearchIndexStatus, String> {
+  Ok(search_index_status(&state))
+}
+
+#[tauri::command]
+pub async fn settings_set_search_index(state: State<'_, AppState>, enabled: bool) -> Result<SearchIndexStatus, String> {
+  info!(event = "settings_set_search_index", enabled = enabled, "Изменение настройки поискового индекса");
+  let db = state.db().map_err(map_err)?;
+  settings::set_search_index_enabled(db.pool(), enabled).await.map_err(map_err)?;
+  match (enabled, state.search_index().is_some()) {
+    (true, false) => state.enable_search_index(db),
+    (false, true) => state.disable_search_index(),
+    _ => {}
+  }
+  Ok(search_index_status(&state))
+}
+
+#[tauri::command]
+pub async fn settings_get_vault_summary(state: State<'_, AppState>) -> Result<bool, String> {
+  let db = state.db().map_err(map_err)?;
+  settings::get_vault_summary_enabled(db.pool()).await.map_err(map_err)
+}
+
+#[tauri::command]
+pub async fn settings_set_vault_summary(state: State<'_, AppState>, enabled: bool) -> Result<bool, String> {
+  info!(event = "settings_set_vault_summary", enabled = enabled, "Изменение настройки сводки хранилища");
+  let db = state.db().map_err(map_err)?;
+  settings::set_vault_summary_enabled(db.pool(), enabled).await.map_err(map_err)?;
+  if enabled {
+    refresh_vault_summary(&state);
+  }
+  Ok(enabled)
+}
+
+#[tauri::command]
+pub async fn settings_get_read_only_channel(state: State<'_, AppState>) -> Result<bool, String> {
+  let db = state.db().map_err(map_err)?;
+  settings::get_read_only_channel(db.pool()).await.map_err(map_err)
+}
+
+#[tauri::command]
+pub async fn settings_set_read_only_channel(state: State<'_, AppState>, enabled: bool) -> Result<bool, String> {
+  info!(event = "settings_set_read_only_channel", enabled = enabled, "Изменение режима канала только для чтения");
+  let db = state.db().map_err(map_err)?;
+  settings::set_read_only_channel(db.pool(), enabled).await.map_err(map_err)?;
+  Ok(enabled)
+}
+
+#[tauri::command]
+pub async fn vault_status(state: State<'_, AppState>) -> Result<vault::VaultStatus, String> {
+  let db = state.db().map_err(map_err)?;
+  vault::status(db.pool()).await.map_err(map_err)
+}
+
+/// Задает пароль шифрования файлов или меняет его (тогда нужен прежний).
+#[tauri::command]
+pub async fn vault_set_passphrase(
+  state: State<'_, AppState>,
+  passphrase: String,
+  old_passphrase: Option<String>
+) -> Result<vault::VaultStatus, String> {
+  info!(event = "vault_set_passphrase", rotate = old_passphrase.is_some(), "Изменение пароля шифрования хранилища");
+  let db = state.db().map_err(map_err)?;
+  vault::set_passphrase(db.pool(), old_passphrase, passphrase).await.map_err(map_err)
+}
+
+#[tauri::command]
+pub async fn vault_unlock(state: State<'_, AppState>, passphrase: String) -> Result<vault::VaultStatus, String> {
+  let db = state.db().map_err(map_err)?;
+  vault::unlock(db.pool(), passphrase).await.map_err(map_err)
+}
+
+#[tauri::command]
+pub async fn vault_lock(state: State<'_, AppState>) -> Result<vault::VaultStatus, String> {
+  info!(event = "vault_lock", "Хранилище заблокировано");
+  vault::lock();
+  let db = state.db().map_err(map_err)?;
+  vault::status(db.pool()).await.map_err(map_err)
+}
+
+#[tauri::command]
+pub async fn settings_set_vault_encryption(state: State<'_, AppState>, enabled: bool) -> Result<vault::VaultStatus, String> {
+  info!(event = "settings_set_vault_encryption", enabled = enabled, "Изменение настройки шифрования файлов");
+  let db = state.db().map_err(map_err)?;
+  vault::set_enabled(db.pool(), enabled).await.map_err(map_err)
+}
 
-    sync::set_sync(pool, "storage_sync_done", &Utc::now().to_rfc3339()).await.map_err(map_err)?;
-    emit_sync(&app, "success", "Синхронизация завершена", processed, total);
-    info!(
-      event = "storage_sync_done",
-      processed = processed,
-      dirs = dir_count,
-      files = file_count,
-      imported = imported_count,
-      failed = failed_count,
-      "Синхронизация завершена"
-    );
+#[derive(serde::Serialize)]
+pub struct StatusPageInfo {
+  pub enabled: bool,
+  pub url: Option<String>
+}
 
-    Ok(())
-  }.await;
+#[tauri::command]
+pub async fn status_page_get(state: State<'_, AppState>) -> Result<StatusPageInfo, String> {
+  let url = state.status_page_url();
+  Ok(StatusPageInfo { enabled: url.is_some(), url })
+}
 
-  if let Err(err) = res.as_ref() {
-    emit_sync(&app, "error", "Синхронизация не удалась", 0, None);
-    tracing::error!(event = "storage_sync_error", error = err, "Ошибка синхронизации");
+#[tauri::command]
+pub async fn status_page_set(state: State<'_, AppState>, enabled: bool) -> Result<StatusPageInfo, String> {
+  info!(event = "status_page_set", enabled = enabled, "Изменение настройки страницы состояния");
+  let db = state.db().map_err(map_err)?;
+  settings::set_status_page_enabled(db.pool(), enabled).await.map_err(map_err)?;
+  if !enabled {
+    state.disable_status_page();
+    return Ok(StatusPageInfo { enabled: false, url: None });
   }
+  let url = state.enable_status_page().map_err(map_err)?;
+  Ok(StatusPageInfo { enabled: true, url: Some(url) })
+}
 
-  res
+#[tauri::command]
+pub async fn dir_prefs_get(state: State<'_, AppState>, dir_id: String) -> Result<dir_prefs::DirPrefs, String> {
+  let db = state.db().map_err(map_err)?;
+  dir_prefs::get_prefs(db.pool(), dir_id.trim()).await.map_err(map_err)
 }
 
 #[tauri::command]
-pub async fn tg_reconcile_recent(
-  app: AppHandle,
+pub async fn dir_prefs_set(
   state: State<'_, AppState>,
-  limit: Option<i64>,
-  force: Option<bool>
-) -> Result<TgReconcileResult, String> {
-  let res: Result<TgReconcileResult, String> = async {
-    let limit = limit.unwrap_or(100).max(1);
-    let db = state.db().map_err(map_err)?;
-    let force = force.unwrap_or(false);
+  dir_id: String,
+  prefs: dir_prefs::DirPrefs
+) -> Result<dir_prefs::DirPrefs, String> {
+  let db = state.db().map_err(map_err)?;
+  dir_prefs::set_prefs(db.pool(), dir_id.trim(), prefs).await.map_err(map_err)
+}
 
-    let sync_done = sync::get_sync(db.pool(), "storage_sync_done").await.map_err(map_err)?;
-    if sync_done.is_none() && !force {
-      return Err(format!(
-        "{RECONCILE_SYNC_REQUIRED}: Сначала запусти импорт из канала хранения или подтверди запуск без него."
-      ));
-    }
+#[tauri::command]
+pub async fn import_rules_list(state: State<'_, AppState>) -> Result<Vec<import_rules::ImportRule>, String> {
+  let db = state.db().map_err(map_err)?;
+  import_rules::list_rules(db.pool()).await.map_err(map_err)
+}
 
-    emit_sync(&app, "start", &format!("Реконсайл последних {limit} сообщений"), 0, Some(limit));
+#[tauri::command]
+pub async fn import_rule_create(
+  state: State<'_, AppState>,
+  rule: import_rules::ImportRuleInput
+) -> Result<import_rules::ImportRule, String> {
+  info!(event = "import_rule_create", name = rule.name.as_str(), target_dir_id = rule.target_dir_id.as_str(), "Создание правила импорта");
+  let db = state.db().map_err(map_err)?;
+  import_rules::create_rule(db.pool(), rule).await.map_err(map_err)
+}
 
-    let tg = state.telegram().map_err(map_err)?;
-    let chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+#[tauri::command]
+pub async fn import_rule_update(
+  state: State<'_, AppState>,
+  rule_id: String,
+  rule: import_rules::ImportRuleInput
+) -> Result<import_rules::ImportRule, String> {
+  info!(event = "import_rule_update", rule_id = rule_id.as_str(), "Изменение правила импорта");
+  let db = state.db().map_err(map_err)?;
+  import_rules::update_rule(db.pool(), &rule_id, rule).await.map_err(map_err)
+}
 
-    let outcome = reconcile::reconcile_recent(db.pool(), tg.as_ref(), chat_id, limit)
-      .await
-      .map_err(map_err)?;
+#[tauri::command]
+pub async fn import_rule_delete(state: State<'_, AppState>, rule_id: String) -> Result<(), String> {
+  info!(event = "import_rule_delete", rule_id = rule_id.as_str(), "Удаление правила импорта");
+  let db = state.db().map_err(map_err)?;
+  import_rules::delete_rule(db.pool(), &rule_id).await.map_err(map_err)
+}
 
-    let marked = outcome.marked_dirs + outcome.marked_files;
-    let cleared = outcome.cleared_dirs + outcome.cleared_files;
-    let message = format!(
-      "Готово: просмотрено {}, битых отмечено {}, восстановлено {}, импортировано {}.",
-      outcome.scanned, marked, cleared, outcome.imported
-    );
+#[tauri::command]
+pub async fn import_rules_reorder(
+  state: State<'_, AppState>,
+  rule_ids: Vec<String>
+) -> Result<Vec<import_rules::ImportRule>, String> {
+  info!(event = "import_rules_reorder", count = rule_ids.len(), "Изменение порядка правил импорта");
+  let db = state.db().map_err(map_err)?;
+  import_rules::reorder_rules(db.pool(), &rule_ids).await.map_err(map_err)
+}
 
-    emit_sync(&app, "success", "Реконсайл завершен", outcome.scanned, Some(limit));
-    if outcome.scanned > 0 && (marked > 0 || cleared > 0 || outcome.imported > 0) {
-      let _ = app.emit("tree_updated", ());
-    }
+#[tauri::command]
+pub async fn source_channels_list(state: State<'_, AppState>) -> Result<Vec<source_channels::SourceChannel>, String> {
+  let db = state.db().map_err(map_err)?;
+  source_channels::list_sources(db.pool()).await.map_err(map_err)
+}
 
-    Ok(TgReconcileResult {
-      message,
-      scanned: outcome.scanned,
-      marked,
-      cleared,
-      imported: outcome.imported
-    })
+#[tauri::command]
+pub async fn source_channel_save(
+  state: State<'_, AppState>,
+  source: source_channels::SourceChannelInput
+) -> Result<source_channels::SourceChannel, String> {
+  info!(event = "source_channel_save", chat_id = source.chat_id, target_dir_id = source.target_dir_id.as_str(), "Сохранение канала-источника");
+  let storage_chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  let extra_storage = storage_channels::list_channels(db.pool()).await.map_err(map_err)?;
+  if source.chat_id == storage_chat_id || extra_storage.iter().any(|c| c.chat_id == source.chat_id) {
+    return Err("Канал хранения нельзя подключить как источник".into());
   }
-  .await;
+  let saved = source_channels::save_source(db.pool(), source).await.map_err(map_err)?;
+  state.invalidate_listings();
+  Ok(saved)
+}
 
-  if let Err(err) = res.as_ref() {
-    emit_sync(&app, "error", "Реконсайл не удался", 0, None);
-    tracing::error!(event = "storage_reconcile_error", error = err, "Ошибка реконсайла");
+#[tauri::command]
+pub async fn source_channel_delete(state: State<'_, AppState>, chat_id: i64) -> Result<(), String> {
+  info!(event = "source_channel_delete", chat_id = chat_id, "Отключение канала-источника");
+  let db = state.db().map_err(map_err)?;
+  let removed = source_channels::delete_source(db.pool(), chat_id).await.map_err(map_err)?;
+  for file_id in &removed {
+    state.search_index_remove_file(file_id);
   }
-
-  res
+  state.invalidate_listings();
+  Ok(())
 }
 
 #[tauri::command]
-pub async fn backup_create(state: State<'_, AppState>) -> Result<BackupResult, String> {
+pub async fn source_channel_sync(state: State<'_, AppState>, chat_id: i64) -> Result<source_channels::SourceSyncReport, String> {
+  info!(event = "source_channel_sync", chat_id = chat_id, "Синхронизация канала-источника");
   let db = state.db().map_err(map_err)?;
   let tg = state.telegram().map_err(map_err)?;
-  let paths = state.paths().map_err(map_err)?;
-  let chat_id = ensure_backup_chat_id(&state).await.map_err(map_err)?;
-
-  let snapshot = backup::create_backup_snapshot(db.pool(), &paths).await.map_err(map_err)?;
-  let caption = backup::build_backup_caption(env!("CARGO_PKG_VERSION"));
-  let res = tg.send_file(chat_id, snapshot.clone(), caption).await.map_err(|e| e.to_string())?;
-  let _ = std::fs::remove_file(&snapshot);
+  let source = source_channels::fetch_source(db.pool(), chat_id).await.map_err(map_err)?;
+  let report = source_channels::sync_source(db.pool(), tg.as_ref(), &source).await.map_err(map_err)?;
+  for file_id in &report.file_ids {
+    state.search_index_refresh_file(&db, file_id).await;
+  }
+  state.invalidate_listings();
+  Ok(report)
+}
 
-  info!(event = "backup_created", chat_id = res.chat_id, message_id = res.message_id, "Бэкап отправлен в канал");
-  Ok(BackupResult { message: "Бэкап создан и отправлен в канал CloudTG Backups.".into() })
+#[tauri::command]
+pub async fn storage_channels_list(state: State<'_, AppState>) -> Result<Vec<storage_channels::StorageChannel>, String> {
+  let db = state.db().map_err(map_err)?;
+  storage_channels::list_channels(db.pool()).await.map_err(map_err)
 }
 
+/// Создает в Telegram еще один канал хранения. Основной канал не меняется:
+/// новый начинает принимать файлы только после назначения ему папок.
 #[tauri::command]
-pub async fn backup_restore(state: State<'_, AppState>) -> Result<BackupResult, String> {
+pub async fn storage_channel_create(state: State<'_, AppState>, title: String) -> Result<storage_channels::StorageChannel, String> {
+  info!(event = "storage_channel_add", title = title.as_str(), "Создание дополнительного канала хранения");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
   let tg = state.telegram().map_err(map_err)?;
-  let paths = state.paths().map_err(map_err)?;
-  let backup_chat_id = ensure_backup_chat_id(&state).await.map_err(map_err)?;
-  let storage_chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
-
-  let backup_msg = tg
-    .search_chat_messages(backup_chat_id, backup::BACKUP_TAG.to_string(), 0, 1)
-    .await
-    .map_err(|e| e.to_string())?
-    .messages
-    .into_iter()
-    .next();
+  let chat_id = tg.storage_create_channel().await.map_err(|e| e.to_string())?;
+  storage_channels::add_channel(db.pool(), chat_id, &title).await.map_err(map_err)
+}
 
-  let latest_storage_date = tg
-    .chat_history(storage_chat_id, 0, 1)
-    .await
-    .map_err(|e| e.to_string())?
-    .messages
-    .first()
-    .map(|m| m.date)
-    .unwrap_or(0);
+/// Назначает папке канал хранения; без `chat_id` папка снова берет канал
+/// родителя или основной. Возвращает канал, куда теперь пойдут загрузки.
+#[tauri::command]
+pub async fn storage_channel_assign(state: State<'_, AppState>, dir_id: String, chat_id: Option<i64>) -> Result<i64, String> {
+  info!(event = "storage_channel_assign", dir_id = dir_id.as_str(), chat_id = chat_id, "Назначение канала хранения папке");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let storage_chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  // Основной канал не назначается: выбрать его значит снять назначение.
+  let chat_id = chat_id.filter(|id| *id != storage_chat_id);
+  if let Some(id) = chat_id {
+    let tg = state.telegram().map_err(map_err)?;
+    if !tg.storage_check_channel(id).await.map_err(|e| e.to_string())? {
+      return Err("Канал хранения недоступен для записи".to_string());
+    }
+  }
+  storage_channels::assign_dir(db.pool(), &dir_id, chat_id).await.map_err(map_err)?;
+  storage_channels::chat_for_dir(db.pool(), &dir_id, storage_chat_id).await.map_err(map_err)
+}
 
-  let pending_path = paths.pending_restore_path();
-  if pending_path.exists() {
-    let _ = std::fs::remove_file(&pending_path);
+/// Догоняет дополнительные каналы хранения после основного. Недоступный
+/// канал не должен ломать основную синхронизацию.
+async fn sync_storage_channels(state: &AppState, db: &crate::db::Db, tg: &dyn crate::telegram::TelegramService) -> anyhow::Result<()> {
+  for channel in storage_channels::list_channels(db.pool()).await? {
+    match storage_channels::sync_channel(db.pool(), tg, &channel).await {
+      Ok(report) => {
+        for file_id in &report.file_ids {
+          state.search_index_refresh_file(db, file_id).await;
+        }
+      }
+      Err(e) => {
+        tracing::warn!(event = "storage_channel_sync_failed", chat_id = channel.chat_id, error = %e, "Не удалось синхронизировать дополнительный канал хранения");
+      }
+    }
   }
+  Ok(())
+}
 
-  if let Some(msg) = backup_msg {
-    if latest_storage_date == 0 || msg.date >= latest_storage_date {
-      tg.download_message_file(backup_chat_id, msg.id, pending_path)
-        .await
-        .map_err(|e| e.to_string())?;
-      return Ok(BackupResult {
-        message: "Бэкап найден. Перезапусти приложение, чтобы применить восстановление.".into()
-      });
+/// Догоняет включенные каналы-источники после синхронизации канала хранения.
+/// Недоступный источник не должен ломать основную синхронизацию.
+async fn sync_enabled_sources(state: &AppState, db: &crate::db::Db, tg: &dyn crate::telegram::TelegramService) -> anyhow::Result<()> {
+  for source in source_channels::list_sources(db.pool()).await? {
+    if !source.enabled {
+      continue;
+    }
+    match source_channels::sync_source(db.pool(), tg, &source).await {
+      Ok(report) => {
+        for file_id in &report.file_ids {
+          state.search_index_refresh_file(db, file_id).await;
+        }
+      }
+      Err(e) => {
+        tracing::warn!(event = "source_channel_sync_failed", chat_id = source.chat_id, error = %e, "Не удалось синхронизировать канал-источник");
+      }
     }
   }
+  Ok(())
+}
 
-  let tdlib_path = match state.db() {
-    Ok(db) => settings::get_tdlib_path(db.pool()).await.ok().flatten(),
-    Err(_) => None
-  };
-  let tdlib_effective = resolve_tdlib_path_effective(&paths, tdlib_path.as_deref())
-    .map(|p| p.to_string_lossy().to_string());
+#[tauri::command]
+pub async fn settings_get_indexer_ignore(state: State<'_, AppState>) -> Result<ignore_list::IgnoreList, String> {
+  let db = state.db().map_err(map_err)?;
+  settings::get_indexer_ignore_list(db.pool()).await.map_err(map_err)
+}
 
-  backup::rebuild_storage_to_path(
-    &pending_path,
-    tg.as_ref(),
-    storage_chat_id,
-    tdlib_effective.as_deref()
-  )
-    .await
-    .map_err(map_err)?;
-  Ok(BackupResult {
-    message: "Актуальный бэкап не найден. Подготовлена новая база из канала хранения. Перезапусти приложение.".into()
-  })
+#[tauri::command]
+pub async fn settings_set_indexer_ignore(
+  state: State<'_, AppState>,
+  list: ignore_list::IgnoreList
+) -> Result<ignore_list::IgnoreList, String> {
+  info!(
+    event = "settings_set_indexer_ignore",
+    senders = list.senders.len(),
+    text_prefixes = list.text_prefixes.len(),
+    content_types = list.content_types.len(),
+    "Изменение списка игнорирования индексатора"
+  );
+  let db = state.db().map_err(map_err)?;
+  let list = settings::set_indexer_ignore_list(db.pool(), list).await.map_err(map_err)?;
+  ignore_list::set_active(list.clone());
+  Ok(list)
+}
+
+#[derive(serde::Serialize)]
+pub struct MaintenanceWindowInfo {
+  pub window: maintenance::MaintenanceWindow,
+  /// Разрешен ли фон прямо сейчас — чтобы UI не считал время сам.
+  pub background_allowed: bool
 }
 
 #[tauri::command]
-pub async fn backup_open_channel(state: State<'_, AppState>) -> Result<BackupResult, String> {
-  let tg = state.telegram().map_err(map_err)?;
-  let backup_chat_id = ensure_backup_chat_id(&state).await.map_err(map_err)?;
+pub async fn settings_get_open_guard(state: State<'_, AppState>) -> Result<open_guard::OpenGuard, String> {
+  let db = state.db().map_err(map_err)?;
+  settings::get_open_guard(db.pool()).await.map_err(map_err)
+}
 
-  let backup_msg = tg
-    .search_chat_messages(backup_chat_id, backup::BACKUP_TAG.to_string(), 0, 1)
-    .await
-    .map_err(|e| e.to_string())?
-    .messages
-    .into_iter()
-    .next();
+#[tauri::command]
+pub async fn settings_set_open_guard(state: State<'_, AppState>, guard: open_guard::OpenGuard) -> Result<open_guard::OpenGuard, String> {
+  info!(event = "settings_set_open_guard", enabled = guard.enabled, "Изменение защиты открытия исполняемых файлов");
+  let db = state.db().map_err(map_err)?;
+  let guard = settings::set_open_guard(db.pool(), guard).await.map_err(map_err)?;
+  open_guard::set_active(guard.clone());
+  Ok(guard)
+}
 
-  let msg_id = if let Some(msg) = backup_msg {
-    Some(msg.id)
-  } else {
-    tg.chat_history(backup_chat_id, 0, 1)
-      .await
-      .map_err(|e| e.to_string())?
-      .messages
-      .first()
-      .map(|m| m.id)
-  };
+#[tauri::command]
+pub async fn settings_get_file_actions(state: State<'_, AppState>) -> Result<file_actions::FileActions, String> {
+  let db = state.db().map_err(map_err)?;
+  settings::get_file_actions(db.pool()).await.map_err(map_err)
+}
 
-  let Some(msg_id) = msg_id else {
-    return Ok(BackupResult {
-      message: format!("Канал бэкапов пуст. chat_id={backup_chat_id}")
-    });
-  };
+#[tauri::command]
+pub async fn settings_set_file_actions(
+  state: State<'_, AppState>,
+  actions: file_actions::FileActions
+) -> Result<file_actions::FileActions, String> {
+  info!(event = "settings_set_file_actions", overrides = actions.by_extension.len(), "Изменение действий двойного клика");
+  let db = state.db().map_err(map_err)?;
+  settings::set_file_actions(db.pool(), actions).await.map_err(map_err)
+}
 
-  let url = files::build_message_link(backup_chat_id, msg_id).map_err(map_err)?;
-  open_url_in_os(&url).map_err(map_err)?;
-  Ok(BackupResult { message: format!("Открываю канал: {url}") })
+#[tauri::command]
+pub async fn settings_get_virus_scan(state: State<'_, AppState>) -> Result<virus_scan::ScannerConfig, String> {
+  let db = state.db().map_err(map_err)?;
+  settings::get_virus_scan(db.pool()).await.map_err(map_err)
+}
+
+#[tauri::command]
+pub async fn settings_set_virus_scan(
+  state: State<'_, AppState>,
+  config: virus_scan::ScannerConfig
+) -> Result<virus_scan::ScannerConfig, String> {
+  info!(event = "settings_set_virus_scan", enabled = config.enabled, command = config.command.as_str(), "Изменение антивирусной проверки");
+  let db = state.db().map_err(map_err)?;
+  let config = settings::set_virus_scan(db.pool(), config).await.map_err(map_err)?;
+  virus_scan::set_active(config.clone());
+  Ok(config)
+}
+
+#[tauri::command]
+pub async fn settings_get_system_dir_names(state: State<'_, AppState>) -> Result<system_dirs::SystemDirNames, String> {
+  let db = state.db().map_err(map_err)?;
+  settings::get_system_dir_names(db.pool()).await.map_err(map_err)
+}
+
+/// Сохраняет имена служебных папок и переименовывает уже созданные папки.
+/// Папки находятся по сохраненному id, поэтому новые копии не создаются.
+#[tauri::command]
+pub async fn settings_set_system_dir_names(
+  app: AppHandle,
+  state: State<'_, AppState>,
+  names: system_dirs::SystemDirNames
+) -> Result<system_dirs::SystemDirNames, String> {
+  info!(event = "settings_set_system_dir_names", "Изменение имен служебных папок");
+  let _session = ensure_channel_writable(&state).await.map_err(map_err)?;
+  let db = state.db().map_err(map_err)?;
+  let pool = db.pool();
+  let old = settings::get_system_dir_names(pool).await.map_err(map_err)?;
+  let names = names.sanitized();
+  if names == old {
+    return Ok(names);
+  }
+  // Папки ищутся до смены активных имен, иначе базы без сохраненных id
+  // не найдут их по старому имени.
+  let mut ids = Vec::new();
+  for dir in system_dirs::SystemDir::RENAMABLE {
+    ids.push(system_dirs::resolve_id(pool, dir).await.map_err(map_err)?);
+  }
+  let names = settings::set_system_dir_names(pool, names).await.map_err(map_err)?;
+  system_dirs::set_active(names.clone());
+  if ids.iter().any(Option::is_some) || names.unknown != old.unknown {
+    let tg = state.telegram().map_err(map_err)?;
+    let storage_chat_id = ensure_storage_chat_id(&state).await.map_err(map_err)?;
+    let renamed = system_dirs::rename_existing(pool, tg.as_ref(), storage_chat_id, &old, &names)
+      .await
+      .map_err(map_err)?;
+    info!(event = "system_dirs_renamed", renamed = renamed, "Служебные папки переименованы");
+    state.invalidate_listings();
+    events::tree_updated(&app);
+  }
+  Ok(names)
 }
 
 #[tauri::command]
-pub async fn settings_get_tg(state: State<'_, AppState>) -> Result<TgSettingsView, String> {
-  info!(event = "settings_get_tg", "Чтение настроек Telegram");
+pub async fn settings_get_maintenance_window(state: State<'_, AppState>) -> Result<MaintenanceWindowInfo, String> {
   let db = state.db().map_err(map_err)?;
-  let mut tdlib_path = settings::get_tdlib_path(db.pool()).await.map_err(map_err)?;
-  let paths = state.paths().map_err(map_err)?;
-  if let Some(p) = resolve_tdlib_path_effective(&paths, tdlib_path.as_deref()) {
-    tdlib_path = Some(p.to_string_lossy().to_string());
-  }
-  let runtime = state.tg_credentials().map(|(creds, _)| creds);
-  let (_, status) = secrets::resolve_credentials(&paths, runtime.as_ref());
-
-  Ok(TgSettingsView {
-    tdlib_path,
-    credentials: TgCredentialsView {
-      available: status.available,
-      source: status.source.map(|s| s.as_str().to_string()),
-      keychain_available: status.keychain_available,
-      encrypted_present: status.encrypted_present,
-      locked: status.locked
-    }
-  })
+  let window = settings::get_maintenance_window(db.pool()).await.map_err(map_err)?;
+  Ok(MaintenanceWindowInfo { window, background_allowed: window.allows_now() })
 }
 
 #[tauri::command]
-pub async fn settings_set_tg(state: State<'_, AppState>, input: TgSettingsInput) -> Result<TgSettingsSaveResult, String> {
+pub async fn settings_set_maintenance_window(
+  state: State<'_, AppState>,
+  window: maintenance::MaintenanceWindow
+) -> Result<MaintenanceWindowInfo, String> {
   info!(
-    event = "settings_set_tg",
-    api_id = input.api_id.unwrap_or(0),
-    api_hash_len = input.api_hash.as_ref().map(|v| v.len()).unwrap_or(0),
-    tdlib_path_present = input.tdlib_path.as_ref().map(|p| !p.is_empty()).unwrap_or(false),
-    remember = input.remember.unwrap_or(true),
-    "Сохранение настроек Telegram"
+    event = "settings_set_maintenance_window",
+    enabled = window.enabled,
+    start_minute = window.start_minute,
+    end_minute = window.end_minute,
+    "Изменение окна обслуживания"
   );
+  let db = state.db().map_err(map_err)?;
+  let window = settings::set_maintenance_window(db.pool(), window).await.map_err(map_err)?;
+  maintenance::set_active(window);
+  download_queue::wake();
+  Ok(MaintenanceWindowInfo { window, background_allowed: window.allows_now() })
+}
 
+#[tauri::command]
+pub async fn settings_get_local_names(state: State<'_, AppState>) -> Result<local_names::LocalNameRules, String> {
   let db = state.db().map_err(map_err)?;
-  if let Some(p) = input.tdlib_path.as_ref().map(|p| p.trim().to_string()).filter(|p| !p.is_empty()) {
-    let path = std::path::Path::new(&p);
-    if !path.exists() {
-      return Err("Указанный путь к TDLib не существует".into());
-    }
-    if !path.is_file() {
-      return Err("Указанный путь к TDLib должен указывать на файл библиотеки".into());
-    }
-  }
+  settings::get_local_name_rules(db.pool()).await.map_err(map_err)
+}
 
-  settings::set_tdlib_path(db.pool(), input.tdlib_path.clone()).await.map_err(map_err)?;
+#[tauri::command]
+pub async fn settings_set_local_names(
+  state: State<'_, AppState>,
+  rules: local_names::LocalNameRules
+) -> Result<local_names::LocalNameRules, String> {
+  info!(event = "settings_set_local_names", profile = ?rules.profile, "Изменение правил локальных имен файлов");
+  let db = state.db().map_err(map_err)?;
+  let rules = settings::set_local_name_rules(db.pool(), rules).await.map_err(map_err)?;
+  local_names::set_active(rules);
+  state.invalidate_listings();
+  Ok(rules)
+}
 
-  let paths = state.paths().map_err(map_err)?;
-  let remember = input.remember.unwrap_or(true);
-  let storage_mode = input.storage_mode.as_deref().unwrap_or("keychain");
-  let mut storage: Option<String> = None;
+/// Как имена из канала будут выглядеть на диске. Без `rules` — по текущим
+/// настройкам, с ними — чтобы показать результат до сохранения.
+#[tauri::command]
+pub async fn local_name_preview(
+  names: Vec<String>,
+  rules: Option<local_names::LocalNameRules>
+) -> Result<Vec<local_names::LocalNamePreview>, String> {
+  let rules = rules.unwrap_or_else(local_names::active);
+  Ok(names.iter().map(|name| local_names::preview(name, &rules)).collect())
+}
 
-  let configured_creds = if let (Some(id), Some(hash)) = (input.api_id, input.api_hash.clone()) {
-    let creds = secrets::normalize_credentials(id, hash).map_err(map_err)?;
-    if !remember {
-      state.set_tg_credentials(creds.clone(), CredentialsSource::Runtime);
-      let _ = secrets::keychain_clear();
-      let _ = secrets::encrypted_clear(&paths);
-      storage = Some(CredentialsSource::Runtime.as_str().to_string());
-      Some(creds)
-    } else {
-      match storage_mode {
-        "encrypted" => {
-          let password = input.password.clone().unwrap_or_default();
-          if password.trim().is_empty() {
-            return Err("Нужен пароль для шифрования.".into());
-          }
-          secrets::encrypted_save(&paths, &creds, &password).map_err(map_err)?;
-          let _ = secrets::keychain_clear();
-          state.set_tg_credentials(creds.clone(), CredentialsSource::EncryptedFile);
-          storage = Some(CredentialsSource::EncryptedFile.as_str().to_string());
-        }
-        "keychain" | "auto" => {
-          match secrets::keychain_set(&creds) {
-            Ok(_) => {
-              let _ = secrets::encrypted_clear(&paths);
-              state.set_tg_credentials(creds.clone(), CredentialsSource::Keychain);
-              storage = Some(CredentialsSource::Keychain.as_str().to_string());
-            }
-            Err(_) => {
-              let password = input.password.clone().unwrap_or_default();
-              if password.trim().is_empty() {
-                return Err("Системное хранилище недоступно. Укажи пароль для шифрования.".into());
-              }
-              secrets::encrypted_save(&paths, &creds, &password).map_err(map_err)?;
-              let _ = secrets::keychain_clear();
-              state.set_tg_credentials(creds.clone(), CredentialsSource::EncryptedFile);
-              storage = Some(CredentialsSource::EncryptedFile.as_str().to_string());
-            }
-          }
-        }
-        "runtime" => {
-          state.set_tg_credentials(creds.clone(), CredentialsSource::Runtime);
-          let _ = secrets::keychain_clear();
-          let _ = secrets::encrypted_clear(&paths);
-          storage = Some(CredentialsSource::Runtime.as_str().to_string());
-        }
-        _ => {
-          return Err("Некорректный способ хранения ключей".into());
-        }
-      }
-      Some(creds)
-    }
-  } else {
-    let runtime = state.tg_credentials().map(|(creds, _)| creds);
-    let (existing, _) = secrets::resolve_credentials(&paths, runtime.as_ref());
-    existing
-  };
+#[derive(serde::Serialize)]
+pub struct TranscriptionSettings {
+  pub mode: Option<transcripts::TranscriptSource>,
+  pub local_available: bool
+}
 
-  if let Some(creds) = configured_creds {
-    let tg = state.telegram().map_err(map_err)?;
-    tg.configure(creds.api_id, creds.api_hash, input.tdlib_path).await.map_err(|e| e.to_string())?;
-    if !matches!(state.auth_state(), AuthState::Ready) {
-      state.set_auth_state(AuthState::Unknown);
+#[tauri::command]
+pub async fn settings_get_transcription(state: State<'_, AppState>) -> Result<TranscriptionSettings, String> {
+  let db = state.db().map_err(map_err)?;
+  let mode = settings::get_transcription_mode(db.pool()).await.map_err(map_err)?;
+  Ok(TranscriptionSettings {
+    mode,
+    local_available: transcripts::TranscriptSource::Local.is_available()
+  })
+}
+
+#[tauri::command]
+pub async fn settings_set_transcription(
+  state: State<'_, AppState>,
+  mode: Option<transcripts::TranscriptSource>
+) -> Result<TranscriptionSettings, String> {
+  info!(event = "settings_set_transcription", mode = mode.map(|m| m.as_str()).unwrap_or("off"), "Изменение режима расшифровки голосовых");
+  if let Some(source) = mode {
+    if !source.is_available() {
+      return Err("Локальное распознавание недоступно в этой сборке".into());
     }
-  } else {
-    state.set_auth_state(AuthState::WaitConfig);
   }
+  let db = state.db().map_err(map_err)?;
+  settings::set_transcription_mode(db.pool(), mode).await.map_err(map_err)?;
+  Ok(TranscriptionSettings {
+    mode,
+    local_available: transcripts::TranscriptSource::Local.is_available()
+  })
+}
 
-  info!(event = "settings_set_tg_done", storage = storage.as_deref().unwrap_or("none"), "Настройки Telegram сохранены");
-  let message = match storage.as_deref() {
-    Some("keychain") => "Ключи сохранены в системном хранилище.".to_string(),
-    Some("encrypted") => "Ключи сохранены в зашифрованном файле.".to_string(),
-    Some("runtime") => "Ключи действуют только в текущем запуске.".to_string(),
-    _ => "Настройки сохранены.".to_string()
-  };
-  Ok(TgSettingsSaveResult { storage, message })
+#[derive(serde::Serialize)]
+pub struct TdlibTimeoutsInfo {
+  pub preset: TimeoutPreset,
+  pub profile: TimeoutProfile
 }
 
 #[tauri::command]
-pub async fn settings_unlock_tg(state: State<'_, AppState>, password: String) -> Result<(), String> {
-  info!(event = "settings_unlock_tg", password_len = password.len(), "Разблокировка ключей");
-  if password.trim().is_empty() {
-    return Err("Нужен пароль для расшифровки".into());
-  }
-  let paths = state.paths().map_err(map_err)?;
-  if !secrets::encrypted_exists(&paths) {
-    return Err("Зашифрованные ключи не найдены".into());
-  }
-  let creds = secrets::encrypted_load(&paths, &password).map_err(map_err)?;
-  state.set_tg_credentials(creds.clone(), CredentialsSource::EncryptedFile);
+pub async fn settings_get_tdlib_timeouts(state: State<'_, AppState>) -> Result<TdlibTimeoutsInfo, String> {
+  let db = state.db().map_err(map_err)?;
+  let (preset, profile) = settings::get_tdlib_timeouts(db.pool()).await.map_err(map_err)?;
+  Ok(TdlibTimeoutsInfo { preset, profile })
+}
 
+#[tauri::command]
+pub async fn settings_set_tdlib_timeouts(
+  state: State<'_, AppState>,
+  preset: TimeoutPreset,
+  custom: Option<TimeoutProfile>
+) -> Result<TdlibTimeoutsInfo, String> {
+  info!(event = "settings_set_tdlib_timeouts", preset = preset.as_str(), "Изменение профиля таймаутов TDLib");
   let db = state.db().map_err(map_err)?;
-  let tdlib_path = settings::get_tdlib_path(db.pool()).await.map_err(map_err)?;
-  let tg = state.telegram().map_err(map_err)?;
-  tg.configure(creds.api_id, creds.api_hash, tdlib_path).await.map_err(|e| e.to_string())?;
-  if !matches!(state.auth_state(), AuthState::Ready) {
-    state.set_auth_state(AuthState::Unknown);
-  }
-  Ok(())
+  let profile = settings::set_tdlib_timeouts(db.pool(), preset, custom).await.map_err(map_err)?;
+  timeouts::set_profile(profile);
+  Ok(TdlibTimeoutsInfo { preset, profile })
 }
 
 async fn reseed_storage_channel(
@@ -1558,7 +4199,7 @@ async fn reseed_storage_channel(
   tg: &dyn crate::telegram::TelegramService,
   old_chat_id: Option<i64>,
   new_chat_id: i64
-) -> anyhow::Result<()> {
+) -> anyhow::Result<u64> {
   info!(event = "storage_channel_reseed_start", old_chat_id = old_chat_id.unwrap_or(0), new_chat_id = new_chat_id, "Пересоздание содержимого канала");
 
   let now = Utc::now().timestamp();
@@ -1573,7 +4214,7 @@ async fn reseed_storage_channel(
     let parent_id = raw_parent.filter(|p| !p.trim().is_empty() && p != "ROOT").unwrap_or_else(|| "ROOT".to_string());
     let msg = make_dir_message(&DirMeta { dir_id: id.clone(), parent_id, name });
     let uploaded = tg.send_dir_message(new_chat_id, msg).await?;
-    sqlx::query("UPDATE directories SET tg_msg_id = ?, updated_at = ?, is_broken = 0 WHERE id = ?")
+    sqlx::query("UPDATE directories SET tg_msg_id = ?, updated_at = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
       .bind(uploaded.message_id)
       .bind(now)
       .bind(&id)
@@ -1581,12 +4222,44 @@ async fn reseed_storage_channel(
       .await?;
   }
 
-  let file_rows = sqlx::query("SELECT id, tg_chat_id, tg_msg_id FROM files ORDER BY tg_chat_id, tg_msg_id")
+  let link_rows = sqlx::query("SELECT id, dir_id, target_kind, target_id FROM links ORDER BY created_at")
+    .fetch_all(pool)
+    .await?;
+
+  for r in link_rows {
+    let id: String = r.get("id");
+    let raw_kind: String = r.get("target_kind");
+    let Some(kind) = LinkKind::parse(&raw_kind) else {
+      continue;
+    };
+    let msg = make_link_message(&LinkMeta {
+      link_id: id.clone(),
+      dir_id: r.get::<String,_>("dir_id"),
+      kind,
+      target_id: r.get::<String,_>("target_id")
+    });
+    let uploaded = tg.send_dir_message(new_chat_id, msg).await?;
+    sqlx::query("UPDATE links SET tg_msg_id = ? WHERE id = ?")
+      .bind(uploaded.message_id)
+      .bind(&id)
+      .execute(pool)
+      .await?;
+  }
+
+  // Переносятся только файлы канала хранения: файлы дополнительных каналов
+  // и каналов-источников остаются там, где лежат.
+  let file_rows = sqlx::query(
+    "SELECT id, tg_chat_id, tg_msg_id FROM files
+     WHERE tg_chat_id NOT IN (SELECT chat_id FROM storage_channels)
+       AND tg_chat_id NOT IN (SELECT chat_id FROM source_channels)
+     ORDER BY tg_chat_id, tg_msg_id"
+  )
     .fetch_all(pool)
     .await?;
 
   let mut current_chat: Option<i64> = None;
   let mut batch: Vec<(String, i64)> = Vec::new();
+  let mut copied: u64 = 0;
 
   for r in file_rows {
     let file_id: String = r.get("id");
@@ -1597,18 +4270,18 @@ async fn reseed_storage_channel(
     }
     if current_chat != Some(chat_id) {
       if let Some(c) = current_chat {
-        flush_file_batch(pool, tg, old_chat_id, new_chat_id, c, &mut batch).await?;
+        copied += flush_file_batch(pool, tg, old_chat_id, new_chat_id, c, &mut batch).await?;
       }
       current_chat = Some(chat_id);
     }
     batch.push((file_id, msg_id));
   }
   if let Some(c) = current_chat {
-    flush_file_batch(pool, tg, old_chat_id, new_chat_id, c, &mut batch).await?;
+    copied += flush_file_batch(pool, tg, old_chat_id, new_chat_id, c, &mut batch).await?;
   }
 
-  info!(event = "storage_channel_reseed_done", "Пересоздание содержимого канала завершено");
-  Ok(())
+  info!(event = "storage_channel_reseed_done", copied = copied, "Пересоздание содержимого канала завершено");
+  Ok(copied)
 }
 
 async fn flush_file_batch(
@@ -1618,22 +4291,23 @@ async fn flush_file_batch(
   new_chat_id: i64,
   chat_id: i64,
   items: &mut Vec<(String, i64)>
-) -> anyhow::Result<()> {
+) -> anyhow::Result<u64> {
   if items.is_empty() {
-    return Ok(());
+    return Ok(0);
   }
   if chat_id == new_chat_id {
     items.clear();
-    return Ok(());
+    return Ok(0);
   }
   if let Some(old) = old_chat_id {
     if chat_id != old {
       tracing::warn!(event = "storage_channel_reseed_skip", chat_id = chat_id, "Файлы относятся к другому каналу, пропускаю");
       items.clear();
-      return Ok(());
+      return Ok(0);
     }
   }
 
+  let mut done: u64 = 0;
   let mut start = 0;
   while start < items.len() {
     let end = (start + 100).min(items.len());
@@ -1648,30 +4322,42 @@ async fn flush_file_batch(
         "TDLib вернул неожиданное число сообщений при копировании"
       );
     }
-    for (idx, result) in copied.into_iter().enumerate().take(chunk.len()) {
-      if let Some(new_id) = result {
-        let file_id = &chunk[idx].0;
-        sqlx::query("UPDATE files SET tg_chat_id = ?, tg_msg_id = ?, is_broken = 0 WHERE id = ?")
+    // Сообщения, на которые TDLib не вернул результат, тоже считаются не скопированными.
+    for (idx, (file_id, old_msg_id)) in chunk.iter().enumerate() {
+      if let Some(new_id) = copied.get(idx).copied().flatten() {
+        sqlx::query("UPDATE files SET tg_chat_id = ?, tg_msg_id = ?, is_broken = 0, broken_reason = NULL, broken_since = NULL WHERE id = ?")
           .bind(new_chat_id)
           .bind(new_id)
           .bind(file_id)
           .execute(pool)
           .await?;
+        files::copy_parts(pool, tg, file_id, new_chat_id, new_id).await?;
+        done += 1;
+      } else if !tg.message_exists(chat_id, *old_msg_id).await.unwrap_or(true) {
+        // Сообщение удалили до переноса: переносить нечего, повтор не поможет.
+        tracing::warn!(
+          event = "storage_channel_reseed_source_deleted",
+          old_chat_id = chat_id,
+          file_id = file_id,
+          "Сообщение файла удалено, перенести его нельзя"
+        );
+        broken::mark_file_broken(pool, file_id, broken::BrokenReason::MessageDeleted).await?;
       } else {
-        let file_id = &chunk[idx].0;
         tracing::warn!(
           event = "storage_channel_reseed_file_failed",
           old_chat_id = chat_id,
           file_id = file_id,
           "Не удалось скопировать файл в новый канал"
         );
+        broken::mark_file_broken(pool, file_id, broken::BrokenReason::ChannelMigrated).await?;
+        migration_failures::record(pool, file_id, chat_id, *old_msg_id, new_chat_id, "TDLib не скопировал сообщение").await?;
       }
     }
     start = end;
   }
 
   items.clear();
-  Ok(())
+  Ok(done)
 }
 
 fn mask_phone(phone: &str) -> String {
@@ -1801,9 +4487,14 @@ mod tests {
   use crate::db::Db;
   use crate::telegram::{
     ChatId,
+    ChatFolder,
     ChatInfo,
+    ChatRef,
     MessageId,
+    RangeSink,
     SearchMessagesResult,
+    StickerSetInfo,
+    StreamingFile,
     TelegramService,
     TgError,
     UploadedMessage
@@ -1819,7 +4510,12 @@ mod tests {
     storage_chat_id: ChatId,
     storage_check_ok: bool,
     payloads: HashMap<(ChatId, MessageId), Vec<u8>>,
-    download_attempts: Vec<(ChatId, MessageId)>
+    download_attempts: Vec<(ChatId, MessageId)>,
+    /// Куда «TDLib» кладет потоковые файлы.
+    stream_dir: Option<PathBuf>,
+    /// Счетчик id сообщений, отправленных или скопированных моком.
+    next_message_id: MessageId,
+    speech_calls: usize
   }
 
   impl MockTelegram {
@@ -1840,6 +4536,11 @@ mod tests {
       self
     }
 
+    fn with_stream_dir(self, dir: PathBuf) -> Self {
+      self.inner.lock().expect("mock lock").stream_dir = Some(dir);
+      self
+    }
+
     fn download_attempts(&self) -> Vec<(ChatId, MessageId)> {
       let guard = self.inner.lock().expect("mock lock");
       guard.download_attempts.clone()
@@ -1894,6 +4595,14 @@ mod tests {
       Ok(())
     }
 
+    async fn storage_chat_ref(&self, _chat_id: ChatId) -> Result<ChatRef, TgError> {
+      Ok(ChatRef::default())
+    }
+
+    async fn storage_resolve_chat_ref(&self, _chat_ref: &ChatRef) -> Result<Option<ChatId>, TgError> {
+      Ok(None)
+    }
+
     async fn backup_check_channel(&self, _chat_id: ChatId) -> Result<bool, TgError> {
       Ok(false)
     }
@@ -1946,8 +4655,10 @@ mod tests {
       Err(TgError::NotImplemented)
     }
 
-    async fn send_dir_message(&self, _chat_id: ChatId, _text: String) -> Result<UploadedMessage, TgError> {
-      Err(TgError::NotImplemented)
+    async fn send_dir_message(&self, chat_id: ChatId, text: String) -> Result<UploadedMessage, TgError> {
+      let mut guard = self.inner.lock().expect("mock lock");
+      guard.next_message_id += 1;
+      Ok(UploadedMessage { chat_id, message_id: 10_000 + guard.next_message_id, caption_or_text: text })
     }
 
     async fn edit_message_text(
@@ -1959,6 +4670,10 @@ mod tests {
       Err(TgError::NotImplemented)
     }
 
+    async fn pin_message(&self, _chat_id: ChatId, _message_id: MessageId) -> Result<(), TgError> {
+      Err(TgError::NotImplemented)
+    }
+
     async fn edit_message_caption(
       &self,
       _chat_id: ChatId,
@@ -1997,11 +4712,23 @@ mod tests {
 
     async fn copy_messages(
       &self,
-      _from_chat_id: ChatId,
-      _to_chat_id: ChatId,
-      _message_ids: Vec<MessageId>
+      from_chat_id: ChatId,
+      to_chat_id: ChatId,
+      message_ids: Vec<MessageId>
     ) -> Result<Vec<Option<MessageId>>, TgError> {
-      Err(TgError::NotImplemented)
+      let mut guard = self.inner.lock().expect("mock lock");
+      let mut out = Vec::with_capacity(message_ids.len());
+      for id in message_ids {
+        let Some(payload) = guard.payloads.get(&(from_chat_id, id)).cloned() else {
+          out.push(None);
+          continue;
+        };
+        guard.next_message_id += 1;
+        let new_id = 10_000 + guard.next_message_id;
+        guard.payloads.insert((to_chat_id, new_id), payload);
+        out.push(Some(new_id));
+      }
+      Ok(out)
     }
 
     async fn delete_messages(
@@ -2035,8 +4762,47 @@ mod tests {
       Ok(target)
     }
 
-    async fn message_exists(&self, _chat_id: ChatId, _message_id: MessageId) -> Result<bool, TgError> {
-      Ok(false)
+    async fn stream_message_file(
+      &self,
+      chat_id: ChatId,
+      message_id: MessageId,
+      ranges: RangeSink
+    ) -> Result<StreamingFile, TgError> {
+      let guard = self.inner.lock().expect("mock lock");
+      let dir = guard.stream_dir.clone().ok_or(TgError::NotImplemented)?;
+      let payload = guard.payloads.get(&(chat_id, message_id)).cloned().unwrap_or_else(|| b"payload".to_vec());
+      drop(guard);
+      std::fs::create_dir_all(&dir).map_err(TgError::Io)?;
+      let path = dir.join(format!("{chat_id}_{message_id}"));
+      std::fs::write(&path, &payload).map_err(TgError::Io)?;
+      ranges(0, payload.len() as u64);
+      Ok(StreamingFile { path, total: Some(payload.len() as u64), file_id: message_id, token: 1 })
+    }
+
+    async fn message_exists(&self, chat_id: ChatId, message_id: MessageId) -> Result<bool, TgError> {
+      Ok(self.inner.lock().expect("mock lock").payloads.contains_key(&(chat_id, message_id)))
+    }
+
+    async fn recognize_speech(&self, _chat_id: ChatId, _message_id: MessageId) -> Result<String, TgError> {
+      self.inner.lock().expect("mock lock").speech_calls += 1;
+      tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+      Ok("Купить молоко".to_string())
+    }
+
+    async fn sticker_set_info(&self, _set_id: String) -> Result<StickerSetInfo, TgError> {
+      Err(TgError::NotImplemented)
+    }
+
+    async fn saved_messages_chat(&self) -> Result<ChatId, TgError> {
+      Err(TgError::NotImplemented)
+    }
+
+    async fn chat_folders(&self) -> Result<Vec<ChatFolder>, TgError> {
+      Ok(Vec::new())
+    }
+
+    async fn folder_chats(&self, _folder_id: i32, _limit: i32) -> Result<Vec<ChatInfo>, TgError> {
+      Ok(Vec::new())
     }
   }
 
@@ -2092,14 +4858,6 @@ mod tests {
     assert!(resolve_download_overwrite(Some(true)));
   }
 
-  #[test]
-  fn is_newer_version_uses_strict_semver_logic() {
-    assert!(is_newer_version("v1.0.7", "1.0.6"));
-    assert!(!is_newer_version("1.0.6", "1.0.6"));
-    assert!(!is_newer_version("latest", "1.0.6"));
-    assert!(!is_newer_version("release-candidate", "1.0.6"));
-  }
-
   #[test]
   fn is_strict_https_url_accepts_only_https() {
     assert!(is_strict_https_url("https://example.com/release"));
@@ -2178,17 +4936,94 @@ mod tests {
     sync::set_sync(db.pool(), "storage_chat_id", "-9001").await?;
     seed_file(&db, "f1", "d1", "report.txt", 0, -1001, 101).await?;
 
-    let existing_dir = paths.cache_dir.join("downloads").join("Документы");
+    let existing_dir = paths.layout().downloads_dir().join("Документы");
     std::fs::create_dir_all(&existing_dir)?;
     let existing_path = existing_dir.join("report.txt");
     std::fs::write(&existing_path, b"cached")?;
+    crate::app::local_copies::record(db.pool(), &paths, "f1", &existing_path).await?;
 
-    let out = file_download_impl(&state, "f1", None).await.map_err(anyhow::Error::msg)?;
+    let out = file_download_impl(&state, "f1", None, None).await.map_err(anyhow::Error::msg)?;
     assert_eq!(out, existing_path.to_string_lossy());
     assert_eq!(tg.download_attempts().len(), 0);
     Ok(())
   }
 
+  #[tokio::test]
+  async fn migration_keeps_old_channel_when_files_are_not_verified() -> anyhow::Result<()> {
+    let tg = MockTelegram::new(-9002, true);
+    let (_tmp, _state, db, paths) = setup_state(Arc::new(tg.clone())).await?;
+    seed_file(&db, "f1", "d1", "left.txt", 0, -1001, 101).await?;
+    seed_file(&db, "f2", "d2", "copied.txt", 0, -9002, 5).await?;
+
+    let mut report = reseed::ChannelMigrationReport { old_chat_id: Some(-1001), new_chat_id: -9002, ..Default::default() };
+    reseed::verify_migrated(db.pool(), &tg, &mut report).await?;
+    let mut ids: Vec<&str> = report.not_migrated.iter().map(|f| f.file_id.as_str()).collect();
+    ids.sort();
+    assert_eq!(ids, vec!["f1", "f2"]);
+    assert_eq!(report.files_checked, 1);
+    assert!(!report.safe_to_delete_old());
+
+    // Старый канал не удаляется, а запоминается для ручной очистки.
+    let report = finish_storage_migration(db.pool(), &tg, &paths, report).await?;
+    assert!(!report.old_deleted);
+    let old = old_storage_channel(db.pool()).await?.expect("old channel");
+    assert_eq!((old.chat_id, old.files_left), (-1001, 1));
+
+    forget_old_storage_channel(db.pool(), -1001).await?;
+    assert!(old_storage_channel(db.pool()).await?.is_none());
+    let reason: Option<String> = sqlx::query("SELECT broken_reason FROM files WHERE id = 'f1'")
+      .fetch_one(db.pool())
+      .await?
+      .get("broken_reason");
+    assert_eq!(reason.as_deref(), Some("message_deleted"));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn reseed_copies_only_storage_files_and_detects_deleted_messages() -> anyhow::Result<()> {
+    let tg = MockTelegram::new(-9002, true)
+      .with_payload(-1001, 101, b"main")
+      .with_payload(-5000, 7, b"source")
+      .with_payload(-7000, 8, b"extra");
+    let (_tmp, _state, db, paths) = setup_state(Arc::new(tg.clone())).await?;
+    seed_file(&db, "main", "d1", "main.txt", 4, -1001, 101).await?;
+    seed_file(&db, "gone", "d2", "gone.txt", 4, -1001, 102).await?;
+    seed_file(&db, "source", "d3", "source.txt", 6, -5000, 7).await?;
+    seed_file(&db, "extra", "d4", "extra.txt", 5, -7000, 8).await?;
+    sqlx::query("INSERT INTO source_channels(chat_id, title, target_dir_id, created_at) VALUES(-5000, 'src', 'd3', 0)")
+      .execute(db.pool())
+      .await?;
+    crate::app::storage_channels::add_channel(db.pool(), -7000, "extra").await?;
+
+    let copied = reseed_storage_channel(db.pool(), &tg, None, -9002).await?;
+    assert_eq!(copied, 1);
+    let chat_of = |id: &'static str| {
+      let pool = db.pool().clone();
+      async move {
+        sqlx::query("SELECT tg_chat_id, broken_reason FROM files WHERE id = ?")
+          .bind(id)
+          .fetch_one(&pool)
+          .await
+          .map(|r| (r.get::<i64, _>("tg_chat_id"), r.try_get::<String, _>("broken_reason").ok()))
+      }
+    };
+    assert_eq!(chat_of("main").await?, (-9002, None));
+    assert_eq!(chat_of("source").await?, (-5000, None));
+    assert_eq!(chat_of("extra").await?, (-7000, None));
+    assert_eq!(chat_of("gone").await?, (-1001, Some("message_deleted".to_string())));
+    let failures: i64 = sqlx::query("SELECT COUNT(1) AS n FROM migration_failures")
+      .fetch_one(db.pool())
+      .await?
+      .get("n");
+    assert_eq!(failures, 0);
+
+    // Удаленное сообщение не держит старый канал: переносить из него нечего.
+    let mut report = reseed::ChannelMigrationReport { old_chat_id: Some(-1001), new_chat_id: -9002, ..Default::default() };
+    reseed::verify_migrated(db.pool(), &tg, &paths, &mut report).await?;
+    assert!(report.not_migrated.iter().all(|f| f.file_id != "gone"));
+    Ok(())
+  }
+
   #[tokio::test]
   async fn resolve_file_open_path_prefers_local_copy() -> anyhow::Result<()> {
     let tg = MockTelegram::new(-9001, true);
@@ -2196,10 +5031,11 @@ mod tests {
     sync::set_sync(db.pool(), "storage_chat_id", "-9001").await?;
     seed_file(&db, "f2", "d2", "book.pdf", 0, -1002, 202).await?;
 
-    let existing_dir = paths.cache_dir.join("downloads").join("Документы");
+    let existing_dir = paths.layout().downloads_dir().join("Документы");
     std::fs::create_dir_all(&existing_dir)?;
     let existing_path = existing_dir.join("book.pdf");
     std::fs::write(&existing_path, b"local")?;
+    crate::app::local_copies::record(db.pool(), &paths, "f2", &existing_path).await?;
 
     let path = resolve_file_open_path(&state, "f2").await.map_err(anyhow::Error::msg)?;
     assert_eq!(path, existing_path);
@@ -2220,6 +5056,83 @@ mod tests {
     Ok(())
   }
 
+  #[tokio::test]
+  async fn stream_prepare_streams_remote_file_and_keeps_it_when_complete() -> anyhow::Result<()> {
+    let tmp_cache = tempdir()?;
+    let tg = MockTelegram::new(-9001, true)
+      .with_payload(-1008, 808, b"0123456789")
+      .with_stream_dir(tmp_cache.path().to_path_buf());
+    let (_tmp, _state, db, paths) = setup_state(Arc::new(tg.clone())).await?;
+    sync::set_sync(db.pool(), "storage_chat_id", "-9001").await?;
+    seed_file(&db, "f8", "d8", "clip.mp4", 0, -1008, 808).await?;
+    seed_file(&db, "f9", "d9", "notes.txt", 0, -1009, 909).await?;
+    let tg_service: Arc<dyn TelegramService> = Arc::new(tg.clone());
+
+    assert!(streaming::prepare(db.pool(), tg_service.clone(), &paths, "f9").await.is_err());
+
+    let source = streaming::prepare(db.pool(), tg_service.clone(), &paths, "f8").await?;
+    assert!(!source.local);
+    assert_eq!(source.total, 10);
+    assert_eq!(std::fs::read(&source.path)?, b"0123456789");
+    assert!(tg.download_attempts().is_empty());
+
+    // Докачанный поток становится обычной копией в каталоге загрузок.
+    assert!(streaming::keep_completed(db.pool(), tg_service.as_ref(), &paths, "f8", &source).await?);
+    let again = streaming::prepare(db.pool(), tg_service.clone(), &paths, "f8").await?;
+    assert!(again.local);
+    assert!(again.control.is_none());
+    assert_eq!(std::fs::read(&again.path)?, b"0123456789");
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn executable_open_requires_confirmation_token() -> anyhow::Result<()> {
+    let tg = MockTelegram::new(-9001, true);
+    let (_tmp, state, db, _paths) = setup_state(Arc::new(tg)).await?;
+    seed_file(&db, "f6", "d6", "setup.exe", 0, -1006, 606).await?;
+    seed_file(&db, "f7", "d7", "notes.txt", 0, -1007, 707).await?;
+
+    assert_eq!(check_open_guard(&state, "f7", None).await.map_err(anyhow::Error::msg)?, None);
+
+    let err = check_open_guard(&state, "f6", None).await.expect_err("must ask");
+    let token = err
+      .strip_prefix("NEEDS_CONFIRMATION:")
+      .and_then(|rest| rest.split(':').next())
+      .expect("token")
+      .to_string();
+    assert!(check_open_guard(&state, "f7", Some(&token)).await.is_ok());
+    assert!(check_open_guard(&state, "f6", Some(&token)).await.is_err());
+
+    let err = check_open_guard(&state, "f6", None).await.expect_err("must ask");
+    let token = err.split(':').nth(1).expect("token").to_string();
+    let confirmed = check_open_guard(&state, "f6", Some(&token)).await.map_err(anyhow::Error::msg)?;
+    assert_eq!(confirmed.as_deref(), Some("setup.exe"));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn prepare_drag_downloads_only_missing_files() -> anyhow::Result<()> {
+    let tg = MockTelegram::new(-9001, true).with_payload(-1005, 505, b"remote");
+    let (_tmp, state, db, paths) = setup_state(Arc::new(tg.clone())).await?;
+    sync::set_sync(db.pool(), "storage_chat_id", "-9001").await?;
+    seed_file(&db, "f4", "d4", "local.txt", 0, -1004, 404).await?;
+    seed_file(&db, "f5", "d5", "remote.txt", 0, -1005, 505).await?;
+
+    let existing_dir = paths.layout().downloads_dir().join("Документы");
+    std::fs::create_dir_all(&existing_dir)?;
+    let existing_path = existing_dir.join("local.txt");
+    std::fs::write(&existing_path, b"local")?;
+    crate::app::local_copies::record(db.pool(), &paths, "f4", &existing_path).await?;
+
+    let ids = vec!["f5".to_string(), "f4".to_string()];
+    let out = prepare_drag_paths(&state, &ids, |_| None).await.map_err(anyhow::Error::msg)?;
+    assert_eq!(out.len(), 2);
+    assert_eq!(std::fs::read(&out[0])?, b"remote");
+    assert_eq!(out[1], existing_path);
+    assert_eq!(tg.download_attempts(), vec![(-1005, 505)]);
+    Ok(())
+  }
+
   #[tokio::test]
   async fn resolve_file_open_folder_path_errors_when_not_downloaded() -> anyhow::Result<()> {
     let tg = MockTelegram::new(-9001, true);
@@ -2237,13 +5150,33 @@ mod tests {
     let (_tmp, state, db, paths) = setup_state(Arc::new(tg)).await?;
     seed_file(&db, "f5", "d5", "photo.jpg", 0, -1005, 505).await?;
 
-    let existing_dir = paths.cache_dir.join("downloads").join("Документы");
+    let existing_dir = paths.layout().downloads_dir().join("Документы");
     std::fs::create_dir_all(&existing_dir)?;
     let existing_path = existing_dir.join("photo.jpg");
     std::fs::write(&existing_path, b"local photo")?;
+    crate::app::local_copies::record(db.pool(), &paths, "f5", &existing_path).await?;
 
     let path = resolve_file_open_folder_path(&state, "f5").await.map_err(anyhow::Error::msg)?;
     assert_eq!(path, existing_path);
     Ok(())
   }
+
+  #[tokio::test]
+  async fn concurrent_transcriptions_of_one_file_recognize_once() -> anyhow::Result<()> {
+    let mock = Arc::new(MockTelegram::new(-100, true));
+    let (_tmp, _state, db, paths) = setup_state(mock.clone()).await?;
+    seed_file(&db, "f1", "d1", "voice.ogg", 10, -100, 1).await?;
+    sqlx::query("UPDATE files SET media_kind = 'voice_note' WHERE id = 'f1'").execute(db.pool()).await?;
+
+    let tg: &dyn TelegramService = mock.as_ref();
+    let (first, second) = tokio::join!(
+      transcripts::transcribe_file(db.pool(), tg, &paths, -100, "f1", transcripts::TranscriptSource::Telegram),
+      transcripts::transcribe_file(db.pool(), tg, &paths, -100, "f1", transcripts::TranscriptSource::Telegram)
+    );
+    assert_eq!(first?.text, "Купить молоко");
+    assert_eq!(second?.text, "Купить молоко");
+    assert_eq!(mock.inner.lock().expect("mock lock").speech_calls, 1);
+    assert_eq!(transcripts::search_transcripts(db.pool(), "молоко", 10).await?.len(), 1);
+    Ok(())
+  }
 }
@@ -3,8 +3,25 @@ use std::path::PathBuf;
 use ::sqlx::migrate::Migrator;
 use sqlx_sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool};
 
+use crate::sqlx::{self, Row};
+
 static MIGRATOR: Migrator = sqlx_macros::migrate!("./migrations");
 
+/// Частые запросы, план которых можно проверить через `explain`. Значения
+/// подставлены константами: плану настоящие данные не нужны.
+const HOT_QUERIES: &[(&str, &str)] = &[
+  ("file_by_message", "SELECT id FROM files WHERE tg_chat_id = -1 AND tg_msg_id = 1"),
+  ("files_in_dir", "SELECT id, name FROM files WHERE dir_id = '' ORDER BY name"),
+  ("file_in_dir_by_name", "SELECT id FROM files WHERE dir_id = '' AND name = ''"),
+  ("dirs_by_parent", "SELECT id, name FROM directories WHERE parent_id = '' ORDER BY name"),
+  ("part_by_message", "SELECT file_id FROM file_parts WHERE tg_chat_id = -1 AND tg_msg_id = 1")
+];
+
+/// Последняя версия схемы, известная этой сборке.
+pub fn latest_schema_version() -> Option<i64> {
+  MIGRATOR.iter().map(|m| m.version).max()
+}
+
 #[derive(Clone)]
 pub struct Db {
   pool: SqlitePool
@@ -30,4 +47,186 @@ impl Db {
     MIGRATOR.run(&self.pool).await?;
     Ok(())
   }
+
+  /// Версия схемы, число строк в таблицах и список индексов — для диагностики
+  /// без выгрузки самой базы.
+  pub async fn schema_info(&self) -> anyhow::Result<SchemaInfo> {
+    let pool = &self.pool;
+    let applied = sqlx::query("SELECT MAX(version) AS version FROM _sqlx_migrations WHERE success = 1")
+      .fetch_one(pool)
+      .await?
+      .try_get::<i64,_>("version")
+      .ok();
+    let expected = latest_schema_version();
+
+    let table_rows = sqlx::query(
+      "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name"
+    )
+      .fetch_all(pool)
+      .await?;
+    let mut tables = Vec::with_capacity(table_rows.len());
+    for row in table_rows {
+      let name: String = row.get("name");
+      let sql = format!("SELECT COUNT(1) AS cnt FROM \"{}\"", name.replace('"', "\"\""));
+      let rows: i64 = sqlx::query(&sql).fetch_one(pool).await?.get("cnt");
+      tables.push(TableInfo { name, rows });
+    }
+
+    let indexes = sqlx::query(
+      "SELECT name, tbl_name, sql FROM sqlite_master WHERE type = 'index' AND name NOT LIKE 'sqlite_%' ORDER BY tbl_name, name"
+    )
+      .fetch_all(pool)
+      .await?
+      .into_iter()
+      .map(|row| {
+        let sql = row.try_get::<String,_>("sql").ok();
+        IndexInfo {
+          name: row.get("name"),
+          table: row.get("tbl_name"),
+          unique: sql.as_deref().map(|s| s.to_uppercase().starts_with("CREATE UNIQUE")).unwrap_or(false)
+        }
+      })
+      .collect();
+
+    Ok(SchemaInfo {
+      version: applied,
+      expected_version: expected,
+      up_to_date: applied.is_some() && applied == expected,
+      tables,
+      indexes
+    })
+  }
+
+  /// План одного из частых запросов `HOT_QUERIES`: шаги `EXPLAIN QUERY PLAN`,
+  /// признак полного просмотра таблицы и сортировки во временном дереве.
+  pub async fn explain(&self, query_id: &str) -> anyhow::Result<QueryPlan> {
+    let Some((_, sql)) = HOT_QUERIES.iter().find(|(id, _)| *id == query_id) else {
+      let known: Vec<&str> = HOT_QUERIES.iter().map(|(id, _)| *id).collect();
+      return Err(anyhow::anyhow!("Неизвестный запрос {query_id}, доступны: {}", known.join(", ")));
+    };
+    let steps: Vec<String> = sqlx::query(&format!("EXPLAIN QUERY PLAN {sql}"))
+      .fetch_all(&self.pool)
+      .await?
+      .into_iter()
+      .map(|row| row.get("detail"))
+      .collect();
+    let full_scan = steps.iter().any(|s| s.starts_with("SCAN ") && !s.contains(" USING "));
+    let temp_sort = steps.iter().any(|s| s.contains("TEMP B-TREE"));
+    Ok(QueryPlan {
+      query_id: query_id.to_string(),
+      sql: sql.to_string(),
+      steps,
+      uses_index: !full_scan,
+      temp_sort
+    })
+  }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct QueryPlan {
+  pub query_id: String,
+  pub sql: String,
+  pub steps: Vec<String>,
+  pub uses_index: bool,
+  pub temp_sort: bool
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SchemaInfo {
+  pub version: Option<i64>,
+  pub expected_version: Option<i64>,
+  pub up_to_date: bool,
+  pub tables: Vec<TableInfo>,
+  pub indexes: Vec<IndexInfo>
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TableInfo {
+  pub name: String,
+  pub rows: i64
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct IndexInfo {
+  pub name: String,
+  pub table: String,
+  pub unique: bool
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::tempdir;
+
+  #[tokio::test]
+  async fn schema_info_reports_latest_migration() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+
+    let info = db.schema_info().await?;
+    assert!(info.up_to_date);
+    assert!(info.tables.iter().any(|t| t.name == "files" && t.rows == 0));
+    assert!(info.indexes.iter().any(|i| i.name == "idx_links_dir"));
+    assert!(info.indexes.iter().any(|i| i.name == "idx_files_message" && i.unique));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn message_index_migration_merges_duplicate_rows() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    // База в состоянии до уникального индекса, с дублями от старых ошибок.
+    let before = Migrator {
+      migrations: MIGRATOR.iter().filter(|m| m.version < 30).cloned().collect::<Vec<_>>().into(),
+      ..Migrator::DEFAULT
+    };
+    before.run(db.pool()).await?;
+    let pool = db.pool();
+    sqlx::query("INSERT INTO directories(id, parent_id, name, tg_msg_id, updated_at) VALUES('d1', NULL, 'Docs', NULL, 0)")
+      .execute(pool)
+      .await?;
+    sqlx::query(
+      "INSERT INTO files(id, dir_id, name, size, hash, tg_chat_id, tg_msg_id, created_at) VALUES
+         ('first', 'd1', 'a.txt', 1, 'h', -1, 5, 10), ('dup', 'd1', 'a.txt', 1, 'h', -1, 5, 20),
+         ('other', 'd1', 'b.txt', 1, 'h', -1, 6, 30)"
+    )
+      .execute(pool)
+      .await?;
+    sqlx::query("INSERT INTO local_copies(file_id, path, size, mtime, recorded_at) VALUES('dup', 'docs/a.txt', 1, 0, 0)")
+      .execute(pool)
+      .await?;
+    sqlx::query("INSERT INTO links(id, dir_id, target_kind, target_id, created_at) VALUES('l1', 'd1', 'file', 'dup', 0)")
+      .execute(pool)
+      .await?;
+
+    db.migrate().await?;
+    let ids: Vec<String> = sqlx::query("SELECT id FROM files ORDER BY id")
+      .fetch_all(pool)
+      .await?
+      .into_iter()
+      .map(|r| r.get("id"))
+      .collect();
+    assert_eq!(ids, vec!["first", "other"]);
+    let copy: String = sqlx::query("SELECT file_id FROM local_copies").fetch_one(pool).await?.get("file_id");
+    assert_eq!(copy, "first");
+    let link: String = sqlx::query("SELECT target_id FROM links WHERE id = 'l1'").fetch_one(pool).await?.get("target_id");
+    assert_eq!(link, "first");
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn hot_queries_use_indexes() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+
+    for (query_id, _) in HOT_QUERIES {
+      let plan = db.explain(query_id).await?;
+      assert!(plan.uses_index, "{query_id}: {:?}", plan.steps);
+      assert!(!plan.temp_sort, "{query_id}: {:?}", plan.steps);
+    }
+    assert!(db.explain("missing").await.is_err());
+    Ok(())
+  }
 }
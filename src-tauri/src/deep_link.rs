@@ -0,0 +1,186 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use crate::sqlx::{self, Row};
+use sqlx_sqlite::SqlitePool;
+use tauri::{AppHandle, Emitter, Manager, WebviewUrl, WebviewWindowBuilder};
+
+use crate::app::dirs::dir_exists;
+use crate::state::AppState;
+
+/// Ссылки вида `cloudtg://file/<id>` и `cloudtg://dir/<id>` открывают
+/// приложение на нужной папке. Их дают лента действий и страница состояния.
+pub const SCHEME: &str = "cloudtg";
+const MAIN_WINDOW: &str = "main";
+const MAX_ID_LEN: usize = 64;
+
+/// Последний переход, который UI еще не забрал: ссылка могла прийти при
+/// запуске, до того как окно подписалось на события.
+static PENDING: Lazy<Mutex<Option<Navigation>>> = Lazy::new(|| Mutex::new(None));
+/// Окно подписалось на `deep_link` и забрало отложенный переход: дальше
+/// переходы только отправляются событием.
+static LISTENING: AtomicBool = AtomicBool::new(false);
+/// Ссылка, пришедшая до открытия базы: разбирается после инициализации.
+static QUEUED: Lazy<Mutex<Option<DeepLink>>> = Lazy::new(|| Mutex::new(None));
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeepLink {
+  File(String),
+  Dir(String)
+}
+
+/// Куда перейти в главном окне.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Navigation {
+  pub dir_id: String,
+  /// Файл, который нужно выделить в папке.
+  pub file_id: Option<String>
+}
+
+impl DeepLink {
+  pub fn parse(url: &str) -> Option<DeepLink> {
+    let rest = url.trim().strip_prefix(SCHEME)?.strip_prefix("://")?;
+    let rest = rest.split(['?', '#']).next().unwrap_or("");
+    let (kind, id) = rest.trim_end_matches('/').split_once('/')?;
+    if !valid_id(id) {
+      return None;
+    }
+    match kind {
+      "file" => Some(DeepLink::File(id.to_string())),
+      "dir" | "folder" => Some(DeepLink::Dir(id.to_string())),
+      _ => None
+    }
+  }
+
+  pub fn url(&self) -> String {
+    match self {
+      DeepLink::File(id) => format!("{SCHEME}://file/{id}"),
+      DeepLink::Dir(id) => format!("{SCHEME}://dir/{id}")
+    }
+  }
+}
+
+fn valid_id(id: &str) -> bool {
+  !id.is_empty() && id.len() <= MAX_ID_LEN && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+async fn resolve(pool: &SqlitePool, link: &DeepLink) -> anyhow::Result<Option<Navigation>> {
+  match link {
+    DeepLink::Dir(id) => Ok(dir_exists(pool, id).await?.then(|| Navigation { dir_id: id.clone(), file_id: None })),
+    DeepLink::File(id) => Ok(
+      sqlx::query("SELECT dir_id FROM files WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?
+        .map(|r| Navigation { dir_id: r.get("dir_id"), file_id: Some(id.clone()) })
+    )
+  }
+}
+
+/// Поднимает главное окно, например при повторном запуске приложения.
+pub fn focus_main(app: &AppHandle) {
+  if let Some(win) = app.get_webview_window(MAIN_WINDOW) {
+    let _ = win.unminimize();
+    let _ = win.show();
+    let _ = win.set_focus();
+  }
+}
+
+/// Разбирает ссылки (или аргументы запуска) и переходит по последней
+/// подходящей. Посторонние аргументы пропускаются. Если база еще не
+/// открыта, ссылка ждет `resume_queued`.
+pub fn handle_urls(app: &AppHandle, urls: impl IntoIterator<Item = String>) {
+  let Some(link) = urls.into_iter().filter_map(|u| DeepLink::parse(&u)).last() else {
+    return;
+  };
+  focus_main(app);
+  {
+    // Под той же блокировкой, что и в resume_queued: иначе ссылка может
+    // лечь в очередь сразу после того, как ее разобрали.
+    let mut queued = QUEUED.lock();
+    if app.state::<AppState>().db().is_err() {
+      *queued = Some(link);
+      return;
+    }
+  }
+  spawn_navigate(app.clone(), link);
+}
+
+/// Переходит по ссылке, отложенной до окончания инициализации.
+pub fn resume_queued(app: &AppHandle) {
+  if let Some(link) = QUEUED.lock().take() {
+    spawn_navigate(app.clone(), link);
+  }
+}
+
+fn spawn_navigate(app: AppHandle, link: DeepLink) {
+  tauri::async_runtime::spawn(async move {
+    let res = async {
+      let db = app.state::<AppState>().db()?;
+      resolve(db.pool(), &link).await
+    }
+    .await;
+    match res {
+      Ok(Some(nav)) => {
+        if !LISTENING.load(Ordering::Acquire) {
+          *PENDING.lock() = Some(nav.clone());
+        }
+        let _ = app.emit_to(MAIN_WINDOW, "deep_link", nav);
+      }
+      Ok(None) => {
+        tracing::warn!(event = "deep_link_not_found", url = link.url().as_str(), "Ссылка ведет на удаленный файл или папку");
+      }
+      Err(e) => {
+        tracing::warn!(event = "deep_link_failed", url = link.url().as_str(), error = %e, "Не удалось открыть ссылку");
+      }
+    }
+  });
+}
+
+/// Забирает переход, пришедший до подписки окна на события. UI вызывает
+/// это после подписки, поэтому следующие переходы в PENDING не попадают.
+pub fn take_pending() -> Option<Navigation> {
+  let mut pending = PENDING.lock();
+  LISTENING.store(true, Ordering::Release);
+  pending.take()
+}
+
+/// Отдельное небольшое окно с одной папкой. Повторный вызов для той же
+/// папки поднимает уже открытое окно.
+pub fn open_folder_window(app: &AppHandle, dir_id: &str, title: &str) -> anyhow::Result<()> {
+  if !valid_id(dir_id) {
+    return Err(anyhow::anyhow!("Некорректный id папки"));
+  }
+  let label = format!("folder-{dir_id}");
+  if let Some(win) = app.get_webview_window(&label) {
+    let _ = win.unminimize();
+    let _ = win.set_focus();
+    return Ok(());
+  }
+  WebviewWindowBuilder::new(app, &label, WebviewUrl::App(format!("index.html?dir={dir_id}").into()))
+    .title(format!("CloudTG — {title}"))
+    .inner_size(560.0, 640.0)
+    .build()?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_file_and_dir_links() {
+    let file = DeepLink::parse("cloudtg://file/01HZX3Q8J6ZP2V1T0M4R5K7N9A").expect("file link");
+    assert_eq!(file, DeepLink::File("01HZX3Q8J6ZP2V1T0M4R5K7N9A".into()));
+    assert_eq!(DeepLink::parse(&file.url()), Some(file));
+    assert_eq!(DeepLink::parse("cloudtg://dir/abc/?from=feed"), Some(DeepLink::Dir("abc".into())));
+    assert_eq!(DeepLink::parse("cloudtg://folder/abc"), Some(DeepLink::Dir("abc".into())));
+
+    assert_eq!(DeepLink::parse("cloudtg://file/"), None);
+    assert_eq!(DeepLink::parse("cloudtg://file/../secrets"), None);
+    assert_eq!(DeepLink::parse("cloudtg://share/abc"), None);
+    assert_eq!(DeepLink::parse("https://file/abc"), None);
+    assert_eq!(DeepLink::parse("--portable"), None);
+  }
+}
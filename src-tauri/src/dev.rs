@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::telegram::{ChatId, HistoryMessage, MessageId, SearchMessagesResult, TgError};
+
+/// Симуляция для QA синхронизации, сверки и восстановления без аккаунта
+/// Telegram. Работает только в сборке с фичей `dev` (она включает
+/// `mock_telegram`): mock-сервис отдает внедренные истории каналов и
+/// внедренные ошибки, а часы планировщиков можно перевести вперед.
+static HISTORIES: Lazy<Mutex<HashMap<ChatId, Vec<HistoryMessage>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static FAULTS: Lazy<Mutex<Vec<Fault>>> = Lazy::new(|| Mutex::new(Vec::new()));
+static CLOCK_OFFSET_SECS: AtomicI64 = AtomicI64::new(0);
+const FIRST_SYNTHETIC_ID: MessageId = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SimulatedError {
+  AuthRequired,
+  Io,
+  Timeout,
+  FloodWait,
+  NotFound
+}
+
+impl SimulatedError {
+  /// Тексты повторяют ответы TDLib, чтобы сработали те же ветки разбора ошибок.
+  fn to_error(self) -> TgError {
+    match self {
+      SimulatedError::AuthRequired => TgError::AuthRequired,
+      SimulatedError::Io => TgError::Io(std::io::Error::other("симуляция: ошибка ввода-вывода")),
+      SimulatedError::Timeout => TgError::Other("Таймаут запроса TDLib (симуляция)".into()),
+      SimulatedError::FloodWait => TgError::Other("Too Many Requests: retry after 30".into()),
+      SimulatedError::NotFound => TgError::Other("Message not found".into())
+    }
+  }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Fault {
+  /// Имя метода `TelegramService` или `*` для любого.
+  pub method: String,
+  pub error: SimulatedError,
+  pub remaining: u32
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct SyntheticMessage {
+  #[serde(default)]
+  pub date: Option<i64>,
+  #[serde(default)]
+  pub text: Option<String>,
+  #[serde(default)]
+  pub caption: Option<String>,
+  #[serde(default)]
+  pub file_name: Option<String>,
+  #[serde(default)]
+  pub file_size: Option<i64>,
+  #[serde(default)]
+  pub content_type: Option<String>
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SimulationState {
+  pub histories: HashMap<ChatId, usize>,
+  pub faults: Vec<Fault>,
+  pub clock_offset_secs: i64
+}
+
+/// Добавляет сообщения в конец истории канала; возвращает их id.
+pub fn inject_history(chat_id: ChatId, messages: Vec<SyntheticMessage>) -> Vec<MessageId> {
+  let mut histories = HISTORIES.lock();
+  let history = histories.entry(chat_id).or_default();
+  let mut next_id = history.last().map(|m| m.id + 1).unwrap_or(FIRST_SYNTHETIC_ID);
+  let now = Utc::now().timestamp();
+  let mut ids = Vec::with_capacity(messages.len());
+  for msg in messages {
+    let default_type = if msg.file_name.is_some() { "messageDocument" } else { "messageText" };
+    let content_type = msg.content_type.unwrap_or_else(|| default_type.to_string());
+    history.push(HistoryMessage {
+      id: next_id,
+      date: msg.date.unwrap_or(now),
+      text: msg.text,
+      caption: msg.caption,
+      file_size: msg.file_size,
+      file_name: msg.file_name,
+      sender_id: None,
+      media_group_id: None,
+      content_type: Some(content_type),
+      sticker_set_id: None,
+      file_unique_id: None
+    });
+    ids.push(next_id);
+    next_id += 1;
+  }
+  ids
+}
+
+/// Страница истории от новых к старым, как `getChatHistory`. `query` фильтрует
+/// по тексту и подписи, как поиск по чату.
+pub fn history_page(chat_id: ChatId, from_message_id: MessageId, limit: i32, query: Option<&str>) -> SearchMessagesResult {
+  let histories = HISTORIES.lock();
+  let matches = |m: &&HistoryMessage| match query {
+    Some(q) => m.text.as_deref().unwrap_or("").contains(q) || m.caption.as_deref().unwrap_or("").contains(q),
+    None => true
+  };
+  let all: Vec<&HistoryMessage> = histories.get(&chat_id).map(|h| h.iter().rev().filter(matches).collect()).unwrap_or_default();
+  let messages: Vec<HistoryMessage> = all
+    .iter()
+    .filter(|m| from_message_id == 0 || m.id < from_message_id)
+    .take(limit.max(1) as usize)
+    .map(|m| (*m).clone())
+    .collect();
+  let next_from_message_id = messages.last().map(|m| m.id).unwrap_or(0);
+  SearchMessagesResult {
+    total_count: Some(all.len() as i64),
+    next_from_message_id,
+    messages
+  }
+}
+
+pub fn message_exists(chat_id: ChatId, message_id: MessageId) -> Option<bool> {
+  let histories = HISTORIES.lock();
+  histories.get(&chat_id).map(|h| h.iter().any(|m| m.id == message_id))
+}
+
+pub fn delete_messages(chat_id: ChatId, message_ids: &[MessageId]) {
+  if let Some(history) = HISTORIES.lock().get_mut(&chat_id) {
+    history.retain(|m| !message_ids.contains(&m.id));
+  }
+}
+
+/// Следующие `count` вызовов метода завершатся ошибкой.
+pub fn add_fault(method: &str, error: SimulatedError, count: u32) {
+  FAULTS.lock().push(Fault { method: method.trim().to_string(), error, remaining: count.max(1) });
+}
+
+pub fn take_fault(method: &str) -> Option<TgError> {
+  let mut faults = FAULTS.lock();
+  let idx = faults.iter().position(|f| f.method == method || f.method == "*")?;
+  let error = faults[idx].error;
+  faults[idx].remaining -= 1;
+  if faults[idx].remaining == 0 {
+    faults.remove(idx);
+  }
+  tracing::info!(event = "dev_fault_injected", method = method, "Симулированная ошибка Telegram");
+  Some(error.to_error())
+}
+
+/// Переводит часы планировщиков вперед (окно обслуживания и т.п.).
+pub fn fast_forward(seconds: i64) -> i64 {
+  CLOCK_OFFSET_SECS.fetch_add(seconds, Ordering::Relaxed) + seconds
+}
+
+pub fn clock_offset_secs() -> i64 {
+  CLOCK_OFFSET_SECS.load(Ordering::Relaxed)
+}
+
+pub fn state() -> SimulationState {
+  SimulationState {
+    histories: HISTORIES.lock().iter().map(|(chat, h)| (*chat, h.len())).collect(),
+    faults: FAULTS.lock().clone(),
+    clock_offset_secs: clock_offset_secs()
+  }
+}
+
+pub fn reset() {
+  HISTORIES.lock().clear();
+  FAULTS.lock().clear();
+  CLOCK_OFFSET_SECS.store(0, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn doc(name: &str) -> SyntheticMessage {
+    SyntheticMessage {
+      date: None,
+      text: None,
+      caption: Some(format!("#ocltg {name}")),
+      file_name: Some(name.to_string()),
+      file_size: Some(1),
+      content_type: None
+    }
+  }
+
+  #[test]
+  fn injected_history_pages_and_faults_run_out() {
+    let chat: ChatId = -42_4242;
+    let ids = inject_history(chat, vec![doc("a.txt"), doc("b.txt"), doc("c.txt")]);
+    assert_eq!(ids.len(), 3);
+
+    let first = history_page(chat, 0, 2, None);
+    assert_eq!(first.messages.iter().map(|m| m.id).collect::<Vec<_>>(), vec![ids[2], ids[1]]);
+    let second = history_page(chat, first.next_from_message_id, 2, None);
+    assert_eq!(second.messages.len(), 1);
+    assert_eq!(history_page(chat, 0, 10, Some("b.txt")).messages.len(), 1);
+
+    add_fault("dev_test_method", SimulatedError::FloodWait, 2);
+    assert!(take_fault("dev_test_method").is_some());
+    assert!(take_fault("dev_test_method").is_some());
+    assert!(take_fault("dev_test_method").is_none());
+  }
+}
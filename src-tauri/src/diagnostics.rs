@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use sqlx_sqlite::SqlitePool;
+use tauri::Manager;
+
+use crate::sqlx::{self, Row};
+use crate::telegram::TgError;
+
+/// Анонимная диагностика, только с явного согласия пользователя. Копятся
+/// лишь счетчики операций и коды ошибок по дням — никаких имен файлов,
+/// текстов сообщений или идентификаторов чатов. Ключи — фиксированные коды
+/// из кода приложения, а не данные пользователя.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(300);
+const RETENTION_DAYS: i64 = 90;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static PENDING: Lazy<Mutex<HashMap<(Kind, String), i64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+  Operation,
+  Error
+}
+
+impl Kind {
+  fn as_str(self) -> &'static str {
+    match self {
+      Kind::Operation => "operation",
+      Kind::Error => "error"
+    }
+  }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Counter {
+  pub day: String,
+  pub kind: String,
+  pub code: String,
+  pub count: i64
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DiagnosticsReport {
+  pub app_version: String,
+  pub os: String,
+  pub arch: String,
+  pub generated_at: i64,
+  pub counters: Vec<Counter>
+}
+
+pub fn set_enabled(enabled: bool) {
+  ENABLED.store(enabled, Ordering::Relaxed);
+  if !enabled {
+    PENDING.lock().clear();
+  }
+}
+
+pub fn is_enabled() -> bool {
+  ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn record_operation(code: &str) {
+  record(Kind::Operation, code);
+}
+
+pub fn record_error(code: &str) {
+  record(Kind::Error, code);
+}
+
+/// Код ошибки команды по ее типу; текст ошибки в диагностику не попадает.
+pub fn record_command_error(err: &anyhow::Error) {
+  if is_enabled() {
+    record_error(error_code(err));
+  }
+}
+
+fn record(kind: Kind, code: &str) {
+  if !is_enabled() {
+    return;
+  }
+  *PENDING.lock().entry((kind, code.to_string())).or_insert(0) += 1;
+}
+
+pub fn error_code(err: &anyhow::Error) -> &'static str {
+  if let Some(tg) = err.downcast_ref::<TgError>() {
+    return match tg {
+      TgError::NotImplemented => "tg_not_implemented",
+      TgError::AuthRequired => "tg_auth_required",
+      TgError::SendPending => "tg_send_pending",
+      TgError::Io(e) => io_code(e),
+      TgError::Other(_) => "tg_other"
+    };
+  }
+  if let Some(e) = err.downcast_ref::<std::io::Error>() {
+    return io_code(e);
+  }
+  if err.downcast_ref::<::sqlx::Error>().is_some() {
+    return "db";
+  }
+  "other"
+}
+
+fn io_code(err: &std::io::Error) -> &'static str {
+  match err.kind() {
+    std::io::ErrorKind::NotFound => "io_not_found",
+    std::io::ErrorKind::PermissionDenied => "io_permission_denied",
+    std::io::ErrorKind::TimedOut => "io_timed_out",
+    _ => "io_other"
+  }
+}
+
+/// Переносит накопленные счетчики в БД.
+pub async fn flush(pool: &SqlitePool) -> anyhow::Result<()> {
+  let pending: Vec<((Kind, String), i64)> = PENDING.lock().drain().collect();
+  if pending.is_empty() {
+    return Ok(());
+  }
+  let day = Utc::now().format("%Y-%m-%d").to_string();
+  for ((kind, code), count) in pending {
+    sqlx::query(
+      "INSERT INTO diagnostics_counters(day, kind, code, count) VALUES(?, ?, ?, ?)
+       ON CONFLICT(day, kind, code) DO UPDATE SET count = count + excluded.count"
+    )
+      .bind(&day)
+      .bind(kind.as_str())
+      .bind(&code)
+      .bind(count)
+      .execute(pool)
+      .await?;
+  }
+  let cutoff = (Utc::now() - chrono::Duration::days(RETENTION_DAYS)).format("%Y-%m-%d").to_string();
+  sqlx::query("DELETE FROM diagnostics_counters WHERE day < ?")
+    .bind(cutoff)
+    .execute(pool)
+    .await?;
+  Ok(())
+}
+
+pub async fn report(pool: &SqlitePool) -> anyhow::Result<DiagnosticsReport> {
+  flush(pool).await?;
+  let counters = sqlx::query("SELECT day, kind, code, count FROM diagnostics_counters ORDER BY day, kind, code")
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| Counter {
+      day: row.get("day"),
+      kind: row.get("kind"),
+      code: row.get("code"),
+      count: row.get("count")
+    })
+    .collect();
+  Ok(DiagnosticsReport {
+    app_version: env!("CARGO_PKG_VERSION").to_string(),
+    os: std::env::consts::OS.to_string(),
+    arch: std::env::consts::ARCH.to_string(),
+    generated_at: Utc::now().timestamp(),
+    counters
+  })
+}
+
+/// При отказе от диагностики собранные данные удаляются.
+pub async fn clear(pool: &SqlitePool) -> anyhow::Result<()> {
+  PENDING.lock().clear();
+  sqlx::query("DELETE FROM diagnostics_counters").execute(pool).await?;
+  Ok(())
+}
+
+/// Адрес для отправки отчета; без него доступен только экспорт.
+pub fn submit_url() -> Option<String> {
+  std::env::var("CLOUDTG_DIAGNOSTICS_URL")
+    .ok()
+    .or_else(|| option_env!("CLOUDTG_DIAGNOSTICS_URL").map(str::to_string))
+    .map(|v| v.trim().to_string())
+    .filter(|v| v.starts_with("https://"))
+}
+
+/// База берется на каждом сбросе: после смены аккаунта диагностика пишется в его базу.
+pub fn spawn_flusher(app: tauri::AppHandle) {
+  tauri::async_runtime::spawn(async move {
+    loop {
+      tokio::time::sleep(FLUSH_INTERVAL).await;
+      if !is_enabled() {
+        continue;
+      }
+      let Ok(db) = app.state::<crate::state::AppState>().db() else {
+        continue;
+      };
+      if let Err(e) = flush(db.pool()).await {
+        tracing::debug!(event = "diagnostics_flush_failed", error = %e, "Не удалось сохранить диагностику");
+      }
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::Db;
+  use tempfile::tempdir;
+
+  #[tokio::test]
+  async fn counters_are_kept_only_with_consent() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let db = Db::connect(tmp.path().join("test.sqlite")).await?;
+    db.migrate().await?;
+
+    record_operation("diag_test_op");
+    set_enabled(true);
+    record_operation("diag_test_op");
+    record_operation("diag_test_op");
+    record_command_error(&anyhow::Error::new(TgError::AuthRequired));
+    let collected = report(db.pool()).await?;
+    let count = |code: &str| collected.counters.iter().filter(|c| c.code == code).map(|c| c.count).sum::<i64>();
+    assert_eq!(count("diag_test_op"), 2);
+    assert!(count("tg_auth_required") >= 1);
+
+    set_enabled(false);
+    clear(db.pool()).await?;
+    record_operation("diag_test_op");
+    assert!(report(db.pool()).await?.counters.is_empty());
+    Ok(())
+  }
+}
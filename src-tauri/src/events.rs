@@ -0,0 +1,207 @@
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tauri::{AppHandle, Emitter};
+
+use crate::telegram::{DownloadProgress, ProgressSink};
+
+/// Шина изменений дерева. Точечные события `dir_changed` / `file_changed`
+/// копятся в течение `DEBOUNCE` и уходят пачкой; если их набралось больше
+/// `COLLAPSE_THRESHOLD` или в пачке был массовый `tree_updated`, UI получает
+/// один `tree_updated` и перечитывает дерево целиком.
+const DEBOUNCE: Duration = Duration::from_millis(150);
+const COLLAPSE_THRESHOLD: usize = 64;
+
+static PENDING: Lazy<Mutex<Pending>> = Lazy::new(|| Mutex::new(Pending::default()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+  Created,
+  Updated,
+  Moved,
+  Deleted
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct DirChanged {
+  pub id: String,
+  pub change: Change,
+  pub parent_id: Option<String>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct FileChanged {
+  pub id: String,
+  pub change: Change,
+  pub dir_id: Option<String>,
+  /// Прежняя папка перемещенного файла.
+  pub from_dir_id: Option<String>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TreeEvent {
+  Dir(DirChanged),
+  File(FileChanged)
+}
+
+#[derive(Default)]
+struct Pending {
+  events: Vec<TreeEvent>,
+  bulk: bool,
+  scheduled: bool
+}
+
+pub fn dir_changed(app: &AppHandle, id: &str, change: Change, parent_id: Option<&str>) {
+  push(app, Some(TreeEvent::Dir(DirChanged {
+    id: id.to_string(),
+    change,
+    parent_id: parent_id.map(str::to_string)
+  })));
+}
+
+pub fn file_changed(app: &AppHandle, id: &str, change: Change, dir_id: Option<&str>) {
+  push(app, Some(TreeEvent::File(FileChanged {
+    id: id.to_string(),
+    change,
+    dir_id: dir_id.map(str::to_string),
+    from_dir_id: None
+  })));
+}
+
+/// Файл перенесен: UI перечитывает и прежнюю папку, и новую.
+pub fn file_moved(app: &AppHandle, id: &str, from_dir_id: Option<&str>, dir_id: &str) {
+  push(app, Some(TreeEvent::File(FileChanged {
+    id: id.to_string(),
+    change: Change::Moved,
+    dir_id: Some(dir_id.to_string()),
+    from_dir_id: from_dir_id.map(str::to_string)
+  })));
+}
+
+/// Для массовых изменений (синхронизация, импорт, восстановление).
+pub fn tree_updated(app: &AppHandle) {
+  push(app, None);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct FileDownloadProgress {
+  pub file_id: String,
+  pub downloaded: u64,
+  pub total: Option<u64>
+}
+
+/// Ход скачивания файла хранилища. Идет мимо шины дерева и без дебаунса:
+/// частоту уже ограничивает backend Telegram.
+pub fn file_download_progress(app: &AppHandle, file_id: &str, progress: DownloadProgress) {
+  let _ = app.emit("file_download_progress", FileDownloadProgress {
+    file_id: file_id.to_string(),
+    downloaded: progress.downloaded,
+    total: progress.total
+  });
+}
+
+/// Приемник для `files::download_file`, пересылающий ход в UI по `file_id`.
+pub fn download_progress_sink(app: &AppHandle, file_id: &str) -> ProgressSink {
+  let app = app.clone();
+  let file_id = file_id.to_string();
+  std::sync::Arc::new(move |progress| file_download_progress(&app, &file_id, progress))
+}
+
+/// Ход пакетной загрузки (например, перетащенных в окно файлов): одно
+/// событие на каждый файл, последнее — с `finished`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct UploadProgress {
+  pub batch_id: String,
+  pub dir_id: String,
+  /// Сколько файлов пакета уже обработано.
+  pub done: usize,
+  pub total: usize,
+  pub name: Option<String>,
+  pub file_id: Option<String>,
+  pub error: Option<String>,
+  pub finished: bool
+}
+
+pub fn upload_progress(app: &AppHandle, progress: &UploadProgress) {
+  let _ = app.emit("upload_progress", progress);
+}
+
+fn push(app: &AppHandle, event: Option<TreeEvent>) {
+  let mut pending = PENDING.lock();
+  match event {
+    Some(event) => pending.events.push(event),
+    None => pending.bulk = true
+  }
+  if pending.scheduled {
+    return;
+  }
+  pending.scheduled = true;
+  let app = app.clone();
+  tauri::async_runtime::spawn(async move {
+    tokio::time::sleep(DEBOUNCE).await;
+    flush(&app);
+  });
+}
+
+fn flush(app: &AppHandle) {
+  let (events, bulk) = {
+    let mut pending = PENDING.lock();
+    pending.scheduled = false;
+    (std::mem::take(&mut pending.events), std::mem::take(&mut pending.bulk))
+  };
+  let events = coalesce(events);
+  if bulk || events.len() > COLLAPSE_THRESHOLD {
+    let _ = app.emit("tree_updated", ());
+    return;
+  }
+  for event in events {
+    let _ = match event {
+      TreeEvent::Dir(payload) => app.emit("dir_changed", payload),
+      TreeEvent::File(payload) => app.emit("file_changed", payload)
+    };
+  }
+}
+
+/// Оставляет по одному событию на объект — последнее, но на прежнем месте в очереди.
+fn coalesce(events: Vec<TreeEvent>) -> Vec<TreeEvent> {
+  let mut out: Vec<TreeEvent> = Vec::with_capacity(events.len());
+  for event in events {
+    let existing = out.iter_mut().find(|e| same_target(e, &event));
+    match existing {
+      Some(slot) => *slot = event,
+      None => out.push(event)
+    }
+  }
+  out
+}
+
+fn same_target(a: &TreeEvent, b: &TreeEvent) -> bool {
+  match (a, b) {
+    (TreeEvent::Dir(a), TreeEvent::Dir(b)) => a.id == b.id,
+    (TreeEvent::File(a), TreeEvent::File(b)) => a.id == b.id,
+    _ => false
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn file(id: &str, change: Change) -> TreeEvent {
+    TreeEvent::File(FileChanged { id: id.into(), change, dir_id: None, from_dir_id: None })
+  }
+
+  #[test]
+  fn coalesce_keeps_last_change_per_target() {
+    let dir = TreeEvent::Dir(DirChanged { id: "f1".into(), change: Change::Created, parent_id: None });
+    let out = coalesce(vec![
+      file("f1", Change::Created),
+      dir.clone(),
+      file("f2", Change::Updated),
+      file("f1", Change::Deleted)
+    ]);
+    assert_eq!(out, vec![file("f1", Change::Deleted), dir, file("f2", Change::Updated)]);
+  }
+}
@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 
+use icu_normalizer::ComposingNormalizerBorrowed;
+
 pub const TAG_PREFIX: &str = "#ocltg #v1";
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -10,6 +12,15 @@ pub struct FileMeta {
   pub hash_short: String
 }
 
+/// Часть большого файла: подпись как у файла плюс маркер `#part i/N`.
+/// Номер части считается с единицы.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartMeta {
+  pub file: FileMeta,
+  pub index: u32,
+  pub total: u32
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DirMeta {
   pub dir_id: String,
@@ -17,6 +28,37 @@ pub struct DirMeta {
   pub name: String
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+  File,
+  Dir
+}
+
+impl LinkKind {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      LinkKind::File => "file",
+      LinkKind::Dir => "dir"
+    }
+  }
+
+  pub fn parse(raw: &str) -> Option<Self> {
+    match raw {
+      "file" => Some(LinkKind::File),
+      "dir" => Some(LinkKind::Dir),
+      _ => None
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkMeta {
+  pub link_id: String,
+  pub dir_id: String, // папка, в которой показывается ссылка
+  pub kind: LinkKind,
+  pub target_id: String
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum MetaError {
   #[error("not a cloudtg message")]
@@ -35,13 +77,38 @@ fn kv_map(input: &str) -> HashMap<String, String> {
 
 pub fn make_file_caption(m: &FileMeta) -> String {
   format!("{TAG_PREFIX} #file d={} f={} n={} h={}",
-    m.dir_id, m.file_id, escape_spaces(&m.name), m.hash_short
+    m.dir_id, m.file_id, escape_spaces(&caption_name(&m.name)), m.hash_short
+  )
+}
+
+pub fn make_part_caption(m: &PartMeta) -> String {
+  format!("{TAG_PREFIX} #part {}/{} d={} f={} n={} h={}",
+    m.index, m.total, m.file.dir_id, m.file.file_id, escape_spaces(&caption_name(&m.file.name)), m.file.hash_short
   )
 }
 
+/// Добавляет к подписи файла или части отметку `#enc k=<отпечаток ключа>`.
+pub fn mark_encrypted(caption: &str, key_id: &str) -> String {
+  format!("{caption} #enc k={key_id}")
+}
+
+/// Отпечаток ключа, которым зашифровано содержимое, если подпись его несет.
+pub fn encryption_key_id(caption: &str) -> Option<String> {
+  if !caption.split_whitespace().any(|w| w == "#enc") {
+    return None;
+  }
+  kv_map(caption).remove("k").filter(|k| !k.is_empty())
+}
+
 pub fn make_dir_message(m: &DirMeta) -> String {
   format!("{TAG_PREFIX} #dir d={} p={} name={}",
-    m.dir_id, m.parent_id, escape_spaces(&m.name)
+    m.dir_id, m.parent_id, escape_spaces(&caption_name(&m.name))
+  )
+}
+
+pub fn make_link_message(m: &LinkMeta) -> String {
+  format!("{TAG_PREFIX} #link l={} d={} k={} t={}",
+    m.link_id, m.dir_id, m.kind.as_str(), m.target_id
   )
 }
 
@@ -58,6 +125,33 @@ pub fn parse_file_caption(caption: &str) -> Result<FileMeta, MetaError> {
   })
 }
 
+pub fn parse_part_caption(caption: &str) -> Result<PartMeta, MetaError> {
+  if !caption.contains("#ocltg") || !caption.contains("#v1") {
+    return Err(MetaError::NotCloudtg);
+  }
+  let mut words = caption.split_whitespace().skip_while(|w| *w != "#part");
+  if words.next().is_none() {
+    return Err(MetaError::NotCloudtg);
+  }
+  let (index, total) = words
+    .next()
+    .and_then(|w| w.split_once('/'))
+    .and_then(|(i, n)| Some((i.parse::<u32>().ok()?, n.parse::<u32>().ok()?)))
+    .filter(|(i, n)| *i >= 1 && i <= n)
+    .ok_or(MetaError::Missing("part"))?;
+  let map = kv_map(caption);
+  Ok(PartMeta {
+    file: FileMeta {
+      dir_id: map.get("d").cloned().ok_or(MetaError::Missing("d"))?,
+      file_id: map.get("f").cloned().ok_or(MetaError::Missing("f"))?,
+      name: unescape_spaces(map.get("n").cloned().ok_or(MetaError::Missing("n"))?.as_str()),
+      hash_short: map.get("h").cloned().ok_or(MetaError::Missing("h"))?
+    },
+    index,
+    total
+  })
+}
+
 pub fn parse_dir_message(text: &str) -> Result<DirMeta, MetaError> {
   if !text.contains("#ocltg") || !text.contains("#v1") || !text.contains("#dir") {
     return Err(MetaError::NotCloudtg);
@@ -70,6 +164,44 @@ pub fn parse_dir_message(text: &str) -> Result<DirMeta, MetaError> {
   })
 }
 
+pub fn parse_link_message(text: &str) -> Result<LinkMeta, MetaError> {
+  if !text.contains("#ocltg") || !text.contains("#v1") || !text.contains("#link") {
+    return Err(MetaError::NotCloudtg);
+  }
+  let map = kv_map(text);
+  let kind = map.get("k").ok_or(MetaError::Missing("k"))?;
+  Ok(LinkMeta {
+    link_id: map.get("l").cloned().ok_or(MetaError::Missing("l"))?,
+    dir_id: map.get("d").cloned().ok_or(MetaError::Missing("d"))?,
+    kind: LinkKind::parse(kind).ok_or(MetaError::Missing("k"))?,
+    target_id: map.get("t").cloned().ok_or(MetaError::Missing("t"))?
+  })
+}
+
+/// Имя в том виде, в каком оно попадает в подпись: NFC, без управляющих
+/// символов направления текста и невидимых пробелов, любые пробельные
+/// символы — обычный пробел. Иначе `kv_map` режет имя на неразрывном
+/// пробеле, а RTL-метки переворачивают соседние поля подписи в клиенте
+/// Telegram. Настоящее имя остается в базе; см. [`is_caption_form_of`].
+pub fn caption_name(name: &str) -> String {
+  ComposingNormalizerBorrowed::new_nfc()
+    .normalize(name)
+    .chars()
+    .filter(|c| !is_bidi_control(*c) && !matches!(c, '\u{200B}' | '\u{2060}' | '\u{FEFF}'))
+    .map(|c| if c.is_whitespace() || c.is_control() { ' ' } else { c })
+    .collect()
+}
+
+/// Имя из подписи получено из `name` через [`caption_name`]: тогда в базе
+/// нужно оставить `name`, а не упрощенное имя из подписи.
+pub fn is_caption_form_of(caption: &str, name: &str) -> bool {
+  caption != name && caption_name(name) == caption
+}
+
+fn is_bidi_control(c: char) -> bool {
+  matches!(c, '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
 // Replace spaces with underscores, escape underscore itself.
 fn escape_spaces(s: &str) -> String {
   s.replace('_', "__").replace(' ', "_")
@@ -98,6 +230,76 @@ mod tests {
     assert_eq!(parsed, m);
   }
 
+  #[test]
+  fn part_roundtrip_is_not_a_whole_file() {
+    let m = PartMeta {
+      file: FileMeta {
+        dir_id: "01HAAA".into(),
+        file_id: "01HBBB".into(),
+        name: "disk image.iso".into(),
+        hash_short: "1a2b3c4d".into()
+      },
+      index: 2,
+      total: 3
+    };
+    let cap = make_part_caption(&m);
+    assert!(cap.contains("#part 2/3"));
+    assert_eq!(parse_part_caption(&cap).unwrap(), m);
+    assert!(parse_file_caption(&cap).is_err());
+    assert!(parse_part_caption(&make_file_caption(&m.file)).is_err());
+    assert!(parse_part_caption("#ocltg #v1 #part 4/3 d=a f=b n=c h=d").is_err());
+  }
+
+  #[test]
+  fn encrypted_caption_keeps_file_meta() {
+    let m = FileMeta {
+      dir_id: "01HAAA".into(),
+      file_id: "01HBBB".into(),
+      name: "tax return.pdf".into(),
+      hash_short: "1a2b3c4d".into()
+    };
+    let cap = mark_encrypted(&make_file_caption(&m), "9f8e7d6c");
+    assert_eq!(parse_file_caption(&cap).unwrap(), m);
+    assert_eq!(encryption_key_id(&cap).as_deref(), Some("9f8e7d6c"));
+    assert_eq!(encryption_key_id(&make_file_caption(&m)), None);
+  }
+
+  #[test]
+  fn hostile_names_keep_caption_structure() {
+    let corpus = [
+      "report final_v2.pdf",
+      "\u{202E}fdp.exe",
+      "\u{2067}שלום עולם\u{2069}.txt",
+      "e\u{301}te\u{301}.txt",
+      "zero\u{200B}width\u{FEFF}.txt",
+      "👨\u{200D}👩\u{200D}👧 семья.jpg",
+      "nbsp\u{00A0}ideo\u{3000}name.txt",
+      "tab\tline\nbreak.txt",
+      "__double__.txt",
+      "x n=evil h=0 #file.txt",
+      "\u{200F}"
+    ];
+    for name in corpus {
+      let m = FileMeta { dir_id: "01HAAA".into(), file_id: "01HBBB".into(), name: name.into(), hash_short: "1a2b".into() };
+      let cap = make_file_caption(&m);
+      assert_eq!(cap.split_whitespace().count(), 7, "{name:?}");
+      assert!(!cap.chars().any(is_bidi_control), "{name:?}");
+      let parsed = parse_file_caption(&cap).unwrap();
+      assert_eq!(parsed.file_id, "01HBBB");
+      assert_eq!(parsed.hash_short, "1a2b");
+      assert_eq!(parsed.name, caption_name(name), "{name:?}");
+      assert_eq!(caption_name(&parsed.name), parsed.name, "{name:?}");
+      assert_eq!(is_caption_form_of(&parsed.name, name), parsed.name != name, "{name:?}");
+
+      let dir = DirMeta { dir_id: "01HCCC".into(), parent_id: "ROOT".into(), name: name.into() };
+      assert_eq!(parse_dir_message(&make_dir_message(&dir)).unwrap().name, caption_name(name));
+    }
+    assert_eq!(caption_name("e\u{301}te\u{301}.txt"), "\u{e9}t\u{e9}.txt");
+    assert_eq!(caption_name("\u{202E}fdp.exe"), "fdp.exe");
+    assert!(caption_name("👨\u{200D}👩\u{200D}👧.jpg").contains('\u{200D}'));
+    assert!(!is_caption_form_of("other.txt", "\u{202E}fdp.exe"));
+  }
+
   #[test]
   fn dir_roundtrip() {
     let m = DirMeta { dir_id: "01HCCC".into(), parent_id: "ROOT".into(), name: "My Projects".into() };
@@ -105,4 +307,19 @@ mod tests {
     let parsed = parse_dir_message(&txt).unwrap();
     assert_eq!(parsed, m);
   }
+
+  #[test]
+  fn link_roundtrip() {
+    let m = LinkMeta {
+      link_id: "01HDDD".into(),
+      dir_id: "01HCCC".into(),
+      kind: LinkKind::File,
+      target_id: "01HBBB".into()
+    };
+    let txt = make_link_message(&m);
+    let parsed = parse_link_message(&txt).unwrap();
+    assert_eq!(parsed, m);
+    assert!(parse_dir_message(&txt).is_err());
+    assert!(parse_file_caption(&txt).is_err());
+  }
 }
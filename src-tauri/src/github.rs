@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use ureq::typestate::WithoutBody;
+use ureq::{Agent, RequestBuilder};
+
+/// Токен из окружения поднимает лимит запросов к API GitHub. Без него
+/// работает анонимный доступ.
+pub fn token() -> Option<String> {
+  std::env::var("GITHUB_TOKEN")
+    .ok()
+    .or_else(|| std::env::var("GH_TOKEN").ok())
+}
+
+pub fn agent(recv_timeout: Duration) -> Agent {
+  ureq::Agent::config_builder()
+    .timeout_connect(Some(Duration::from_secs(10)))
+    .timeout_recv_body(Some(recv_timeout))
+    .build()
+    .into()
+}
+
+/// GET с User-Agent и токеном, если он задан.
+pub fn get(agent: &Agent, url: &str) -> RequestBuilder<WithoutBody> {
+  let req = agent.get(url).header("User-Agent", "cloudtg");
+  match token() {
+    Some(token) => req.header("Authorization", &format!("Bearer {token}")),
+    None => req
+  }
+}
+
+/// GET к REST API GitHub.
+pub fn api_get(agent: &Agent, url: &str) -> RequestBuilder<WithoutBody> {
+  get(agent, url).header("Accept", "application/vnd.github+json")
+}
+
+pub fn https_host(url: &str) -> Option<String> {
+  let trimmed = url.trim();
+  let rest = trimmed.strip_prefix("https://")?;
+  let host_port = rest.split(&['/', '?', '#'][..]).next()?.trim();
+  if host_port.is_empty() || host_port.contains('@') {
+    return None;
+  }
+  let host = host_port
+    .split(':')
+    .next()
+    .unwrap_or("")
+    .trim_matches(&['[', ']'][..])
+    .to_ascii_lowercase();
+  if host.is_empty() {
+    None
+  } else {
+    Some(host)
+  }
+}
+
+/// Хосты GitHub, с которых отдаются релизы и их файлы.
+pub fn is_trusted_host(host: &str) -> bool {
+  matches!(
+    host,
+    "github.com"
+      | "api.github.com"
+      | "raw.githubusercontent.com"
+      | "objects.githubusercontent.com"
+      | "github-releases.githubusercontent.com"
+      | "githubusercontent.com"
+      | "codeload.github.com"
+  ) || host.ends_with(".githubusercontent.com")
+}
@@ -1,9 +1,22 @@
 pub mod logging;
+pub mod metrics;
+pub mod startup;
+pub mod diagnostics;
+pub mod recent_errors;
+pub mod dev;
+pub mod status_page;
+pub mod stream_server;
+pub mod events;
+pub mod deep_link;
+pub mod github;
+pub mod updater;
 pub mod paths;
+pub mod accounts;
 pub mod state;
 pub mod commands;
 pub mod settings;
 pub mod secrets;
+pub mod quarantine;
 
 pub mod app;
 pub mod db;
@@ -33,17 +33,48 @@ fn apply_webview_icon(window: &tauri::WebviewWindow, icon: &tauri::image::Image<
   }
 }
 
+fn register_deep_links(app: &tauri::App) {
+  use tauri_plugin_deep_link::DeepLinkExt;
+  // На Linux и в dev-сборке Windows схема регистрируется при запуске, а не установщиком.
+  #[cfg(any(target_os = "linux", all(debug_assertions, windows)))]
+  if let Err(e) = app.deep_link().register_all() {
+    tracing::warn!(event = "deep_link_register_failed", error = %e, "Не удалось зарегистрировать ссылки cloudtg://");
+  }
+  let handle = app.handle().clone();
+  app.deep_link().on_open_url(move |event| {
+    cloudtg_lib::deep_link::handle_urls(&handle, event.urls().into_iter().map(|u| u.to_string()));
+  });
+  if let Ok(Some(urls)) = app.deep_link().get_current() {
+    cloudtg_lib::deep_link::handle_urls(app.handle(), urls.into_iter().map(|u| u.to_string()));
+  }
+}
+
 fn main() {
+  cloudtg_lib::startup::mark_launch();
   let _ = dotenvy::dotenv();
   cloudtg_lib::logging::init();
   let icon_for_setup = load_app_icon();
 
   tauri::Builder::default()
+    // Повторный запуск, в том числе по ссылке cloudtg://, поднимает уже открытое окно;
+    // саму ссылку плагин передает в deep-link.
+    .plugin(tauri_plugin_single_instance::init(|app, _argv, _cwd| {
+      cloudtg_lib::deep_link::focus_main(app);
+    }))
+    .plugin(tauri_plugin_deep_link::init())
     .manage(AppState::new())
     .plugin(tauri_plugin_clipboard_manager::init())
+    .plugin(tauri_plugin_drag::init())
     .invoke_handler(tauri::generate_handler![
       commands::auth_status,
-      commands::app_check_update,
+      commands::account_list,
+      commands::account_add,
+      commands::account_switch,
+      commands::errors_recent,
+      commands::errors_report,
+      commands::errors_clear,
+      commands::app_update_check,
+      commands::app_update_install,
       commands::app_open_url,
       commands::app_help_text,
       commands::auth_start,
@@ -56,39 +87,186 @@ fn main() {
       commands::dir_create,
       commands::dir_rename,
       commands::dir_move,
+      commands::dir_copy,
       commands::dir_delete,
       commands::dir_repair,
       commands::dir_list_tree,
+      commands::dir_get_node,
+      commands::link_create,
+      commands::link_delete,
+      commands::broken_report,
+      commands::metrics_dump,
+      commands::app_health,
+      commands::app_first_paint,
+      commands::db_schema_info,
+      commands::db_explain,
       commands::file_list,
       commands::file_search,
+      commands::quick_search,
+      commands::file_set_favorite,
+      commands::virtual_folder_create,
+      commands::virtual_folder_list,
+      commands::virtual_folder_files,
+      commands::virtual_folder_delete,
+      commands::file_group_list,
+      commands::collections_browse,
+      commands::file_transcribe,
+      commands::file_transcript_get,
+      commands::transcripts_search,
+      commands::note_create,
+      commands::note_get,
+      commands::note_update,
+      commands::notes_search,
       commands::file_pick,
       commands::file_pick_upload,
       commands::file_prepare_upload_paths,
+      commands::file_drop_upload,
       commands::tdlib_pick,
       commands::tdlib_cache_size,
       commands::tdlib_cache_clear,
       commands::file_upload,
+      commands::inbox_capture,
       commands::file_move,
+      commands::file_move_many,
+      commands::file_rename,
+      commands::file_copy,
       commands::file_delete,
       commands::file_repair,
+      commands::file_verify,
+      commands::repair_all,
       commands::file_delete_many,
       commands::file_download,
+      commands::file_download_resume,
+      commands::downloads_interrupted,
+      commands::file_queue_download,
+      commands::dir_queue_download,
+      commands::dir_download,
+      commands::dir_download_cancel,
+      commands::transfer_queue_upload,
+      commands::transfer_queue_download,
+      commands::transfer_list,
+      commands::transfer_pause,
+      commands::transfer_resume,
+      commands::transfer_cancel,
+      commands::settings_get_transfer_concurrency,
+      commands::settings_set_transfer_concurrency,
+      commands::download_queue_list,
+      commands::download_queue_set_priority,
+      commands::download_queue_reorder,
+      commands::download_queue_remove,
       commands::file_open,
+      commands::file_stream,
       commands::file_open_folder,
+      commands::file_copy_to_clipboard,
+      commands::file_prepare_drag,
+      commands::file_preview_text,
+      commands::file_thumbnail,
+      commands::file_activate,
+      commands::deep_link_pending,
+      commands::window_open_folder,
+      commands::storage_stats,
+      commands::cache_usage,
+      commands::cache_clear,
+      commands::settings_get_cache_limit,
+      commands::settings_set_cache_limit,
+      commands::downloads_usage,
       commands::file_share_link,
       commands::file_share_to_chat,
       commands::tg_search_chats,
       commands::tg_recent_chats,
+      commands::tg_chat_folders,
+      commands::tg_folder_chats,
       commands::tg_test_message,
       commands::tg_create_channel,
+      commands::tg_old_channel,
+      commands::tg_delete_old_channel,
+      commands::storage_reseed_subtree,
+      commands::migration_failures_list,
+      commands::migration_failures_retry,
       commands::tg_sync_storage,
       commands::tg_reconcile_recent,
+      commands::sync_auto_enable,
+      commands::sync_auto_status,
+      commands::storage_unindexed_scan,
+      commands::storage_unindexed_import,
+      commands::unassigned_auto_sort,
+      commands::dir_prefs_get,
+      commands::dir_prefs_set,
+      commands::import_rules_list,
+      commands::import_rule_create,
+      commands::import_rule_update,
+      commands::import_rule_delete,
+      commands::import_rules_reorder,
+      commands::source_channels_list,
+      commands::source_channel_save,
+      commands::source_channel_delete,
+      commands::source_channel_sync,
+      commands::storage_channels_list,
+      commands::storage_channel_create,
+      commands::storage_channel_assign,
       commands::backup_create,
       commands::backup_restore,
+      commands::bootstrap_export,
+      commands::bootstrap_import,
+      commands::storage_resync_chat,
+      commands::backup_extract,
       commands::backup_open_channel,
+      commands::settings_get_backup_retention,
+      commands::settings_set_backup_retention,
       commands::settings_get_tg,
       commands::settings_set_tg,
-      commands::settings_unlock_tg
+      commands::settings_unlock_tg,
+      commands::password_strength,
+      commands::secrets_rotate,
+      commands::diagnostics_get,
+      commands::diagnostics_set,
+      commands::diagnostics_export,
+      commands::diagnostics_submit,
+      #[cfg(feature = "dev")]
+      commands::dev_inject_history,
+      #[cfg(feature = "dev")]
+      commands::dev_simulate_error,
+      #[cfg(feature = "dev")]
+      commands::dev_fast_forward,
+      #[cfg(feature = "dev")]
+      commands::dev_simulation_state,
+      #[cfg(feature = "dev")]
+      commands::dev_simulation_reset,
+      commands::secrets_fido2_status,
+      commands::secrets_fido2_enroll,
+      commands::secrets_fido2_remove,
+      commands::settings_get_search_index,
+      commands::settings_set_search_index,
+      commands::settings_get_vault_summary,
+      commands::settings_set_vault_summary,
+      commands::settings_get_read_only_channel,
+      commands::settings_set_read_only_channel,
+      commands::vault_status,
+      commands::vault_set_passphrase,
+      commands::vault_unlock,
+      commands::vault_lock,
+      commands::settings_set_vault_encryption,
+      commands::status_page_get,
+      commands::status_page_set,
+      commands::settings_get_tdlib_timeouts,
+      commands::settings_set_tdlib_timeouts,
+      commands::settings_get_indexer_ignore,
+      commands::settings_set_indexer_ignore,
+      commands::settings_get_transcription,
+      commands::settings_set_transcription,
+      commands::settings_get_maintenance_window,
+      commands::settings_set_maintenance_window,
+      commands::settings_get_local_names,
+      commands::settings_set_local_names,
+      commands::local_name_preview,
+      commands::settings_get_open_guard,
+      commands::settings_set_open_guard,
+      commands::settings_get_file_actions,
+      commands::settings_set_file_actions,
+      commands::settings_get_virus_scan,
+      commands::settings_set_virus_scan,
+      commands::settings_get_system_dir_names,
+      commands::settings_set_system_dir_names
     ])
     .setup(move |app| {
       if let Some(icon) = icon_for_setup.clone() {
@@ -96,8 +274,10 @@ fn main() {
           apply_webview_icon(&win, &icon);
         }
       }
+      cloudtg_lib::recent_errors::install(app.handle().clone());
       let state = app.state::<AppState>();
       state.spawn_init(app.handle().clone());
+      register_deep_links(app);
       Ok(())
     })
     .run(tauri::generate_context!())
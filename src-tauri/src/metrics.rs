@@ -0,0 +1,147 @@
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Счетчики процесса для мониторинга долгих синхронизаций.
+/// Живут до перезапуска приложения и не сохраняются в БД.
+static UPLOADS: AtomicU64 = AtomicU64::new(0);
+static UPLOAD_ERRORS: AtomicU64 = AtomicU64::new(0);
+static DOWNLOADS: AtomicU64 = AtomicU64::new(0);
+static DOWNLOAD_ERRORS: AtomicU64 = AtomicU64::new(0);
+static COMMAND_ERRORS: AtomicU64 = AtomicU64::new(0);
+static TDLIB_RECONNECTS: AtomicU64 = AtomicU64::new(0);
+static SYNC_RUNS: AtomicU64 = AtomicU64::new(0);
+static SYNC_FAILURES: AtomicU64 = AtomicU64::new(0);
+static SYNC_MESSAGES: AtomicU64 = AtomicU64::new(0);
+static SYNC_DURATION_MS_SUM: AtomicU64 = AtomicU64::new(0);
+static SYNC_LAST_DURATION_MS: AtomicU64 = AtomicU64::new(0);
+
+static TDLIB_CONNECTED: AtomicBool = AtomicBool::new(false);
+static TDLIB_WAS_CONNECTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transfer {
+  Upload,
+  Download
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MetricsSnapshot {
+  pub uploads_total: u64,
+  pub upload_errors_total: u64,
+  pub downloads_total: u64,
+  pub download_errors_total: u64,
+  pub command_errors_total: u64,
+  pub tdlib_reconnects_total: u64,
+  pub tdlib_connected: bool,
+  pub sync_runs_total: u64,
+  pub sync_failures_total: u64,
+  pub sync_messages_total: u64,
+  pub sync_duration_seconds_sum: f64,
+  pub sync_last_duration_seconds: f64,
+  pub sync_ignored: crate::app::ignore_list::IgnoreCounters
+}
+
+pub fn record_transfer(kind: Transfer, ok: bool) {
+  let counter = match (kind, ok) {
+    (Transfer::Upload, true) => &UPLOADS,
+    (Transfer::Upload, false) => &UPLOAD_ERRORS,
+    (Transfer::Download, true) => &DOWNLOADS,
+    (Transfer::Download, false) => &DOWNLOAD_ERRORS
+  };
+  counter.fetch_add(1, Ordering::Relaxed);
+  crate::diagnostics::record_operation(match (kind, ok) {
+    (Transfer::Upload, true) => "upload",
+    (Transfer::Upload, false) => "upload_failed",
+    (Transfer::Download, true) => "download",
+    (Transfer::Download, false) => "download_failed"
+  });
+}
+
+pub fn record_command_error() {
+  COMMAND_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Переподключением считается каждый возврат в готовое состояние после первого.
+pub fn record_connection_state(ready: bool) {
+  let was_ready = TDLIB_CONNECTED.swap(ready, Ordering::Relaxed);
+  if ready && !was_ready && TDLIB_WAS_CONNECTED.swap(true, Ordering::Relaxed) {
+    TDLIB_RECONNECTS.fetch_add(1, Ordering::Relaxed);
+  }
+}
+
+pub fn record_sync(duration: Duration, messages: u64, ok: bool) {
+  let ms = duration.as_millis().min(u64::MAX as u128) as u64;
+  SYNC_RUNS.fetch_add(1, Ordering::Relaxed);
+  if !ok {
+    SYNC_FAILURES.fetch_add(1, Ordering::Relaxed);
+  }
+  SYNC_MESSAGES.fetch_add(messages, Ordering::Relaxed);
+  SYNC_DURATION_MS_SUM.fetch_add(ms, Ordering::Relaxed);
+  SYNC_LAST_DURATION_MS.store(ms, Ordering::Relaxed);
+  crate::diagnostics::record_operation(if ok { "sync" } else { "sync_failed" });
+}
+
+pub fn snapshot() -> MetricsSnapshot {
+  let secs = |v: &AtomicU64| v.load(Ordering::Relaxed) as f64 / 1000.0;
+  MetricsSnapshot {
+    uploads_total: UPLOADS.load(Ordering::Relaxed),
+    upload_errors_total: UPLOAD_ERRORS.load(Ordering::Relaxed),
+    downloads_total: DOWNLOADS.load(Ordering::Relaxed),
+    download_errors_total: DOWNLOAD_ERRORS.load(Ordering::Relaxed),
+    command_errors_total: COMMAND_ERRORS.load(Ordering::Relaxed),
+    tdlib_reconnects_total: TDLIB_RECONNECTS.load(Ordering::Relaxed),
+    tdlib_connected: TDLIB_CONNECTED.load(Ordering::Relaxed),
+    sync_runs_total: SYNC_RUNS.load(Ordering::Relaxed),
+    sync_failures_total: SYNC_FAILURES.load(Ordering::Relaxed),
+    sync_messages_total: SYNC_MESSAGES.load(Ordering::Relaxed),
+    sync_duration_seconds_sum: secs(&SYNC_DURATION_MS_SUM),
+    sync_last_duration_seconds: secs(&SYNC_LAST_DURATION_MS),
+    sync_ignored: crate::app::ignore_list::counters()
+  }
+}
+
+/// Текстовый формат Prometheus (exposition format 0.0.4).
+pub fn render_prometheus(s: &MetricsSnapshot) -> String {
+  let mut out = String::new();
+  let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+    let _ = writeln!(out, "# HELP cloudtg_{name} {help}");
+    let _ = writeln!(out, "# TYPE cloudtg_{name} {kind}");
+    let _ = writeln!(out, "cloudtg_{name} {value}");
+  };
+  metric("uploads_total", "counter", "Successful uploads.", s.uploads_total.to_string());
+  metric("upload_errors_total", "counter", "Failed uploads.", s.upload_errors_total.to_string());
+  metric("downloads_total", "counter", "Successful downloads.", s.downloads_total.to_string());
+  metric("download_errors_total", "counter", "Failed downloads.", s.download_errors_total.to_string());
+  metric("command_errors_total", "counter", "Commands finished with an error.", s.command_errors_total.to_string());
+  metric("tdlib_reconnects_total", "counter", "TDLib reconnects after the first connection.", s.tdlib_reconnects_total.to_string());
+  metric("tdlib_connected", "gauge", "Whether TDLib is connected.", u8::from(s.tdlib_connected).to_string());
+  metric("sync_runs_total", "counter", "Storage sync runs.", s.sync_runs_total.to_string());
+  metric("sync_failures_total", "counter", "Failed storage sync runs.", s.sync_failures_total.to_string());
+  metric("sync_messages_total", "counter", "Messages processed by storage sync.", s.sync_messages_total.to_string());
+  metric("sync_duration_seconds_sum", "counter", "Total time spent in storage sync.", s.sync_duration_seconds_sum.to_string());
+  metric("sync_last_duration_seconds", "gauge", "Duration of the last storage sync.", s.sync_last_duration_seconds.to_string());
+  metric("sync_ignored_sender_total", "counter", "Realtime messages skipped by sender.", s.sync_ignored.sender.to_string());
+  metric("sync_ignored_text_prefix_total", "counter", "Realtime messages skipped by text prefix.", s.sync_ignored.text_prefix.to_string());
+  metric("sync_ignored_content_type_total", "counter", "Realtime messages skipped by content type.", s.sync_ignored.content_type.to_string());
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn prometheus_output_lists_every_metric() {
+    record_connection_state(true);
+    record_connection_state(false);
+    record_connection_state(true);
+    let snap = snapshot();
+    assert!(snap.tdlib_reconnects_total >= 1);
+
+    let text = render_prometheus(&snap);
+    assert!(text.contains("# TYPE cloudtg_uploads_total counter\ncloudtg_uploads_total "));
+    assert!(text.contains("cloudtg_tdlib_connected 1\n"));
+    assert_eq!(text.lines().filter(|l| l.starts_with("cloudtg_")).count(), 15);
+  }
+}
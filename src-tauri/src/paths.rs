@@ -1,28 +1,42 @@
 use std::env;
 use std::path::{Path, PathBuf};
 
+use sha2::{Digest, Sha256};
+
 #[derive(Clone, Debug)]
 pub struct Paths {
   pub base_dir: PathBuf,
   pub data_dir: PathBuf,
   pub cache_dir: PathBuf,
   pub logs_dir: PathBuf,
-  pub resource_dir: Option<PathBuf>
+  pub resource_dir: Option<PathBuf>,
+  /// Переносной режим: все лежит рядом с исполняемым файлом, системное
+  /// хранилище ключей не используется.
+  pub portable: bool
 }
 
 impl Paths {
   pub fn detect() -> anyhow::Result<Self> {
+    let exe_dir = || -> anyhow::Result<PathBuf> {
+      let exe = std::env::current_exe()?;
+      Ok(exe.parent().unwrap_or_else(|| Path::new(".")).to_path_buf())
+    };
+    if portable_requested(env::args(), env::var("CLOUDTG_PORTABLE").ok()) {
+      return Ok(Self { portable: true, ..Self::from_base(exe_dir()?) });
+    }
     let base_dir = if let Ok(p) = env::var("CLOUDTG_BASE_DIR") {
       PathBuf::from(p)
     } else {
-      let exe = std::env::current_exe()?;
-      exe.parent().unwrap_or_else(|| Path::new(".")).to_path_buf()
+      exe_dir()?
     };
     let mut paths = Self::from_base(base_dir);
 
     #[cfg(not(target_os = "windows"))]
     {
-      let storage_dir = resolve_storage_dir(&paths.base_dir);
+      let mut storage_dir = resolve_storage_dir(&paths.base_dir);
+      if let Some(previous) = previous_storage(&paths.base_dir, &storage_dir) {
+        storage_dir = adopt_previous_storage(&previous, &storage_dir);
+      }
       paths.data_dir = storage_dir.join("data");
       paths.cache_dir = storage_dir.join("cache");
       paths.logs_dir = storage_dir.join("logs");
@@ -40,7 +54,8 @@ impl Paths {
       data_dir,
       cache_dir,
       logs_dir,
-      resource_dir: None
+      resource_dir: None,
+      portable: false
     }
   }
 
@@ -49,11 +64,29 @@ impl Paths {
     self
   }
 
+  /// Каталоги аккаунта: своя база, сессия TDLib и загрузки. Каждое
+  /// хранилище получает подкаталог `accounts/<id>`; только основной аккаунт
+  /// установок, заведенных до нескольких аккаунтов, остается в корне.
+  pub fn for_account(&self, account_id: &str) -> Paths {
+    if account_id == crate::accounts::DEFAULT_ACCOUNT && self.sqlite_path().exists() {
+      return self.clone();
+    }
+    Paths {
+      data_dir: self.data_dir.join("accounts").join(account_id),
+      cache_dir: self.cache_dir.join("accounts").join(account_id),
+      ..self.clone()
+    }
+  }
+
+  pub fn accounts_path(&self) -> PathBuf {
+    self.data_dir.join("accounts.json")
+  }
+
   pub fn ensure_dirs(&self) -> anyhow::Result<()> {
     std::fs::create_dir_all(&self.data_dir)?;
     std::fs::create_dir_all(&self.cache_dir)?;
     std::fs::create_dir_all(&self.logs_dir)?;
-    std::fs::create_dir_all(self.cache_dir.join("downloads"))?;
+    std::fs::create_dir_all(self.layout().downloads_dir())?;
     Ok(())
   }
 
@@ -61,17 +94,210 @@ impl Paths {
     self.data_dir.join("cloudtg.sqlite")
   }
 
+  pub fn layout(&self) -> Layout<'_> {
+    Layout { paths: self }
+  }
+}
+
+/// Временные каталоги, которые приложение создает внутри кэша.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scratch {
+  Verify,
+  Notes,
+  Inbox
+}
+
+impl Scratch {
+  fn dir_name(self) -> &'static str {
+    match self {
+      Scratch::Verify => "verify",
+      Scratch::Notes => "notes",
+      Scratch::Inbox => "inbox"
+    }
+  }
+}
+
+/// Раскладка файлов внутри каталогов `Paths`: загрузки, миниатюры,
+/// временные файлы, резервные копии и восстановление базы. Пути собираются
+/// только здесь, поэтому у каждого аккаунта (`Paths::for_account`) они свои.
+#[derive(Clone, Copy, Debug)]
+pub struct Layout<'a> {
+  paths: &'a Paths
+}
+
+impl Layout<'_> {
+  /// Корень скачанных файлов; внутри повторяется дерево папок хранилища.
+  pub fn downloads_dir(&self) -> PathBuf {
+    self.paths.cache_dir.join("downloads")
+  }
+
+  /// Миниатюры одного файла; имя каждой включает хэш содержимого.
+  pub fn thumbnails_dir(&self, file_id: &str) -> PathBuf {
+    self.paths.cache_dir.join("thumbnails").join(file_id)
+  }
+
+  pub fn previews_dir(&self) -> PathBuf {
+    self.paths.cache_dir.join("previews")
+  }
+
+  pub fn archive_path(&self, name: &str) -> PathBuf {
+    self.paths.cache_dir.join("archives").join(format!("{name}.zip"))
+  }
+
+  pub fn scratch_dir(&self, kind: Scratch) -> PathBuf {
+    self.paths.cache_dir.join(kind.dir_name())
+  }
+
+  pub fn tdlib_files_dir(&self) -> PathBuf {
+    self.paths.cache_dir.join("tdlib_files")
+  }
+
+  pub fn chat_photos_dir(&self) -> PathBuf {
+    self.paths.cache_dir.join("chat_photos")
+  }
+
   pub fn backup_dir(&self) -> PathBuf {
-    self.cache_dir.join("backups")
+    self.paths.cache_dir.join("backups")
+  }
+
+  /// Картинка под курсором при перетаскивании файлов из приложения.
+  pub fn drag_icon_path(&self) -> PathBuf {
+    self.paths.cache_dir.join("drag-icon.png")
   }
 
   pub fn pending_restore_path(&self) -> PathBuf {
-    self.data_dir.join("cloudtg.sqlite.pending")
+    self.paths.data_dir.join("cloudtg.sqlite.pending")
   }
 
   pub fn previous_db_path(&self) -> PathBuf {
-    self.data_dir.join("cloudtg.sqlite.prev")
+    self.paths.data_dir.join("cloudtg.sqlite.prev")
+  }
+
+  /// Восстановленная база, которая не открылась и была откатена.
+  pub fn failed_restore_path(&self) -> PathBuf {
+    self.paths.data_dir.join("cloudtg.sqlite.failed")
+  }
+}
+
+/// Переносной режим включают флагом `--portable` или `CLOUDTG_PORTABLE=1`,
+/// чтобы запускать приложение с флешки на разных компьютерах.
+fn portable_requested(mut args: impl Iterator<Item = String>, env_value: Option<String>) -> bool {
+  let from_env = env_value
+    .map(|v| matches!(v.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+    .unwrap_or(false);
+  from_env || args.any(|a| a == "--portable")
+}
+
+const STORAGE_SUBDIRS: [&str; 3] = ["data", "cache", "logs"];
+
+/// База основного аккаунта старых установок лежит в корне, остальные — в
+/// `data/accounts`.
+fn has_storage_db(dir: &Path) -> bool {
+  let data = dir.join("data");
+  data.join("cloudtg.sqlite").exists() || data.join("accounts").is_dir()
+}
+
+/// Перенос нужен, если база есть в прежнем каталоге, а в новом еще нет.
+pub fn needs_relocation(from: &Path, to: &Path) -> bool {
+  from != to && has_storage_db(from) && !has_storage_db(to)
+}
+
+/// Переносит данные, кэш и логи из прежнего каталога хранения в новый, если
+/// в новом еще нет базы. Каталоги переименовываются, а между дисками
+/// копируются, сверяются и только потом удаляются. Если хоть один каталог
+/// перенести не удалось, уже перенесенные возвращаются на место. Возвращает,
+/// был ли перенос.
+pub fn relocate_storage(from: &Path, to: &Path) -> anyhow::Result<bool> {
+  if !needs_relocation(from, to) {
+    return Ok(false);
+  }
+  std::fs::create_dir_all(to)?;
+  for name in STORAGE_SUBDIRS {
+    let dst = to.join(name);
+    if from.join(name).is_dir() && dst.exists() && std::fs::read_dir(&dst)?.next().is_some() {
+      return Err(anyhow::anyhow!("Каталог {} уже не пуст", dst.display()));
+    }
+  }
+
+  // (откуда, куда, переименован ли)
+  let mut moved: Vec<(PathBuf, PathBuf, bool)> = Vec::new();
+  let mut result = Ok(());
+  for name in STORAGE_SUBDIRS {
+    let src = from.join(name);
+    let dst = to.join(name);
+    if !src.is_dir() {
+      continue;
+    }
+    if dst.exists() {
+      let _ = std::fs::remove_dir(&dst);
+    }
+    if std::fs::rename(&src, &dst).is_ok() {
+      moved.push((src, dst, true));
+      continue;
+    }
+    if let Err(e) = copy_tree(&src, &dst).and_then(|_| verify_tree(&src, &dst)) {
+      let _ = std::fs::remove_dir_all(&dst);
+      result = Err(e);
+      break;
+    }
+    moved.push((src, dst, false));
+  }
+
+  if let Err(e) = result {
+    for (src, dst, renamed) in moved.into_iter().rev() {
+      if renamed {
+        let _ = std::fs::rename(&dst, &src);
+      } else {
+        let _ = std::fs::remove_dir_all(&dst);
+      }
+    }
+    return Err(e);
+  }
+  for (src, _, renamed) in moved {
+    if !renamed {
+      std::fs::remove_dir_all(&src)?;
+    }
+  }
+  Ok(true)
+}
+
+fn copy_tree(src: &Path, dst: &Path) -> anyhow::Result<()> {
+  std::fs::create_dir_all(dst)?;
+  for entry in std::fs::read_dir(src)? {
+    let entry = entry?;
+    let target = dst.join(entry.file_name());
+    if entry.file_type()?.is_dir() {
+      copy_tree(&entry.path(), &target)?;
+    } else {
+      std::fs::copy(entry.path(), &target)?;
+    }
   }
+  Ok(())
+}
+
+/// Сверяет копию с оригиналом: каждый файл на месте, с тем же размером и sha256.
+fn verify_tree(src: &Path, dst: &Path) -> anyhow::Result<()> {
+  for entry in std::fs::read_dir(src)? {
+    let entry = entry?;
+    let target = dst.join(entry.file_name());
+    if entry.file_type()?.is_dir() {
+      verify_tree(&entry.path(), &target)?;
+      continue;
+    }
+    let same = std::fs::metadata(&target).map(|m| m.len()).ok() == Some(entry.metadata()?.len())
+      && file_sha256(&entry.path())? == file_sha256(&target)?;
+    if !same {
+      return Err(anyhow::anyhow!("Копия {} не совпадает с оригиналом", target.display()));
+    }
+  }
+  Ok(())
+}
+
+fn file_sha256(path: &Path) -> anyhow::Result<Vec<u8>> {
+  let mut file = std::fs::File::open(path)?;
+  let mut hasher = Sha256::new();
+  std::io::copy(&mut file, &mut hasher)?;
+  Ok(hasher.finalize().to_vec())
 }
 
 #[cfg(not(target_os = "windows"))]
@@ -99,6 +325,50 @@ fn resolve_storage_dir(base_dir: &Path) -> PathBuf {
   base_dir.to_path_buf()
 }
 
+/// Каталог хранения сменился (задан `CLOUDTG_STORAGE_DIR` или снова доступен
+/// каталог рядом с бинарём), а данные остались в прежнем месте.
+#[cfg(not(target_os = "windows"))]
+fn previous_storage(base_dir: &Path, storage_dir: &Path) -> Option<PathBuf> {
+  [Some(base_dir.to_path_buf()), user_storage_dir()]
+    .into_iter()
+    .flatten()
+    .find(|from| needs_relocation(from, storage_dir))
+}
+
+/// Переносит данные только с согласия пользователя. Возвращает каталог, с
+/// которым работать дальше: при отказе или ошибке — прежний, чтобы не
+/// начинать с пустой базой.
+#[cfg(not(target_os = "windows"))]
+fn adopt_previous_storage(from: &Path, to: &Path) -> PathBuf {
+  let confirmed = matches!(
+    rfd::MessageDialog::new()
+      .set_title("Перенос данных CloudTG")
+      .set_level(rfd::MessageLevel::Info)
+      .set_description(format!(
+        "Каталог хранения сменился.\n\nПеренести данные из {} в {}?\n\nЕсли отказаться, приложение продолжит работать со старым каталогом.",
+        from.display(),
+        to.display()
+      ))
+      .set_buttons(rfd::MessageButtons::YesNo)
+      .show(),
+    rfd::MessageDialogResult::Ok | rfd::MessageDialogResult::Yes
+  );
+  if !confirmed {
+    eprintln!("Перенос данных отменен, использую {from:?}.");
+    return from.to_path_buf();
+  }
+  match relocate_storage(from, to) {
+    Ok(_) => {
+      eprintln!("Данные перенесены из {from:?} в {to:?}.");
+      to.to_path_buf()
+    }
+    Err(e) => {
+      eprintln!("Не удалось перенести данные из {from:?} в {to:?}: {e}. Использую прежний каталог.");
+      from.to_path_buf()
+    }
+  }
+}
+
 #[cfg(not(target_os = "windows"))]
 fn can_use_storage(dir: &Path) -> bool {
   if std::fs::create_dir_all(dir).is_err() {
@@ -128,3 +398,76 @@ fn user_storage_dir() -> Option<PathBuf> {
   }
   None
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::tempdir;
+
+  #[test]
+  fn portable_mode_from_flag_or_env() {
+    let args = |list: &[&str]| list.iter().map(|a| a.to_string()).collect::<Vec<_>>().into_iter();
+    assert!(portable_requested(args(&["cloudtg", "--portable"]), None));
+    assert!(portable_requested(args(&["cloudtg"]), Some("1".into())));
+    assert!(portable_requested(args(&["cloudtg"]), Some(" TRUE ".into())));
+    assert!(!portable_requested(args(&["cloudtg"]), Some("0".into())));
+    assert!(!portable_requested(args(&["cloudtg", "portable"]), None));
+  }
+
+  #[test]
+  fn relocate_moves_storage_once() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let old = Paths::from_base(tmp.path().join("old"));
+    let new = Paths::from_base(tmp.path().join("new"));
+    std::fs::create_dir_all(old.layout().downloads_dir().join("docs"))?;
+    std::fs::create_dir_all(&old.data_dir)?;
+    std::fs::write(old.sqlite_path(), b"db")?;
+    std::fs::write(old.layout().downloads_dir().join("docs").join("a.txt"), b"a")?;
+    // Пустой каталог нового места (его создает проверка прав) не мешает.
+    std::fs::create_dir_all(&new.cache_dir)?;
+
+    assert!(relocate_storage(&old.base_dir, &new.base_dir)?);
+    assert_eq!(std::fs::read(new.sqlite_path())?, b"db");
+    assert!(new.layout().downloads_dir().join("docs").join("a.txt").exists());
+    assert!(!old.data_dir.exists());
+
+    std::fs::create_dir_all(&old.data_dir)?;
+    std::fs::write(old.sqlite_path(), b"other")?;
+    assert!(!relocate_storage(&old.base_dir, &new.base_dir)?);
+    assert_eq!(std::fs::read(new.sqlite_path())?, b"db");
+    Ok(())
+  }
+
+  #[test]
+  fn relocate_keeps_source_when_target_is_busy() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let old = Paths::from_base(tmp.path().join("old"));
+    let new = Paths::from_base(tmp.path().join("new"));
+    std::fs::create_dir_all(&old.data_dir)?;
+    std::fs::create_dir_all(&old.logs_dir)?;
+    std::fs::write(old.sqlite_path(), b"db")?;
+    std::fs::create_dir_all(&new.logs_dir)?;
+    std::fs::write(new.logs_dir.join("other.log"), b"x")?;
+
+    assert!(relocate_storage(&old.base_dir, &new.base_dir).is_err());
+    assert_eq!(std::fs::read(old.sqlite_path())?, b"db");
+    assert!(!new.sqlite_path().exists());
+    Ok(())
+  }
+
+  #[test]
+  fn copy_is_verified_against_source() -> anyhow::Result<()> {
+    let tmp = tempdir()?;
+    let (src, dst) = (tmp.path().join("src"), tmp.path().join("dst"));
+    std::fs::create_dir_all(src.join("nested"))?;
+    std::fs::write(src.join("nested").join("a.bin"), b"abc")?;
+    copy_tree(&src, &dst)?;
+    verify_tree(&src, &dst)?;
+
+    std::fs::write(dst.join("nested").join("a.bin"), b"abd")?;
+    assert!(verify_tree(&src, &dst).is_err());
+    std::fs::remove_file(dst.join("nested").join("a.bin"))?;
+    assert!(verify_tree(&src, &dst).is_err());
+    Ok(())
+  }
+}
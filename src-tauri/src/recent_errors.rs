@@ -0,0 +1,150 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use chrono::Utc;
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::state::AppState;
+
+/// Последние ошибки, которые увидел пользователь, для панели проблем в UI.
+/// У каждого аккаунта свой список в `AppState`, живет до перезапуска
+/// приложения. Код и текст записываются в `map_err`, а имя команды сообщает
+/// интерфейс: команда выполняется в отдельной задаче Tauri, и сам `map_err`
+/// не знает, из какой команды его вызвали.
+pub const MAX_RECENT: usize = 50;
+
+/// Ответы-вопросы к пользователю: интерфейс показывает по ним диалог, а не
+/// ошибку.
+const PROMPT_CODES: &[&str] = &["NEEDS_CONFIRMATION"];
+
+static APP: OnceCell<AppHandle> = OnceCell::new();
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct RecentError {
+  pub id: u64,
+  pub code: String,
+  pub message: String,
+  pub command: Option<String>,
+  pub at: i64
+}
+
+#[derive(Debug, Default)]
+pub struct RecentErrors {
+  entries: Mutex<VecDeque<RecentError>>
+}
+
+impl RecentErrors {
+  /// Запоминает ошибку команды; вопросы пользователю пропускаются.
+  pub fn record(&self, code: &str, message: &str) -> Option<RecentError> {
+    if is_prompt(message) {
+      return None;
+    }
+    let entry = RecentError {
+      id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+      code: code.to_string(),
+      message: message.to_string(),
+      command: None,
+      at: Utc::now().timestamp()
+    };
+    self.push(entry.clone());
+    Some(entry)
+  }
+
+  /// Привязывает имя команды к последней ошибке с тем же текстом. Если такой
+  /// записи нет (ошибка случилась до `map_err`, например при разборе
+  /// аргументов), создается новая с кодом `ipc`.
+  pub fn attach_command(&self, command: &str, message: &str) -> Option<RecentError> {
+    if is_prompt(message) {
+      return None;
+    }
+    let mut entries = self.entries.lock();
+    if let Some(entry) = entries.iter_mut().rev().find(|e| e.command.is_none() && e.message == message) {
+      entry.command = Some(command.to_string());
+      return Some(entry.clone());
+    }
+    drop(entries);
+    let entry = RecentError {
+      id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+      code: "ipc".to_string(),
+      message: message.to_string(),
+      command: Some(command.to_string()),
+      at: Utc::now().timestamp()
+    };
+    self.push(entry.clone());
+    Some(entry)
+  }
+
+  /// Ошибки от новых к старым, не больше `limit`.
+  pub fn list(&self, limit: usize) -> Vec<RecentError> {
+    self.entries.lock().iter().rev().take(limit).cloned().collect()
+  }
+
+  pub fn clear(&self) {
+    self.entries.lock().clear();
+  }
+
+  fn push(&self, entry: RecentError) {
+    let mut entries = self.entries.lock();
+    if entries.len() == MAX_RECENT {
+      entries.pop_front();
+    }
+    entries.push_back(entry);
+  }
+}
+
+/// Дает `report` доступ к состоянию приложения; вызывается при запуске.
+pub fn install(app: AppHandle) {
+  let _ = APP.set(app);
+}
+
+/// Записывает ошибку в список активного аккаунта и рассылает ее событием
+/// `error_occurred`. До `install` (и в тестах) ничего не делает.
+pub fn report(code: &str, message: &str) {
+  let Some(app) = APP.get() else {
+    return;
+  };
+  if let Some(entry) = app.state::<AppState>().recent_errors().record(code, message) {
+    let _ = app.emit("error_occurred", entry);
+  }
+}
+
+fn is_prompt(message: &str) -> bool {
+  message
+    .split_once(':')
+    .is_some_and(|(prefix, _)| PROMPT_CODES.contains(&prefix))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn keeps_latest_errors_and_attaches_commands() {
+    let errors = RecentErrors::default();
+    errors.record("db", "файл не найден").expect("entry");
+    assert!(errors.record("other", "NEEDS_CONFIRMATION:abc: Открыть исполняемый файл?").is_none());
+    // Коды ошибок в верхнем регистре остаются ошибками.
+    assert!(errors.record("other", "OPEN_BLOCKED: Файл заблокирован").is_some());
+    assert!(errors.record("other", "RECONCILE_SYNC_REQUIRED: Нужна синхронизация").is_some());
+
+    let attached = errors.attach_command("file_open", "файл не найден").expect("entry");
+    assert_eq!(attached.code, "db");
+    assert_eq!(attached.command.as_deref(), Some("file_open"));
+    assert!(errors.attach_command("file_open", "NEEDS_CONFIRMATION:abc: Открыть исполняемый файл?").is_none());
+
+    let fresh = errors.attach_command("dir_create", "invalid args").expect("entry");
+    assert_eq!(fresh.code, "ipc");
+    assert_eq!(errors.list(1), vec![fresh]);
+
+    for i in 0..MAX_RECENT + 5 {
+      errors.record("other", &format!("ошибка {i}"));
+    }
+    assert_eq!(errors.list(usize::MAX).len(), MAX_RECENT);
+    assert!(!errors.list(usize::MAX).iter().any(|e| e.id == attached.id));
+    errors.clear();
+    assert!(errors.list(usize::MAX).is_empty());
+  }
+}
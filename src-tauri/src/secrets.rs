@@ -1,9 +1,11 @@
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 use chacha20poly1305::{aead::Aead, KeyInit, XChaCha20Poly1305, XNonce};
 use getrandom::fill as getrandom_fill;
+use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 
 use crate::paths::Paths;
@@ -50,9 +52,89 @@ struct EncryptedPayload {
   v: u8,
   salt: String,
   nonce: String,
-  ciphertext: String
+  ciphertext: String,
+  /// Параметры Argon2id; с версии 2. В версии 1 всегда `KdfParams::LEGACY`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  kdf: Option<KdfParams>,
+  /// Подсказка к паролю хранится открытым текстом, чтобы показать ее до разблокировки.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  hint: Option<String>,
+  /// Аппаратный ключ FIDO2 как второй фактор: ключ шифрования выводится из
+  /// пароля вместе с ответом hmac-secret, и без касания ключа пароля мало.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  fido2: Option<Fido2Slot>
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+struct Fido2Slot {
+  rp_id: String,
+  credential_id: String,
+  hmac_salt: String
+}
+
+/// Привязанный аппаратный ключ вместе с его ответом, полученным при разблокировке.
+struct Fido2Factor {
+  slot: Fido2Slot,
+  secret: [u8; 32]
+}
+
+const FIDO2_RP_ID: &str = "cloudtg.local";
+
+const MAX_HINT_CHARS: usize = 200;
+const PAYLOAD_VERSION: u8 = 2;
+
+/// Параметры Argon2id: память в КиБ, число проходов и потоков.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+  pub m_cost: u32,
+  pub t_cost: u32,
+  pub p_cost: u32
+}
+
+impl KdfParams {
+  /// `Argon2::default()`, которым шифровались ключи версии 1.
+  pub const LEGACY: KdfParams = KdfParams { m_cost: 19_456, t_cost: 2, p_cost: 1 };
+
+  fn work(&self) -> u64 {
+    self.m_cost as u64 * self.t_cost as u64
+  }
+
+  /// Заметно слабее целевых параметров; небольшой разброс калибровки не в счет.
+  fn below(&self, target: &KdfParams) -> bool {
+    self.work() * 2 <= target.work()
+  }
+}
+
+const KDF_TARGET_TIME: Duration = Duration::from_millis(250);
+const KDF_MAX_M_COST: u32 = 256 * 1024;
+const KDF_MAX_T_COST: u32 = 8;
+/// Верхние границы для параметров из файла, чтобы испорченный заголовок не съел всю память.
+const KDF_LIMIT_M_COST: u32 = 1024 * 1024;
+const KDF_LIMIT_T_COST: u32 = 64;
+
+static TARGET_KDF: OnceCell<KdfParams> = OnceCell::new();
+
+/// Оценка пароля в духе zxcvbn: 0 — подбирается мгновенно, 4 — надежный.
+#[derive(Clone, Debug, Serialize)]
+pub struct PasswordStrength {
+  pub score: u8,
+  /// Десятичный логарифм ожидаемого числа попыток перебора.
+  pub guesses_log10: f64,
+  pub warning: Option<String>,
+  pub suggestions: Vec<String>
+}
+
+const COMMON_PASSWORDS: &[&str] = &[
+  "password", "passw0rd", "123456", "12345678", "123456789", "1234567890", "111111", "000000",
+  "qwerty", "qwertyuiop", "asdfgh", "zxcvbn", "abc123", "iloveyou", "admin", "letmein", "welcome",
+  "monkey", "dragon", "master", "secret", "sunshine", "football", "login", "test", "cloudtg",
+  "telegram", "пароль", "йцукен", "qwe123", "1q2w3e4r", "1qaz2wsx"
+];
+
+const KEYBOARD_ROWS: &[&str] = &[
+  "1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm", "йцукенгшщзхъ", "фывапролджэ", "ячсмитьбю"
+];
+
 pub fn env_credentials() -> Option<TgCredentials> {
   let api_id = env_api_id();
   let api_hash = env_api_hash();
@@ -89,7 +171,7 @@ pub fn resolve_credentials(paths: &Paths, runtime: Option<&TgCredentials>) -> (O
     return (Some(creds.clone()), status);
   }
 
-  match keychain_get() {
+  match keychain_get(paths) {
     Ok(Some(creds)) => {
       status.available = true;
       status.source = Some(CredentialsSource::Keychain);
@@ -148,12 +230,23 @@ pub fn encrypted_exists(paths: &Paths) -> bool {
   encrypted_path(paths).exists()
 }
 
-pub fn encrypted_save(paths: &Paths, creds: &TgCredentials, password: &str) -> anyhow::Result<()> {
+/// Сохраняет ключи под паролем. Если к прежнему файлу был привязан аппаратный
+/// ключ, привязка сохраняется, и для записи нужно его коснуться.
+pub fn encrypted_save(paths: &Paths, creds: &TgCredentials, password: &str, hint: Option<&str>) -> anyhow::Result<()> {
   if password.trim().is_empty() {
     return Err(anyhow::anyhow!("Нужен пароль для шифрования"));
   }
-  let payload = encrypt_payload(creds, password)?;
+  let hint = normalize_hint(hint, password)?;
   let path = encrypted_path(paths);
+  let slot = std::fs::read(&path)
+    .ok()
+    .and_then(|data| serde_json::from_slice::<EncryptedPayload>(&data).ok())
+    .and_then(|sealed| sealed.fido2);
+  let factor = match slot {
+    Some(slot) => Some(touch(slot).map_err(|e| anyhow::anyhow!("{e}. Ключи под аппаратным ключом не перезаписаны"))?),
+    None => None
+  };
+  let payload = encrypt_payload(creds, password, hint, factor.as_ref())?;
   if let Some(parent) = path.parent() {
     std::fs::create_dir_all(parent)?;
   }
@@ -161,10 +254,299 @@ pub fn encrypted_save(paths: &Paths, creds: &TgCredentials, password: &str) -> a
   Ok(())
 }
 
+/// Расшифровывает ключи; с привязанным аппаратным ключом нужно еще и его
+/// касание. Если файл зашифрован параметрами слабее текущих целевых, он сразу
+/// перешифровывается тем же паролем; ошибка при этом не мешает разблокировке.
 pub fn encrypted_load(paths: &Paths, password: &str) -> anyhow::Result<TgCredentials> {
   let path = encrypted_path(paths);
   let data = std::fs::read(&path).with_context(|| "Не удалось прочитать зашифрованные ключи")?;
-  decrypt_payload(&data, password)
+  let (creds, sealed, factor) = open_payload(&data, password)?;
+  let current = sealed.kdf.unwrap_or(KdfParams::LEGACY);
+  let target = target_kdf();
+  if sealed.v < PAYLOAD_VERSION || current.below(&target) {
+    let upgraded = encrypt_payload_with(&creds, password, sealed.hint, target, factor.as_ref())
+      .and_then(|bytes| write_atomic(&path, &bytes));
+    match upgraded {
+      Ok(()) => tracing::info!(
+        event = "secrets_kdf_upgraded",
+        from_version = sealed.v,
+        m_cost = target.m_cost,
+        t_cost = target.t_cost,
+        "Зашифрованные ключи перешифрованы с усиленными параметрами"
+      ),
+      Err(e) => tracing::warn!(event = "secrets_kdf_upgrade_failed", error = %e, "Не удалось перешифровать ключи")
+    }
+  }
+  Ok(creds)
+}
+
+/// Меняет пароль зашифрованных ключей. Старая подсказка относится к старому
+/// паролю, поэтому сохраняется только новая.
+pub fn encrypted_rotate(paths: &Paths, old_password: &str, new_password: &str, new_hint: Option<&str>) -> anyhow::Result<()> {
+  if new_password.trim().is_empty() {
+    return Err(anyhow::anyhow!("Нужен новый пароль"));
+  }
+  if old_password == new_password {
+    return Err(anyhow::anyhow!("Новый пароль совпадает со старым"));
+  }
+  let path = encrypted_path(paths);
+  let data = std::fs::read(&path).with_context(|| "Не удалось прочитать зашифрованные ключи")?;
+  let (creds, _, factor) = open_payload(&data, old_password)?;
+  let hint = normalize_hint(new_hint, new_password)?;
+  // Аппаратный ключ остается вторым фактором и для нового пароля.
+  let payload = encrypt_payload_with(&creds, new_password, hint, target_kdf(), factor.as_ref())?;
+  write_atomic(&path, &payload)?;
+  tracing::info!(event = "secrets_rotated", "Пароль зашифрованных ключей изменен");
+  Ok(())
+}
+
+/// Есть ли в сборке поддержка FIDO2 и доступны ли утилиты libfido2.
+pub fn fido2_supported() -> bool {
+  fido2::tools_available()
+}
+
+pub fn fido2_enrolled(paths: &Paths) -> bool {
+  std::fs::read(encrypted_path(paths))
+    .ok()
+    .and_then(|data| serde_json::from_slice::<EncryptedPayload>(&data).ok())
+    .map(|sealed| sealed.fido2.is_some())
+    .unwrap_or(false)
+}
+
+/// Привязывает аппаратный ключ вторым фактором: создает на нем учетные данные
+/// с hmac-secret и перешифровывает ключи паролем вместе с его ответом. Нужны
+/// пароль и два касания нового ключа (регистрация и проверка); прежний
+/// привязанный ключ тоже нужно коснуться.
+pub fn fido2_enroll(paths: &Paths, password: &str) -> anyhow::Result<()> {
+  let path = encrypted_path(paths);
+  let data = std::fs::read(&path).with_context(|| "Не удалось прочитать зашифрованные ключи")?;
+  let (creds, sealed, _) = open_payload(&data, password)?;
+
+  let device = fido2::device()?;
+  let credential_id = fido2::make_credential(&device, FIDO2_RP_ID)?;
+  let mut hmac_salt = [0u8; 32];
+  getrandom_fill(&mut hmac_salt).map_err(|e| anyhow::anyhow!("Не удалось получить случайные байты: {e}"))?;
+  let secret = fido2::hmac_secret(&device, FIDO2_RP_ID, &credential_id, &hmac_salt)?;
+  let factor = Fido2Factor {
+    slot: Fido2Slot {
+      rp_id: FIDO2_RP_ID.to_string(),
+      credential_id: BASE64.encode(credential_id),
+      hmac_salt: BASE64.encode(hmac_salt)
+    },
+    secret
+  };
+  let kdf = sealed.kdf.unwrap_or_else(target_kdf);
+  write_atomic(&path, &encrypt_payload_with(&creds, password, sealed.hint, kdf, Some(&factor))?)?;
+  tracing::info!(event = "secrets_fido2_enrolled", "Аппаратный ключ привязан к зашифрованным ключам");
+  Ok(())
+}
+
+/// Отвязывает аппаратный ключ: нужны пароль и последнее касание ключа,
+/// после чего ключи снова открываются одним паролем.
+pub fn fido2_remove(paths: &Paths, password: &str) -> anyhow::Result<()> {
+  let path = encrypted_path(paths);
+  let data = std::fs::read(&path).with_context(|| "Не удалось прочитать зашифрованные ключи")?;
+  let (creds, sealed, factor) = open_payload(&data, password)?;
+  if factor.is_none() {
+    return Ok(());
+  }
+  let kdf = sealed.kdf.unwrap_or_else(target_kdf);
+  write_atomic(&path, &encrypt_payload_with(&creds, password, sealed.hint, kdf, None)?)?;
+  tracing::info!(event = "secrets_fido2_removed", "Аппаратный ключ отвязан от зашифрованных ключей");
+  Ok(())
+}
+
+/// Получает ответ hmac-secret от привязанного аппаратного ключа.
+fn touch(slot: Fido2Slot) -> anyhow::Result<Fido2Factor> {
+  let decode = |v: &str| BASE64.decode(v.as_bytes()).map_err(|_| anyhow::anyhow!("Поврежденные данные аппаратного ключа"));
+  let credential_id = decode(&slot.credential_id)?;
+  let hmac_salt = decode(&slot.hmac_salt)?;
+  let secret = fido2::device()
+    .and_then(|device| fido2::hmac_secret(&device, &slot.rp_id, &credential_id, &hmac_salt))
+    .map_err(|e| anyhow::anyhow!("Нужно касание привязанного аппаратного ключа: {e}"))?;
+  Ok(Fido2Factor { slot, secret })
+}
+
+/// Ключ шифрования со вторым фактором: ни пароль, ни ответ аппаратного ключа
+/// по отдельности его не дают.
+fn combine_keys(password_key: &[u8; 32], secret: &[u8; 32]) -> [u8; 32] {
+  use sha2::{Digest, Sha256};
+  let mut hasher = Sha256::new();
+  hasher.update(b"cloudtg-fido2-v1");
+  hasher.update(password_key);
+  hasher.update(secret);
+  hasher.finalize().into()
+}
+
+/// Параметры Argon2id, которые на этой машине дают примерно `KDF_TARGET_TIME`.
+/// Калибруются при первом шифровании за запуск.
+pub fn target_kdf() -> KdfParams {
+  *TARGET_KDF.get_or_init(|| {
+    let params = calibrate_kdf(KDF_TARGET_TIME);
+    tracing::info!(event = "secrets_kdf_calibrated", m_cost = params.m_cost, t_cost = params.t_cost, "Параметры Argon2 откалиброваны");
+    params
+  })
+}
+
+/// Сначала наращивает память (до 256 МиБ), затем число проходов, пока
+/// вычисление ключа не займет `target`. Ниже `LEGACY` не опускается.
+fn calibrate_kdf(target: Duration) -> KdfParams {
+  let mut params = KdfParams::LEGACY;
+  let salt = [0u8; 16];
+  loop {
+    let started = Instant::now();
+    if derive_key("calibration", &salt, params).is_err() {
+      return params;
+    }
+    if started.elapsed() >= target {
+      return params;
+    }
+    if params.m_cost * 2 <= KDF_MAX_M_COST {
+      params.m_cost *= 2;
+    } else if params.t_cost < KDF_MAX_T_COST {
+      params.t_cost += 1;
+    } else {
+      return params;
+    }
+  }
+}
+
+fn derive_key(password: &str, salt: &[u8], params: KdfParams) -> anyhow::Result<[u8; 32]> {
+  if params.m_cost > KDF_LIMIT_M_COST || params.t_cost > KDF_LIMIT_T_COST || !(1..=16).contains(&params.p_cost) {
+    return Err(anyhow::anyhow!("Некорректные параметры шифрования в зашифрованных ключах"));
+  }
+  let argon_params = argon2::Params::new(params.m_cost, params.t_cost, params.p_cost, Some(32))
+    .map_err(|e| anyhow::anyhow!("Некорректные параметры шифрования: {e}"))?;
+  let mut key = [0u8; 32];
+  argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, argon_params)
+    .hash_password_into(password.as_bytes(), salt, &mut key)
+    .map_err(|e| anyhow::anyhow!("Не удалось создать ключ шифрования: {e}"))?;
+  Ok(key)
+}
+
+/// Подсказка к паролю зашифрованных ключей, если она была задана.
+pub fn encrypted_hint(paths: &Paths) -> Option<String> {
+  let data = std::fs::read(encrypted_path(paths)).ok()?;
+  serde_json::from_slice::<EncryptedPayload>(&data).ok()?.hint
+}
+
+fn normalize_hint(hint: Option<&str>, password: &str) -> anyhow::Result<Option<String>> {
+  let Some(hint) = hint.map(str::trim).filter(|h| !h.is_empty()) else {
+    return Ok(None);
+  };
+  if hint.chars().count() > MAX_HINT_CHARS {
+    return Err(anyhow::anyhow!("Подсказка слишком длинная (максимум {MAX_HINT_CHARS} символов)"));
+  }
+  let secret = password.trim().to_lowercase();
+  if !secret.is_empty() && hint.to_lowercase().contains(&secret) {
+    return Err(anyhow::anyhow!("Подсказка не должна содержать сам пароль"));
+  }
+  Ok(Some(hint.to_string()))
+}
+
+/// Оценивает пароль без внешних словарей: распространенные пароли, повторы,
+/// последовательности и соседние клавиши почти не добавляют стойкости.
+pub fn password_strength(password: &str) -> PasswordStrength {
+  let chars: Vec<char> = password.chars().collect();
+  let mut warning = None;
+  let mut suggestions = Vec::new();
+  if chars.is_empty() {
+    return PasswordStrength {
+      score: 0,
+      guesses_log10: 0.0,
+      warning: Some("Пароль пустой".into()),
+      suggestions: vec!["Используй не меньше 12 символов".into()]
+    };
+  }
+
+  let lower = password.to_lowercase();
+  let stem = lower.trim_end_matches(|c: char| c.is_ascii_digit() || c.is_ascii_punctuation());
+  let suffix_len = lower.chars().count() - stem.chars().count();
+  let guesses_log10 = if COMMON_PASSWORDS.contains(&lower.as_str()) {
+    warning = Some("Это один из самых распространенных паролей".into());
+    2.0
+  } else if !stem.is_empty() && COMMON_PASSWORDS.contains(&stem) {
+    warning = Some("Распространенное слово с цифрами в конце легко подобрать".into());
+    // Хвосты вроде 123 или ! перебираются первыми, поэтому цифра стоит меньше полной.
+    2.0 + suffix_len as f64 * 0.5
+  } else {
+    let lower_chars: Vec<char> = lower.chars().collect();
+    let mut effective = 1.0;
+    for pair in lower_chars.windows(2) {
+      effective += if pair[0] == pair[1] {
+        0.1
+      } else if is_sequential(pair[0], pair[1]) {
+        0.3
+      } else {
+        1.0
+      };
+    }
+    if effective < chars.len() as f64 * 0.6 {
+      warning = Some("Повторы и последовательности вроде aaa, 1234 или qwerty легко угадать".into());
+    }
+    effective * (charset_size(&chars) as f64).log10()
+  };
+
+  let score = match guesses_log10 {
+    g if g < 3.0 => 0,
+    g if g < 6.0 => 1,
+    g if g < 8.0 => 2,
+    g if g < 10.0 => 3,
+    _ => 4
+  };
+  if chars.len() < 12 {
+    suggestions.push("Используй не меньше 12 символов".into());
+  }
+  if char_classes(&chars) < 3 {
+    suggestions.push("Добавь цифры, заглавные буквы или символы".into());
+  }
+  if score < 3 {
+    suggestions.push("Фраза из нескольких несвязанных слов надежнее и легче запоминается".into());
+  }
+  PasswordStrength { score, guesses_log10, warning, suggestions }
+}
+
+fn is_sequential(a: char, b: char) -> bool {
+  if (a as i64 - b as i64).abs() == 1 {
+    return true;
+  }
+  KEYBOARD_ROWS.iter().any(|row| {
+    let pos_a = row.chars().position(|c| c == a);
+    let pos_b = row.chars().position(|c| c == b);
+    matches!((pos_a, pos_b), (Some(x), Some(y)) if x.abs_diff(y) == 1)
+  })
+}
+
+fn char_classes(chars: &[char]) -> usize {
+  [
+    chars.iter().any(|c| c.is_lowercase()),
+    chars.iter().any(|c| c.is_uppercase()),
+    chars.iter().any(|c| c.is_ascii_digit()),
+    chars.iter().any(|c| !c.is_alphanumeric())
+  ]
+    .iter()
+    .filter(|present| **present)
+    .count()
+}
+
+fn charset_size(chars: &[char]) -> u32 {
+  let mut size = 0;
+  if chars.iter().any(|c| c.is_ascii_lowercase()) {
+    size += 26;
+  }
+  if chars.iter().any(|c| c.is_ascii_uppercase()) {
+    size += 26;
+  }
+  if chars.iter().any(|c| c.is_ascii_digit()) {
+    size += 10;
+  }
+  if chars.iter().any(|c| c.is_ascii() && !c.is_ascii_alphanumeric()) {
+    size += 33;
+  }
+  if chars.iter().any(|c| !c.is_ascii()) {
+    size += 66;
+  }
+  size.max(10)
 }
 
 pub fn encrypted_clear(paths: &Paths) -> anyhow::Result<()> {
@@ -175,8 +557,8 @@ pub fn encrypted_clear(paths: &Paths) -> anyhow::Result<()> {
   Ok(())
 }
 
-pub fn keychain_get() -> anyhow::Result<Option<TgCredentials>> {
-  let entry = keychain_entry()?;
+pub fn keychain_get(paths: &Paths) -> anyhow::Result<Option<TgCredentials>> {
+  let entry = keychain_entry(paths)?;
   match entry.get_password() {
     Ok(secret) => {
       let creds: TgCredentials = serde_json::from_str(&secret)
@@ -193,15 +575,15 @@ pub fn keychain_get() -> anyhow::Result<Option<TgCredentials>> {
   }
 }
 
-pub fn keychain_set(creds: &TgCredentials) -> anyhow::Result<()> {
-  let entry = keychain_entry()?;
+pub fn keychain_set(paths: &Paths, creds: &TgCredentials) -> anyhow::Result<()> {
+  let entry = keychain_entry(paths)?;
   let payload = serde_json::to_string(creds)?;
   entry.set_password(&payload).map_err(|e| anyhow::anyhow!("Не удалось сохранить ключи в системном хранилище: {e}"))?;
   Ok(())
 }
 
-pub fn keychain_clear() -> anyhow::Result<()> {
-  let entry = keychain_entry()?;
+pub fn keychain_clear(paths: &Paths) -> anyhow::Result<()> {
+  let entry = keychain_entry(paths)?;
   match entry.delete_credential() {
     Ok(_) => Ok(()),
     Err(err) => {
@@ -214,7 +596,12 @@ pub fn keychain_clear() -> anyhow::Result<()> {
   }
 }
 
-fn keychain_entry() -> anyhow::Result<keyring::Entry> {
+/// В переносном режиме системное хранилище недоступно: ключи остались бы
+/// на компьютере, с которого запускали приложение.
+fn keychain_entry(paths: &Paths) -> anyhow::Result<keyring::Entry> {
+  if paths.portable {
+    return Err(anyhow::anyhow!("В переносном режиме системное хранилище не используется"));
+  }
   #[allow(clippy::redundant_closure)]
   keyring::Entry::new(KEYCHAIN_SERVICE, KEYCHAIN_ACCOUNT)
     .map_err(|e| anyhow::anyhow!("Не удалось инициализировать системное хранилище: {e}"))
@@ -239,36 +626,108 @@ fn env_api_hash() -> Option<String> {
   if hash.is_empty() { None } else { Some(hash) }
 }
 
-fn encrypt_payload(creds: &TgCredentials, password: &str) -> anyhow::Result<Vec<u8>> {
+fn encrypt_payload(
+  creds: &TgCredentials,
+  password: &str,
+  hint: Option<String>,
+  fido2: Option<&Fido2Factor>
+) -> anyhow::Result<Vec<u8>> {
+  encrypt_payload_with(creds, password, hint, target_kdf(), fido2)
+}
+
+fn encrypt_payload_with(
+  creds: &TgCredentials,
+  password: &str,
+  hint: Option<String>,
+  kdf: KdfParams,
+  fido2: Option<&Fido2Factor>
+) -> anyhow::Result<Vec<u8>> {
   let payload = serde_json::to_vec(creds)?;
   let mut salt = [0u8; 16];
   getrandom_fill(&mut salt).map_err(|e| anyhow::anyhow!("Не удалось получить случайные байты: {e}"))?;
-  let mut key = [0u8; 32];
-  argon2::Argon2::default()
-    .hash_password_into(password.as_bytes(), &salt, &mut key)
-    .map_err(|e| anyhow::anyhow!("Не удалось создать ключ шифрования: {e}"))?;
-  let cipher = XChaCha20Poly1305::new((&key).into());
-  let mut nonce = [0u8; 24];
-  getrandom_fill(&mut nonce).map_err(|e| anyhow::anyhow!("Не удалось получить случайные байты: {e}"))?;
-  let ciphertext = cipher.encrypt(XNonce::from_slice(&nonce), payload.as_ref())
-    .map_err(|_| anyhow::anyhow!("Не удалось зашифровать ключи"))?;
+  let mut key = derive_key(password, &salt, kdf)?;
+  if let Some(factor) = fido2 {
+    key = combine_keys(&key, &factor.secret);
+  }
+  let (nonce, ciphertext) = seal_bytes(&key, &payload)?;
 
   let sealed = EncryptedPayload {
-    v: 1,
+    v: PAYLOAD_VERSION,
     salt: BASE64.encode(salt),
-    nonce: BASE64.encode(nonce),
-    ciphertext: BASE64.encode(ciphertext)
+    nonce,
+    ciphertext,
+    kdf: Some(kdf),
+    hint,
+    fido2: fido2.map(|factor| factor.slot.clone())
   };
 
   serde_json::to_vec(&sealed).map_err(|e| anyhow::anyhow!("Не удалось сериализовать ключи: {e}"))
 }
 
+/// Шифрует данные ключом; возвращает nonce и шифротекст в base64.
+fn seal_bytes(key: &[u8; 32], plain: &[u8]) -> anyhow::Result<(String, String)> {
+  let cipher = XChaCha20Poly1305::new(key.into());
+  let mut nonce = [0u8; 24];
+  getrandom_fill(&mut nonce).map_err(|e| anyhow::anyhow!("Не удалось получить случайные байты: {e}"))?;
+  let ciphertext = cipher.encrypt(XNonce::from_slice(&nonce), plain)
+    .map_err(|_| anyhow::anyhow!("Не удалось зашифровать ключи"))?;
+  Ok((BASE64.encode(nonce), BASE64.encode(ciphertext)))
+}
+
+#[cfg(test)]
 fn decrypt_payload(data: &[u8], password: &str) -> anyhow::Result<TgCredentials> {
+  open_payload(data, password).map(|(creds, _, _)| creds)
+}
+
+/// Открывает файл ключей; если привязан аппаратный ключ, сначала ждет его
+/// касания. Возвращает и второй фактор, чтобы перешифровка его сохранила.
+fn open_payload(data: &[u8], password: &str) -> anyhow::Result<(TgCredentials, EncryptedPayload, Option<Fido2Factor>)> {
   let sealed: EncryptedPayload = serde_json::from_slice(data)
     .map_err(|e| anyhow::anyhow!("Некорректный формат зашифрованных ключей: {e}"))?;
-  if sealed.v != 1 {
-    return Err(anyhow::anyhow!("Неподдерживаемая версия зашифрованных ключей"));
-  }
+  let factor = match sealed.fido2.clone() {
+    Some(slot) => Some(touch(slot)?),
+    None => None
+  };
+  let plain = open_sealed(&sealed, password, factor.as_ref().map(|f| &f.secret))?;
+  let creds: TgCredentials = serde_json::from_slice(&plain)
+    .map_err(|e| anyhow::anyhow!("Некорректные данные ключей: {e}"))?;
+  Ok((creds, sealed, factor))
+}
+
+/// Шифрует произвольные данные паролем в том же формате, что и ключи API
+/// (Argon2id + XChaCha20-Poly1305). Используется для переносимых блобов,
+/// например бутстрапа второго устройства.
+pub fn seal_blob(plain: &[u8], password: &str) -> anyhow::Result<Vec<u8>> {
+  let kdf = target_kdf();
+  let mut salt = [0u8; 16];
+  getrandom_fill(&mut salt).map_err(|e| anyhow::anyhow!("Не удалось получить случайные байты: {e}"))?;
+  let key = derive_key(password, &salt, kdf)?;
+  let (nonce, ciphertext) = seal_bytes(&key, plain)?;
+  let sealed = EncryptedPayload {
+    v: PAYLOAD_VERSION,
+    salt: BASE64.encode(salt),
+    nonce,
+    ciphertext,
+    kdf: Some(kdf),
+    hint: None,
+    fido2: None
+  };
+  serde_json::to_vec(&sealed).map_err(|e| anyhow::anyhow!("Не удалось сериализовать зашифрованные данные: {e}"))
+}
+
+pub fn open_blob(data: &[u8], password: &str) -> anyhow::Result<Vec<u8>> {
+  let sealed: EncryptedPayload = serde_json::from_slice(data)
+    .map_err(|e| anyhow::anyhow!("Некорректный формат зашифрованных данных: {e}"))?;
+  open_sealed(&sealed, password, None)
+}
+
+fn open_sealed(sealed: &EncryptedPayload, password: &str, fido2_secret: Option<&[u8; 32]>) -> anyhow::Result<Vec<u8>> {
+  let kdf = match (sealed.v, sealed.kdf) {
+    (1, _) => KdfParams::LEGACY,
+    (2, Some(kdf)) => kdf,
+    (2, None) => return Err(anyhow::anyhow!("В зашифрованных ключах нет параметров шифрования")),
+    _ => return Err(anyhow::anyhow!("Неподдерживаемая версия зашифрованных ключей"))
+  };
 
   let salt = BASE64.decode(sealed.salt.as_bytes())
     .map_err(|_| anyhow::anyhow!("Некорректная соль в зашифрованных ключах"))?;
@@ -280,17 +739,15 @@ fn decrypt_payload(data: &[u8], password: &str) -> anyhow::Result<TgCredentials>
     return Err(anyhow::anyhow!("Некорректная длина nonce в зашифрованных ключах"));
   }
 
-  let mut key = [0u8; 32];
-  argon2::Argon2::default()
-    .hash_password_into(password.as_bytes(), &salt, &mut key)
-    .map_err(|e| anyhow::anyhow!("Не удалось создать ключ шифрования: {e}"))?;
+  let key = match (&sealed.fido2, fido2_secret) {
+    (Some(_), Some(secret)) => combine_keys(&derive_key(password, &salt, kdf)?, secret),
+    (Some(_), None) => return Err(anyhow::anyhow!("Для этих данных нужен аппаратный ключ")),
+    (None, _) => derive_key(password, &salt, kdf)?
+  };
 
   let cipher = XChaCha20Poly1305::new((&key).into());
-  let plain = cipher.decrypt(XNonce::from_slice(&nonce), ciphertext.as_ref())
-    .map_err(|_| anyhow::anyhow!("Неверный пароль или поврежденные данные"))?;
-  let creds: TgCredentials = serde_json::from_slice(&plain)
-    .map_err(|e| anyhow::anyhow!("Некорректные данные ключей: {e}"))?;
-  Ok(creds)
+  cipher.decrypt(XNonce::from_slice(&nonce), ciphertext.as_ref())
+    .map_err(|_| anyhow::anyhow!("Неверный пароль или поврежденные данные"))
 }
 
 fn write_atomic(path: &Path, data: &[u8]) -> anyhow::Result<()> {
@@ -305,6 +762,107 @@ fn write_atomic(path: &Path, data: &[u8]) -> anyhow::Result<()> {
   Ok(())
 }
 
+#[cfg(feature = "fido2")]
+mod fido2 {
+  use std::io::Write;
+  use std::process::{Command, Stdio};
+
+  use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+
+  /// Работает через утилиты libfido2 (`fido2-token`, `fido2-cred`, `fido2-assert`).
+  /// Устройство можно задать через CLOUDTG_FIDO2_DEVICE, иначе берется первое найденное.
+  pub fn tools_available() -> bool {
+    Command::new("fido2-token").arg("-V").output().map(|o| o.status.success()).unwrap_or(false)
+  }
+
+  pub fn device() -> anyhow::Result<String> {
+    if let Ok(dev) = std::env::var("CLOUDTG_FIDO2_DEVICE") {
+      return Ok(dev);
+    }
+    let output = Command::new("fido2-token")
+      .arg("-L")
+      .output()
+      .map_err(|e| anyhow::anyhow!("Не удалось запустить fido2-token: {e}"))?;
+    String::from_utf8_lossy(&output.stdout)
+      .lines()
+      .find_map(|line| line.split_once(": ").map(|(dev, _)| dev.trim().to_string()))
+      .filter(|dev| !dev.is_empty())
+      .ok_or_else(|| anyhow::anyhow!("Аппаратный ключ не найден. Подключи ключ и повтори"))
+  }
+
+  pub fn make_credential(device: &str, rp_id: &str) -> anyhow::Result<Vec<u8>> {
+    let input = format!("{}\n{rp_id}\ncloudtg\n{}\n", client_data_hash()?, BASE64.encode(random_bytes::<32>()?));
+    let lines = run("fido2-cred", &["-M", "-h", device], &input)?;
+    // Вывод: хэш, rp_id, формат, authdata, credential id, подпись, сертификат.
+    let id = lines.get(4).ok_or_else(|| anyhow::anyhow!("fido2-cred вернул неполный ответ"))?;
+    BASE64.decode(id.as_bytes()).map_err(|_| anyhow::anyhow!("fido2-cred вернул некорректный credential id"))
+  }
+
+  pub fn hmac_secret(device: &str, rp_id: &str, credential_id: &[u8], salt: &[u8]) -> anyhow::Result<[u8; 32]> {
+    let input = format!(
+      "{}\n{rp_id}\n{}\n{}\n",
+      client_data_hash()?,
+      BASE64.encode(credential_id),
+      BASE64.encode(salt)
+    );
+    let lines = run("fido2-assert", &["-G", "-h", device], &input)?;
+    // hmac-secret идет последней строкой ответа.
+    let secret = lines
+      .last()
+      .and_then(|line| BASE64.decode(line.as_bytes()).ok())
+      .ok_or_else(|| anyhow::anyhow!("Аппаратный ключ не вернул hmac-secret"))?;
+    secret.try_into().map_err(|_| anyhow::anyhow!("Аппаратный ключ вернул hmac-secret неожиданной длины"))
+  }
+
+  fn client_data_hash() -> anyhow::Result<String> {
+    Ok(BASE64.encode(random_bytes::<32>()?))
+  }
+
+  fn random_bytes<const N: usize>() -> anyhow::Result<[u8; N]> {
+    let mut out = [0u8; N];
+    getrandom::fill(&mut out).map_err(|e| anyhow::anyhow!("Не удалось получить случайные байты: {e}"))?;
+    Ok(out)
+  }
+
+  fn run(bin: &str, args: &[&str], input: &str) -> anyhow::Result<Vec<String>> {
+    let mut child = Command::new(bin)
+      .args(args)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn()
+      .map_err(|e| anyhow::anyhow!("Не удалось запустить {bin}: {e}"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+      stdin.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+      let stderr = String::from_utf8_lossy(&output.stderr);
+      return Err(anyhow::anyhow!("{bin} завершился с ошибкой: {}", stderr.trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).lines().map(|l| l.trim().to_string()).filter(|l| !l.is_empty()).collect())
+  }
+}
+
+#[cfg(not(feature = "fido2"))]
+mod fido2 {
+  pub fn tools_available() -> bool {
+    false
+  }
+
+  pub fn device() -> anyhow::Result<String> {
+    Err(anyhow::anyhow!("Аппаратные ключи FIDO2 не поддерживаются в этой сборке"))
+  }
+
+  pub fn make_credential(_device: &str, _rp_id: &str) -> anyhow::Result<Vec<u8>> {
+    device().map(|_| Vec::new())
+  }
+
+  pub fn hmac_secret(_device: &str, _rp_id: &str, _credential_id: &[u8], _salt: &[u8]) -> anyhow::Result<[u8; 32]> {
+    device().map(|_| [0u8; 32])
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -331,7 +889,7 @@ mod tests {
   #[test]
   fn encrypt_and_decrypt_roundtrip() {
     let creds = test_creds();
-    let encrypted = encrypt_payload(&creds, "pass123").expect("encrypt");
+    let encrypted = encrypt_payload(&creds, "pass123", None, None).expect("encrypt");
     let decrypted = decrypt_payload(&encrypted, "pass123").expect("decrypt");
     assert_eq!(decrypted.api_id, creds.api_id);
     assert_eq!(decrypted.api_hash, creds.api_hash);
@@ -340,7 +898,7 @@ mod tests {
   #[test]
   fn decrypt_fails_with_wrong_password() {
     let creds = test_creds();
-    let encrypted = encrypt_payload(&creds, "correct").expect("encrypt");
+    let encrypted = encrypt_payload(&creds, "correct", None, None).expect("encrypt");
     let err = decrypt_payload(&encrypted, "wrong").expect_err("must fail");
     assert!(err.to_string().contains("Неверный пароль"));
   }
@@ -348,7 +906,7 @@ mod tests {
   #[test]
   fn decrypt_rejects_unsupported_version() {
     let payload = serde_json::json!({
-      "v": 2,
+      "v": 3,
       "salt": BASE64.encode([1u8; 16]),
       "nonce": BASE64.encode([1u8; 24]),
       "ciphertext": BASE64.encode([1u8; 16])
@@ -371,14 +929,39 @@ mod tests {
     assert!(err.to_string().contains("Некорректная длина nonce"));
   }
 
+  #[test]
+  fn legacy_payload_is_upgraded_on_unlock() {
+    let tmp = tempdir().expect("tempdir");
+    let paths = Paths::from_base(tmp.path().to_path_buf());
+    let creds = test_creds();
+    let mut legacy: serde_json::Value =
+      serde_json::from_slice(&encrypt_payload_with(&creds, "pass", Some("подсказка".into()), KdfParams::LEGACY, None).expect("encrypt"))
+        .expect("json");
+    legacy["v"] = 1.into();
+    legacy.as_object_mut().expect("object").remove("kdf");
+    let path = encrypted_path(&paths);
+    std::fs::create_dir_all(path.parent().expect("parent")).expect("mkdir");
+    std::fs::write(&path, serde_json::to_vec(&legacy).expect("to vec")).expect("write");
+
+    let loaded = encrypted_load(&paths, "pass").expect("load");
+    assert_eq!(loaded.api_hash, creds.api_hash);
+
+    let upgraded: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).expect("read")).expect("json");
+    assert_eq!(upgraded["v"], 2);
+    assert!(upgraded["kdf"]["m_cost"].as_u64().expect("m_cost") >= KdfParams::LEGACY.m_cost as u64);
+    assert_eq!(upgraded["hint"], "подсказка");
+    assert_eq!(encrypted_load(&paths, "pass").expect("reload").api_id, creds.api_id);
+  }
+
   #[test]
   fn encrypted_save_load_and_clear_roundtrip() {
     let tmp = tempdir().expect("tempdir");
     let paths = Paths::from_base(tmp.path().to_path_buf());
     let creds = test_creds();
 
-    encrypted_save(&paths, &creds, "test-pass").expect("save");
+    encrypted_save(&paths, &creds, "test-pass", Some("  любимая книга  ")).expect("save");
     assert!(encrypted_exists(&paths));
+    assert_eq!(encrypted_hint(&paths).as_deref(), Some("любимая книга"));
 
     let loaded = encrypted_load(&paths, "test-pass").expect("load");
     assert_eq!(loaded.api_id, creds.api_id);
@@ -388,15 +971,102 @@ mod tests {
     assert!(!encrypted_exists(&paths));
   }
 
+  #[test]
+  fn encrypted_rotate_reencrypts_with_new_password() {
+    let tmp = tempdir().expect("tempdir");
+    let paths = Paths::from_base(tmp.path().to_path_buf());
+    let creds = test_creds();
+    encrypted_save(&paths, &creds, "old-pass", Some("старая")).expect("save");
+
+    let err = encrypted_rotate(&paths, "wrong", "new-pass", None).expect_err("must fail");
+    assert!(err.to_string().contains("Неверный пароль"));
+
+    encrypted_rotate(&paths, "old-pass", "new-pass", None).expect("rotate");
+    assert!(encrypted_load(&paths, "old-pass").is_err());
+    assert_eq!(encrypted_load(&paths, "new-pass").expect("load").api_hash, creds.api_hash);
+    assert_eq!(encrypted_hint(&paths), None);
+  }
+
+  #[test]
+  fn fido2_factor_is_required_on_top_of_password() {
+    let creds = test_creds();
+    let factor = Fido2Factor {
+      slot: Fido2Slot {
+        rp_id: FIDO2_RP_ID.to_string(),
+        credential_id: BASE64.encode([7u8; 16]),
+        hmac_salt: BASE64.encode([1u8; 32])
+      },
+      secret: [5u8; 32]
+    };
+    let bytes = encrypt_payload_with(&creds, "pass", None, KdfParams::LEGACY, Some(&factor)).expect("encrypt");
+    let sealed: EncryptedPayload = serde_json::from_slice(&bytes).expect("parse");
+    assert!(sealed.fido2.is_some());
+
+    let plain = open_sealed(&sealed, "pass", Some(&factor.secret)).expect("open");
+    assert_eq!(serde_json::from_slice::<TgCredentials>(&plain).expect("creds").api_id, creds.api_id);
+    // Ни пароль без касания, ни касание с чужим паролем ключи не открывают.
+    assert!(open_sealed(&sealed, "pass", None).expect_err("must fail").to_string().contains("аппаратный ключ"));
+    assert!(open_sealed(&sealed, "pass", Some(&[6u8; 32])).is_err());
+    assert!(open_sealed(&sealed, "wrong", Some(&factor.secret)).is_err());
+  }
+
+  #[test]
+  fn enrolled_file_is_not_opened_without_the_key() {
+    let tmp = tempdir().expect("tempdir");
+    let paths = Paths::from_base(tmp.path().to_path_buf());
+    let path = encrypted_path(&paths);
+    std::fs::create_dir_all(path.parent().expect("parent")).expect("mkdir");
+    let factor = Fido2Factor {
+      slot: Fido2Slot {
+        rp_id: FIDO2_RP_ID.to_string(),
+        credential_id: BASE64.encode([7u8; 16]),
+        hmac_salt: BASE64.encode([1u8; 32])
+      },
+      secret: [5u8; 32]
+    };
+    let bytes = encrypt_payload_with(&test_creds(), "pass", None, KdfParams::LEGACY, Some(&factor)).expect("encrypt");
+    write_atomic(&path, &bytes).expect("write");
+    assert!(fido2_enrolled(&paths));
+
+    // В тестовой сборке ключа нет: пароля мало, файл не меняется.
+    assert!(encrypted_load(&paths, "pass").is_err());
+    assert!(encrypted_rotate(&paths, "pass", "new-pass", None).is_err());
+    assert!(fido2_remove(&paths, "pass").is_err());
+    assert!(encrypted_save(&paths, &test_creds(), "other", None).is_err());
+    assert_eq!(std::fs::read(&path).expect("read"), bytes);
+  }
+
   #[test]
   fn encrypted_save_requires_non_empty_password() {
     let tmp = tempdir().expect("tempdir");
     let paths = Paths::from_base(tmp.path().to_path_buf());
     let creds = test_creds();
-    let err = encrypted_save(&paths, &creds, "   ").expect_err("must fail");
+    let err = encrypted_save(&paths, &creds, "   ", None).expect_err("must fail");
     assert!(err.to_string().contains("Нужен пароль"));
   }
 
+  #[test]
+  fn encrypted_save_rejects_hint_with_password() {
+    let tmp = tempdir().expect("tempdir");
+    let paths = Paths::from_base(tmp.path().to_path_buf());
+    let err = encrypted_save(&paths, &test_creds(), "Secret42", Some("это secret42")).expect_err("must fail");
+    assert!(err.to_string().contains("Подсказка"));
+    assert!(!encrypted_exists(&paths));
+  }
+
+  #[test]
+  fn password_strength_penalizes_common_and_patterned_passwords() {
+    assert_eq!(password_strength("").score, 0);
+    assert_eq!(password_strength("password").score, 0);
+    assert!(password_strength("qwerty123").score <= 1);
+    assert!(password_strength("aaaaaaaaaaaa").score <= 1);
+    assert!(password_strength("abcdefgh").warning.is_some());
+
+    let strong = password_strength("Сова-пьет-42-кофе-Zebra");
+    assert_eq!(strong.score, 4);
+    assert!(strong.warning.is_none());
+  }
+
   #[test]
   fn tdlib_db_encryption_key_creates_and_reuses_existing_key() {
     let tmp = tempdir().expect("tempdir");
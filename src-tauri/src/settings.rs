@@ -1,6 +1,20 @@
 use crate::sqlx::{self, Row};
 use sqlx_sqlite::SqlitePool;
 
+use crate::app::auto_sync::AutoSyncConfig;
+use crate::app::backup::BackupRetention;
+use crate::app::file_actions::FileActions;
+use crate::app::ignore_list::IgnoreList;
+use crate::app::local_cache::CacheLimit;
+use crate::app::local_names::LocalNameRules;
+use crate::app::maintenance::MaintenanceWindow;
+use crate::app::open_guard::OpenGuard;
+use crate::app::system_dirs::SystemDirNames;
+use crate::app::download_queue;
+use crate::app::virus_scan::ScannerConfig;
+use crate::app::transcripts::TranscriptSource;
+use crate::telegram::timeouts::{TimeoutPreset, TimeoutProfile};
+
 pub async fn get_tdlib_path(pool: &SqlitePool) -> anyhow::Result<Option<String>> {
   get_value(pool, "tdlib_path").await
 }
@@ -14,6 +28,280 @@ pub async fn set_tdlib_path(pool: &SqlitePool, tdlib_path: Option<String>) -> an
   Ok(())
 }
 
+pub async fn get_search_index_enabled(pool: &SqlitePool) -> anyhow::Result<bool> {
+  get_flag(pool, "search_index_enabled").await
+}
+
+pub async fn set_search_index_enabled(pool: &SqlitePool, enabled: bool) -> anyhow::Result<()> {
+  set_flag(pool, "search_index_enabled", enabled).await
+}
+
+pub async fn get_vault_summary_enabled(pool: &SqlitePool) -> anyhow::Result<bool> {
+  get_flag(pool, "vault_summary_enabled").await
+}
+
+pub async fn set_vault_summary_enabled(pool: &SqlitePool, enabled: bool) -> anyhow::Result<()> {
+  set_flag(pool, "vault_summary_enabled", enabled).await
+}
+
+/// Канал хранения подключен только для чтения: CloudTG ничего в нем не
+/// пишет и не правит, а все выведенное при импорте хранит в своей базе.
+pub async fn get_read_only_channel(pool: &SqlitePool) -> anyhow::Result<bool> {
+  get_flag(pool, "read_only_channel").await
+}
+
+pub async fn set_read_only_channel(pool: &SqlitePool, enabled: bool) -> anyhow::Result<()> {
+  set_flag(pool, "read_only_channel", enabled).await
+}
+
+/// Новые файлы шифруются ключом хранилища перед отправкой.
+pub async fn get_vault_encryption(pool: &SqlitePool) -> anyhow::Result<bool> {
+  get_flag(pool, "vault_encryption").await
+}
+
+pub async fn set_vault_encryption(pool: &SqlitePool, enabled: bool) -> anyhow::Result<()> {
+  set_flag(pool, "vault_encryption", enabled).await
+}
+
+/// Ключ хранилища, зашифрованный паролем (формат `secrets::seal_blob`).
+pub async fn get_vault_key(pool: &SqlitePool) -> anyhow::Result<Option<String>> {
+  get_value(pool, "vault_key").await
+}
+
+pub async fn set_vault_key(pool: &SqlitePool, sealed: &str) -> anyhow::Result<()> {
+  set_value(pool, "vault_key", sealed).await
+}
+
+pub async fn get_status_page_enabled(pool: &SqlitePool) -> anyhow::Result<bool> {
+  get_flag(pool, "status_page_enabled").await
+}
+
+pub async fn set_status_page_enabled(pool: &SqlitePool, enabled: bool) -> anyhow::Result<()> {
+  set_flag(pool, "status_page_enabled", enabled).await
+}
+
+/// Активный профиль таймаутов TDLib. Для `custom` значения хранятся JSON-ом
+/// отдельно, чтобы переключение на пресет и обратно их не теряло.
+pub async fn get_tdlib_timeouts(pool: &SqlitePool) -> anyhow::Result<(TimeoutPreset, TimeoutProfile)> {
+  let preset = get_value(pool, "tdlib_timeout_preset")
+    .await?
+    .as_deref()
+    .and_then(TimeoutPreset::parse)
+    .unwrap_or(TimeoutPreset::Normal);
+  let custom = get_value(pool, "tdlib_timeout_custom")
+    .await?
+    .and_then(|raw| serde_json::from_str::<TimeoutProfile>(&raw).ok());
+  Ok((preset, TimeoutProfile::for_preset(preset, custom)))
+}
+
+pub async fn set_tdlib_timeouts(
+  pool: &SqlitePool,
+  preset: TimeoutPreset,
+  custom: Option<TimeoutProfile>
+) -> anyhow::Result<TimeoutProfile> {
+  match preset {
+    TimeoutPreset::Normal => clear_value(pool, "tdlib_timeout_preset").await?,
+    _ => set_value(pool, "tdlib_timeout_preset", preset.as_str()).await?
+  }
+  if let Some(custom) = custom {
+    let raw = serde_json::to_string(&custom.sanitized())?;
+    set_value(pool, "tdlib_timeout_custom", &raw).await?;
+  }
+  Ok(get_tdlib_timeouts(pool).await?.1)
+}
+
+pub async fn get_indexer_ignore_list(pool: &SqlitePool) -> anyhow::Result<IgnoreList> {
+  let list = get_value(pool, "indexer_ignore_list")
+    .await?
+    .and_then(|raw| serde_json::from_str::<IgnoreList>(&raw).ok())
+    .unwrap_or_default();
+  Ok(list)
+}
+
+pub async fn set_indexer_ignore_list(pool: &SqlitePool, list: IgnoreList) -> anyhow::Result<IgnoreList> {
+  let list = list.normalized();
+  if list == IgnoreList::default() {
+    clear_value(pool, "indexer_ignore_list").await?;
+  } else {
+    set_value(pool, "indexer_ignore_list", &serde_json::to_string(&list)?).await?;
+  }
+  Ok(list)
+}
+
+/// Автоматическая расшифровка новых голосовых: `None` — выключена.
+pub async fn get_transcription_mode(pool: &SqlitePool) -> anyhow::Result<Option<TranscriptSource>> {
+  Ok(get_value(pool, "transcription_mode").await?.as_deref().and_then(TranscriptSource::parse))
+}
+
+pub async fn set_transcription_mode(pool: &SqlitePool, mode: Option<TranscriptSource>) -> anyhow::Result<()> {
+  match mode {
+    Some(source) => set_value(pool, "transcription_mode", source.as_str()).await,
+    None => clear_value(pool, "transcription_mode").await
+  }
+}
+
+pub async fn get_maintenance_window(pool: &SqlitePool) -> anyhow::Result<MaintenanceWindow> {
+  let window = get_value(pool, "maintenance_window")
+    .await?
+    .and_then(|raw| serde_json::from_str::<MaintenanceWindow>(&raw).ok())
+    .unwrap_or_default();
+  Ok(window.sanitized())
+}
+
+pub async fn set_maintenance_window(pool: &SqlitePool, window: MaintenanceWindow) -> anyhow::Result<MaintenanceWindow> {
+  let window = window.sanitized();
+  set_value(pool, "maintenance_window", &serde_json::to_string(&window)?).await?;
+  Ok(window)
+}
+
+pub async fn get_auto_sync(pool: &SqlitePool) -> anyhow::Result<AutoSyncConfig> {
+  let config = get_value(pool, "auto_sync")
+    .await?
+    .and_then(|raw| serde_json::from_str::<AutoSyncConfig>(&raw).ok())
+    .unwrap_or_default();
+  Ok(config.sanitized())
+}
+
+pub async fn set_auto_sync(pool: &SqlitePool, config: AutoSyncConfig) -> anyhow::Result<AutoSyncConfig> {
+  let config = config.sanitized();
+  set_value(pool, "auto_sync", &serde_json::to_string(&config)?).await?;
+  Ok(config)
+}
+
+pub async fn get_cache_limit(pool: &SqlitePool) -> anyhow::Result<CacheLimit> {
+  let limit = get_value(pool, "cache_limit")
+    .await?
+    .and_then(|raw| serde_json::from_str::<CacheLimit>(&raw).ok())
+    .unwrap_or_default();
+  Ok(limit.sanitized())
+}
+
+pub async fn set_cache_limit(pool: &SqlitePool, limit: CacheLimit) -> anyhow::Result<CacheLimit> {
+  let limit = limit.sanitized();
+  set_value(pool, "cache_limit", &serde_json::to_string(&limit)?).await?;
+  Ok(limit)
+}
+
+pub async fn get_local_name_rules(pool: &SqlitePool) -> anyhow::Result<LocalNameRules> {
+  let rules = get_value(pool, "local_name_rules")
+    .await?
+    .and_then(|raw| serde_json::from_str::<LocalNameRules>(&raw).ok())
+    .unwrap_or_default();
+  Ok(rules.sanitized())
+}
+
+pub async fn set_local_name_rules(pool: &SqlitePool, rules: LocalNameRules) -> anyhow::Result<LocalNameRules> {
+  let rules = rules.sanitized();
+  set_value(pool, "local_name_rules", &serde_json::to_string(&rules)?).await?;
+  Ok(rules)
+}
+
+/// Сколько передач из очереди идут одновременно.
+pub async fn get_transfer_concurrency(pool: &SqlitePool) -> anyhow::Result<usize> {
+  Ok(
+    get_value(pool, "transfer_concurrency")
+      .await?
+      .and_then(|raw| raw.parse::<usize>().ok())
+      .map(|n| n.clamp(1, download_queue::MAX_CONCURRENCY))
+      .unwrap_or(download_queue::DEFAULT_CONCURRENCY)
+  )
+}
+
+pub async fn set_transfer_concurrency(pool: &SqlitePool, limit: usize) -> anyhow::Result<usize> {
+  let limit = limit.clamp(1, download_queue::MAX_CONCURRENCY);
+  set_value(pool, "transfer_concurrency", &limit.to_string()).await?;
+  Ok(limit)
+}
+
+pub async fn get_open_guard(pool: &SqlitePool) -> anyhow::Result<OpenGuard> {
+  let guard = get_value(pool, "open_guard")
+    .await?
+    .and_then(|raw| serde_json::from_str::<OpenGuard>(&raw).ok())
+    .unwrap_or_default();
+  Ok(guard.normalized())
+}
+
+pub async fn set_open_guard(pool: &SqlitePool, guard: OpenGuard) -> anyhow::Result<OpenGuard> {
+  let guard = guard.normalized();
+  set_value(pool, "open_guard", &serde_json::to_string(&guard)?).await?;
+  Ok(guard)
+}
+
+pub async fn get_file_actions(pool: &SqlitePool) -> anyhow::Result<FileActions> {
+  let actions = get_value(pool, "file_actions")
+    .await?
+    .and_then(|raw| serde_json::from_str::<FileActions>(&raw).ok())
+    .unwrap_or_default();
+  Ok(actions.normalized())
+}
+
+pub async fn set_file_actions(pool: &SqlitePool, actions: FileActions) -> anyhow::Result<FileActions> {
+  let actions = actions.normalized();
+  set_value(pool, "file_actions", &serde_json::to_string(&actions)?).await?;
+  Ok(actions)
+}
+
+pub async fn get_virus_scan(pool: &SqlitePool) -> anyhow::Result<ScannerConfig> {
+  let config = get_value(pool, "virus_scan")
+    .await?
+    .and_then(|raw| serde_json::from_str::<ScannerConfig>(&raw).ok())
+    .unwrap_or_default();
+  Ok(config.sanitized())
+}
+
+pub async fn set_virus_scan(pool: &SqlitePool, config: ScannerConfig) -> anyhow::Result<ScannerConfig> {
+  let config = config.sanitized();
+  set_value(pool, "virus_scan", &serde_json::to_string(&config)?).await?;
+  Ok(config)
+}
+
+pub async fn get_system_dir_names(pool: &SqlitePool) -> anyhow::Result<SystemDirNames> {
+  let names = get_value(pool, "system_dir_names")
+    .await?
+    .and_then(|raw| serde_json::from_str::<SystemDirNames>(&raw).ok())
+    .unwrap_or_default();
+  Ok(names.sanitized())
+}
+
+pub async fn set_system_dir_names(pool: &SqlitePool, names: SystemDirNames) -> anyhow::Result<SystemDirNames> {
+  let names = names.sanitized();
+  set_value(pool, "system_dir_names", &serde_json::to_string(&names)?).await?;
+  Ok(names)
+}
+
+pub async fn get_diagnostics_enabled(pool: &SqlitePool) -> anyhow::Result<bool> {
+  get_flag(pool, "diagnostics_enabled").await
+}
+
+pub async fn set_diagnostics_enabled(pool: &SqlitePool, enabled: bool) -> anyhow::Result<()> {
+  set_flag(pool, "diagnostics_enabled", enabled).await
+}
+
+pub async fn get_backup_retention(pool: &SqlitePool) -> anyhow::Result<BackupRetention> {
+  Ok(
+    get_value(pool, "backup_retention")
+      .await?
+      .and_then(|raw| serde_json::from_str::<BackupRetention>(&raw).ok())
+      .unwrap_or_default()
+  )
+}
+
+pub async fn set_backup_retention(pool: &SqlitePool, retention: BackupRetention) -> anyhow::Result<()> {
+  set_value(pool, "backup_retention", &serde_json::to_string(&retention)?).await
+}
+
+async fn get_flag(pool: &SqlitePool, key: &str) -> anyhow::Result<bool> {
+  Ok(get_value(pool, key).await?.as_deref() == Some("1"))
+}
+
+async fn set_flag(pool: &SqlitePool, key: &str, enabled: bool) -> anyhow::Result<()> {
+  if enabled {
+    set_value(pool, key, "1").await
+  } else {
+    clear_value(pool, key).await
+  }
+}
+
 async fn get_value(pool: &SqlitePool, key: &str) -> anyhow::Result<Option<String>> {
   let row = sqlx::query("SELECT value FROM sync_state WHERE key = ?")
     .bind(key)
@@ -0,0 +1,140 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+use tracing::Instrument;
+
+use crate::state::AuthState;
+
+/// Замеры запуска: сколько заняли открытие базы, миграции, загрузка TDLib и
+/// первое состояние авторизации. Отсчет идет от `mark_launch` в `main`.
+/// Второстепенная работа ждет `after_first_paint`, чтобы не отнимать время у
+/// первой отрисовки окна.
+const FIRST_PAINT_FALLBACK: Duration = Duration::from_secs(15);
+
+static ORIGIN: Lazy<Instant> = Lazy::new(Instant::now);
+static PHASES: Lazy<Mutex<Vec<StartupPhase>>> = Lazy::new(|| Mutex::new(Vec::new()));
+static FIRST_PAINT_MS: Lazy<Mutex<Option<u64>>> = Lazy::new(|| Mutex::new(None));
+static PAINTED: AtomicBool = AtomicBool::new(false);
+static PAINT: Lazy<Notify> = Lazy::new(Notify::new);
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct StartupPhase {
+  pub name: String,
+  /// От запуска процесса до начала этапа.
+  pub start_ms: u64,
+  pub duration_ms: u64,
+  pub ok: bool
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct StartupReport {
+  pub phases: Vec<StartupPhase>,
+  pub first_paint_ms: Option<u64>
+}
+
+/// Фиксирует момент запуска. Вызывается первой строкой `main`.
+pub fn mark_launch() {
+  Lazy::force(&ORIGIN);
+}
+
+fn since_launch(at: Instant) -> u64 {
+  at.saturating_duration_since(*ORIGIN).as_millis() as u64
+}
+
+/// Записывается только первый замер этапа: повторы (например, открытие базы
+/// при смене аккаунта) к запуску уже не относятся.
+fn record(name: &str, started: Instant, ok: bool) {
+  let phase = StartupPhase {
+    name: name.to_string(),
+    start_ms: since_launch(started),
+    duration_ms: started.elapsed().as_millis() as u64,
+    ok
+  };
+  let mut phases = PHASES.lock();
+  if phases.iter().any(|p| p.name == name) {
+    return;
+  }
+  tracing::info!(
+    event = "startup_phase",
+    phase = name,
+    start_ms = phase.start_ms,
+    duration_ms = phase.duration_ms,
+    ok = ok,
+    "Этап запуска завершен"
+  );
+  phases.push(phase);
+}
+
+pub async fn measure<T, E>(name: &str, fut: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+  let started = Instant::now();
+  let res = fut.instrument(tracing::info_span!("startup_phase", phase = name)).await;
+  record(name, started, res.is_ok());
+  res
+}
+
+pub fn measure_sync<T, E>(name: &str, f: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+  let started = Instant::now();
+  let res = tracing::info_span!("startup_phase", phase = name).in_scope(f);
+  record(name, started, res.is_ok());
+  res
+}
+
+/// Первое известное состояние авторизации: с этого момента UI знает, что
+/// показывать — вход или файлы.
+pub fn auth_resolved(state: &AuthState) {
+  if *state != AuthState::Unknown {
+    record("initial_auth", *ORIGIN, *state == AuthState::Ready);
+  }
+}
+
+/// Окно отрисовано; отложенная работа может начинаться.
+pub fn first_paint() {
+  if PAINTED.swap(true, Ordering::SeqCst) {
+    return;
+  }
+  let ms = since_launch(Instant::now());
+  *FIRST_PAINT_MS.lock() = Some(ms);
+  tracing::info!(event = "startup_first_paint", ms = ms, "Окно отрисовано");
+  PAINT.notify_waiters();
+}
+
+/// Ждет первой отрисовки окна, но не дольше `FIRST_PAINT_FALLBACK`: без
+/// окна (или если UI не сообщил) фоновая работа все равно начнется.
+pub async fn after_first_paint() {
+  let notified = PAINT.notified();
+  if PAINTED.load(Ordering::SeqCst) {
+    return;
+  }
+  let _ = tokio::time::timeout(FIRST_PAINT_FALLBACK, notified).await;
+}
+
+pub fn report() -> StartupReport {
+  StartupReport { phases: PHASES.lock().clone(), first_paint_ms: *FIRST_PAINT_MS.lock() }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn records_first_measure_and_releases_deferred_work() {
+    mark_launch();
+    let res: Result<u8, String> = measure("test_db_open", async { Ok(1) }).await;
+    assert_eq!(res, Ok(1));
+    let _: Result<(), String> = measure_sync("test_db_open", || Err("повтор".into()));
+    let phases: Vec<_> = report().phases.into_iter().filter(|p| p.name == "test_db_open").collect();
+    assert_eq!(phases.len(), 1);
+    assert!(phases[0].ok);
+
+    let waiter = tokio::spawn(after_first_paint());
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    first_paint();
+    tokio::time::timeout(Duration::from_secs(1), waiter).await.expect("deferred work released").unwrap();
+    assert!(report().first_paint_ms.is_some());
+    after_first_paint().await;
+  }
+}
@@ -4,24 +4,65 @@ use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
 use parking_lot::RwLock;
-use tauri::{AppHandle, Manager};
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock as AsyncRwLock};
 use ulid::Ulid;
 
-use crate::{paths::Paths, db::Db, telegram::{TelegramService, make_telegram_service}, secrets::{TgCredentials, CredentialsSource}};
+use crate::app::files::FileItem;
+use crate::app::search_index::SearchIndex;
+use crate::status_page::{self, StatusPageHandle};
+use crate::stream_server::{self, Available, StreamControl, StreamServerHandle};
+use crate::accounts::{self, DEFAULT_ACCOUNT};
+use crate::recent_errors::RecentErrors;
+use crate::{paths::Paths, db::Db, telegram::{ChatInfo, TelegramService, make_telegram_service}, secrets::{TgCredentials, CredentialsSource}};
 
 #[derive(Clone)]
 pub struct AppState {
   inner: Arc<RwLock<Inner>>,
+  /// Выбор канала хранения (и пересоздание при его потере) идет строго по одному.
+  storage_chat: Arc<tokio::sync::Mutex<StorageChatSlot>>,
+  /// Команды, которые пишут в базу или канал, держат блокировку на чтение;
+  /// смена аккаунта берет ее на запись и ждет, пока они завершатся.
+  session_gate: Arc<AsyncRwLock<()>>,
 }
 
 struct Inner {
+  /// Корень каталогов данных; `paths` ниже — каталоги активного аккаунта.
+  root_paths: Option<Paths>,
+  active_account: String,
   paths: Option<Paths>,
   db: Option<Db>,
   telegram: Option<Arc<dyn TelegramService>>,
   auth_state: AuthState,
+  /// Открытые, но не выбранные аккаунты. Их сессии TDLib остаются
+  /// авторизованными, и обратное переключение не ждет входа.
+  sessions: HashMap<String, AccountSession>,
+  /// Состояние авторизации неактивных аккаунтов, в том числе еще открывающихся.
+  account_auth: HashMap<String, AuthState>,
   tg_credentials: Option<TgCredentials>,
   tg_credentials_source: Option<CredentialsSource>,
-  upload_permits: HashMap<String, UploadPermit>
+  upload_permits: HashMap<String, UploadPermit>,
+  open_confirmations: HashMap<String, OpenConfirmation>,
+  listing_generation: u64,
+  listing_cache: HashMap<String, CachedListing>,
+  chat_cache: HashMap<String, CachedChats>,
+  search_index: Option<Arc<SearchIndex>>,
+  status_page: Option<StatusPageHandle>,
+  stream_server: Option<StreamServerHandle>,
+  /// Фоновые обработчики активного аккаунта; при переключении перезапускаются.
+  workers: Vec<JoinHandle<()>>,
+  /// Ошибки, показанные пользователю, по аккаунтам.
+  recent_errors: HashMap<String, Arc<RecentErrors>>
+}
+
+/// Держится командой на все время изменения данных активного аккаунта.
+pub type SessionGuard = OwnedRwLockReadGuard<()>;
+
+struct AccountSession {
+  paths: Paths,
+  db: Db,
+  telegram: Arc<dyn TelegramService>
 }
 
 struct UploadPermit {
@@ -29,6 +70,46 @@ struct UploadPermit {
   expires_at: Instant
 }
 
+struct OpenConfirmation {
+  file_id: String,
+  expires_at: Instant
+}
+
+struct CachedListing {
+  generation: u64,
+  items: Vec<FileItem>,
+  stored_at: Instant
+}
+
+struct CachedChats {
+  items: Vec<ChatInfo>,
+  stored_at: Instant
+}
+
+/// Канал хранения, уже проверенный в Telegram. Проверка — сетевой запрос,
+/// поэтому результат недолго переиспользуется, пока id в базе не сменился.
+#[derive(Default)]
+pub struct StorageChatSlot {
+  validated: Option<(i64, Instant)>
+}
+
+impl StorageChatSlot {
+  pub fn fresh(&self, current: Option<i64>) -> Option<i64> {
+    self
+      .validated
+      .filter(|(id, at)| Some(*id) == current && at.elapsed() < STORAGE_CHAT_TTL)
+      .map(|(id, _)| id)
+  }
+
+  pub fn store(&mut self, chat_id: i64) {
+    self.validated = Some((chat_id, Instant::now()));
+  }
+
+  pub fn forget(&mut self) {
+    self.validated = None;
+  }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
 pub enum AuthState {
   Unknown,
@@ -40,18 +121,47 @@ pub enum AuthState {
   Closed
 }
 
+impl AuthState {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      AuthState::Unknown => "unknown",
+      AuthState::WaitConfig => "wait_config",
+      AuthState::WaitPhone => "wait_phone",
+      AuthState::WaitCode => "wait_code",
+      AuthState::WaitPassword => "wait_password",
+      AuthState::Ready => "ready",
+      AuthState::Closed => "closed"
+    }
+  }
+}
+
 impl AppState {
   pub fn new() -> Self {
     Self {
       inner: Arc::new(RwLock::new(Inner {
+        root_paths: None,
+        active_account: DEFAULT_ACCOUNT.to_string(),
         paths: None,
         db: None,
         telegram: None,
         auth_state: AuthState::Unknown,
+        sessions: HashMap::new(),
+        account_auth: HashMap::new(),
         tg_credentials: None,
         tg_credentials_source: None,
-        upload_permits: HashMap::new()
-      }))
+        upload_permits: HashMap::new(),
+        open_confirmations: HashMap::new(),
+        listing_generation: 0,
+        listing_cache: HashMap::new(),
+        chat_cache: HashMap::new(),
+        search_index: None,
+        status_page: None,
+        stream_server: None,
+        workers: Vec::new(),
+        recent_errors: HashMap::new()
+      })),
+      storage_chat: Arc::new(tokio::sync::Mutex::new(StorageChatSlot::default())),
+      session_gate: Arc::new(AsyncRwLock::new(()))
     }
   }
 
@@ -60,9 +170,47 @@ impl AppState {
   }
 
   pub fn set_auth_state(&self, s: AuthState) {
+    crate::startup::auth_resolved(&s);
     self.inner.write().auth_state = s;
   }
 
+  pub fn active_account(&self) -> String {
+    self.inner.read().active_account.clone()
+  }
+
+  /// Последние ошибки активного аккаунта.
+  pub fn recent_errors(&self) -> Arc<RecentErrors> {
+    let mut w = self.inner.write();
+    let account = w.active_account.clone();
+    w.recent_errors.entry(account).or_default().clone()
+  }
+
+  /// Состояние авторизации от сессии аккаунта. Возвращает `true`, если
+  /// аккаунт активен и состояние нужно показать в интерфейсе.
+  pub fn set_account_auth_state(&self, account: &str, s: AuthState) -> bool {
+    let mut inner = self.inner.write();
+    if inner.active_account == account {
+      crate::startup::auth_resolved(&s);
+      inner.auth_state = s;
+      return true;
+    }
+    inner.account_auth.insert(account.to_string(), s);
+    false
+  }
+
+  /// `None`, если сессия аккаунта в этом запуске еще не открывалась.
+  pub fn account_auth_state(&self, account: &str) -> Option<AuthState> {
+    let inner = self.inner.read();
+    if inner.active_account == account {
+      return Some(inner.auth_state.clone());
+    }
+    inner.account_auth.get(account).cloned()
+  }
+
+  pub fn root_paths(&self) -> anyhow::Result<Paths> {
+    self.inner.read().root_paths.clone().ok_or_else(|| anyhow::anyhow!("Пути еще не инициализированы"))
+  }
+
   pub fn db(&self) -> anyhow::Result<Db> {
     self.inner.read().db.clone().ok_or_else(|| anyhow::anyhow!("База данных еще не инициализирована"))
   }
@@ -129,6 +277,191 @@ impl AppState {
     inner.upload_permits.remove(token).map(|permit| permit.path)
   }
 
+  /// Одноразовый токен подтверждения запуска исполняемого файла.
+  pub fn issue_open_confirmation(&self, file_id: &str) -> String {
+    let mut inner = self.inner.write();
+    let now = Instant::now();
+    inner.open_confirmations.retain(|_, c| c.expires_at > now);
+    let token = Ulid::new().to_string();
+    inner.open_confirmations.insert(
+      token.clone(),
+      OpenConfirmation {
+        file_id: file_id.to_string(),
+        expires_at: now + Duration::from_secs(2 * 60)
+      }
+    );
+    token
+  }
+
+  /// Токен годится только для того файла, для которого выдан.
+  pub fn consume_open_confirmation(&self, token: &str, file_id: &str) -> bool {
+    let mut inner = self.inner.write();
+    let now = Instant::now();
+    inner.open_confirmations.retain(|_, c| c.expires_at > now);
+    match inner.open_confirmations.remove(token) {
+      Some(confirmation) => confirmation.file_id == file_id,
+      None => false
+    }
+  }
+
+  /// Текущее поколение кеша списков файлов. Читать до запроса к БД, чтобы
+  /// не сохранить результат, устаревший из-за параллельной мутации.
+  pub fn listing_generation(&self) -> u64 {
+    self.inner.read().listing_generation
+  }
+
+  pub fn cached_listing(&self, dir_id: &str) -> Option<Vec<FileItem>> {
+    let inner = self.inner.read();
+    inner
+      .listing_cache
+      .get(dir_id)
+      .filter(|cached| cached.generation == inner.listing_generation)
+      .map(|cached| cached.items.clone())
+  }
+
+  pub fn store_listing(&self, dir_id: &str, generation: u64, items: Vec<FileItem>) {
+    let mut inner = self.inner.write();
+    if generation != inner.listing_generation {
+      return;
+    }
+    if inner.listing_cache.len() >= MAX_CACHED_LISTINGS && !inner.listing_cache.contains_key(dir_id) {
+      let oldest = inner
+        .listing_cache
+        .iter()
+        .min_by_key(|(_, cached)| cached.stored_at)
+        .map(|(key, _)| key.clone());
+      if let Some(key) = oldest {
+        inner.listing_cache.remove(&key);
+      }
+    }
+    inner.listing_cache.insert(
+      dir_id.to_string(),
+      CachedListing { generation, items, stored_at: Instant::now() }
+    );
+  }
+
+  /// Вызывается после любых изменений файлов, папок или локальных копий.
+  pub fn invalidate_listings(&self) {
+    let mut inner = self.inner.write();
+    inner.listing_generation = inner.listing_generation.wrapping_add(1);
+    inner.listing_cache.clear();
+    drop(inner);
+    crate::app::storage_stats::mark_dirty();
+  }
+
+  /// Списки чатов для диалога «Поделиться» живут недолго: название или аватар
+  /// могут поменяться, а повторное открытие диалога не должно ждать TDLib.
+  pub fn cached_chats(&self, key: &str) -> Option<Vec<ChatInfo>> {
+    self
+      .inner
+      .read()
+      .chat_cache
+      .get(key)
+      .filter(|cached| cached.stored_at.elapsed() < CHAT_CACHE_TTL)
+      .map(|cached| cached.items.clone())
+  }
+
+  pub fn store_chats(&self, key: &str, items: Vec<ChatInfo>) {
+    let mut inner = self.inner.write();
+    inner.chat_cache.retain(|_, cached| cached.stored_at.elapsed() < CHAT_CACHE_TTL);
+    if inner.chat_cache.len() >= MAX_CACHED_CHAT_LISTS {
+      inner.chat_cache.clear();
+    }
+    inner.chat_cache.insert(key.to_string(), CachedChats { items, stored_at: Instant::now() });
+  }
+
+  /// Держать guard нужно все время выбора или пересоздания канала: второй
+  /// вызов дождется первого и возьмет уже проверенный id.
+  pub async fn lock_storage_chat(&self) -> tokio::sync::MutexGuard<'_, StorageChatSlot> {
+    self.storage_chat.lock().await
+  }
+
+  /// Брать до чтения `db()`/`telegram()`: пока guard жив, аккаунт не сменится.
+  pub async fn session_guard(&self) -> SessionGuard {
+    self.session_gate.clone().read_owned().await
+  }
+
+  /// Ждет завершения команд, взявших `session_guard`, и не пускает новые.
+  async fn drain_sessions(&self) -> OwnedRwLockWriteGuard<()> {
+    self.session_gate.clone().write_owned().await
+  }
+
+  pub fn search_index(&self) -> Option<Arc<SearchIndex>> {
+    self.inner.read().search_index.clone()
+  }
+
+  /// Включает индекс поиска и прогревает его в фоне после первой отрисовки
+  /// окна. Пока прогрев не завершен, поиск идет через SQL.
+  pub fn enable_search_index(&self, db: Db) {
+    let index = Arc::new(SearchIndex::new());
+    self.inner.write().search_index = Some(index.clone());
+    tauri::async_runtime::spawn(async move {
+      crate::startup::after_first_paint().await;
+      if let Err(e) = index.warm(db.pool()).await {
+        tracing::warn!(event = "search_index_warm_failed", error = %e, "Не удалось построить поисковый индекс");
+      }
+    });
+  }
+
+  pub fn disable_search_index(&self) {
+    self.inner.write().search_index = None;
+  }
+
+  /// Обновляет запись файла в индексе поиска, если индекс включен.
+  pub async fn search_index_refresh_file(&self, db: &Db, file_id: &str) {
+    let Some(index) = self.search_index() else {
+      return;
+    };
+    if let Err(e) = index.refresh_file(db.pool(), file_id).await {
+      tracing::warn!(event = "search_index_refresh_failed", file_id = file_id, error = %e, "Не удалось обновить поисковый индекс");
+    }
+  }
+
+  pub fn search_index_remove_file(&self, file_id: &str) {
+    if let Some(index) = self.search_index() {
+      index.remove(file_id);
+    }
+  }
+
+  pub fn status_page_url(&self) -> Option<String> {
+    self.inner.read().status_page.as_ref().map(|h| h.url())
+  }
+
+  /// Запускает страницу состояния, если она еще не запущена, и возвращает ее адрес.
+  pub fn enable_status_page(&self) -> anyhow::Result<String> {
+    let mut inner = self.inner.write();
+    if let Some(handle) = inner.status_page.as_ref() {
+      return Ok(handle.url());
+    }
+    let handle = status_page::start()?;
+    let url = handle.url();
+    inner.status_page = Some(handle);
+    Ok(url)
+  }
+
+  pub fn disable_status_page(&self) {
+    self.inner.write().status_page = None;
+  }
+
+  /// Отдает файл плееру через локальный сервер, запуская его при первом вызове.
+  pub fn register_stream(
+    &self,
+    path: PathBuf,
+    name: &str,
+    total: u64,
+    available: Available,
+    control: Option<Arc<dyn StreamControl>>
+  ) -> anyhow::Result<String> {
+    let mut inner = self.inner.write();
+    if inner.stream_server.is_none() {
+      inner.stream_server = Some(stream_server::start()?);
+    }
+    match inner.stream_server.as_ref() {
+      Some(server) => server.register(path, name, total, available, control),
+      None => Err(anyhow::anyhow!("Сервер потокового открытия не запущен"))
+    }
+  }
+
   #[cfg(test)]
   pub fn set_paths_for_tests(&self, paths: Paths) {
     self.inner.write().paths = Some(paths);
@@ -154,49 +487,284 @@ impl AppState {
   }
 
   async fn init(&self, app: AppHandle) -> anyhow::Result<()> {
-    let paths = Paths::detect()?.with_resource_dir(app.path().resource_dir().ok());
-    paths.ensure_dirs()?;
-    if let Err(e) = apply_pending_restore(&paths) {
-      tracing::warn!(error = %e, "Не удалось применить подготовленное восстановление базы");
+    let root = Paths::detect()?.with_resource_dir(app.path().resource_dir().ok());
+    root.ensure_dirs()?;
+    let registry = match accounts::load(&root) {
+      Ok(registry) => registry,
+      Err(e) => {
+        tracing::warn!(event = "accounts_load_failed", error = %e, "Не удалось прочитать список аккаунтов, открываю основной");
+        accounts::AccountRegistry::default()
+      }
+    };
+    let account = registry.active;
+    tracing::info!(event = "init_paths", base_dir = %root.base_dir.display(), account = account.as_str(), "Пути приложения инициализированы");
+    {
+      let mut w = self.inner.write();
+      w.root_paths = Some(root.clone());
+      w.active_account = account.clone();
     }
-    tracing::info!(event = "init_paths", base_dir = %paths.base_dir.display(), "Пути приложения инициализированы");
 
-    let db = Db::connect(paths.sqlite_path()).await?;
-    db.migrate().await?;
-    tracing::info!(event = "init_db", db_path = %paths.sqlite_path().display(), "База данных подключена");
+    let AccountSession { paths, db, telegram } = self.open_session(&app, &root, &account).await?;
+    load_db_settings(&db).await;
 
-    let (tg_settings, _) = crate::secrets::resolve_credentials(&paths, None);
-    let tdlib_path = crate::settings::get_tdlib_path(db.pool()).await?;
-    let telegram = make_telegram_service(paths.clone(), app.clone(), tg_settings, tdlib_path)?;
-    tracing::info!(event = "init_telegram_service", "Telegram сервис инициализирован");
+    let search_index_enabled = crate::settings::get_search_index_enabled(db.pool()).await.unwrap_or(false);
+    let status_page_enabled = crate::settings::get_status_page_enabled(db.pool()).await.unwrap_or(false);
+    crate::diagnostics::spawn_flusher(app.clone());
+    spawn_local_copies_backfill(&db, &paths);
+    spawn_deferred_warmup(self.clone(), db.clone(), paths.clone());
 
     {
       let mut w = self.inner.write();
       w.paths = Some(paths);
-      w.db = Some(db);
+      w.db = Some(db.clone());
       w.telegram = Some(telegram);
       // если mock_telegram включён, считаем, что "авторизовано"
-      w.auth_state = if cfg!(feature = "mock_telegram") { AuthState::Ready } else { AuthState::Unknown };
+      if cfg!(feature = "mock_telegram") {
+        w.auth_state = AuthState::Ready;
+        crate::startup::auth_resolved(&w.auth_state);
+      }
+    }
+
+    if search_index_enabled {
+      self.enable_search_index(db);
+    }
+    if status_page_enabled {
+      if let Err(e) = self.enable_status_page() {
+        tracing::warn!(event = "status_page_start_failed", error = %e, "Не удалось запустить страницу состояния");
+      }
+    }
+    self.start_workers(&app);
+    crate::deep_link::resume_queued(&app);
+
+    Ok(())
+  }
+
+  fn start_workers(&self, app: &AppHandle) {
+    let workers = vec![
+      crate::app::download_queue::spawn_worker(app.clone()),
+      crate::app::hash_upgrade::spawn_worker(app.clone()),
+      crate::app::storage_stats::spawn_worker(app.clone()),
+      crate::app::auto_sync::spawn_worker(app.clone()),
+    ];
+    self.inner.write().workers = workers;
+  }
+
+  /// Останавливает фоновые обработчики и их задачи: они держат базу и
+  /// Telegram прежнего аккаунта.
+  async fn stop_workers(&self) {
+    let workers = std::mem::take(&mut self.inner.write().workers);
+    crate::app::download_queue::abort_running();
+    for handle in workers {
+      handle.abort();
+      let _ = handle.await;
     }
+  }
+
+  /// Открывает базу и сессию Telegram аккаунта в его каталогах.
+  async fn open_session(&self, app: &AppHandle, root: &Paths, account: &str) -> anyhow::Result<AccountSession> {
+    let paths = root.for_account(account);
+    paths.ensure_dirs()?;
+    let db = open_account_db(&paths).await?;
+    tracing::info!(event = "init_db", db_path = %paths.sqlite_path().display(), "База данных подключена");
+
+    // Ключи, введенные в этом запуске, годятся и для других аккаунтов.
+    let runtime = self.tg_credentials().map(|(creds, _)| creds);
+    let (tg_settings, _) = crate::secrets::resolve_credentials(&paths, runtime.as_ref());
+    let tdlib_path = crate::settings::get_tdlib_path(db.pool()).await?;
+    let telegram = make_telegram_service(paths.clone(), app.clone(), tg_settings, tdlib_path, account.to_string())?;
+    tracing::info!(event = "init_telegram_service", account = account, "Telegram сервис инициализирован");
+    Ok(AccountSession { paths, db, telegram })
+  }
 
+  /// Переключает приложение на другой аккаунт. Сессия прежнего остается
+  /// открытой; изменяющие команды дожидаются, фоновые обработчики
+  /// перезапускаются на базе нового, а ключ хранилища и состояние в памяти,
+  /// относящееся к прежнему аккаунту, сбрасываются.
+  pub async fn switch_account(&self, app: &AppHandle, account: &str) -> anyhow::Result<()> {
+    let _drained = self.drain_sessions().await;
+    // Выбор канала хранения не должен застать смену базы на полпути.
+    let mut storage_chat = self.lock_storage_chat().await;
+    let root = self.root_paths()?;
+    let mut registry = accounts::load(&root)?;
+    if registry.get(account).is_none() {
+      return Err(anyhow::anyhow!("Аккаунт не найден"));
+    }
+    if self.active_account() == account {
+      return Ok(());
+    }
+    let parked = self.inner.write().sessions.remove(account);
+    let session = match parked {
+      Some(session) => session,
+      None => self.open_session(app, &root, account).await?
+    };
+    let (db, paths) = (session.db.clone(), session.paths.clone());
+    self.stop_workers().await;
+    reset_account_memory(&self.active_account());
+    let auth_state = {
+      let mut w = self.inner.write();
+      if let (Some(paths), Some(db), Some(telegram)) = (w.paths.take(), w.db.take(), w.telegram.take()) {
+        let previous = w.active_account.clone();
+        let previous_auth = w.auth_state.clone();
+        w.account_auth.insert(previous.clone(), previous_auth);
+        w.sessions.insert(previous, AccountSession { paths, db, telegram });
+      }
+      w.active_account = account.to_string();
+      w.auth_state = w.account_auth.remove(account).unwrap_or(AuthState::Unknown);
+      w.paths = Some(session.paths);
+      w.db = Some(session.db);
+      w.telegram = Some(session.telegram);
+      w.chat_cache.clear();
+      w.auth_state.clone()
+    };
+    storage_chat.forget();
+    drop(storage_chat);
+    self.invalidate_listings();
+
+    load_db_settings(&db).await;
+    if crate::settings::get_search_index_enabled(db.pool()).await.unwrap_or(false) {
+      self.enable_search_index(db.clone());
+    } else {
+      self.disable_search_index();
+    }
+    spawn_local_copies_backfill(&db, &paths);
+    self.start_workers(app);
+    registry.active = account.to_string();
+    accounts::save(&root, &registry)?;
+
+    let _ = app.emit("auth_state_changed", serde_json::json!({ "state": auth_state.as_str() }));
+    crate::events::tree_updated(app);
+    tracing::info!(event = "account_switched", account = account, "Аккаунт переключен");
     Ok(())
   }
 }
 
+/// Состояние в памяти, которое принадлежит активному аккаунту: расшифрованный
+/// ключ хранилища, очереди фоновых задач и предупреждения о лимитах.
+fn reset_account_memory(previous: &str) {
+  crate::app::vault::lock();
+  crate::app::hash_upgrade::clear_queue();
+  crate::app::auto_sync::reset();
+  crate::telegram::discard_realtime_updates();
+  crate::telegram::limits::forget_warnings(previous);
+}
+
+/// Настройки из базы, которые модули держат у себя в памяти.
+async fn load_db_settings(db: &Db) {
+  crate::diagnostics::set_enabled(crate::settings::get_diagnostics_enabled(db.pool()).await.unwrap_or(false));
+  match crate::settings::get_tdlib_timeouts(db.pool()).await {
+    Ok((_, profile)) => crate::telegram::timeouts::set_profile(profile),
+    Err(e) => tracing::warn!(event = "tdlib_timeouts_load_failed", error = %e, "Не удалось загрузить профиль таймаутов TDLib")
+  }
+  match crate::settings::get_indexer_ignore_list(db.pool()).await {
+    Ok(list) => crate::app::ignore_list::set_active(list),
+    Err(e) => tracing::warn!(event = "indexer_ignore_list_load_failed", error = %e, "Не удалось загрузить список игнорирования индексатора")
+  }
+  match crate::settings::get_maintenance_window(db.pool()).await {
+    Ok(window) => crate::app::maintenance::set_active(window),
+    Err(e) => tracing::warn!(event = "maintenance_window_load_failed", error = %e, "Не удалось загрузить окно обслуживания")
+  }
+  match crate::settings::get_auto_sync(db.pool()).await {
+    Ok(config) => crate::app::auto_sync::set_active(config),
+    Err(e) => tracing::warn!(event = "auto_sync_load_failed", error = %e, "Не удалось загрузить настройки автосинхронизации")
+  }
+  match crate::settings::get_local_name_rules(db.pool()).await {
+    Ok(rules) => crate::app::local_names::set_active(rules),
+    Err(e) => tracing::warn!(event = "local_name_rules_load_failed", error = %e, "Не удалось загрузить правила локальных имен")
+  }
+  match crate::settings::get_open_guard(db.pool()).await {
+    Ok(guard) => crate::app::open_guard::set_active(guard),
+    Err(e) => tracing::warn!(event = "open_guard_load_failed", error = %e, "Не удалось загрузить настройки защиты открытия файлов")
+  }
+  match crate::settings::get_virus_scan(db.pool()).await {
+    Ok(config) => crate::app::virus_scan::set_active(config),
+    Err(e) => tracing::warn!(event = "virus_scan_load_failed", error = %e, "Не удалось загрузить настройки антивирусной проверки")
+  }
+  match crate::settings::get_system_dir_names(db.pool()).await {
+    Ok(names) => crate::app::system_dirs::set_active(names),
+    Err(e) => tracing::warn!(event = "system_dir_names_load_failed", error = %e, "Не удалось загрузить имена служебных папок")
+  }
+}
+
+fn spawn_local_copies_backfill(db: &Db, paths: &Paths) {
+  let pool = db.pool().clone();
+  let paths = paths.clone();
+  tauri::async_runtime::spawn(async move {
+    crate::startup::after_first_paint().await;
+    crate::app::maintenance::wait_for_window().await;
+    if let Err(e) = crate::app::local_copies::backfill(&pool, &paths).await {
+      tracing::warn!(event = "local_copies_index_failed", error = %e, "Не удалось занести старые загрузки в базу");
+    }
+  });
+}
+
+/// Прогрев, без которого окно может отрисоваться: миниатюры недавно
+/// скачанных файлов и список недавних чатов.
+fn spawn_deferred_warmup(state: AppState, db: Db, paths: Paths) {
+  tauri::async_runtime::spawn(async move {
+    crate::startup::after_first_paint().await;
+    crate::app::maintenance::wait_for_window().await;
+    match crate::app::thumbnails::warm_local(db.pool(), &paths).await {
+      Ok(rendered) => tracing::debug!(event = "thumbnail_warm_done", rendered = rendered, "Миниатюры недавних файлов подготовлены"),
+      Err(e) => tracing::warn!(event = "thumbnail_warm_failed", error = %e, "Не удалось заранее подготовить миниатюры")
+    }
+    if state.auth_state() != AuthState::Ready || state.cached_chats("recent").is_some() {
+      return;
+    }
+    let Ok(tg) = state.telegram() else {
+      return;
+    };
+    match tg.recent_chats(RECENT_CHATS_LIMIT).await {
+      Ok(items) => state.store_chats("recent", items),
+      Err(e) => tracing::debug!(event = "chat_cache_warm_failed", error = %e, "Не удалось заранее загрузить недавние чаты")
+    }
+  });
+}
+
 impl Default for AppState {
   fn default() -> Self {
     Self::new()
   }
 }
 
-fn apply_pending_restore(paths: &Paths) -> anyhow::Result<()> {
-  let pending = paths.pending_restore_path();
+/// Открывает базу аккаунта, применив подготовленное восстановление; если
+/// восстановленная база не открылась, возвращает прежнюю.
+async fn open_account_db(paths: &Paths) -> anyhow::Result<Db> {
+  let restored = match apply_pending_restore(paths) {
+    Ok(restored) => restored,
+    Err(e) => {
+      tracing::warn!(error = %e, "Не удалось применить подготовленное восстановление базы");
+      false
+    }
+  };
+  match open_db(paths).await {
+    Ok(db) => Ok(db),
+    Err(e) if restored => {
+      tracing::error!(event = "db_restore_open_failed", error = %e, "Восстановленная база не открылась, возвращаю прежнюю");
+      rollback_restore(paths)?;
+      open_db(paths).await
+    }
+    Err(e) => Err(e)
+  }
+}
+
+async fn open_db(paths: &Paths) -> anyhow::Result<Db> {
+  let db = crate::startup::measure("db_open", Db::connect(paths.sqlite_path())).await?;
+  if let Err(e) = crate::startup::measure("migrations", db.migrate()).await {
+    db.pool().close().await;
+    return Err(e);
+  }
+  Ok(db)
+}
+
+/// Возвращает `true`, если база была подменена подготовленным восстановлением.
+fn apply_pending_restore(paths: &Paths) -> anyhow::Result<bool> {
+  let pending = paths.layout().pending_restore_path();
   if !pending.exists() {
-    return Ok(());
+    return Ok(false);
   }
 
   let db_path = paths.sqlite_path();
-  let prev_path = paths.previous_db_path();
+  let prev_path = paths.layout().previous_db_path();
 
   remove_sqlite_sidecars(&db_path);
   remove_sqlite_sidecars(&pending);
@@ -217,6 +785,31 @@ fn apply_pending_restore(paths: &Paths) -> anyhow::Result<()> {
     prev_path = %prev_path.display(),
     "Применено восстановление базы"
   );
+  Ok(true)
+}
+
+/// Откатывает восстановление: неудачная база откладывается в `failed_restore_path`,
+/// на место возвращается прежняя.
+fn rollback_restore(paths: &Paths) -> anyhow::Result<()> {
+  let db_path = paths.sqlite_path();
+  let prev_path = paths.layout().previous_db_path();
+  let failed_path = paths.layout().failed_restore_path();
+  if !prev_path.exists() {
+    return Err(anyhow::anyhow!("Прежняя база не найдена, откатить восстановление нельзя"));
+  }
+
+  remove_sqlite_sidecars(&db_path);
+  if db_path.exists() {
+    let _ = std::fs::remove_file(&failed_path);
+    std::fs::rename(&db_path, &failed_path)?;
+  }
+  std::fs::rename(&prev_path, &db_path)?;
+  tracing::warn!(
+    event = "db_restore_rolled_back",
+    db_path = %db_path.display(),
+    failed_path = %failed_path.display(),
+    "Восстановление откатено, используется прежняя база"
+  );
   Ok(())
 }
 
@@ -229,8 +822,87 @@ fn remove_sqlite_sidecars(path: &Path) {
 }
 
 const MAX_UPLOAD_PERMITS: usize = 512;
+const MAX_CACHED_LISTINGS: usize = 64;
+const MAX_CACHED_CHAT_LISTS: usize = 32;
+const CHAT_CACHE_TTL: Duration = Duration::from_secs(60);
+/// Сколько недавних чатов показывает выбор чата.
+pub const RECENT_CHATS_LIMIT: i32 = 12;
+const STORAGE_CHAT_TTL: Duration = Duration::from_secs(30);
 
 fn cleanup_upload_permits(permits: &mut HashMap<String, UploadPermit>) {
   let now = Instant::now();
   permits.retain(|_, permit| permit.expires_at > now);
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn listing_cache_drops_stale_generations() {
+    let state = AppState::new();
+    let generation = state.listing_generation();
+    state.store_listing("d1", generation, Vec::new());
+    assert!(state.cached_listing("d1").is_some());
+
+    state.invalidate_listings();
+    assert!(state.cached_listing("d1").is_none());
+
+    // Результат, прочитанный до мутации, не должен попасть в кеш.
+    state.store_listing("d1", generation, Vec::new());
+    assert!(state.cached_listing("d1").is_none());
+  }
+
+  #[tokio::test]
+  async fn account_switch_waits_for_running_commands() {
+    let state = AppState::new();
+    let command = state.session_guard().await;
+    let switch = {
+      let state = state.clone();
+      tokio::spawn(async move {
+        let _drained = state.drain_sessions().await;
+      })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!switch.is_finished());
+    drop(command);
+    tokio::time::timeout(Duration::from_secs(1), switch).await.unwrap().unwrap();
+  }
+
+  #[test]
+  fn storage_chat_slot_follows_saved_id() {
+    let mut slot = StorageChatSlot::default();
+    assert_eq!(slot.fresh(Some(-100)), None);
+    slot.store(-100);
+    assert_eq!(slot.fresh(Some(-100)), Some(-100));
+    // Канал сменили в обход слота (восстановление бэкапа, прогрев).
+    assert_eq!(slot.fresh(Some(-200)), None);
+    slot.forget();
+    assert_eq!(slot.fresh(Some(-100)), None);
+  }
+
+  #[test]
+  fn failed_restore_rolls_back_to_previous_db() -> anyhow::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let paths = Paths {
+      base_dir: tmp.path().to_path_buf(),
+      data_dir: tmp.path().to_path_buf(),
+      cache_dir: tmp.path().join("cache"),
+      logs_dir: tmp.path().join("logs"),
+      resource_dir: None,
+      portable: false
+    };
+    std::fs::write(paths.sqlite_path(), b"old")?;
+    std::fs::write(paths.layout().pending_restore_path(), b"new")?;
+
+    assert!(apply_pending_restore(&paths)?);
+    assert_eq!(std::fs::read(paths.sqlite_path())?, b"new");
+    assert!(!apply_pending_restore(&paths)?);
+
+    rollback_restore(&paths)?;
+    assert_eq!(std::fs::read(paths.sqlite_path())?, b"old");
+    assert_eq!(std::fs::read(paths.layout().failed_restore_path())?, b"new");
+    assert!(!paths.layout().previous_db_path().exists());
+    Ok(())
+  }
+}
@@ -0,0 +1,280 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use chrono::Utc;
+use getrandom::fill as getrandom_fill;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::deep_link::DeepLink;
+use crate::metrics;
+
+/// Локальная страница состояния только для чтения: работает, пока webview закрыт,
+/// слушает только 127.0.0.1 и отвечает лишь на запросы со случайным токеном.
+const MAX_ACTIVITY: usize = 50;
+const ACCEPT_POLL: Duration = Duration::from_millis(200);
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+static ACTIVITY: Lazy<Mutex<VecDeque<ActivityEntry>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+static SYNC_STATUS: Lazy<Mutex<Option<SyncSnapshot>>> = Lazy::new(|| Mutex::new(None));
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ActivityEntry {
+  pub at: i64,
+  pub message: String,
+  /// Ссылка `cloudtg://` на файл или папку, о которых запись.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub link: Option<String>
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SyncSnapshot {
+  pub state: String,
+  pub message: String,
+  pub processed: i64,
+  pub total: Option<i64>,
+  pub at: i64
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct StatusPayload {
+  generated_at: i64,
+  sync: Option<SyncSnapshot>,
+  metrics: metrics::MetricsSnapshot,
+  activity: Vec<ActivityEntry>
+}
+
+pub fn record_activity(message: impl Into<String>) {
+  push_activity(message.into(), None);
+}
+
+pub fn record_activity_link(message: impl Into<String>, link: &DeepLink) {
+  push_activity(message.into(), Some(link.url()));
+}
+
+fn push_activity(message: String, link: Option<String>) {
+  let mut guard = ACTIVITY.lock();
+  guard.push_front(ActivityEntry { at: Utc::now().timestamp(), message, link });
+  guard.truncate(MAX_ACTIVITY);
+}
+
+pub fn record_sync_status(state: &str, message: &str, processed: i64, total: Option<i64>) {
+  *SYNC_STATUS.lock() = Some(SyncSnapshot {
+    state: state.to_string(),
+    message: message.to_string(),
+    processed,
+    total,
+    at: Utc::now().timestamp()
+  });
+}
+
+pub struct StatusPageHandle {
+  pub port: u16,
+  token: String,
+  stop: Arc<AtomicBool>
+}
+
+impl StatusPageHandle {
+  pub fn url(&self) -> String {
+    format!("http://127.0.0.1:{}/?token={}", self.port, self.token)
+  }
+
+  pub fn stop(&self) {
+    self.stop.store(true, Ordering::Relaxed);
+  }
+}
+
+impl Drop for StatusPageHandle {
+  fn drop(&mut self) {
+    self.stop();
+  }
+}
+
+/// Запускает сервер на свободном порту loopback-интерфейса.
+pub fn start() -> anyhow::Result<StatusPageHandle> {
+  let listener = TcpListener::bind(("127.0.0.1", 0))?;
+  listener.set_nonblocking(true)?;
+  let port = listener.local_addr()?.port();
+  let token = random_token()?;
+  let stop = Arc::new(AtomicBool::new(false));
+
+  let thread_token = token.clone();
+  let thread_stop = stop.clone();
+  std::thread::Builder::new()
+    .name("cloudtg-status-page".into())
+    .spawn(move || serve(listener, thread_token, thread_stop))?;
+
+  tracing::info!(event = "status_page_started", port = port, "Страница состояния запущена");
+  Ok(StatusPageHandle { port, token, stop })
+}
+
+fn serve(listener: TcpListener, token: String, stop: Arc<AtomicBool>) {
+  while !stop.load(Ordering::Relaxed) {
+    match listener.accept() {
+      Ok((stream, _)) => {
+        if let Err(e) = handle_connection(stream, &token) {
+          tracing::debug!(event = "status_page_request_failed", error = %e, "Не удалось обработать запрос страницы состояния");
+        }
+      }
+      Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => std::thread::sleep(ACCEPT_POLL),
+      Err(e) => {
+        tracing::warn!(event = "status_page_accept_failed", error = %e, "Ошибка приема соединения");
+        std::thread::sleep(ACCEPT_POLL);
+      }
+    }
+  }
+  tracing::info!(event = "status_page_stopped", "Страница состояния остановлена");
+}
+
+fn handle_connection(mut stream: TcpStream, token: &str) -> std::io::Result<()> {
+  stream.set_nonblocking(false)?;
+  stream.set_read_timeout(Some(READ_TIMEOUT))?;
+  let mut request_line = String::new();
+  BufReader::new(&stream).read_line(&mut request_line)?;
+
+  let mut parts = request_line.split_whitespace();
+  let method = parts.next().unwrap_or("");
+  let target = parts.next().unwrap_or("");
+  let (path, query) = target.split_once('?').unwrap_or((target, ""));
+  let provided = query
+    .split('&')
+    .find_map(|pair| pair.strip_prefix("token="))
+    .unwrap_or("");
+
+  if method != "GET" {
+    return respond(&mut stream, "405 Method Not Allowed", "text/plain; charset=utf-8", "Только GET");
+  }
+  if !constant_time_eq(provided.as_bytes(), token.as_bytes()) {
+    return respond(&mut stream, "403 Forbidden", "text/plain; charset=utf-8", "Нужен токен доступа");
+  }
+
+  let payload = StatusPayload {
+    generated_at: Utc::now().timestamp(),
+    sync: SYNC_STATUS.lock().clone(),
+    metrics: metrics::snapshot(),
+    activity: ACTIVITY.lock().iter().cloned().collect()
+  };
+  match path {
+    "/" => respond(&mut stream, "200 OK", "text/html; charset=utf-8", &render_html(&payload)),
+    "/status.json" => {
+      let body = serde_json::to_string(&payload).unwrap_or_else(|_| "{}".to_string());
+      respond(&mut stream, "200 OK", "application/json", &body)
+    }
+    _ => respond(&mut stream, "404 Not Found", "text/plain; charset=utf-8", "Не найдено")
+  }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> std::io::Result<()> {
+  let head = format!(
+    "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nX-Content-Type-Options: nosniff\r\nReferrer-Policy: no-referrer\r\nConnection: close\r\n\r\n",
+    body.len()
+  );
+  stream.write_all(head.as_bytes())?;
+  stream.write_all(body.as_bytes())?;
+  stream.flush()
+}
+
+fn render_html(p: &StatusPayload) -> String {
+  let sync = match &p.sync {
+    Some(s) => {
+      let progress = match s.total {
+        Some(total) => format!("{}/{}", s.processed, total),
+        None => s.processed.to_string()
+      };
+      format!("{} — {} ({progress})", escape_html(&s.state), escape_html(&s.message))
+    }
+    None => "синхронизация еще не запускалась".to_string()
+  };
+  let m = &p.metrics;
+  let activity: String = p
+    .activity
+    .iter()
+    .map(|a| {
+      let message = match &a.link {
+        Some(link) => format!("<a href=\"{}\">{}</a>", escape_html(link), escape_html(&a.message)),
+        None => escape_html(&a.message)
+      };
+      format!("<li>{} — {message}</li>", format_ts(a.at))
+    })
+    .collect();
+  format!(
+    "<!doctype html><html lang=\"ru\"><head><meta charset=\"utf-8\"><meta http-equiv=\"refresh\" content=\"5\">\
+     <title>CloudTG — состояние</title></head><body>\
+     <h1>CloudTG</h1>\
+     <h2>Синхронизация</h2><p>{sync}</p>\
+     <h2>Передачи</h2><ul>\
+     <li>Загружено: {} (ошибок: {})</li><li>Скачано: {} (ошибок: {})</li>\
+     <li>TDLib: {} (переподключений: {})</li></ul>\
+     <h2>Последние действия</h2><ul>{activity}</ul>\
+     <p><small>Обновлено {}</small></p></body></html>",
+    m.uploads_total,
+    m.upload_errors_total,
+    m.downloads_total,
+    m.download_errors_total,
+    if m.tdlib_connected { "подключен" } else { "нет соединения" },
+    m.tdlib_reconnects_total,
+    format_ts(p.generated_at)
+  )
+}
+
+fn format_ts(ts: i64) -> String {
+  chrono::DateTime::from_timestamp(ts, 0)
+    .map(|d| d.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+    .unwrap_or_default()
+}
+
+fn escape_html(value: &str) -> String {
+  value
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+fn random_token() -> anyhow::Result<String> {
+  let mut buf = [0u8; 16];
+  getrandom_fill(&mut buf).map_err(|e| anyhow::anyhow!("Не удалось сгенерировать токен: {e}"))?;
+  Ok(hex::encode(buf))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  if a.len() != b.len() {
+    return false;
+  }
+  a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Read;
+
+  fn get(port: u16, target: &str) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    write!(stream, "GET {target} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut out = String::new();
+    stream.read_to_string(&mut out).unwrap();
+    out
+  }
+
+  #[test]
+  fn serves_status_only_with_token() {
+    record_activity("Загружен <script>");
+    record_activity_link("Скачан файл", &DeepLink::File("f1".into()));
+    let handle = start().unwrap();
+    assert!(get(handle.port, "/").starts_with("HTTP/1.1 403"));
+
+    let page = get(handle.port, &format!("/?token={}", handle.token));
+    assert!(page.starts_with("HTTP/1.1 200"));
+    assert!(page.contains("Загружен &lt;script&gt;"));
+    assert!(page.contains("<a href=\"cloudtg://file/f1\">Скачан файл</a>"));
+
+    let json = get(handle.port, &format!("/status.json?token={}", handle.token));
+    assert!(json.contains("\"activity\""));
+    handle.stop();
+  }
+}
@@ -0,0 +1,516 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use getrandom::fill as getrandom_fill;
+use parking_lot::Mutex;
+
+use crate::telegram::RangeSink;
+
+/// Раздает видео и аудио плееру ОС, пока TDLib их докачивает. Слушает только
+/// 127.0.0.1, адрес каждого файла содержит случайный id. Плеер перематывает
+/// запросами Range; за еще не скачанным скачивание перематывается следом.
+const MAX_STREAMS: usize = 16;
+const ACCEPT_POLL: Duration = Duration::from_millis(200);
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const WAIT_POLL: Duration = Duration::from_millis(100);
+/// Сколько ждать новых байт, прежде чем оборвать ответ. Отсчет сбрасывается,
+/// пока скачивание продвигается хоть где-то в файле.
+const STALL_TIMEOUT: Duration = Duration::from_secs(60);
+/// Как часто повторять перемотку скачивания, если байты так и не пришли:
+/// другое соединение плеера могло увести скачивание в свое место.
+const SEEK_RETRY: Duration = Duration::from_secs(5);
+/// Поток без соединений дольше этого считается закрытым плеером: скачивание
+/// останавливается, адрес перестает работать.
+const IDLE_RELEASE: Duration = Duration::from_secs(120);
+const CHUNK: usize = 256 * 1024;
+
+/// Скачанные диапазоны файла: отсортированы и не пересекаются.
+#[derive(Clone, Default)]
+pub struct Available(Arc<Mutex<Vec<(u64, u64)>>>);
+
+impl Available {
+  pub fn complete(total: u64) -> Available {
+    let available = Available::default();
+    available.add(0, total);
+    available
+  }
+
+  /// Отмечает скачанными байты `start..end`.
+  pub fn add(&self, start: u64, end: u64) {
+    if start >= end {
+      return;
+    }
+    let mut ranges = self.0.lock();
+    let (mut start, mut end) = (start, end);
+    ranges.retain(|&(s, e)| {
+      if e < start || s > end {
+        return true;
+      }
+      start = start.min(s);
+      end = end.max(e);
+      false
+    });
+    let at = ranges.partition_point(|&(s, _)| s < start);
+    ranges.insert(at, (start, end));
+  }
+
+  /// До какого места файл скачан подряд начиная с `pos`; `pos`, если
+  /// байта в `pos` еще нет.
+  pub fn end_from(&self, pos: u64) -> u64 {
+    self
+      .0
+      .lock()
+      .iter()
+      .find(|&&(s, e)| s <= pos && pos < e)
+      .map(|&(_, e)| e)
+      .unwrap_or(pos)
+  }
+
+  /// Сколько байт скачано всего.
+  pub fn downloaded(&self) -> u64 {
+    self.0.lock().iter().map(|&(s, e)| e - s).sum()
+  }
+
+  /// Приемник для скачивания, которое сообщает скачанные диапазоны.
+  pub fn sink(&self) -> RangeSink {
+    let available = self.clone();
+    Arc::new(move |start, end| available.add(start, end))
+  }
+}
+
+/// Управление скачиванием за потоком.
+pub trait StreamControl: Send + Sync {
+  /// Плееру нужны байты с `offset`, а их еще нет: качать оттуда.
+  fn seek(&self, offset: u64);
+  /// Плеер закрыт или ссылка вытеснена: скачивание больше не нужно.
+  fn release(&self);
+}
+
+struct Stream {
+  path: PathBuf,
+  total: u64,
+  mime: &'static str,
+  available: Available,
+  control: Option<Arc<dyn StreamControl>>,
+  created_at: Instant,
+  connections: AtomicUsize,
+  last_used: Mutex<Instant>
+}
+
+impl Drop for Stream {
+  fn drop(&mut self) {
+    if let Some(control) = &self.control {
+      control.release();
+    }
+  }
+}
+
+/// Соединение плеера с потоком; по ним видно, что плеер закрыт.
+struct Connection<'a>(&'a Stream);
+
+impl<'a> Connection<'a> {
+  fn open(stream: &'a Stream) -> Connection<'a> {
+    stream.connections.fetch_add(1, Ordering::Relaxed);
+    Connection(stream)
+  }
+}
+
+impl Drop for Connection<'_> {
+  fn drop(&mut self) {
+    *self.0.last_used.lock() = Instant::now();
+    self.0.connections.fetch_sub(1, Ordering::Relaxed);
+  }
+}
+
+type Streams = Arc<Mutex<HashMap<String, Arc<Stream>>>>;
+
+pub struct StreamServerHandle {
+  pub port: u16,
+  streams: Streams,
+  stop: Arc<AtomicBool>
+}
+
+impl StreamServerHandle {
+  /// Открывает файл для плеера и возвращает его адрес. Самые старые
+  /// ссылки перестают работать, когда их больше `MAX_STREAMS`. `control`
+  /// нужен недокачанному файлу: через него скачивание идет за плеером.
+  pub fn register(
+    &self,
+    path: PathBuf,
+    name: &str,
+    total: u64,
+    available: Available,
+    control: Option<Arc<dyn StreamControl>>
+  ) -> anyhow::Result<String> {
+    let mime = media_mime(name).ok_or_else(|| anyhow::anyhow!("Потоком открываются только видео и аудио"))?;
+    let id = random_id()?;
+    let mut streams = self.streams.lock();
+    while streams.len() >= MAX_STREAMS {
+      let Some(oldest) = streams.iter().min_by_key(|(_, s)| s.created_at).map(|(id, _)| id.clone()) else {
+        break;
+      };
+      streams.remove(&oldest);
+    }
+    let now = Instant::now();
+    streams.insert(id.clone(), Arc::new(Stream {
+      path,
+      total,
+      mime,
+      available,
+      control,
+      created_at: now,
+      connections: AtomicUsize::new(0),
+      last_used: Mutex::new(now)
+    }));
+    Ok(format!("http://127.0.0.1:{}/{id}/{}", self.port, encode_path_segment(name)))
+  }
+
+  pub fn stop(&self) {
+    self.stop.store(true, Ordering::Relaxed);
+  }
+}
+
+impl Drop for StreamServerHandle {
+  fn drop(&mut self) {
+    self.stop();
+  }
+}
+
+/// Тип содержимого для видео и аудио по расширению; для остального `None`.
+pub fn media_mime(name: &str) -> Option<&'static str> {
+  let ext = name.rsplit_once('.')?.1.to_ascii_lowercase();
+  Some(match ext.as_str() {
+    "mp4" | "m4v" => "video/mp4",
+    "mov" => "video/quicktime",
+    "mkv" => "video/x-matroska",
+    "webm" => "video/webm",
+    "avi" => "video/x-msvideo",
+    "mp3" => "audio/mpeg",
+    "m4a" => "audio/mp4",
+    "aac" => "audio/aac",
+    "ogg" | "oga" | "opus" => "audio/ogg",
+    "flac" => "audio/flac",
+    "wav" => "audio/wav",
+    _ => return None
+  })
+}
+
+/// Запускает сервер на свободном порту loopback-интерфейса.
+pub fn start() -> anyhow::Result<StreamServerHandle> {
+  let listener = TcpListener::bind(("127.0.0.1", 0))?;
+  listener.set_nonblocking(true)?;
+  let port = listener.local_addr()?.port();
+  let streams: Streams = Arc::new(Mutex::new(HashMap::new()));
+  let stop = Arc::new(AtomicBool::new(false));
+
+  let thread_streams = streams.clone();
+  let thread_stop = stop.clone();
+  std::thread::Builder::new()
+    .name("cloudtg-stream-server".into())
+    .spawn(move || serve(listener, thread_streams, thread_stop))?;
+
+  tracing::info!(event = "stream_server_started", port = port, "Сервер потокового открытия запущен");
+  Ok(StreamServerHandle { port, streams, stop })
+}
+
+fn serve(listener: TcpListener, streams: Streams, stop: Arc<AtomicBool>) {
+  while !stop.load(Ordering::Relaxed) {
+    match listener.accept() {
+      Ok((stream, _)) => {
+        // Плееры держат несколько соединений сразу, а ответ может ждать скачивания.
+        let streams = streams.clone();
+        let stop = stop.clone();
+        let spawned = std::thread::Builder::new()
+          .name("cloudtg-stream-conn".into())
+          .spawn(move || {
+            if let Err(e) = handle_connection(stream, &streams, &stop) {
+              tracing::debug!(event = "stream_request_failed", error = %e, "Соединение потокового открытия прервано");
+            }
+          });
+        if let Err(e) = spawned {
+          tracing::warn!(event = "stream_thread_failed", error = %e, "Не удалось обработать соединение");
+        }
+      }
+      Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+        release_idle(&streams);
+        std::thread::sleep(ACCEPT_POLL);
+      }
+      Err(e) => {
+        tracing::warn!(event = "stream_accept_failed", error = %e, "Ошибка приема соединения");
+        std::thread::sleep(ACCEPT_POLL);
+      }
+    }
+  }
+  tracing::info!(event = "stream_server_stopped", "Сервер потокового открытия остановлен");
+}
+
+/// Убирает потоки, которые плеер давно не читает; их скачивание
+/// останавливается вместе с последней ссылкой на поток.
+fn release_idle(streams: &Streams) {
+  streams.lock().retain(|_, s| {
+    s.control.is_none() || s.connections.load(Ordering::Relaxed) > 0 || s.last_used.lock().elapsed() < IDLE_RELEASE
+  });
+}
+
+fn handle_connection(mut stream: TcpStream, streams: &Streams, stop: &AtomicBool) -> std::io::Result<()> {
+  stream.set_nonblocking(false)?;
+  stream.set_read_timeout(Some(READ_TIMEOUT))?;
+  let mut reader = BufReader::new(&stream);
+  let mut request_line = String::new();
+  reader.read_line(&mut request_line)?;
+  let mut range_header: Option<String> = None;
+  loop {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+      break;
+    }
+    if let Some((key, value)) = line.split_once(':') {
+      if key.trim().eq_ignore_ascii_case("range") {
+        range_header = Some(value.trim().to_string());
+      }
+    }
+  }
+
+  let mut parts = request_line.split_whitespace();
+  let method = parts.next().unwrap_or("");
+  let target = parts.next().unwrap_or("");
+  if method != "GET" && method != "HEAD" {
+    return respond_empty(&mut stream, "405 Method Not Allowed");
+  }
+  let id = target.trim_start_matches('/').split(['/', '?']).next().unwrap_or("");
+  let Some(entry) = streams.lock().get(id).cloned() else {
+    return respond_empty(&mut stream, "404 Not Found");
+  };
+  let _connection = Connection::open(&entry);
+
+  let (status, start, end) = match range_header.as_deref() {
+    None => ("200 OK", 0, entry.total.saturating_sub(1)),
+    Some(header) => match parse_range(header, entry.total) {
+      Some((start, end)) => ("206 Partial Content", start, end),
+      None => {
+        let head = format!(
+          "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */{}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+          entry.total
+        );
+        stream.write_all(head.as_bytes())?;
+        return stream.flush();
+      }
+    }
+  };
+  let length = if entry.total == 0 { 0 } else { end - start + 1 };
+  let mut head = format!(
+    "HTTP/1.1 {status}\r\nContent-Type: {}\r\nContent-Length: {length}\r\nAccept-Ranges: bytes\r\nCache-Control: no-store\r\nX-Content-Type-Options: nosniff\r\nConnection: close\r\n",
+    entry.mime
+  );
+  if status.starts_with("206") {
+    head.push_str(&format!("Content-Range: bytes {start}-{end}/{}\r\n", entry.total));
+  }
+  head.push_str("\r\n");
+  stream.write_all(head.as_bytes())?;
+  if method == "HEAD" || length == 0 {
+    return stream.flush();
+  }
+  send_body(&mut stream, &entry, start, end, stop)
+}
+
+/// Отдает байты `start..=end`, дожидаясь тех, что еще не скачаны, и
+/// перематывая скачивание к ним.
+fn send_body(stream: &mut TcpStream, entry: &Stream, start: u64, end: u64, stop: &AtomicBool) -> std::io::Result<()> {
+  let mut file: Option<std::fs::File> = None;
+  let mut buf = vec![0u8; CHUNK];
+  let mut pos = start;
+  let mut last_progress = Instant::now();
+  let mut downloaded = entry.available.downloaded();
+  let mut last_seek: Option<(u64, Instant)> = None;
+  while pos <= end {
+    let available = entry.available.end_from(pos).min(entry.total);
+    if available <= pos {
+      if stop.load(Ordering::Relaxed) || last_progress.elapsed() >= STALL_TIMEOUT {
+        return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "скачивание не продвигается"));
+      }
+      let now_downloaded = entry.available.downloaded();
+      if now_downloaded > downloaded {
+        downloaded = now_downloaded;
+        last_progress = Instant::now();
+      }
+      if let Some(control) = &entry.control {
+        let due = match last_seek {
+          Some((at, when)) => at != pos || when.elapsed() >= SEEK_RETRY,
+          None => true
+        };
+        if due {
+          control.seek(pos);
+          last_seek = Some((pos, Instant::now()));
+        }
+      }
+      std::thread::sleep(WAIT_POLL);
+      continue;
+    }
+    last_progress = Instant::now();
+    // Файл открываем, когда в нем уже есть нужные байты: TDLib мог создать его не сразу.
+    let f = match file.as_mut() {
+      Some(f) => f,
+      None => file.insert(std::fs::File::open(&entry.path)?)
+    };
+    let want = (available - pos).min(end + 1 - pos).min(CHUNK as u64) as usize;
+    f.seek(SeekFrom::Start(pos))?;
+    let n = f.read(&mut buf[..want])?;
+    if n == 0 {
+      std::thread::sleep(WAIT_POLL);
+      continue;
+    }
+    stream.write_all(&buf[..n])?;
+    pos += n as u64;
+  }
+  stream.flush()
+}
+
+/// Первый диапазон из `bytes=a-b`, `bytes=a-` или `bytes=-n` в пределах файла.
+fn parse_range(header: &str, total: u64) -> Option<(u64, u64)> {
+  let spec = header.trim().strip_prefix("bytes=")?.split(',').next()?.trim();
+  let (from, to) = spec.split_once('-')?;
+  if total == 0 {
+    return None;
+  }
+  let (start, end) = match (from.trim(), to.trim()) {
+    ("", suffix) => {
+      let n = suffix.parse::<u64>().ok()?.min(total);
+      if n == 0 {
+        return None;
+      }
+      (total - n, total - 1)
+    }
+    (from, "") => (from.parse::<u64>().ok()?, total - 1),
+    (from, to) => (from.parse::<u64>().ok()?, to.parse::<u64>().ok()?.min(total - 1))
+  };
+  (start <= end && start < total).then_some((start, end))
+}
+
+fn respond_empty(stream: &mut TcpStream, status: &str) -> std::io::Result<()> {
+  let head = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+  stream.write_all(head.as_bytes())?;
+  stream.flush()
+}
+
+/// Имя файла в адресе: по нему плеер показывает название и узнает формат.
+fn encode_path_segment(name: &str) -> String {
+  name
+    .bytes()
+    .map(|b| match b {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+      _ => format!("%{b:02X}")
+    })
+    .collect()
+}
+
+fn random_id() -> anyhow::Result<String> {
+  let mut buf = [0u8; 16];
+  getrandom_fill(&mut buf).map_err(|e| anyhow::anyhow!("Не удалось сгенерировать id потока: {e}"))?;
+  Ok(hex::encode(buf))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn request(port: u16, target: &str, range: Option<&str>) -> Vec<u8> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let range = range.map(|r| format!("Range: {r}\r\n")).unwrap_or_default();
+    write!(stream, "GET {target} HTTP/1.1\r\nHost: localhost\r\n{range}\r\n").unwrap();
+    let mut out = Vec::new();
+    stream.read_to_end(&mut out).unwrap();
+    out
+  }
+
+  #[test]
+  fn parses_byte_ranges() {
+    assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 99)));
+    assert_eq!(parse_range("bytes=900-", 1000), Some((900, 999)));
+    assert_eq!(parse_range("bytes=-100", 1000), Some((900, 999)));
+    assert_eq!(parse_range("bytes=990-2000", 1000), Some((990, 999)));
+    assert_eq!(parse_range("bytes=1000-", 1000), None);
+    assert_eq!(parse_range("bytes=5-1", 1000), None);
+    assert_eq!(parse_range("items=0-1", 1000), None);
+  }
+
+  #[test]
+  fn serves_ranges_as_bytes_arrive() {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("clip.mp4");
+    std::fs::write(&path, b"0123456789").unwrap();
+    let handle = start().unwrap();
+    let available = Available::default();
+    available.sink()(0, 4);
+    let url = handle.register(path, "my clip.mp4", 10, available.clone(), None).unwrap();
+    let target = url.split_once(&format!(":{}", handle.port)).unwrap().1.to_string();
+    assert!(target.ends_with("/my%20clip.mp4"));
+
+    let head = request(handle.port, &target, Some("bytes=0-3"));
+    let text = String::from_utf8_lossy(&head);
+    assert!(text.starts_with("HTTP/1.1 206"));
+    assert!(text.contains("Content-Range: bytes 0-3/10"));
+    assert!(text.ends_with("0123"));
+
+    // Конец файла еще не скачан: ответ дождется его.
+    let port = handle.port;
+    let waiting = std::thread::spawn(move || request(port, &target, Some("bytes=8-")));
+    std::thread::sleep(Duration::from_millis(300));
+    available.sink()(8, 10);
+    assert!(String::from_utf8_lossy(&waiting.join().unwrap()).ends_with("89"));
+
+    assert!(String::from_utf8_lossy(&request(handle.port, "/unknown/x.mp4", None)).starts_with("HTTP/1.1 404"));
+    assert!(handle.register(tmp.path().join("a.exe"), "a.exe", 1, Available::complete(1), None).is_err());
+    handle.stop();
+  }
+
+  #[test]
+  fn available_merges_ranges() {
+    let available = Available::default();
+    available.add(0, 4);
+    available.add(10, 20);
+    assert_eq!(available.end_from(2), 4);
+    assert_eq!(available.end_from(4), 4);
+    assert_eq!(available.end_from(12), 20);
+    available.add(4, 10);
+    assert_eq!(available.end_from(0), 20);
+    assert_eq!(available.downloaded(), 20);
+  }
+
+  struct SeekRecorder {
+    available: Available,
+    seeks: Mutex<Vec<u64>>
+  }
+
+  impl StreamControl for SeekRecorder {
+    fn seek(&self, offset: u64) {
+      self.seeks.lock().push(offset);
+      self.available.add(offset, offset + 2);
+    }
+
+    fn release(&self) {}
+  }
+
+  #[test]
+  fn seeks_download_to_requested_range() {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("movie.mp4");
+    std::fs::write(&path, b"0123456789").unwrap();
+    let handle = start().unwrap();
+    let available = Available::default();
+    available.add(0, 2);
+    let control = Arc::new(SeekRecorder { available: available.clone(), seeks: Mutex::new(Vec::new()) });
+    let url = handle.register(path, "movie.mp4", 10, available, Some(control.clone())).unwrap();
+    let target = url.split_once(&format!(":{}", handle.port)).unwrap().1.to_string();
+
+    // Конец еще не скачан: скачивание перематывается к нему, а не ждет очереди с начала.
+    let body = request(handle.port, &target, Some("bytes=6-7"));
+    assert!(String::from_utf8_lossy(&body).ends_with("67"));
+    assert_eq!(*control.seeks.lock(), vec![6]);
+    handle.stop();
+  }
+}
@@ -0,0 +1,255 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+/// Предупреждения о лимитах Telegram, замеченные по ошибкам и обновлениям TDLib.
+/// Ошибки с таймером (flood wait, slow mode) живут до конца ожидания,
+/// остальные — сутки, чтобы не висеть вечно после исправления.
+const DEFAULT_TTL_SECS: i64 = 24 * 60 * 60;
+/// Предупреждаем, когда до лимита закрепленных чатов осталось столько мест.
+const PINNED_HEADROOM: i64 = 1;
+
+// У каждого аккаунта свой клиент TDLib, и отложенный аккаунт продолжает
+// получать обновления: его лимиты не должны попадать в предупреждения активного.
+static ACCOUNTS: Lazy<Mutex<HashMap<String, AccountLimits>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Default)]
+struct AccountLimits {
+  warnings: HashMap<LimitKind, LimitWarning>,
+  options: HashMap<String, i64>,
+  pinned_chats: HashSet<i64>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LimitKind {
+  ChannelsTooMuch,
+  PinnedChats,
+  FloodWait,
+  SlowMode,
+  FileTooBig
+}
+
+impl LimitKind {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      LimitKind::ChannelsTooMuch => "channels_too_much",
+      LimitKind::PinnedChats => "pinned_chats",
+      LimitKind::FloodWait => "flood_wait",
+      LimitKind::SlowMode => "slow_mode",
+      LimitKind::FileTooBig => "file_too_big"
+    }
+  }
+
+  fn severity(&self) -> &'static str {
+    match self {
+      LimitKind::ChannelsTooMuch | LimitKind::FileTooBig => "critical",
+      _ => "warning"
+    }
+  }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct LimitWarning {
+  pub code: String,
+  pub severity: String,
+  pub message: String,
+  pub action: String,
+  pub detected_at: i64,
+  pub expires_at: i64
+}
+
+/// Разбирает текст ошибки TDLib и запоминает предупреждение, если это лимит.
+pub fn record_error(account: &str, message: &str) -> Option<LimitKind> {
+  let (kind, wait) = classify_error(message)?;
+  let now = Utc::now().timestamp();
+  let expires_at = now + wait.unwrap_or(DEFAULT_TTL_SECS).max(1);
+  let (text, action) = describe(kind, wait);
+  tracing::warn!(event = "tg_limit_detected", account = account, code = kind.as_str(), wait = wait.unwrap_or(0), "Обнаружен лимит Telegram");
+  ACCOUNTS.lock().entry(account.to_string()).or_default().warnings.insert(kind, LimitWarning {
+    code: kind.as_str().to_string(),
+    severity: kind.severity().to_string(),
+    message: text,
+    action: action.to_string(),
+    detected_at: now,
+    expires_at
+  });
+  Some(kind)
+}
+
+/// Сколько Telegram просит подождать (`retry_after`), если ошибка — FLOOD_WAIT.
+pub fn flood_wait(message: &str) -> Option<Duration> {
+  match classify_error(message)? {
+    (LimitKind::FloodWait, Some(secs)) => Some(Duration::from_secs(secs.max(0) as u64)),
+    _ => None
+  }
+}
+
+/// Запоминает числовые опции из `updateOption`, нужные для оценки лимитов.
+pub fn record_option(account: &str, name: &str, value: i64) {
+  if matches!(name, "pinned_chat_count_max" | "pinned_archived_chat_count_max") {
+    ACCOUNTS.lock().entry(account.to_string()).or_default().options.insert(name.to_string(), value);
+  }
+}
+
+/// Учитывает `updateChatPosition` основного списка чатов.
+pub fn record_chat_position(account: &str, chat_id: i64, is_pinned: bool) {
+  let mut guard = ACCOUNTS.lock();
+  let limits = guard.entry(account.to_string()).or_default();
+  if is_pinned {
+    limits.pinned_chats.insert(chat_id);
+  } else {
+    limits.pinned_chats.remove(&chat_id);
+  }
+  check_pinned_count(limits);
+}
+
+/// Забывает предупреждения об ошибках аккаунта, который уходит в фон:
+/// к возвращению они устареют. Закрепленные чаты и опции TDLib присылает
+/// только при запуске клиента, поэтому они остаются.
+pub fn forget_warnings(account: &str) {
+  let mut guard = ACCOUNTS.lock();
+  if let Some(limits) = guard.get_mut(account) {
+    limits.warnings.clear();
+    check_pinned_count(limits);
+  }
+}
+
+fn check_pinned_count(limits: &mut AccountLimits) {
+  let Some(max) = limits.options.get("pinned_chat_count_max").copied() else {
+    return;
+  };
+  let pinned = limits.pinned_chats.len() as i64;
+  if max <= 0 || pinned + PINNED_HEADROOM < max {
+    limits.warnings.remove(&LimitKind::PinnedChats);
+    return;
+  }
+  let now = Utc::now().timestamp();
+  limits.warnings.insert(LimitKind::PinnedChats, LimitWarning {
+    code: LimitKind::PinnedChats.as_str().to_string(),
+    severity: LimitKind::PinnedChats.severity().to_string(),
+    message: format!("Закреплено {pinned} из {max} чатов."),
+    action: "Открепи ненужные чаты, чтобы CloudTG мог закрепить канал хранения.".to_string(),
+    detected_at: now,
+    expires_at: now + DEFAULT_TTL_SECS
+  });
+}
+
+/// Актуальные предупреждения аккаунта, самые серьезные первыми.
+pub fn active_warnings(account: &str) -> Vec<LimitWarning> {
+  let now = Utc::now().timestamp();
+  let mut guard = ACCOUNTS.lock();
+  let Some(limits) = guard.get_mut(account) else {
+    return Vec::new();
+  };
+  limits.warnings.retain(|_, w| w.expires_at > now);
+  let mut out: Vec<LimitWarning> = limits.warnings.values().cloned().collect();
+  out.sort_by(|a, b| {
+    (a.severity != "critical")
+      .cmp(&(b.severity != "critical"))
+      .then_with(|| b.detected_at.cmp(&a.detected_at))
+  });
+  out
+}
+
+fn classify_error(message: &str) -> Option<(LimitKind, Option<i64>)> {
+  let upper = message.to_uppercase();
+  if upper.contains("CHANNELS_TOO_MUCH") || upper.contains("CHANNELS_ADMIN_PUBLIC_TOO_MUCH") {
+    return Some((LimitKind::ChannelsTooMuch, None));
+  }
+  if upper.contains("PINNED_DIALOGS_TOO_MUCH") {
+    return Some((LimitKind::PinnedChats, None));
+  }
+  if upper.contains("SLOWMODE_WAIT") {
+    return Some((LimitKind::SlowMode, trailing_number(&upper)));
+  }
+  if upper.contains("FLOOD_WAIT") || upper.contains("TOO MANY REQUESTS") {
+    return Some((LimitKind::FloodWait, trailing_number(&upper)));
+  }
+  if upper.contains("FILE_PARTS_INVALID") || upper.contains("FILE IS TOO BIG") || upper.contains("REQUEST ENTITY TOO LARGE") {
+    return Some((LimitKind::FileTooBig, None));
+  }
+  None
+}
+
+fn trailing_number(value: &str) -> Option<i64> {
+  let digits: String = value
+    .chars()
+    .rev()
+    .skip_while(|c| !c.is_ascii_digit())
+    .take_while(|c| c.is_ascii_digit())
+    .collect();
+  digits.chars().rev().collect::<String>().parse::<i64>().ok()
+}
+
+fn describe(kind: LimitKind, wait: Option<i64>) -> (String, &'static str) {
+  match kind {
+    LimitKind::ChannelsTooMuch => (
+      "Аккаунт состоит в максимальном числе каналов.".to_string(),
+      "Выйди из ненужных каналов и групп, иначе CloudTG не сможет создать канал хранения или бэкапа."
+    ),
+    LimitKind::PinnedChats => (
+      "Достигнут лимит закрепленных чатов.".to_string(),
+      "Открепи ненужные чаты, чтобы CloudTG мог закрепить канал хранения."
+    ),
+    LimitKind::FloodWait => (
+      match wait {
+        Some(secs) => format!("Telegram ограничил частоту запросов на {secs} с."),
+        None => "Telegram ограничил частоту запросов.".to_string()
+      },
+      "Подожди окончания ограничения и не запускай массовые операции."
+    ),
+    LimitKind::SlowMode => (
+      match wait {
+        Some(secs) => format!("В канале включен медленный режим, следующая отправка через {secs} с."),
+        None => "В канале включен медленный режим.".to_string()
+      },
+      "Отключи медленный режим в настройках канала хранения."
+    ),
+    LimitKind::FileTooBig => (
+      "Файл превышает лимит размера загрузки Telegram.".to_string(),
+      "Раздели файл на части или используй аккаунт с повышенным лимитом."
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn classify_extracts_wait_seconds() {
+    assert_eq!(classify_error("Too Many Requests: retry after 42"), Some((LimitKind::FloodWait, Some(42))));
+    assert_eq!(classify_error("SLOWMODE_WAIT_30"), Some((LimitKind::SlowMode, Some(30))));
+    assert_eq!(classify_error("CHANNELS_TOO_MUCH"), Some((LimitKind::ChannelsTooMuch, None)));
+    assert_eq!(classify_error("Chat not found"), None);
+    assert_eq!(flood_wait("FLOOD_WAIT_7"), Some(Duration::from_secs(7)));
+    assert_eq!(flood_wait("SLOWMODE_WAIT_30"), None);
+  }
+
+  #[test]
+  fn pinned_warning_follows_chat_positions() {
+    let pinned = |account: &str| active_warnings(account).iter().any(|w| w.code == "pinned_chats");
+    record_option("pinned", "pinned_chat_count_max", 3);
+    record_chat_position("pinned", 1, true);
+    assert!(!pinned("pinned"));
+    record_chat_position("pinned", 2, true);
+    assert!(pinned("pinned"));
+    // Закрепленное состояние переживает уход аккаунта в фон.
+    forget_warnings("pinned");
+    assert!(pinned("pinned"));
+    record_chat_position("pinned", 2, false);
+    assert!(!pinned("pinned"));
+  }
+
+  #[test]
+  fn warnings_stay_with_their_account() {
+    record_error("parked", "FLOOD_WAIT_30");
+    assert!(active_warnings("parked").iter().any(|w| w.code == "flood_wait"));
+    assert!(active_warnings("active").is_empty());
+    forget_warnings("parked");
+    assert!(active_warnings("parked").is_empty());
+  }
+}
@@ -3,7 +3,9 @@ use std::{collections::VecDeque, path::PathBuf};
 use parking_lot::Mutex;
 
 use crate::paths::Paths;
-use super::{ChatId, MessageId, TelegramService, TgError, UploadedMessage, SearchMessagesResult, HistoryMessage, ChatInfo};
+use super::{ChatId, ChatRef, MessageId, TelegramService, TgError, UploadedMessage, SearchMessagesResult, HistoryMessage, ChatInfo, ChatFolder, StickerSetInfo};
+
+const SAVED_MESSAGES_CHAT_ID: ChatId = 999;
 
 pub struct MockTelegram {
   paths: Paths,
@@ -28,6 +30,20 @@ impl MockTelegram {
     }
   }
 
+  /// Ошибка, внедренная через симуляцию (`dev`).
+  #[cfg(feature = "dev")]
+  fn fault(&self, method: &str) -> Result<(), TgError> {
+    match crate::dev::take_fault(method) {
+      Some(err) => Err(err),
+      None => Ok(())
+    }
+  }
+
+  #[cfg(not(feature = "dev"))]
+  fn fault(&self, _method: &str) -> Result<(), TgError> {
+    Ok(())
+  }
+
   fn alloc_msg_id(&self) -> MessageId {
     let mut g = self.next_msg_id.lock();
     let id = *g;
@@ -66,6 +82,15 @@ impl TelegramService for MockTelegram {
     Ok(())
   }
 
+  async fn storage_chat_ref(&self, _chat_id: ChatId) -> Result<ChatRef, TgError> {
+    Ok(ChatRef::default())
+  }
+
+  async fn storage_resolve_chat_ref(&self, _chat_ref: &ChatRef) -> Result<Option<ChatId>, TgError> {
+    self.fault("storage_resolve_chat_ref")?;
+    Ok(None)
+  }
+
   async fn backup_check_channel(&self, _chat_id: ChatId) -> Result<bool, TgError> {
     Ok(*self.authed.lock())
   }
@@ -75,18 +100,30 @@ impl TelegramService for MockTelegram {
     Ok(*self.backup_chat_id.lock())
   }
 
-  async fn chat_history(&self, _chat_id: ChatId, _from_message_id: MessageId, _limit: i32)
+  async fn chat_history(&self, chat_id: ChatId, from_message_id: MessageId, limit: i32)
     -> Result<SearchMessagesResult, TgError> {
+    self.fault("chat_history")?;
+    if cfg!(feature = "dev") {
+      return Ok(crate::dev::history_page(chat_id, from_message_id, limit, None));
+    }
     Ok(SearchMessagesResult { total_count: None, next_from_message_id: 0, messages: Vec::new() })
   }
 
-  async fn search_chat_messages(&self, _chat_id: ChatId, _query: String, _from_message_id: MessageId, _limit: i32)
+  async fn search_chat_messages(&self, chat_id: ChatId, query: String, from_message_id: MessageId, limit: i32)
     -> Result<SearchMessagesResult, TgError> {
+    self.fault("search_chat_messages")?;
+    if cfg!(feature = "dev") {
+      return Ok(crate::dev::history_page(chat_id, from_message_id, limit, Some(&query)));
+    }
     Ok(SearchMessagesResult { total_count: Some(0), next_from_message_id: 0, messages: Vec::new() })
   }
 
-  async fn search_storage_messages(&self, _chat_id: ChatId, _from_message_id: MessageId, _limit: i32)
+  async fn search_storage_messages(&self, chat_id: ChatId, from_message_id: MessageId, limit: i32)
     -> Result<SearchMessagesResult, TgError> {
+    self.fault("search_storage_messages")?;
+    if cfg!(feature = "dev") {
+      return Ok(crate::dev::history_page(chat_id, from_message_id, limit, Some("#ocltg")));
+    }
     Ok(SearchMessagesResult { total_count: Some(0), next_from_message_id: 0, messages: Vec::new() })
   }
 
@@ -98,27 +135,47 @@ impl TelegramService for MockTelegram {
     Ok(Vec::new())
   }
 
+  async fn chat_folders(&self) -> Result<Vec<ChatFolder>, TgError> {
+    Ok(Vec::new())
+  }
+
+  async fn folder_chats(&self, _folder_id: i32, _limit: i32) -> Result<Vec<ChatInfo>, TgError> {
+    Ok(Vec::new())
+  }
+
+  async fn saved_messages_chat(&self) -> Result<ChatId, TgError> {
+    self.fault("saved_messages_chat")?;
+    Ok(SAVED_MESSAGES_CHAT_ID)
+  }
+
   async fn send_text_message(&self, chat_id: ChatId, text: String) -> Result<UploadedMessage, TgError> {
+    self.fault("send_text_message")?;
     let msg = UploadedMessage { chat_id, message_id: self.alloc_msg_id(), caption_or_text: text };
     self.messages.lock().push_back(msg.clone());
     Ok(msg)
   }
 
   async fn send_dir_message(&self, chat_id: ChatId, text: String) -> Result<UploadedMessage, TgError> {
+    self.fault("send_dir_message")?;
     let msg = UploadedMessage { chat_id, message_id: self.alloc_msg_id(), caption_or_text: text };
     self.messages.lock().push_back(msg.clone());
     Ok(msg)
   }
 
   async fn edit_message_text(&self, _chat_id: ChatId, _message_id: MessageId, _text: String) -> Result<(), TgError> {
-    Ok(())
+    self.fault("edit_message_text")
   }
 
   async fn edit_message_caption(&self, _chat_id: ChatId, _message_id: MessageId, _caption: String) -> Result<(), TgError> {
+    self.fault("edit_message_caption")
+  }
+
+  async fn pin_message(&self, _chat_id: ChatId, _message_id: MessageId) -> Result<(), TgError> {
     Ok(())
   }
 
   async fn send_file(&self, chat_id: ChatId, path: PathBuf, caption: String) -> Result<UploadedMessage, TgError> {
+    self.fault("send_file")?;
     let uploads_dir = self.paths.cache_dir.join("mock_uploads");
     std::fs::create_dir_all(&uploads_dir).map_err(TgError::Io)?;
     let filename = path.file_name().unwrap_or_default().to_string_lossy().to_string();
@@ -156,6 +213,7 @@ impl TelegramService for MockTelegram {
   }
 
   async fn download_message_file(&self, _chat_id: ChatId, _message_id: MessageId, target: PathBuf) -> Result<PathBuf, TgError> {
+    self.fault("download_message_file")?;
     if let Some(parent) = target.parent() { std::fs::create_dir_all(parent).map_err(TgError::Io)?; }
     if !target.exists() {
       std::fs::write(&target, b"mock download: tdlib not enabled\n").map_err(TgError::Io)?;
@@ -163,11 +221,27 @@ impl TelegramService for MockTelegram {
     Ok(target)
   }
 
-  async fn message_exists(&self, _chat_id: ChatId, _message_id: MessageId) -> Result<bool, TgError> {
+  async fn message_exists(&self, chat_id: ChatId, message_id: MessageId) -> Result<bool, TgError> {
+    self.fault("message_exists")?;
+    if cfg!(feature = "dev") {
+      if let Some(exists) = crate::dev::message_exists(chat_id, message_id) {
+        return Ok(exists);
+      }
+    }
     Ok(true)
   }
 
-  async fn delete_messages(&self, _chat_id: ChatId, _message_ids: Vec<MessageId>, _revoke: bool) -> Result<(), TgError> {
+  async fn recognize_speech(&self, _chat_id: ChatId, _message_id: MessageId) -> Result<String, TgError> {
+    Ok(String::new())
+  }
+
+  async fn sticker_set_info(&self, set_id: String) -> Result<StickerSetInfo, TgError> {
+    Ok(StickerSetInfo { name: format!("mock_{set_id}"), title: format!("Mock {set_id}"), id: set_id })
+  }
+
+  async fn delete_messages(&self, chat_id: ChatId, message_ids: Vec<MessageId>, _revoke: bool) -> Result<(), TgError> {
+    self.fault("delete_messages")?;
+    crate::dev::delete_messages(chat_id, &message_ids);
     Ok(())
   }
 }
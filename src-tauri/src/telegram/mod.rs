@@ -12,6 +12,41 @@ pub struct UploadedMessage {
   pub caption_or_text: String
 }
 
+/// Ход скачивания: сколько байт уже получено и полный размер, если известен.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct DownloadProgress {
+  pub downloaded: u64,
+  pub total: Option<u64>
+}
+
+/// Файл, который TDLib докачивает в свой кеш. Читать его можно в пределах
+/// скачанных диапазонов, о которых сообщает `RangeSink`.
+#[derive(Debug, Clone)]
+pub struct StreamingFile {
+  pub path: std::path::PathBuf,
+  pub total: Option<u64>,
+  /// Id файла у backend'а и ждущего его потока: по ним поток перематывается
+  /// и останавливается.
+  pub file_id: i64,
+  pub token: i64
+}
+
+/// Приемник скачанных диапазонов потокового файла: начало и конец (не
+/// включая). Вызывается из потока обновлений backend'а.
+pub type RangeSink = Arc<dyn Fn(u64, u64) + Send + Sync>;
+
+/// Приемник хода скачивания. Backend вызывает его из своего потока
+/// обновлений, поэтому он должен отрабатывать быстро.
+pub type ProgressSink = Arc<dyn Fn(DownloadProgress) + Send + Sync>;
+
+/// Одна правка подписи в пакете `edit_message_captions`.
+#[derive(Debug, Clone)]
+pub struct CaptionEdit {
+  pub chat_id: ChatId,
+  pub message_id: MessageId,
+  pub caption: String
+}
+
 #[derive(Debug, Clone)]
 pub struct HistoryMessage {
   pub id: MessageId,
@@ -19,7 +54,40 @@ pub struct HistoryMessage {
   pub text: Option<String>,
   pub caption: Option<String>,
   pub file_size: Option<i64>,
-  pub file_name: Option<String>
+  pub file_name: Option<String>,
+  pub sender_id: Option<i64>,
+  /// Общий id сообщений одного альбома (media_album_id в TDLib).
+  pub media_group_id: Option<String>,
+  /// Тип содержимого TDLib как есть: `messageDocument`, `messageVoiceNote`...
+  pub content_type: Option<String>,
+  /// Набор, из которого отправлен стикер (set_id в TDLib).
+  pub sticker_set_id: Option<String>,
+  /// remote.unique_id файла: один и тот же у всех пересланных копий.
+  pub file_unique_id: Option<String>
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct StickerSetInfo {
+  pub id: String,
+  pub name: String,
+  pub title: String
+}
+
+/// `messageVoiceNote` -> `voice_note`: короткое имя типа для БД и настроек.
+pub fn content_kind(content_type: &str) -> String {
+  let raw = content_type.strip_prefix("message").unwrap_or(content_type);
+  let mut out = String::with_capacity(raw.len() + 4);
+  for (i, ch) in raw.chars().enumerate() {
+    if ch.is_uppercase() {
+      if i > 0 {
+        out.push('_');
+      }
+      out.extend(ch.to_lowercase());
+    } else {
+      out.push(ch);
+    }
+  }
+  out
 }
 
 #[derive(Debug, Clone)]
@@ -34,7 +102,24 @@ pub struct ChatInfo {
   pub id: ChatId,
   pub title: String,
   pub kind: String,
-  pub username: Option<String>
+  pub username: Option<String>,
+  /// Локальный путь к маленькой аватарке в кеше приложения.
+  pub photo_path: Option<String>
+}
+
+/// Папка чатов Telegram (chat folder), как ее видит пользователь.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ChatFolder {
+  pub id: i32,
+  pub title: String
+}
+
+/// Устойчивые признаки канала, по которым его можно найти, если chat_id
+/// перестал открываться (например, после потери базы TDLib).
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ChatRef {
+  pub supergroup_id: Option<i64>,
+  pub invite_link: Option<String>
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -43,6 +128,9 @@ pub enum TgError {
   NotImplemented,
   #[error("требуется авторизация")]
   AuthRequired,
+  /// Вызов не дождался подтверждения, но TDLib продолжает отправку.
+  #[error("Telegram еще не подтвердил отправку, она продолжается в фоне")]
+  SendPending,
   #[error("ошибка ввода-вывода: {0}")]
   Io(#[from] std::io::Error),
   #[error("{0}")]
@@ -63,6 +151,10 @@ pub trait TelegramService: Send + Sync {
   async fn storage_get_or_create_channel(&self) -> Result<ChatId, TgError>;
   async fn storage_create_channel(&self) -> Result<ChatId, TgError>;
   async fn storage_delete_channel(&self, chat_id: ChatId) -> Result<(), TgError>;
+  async fn storage_chat_ref(&self, chat_id: ChatId) -> Result<ChatRef, TgError>;
+  /// Прогревает списки чатов и ищет канал хранения по `ChatRef`;
+  /// возвращает только проверенный `storage_check_channel` chat_id.
+  async fn storage_resolve_chat_ref(&self, chat_ref: &ChatRef) -> Result<Option<ChatId>, TgError>;
   async fn backup_check_channel(&self, chat_id: ChatId) -> Result<bool, TgError>;
   async fn backup_get_or_create_channel(&self) -> Result<ChatId, TgError>;
   async fn chat_history(&self, chat_id: ChatId, from_message_id: MessageId, limit: i32)
@@ -73,11 +165,27 @@ pub trait TelegramService: Send + Sync {
     -> Result<SearchMessagesResult, TgError>;
   async fn search_chats(&self, query: String, limit: i32) -> Result<Vec<ChatInfo>, TgError>;
   async fn recent_chats(&self, limit: i32) -> Result<Vec<ChatInfo>, TgError>;
+  /// Папки чатов аккаунта в порядке, заданном пользователем.
+  async fn chat_folders(&self) -> Result<Vec<ChatFolder>, TgError>;
+  async fn folder_chats(&self, folder_id: i32, limit: i32) -> Result<Vec<ChatInfo>, TgError>;
+  /// Чат «Избранное» (Saved Messages) текущего аккаунта.
+  async fn saved_messages_chat(&self) -> Result<ChatId, TgError>;
 
   async fn send_text_message(&self, chat_id: ChatId, text: String) -> Result<UploadedMessage, TgError>;
   async fn send_dir_message(&self, chat_id: ChatId, text: String) -> Result<UploadedMessage, TgError>;
   async fn edit_message_text(&self, chat_id: ChatId, message_id: MessageId, text: String) -> Result<(), TgError>;
   async fn edit_message_caption(&self, chat_id: ChatId, message_id: MessageId, caption: String) -> Result<(), TgError>;
+  /// Правит подписи пачкой и возвращает результат по каждой правке в порядке
+  /// входа. Ошибка всего вызова означает, что до правок дело не дошло.
+  /// По умолчанию правки идут по одной через `edit_message_caption`.
+  async fn edit_message_captions(&self, edits: Vec<CaptionEdit>) -> Result<Vec<Result<(), TgError>>, TgError> {
+    let mut out = Vec::with_capacity(edits.len());
+    for edit in edits {
+      out.push(self.edit_message_caption(edit.chat_id, edit.message_id, edit.caption).await);
+    }
+    Ok(out)
+  }
+  async fn pin_message(&self, chat_id: ChatId, message_id: MessageId) -> Result<(), TgError>;
   async fn send_file(&self, chat_id: ChatId, path: std::path::PathBuf, caption: String) -> Result<UploadedMessage, TgError>;
   async fn send_file_from_message(&self, chat_id: ChatId, message_id: MessageId, caption: String) -> Result<UploadedMessage, TgError>;
   async fn forward_message(&self, from_chat_id: ChatId, to_chat_id: ChatId, message_id: MessageId) -> Result<MessageId, TgError>;
@@ -86,9 +194,56 @@ pub trait TelegramService: Send + Sync {
   async fn delete_messages(&self, chat_id: ChatId, message_ids: Vec<MessageId>, revoke: bool) -> Result<(), TgError>;
 
   async fn download_message_file(&self, chat_id: ChatId, message_id: MessageId, target: std::path::PathBuf) -> Result<std::path::PathBuf, TgError>;
+  /// Как `download_message_file`, но сообщает ход скачивания в `progress`.
+  /// По умолчанию ход не сообщается.
+  async fn download_message_file_with_progress(
+    &self,
+    chat_id: ChatId,
+    message_id: MessageId,
+    target: std::path::PathBuf,
+    _progress: ProgressSink
+  ) -> Result<std::path::PathBuf, TgError> {
+    self.download_message_file(chat_id, message_id, target).await
+  }
+  /// Начинает скачивание файла сообщения с высоким приоритетом и
+  /// возвращает путь к частично скачанному файлу, как только он появился.
+  /// В `ranges` приходят непрерывно скачанные диапазоны.
+  async fn stream_message_file(
+    &self,
+    _chat_id: ChatId,
+    _message_id: MessageId,
+    _ranges: RangeSink
+  ) -> Result<StreamingFile, TgError> {
+    Err(TgError::NotImplemented)
+  }
+  /// Продолжает потоковое скачивание с `offset`: плеер перемотал дальше
+  /// скачанного.
+  async fn stream_seek(&self, _file: &StreamingFile, _offset: u64) -> Result<(), TgError> {
+    Err(TgError::NotImplemented)
+  }
+  /// Останавливает потоковое скачивание, когда плеер его больше не читает.
+  async fn stream_stop(&self, _file: &StreamingFile) -> Result<(), TgError> {
+    Err(TgError::NotImplemented)
+  }
   async fn message_exists(&self, chat_id: ChatId, message_id: MessageId) -> Result<bool, TgError>;
+  /// Проверяет сообщения пачкой и возвращает ответ по каждому в порядке входа.
+  /// По умолчанию проверки идут по одной через `message_exists`.
+  async fn messages_exist(&self, chat_id: ChatId, message_ids: Vec<MessageId>) -> Result<Vec<bool>, TgError> {
+    let mut out = Vec::with_capacity(message_ids.len());
+    for message_id in message_ids {
+      out.push(self.message_exists(chat_id, message_id).await?);
+    }
+    Ok(out)
+  }
+  /// Расшифровка голосового или видеосообщения силами Telegram (нужен Premium).
+  async fn recognize_speech(&self, chat_id: ChatId, message_id: MessageId) -> Result<String, TgError>;
+  async fn sticker_set_info(&self, set_id: String) -> Result<StickerSetInfo, TgError>;
 }
 
+pub mod limits;
+pub mod send_queue;
+pub mod timeouts;
+
 #[cfg(feature = "mock_telegram")]
 mod mock;
 #[cfg(feature = "mock_telegram")]
@@ -97,20 +252,31 @@ pub use mock::MockTelegram;
 #[cfg(feature = "tdlib")]
 mod tdlib;
 
+/// Отбрасывает realtime-обновления, еще ждущие в очереди индексации: после
+/// смены аккаунта они относились бы к чужой базе.
+pub fn discard_realtime_updates() {
+  #[cfg(feature = "tdlib")]
+  tdlib::discard_realtime_updates();
+}
+
+/// `account` — аккаунт, которому принадлежит сессия: по нему клиент
+/// сообщает свое состояние авторизации и понимает, активен ли он.
 pub fn make_telegram_service(
   paths: Paths,
   app: tauri::AppHandle,
   tg_settings: Option<crate::secrets::TgCredentials>,
-  tdlib_path: Option<String>
+  tdlib_path: Option<String>,
+  account: String
 ) -> anyhow::Result<Arc<dyn TelegramService>> {
   #[cfg(feature = "mock_telegram")]
   {
+    let _ = account;
     return Ok(Arc::new(MockTelegram::new(paths, app)));
   }
 
   #[cfg(all(not(feature = "mock_telegram"), feature = "tdlib"))]
   {
-    Ok(Arc::new(tdlib::TdlibTelegram::new(paths, app, tg_settings, tdlib_path)?))
+    Ok(Arc::new(tdlib::TdlibTelegram::new(paths, app, tg_settings, tdlib_path, account)?))
   }
 
   #[cfg(all(not(feature = "mock_telegram"), not(feature = "tdlib")))]
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+use super::ChatId;
+
+/// Очередь служебных сообщений по чатам: сообщения директорий и правки подписей
+/// уходят в канал строго по одному и в порядке вызова, иначе другие клиенты могут
+/// увидеть переименование раньше создания. Загрузки файлов сюда не попадают.
+#[derive(Default)]
+pub struct ChatSendQueue {
+  chats: Mutex<HashMap<ChatId, Arc<AsyncMutex<()>>>>
+}
+
+impl ChatSendQueue {
+  /// Ждет своей очереди в чате. Пока гард жив, остальные служебные операции
+  /// этого чата стоят; tokio::Mutex выдает доступ в порядке запросов.
+  pub async fn acquire(&self, chat_id: ChatId) -> OwnedMutexGuard<()> {
+    let lane = self
+      .chats
+      .lock()
+      .entry(chat_id)
+      .or_insert_with(|| Arc::new(AsyncMutex::new(())))
+      .clone();
+    lane.lock_owned().await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  #[tokio::test]
+  async fn operations_in_one_chat_keep_call_order() {
+    let queue = Arc::new(ChatSendQueue::default());
+    let log = Arc::new(Mutex::new(Vec::new()));
+
+    let first = queue.acquire(1).await;
+    let mut tasks = Vec::new();
+    for step in ["create", "rename", "move"] {
+      let queue = queue.clone();
+      let log = log.clone();
+      tasks.push(tokio::spawn(async move {
+        let _turn = queue.acquire(1).await;
+        log.lock().push(step);
+      }));
+      // Даем задаче встать в очередь, прежде чем запускать следующую.
+      tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // Другой чат не ждет первый.
+    drop(queue.acquire(2).await);
+    assert!(log.lock().is_empty());
+
+    drop(first);
+    for task in tasks {
+      task.await.unwrap();
+    }
+    assert_eq!(*log.lock(), vec!["create", "rename", "move"]);
+  }
+}
@@ -1,15 +1,19 @@
 use std::{
+  borrow::Cow,
   collections::HashMap,
   ffi::{CStr, CString},
   io::{BufRead, BufReader, Read, Write, Cursor},
   os::raw::{c_char, c_double, c_int, c_void},
   path::{Path, PathBuf},
   process::{Command, Stdio},
-  sync::mpsc,
-  time::Duration
+  panic::AssertUnwindSafe,
+  sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering},
+  sync::{mpsc, Arc},
+  time::{Duration, Instant}
 };
 
 use libloading::Library;
+use futures_util::StreamExt;
 use serde::Deserialize;
 use serde_json::{json, Value};
 use sha2::{Digest, Sha256};
@@ -23,12 +27,19 @@ use image::{DynamicImage, ImageFormat, RgbaImage};
 use image::imageops::FilterType;
 use chrono::Utc;
 use parking_lot::Mutex;
+use once_cell::sync::{Lazy, OnceCell};
 
+use crate::github;
 use crate::paths::Paths;
 use crate::state::{AppState, AuthState};
 use crate::secrets::TgCredentials;
-use crate::app::{indexer, sync};
-use super::{ChatId, MessageId, TelegramService, TgError, UploadedMessage, HistoryMessage, SearchMessagesResult, ChatInfo};
+use crate::app::{ignore_list, indexer, pending_uploads, reconcile, storage_channels, sync, transcripts};
+use crate::fsmeta::parse_file_caption;
+use crate::events::{self, Change};
+use super::limits;
+use super::send_queue::ChatSendQueue;
+use super::timeouts::{self, RequestPolicy, TimeoutClass};
+use super::{CaptionEdit, ChatId, ChatRef, DownloadProgress, MessageId, ProgressSink, RangeSink, TelegramService, TgError, UploadedMessage, HistoryMessage, SearchMessagesResult, ChatInfo, ChatFolder, StickerSetInfo, StreamingFile};
 
 #[derive(Clone)]
 struct TdlibConfig {
@@ -141,7 +152,7 @@ impl TdlibClient {
   fn receive(&self, timeout: f64) -> Option<String> {
     let ptr = unsafe { (self.receive)(self.client, timeout) };
     if ptr.is_null() { return None; }
-    let s = unsafe { CStr::from_ptr(ptr) }.to_string_lossy().to_string();
+    let s = unsafe { CStr::from_ptr(ptr) }.to_string_lossy().into_owned();
     Some(s)
   }
 
@@ -168,18 +179,156 @@ pub struct TdlibTelegram {
   tx: mpsc::Sender<TdlibCommand>,
   paths: Paths,
   send_waiters: SendWaiters,
-  send_results: SendResults
+  send_results: SendResults,
+  download_watches: DownloadWatches,
+  metadata_queue: ChatSendQueue,
+  account: String
 }
 
 enum TdlibCommand {
   Td(String),
   SetConfig { api_id: i32, api_hash: String, tdlib_path: Option<String> },
-  Request { payload: Value, respond_to: oneshot::Sender<anyhow::Result<Value>> }
+  /// Ответ приходит текстом: разбирает его тот, кто ждет.
+  Request { payload: Value, respond_to: oneshot::Sender<anyhow::Result<String>> }
 }
 
-type PendingRequests = HashMap<u64, oneshot::Sender<anyhow::Result<Value>>>;
+/// Почему рабочий цикл TDLib вернул управление супервизору.
+#[derive(Debug, PartialEq, Eq)]
+enum WorkerExit {
+  Shutdown,
+  /// Клиент закрылся после выхода из аккаунта: это не сбой, нужен только новый клиент.
+  LoggedOut,
+  Restart(String)
+}
+
+#[derive(Clone, serde::Serialize)]
+struct TdlibRestartedEvent {
+  cause: String,
+  attempt: usize
+}
+
+// Частые перезапуски подряд разводим по времени, чтобы не крутиться вхолостую.
+const RESTART_WINDOW: Duration = Duration::from_secs(60);
+const RESTART_BACKOFF_BASE: Duration = Duration::from_secs(1);
+
+/// Учет перезапусков после сбоев за последние `RESTART_WINDOW`.
+#[derive(Default)]
+struct RestartPolicy {
+  recent: Vec<Instant>
+}
+
+impl RestartPolicy {
+  /// Записывает перезапуск и возвращает его номер в окне и паузу перед ним.
+  fn record(&mut self, now: Instant) -> (usize, Duration) {
+    self.recent.retain(|at| now.duration_since(*at) < RESTART_WINDOW);
+    self.recent.push(now);
+    let attempt = self.recent.len();
+    let backoff = if attempt > 1 {
+      RESTART_BACKOFF_BASE * 2u32.pow((attempt as u32 - 2).min(4))
+    } else {
+      Duration::ZERO
+    };
+    (attempt, backoff)
+  }
+}
+
+/// Закрытие клиента после `authorizationStateLoggingOut` — штатный выход,
+/// без него — сбой.
+fn closed_exit(logging_out: bool) -> WorkerExit {
+  if logging_out {
+    WorkerExit::LoggedOut
+  } else {
+    WorkerExit::Restart("клиент TDLib закрыт".to_string())
+  }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+  if let Some(s) = payload.downcast_ref::<&str>() {
+    (*s).to_string()
+  } else if let Some(s) = payload.downcast_ref::<String>() {
+    s.clone()
+  } else {
+    "неизвестная причина".to_string()
+  }
+}
+
+type PendingRequests = HashMap<u64, oneshot::Sender<anyhow::Result<String>>>;
 type SendWaiters = std::sync::Arc<Mutex<HashMap<i64, oneshot::Sender<anyhow::Result<i64>>>>>;
 type SendResults = std::sync::Arc<Mutex<HashMap<i64, Result<i64, String>>>>;
+/// Ожидающие скачивания по id файла TDLib: один файл могут ждать несколько вызовов.
+type DownloadWatches = std::sync::Arc<Mutex<HashMap<i64, Vec<DownloadWatch>>>>;
+
+struct DownloadWatch {
+  token: i64,
+  progress: Option<ProgressSink>,
+  /// Для потокового открытия: скачанные диапазоны вместо общего хода. Такой
+  /// ждущий снимается только по завершении или остановке потока.
+  ranges: Option<RangeSink>,
+  last_emit: Option<Instant>,
+  /// Скачивание точно идет: было активное обновление или TDLib уже ответил
+  /// на downloadFile. Неактивный файл до этого — старое состояние, а не обрыв.
+  started: bool,
+  done: oneshot::Sender<Result<Value, String>>
+}
+
+/// Отменяет скачивание в TDLib, если ждущий вызов прервали (отмена или пауза
+/// передачи): иначе TDLib докачивал бы файл, который уже никто не ждет.
+struct CancelDownloadOnDrop<'a> {
+  tg: &'a TdlibTelegram,
+  file_id: i64,
+  token: i64,
+  armed: bool
+}
+
+impl CancelDownloadOnDrop<'_> {
+  fn disarm(mut self) {
+    self.armed = false;
+  }
+}
+
+impl Drop for CancelDownloadOnDrop<'_> {
+  fn drop(&mut self) {
+    if self.armed && self.tg.forget_download_watch(self.file_id, self.token) {
+      self.tg.send_td(json!({"@type":"cancelDownloadFile","file_id": self.file_id,"only_if_pending": false}));
+    }
+  }
+}
+
+/// Удаляет еще не подтвержденное сообщение, если ждущий отправку вызов
+/// прервали: TDLib при этом останавливает загрузку файла.
+struct AbandonSendOnDrop<'a> {
+  tg: &'a TdlibTelegram,
+  chat_id: ChatId,
+  message_id: MessageId,
+  armed: bool
+}
+
+impl AbandonSendOnDrop<'_> {
+  fn disarm(mut self) {
+    self.armed = false;
+  }
+}
+
+impl Drop for AbandonSendOnDrop<'_> {
+  fn drop(&mut self) {
+    if !self.armed {
+      return;
+    }
+    self.tg.send_waiters.lock().remove(&self.message_id);
+    self.tg.send_results.lock().remove(&self.message_id);
+    self.tg.send_td(json!({
+      "@type":"deleteMessages",
+      "chat_id": self.chat_id,
+      "message_ids": [self.message_id],
+      "revoke": true
+    }));
+    tracing::info!(event = "tdlib_send_file_abandoned", chat_id = self.chat_id, message_id = self.message_id, "Отправка файла прервана");
+  }
+}
+
+static NEXT_DOWNLOAD_TOKEN: AtomicI64 = AtomicI64::new(1);
+/// Не чаще этого ход скачивания уходит наверх; завершение сообщается всегда.
+const DOWNLOAD_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
 
 const STORAGE_CHANNEL_TITLE: &str = "CloudTG";
 const STORAGE_CHANNEL_TITLE_LEGACY: &str = "CloudVault";
@@ -230,7 +379,7 @@ fn normalize_session_name(raw: &str) -> String {
 }
 
 fn ensure_tdlib_files_session_dirs(paths: &Paths, session_name: &str) -> std::io::Result<PathBuf> {
-  let files_dir = paths.cache_dir.join("tdlib_files").join(session_name);
+  let files_dir = paths.layout().tdlib_files_dir().join(session_name);
   std::fs::create_dir_all(files_dir.join("temp"))?;
   Ok(files_dir)
 }
@@ -357,6 +506,39 @@ fn extract_file_name(content: &Value) -> Option<String> {
   None
 }
 
+/// Id пользователя или чата из `sender_id` сообщения.
+fn extract_sender_id(sender: &Value) -> Option<i64> {
+  match sender.get("@type").and_then(|v| v.as_str()) {
+    Some("messageSenderUser") => sender.get("user_id").and_then(|v| v.as_i64()),
+    Some("messageSenderChat") => sender.get("chat_id").and_then(|v| v.as_i64()),
+    _ => None
+  }
+}
+
+/// `media_album_id` сообщения: TDLib отдает int64 строкой; 0 означает, что
+/// сообщение не из альбома.
+fn extract_media_group_id(album_id: &Value) -> Option<String> {
+  let raw = match album_id {
+    Value::String(s) => s.clone(),
+    Value::Number(n) => n.to_string(),
+    _ => return None
+  };
+  if raw.is_empty() || raw == "0" { None } else { Some(raw) }
+}
+
+fn extract_content_type(content: &Value) -> Option<String> {
+  content.get("@type").and_then(|v| v.as_str()).map(str::to_string)
+}
+
+fn extract_sticker_set_id(content: &Value) -> Option<String> {
+  let raw = match content.get("sticker")?.get("set_id")? {
+    Value::String(s) => s.clone(),
+    Value::Number(n) => n.to_string(),
+    _ => return None
+  };
+  if raw.is_empty() || raw == "0" { None } else { Some(raw) }
+}
+
 fn history_message_from_content(message_id: i64, date: i64, content: &Value) -> HistoryMessage {
   let (text, caption, file_size, file_name) = (
     extract_text(content),
@@ -364,7 +546,19 @@ fn history_message_from_content(message_id: i64, date: i64, content: &Value) ->
     extract_file_size(content),
     extract_file_name(content)
   );
-  HistoryMessage { id: message_id, date, text, caption, file_size, file_name }
+  HistoryMessage {
+    id: message_id,
+    date,
+    text,
+    caption,
+    file_size,
+    file_name,
+    sender_id: None,
+    media_group_id: None,
+    content_type: extract_content_type(content),
+    sticker_set_id: extract_sticker_set_id(content),
+    file_unique_id: extract_file_unique_id(content)
+  }
 }
 
 fn history_message_from_object(message: &Value) -> Option<(ChatId, HistoryMessage)> {
@@ -379,65 +573,466 @@ fn history_message_from_object(message: &Value) -> Option<(ChatId, HistoryMessag
   } else {
     (None, None, None, None)
   };
-  Some((chat_id, HistoryMessage { id: message_id, date, text, caption, file_size, file_name }))
+  Some((chat_id, HistoryMessage {
+    id: message_id,
+    date,
+    text,
+    caption,
+    file_size,
+    file_name,
+    sender_id: message.get("sender_id").and_then(extract_sender_id),
+    media_group_id: message.get("media_album_id").and_then(extract_media_group_id),
+    content_type: message.get("content").and_then(extract_content_type),
+    sticker_set_id: message.get("content").and_then(extract_sticker_set_id),
+    file_unique_id: message.get("content").and_then(extract_file_unique_id)
+  }))
+}
+
+/// Страница сообщений `getChatHistory` и `searchChatMessages`. Разбирается
+/// прямо из текста ответа: лишние поля сообщений пропускаются, в `Value`
+/// попадает только то, что читают извлекатели.
+#[derive(Deserialize)]
+struct MessagesPage {
+  total_count: Option<i64>,
+  next_from_message_id: Option<i64>,
+  /// TDLib может вернуть `null` на месте недоступного сообщения.
+  #[serde(default)]
+  messages: Vec<Option<PageMessage>>
+}
+
+#[derive(Deserialize)]
+struct PageMessage {
+  #[serde(default)]
+  id: i64,
+  #[serde(default)]
+  date: i64,
+  sender_id: Option<Value>,
+  media_album_id: Option<Value>,
+  content: Option<Value>
+}
+
+impl MessagesPage {
+  fn into_history(self) -> Vec<HistoryMessage> {
+    self
+      .messages
+      .into_iter()
+      .flatten()
+      .filter(|m| m.id != 0)
+      .map(|m| {
+        let mut msg = history_message_from_content(m.id, m.date, m.content.as_ref().unwrap_or(&Value::Null));
+        msg.sender_id = m.sender_id.as_ref().and_then(extract_sender_id);
+        msg.media_group_id = m.media_album_id.as_ref().and_then(extract_media_group_id);
+        msg
+      })
+      .collect()
+  }
+}
+
+/// Служебные поля ответа TDLib. Они заимствуются из текста, поэтому проверка,
+/// ждет ли ответа запрос, не строит дерево `Value` даже для огромных ответов.
+#[derive(Deserialize)]
+struct ResponseEnvelope<'a> {
+  #[serde(rename = "@type", borrow)]
+  kind: Option<Cow<'a, str>>,
+  #[serde(rename = "@extra", borrow)]
+  extra: Option<RequestExtra<'a>>
+}
+
+/// `@extra` приходит так же, как был отправлен: числом или строкой.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RequestExtra<'a> {
+  Number(u64),
+  Text(#[serde(borrow)] Cow<'a, str>)
+}
+
+#[derive(Deserialize)]
+struct ErrorBody<'a> {
+  #[serde(default, borrow)]
+  message: Option<Cow<'a, str>>,
+  #[serde(default)]
+  code: i64
+}
+
+fn parse_response<'a, T: Deserialize<'a>>(raw: &'a str) -> Result<T, TgError> {
+  serde_json::from_str(raw).map_err(|e| TgError::Other(format!("Некорректный ответ TDLib: {e}")))
+}
+
+// Realtime-обновления идут через ограниченную очередь с одним потребителем:
+// альбом из сотни сообщений превращается в несколько пачек, а не в сотню задач к БД.
+const REALTIME_QUEUE_CAPACITY: usize = 512;
+const REALTIME_BATCH_MAX: usize = 100;
+const REALTIME_BATCH_WINDOW: Duration = Duration::from_millis(200);
+const REALTIME_BATCH_IDLE: Duration = Duration::from_millis(30);
+
+static REALTIME_QUEUE: OnceCell<tokio::sync::mpsc::Sender<(u64, ChatId, HistoryMessage)>> = OnceCell::new();
+// Наименьший id сообщения, не попавшего в переполненную очередь; i64::MAX — потерь нет.
+static REALTIME_DROPPED_MIN: AtomicI64 = AtomicI64::new(i64::MAX);
+const STORAGE_DELETE_SETTLE: Duration = Duration::from_secs(2);
+// Эпоха очереди: сообщения, поставленные до смены аккаунта, потребитель пропускает.
+static REALTIME_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+pub fn discard_realtime_updates() {
+  REALTIME_EPOCH.fetch_add(1, Ordering::SeqCst);
+  REALTIME_DROPPED_MIN.store(i64::MAX, Ordering::Relaxed);
 }
 
 fn schedule_storage_index(app: &tauri::AppHandle, chat_id: i64, msg: HistoryMessage) {
+  // Дешевая проверка до любого обращения к БД.
+  if ignore_list::should_ignore(&msg) {
+    tracing::trace!(event = "storage_index_ignored", chat_id = chat_id, message_id = msg.id, "Сообщение пропущено по списку игнорирования");
+    return;
+  }
+  let queue = REALTIME_QUEUE.get_or_init(|| {
+    let (tx, rx) = tokio::sync::mpsc::channel(REALTIME_QUEUE_CAPACITY);
+    let app = app.clone();
+    tauri::async_runtime::spawn(run_realtime_indexer(app, rx));
+    tx
+  });
+  let msg_id = msg.id;
+  // Поток TDLib блокировать нельзя: потребитель сам ждет ответов TDLib.
+  if queue.try_send((REALTIME_EPOCH.load(Ordering::SeqCst), chat_id, msg)).is_err() {
+    REALTIME_DROPPED_MIN.fetch_min(msg_id, Ordering::Relaxed);
+    tracing::warn!(event = "storage_index_queue_full", chat_id = chat_id, message_id = msg_id, "Очередь индексации переполнена, сообщение догонит следующая синхронизация");
+  }
+}
+
+/// Сообщения основного или дополнительного канала хранения удалены, например
+/// с другого устройства: помечаем их файлы и папки битыми. Пауза дает
+/// завершиться собственным операциям приложения, которые сначала удаляют
+/// старое сообщение, а потом записывают новое: записи ищутся по текущим
+/// координатам, и замененные уже не попадут под отметку.
+fn schedule_storage_delete(app: &tauri::AppHandle, chat_id: ChatId, message_ids: Vec<MessageId>) {
   let app = app.clone();
   tauri::async_runtime::spawn(async move {
+    tokio::time::sleep(STORAGE_DELETE_SETTLE).await;
     let state = app.state::<AppState>();
-    let db = match state.db() {
-      Ok(db) => db,
-      Err(e) => {
-        tracing::debug!(event = "storage_index_skip", error = %e, "База данных еще не готова");
-        return;
-      }
+    let Ok(db) = state.db() else {
+      return;
     };
     let pool = db.pool();
-    let storage_chat_id = match sync::get_sync(pool, "storage_chat_id").await {
-      Ok(Some(v)) => v.parse::<i64>().ok(),
-      Ok(None) => None,
+    let storage_chat_id = sync::get_sync(pool, "storage_chat_id").await.ok().flatten().and_then(|v| v.parse::<i64>().ok());
+    let main_chat = storage_chat_id == Some(chat_id);
+    if !main_chat && !storage_channels::is_extra_chat(pool, chat_id).await.unwrap_or(false) {
+      return;
+    }
+    match reconcile::mark_deleted_messages(pool, chat_id, main_chat, &message_ids).await {
+      Ok(deleted) if deleted.is_empty() => {}
+      Ok(deleted) => {
+        tracing::info!(
+          event = "storage_messages_deleted",
+          files = deleted.files.len(),
+          dirs = deleted.dirs.len(),
+          links = deleted.links,
+          "Записи удаленных в Telegram сообщений помечены битыми"
+        );
+        state.invalidate_listings();
+        match (deleted.files.as_slice(), deleted.dirs.is_empty() && deleted.links == 0) {
+          ([file_id], true) => events::file_changed(&app, file_id, Change::Updated, None),
+          _ => events::tree_updated(&app)
+        }
+      }
       Err(e) => {
-        tracing::debug!(event = "storage_index_skip", error = %e, "Не удалось прочитать storage_chat_id");
-        None
+        tracing::warn!(event = "storage_delete_failed", error = %e, "Не удалось отметить удаленные сообщения");
       }
-    };
-    let Some(storage_chat_id) = storage_chat_id else { return; };
-    if storage_chat_id != chat_id {
+    }
+  });
+}
+
+/// Отправка файла окончательно не удалась: снимаем отметку о незавершенной
+/// загрузке, чтобы повтор пользователя отправил файл заново.
+fn schedule_send_failed(app: &tauri::AppHandle, message: &Value, old_message_id: MessageId) {
+  let Some((chat_id, msg)) = history_message_from_object(message) else {
+    return;
+  };
+  let Some(meta) = msg.caption.as_deref().and_then(|c| parse_file_caption(c).ok()) else {
+    return;
+  };
+  let app = app.clone();
+  tauri::async_runtime::spawn(async move {
+    let state = app.state::<AppState>();
+    let Ok(db) = state.db() else {
       return;
+    };
+    if let Err(e) = pending_uploads::fail(db.pool(), &meta.file_id, chat_id, old_message_id).await {
+      tracing::warn!(event = "pending_upload_fail_failed", file_id = meta.file_id.as_str(), error = %e, "Не удалось снять отметку о незавершенной загрузке");
     }
+  });
+}
 
-    let tg = match state.telegram() {
-      Ok(tg) => tg,
-      Err(e) => {
-        tracing::debug!(event = "storage_index_skip", error = %e, "Telegram сервис еще не готов");
+async fn run_realtime_indexer(app: tauri::AppHandle, mut rx: tokio::sync::mpsc::Receiver<(u64, ChatId, HistoryMessage)>) {
+  while let Some(first) = rx.recv().await {
+    // Пачка закрывается, когда поток обновлений затих на REALTIME_BATCH_IDLE,
+    // но не позже REALTIME_BATCH_WINDOW после первого сообщения.
+    let deadline = tokio::time::Instant::now() + REALTIME_BATCH_WINDOW;
+    let mut batch = vec![first];
+    while batch.len() < REALTIME_BATCH_MAX {
+      let idle = (tokio::time::Instant::now() + REALTIME_BATCH_IDLE).min(deadline);
+      match tokio::time::timeout_at(idle, rx.recv()).await {
+        Ok(Some(item)) => batch.push(item),
+        Ok(None) | Err(_) => break
+      }
+    }
+    let epoch = REALTIME_EPOCH.load(Ordering::SeqCst);
+    let batch: Vec<(ChatId, HistoryMessage)> = batch
+      .into_iter()
+      .filter(|(queued_at, _, _)| *queued_at == epoch)
+      .map(|(_, chat_id, msg)| (chat_id, msg))
+      .collect();
+    if batch.is_empty() {
+      continue;
+    }
+    index_realtime_batch(&app, batch).await;
+  }
+}
+
+// Голосовые ждут расшифровки в общей очереди, которую разбирает одна задача:
+// пачки не запускают распознавание параллельно друг другу.
+static TRANSCRIBE_QUEUE: Lazy<Mutex<Vec<(ChatId, transcripts::TranscriptSource, String)>>> = Lazy::new(|| Mutex::new(Vec::new()));
+static TRANSCRIBE_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Расшифровка идет отдельно от пачки: распознавание занимает секунды и не должно
+/// задерживать индексацию следующих сообщений.
+fn schedule_transcriptions(
+  app: &tauri::AppHandle,
+  storage_chat_id: ChatId,
+  source: transcripts::TranscriptSource,
+  file_ids: Vec<String>
+) {
+  let mut queue = TRANSCRIBE_QUEUE.lock();
+  for file_id in file_ids {
+    if !queue.iter().any(|(_, _, queued)| *queued == file_id) {
+      queue.push((storage_chat_id, source, file_id));
+    }
+  }
+  // Флаг меняется только под блокировкой очереди, поэтому задача не может
+  // завершиться, пропустив добавленные сейчас файлы.
+  if TRANSCRIBE_RUNNING.swap(true, Ordering::SeqCst) {
+    return;
+  }
+  drop(queue);
+  tauri::async_runtime::spawn(transcribe_new_voice_notes(app.clone()));
+}
+
+async fn transcribe_new_voice_notes(app: tauri::AppHandle) {
+  let state = app.state::<AppState>();
+  loop {
+    let (storage_chat_id, source, file_id) = {
+      let mut queue = TRANSCRIBE_QUEUE.lock();
+      if queue.is_empty() {
+        TRANSCRIBE_RUNNING.store(false, Ordering::SeqCst);
         return;
       }
+      queue.remove(0)
+    };
+    let (Ok(db), Ok(tg), Ok(paths)) = (state.db(), state.telegram(), state.paths()) else {
+      continue;
     };
+    if matches!(transcripts::get_transcript(db.pool(), &file_id).await, Ok(Some(_))) {
+      continue;
+    }
+    if let Err(e) = transcripts::transcribe_file(db.pool(), tg.as_ref(), &paths, storage_chat_id, &file_id, source).await {
+      tracing::warn!(event = "transcript_auto_failed", file_id = file_id.as_str(), error = %e, "Не удалось расшифровать голосовое сообщение");
+    }
+  }
+}
 
-    let mut unassigned = None;
-    match indexer::index_storage_message(pool, tg.as_ref(), storage_chat_id, &msg, &mut unassigned).await {
-      Ok(outcome) => {
-        if outcome.dir || outcome.file || outcome.imported {
-          let _ = app.emit("tree_updated", ());
+// Ожидающие результата распознавания речи по сообщению.
+static SPEECH_WAITERS: Lazy<Mutex<HashMap<(ChatId, MessageId), Vec<Arc<tokio::sync::Notify>>>>> =
+  Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Подписка на обновления сообщения с распознаваемой речью; снимается при drop.
+struct SpeechWaiter {
+  key: (ChatId, MessageId),
+  notify: Arc<tokio::sync::Notify>
+}
+
+impl SpeechWaiter {
+  fn register(chat_id: ChatId, message_id: MessageId) -> Self {
+    let notify = Arc::new(tokio::sync::Notify::new());
+    SPEECH_WAITERS.lock().entry((chat_id, message_id)).or_default().push(notify.clone());
+    Self { key: (chat_id, message_id), notify }
+  }
+}
+
+impl Drop for SpeechWaiter {
+  fn drop(&mut self) {
+    let mut waiters = SPEECH_WAITERS.lock();
+    if let Some(list) = waiters.get_mut(&self.key) {
+      list.retain(|n| !Arc::ptr_eq(n, &self.notify));
+      if list.is_empty() {
+        waiters.remove(&self.key);
+      }
+    }
+  }
+}
+
+/// Будит ожидающих распознавания: одно сообщение или, без ключа, все.
+fn wake_speech_waiters(key: Option<(ChatId, MessageId)>) {
+  let waiters = SPEECH_WAITERS.lock();
+  let lists: Vec<&Vec<Arc<tokio::sync::Notify>>> = match key {
+    Some(key) => waiters.get(&key).into_iter().collect(),
+    None => waiters.values().collect()
+  };
+  for notify in lists.into_iter().flatten() {
+    notify.notify_one();
+  }
+}
+
+/// Текст распознавания из содержимого сообщения; `None`, пока оно идет.
+fn speech_result(content: &Value) -> Result<Option<String>, TgError> {
+  let media = content.get("voice_note").or_else(|| content.get("video_note"));
+  let Some(media) = media else {
+    return Err(TgError::Other("Сообщение не содержит голосового или видеосообщения".into()));
+  };
+  let result = media.get("speech_recognition_result").unwrap_or(&Value::Null);
+  match result.get("@type").and_then(|v| v.as_str()) {
+    Some("speechRecognitionResultText") => {
+      Ok(Some(result.get("text").and_then(|v| v.as_str()).unwrap_or("").to_string()))
+    }
+    Some("speechRecognitionResultError") => {
+      let message = result
+        .get("error")
+        .and_then(|e| e.get("message"))
+        .and_then(|v| v.as_str())
+        .unwrap_or("неизвестная ошибка");
+      Err(TgError::Other(format!("Telegram не смог распознать речь: {message}")))
+    }
+    _ => Ok(None)
+  }
+}
+
+async fn index_realtime_batch(app: &tauri::AppHandle, batch: Vec<(ChatId, HistoryMessage)>) {
+  let state = app.state::<AppState>();
+  let db = match state.db() {
+    Ok(db) => db,
+    Err(e) => {
+      tracing::debug!(event = "storage_index_skip", error = %e, "База данных еще не готова");
+      return;
+    }
+  };
+  let pool = db.pool();
+  let storage_chat_id = match sync::get_sync(pool, "storage_chat_id").await {
+    Ok(Some(v)) => v.parse::<i64>().ok(),
+    Ok(None) => None,
+    Err(e) => {
+      tracing::debug!(event = "storage_index_skip", error = %e, "Не удалось прочитать storage_chat_id");
+      None
+    }
+  };
+  let Some(storage_chat_id) = storage_chat_id else { return; };
+  let extra_chats = storage_channels::chat_ids(pool).await.unwrap_or_default();
+
+  // Из нескольких обновлений одного сообщения важно только последнее.
+  let mut messages: Vec<(ChatId, HistoryMessage)> = Vec::with_capacity(batch.len());
+  for (chat_id, msg) in batch {
+    if chat_id != storage_chat_id && !extra_chats.contains(&chat_id) {
+      continue;
+    }
+    match messages.iter_mut().find(|(c, m)| *c == chat_id && m.id == msg.id) {
+      Some(slot) => slot.1 = msg,
+      None => messages.push((chat_id, msg))
+    }
+  }
+  if messages.is_empty() {
+    return;
+  }
+
+  let tg = match state.telegram() {
+    Ok(tg) => tg,
+    Err(e) => {
+      tracing::debug!(event = "storage_index_skip", error = %e, "Telegram сервис еще не готов");
+      return;
+    }
+  };
+
+  // Папки и файлы с разметкой пишутся по каналу одной транзакцией, как
+  // страница истории при синхронизации. Если пачка не записалась, сообщения
+  // разбираются по одному, чтобы одно плохое не потеряло остальные.
+  let mut unassigned = None;
+  let mut indexed: Vec<(&HistoryMessage, indexer::IndexOutcome)> = Vec::with_capacity(messages.len());
+  let mut chats: Vec<ChatId> = messages.iter().map(|(chat_id, _)| *chat_id).collect();
+  chats.sort_unstable();
+  chats.dedup();
+  for chat_id in chats {
+    let msgs: Vec<HistoryMessage> = messages.iter().filter(|(c, _)| *c == chat_id).map(|(_, m)| m.clone()).collect();
+    match indexer::index_storage_batch(pool, tg.as_ref(), chat_id, &msgs, &mut unassigned).await {
+      Ok(outcomes) => {
+        for (msg, outcome) in messages.iter().filter(|(c, _)| *c == chat_id).map(|(_, m)| m).zip(outcomes) {
+          indexed.push((msg, outcome));
         }
       }
       Err(e) => {
-        tracing::warn!(event = "storage_index_failed", error = %e, "Не удалось обработать обновление");
+        tracing::warn!(event = "storage_index_batch_failed", chat_id = chat_id, error = %e, "Пачка обновлений не записалась, обрабатываю по одному");
+        for msg in messages.iter().filter(|(c, _)| *c == chat_id).map(|(_, m)| m) {
+          match indexer::index_storage_message(pool, tg.as_ref(), chat_id, msg, &mut unassigned).await {
+            Ok(outcome) => indexed.push((msg, outcome)),
+            Err(e) => {
+              tracing::warn!(event = "storage_index_failed", message_id = msg.id, error = %e, "Не удалось обработать обновление");
+            }
+          }
+        }
       }
     }
+  }
 
-    if msg.id > 0 {
-      let current = sync::get_sync(pool, "storage_last_message_id")
-        .await
-        .ok()
-        .and_then(|v| v.and_then(|s| s.parse::<i64>().ok()))
-        .unwrap_or(0);
-      if msg.id > current {
-        let _ = sync::set_sync(pool, "storage_last_message_id", &msg.id.to_string()).await;
+  let mut changed_files: Vec<String> = Vec::new();
+  let mut voice_files: Vec<String> = Vec::new();
+  let mut structural = false;
+  for (msg, outcome) in indexed {
+    if outcome.dir || outcome.link || outcome.imported {
+      structural = true;
+    }
+    if let Some(file_id) = outcome.file_id {
+      let kind = msg.content_type.as_deref().map(super::content_kind);
+      if transcripts::is_transcribable(kind.as_deref()) && !voice_files.contains(&file_id) {
+        voice_files.push(file_id.clone());
+      }
+      state.search_index_refresh_file(&db, &file_id).await;
+      if !changed_files.contains(&file_id) {
+        changed_files.push(file_id);
       }
     }
-  });
+  }
+
+  if structural || !changed_files.is_empty() {
+    state.invalidate_listings();
+    match (structural, changed_files.as_slice()) {
+      (false, [file_id]) => events::file_changed(app, file_id, Change::Updated, None),
+      _ => events::tree_updated(app)
+    }
+  }
+  tracing::debug!(
+    event = "storage_index_batch",
+    messages = messages.len(),
+    files = changed_files.len(),
+    "Обработана пачка realtime-обновлений"
+  );
+
+  if !voice_files.is_empty() {
+    if let Ok(Some(source)) = crate::settings::get_transcription_mode(pool).await {
+      schedule_transcriptions(app, storage_chat_id, source, voice_files);
+    }
+  }
+
+  // Отметка последнего сообщения ведется только для основного канала;
+  // дополнительные догоняются по своей `storage_channels.last_message_id`.
+  let newest = messages.iter().filter(|(c, _)| *c == storage_chat_id).map(|(_, m)| m.id).max().unwrap_or(0);
+  let dropped_min = REALTIME_DROPPED_MIN.swap(i64::MAX, Ordering::Relaxed);
+  let current = sync::get_sync(pool, "storage_last_message_id")
+    .await
+    .ok()
+    .and_then(|v| v.and_then(|s| s.parse::<i64>().ok()))
+    .unwrap_or(0);
+  // После потерь откатываем отметку, чтобы следующая синхронизация перечитала пропущенное.
+  let next = if dropped_min != i64::MAX {
+    current.min(dropped_min - 1)
+  } else {
+    current.max(newest)
+  };
+  if next != current {
+    let _ = sync::set_sync(pool, "storage_last_message_id", &next.to_string()).await;
+  }
 }
 
 fn file_ref_from_obj(obj: &serde_json::Map<String, Value>) -> Option<(i64, Option<String>)> {
@@ -451,31 +1046,40 @@ fn file_ref_from_obj(obj: &serde_json::Map<String, Value>) -> Option<(i64, Optio
 }
 
 fn extract_file_ref_from_content(content: &Value) -> Option<(i64, Option<String>)> {
+  main_file_object(content).and_then(file_ref_from_obj)
+}
+
+/// remote.unique_id основного файла: не меняется при пересылке и копировании.
+fn extract_file_unique_id(content: &Value) -> Option<String> {
+  main_file_object(content)?
+    .get("remote")
+    .and_then(|v| v.get("unique_id"))
+    .and_then(|v| v.as_str())
+    .filter(|s| !s.is_empty())
+    .map(str::to_string)
+}
+
+/// Объект `file` основного содержимого сообщения, без превью.
+fn main_file_object(content: &Value) -> Option<&serde_json::Map<String, Value>> {
   let t = content.get("@type").and_then(|v| v.as_str()).unwrap_or("");
-  let pick_nested = |parent: &Value, key: &str, nested: &str| {
-    parent
-      .get(key)
-      .and_then(|v| v.as_object())
-      .and_then(|o| o.get(nested))
+  let is_file = |obj: &&serde_json::Map<String, Value>| obj.get("id").and_then(|v| v.as_i64()).is_some();
+  let pick = |key: &str, nested: &str| {
+    let outer = content.get(key).and_then(|v| v.as_object())?;
+    outer
+      .get(nested)
       .and_then(|v| v.as_object())
-      .and_then(file_ref_from_obj)
+      .filter(is_file)
+      .or_else(|| Some(outer).filter(is_file))
   };
 
   let by_type = match t {
-    "messageDocument" => pick_nested(content, "document", "document")
-      .or_else(|| content.get("document").and_then(|v| v.as_object()).and_then(file_ref_from_obj)),
-    "messageVideo" => pick_nested(content, "video", "video")
-      .or_else(|| content.get("video").and_then(|v| v.as_object()).and_then(file_ref_from_obj)),
-    "messageAudio" => pick_nested(content, "audio", "audio")
-      .or_else(|| content.get("audio").and_then(|v| v.as_object()).and_then(file_ref_from_obj)),
-    "messageVoiceNote" => pick_nested(content, "voice_note", "voice")
-      .or_else(|| content.get("voice_note").and_then(|v| v.as_object()).and_then(file_ref_from_obj)),
-    "messageVideoNote" => pick_nested(content, "video_note", "video")
-      .or_else(|| content.get("video_note").and_then(|v| v.as_object()).and_then(file_ref_from_obj)),
-    "messageAnimation" => pick_nested(content, "animation", "animation")
-      .or_else(|| content.get("animation").and_then(|v| v.as_object()).and_then(file_ref_from_obj)),
-    "messageSticker" => pick_nested(content, "sticker", "sticker")
-      .or_else(|| content.get("sticker").and_then(|v| v.as_object()).and_then(file_ref_from_obj)),
+    "messageDocument" => pick("document", "document"),
+    "messageVideo" => pick("video", "video"),
+    "messageAudio" => pick("audio", "audio"),
+    "messageVoiceNote" => pick("voice_note", "voice"),
+    "messageVideoNote" => pick("video_note", "video"),
+    "messageAnimation" => pick("animation", "animation"),
+    "messageSticker" => pick("sticker", "sticker"),
     _ => None
   };
 
@@ -483,24 +1087,24 @@ fn extract_file_ref_from_content(content: &Value) -> Option<(i64, Option<String>
     return by_type;
   }
 
-  fn find_file_ref(value: &Value) -> Option<(i64, Option<String>)> {
+  fn find_file(value: &Value) -> Option<&serde_json::Map<String, Value>> {
     match value {
       Value::Object(map) => {
-        if let Some(found) = file_ref_from_obj(map) {
-          return Some(found);
+        if map.get("id").and_then(|v| v.as_i64()).is_some() {
+          return Some(map);
         }
         for (k, v) in map {
           if k == "thumbnail" || k == "minithumbnail" {
             continue;
           }
-          if let Some(found) = find_file_ref(v) {
+          if let Some(found) = find_file(v) {
             return Some(found);
           }
         }
       }
       Value::Array(arr) => {
         for v in arr {
-          if let Some(found) = find_file_ref(v) {
+          if let Some(found) = find_file(v) {
             return Some(found);
           }
         }
@@ -510,7 +1114,7 @@ fn extract_file_ref_from_content(content: &Value) -> Option<(i64, Option<String>
     None
   }
 
-  find_file_ref(content)
+  find_file(content)
 }
 
 fn extract_active_username(value: &Value) -> Option<String> {
@@ -547,7 +1151,7 @@ fn local_path_from_file(value: &Value) -> Option<String> {
 }
 
 async fn chat_info_from_id(tg: &TdlibTelegram, chat_id: i64) -> Option<ChatInfo> {
-  let chat = tg.request(json!({"@type":"getChat","chat_id":chat_id}), Duration::from_secs(10)).await.ok()?;
+  let chat = tg.request_with(json!({"@type":"getChat","chat_id":chat_id}), timeouts::policy(TimeoutClass::Interactive)).await.ok()?;
   let title = chat.get("title").and_then(|v| v.as_str()).unwrap_or("Без названия").to_string();
   let chat_type = chat.get("type").and_then(|v| v.as_object());
   let type_name = chat_type
@@ -565,7 +1169,7 @@ async fn chat_info_from_id(tg: &TdlibTelegram, chat_id: i64) -> Option<ChatInfo>
     kind = if is_channel { "канал" } else { "группа" }.to_string();
     if username.is_none() {
       if let Some(supergroup_id) = chat_type.and_then(|t| t.get("supergroup_id")).and_then(|v| v.as_i64()) {
-        if let Ok(sg) = tg.request(json!({"@type":"getSupergroup","supergroup_id":supergroup_id}), Duration::from_secs(10)).await {
+        if let Ok(sg) = tg.request_with(json!({"@type":"getSupergroup","supergroup_id":supergroup_id}), timeouts::policy(TimeoutClass::Interactive)).await {
           username = extract_active_username(&sg);
         }
       }
@@ -576,7 +1180,242 @@ async fn chat_info_from_id(tg: &TdlibTelegram, chat_id: i64) -> Option<ChatInfo>
     kind = "личный чат".to_string();
   }
 
-  Some(ChatInfo { id: chat_id, title, kind, username })
+  let photo_path = match chat.get("photo").and_then(|p| p.get("small")) {
+    Some(small) => chat_photo_path(tg, chat_id, small).await,
+    None => None
+  };
+  Some(ChatInfo { id: chat_id, title, kind, username, photo_path })
+}
+
+/// Маленькая аватарка чата в `cache/chat_photos`. Имя файла включает
+/// remote unique_id, поэтому смена аватарки дает новый файл, а старая копия
+/// переиспользуется без обращения к TDLib. Ошибки не мешают показать чат.
+async fn chat_photo_path(tg: &TdlibTelegram, chat_id: i64, small: &Value) -> Option<String> {
+  let unique_id = small
+    .get("remote")
+    .and_then(|r| r.get("unique_id"))
+    .and_then(|v| v.as_str())
+    .filter(|v| !v.trim().is_empty())?;
+  let safe_unique: String = unique_id.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-').collect();
+  let dir = tg.paths.layout().chat_photos_dir();
+  let target = dir.join(format!("{chat_id}_{safe_unique}.jpg"));
+  if target.is_file() {
+    return Some(target.to_string_lossy().to_string());
+  }
+
+  let mut src = local_path_from_file(small);
+  if src.is_none() {
+    let file_id = small.get("id").and_then(|v| v.as_i64())?;
+    let downloaded = tg
+      .request(
+        json!({"@type":"downloadFile","file_id":file_id,"priority":1,"offset":0,"limit":0,"synchronous":true}),
+        timeouts::get(TimeoutClass::Interactive)
+      )
+      .await;
+    match downloaded {
+      Ok(file) => src = local_path_from_file(&file),
+      Err(e) => {
+        tracing::debug!(event = "chat_photo_download_failed", chat_id = chat_id, error = %e, "Не удалось скачать аватарку чата");
+        return None;
+      }
+    }
+  }
+  let src = src?;
+  if let Err(e) = std::fs::create_dir_all(&dir).and_then(|_| std::fs::copy(&src, &target)) {
+    tracing::debug!(event = "chat_photo_copy_failed", chat_id = chat_id, error = %e, "Не удалось сохранить аватарку чата");
+    return None;
+  }
+  Some(target.to_string_lossy().to_string())
+}
+
+// Список папок чатов TDLib присылает только обновлением updateChatFolders,
+// у каждого аккаунта свой.
+static CHAT_FOLDERS: Lazy<Mutex<HashMap<String, Vec<ChatFolder>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn record_chat_folders(account: &str, update: &Value) {
+  let Some(list) = update.get("chat_folders").and_then(|v| v.as_array()) else {
+    return;
+  };
+  let folders = list
+    .iter()
+    .filter_map(|info| {
+      let id = info.get("id").and_then(|v| v.as_i64())? as i32;
+      // В новых версиях TDLib имя — chatFolderName с форматированным текстом,
+      // в старых — строка title.
+      let title = info
+        .get("name")
+        .and_then(|n| n.get("text"))
+        .and_then(|t| t.get("text"))
+        .and_then(|v| v.as_str())
+        .or_else(|| info.get("title").and_then(|v| v.as_str()))
+        .unwrap_or("Без названия")
+        .to_string();
+      Some(ChatFolder { id, title })
+    })
+    .collect();
+  CHAT_FOLDERS.lock().insert(account.to_string(), folders);
+}
+
+fn download_progress_of(file: &Value) -> DownloadProgress {
+  let positive = |key: &str| file.get(key).and_then(|v| v.as_i64()).filter(|v| *v > 0).map(|v| v as u64);
+  let downloaded = file
+    .get("local")
+    .and_then(|l| l.get("downloaded_size"))
+    .and_then(|v| v.as_i64())
+    .unwrap_or(0)
+    .max(0) as u64;
+  DownloadProgress { downloaded, total: positive("size").or_else(|| positive("expected_size")) }
+}
+
+/// Непрерывно скачанный диапазон от места, с которого сейчас качает TDLib:
+/// частично скачанный файл можно читать лишь в его пределах.
+fn stream_range_of(file: &Value) -> (u64, u64) {
+  if download_completed(file) {
+    return (0, download_progress_of(file).downloaded);
+  }
+  let offset = file
+    .get("local")
+    .and_then(|l| l.get("download_offset"))
+    .and_then(|v| v.as_i64())
+    .unwrap_or(0)
+    .max(0) as u64;
+  (offset, offset + downloaded_prefix(file) as u64)
+}
+
+/// Путь к файлу в кеше TDLib, в том числе к еще не докачанному.
+fn partial_local_path(file: &Value) -> Option<PathBuf> {
+  let path = file.get("local")?.get("path")?.as_str()?.trim();
+  (!path.is_empty()).then(|| PathBuf::from(path))
+}
+
+fn downloaded_prefix(file: &Value) -> i64 {
+  if download_completed(file) {
+    return 0;
+  }
+  file
+    .get("local")
+    .and_then(|l| l.get("downloaded_prefix_size"))
+    .and_then(|v| v.as_i64())
+    .unwrap_or(0)
+    .max(0)
+}
+
+fn download_completed(file: &Value) -> bool {
+  file
+    .get("local")
+    .and_then(|l| l.get("is_downloading_completed"))
+    .and_then(|v| v.as_bool())
+    .unwrap_or(false)
+}
+
+/// Раздает `updateFile` ожидающим скачиваниям: ход — приемникам (не чаще
+/// `DOWNLOAD_PROGRESS_INTERVAL`), итог — ждущим вызовам.
+fn handle_file_update(file: &Value, watches: &DownloadWatches) {
+  let Some(file_id) = file.get("id").and_then(|v| v.as_i64()) else {
+    return;
+  };
+  let completed = download_completed(file);
+  let active = file
+    .get("local")
+    .and_then(|l| l.get("is_downloading_active"))
+    .and_then(|v| v.as_bool())
+    .unwrap_or(false);
+  let progress = download_progress_of(file);
+  let (range_start, range_end) = stream_range_of(file);
+
+  let (sinks, range_sinks, finished) = {
+    let mut guard = watches.lock();
+    let Some(list) = guard.get_mut(&file_id) else {
+      return;
+    };
+    let mut sinks: Vec<ProgressSink> = Vec::new();
+    let mut range_sinks: Vec<RangeSink> = Vec::new();
+    for watch in list.iter_mut() {
+      let due = match watch.last_emit {
+        Some(at) => completed || at.elapsed() >= DOWNLOAD_PROGRESS_INTERVAL,
+        None => true
+      };
+      if due && (watch.progress.is_some() || watch.ranges.is_some()) {
+        sinks.extend(watch.progress.clone());
+        range_sinks.extend(watch.ranges.clone());
+        watch.last_emit = Some(Instant::now());
+      }
+      watch.started |= active;
+    }
+    // Неактивный файл до старта — это еще не скачивание, а не его обрыв.
+    // Поток при перемотке тоже может на миг остановиться, его ждущий
+    // остается до `stream_stop`.
+    let (finished, waiting): (Vec<DownloadWatch>, Vec<DownloadWatch>) = std::mem::take(list)
+      .into_iter()
+      .partition(|w| completed || (!active && w.started && w.ranges.is_none()));
+    if waiting.is_empty() {
+      guard.remove(&file_id);
+    } else {
+      *list = waiting;
+    }
+    (sinks, range_sinks, finished)
+  };
+
+  for sink in sinks {
+    sink(progress);
+  }
+  for sink in range_sinks {
+    sink(range_start, range_end);
+  }
+  for watch in finished {
+    let res = if completed { Ok(file.clone()) } else { Err("Скачивание прервано".to_string()) };
+    let _ = watch.done.send(res);
+  }
+}
+
+/// Отмечает ждущего как начавшего скачивание, когда TDLib ответил на
+/// downloadFile. Все `updateFile` после ответа относятся к этому запуску, так
+/// что обрыв распознается, даже если активного обновления так и не было.
+fn mark_download_started(watches: &DownloadWatches, file_id: i64, token: i64) {
+  if let Some(watch) = watches.lock().get_mut(&file_id).and_then(|list| list.iter_mut().find(|w| w.token == token)) {
+    watch.started = true;
+  }
+}
+
+/// Сколько getChat держать в полете одновременно при сборке списка чатов.
+const CHAT_INFO_CONCURRENCY: usize = 4;
+
+/// Разрешает чаты параллельно, сохраняя порядок и пропуская повторы и
+/// недоступные чаты.
+async fn chat_infos_from_ids(tg: &TdlibTelegram, ids: Vec<i64>, skip: &[i64]) -> Vec<ChatInfo> {
+  let mut seen: std::collections::HashSet<i64> = skip.iter().copied().collect();
+  let ids: Vec<i64> = ids.into_iter().filter(|id| seen.insert(*id)).collect();
+  futures_util::stream::iter(ids)
+    .map(|chat_id| chat_info_from_id(tg, chat_id))
+    .buffered(CHAT_INFO_CONCURRENCY)
+    .filter_map(|info| async move { info })
+    .collect()
+    .await
+}
+
+/// Сколько правок подписей держать в полете одновременно в пакетном режиме.
+const CAPTION_EDIT_CONCURRENCY: usize = 4;
+/// Пауза между запусками правок в пачке, чтобы не упереться во flood wait.
+const CAPTION_EDIT_PACING: Duration = Duration::from_millis(100);
+/// Сколько раз одна правка ждет FLOOD_WAIT; дольше этого ожидания пачка не ждет
+/// и отдает ошибку всем оставшимся правкам.
+const CAPTION_EDIT_FLOOD_RETRIES: u32 = 3;
+const CAPTION_EDIT_MAX_FLOOD_WAIT: Duration = Duration::from_secs(300);
+
+#[derive(Default)]
+struct CaptionFloodGate {
+  until: Option<tokio::time::Instant>,
+  abort: Option<String>
+}
+
+fn caption_edit_payload(chat_id: ChatId, message_id: MessageId, caption: String) -> Value {
+  json!({
+    "@type":"editMessageCaption",
+    "chat_id": chat_id,
+    "message_id": message_id,
+    "caption": { "@type":"formattedText", "text": caption },
+    "show_caption_above_media": false
+  })
 }
 
 impl TdlibTelegram {
@@ -584,16 +1423,19 @@ impl TdlibTelegram {
     paths: Paths,
     app: tauri::AppHandle,
     initial_settings: Option<TgCredentials>,
-    initial_tdlib_path: Option<String>
+    initial_tdlib_path: Option<String>,
+    account: String
   ) -> anyhow::Result<Self> {
     let (tx, rx) = mpsc::channel::<TdlibCommand>();
     let send_waiters: SendWaiters = std::sync::Arc::new(Mutex::new(HashMap::new()));
     let send_results: SendResults = std::sync::Arc::new(Mutex::new(HashMap::new()));
+    let download_watches: DownloadWatches = std::sync::Arc::new(Mutex::new(HashMap::new()));
 
     let app_for_thread = app.clone();
     let paths_for_thread = paths.clone();
     let waiters_for_thread = send_waiters.clone();
     let results_for_thread = send_results.clone();
+    let downloads_for_thread = download_watches.clone();
     let session_name = tdlib_session_name();
     let mut config = match initial_settings {
       Some(s) => Some(TdlibConfig::from_settings(&paths, s.api_id, s.api_hash, &session_name)?),
@@ -601,8 +1443,10 @@ impl TdlibTelegram {
     };
     let mut lib_path = resolve_tdlib_path(&paths, initial_tdlib_path.as_deref());
 
+    let account_for_folders = account.clone();
+
     std::thread::spawn(move || {
-      let mut last_state: Option<AuthState> = None;
+      let mut last_state = AuthTracker { account, last: None, logging_out: false };
       let mut waiting_for_params = false;
       let mut params_sent = false;
       let mut client: Option<TdlibClient> = None;
@@ -615,154 +1459,504 @@ impl TdlibTelegram {
         set_auth_state(&app_for_thread, AuthState::WaitConfig, &mut last_state);
       }
 
-      loop {
-        match rx.recv_timeout(Duration::from_millis(10)) {
-          Ok(cmd) => {
-            let mut cmd_ctx = CommandCtx {
-              paths: &paths_for_thread,
-              config: &mut config,
-              lib_path: &mut lib_path,
-              client: &mut client,
-              waiting_for_params: &mut waiting_for_params,
-              params_sent: &mut params_sent,
-              pending_requests: &mut pending_requests,
-              next_request_id: &mut next_request_id,
-              build_attempted: &mut build_attempted,
-              pending: &mut pending,
-              app: &app_for_thread,
-              last_state: &mut last_state
-            };
-            handle_command(cmd, &mut cmd_ctx);
-          }
-          Err(mpsc::RecvTimeoutError::Timeout) => {}
-          Err(mpsc::RecvTimeoutError::Disconnected) => break
-        }
-
-        while let Ok(cmd) = rx.try_recv() {
-          let mut cmd_ctx = CommandCtx {
-            paths: &paths_for_thread,
-            config: &mut config,
-            lib_path: &mut lib_path,
-            client: &mut client,
-            waiting_for_params: &mut waiting_for_params,
-            params_sent: &mut params_sent,
-            pending_requests: &mut pending_requests,
-            next_request_id: &mut next_request_id,
-            build_attempted: &mut build_attempted,
-            pending: &mut pending,
-            app: &app_for_thread,
-            last_state: &mut last_state
-          };
-          handle_command(cmd, &mut cmd_ctx);
-        }
+      let mut restarts = RestartPolicy::default();
+
+      'supervisor: loop {
+        let outcome = std::panic::catch_unwind(AssertUnwindSafe(|| {
+          loop {
+            match rx.recv_timeout(Duration::from_millis(10)) {
+              Ok(cmd) => {
+                let mut cmd_ctx = CommandCtx {
+                  paths: &paths_for_thread,
+                  config: &mut config,
+                  lib_path: &mut lib_path,
+                  client: &mut client,
+                  waiting_for_params: &mut waiting_for_params,
+                  params_sent: &mut params_sent,
+                  pending_requests: &mut pending_requests,
+                  next_request_id: &mut next_request_id,
+                  build_attempted: &mut build_attempted,
+                  pending: &mut pending,
+                  app: &app_for_thread,
+                  last_state: &mut last_state
+                };
+                handle_command(cmd, &mut cmd_ctx);
+              }
+              Err(mpsc::RecvTimeoutError::Timeout) => {}
+              Err(mpsc::RecvTimeoutError::Disconnected) => return WorkerExit::Shutdown
+            }
+
+            while let Ok(cmd) = rx.try_recv() {
+              let mut cmd_ctx = CommandCtx {
+                paths: &paths_for_thread,
+                config: &mut config,
+                lib_path: &mut lib_path,
+                client: &mut client,
+                waiting_for_params: &mut waiting_for_params,
+                params_sent: &mut params_sent,
+                pending_requests: &mut pending_requests,
+                next_request_id: &mut next_request_id,
+                build_attempted: &mut build_attempted,
+                pending: &mut pending,
+                app: &app_for_thread,
+                last_state: &mut last_state
+              };
+              handle_command(cmd, &mut cmd_ctx);
+            }
+
+            if client.is_none() {
+              if config.is_some() && lib_path.is_none() && !build_attempted {
+                build_attempted = true;
+                match attempt_tdlib_download(&paths_for_thread, &app_for_thread) {
+                  Ok(Some(p)) => {
+                    lib_path = Some(p);
+                  }
+                  Ok(None) => {}
+                  Err(e) => {
+                    tracing::warn!("Автоскачивание TDLib не удалось: {e}");
+                    emit_build_log(&app_for_thread, "stderr", &format!("Ошибка загрузки TDLib: {e}"));
+                  }
+                }
+
+                if lib_path.is_none() {
+                  match attempt_tdlib_build(&paths_for_thread, &app_for_thread) {
+                    Ok(p) => {
+                      lib_path = Some(p);
+                    }
+                    Err(e) => {
+                      tracing::error!("Автосборка TDLib не удалась: {e}");
+                      set_auth_state(&app_for_thread, AuthState::WaitConfig, &mut last_state);
+                    }
+                  }
+                }
+              }
+
+              if let (Some(_cfg), Some(lp)) = (config.as_ref(), lib_path.as_ref()) {
+                match crate::startup::measure_sync("tdlib_load", || TdlibClient::load(lp)) {
+                  Ok(c) => {
+                    c.set_verbosity(2);
+                    let _ = c.send(&json!({"@type":"getAuthorizationState"}).to_string());
+                    for msg in pending.drain(..) {
+                      let _ = c.send(&msg);
+                    }
+                    client = Some(c);
+                    waiting_for_params = false;
+                    params_sent = false;
+                  }
+                  Err(e) => {
+                    tracing::error!(tdlib_path = %lp.display(), error = %e, "Не удалось загрузить TDLib");
+                    set_auth_state(&app_for_thread, AuthState::WaitConfig, &mut last_state);
+                  }
+                }
+              }
+            }
+
+            if let Some(c) = client.as_ref() {
+              if let Some(resp) = c.receive(0.1) {
+                let Some(resp) = handle_request_response(resp, &mut pending_requests, &last_state.account) else {
+                  continue;
+                };
+                let value: Value = match serde_json::from_str(&resp) {
+                  Ok(v) => v,
+                  Err(e) => {
+                    tracing::error!("Не удалось распарсить ответ TDLib: {e}");
+                    continue;
+                  }
+                };
+                let mut response_ctx = ResponseCtx {
+                  client: c,
+                  config: &mut config,
+                  waiting_for_params: &mut waiting_for_params,
+                  params_sent: &mut params_sent,
+                  app: &app_for_thread,
+                  last_state: &mut last_state,
+                  send_waiters: &waiters_for_thread,
+                  send_results: &results_for_thread,
+                  download_watches: &downloads_for_thread
+                };
+                if let Err(e) = handle_tdlib_response(&value, &mut response_ctx) {
+                  tracing::error!("Ошибка TDLib: {e}");
+                }
+                if last_state.last == Some(AuthState::Closed) {
+                  return closed_exit(last_state.logging_out);
+                }
+              }
+            }
+          }
+        }));
+
+        let (cause, crashed) = match outcome {
+          Ok(WorkerExit::Shutdown) => break,
+          Ok(WorkerExit::LoggedOut) => ("выход из аккаунта".to_string(), false),
+          Ok(WorkerExit::Restart(cause)) => (cause, true),
+          Err(panic) => (format!("паника в потоке TDLib: {}", panic_message(panic.as_ref())), true)
+        };
+        if crashed {
+          tracing::error!(event = "tdlib_worker_restart", cause = %cause, "Перезапуск клиента TDLib");
+        } else {
+          tracing::info!(event = "tdlib_client_recreated", "Клиент TDLib закрыт после выхода, создаю новый");
+        }
+
+        // Ждущие ответа запросы уже не получат его от старого клиента — отвечаем ошибкой сразу.
+        for (_, tx) in pending_requests.drain() {
+          let _ = tx.send(Err(anyhow::anyhow!("TDLib перезапущен: {cause}")));
+        }
+        for (_, tx) in waiters_for_thread.lock().drain() {
+          let _ = tx.send(Err(anyhow::anyhow!("TDLib перезапущен: {cause}")));
+        }
+        pending.clear();
+        if let Some(c) = client.take() {
+          c.destroy();
+        }
+        waiting_for_params = false;
+        params_sent = false;
+        last_state.last = None;
+        last_state.logging_out = false;
+        crate::metrics::record_connection_state(false);
+        if !crashed {
+          continue;
+        }
+
+        let (attempt, backoff) = restarts.record(Instant::now());
+        let _ = app_for_thread.emit("tdlib_restarted", TdlibRestartedEvent { cause: cause.clone(), attempt });
+        // Пауза не блокирует поток глухо: команды обрабатываются (запросы сразу
+        // получают ошибку), а закрытие канала команд завершает супервизор.
+        let resume_at = Instant::now() + backoff;
+        while let Some(left) = resume_at.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) {
+          match rx.recv_timeout(left) {
+            Ok(cmd) => {
+              let mut cmd_ctx = CommandCtx {
+                paths: &paths_for_thread,
+                config: &mut config,
+                lib_path: &mut lib_path,
+                client: &mut client,
+                waiting_for_params: &mut waiting_for_params,
+                params_sent: &mut params_sent,
+                pending_requests: &mut pending_requests,
+                next_request_id: &mut next_request_id,
+                build_attempted: &mut build_attempted,
+                pending: &mut pending,
+                app: &app_for_thread,
+                last_state: &mut last_state
+              };
+              handle_command(cmd, &mut cmd_ctx);
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => break,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break 'supervisor
+          }
+        }
+      }
+
+      if let Some(c) = client {
+        c.destroy();
+      }
+    });
+
+    Ok(Self { tx, paths, send_waiters, send_results, download_watches, metadata_queue: ChatSendQueue::default(), account: account_for_folders })
+  }
+
+  async fn request(&self, payload: Value, timeout: Duration) -> Result<Value, TgError> {
+    let raw = self.request_raw(payload, timeout).await?;
+    parse_response(&raw)
+  }
+
+  /// Ответ на запрос текстом, для разбора сразу в типизированные структуры.
+  async fn request_raw(&self, payload: Value, timeout: Duration) -> Result<String, TgError> {
+    let (tx, rx) = oneshot::channel();
+    self
+      .tx
+      .send(TdlibCommand::Request { payload, respond_to: tx })
+      .map_err(|_| TgError::Other("TDLib поток не запущен".into()))?;
+
+    match tokio::time::timeout(timeout, rx).await {
+      Ok(Ok(Ok(v))) => Ok(v),
+      Ok(Ok(Err(e))) => Err(TgError::Other(e.to_string())),
+      Ok(Err(_)) => Err(TgError::Other("TDLib не вернул ответ".into())),
+      Err(_) => Err(TgError::Other(timeouts::RESPONSE_TIMEOUT.into()))
+    }
+  }
+
+  /// Запрос с повторами по политике класса. Повторяются только таймауты
+  /// и короткие FLOOD_WAIT, поэтому так вызываются лишь запросы на чтение.
+  async fn request_with(&self, payload: Value, policy: RequestPolicy) -> Result<Value, TgError> {
+    let raw = self.request_raw_with(payload, policy).await?;
+    parse_response(&raw)
+  }
+
+  async fn request_raw_with(&self, payload: Value, policy: RequestPolicy) -> Result<String, TgError> {
+    let mut attempt = 1;
+    loop {
+      match self.request_raw(payload.clone(), policy.timeout).await {
+        Err(TgError::Other(msg)) => {
+          let Some(delay) = policy.retry_delay(attempt, &msg) else {
+            return Err(TgError::Other(msg));
+          };
+          let kind = payload.get("@type").and_then(|v| v.as_str()).unwrap_or("");
+          tracing::warn!(event = "tdlib_request_retry", request = kind, attempt, error = %msg, "Повтор запроса к TDLib");
+          tokio::time::sleep(delay).await;
+          attempt += 1;
+        }
+        other => return other
+      }
+    }
+  }
+
+  async fn ensure_authorized(&self) -> Result<(), TgError> {
+    let state = self
+      .request_with(json!({"@type":"getAuthorizationState"}), timeouts::policy(TimeoutClass::Interactive))
+      .await?;
+    let t = state.get("@type").and_then(|v| v.as_str()).unwrap_or("");
+    if t != "authorizationStateReady" {
+      return Err(TgError::AuthRequired);
+    }
+    Ok(())
+  }
+
+  /// Ждет, пока сервер подтвердит отправку, и возвращает постоянный id сообщения.
+  async fn wait_send_confirmation(&self, msg_id: MessageId, timeout: Duration) -> Result<MessageId, TgError> {
+    let immediate = { self.send_results.lock().remove(&msg_id) };
+    if let Some(result) = immediate {
+      return match result {
+        Ok(id) if id > 0 => Ok(id),
+        Ok(_) => Err(TgError::Other("TDLib вернул некорректный id отправленного сообщения".into())),
+        Err(err) => Err(TgError::Other(err))
+      };
+    }
+
+    let (tx, rx) = oneshot::channel();
+    {
+      let mut guard = self.send_waiters.lock();
+      guard.insert(msg_id, tx);
+    }
+    match tokio::time::timeout(timeout, rx).await {
+      Ok(Ok(Ok(id))) if id > 0 => Ok(id),
+      Ok(Ok(Ok(_))) => Err(TgError::Other("TDLib вернул некорректный id отправленного сообщения".into())),
+      Ok(Ok(Err(e))) => Err(TgError::Other(e.to_string())),
+      Ok(Err(_)) => Err(TgError::Other("TDLib не подтвердил отправку сообщения".into())),
+      Err(_) => {
+        self.send_waiters.lock().remove(&msg_id);
+        // TDLib продолжает отправку: поздний результат подхватит обработчик обновлений.
+        Err(TgError::SendPending)
+      }
+    }
+  }
+
+  async fn download_to(
+    &self,
+    chat_id: ChatId,
+    message_id: MessageId,
+    target: std::path::PathBuf,
+    progress: Option<ProgressSink>
+  ) -> Result<std::path::PathBuf, TgError> {
+    self.ensure_authorized().await?;
+    let session_name = tdlib_session_name();
+    ensure_tdlib_files_session_dirs(&self.paths, &session_name).map_err(TgError::Io)?;
+    let msg = self
+      .request(
+        json!({
+          "@type":"getMessage",
+          "chat_id": chat_id,
+          "message_id": message_id
+        }),
+        timeouts::get(TimeoutClass::Mutation)
+      )
+      .await?;
+
+    let content = msg
+      .get("content")
+      .ok_or_else(|| TgError::Other("Не удалось получить содержимое сообщения".into()))?;
+    let (file_id, _) = extract_file_ref_from_content(content)
+      .ok_or_else(|| TgError::Other("Не удалось получить файл из сообщения".into()))?;
+
+    let size = extract_file_size(content).map(|v| v.max(0) as u64);
+    let downloaded = self.download_tdlib_file(file_id, size, progress).await?;
+
+    let mut local_path = local_path_from_file(&downloaded);
+    if local_path.is_none() {
+      if let Ok(file) = self.request_with(json!({"@type":"getFile","file_id":file_id}), timeouts::policy(TimeoutClass::Interactive)).await {
+        local_path = local_path_from_file(&file);
+      }
+    }
+
+    let Some(src) = local_path else {
+      return Err(TgError::Other("Не удалось получить локальный путь к файлу".into()));
+    };
+    let src_path = PathBuf::from(src);
+    if !src_path.exists() {
+      return Err(TgError::Other("Файл не найден в кеше TDLib".into()));
+    }
 
-        if client.is_none() {
-          if config.is_some() && lib_path.is_none() && !build_attempted {
-            build_attempted = true;
-            match attempt_tdlib_download(&paths_for_thread, &app_for_thread) {
-              Ok(Some(p)) => {
-                lib_path = Some(p);
-              }
-              Ok(None) => {}
-              Err(e) => {
-                tracing::warn!("Автоскачивание TDLib не удалось: {e}");
-                emit_build_log(&app_for_thread, "stderr", &format!("Ошибка загрузки TDLib: {e}"));
-              }
-            }
+    if let Some(parent) = target.parent() {
+      std::fs::create_dir_all(parent).map_err(TgError::Io)?;
+    }
+    std::fs::copy(&src_path, &target).map_err(TgError::Io)?;
+    // Локальная копия уже сохранена в cache/downloads. Просим TDLib удалить внутренний кеш-файл,
+    // чтобы не накапливались дубли в cache/tdlib_files.
+    if let Err(e) = self
+      .request(json!({"@type":"deleteFile","file_id": file_id}), timeouts::get(TimeoutClass::Quick))
+      .await
+    {
+      tracing::debug!(event = "tdlib_delete_cache_file_failed", file_id = file_id, error = %e, "Не удалось очистить кеш TDLib после скачивания");
+    }
+    Ok(target)
+  }
 
-            if lib_path.is_none() {
-              match attempt_tdlib_build(&paths_for_thread, &app_for_thread) {
-                Ok(p) => {
-                  lib_path = Some(p);
-                }
-                Err(e) => {
-                  tracing::error!("Автосборка TDLib не удалась: {e}");
-                  set_auth_state(&app_for_thread, AuthState::WaitConfig, &mut last_state);
-                }
-              }
-            }
-          }
+  /// Запускает несинхронный downloadFile и ждет завершения по `updateFile`,
+  /// по пути сообщая ход в `progress`. Возвращает итоговый объект файла.
+  async fn download_tdlib_file(&self, file_id: i64, size: Option<u64>, progress: Option<ProgressSink>) -> Result<Value, TgError> {
+    // Ждущего регистрируем до запроса: короткий файл может докачаться раньше ответа.
+    let token = NEXT_DOWNLOAD_TOKEN.fetch_add(1, Ordering::Relaxed);
+    let (tx, rx) = oneshot::channel();
+    self.download_watches.lock().entry(file_id).or_default().push(DownloadWatch {
+      token,
+      progress: progress.clone(),
+      ranges: None,
+      last_emit: None,
+      started: false,
+      done: tx
+    });
 
-          if let (Some(_cfg), Some(lp)) = (config.as_ref(), lib_path.as_ref()) {
-            match TdlibClient::load(lp) {
-              Ok(c) => {
-                c.set_verbosity(2);
-                let _ = c.send(&json!({"@type":"getAuthorizationState"}).to_string());
-                for msg in pending.drain(..) {
-                  let _ = c.send(&msg);
-                }
-                client = Some(c);
-                waiting_for_params = false;
-                params_sent = false;
-              }
-              Err(e) => {
-                tracing::error!(tdlib_path = %lp.display(), error = %e, "Не удалось загрузить TDLib");
-                set_auth_state(&app_for_thread, AuthState::WaitConfig, &mut last_state);
-              }
-            }
-          }
-        }
+    let cancel_on_drop = CancelDownloadOnDrop { tg: self, file_id, token, armed: true };
+    let res = self.await_tdlib_download(file_id, token, size, progress, rx).await;
+    cancel_on_drop.disarm();
+    res
+  }
 
-        if let Some(c) = client.as_ref() {
-          if let Some(resp) = c.receive(0.1) {
-            let value: Value = match serde_json::from_str(&resp) {
-              Ok(v) => v,
-              Err(e) => {
-                tracing::error!("Не удалось распарсить ответ TDLib: {e}");
-                continue;
-              }
-            };
-            if handle_request_response(&value, &mut pending_requests) {
-              continue;
-            }
-            let mut response_ctx = ResponseCtx {
-              client: c,
-              config: &mut config,
-              waiting_for_params: &mut waiting_for_params,
-              params_sent: &mut params_sent,
-              app: &app_for_thread,
-              last_state: &mut last_state,
-              send_waiters: &waiters_for_thread,
-              send_results: &results_for_thread
-            };
-            if let Err(e) = handle_tdlib_response(&value, &mut response_ctx) {
-              tracing::error!("Ошибка TDLib: {e}");
-            }
-          }
-        }
+  async fn await_tdlib_download(
+    &self,
+    file_id: i64,
+    token: i64,
+    size: Option<u64>,
+    progress: Option<ProgressSink>,
+    rx: oneshot::Receiver<Result<Value, String>>
+  ) -> Result<Value, TgError> {
+    let offset = self.resume_offset(file_id).await;
+    if offset > 0 {
+      tracing::info!(event = "tdlib_download_resumed", file_id = file_id, offset = offset, "Скачивание продолжается с места обрыва");
+    }
+    let started = self
+      .request_with(
+        json!({
+          "@type":"downloadFile",
+          "file_id": file_id,
+          "priority": 1,
+          "offset": offset,
+          "limit": 0,
+          "synchronous": false
+        }),
+        // Запуск скачивания отвечает быстро; число повторов берется из профиля скачиваний.
+        timeouts::policy(TimeoutClass::Transfer).with_timeout(timeouts::get(TimeoutClass::Mutation))
+      )
+      .await;
+    let started = match started {
+      Ok(file) => file,
+      Err(e) => {
+        self.forget_download_watch(file_id, token);
+        return Err(e);
+      }
+    };
+    mark_download_started(&self.download_watches, file_id, token);
+    if download_completed(&started) {
+      self.forget_download_watch(file_id, token);
+      if let Some(sink) = progress {
+        sink(download_progress_of(&started));
       }
+      return Ok(started);
+    }
 
-      if let Some(c) = client {
-        c.destroy();
+    match tokio::time::timeout(timeouts::for_transfer(size), rx).await {
+      Ok(Ok(Ok(file))) => Ok(file),
+      Ok(Ok(Err(e))) => Err(TgError::Other(e)),
+      Ok(Err(_)) => Err(TgError::Other("TDLib не сообщил о завершении скачивания".into())),
+      Err(_) => {
+        // Скачивание отменяем, только если этот файл больше никто не ждет.
+        if self.forget_download_watch(file_id, token) {
+          let _ = self
+            .request(json!({"@type":"cancelDownloadFile","file_id": file_id,"only_if_pending": false}), timeouts::get(TimeoutClass::Quick))
+            .await;
+        }
+        Err(TgError::Other("Таймаут скачивания файла".into()))
       }
-    });
+    }
+  }
 
-    Ok(Self { tx, paths, send_waiters, send_results })
+  /// Уже скачанное начало файла в кеше TDLib. Скачивание после обрыва или
+  /// перезапуска продолжается с него, а не с нуля.
+  async fn resume_offset(&self, file_id: i64) -> i64 {
+    match self.request(json!({"@type":"getFile","file_id": file_id}), timeouts::get(TimeoutClass::Quick)).await {
+      Ok(file) => downloaded_prefix(&file),
+      Err(_) => 0
+    }
   }
 
-  async fn request(&self, payload: Value, timeout: Duration) -> Result<Value, TgError> {
-    let (tx, rx) = oneshot::channel();
-    self
-      .tx
-      .send(TdlibCommand::Request { payload, respond_to: tx })
-      .map_err(|_| TgError::Other("TDLib поток не запущен".into()))?;
+  /// Запрос без ожидания ответа, например из `Drop`.
+  fn send_td(&self, payload: Value) {
+    let _ = self.tx.send(TdlibCommand::Td(payload.to_string()));
+  }
 
-    match tokio::time::timeout(timeout, rx).await {
-      Ok(Ok(Ok(v))) => Ok(v),
-      Ok(Ok(Err(e))) => Err(TgError::Other(e.to_string())),
-      Ok(Err(_)) => Err(TgError::Other("TDLib не вернул ответ".into())),
-      Err(_) => Err(TgError::Other("Таймаут ответа TDLib".into()))
+  /// Снимает ждущего скачивание; true, если файл больше никто не ждет.
+  fn forget_download_watch(&self, file_id: i64, token: i64) -> bool {
+    let mut guard = self.download_watches.lock();
+    let Some(list) = guard.get_mut(&file_id) else {
+      return true;
+    };
+    list.retain(|w| w.token != token);
+    if list.is_empty() {
+      guard.remove(&file_id);
+      return true;
     }
+    false
   }
 
-  async fn ensure_authorized(&self) -> Result<(), TgError> {
-    let state = self
-      .request(json!({"@type":"getAuthorizationState"}), Duration::from_secs(10))
+  /// Отправка служебного текстового сообщения через очередь чата. Очередь
+  /// отпускается только после подтверждения сервером, поэтому порядок в канале
+  /// совпадает с порядком вызовов.
+  async fn send_metadata_text(&self, chat_id: ChatId, text: &str) -> Result<(ChatId, MessageId), TgError> {
+    let _turn = self.metadata_queue.acquire(chat_id).await;
+    self.ensure_authorized().await?;
+
+    let res = self
+      .request(
+        json!({
+          "@type":"sendMessage",
+          "chat_id": chat_id,
+          "input_message_content": {
+            "@type":"inputMessageText",
+            "text": { "@type":"formattedText", "text": text },
+            "disable_web_page_preview": true,
+            "clear_draft": false
+          }
+        }),
+        timeouts::get(TimeoutClass::Mutation)
+      )
       .await?;
-    let t = state.get("@type").and_then(|v| v.as_str()).unwrap_or("");
-    if t != "authorizationStateReady" {
-      return Err(TgError::AuthRequired);
-    }
+
+    let msg_id = res
+      .get("id")
+      .and_then(|v| v.as_i64())
+      .ok_or_else(|| TgError::Other("TDLib не вернул message.id".into()))?;
+    let chat_id = res
+      .get("chat_id")
+      .and_then(|v| v.as_i64())
+      .unwrap_or(chat_id);
+    let pending = res
+      .get("sending_state")
+      .and_then(|v| v.get("@type"))
+      .and_then(|v| v.as_str())
+      == Some("messageSendingStatePending");
+    let msg_id = if pending {
+      self.wait_send_confirmation(msg_id, timeouts::get(TimeoutClass::Mutation)).await?
+    } else {
+      msg_id
+    };
+    Ok((chat_id, msg_id))
+  }
+
+  /// Правка служебного сообщения через ту же очередь чата.
+  async fn edit_metadata(&self, chat_id: ChatId, payload: Value) -> Result<(), TgError> {
+    let _turn = self.metadata_queue.acquire(chat_id).await;
+    self.ensure_authorized().await?;
+    let _ = self.request(payload, timeouts::get(TimeoutClass::Mutation)).await?;
     Ok(())
   }
 
@@ -771,7 +1965,7 @@ impl TdlibTelegram {
       return Ok(false);
     }
     let sg = self
-      .request(json!({"@type":"getSupergroup","supergroup_id":supergroup_id}), Duration::from_secs(10))
+      .request_with(json!({"@type":"getSupergroup","supergroup_id":supergroup_id}), timeouts::policy(TimeoutClass::Interactive))
       .await?;
     let status = sg
       .get("status")
@@ -789,7 +1983,7 @@ impl TdlibTelegram {
 
     for title in [storage_channel_title(), storage_channel_title_legacy()] {
       if let Ok(res) = self
-        .request(json!({"@type":"searchChats","query":title,"limit":20}), Duration::from_secs(10))
+        .request_with(json!({"@type":"searchChats","query":title,"limit":20}), timeouts::policy(TimeoutClass::Interactive))
         .await
       {
         if let Some(list) = res.get("chat_ids").and_then(|v| v.as_array()) {
@@ -807,7 +2001,7 @@ impl TdlibTelegram {
     if chat_ids.is_empty() {
       for title in [storage_channel_title(), storage_channel_title_legacy()] {
         if let Ok(res) = self
-          .request(json!({"@type":"searchChatsOnServer","query":title,"limit":20}), Duration::from_secs(10))
+          .request_with(json!({"@type":"searchChatsOnServer","query":title,"limit":20}), timeouts::policy(TimeoutClass::Interactive))
           .await
         {
           if let Some(list) = res.get("chat_ids").and_then(|v| v.as_array()) {
@@ -826,7 +2020,7 @@ impl TdlibTelegram {
     let mut fallback: Option<ChatId> = None;
     for chat_id in chat_ids {
       let chat = self
-        .request(json!({"@type":"getChat","chat_id":chat_id}), Duration::from_secs(10))
+        .request_with(json!({"@type":"getChat","chat_id":chat_id}), timeouts::policy(TimeoutClass::Interactive))
         .await?;
       let title = chat.get("title").and_then(|v| v.as_str()).unwrap_or("");
       let chat_type = chat.get("type").and_then(|v| v.as_object());
@@ -848,11 +2042,11 @@ impl TdlibTelegram {
           let _ = self
             .request(
               json!({"@type":"deleteChatHistory","chat_id":chat_id,"remove_from_chat_list":true,"revoke":true}),
-              Duration::from_secs(10)
+              timeouts::get(TimeoutClass::Interactive)
             )
             .await;
           let _ = self
-            .request(json!({"@type":"leaveChat","chat_id":chat_id}), Duration::from_secs(10))
+            .request(json!({"@type":"leaveChat","chat_id":chat_id}), timeouts::get(TimeoutClass::Interactive))
             .await;
           continue;
         }
@@ -873,7 +2067,7 @@ impl TdlibTelegram {
     let mut chat_ids: Vec<ChatId> = Vec::new();
 
     if let Ok(res) = self
-      .request(json!({"@type":"searchChats","query":backup_channel_title(),"limit":20}), Duration::from_secs(10))
+      .request_with(json!({"@type":"searchChats","query":backup_channel_title(),"limit":20}), timeouts::policy(TimeoutClass::Interactive))
       .await
     {
       if let Some(list) = res.get("chat_ids").and_then(|v| v.as_array()) {
@@ -889,7 +2083,7 @@ impl TdlibTelegram {
 
     if chat_ids.is_empty() {
       if let Ok(res) = self
-        .request(json!({"@type":"searchChatsOnServer","query":backup_channel_title(),"limit":20}), Duration::from_secs(10))
+        .request_with(json!({"@type":"searchChatsOnServer","query":backup_channel_title(),"limit":20}), timeouts::policy(TimeoutClass::Interactive))
         .await
       {
         if let Some(list) = res.get("chat_ids").and_then(|v| v.as_array()) {
@@ -906,7 +2100,7 @@ impl TdlibTelegram {
 
     for chat_id in chat_ids {
       let chat = self
-        .request(json!({"@type":"getChat","chat_id":chat_id}), Duration::from_secs(10))
+        .request_with(json!({"@type":"getChat","chat_id":chat_id}), timeouts::policy(TimeoutClass::Interactive))
         .await?;
       let title = chat.get("title").and_then(|v| v.as_str()).unwrap_or("");
       let chat_type = chat.get("type").and_then(|v| v.as_object());
@@ -928,11 +2122,11 @@ impl TdlibTelegram {
           let _ = self
             .request(
               json!({"@type":"deleteChatHistory","chat_id":chat_id,"remove_from_chat_list":true,"revoke":true}),
-              Duration::from_secs(10)
+              timeouts::get(TimeoutClass::Interactive)
             )
             .await;
           let _ = self
-            .request(json!({"@type":"leaveChat","chat_id":chat_id}), Duration::from_secs(10))
+            .request(json!({"@type":"leaveChat","chat_id":chat_id}), timeouts::get(TimeoutClass::Interactive))
             .await;
           continue;
         }
@@ -955,7 +2149,7 @@ impl TdlibTelegram {
           "is_channel":true,
           "description":"Хранилище CloudTG"
         }),
-        Duration::from_secs(20)
+        timeouts::get(TimeoutClass::Mutation)
       )
       .await?;
     let chat_id = chat
@@ -974,7 +2168,7 @@ impl TdlibTelegram {
           "is_channel":true,
           "description":"Бэкапы CloudTG"
         }),
-        Duration::from_secs(20)
+        timeouts::get(TimeoutClass::Mutation)
       )
       .await?;
     let chat_id = chat
@@ -1007,14 +2201,14 @@ impl TdlibTelegram {
 
   async fn ensure_storage_channel_config(&self, chat_id: ChatId) -> Result<(), TgError> {
     let chat = self
-      .request(json!({"@type":"getChat","chat_id":chat_id}), Duration::from_secs(10))
+      .request_with(json!({"@type":"getChat","chat_id":chat_id}), timeouts::policy(TimeoutClass::Interactive))
       .await?;
     let title = chat.get("title").and_then(|v| v.as_str()).unwrap_or("");
     if title != storage_channel_title() {
       let _ = self
         .request(
           json!({"@type":"setChatTitle","chat_id":chat_id,"title":storage_channel_title()}),
-          Duration::from_secs(10)
+          timeouts::get(TimeoutClass::Interactive)
         )
         .await?;
       tracing::info!(event = "storage_channel_title_updated", chat_id = chat_id, "Название канала обновлено");
@@ -1032,7 +2226,7 @@ impl TdlibTelegram {
               "photo": { "@type":"inputFileLocal", "path": path_str }
             }
           }),
-          Duration::from_secs(20)
+          timeouts::get(TimeoutClass::Mutation)
         )
         .await?;
       tracing::info!(event = "storage_channel_photo_updated", chat_id = chat_id, "Иконка канала обновлена");
@@ -1057,7 +2251,7 @@ impl TdlibTelegram {
             "disable_mention_notifications": false
           }
         }),
-        Duration::from_secs(10)
+        timeouts::get(TimeoutClass::Interactive)
       )
       .await?;
     tracing::info!(event = "storage_channel_notifications_enabled", chat_id = chat_id, "Уведомления канала включены");
@@ -1067,14 +2261,14 @@ impl TdlibTelegram {
 
   async fn ensure_backup_channel_config(&self, chat_id: ChatId) -> Result<(), TgError> {
     let chat = self
-      .request(json!({"@type":"getChat","chat_id":chat_id}), Duration::from_secs(10))
+      .request_with(json!({"@type":"getChat","chat_id":chat_id}), timeouts::policy(TimeoutClass::Interactive))
       .await?;
     let title = chat.get("title").and_then(|v| v.as_str()).unwrap_or("");
     if title != backup_channel_title() {
       let _ = self
         .request(
           json!({"@type":"setChatTitle","chat_id":chat_id,"title":backup_channel_title()}),
-          Duration::from_secs(10)
+          timeouts::get(TimeoutClass::Interactive)
         )
         .await?;
       tracing::info!(event = "backup_channel_title_updated", chat_id = chat_id, "Название канала обновлено");
@@ -1092,7 +2286,7 @@ impl TdlibTelegram {
               "photo": { "@type":"inputFileLocal", "path": path_str }
             }
           }),
-          Duration::from_secs(20)
+          timeouts::get(TimeoutClass::Mutation)
         )
         .await?;
       tracing::info!(event = "backup_channel_photo_updated", chat_id = chat_id, "Иконка канала обновлена");
@@ -1117,7 +2311,7 @@ impl TdlibTelegram {
             "disable_mention_notifications": false
           }
         }),
-        Duration::from_secs(10)
+        timeouts::get(TimeoutClass::Interactive)
       )
       .await?;
     tracing::info!(event = "backup_channel_notifications_enabled", chat_id = chat_id, "Уведомления канала включены");
@@ -1148,14 +2342,14 @@ impl TelegramService for TdlibTelegram {
 
   async fn auth_resend_code(&self) -> Result<(), TgError> {
     let _ = self
-      .request(json!({"@type":"resendAuthenticationCode"}), Duration::from_secs(20))
+      .request(json!({"@type":"resendAuthenticationCode"}), timeouts::get(TimeoutClass::Mutation))
       .await?;
     Ok(())
   }
 
   async fn auth_code_resend_timeout(&self) -> Result<Option<i32>, TgError> {
     let state = self
-      .request(json!({"@type":"getAuthorizationState"}), Duration::from_secs(10))
+      .request_with(json!({"@type":"getAuthorizationState"}), timeouts::policy(TimeoutClass::Interactive))
       .await?;
     let state_type = state.get("@type").and_then(|v| v.as_str()).unwrap_or("");
     if state_type != "authorizationStateWaitCode" {
@@ -1188,13 +2382,13 @@ impl TelegramService for TdlibTelegram {
 
   async fn auth_logout(&self) -> Result<(), TgError> {
     let state = self
-      .request(json!({"@type":"getAuthorizationState"}), Duration::from_secs(10))
+      .request_with(json!({"@type":"getAuthorizationState"}), timeouts::policy(TimeoutClass::Interactive))
       .await?;
     let state_type = state.get("@type").and_then(|v| v.as_str()).unwrap_or("");
     if state_type == "authorizationStateClosed" || state_type == "authorizationStateClosing" {
       return Ok(());
     }
-    let _ = self.request(json!({"@type":"logOut"}), Duration::from_secs(20)).await?;
+    let _ = self.request(json!({"@type":"logOut"}), timeouts::get(TimeoutClass::Mutation)).await?;
     Ok(())
   }
 
@@ -1208,7 +2402,7 @@ impl TelegramService for TdlibTelegram {
   async fn storage_check_channel(&self, chat_id: ChatId) -> Result<bool, TgError> {
     self.ensure_authorized().await?;
     let chat = self
-      .request(json!({"@type":"getChat","chat_id":chat_id}), Duration::from_secs(10))
+      .request_with(json!({"@type":"getChat","chat_id":chat_id}), timeouts::policy(TimeoutClass::Interactive))
       .await?;
     let title = chat.get("title").and_then(|v| v.as_str()).unwrap_or("");
     if title != storage_channel_title() && title != storage_channel_title_legacy() {
@@ -1236,7 +2430,7 @@ impl TelegramService for TdlibTelegram {
   async fn backup_check_channel(&self, chat_id: ChatId) -> Result<bool, TgError> {
     self.ensure_authorized().await?;
     let chat = self
-      .request(json!({"@type":"getChat","chat_id":chat_id}), Duration::from_secs(10))
+      .request_with(json!({"@type":"getChat","chat_id":chat_id}), timeouts::policy(TimeoutClass::Interactive))
       .await?;
     let title = chat.get("title").and_then(|v| v.as_str()).unwrap_or("");
     if title != backup_channel_title() {
@@ -1313,12 +2507,87 @@ impl TelegramService for TdlibTelegram {
     Ok(chat_id)
   }
 
+  async fn storage_chat_ref(&self, chat_id: ChatId) -> Result<ChatRef, TgError> {
+    self.ensure_authorized().await?;
+    let chat = self
+      .request_with(json!({"@type":"getChat","chat_id":chat_id}), timeouts::policy(TimeoutClass::Interactive))
+      .await?;
+    let supergroup_id = chat
+      .get("type")
+      .and_then(|t| t.get("supergroup_id"))
+      .and_then(|v| v.as_i64())
+      .filter(|id| *id != 0);
+    let mut invite_link = None;
+    if let Some(id) = supergroup_id {
+      // Ссылку не создаем: новая ссылка открыла бы приватный канал посторонним.
+      if let Ok(full) = self
+        .request_with(json!({"@type":"getSupergroupFullInfo","supergroup_id":id}), timeouts::policy(TimeoutClass::Interactive))
+        .await
+      {
+        invite_link = full
+          .get("invite_link")
+          .and_then(|v| v.get("invite_link"))
+          .and_then(|v| v.as_str())
+          .filter(|v| !v.is_empty())
+          .map(|v| v.to_string());
+      }
+    }
+    Ok(ChatRef { supergroup_id, invite_link })
+  }
+
+  async fn storage_resolve_chat_ref(&self, chat_ref: &ChatRef) -> Result<Option<ChatId>, TgError> {
+    self.ensure_authorized().await?;
+    tracing::info!(event = "storage_chat_warm_up", "Прогрев списков чатов TDLib");
+    for list in ["chatListMain", "chatListArchive"] {
+      if let Err(e) = self
+        .request_with(json!({"@type":"getChats","chat_list":{"@type":list},"limit":500}), timeouts::policy(TimeoutClass::Bulk))
+        .await
+      {
+        tracing::debug!(event = "storage_chat_warm_up_list_failed", list = list, error = %e, "Не удалось загрузить список чатов");
+      }
+    }
+
+    let mut candidates: Vec<ChatId> = Vec::new();
+    if let Some(supergroup_id) = chat_ref.supergroup_id {
+      if let Ok(chat) = self
+        .request(
+          json!({"@type":"createSupergroupChat","supergroup_id":supergroup_id,"force":true}),
+          timeouts::get(TimeoutClass::Interactive)
+        )
+        .await
+      {
+        if let Some(id) = chat.get("id").and_then(|v| v.as_i64()) {
+          candidates.push(id);
+        }
+      }
+    }
+    if let Some(link) = chat_ref.invite_link.as_deref() {
+      if let Ok(info) = self
+        .request_with(json!({"@type":"checkChatInviteLink","invite_link":link}), timeouts::policy(TimeoutClass::Interactive))
+        .await
+      {
+        if let Some(id) = info.get("chat_id").and_then(|v| v.as_i64()).filter(|id| *id != 0) {
+          candidates.push(id);
+        }
+      }
+    }
+    // Поиск по названию сюда не подходит: он может найти другой канал, а
+    // ссылки в БД должны указывать на те же сообщения.
+    for chat_id in candidates {
+      if self.storage_check_channel(chat_id).await.unwrap_or(false) {
+        tracing::info!(event = "storage_chat_resolved", chat_id = chat_id, "Канал хранения найден после прогрева");
+        return Ok(Some(chat_id));
+      }
+    }
+    Ok(None)
+  }
+
   async fn storage_delete_channel(&self, chat_id: ChatId) -> Result<(), TgError> {
     self.ensure_authorized().await?;
     tracing::info!(event = "storage_channel_delete", chat_id = chat_id, "Удаление старого канала хранения");
 
     let chat = self
-      .request(json!({"@type":"getChat","chat_id":chat_id}), Duration::from_secs(10))
+      .request_with(json!({"@type":"getChat","chat_id":chat_id}), timeouts::policy(TimeoutClass::Interactive))
       .await?;
     let chat_type = chat.get("type").and_then(|v| v.as_object());
     let is_channel = chat_type
@@ -1339,7 +2608,7 @@ impl TelegramService for TdlibTelegram {
         let can_delete = self.is_supergroup_usable(supergroup_id).await.unwrap_or(false);
         if can_delete {
           if let Err(e) = self
-            .request(json!({"@type":"deleteSupergroup","supergroup_id":supergroup_id}), Duration::from_secs(10))
+            .request(json!({"@type":"deleteSupergroup","supergroup_id":supergroup_id}), timeouts::get(TimeoutClass::Interactive))
             .await
           {
             tracing::warn!(event = "storage_channel_delete_failed", chat_id = chat_id, error = %e, "Не удалось удалить канал, пробую выйти");
@@ -1353,11 +2622,11 @@ impl TelegramService for TdlibTelegram {
     let _ = self
       .request(
         json!({"@type":"deleteChatHistory","chat_id":chat_id,"remove_from_chat_list":true,"revoke":true}),
-        Duration::from_secs(10)
+        timeouts::get(TimeoutClass::Interactive)
       )
       .await;
     let _ = self
-      .request(json!({"@type":"leaveChat","chat_id":chat_id}), Duration::from_secs(10))
+      .request(json!({"@type":"leaveChat","chat_id":chat_id}), timeouts::get(TimeoutClass::Interactive))
       .await;
     Ok(())
   }
@@ -1366,8 +2635,8 @@ impl TelegramService for TdlibTelegram {
     -> Result<SearchMessagesResult, TgError> {
     self.ensure_authorized().await?;
     let offset = if from_message_id == 0 { 0 } else { -1 };
-    let res = self
-      .request(
+    let raw = self
+      .request_raw_with(
         json!({
           "@type":"getChatHistory",
           "chat_id": chat_id,
@@ -1376,26 +2645,11 @@ impl TelegramService for TdlibTelegram {
           "limit": limit,
           "only_local": false
         }),
-        Duration::from_secs(30)
+        timeouts::policy(TimeoutClass::Bulk)
       )
       .await?;
 
-    let mut messages = Vec::new();
-    if let Some(list) = res.get("messages").and_then(|v| v.as_array()) {
-      for m in list {
-        let id = m.get("id").and_then(|v| v.as_i64()).unwrap_or(0);
-        if id == 0 {
-          continue;
-        }
-        let date = m.get("date").and_then(|v| v.as_i64()).unwrap_or(0);
-        let (text, caption, file_size, file_name) = if let Some(content) = m.get("content") {
-          (extract_text(content), extract_caption(content), extract_file_size(content), extract_file_name(content))
-        } else {
-          (None, None, None, None)
-        };
-        messages.push(HistoryMessage { id, date, text, caption, file_size, file_name });
-      }
-    }
+    let messages = parse_response::<MessagesPage>(&raw)?.into_history();
     let next_from_message_id = messages.last().map(|m| m.id).unwrap_or(0);
 
     Ok(SearchMessagesResult { total_count: None, next_from_message_id, messages })
@@ -1404,8 +2658,8 @@ impl TelegramService for TdlibTelegram {
   async fn search_chat_messages(&self, chat_id: ChatId, query: String, from_message_id: MessageId, limit: i32)
     -> Result<SearchMessagesResult, TgError> {
     self.ensure_authorized().await?;
-    let res = self
-      .request(
+    let raw = self
+      .request_raw_with(
         json!({
           "@type":"searchChatMessages",
           "chat_id": chat_id,
@@ -1417,35 +2671,14 @@ impl TelegramService for TdlibTelegram {
           "sender_id": null,
           "topic_id": null
         }),
-        Duration::from_secs(30)
+        timeouts::policy(TimeoutClass::Bulk)
       )
       .await?;
 
-    let total_count = res
-      .get("total_count")
-      .and_then(|v| v.as_i64())
-      .and_then(|v| if v < 0 { None } else { Some(v) });
-    let next_from_message_id = res
-      .get("next_from_message_id")
-      .and_then(|v| v.as_i64())
-      .unwrap_or(0);
-
-    let mut messages = Vec::new();
-    if let Some(list) = res.get("messages").and_then(|v| v.as_array()) {
-      for m in list {
-        let id = m.get("id").and_then(|v| v.as_i64()).unwrap_or(0);
-        if id == 0 {
-          continue;
-        }
-        let date = m.get("date").and_then(|v| v.as_i64()).unwrap_or(0);
-        let (text, caption, file_size, file_name) = if let Some(content) = m.get("content") {
-          (extract_text(content), extract_caption(content), extract_file_size(content), extract_file_name(content))
-        } else {
-          (None, None, None, None)
-        };
-        messages.push(HistoryMessage { id, date, text, caption, file_size, file_name });
-      }
-    }
+    let page: MessagesPage = parse_response(&raw)?;
+    let total_count = page.total_count.filter(|v| *v >= 0);
+    let next_from_message_id = page.next_from_message_id.unwrap_or(0);
+    let messages = page.into_history();
 
     Ok(SearchMessagesResult { total_count, next_from_message_id, messages })
   }
@@ -1458,12 +2691,9 @@ impl TelegramService for TdlibTelegram {
   async fn search_chats(&self, query: String, limit: i32) -> Result<Vec<ChatInfo>, TgError> {
     self.ensure_authorized().await?;
     let q = query.trim().to_string();
-    let mut out: Vec<ChatInfo> = Vec::new();
-    let mut seen: std::collections::HashSet<i64> = std::collections::HashSet::new();
-
     let mut ids: Vec<i64> = Vec::new();
     if !q.is_empty() {
-      if let Ok(res) = self.request(json!({"@type":"searchChats","query":q,"limit":limit}), Duration::from_secs(10)).await {
+      if let Ok(res) = self.request_with(json!({"@type":"searchChats","query":q,"limit":limit}), timeouts::policy(TimeoutClass::Interactive)).await {
         if let Some(list) = res.get("chat_ids").and_then(|v| v.as_array()) {
           for id in list {
             if let Some(v) = id.as_i64() {
@@ -1473,7 +2703,7 @@ impl TelegramService for TdlibTelegram {
         }
       }
       if ids.is_empty() {
-        if let Ok(res) = self.request(json!({"@type":"searchChatsOnServer","query":q,"limit":limit}), Duration::from_secs(10)).await {
+        if let Ok(res) = self.request_with(json!({"@type":"searchChatsOnServer","query":q,"limit":limit}), timeouts::policy(TimeoutClass::Interactive)).await {
           if let Some(list) = res.get("chat_ids").and_then(|v| v.as_array()) {
             for id in list {
               if let Some(v) = id.as_i64() {
@@ -1487,40 +2717,46 @@ impl TelegramService for TdlibTelegram {
 
     if q.starts_with('@') {
       let username = q.trim_start_matches('@');
-      if let Ok(chat) = self.request(json!({"@type":"searchPublicChat","username":username}), Duration::from_secs(10)).await {
+      if let Ok(chat) = self.request_with(json!({"@type":"searchPublicChat","username":username}), timeouts::policy(TimeoutClass::Interactive)).await {
         if let Some(chat_id) = chat.get("id").and_then(|v| v.as_i64()) {
           ids.push(chat_id);
         }
       }
     }
 
-    for chat_id in ids {
-      if seen.contains(&chat_id) {
-        continue;
-      }
-      if let Some(info) = chat_info_from_id(self, chat_id).await {
-        seen.insert(chat_id);
-        out.push(info);
-      }
-    }
-    Ok(out)
+    Ok(chat_infos_from_ids(self, ids, &[]).await)
+  }
+
+  async fn saved_messages_chat(&self) -> Result<ChatId, TgError> {
+    self.ensure_authorized().await?;
+    let me = self.request_with(json!({"@type":"getMe"}), timeouts::policy(TimeoutClass::Interactive)).await?;
+    let user_id = me
+      .get("id")
+      .and_then(|v| v.as_i64())
+      .ok_or_else(|| TgError::Other("TDLib не вернул id текущего пользователя".into()))?;
+    let chat = self
+      .request(json!({"@type":"createPrivateChat","user_id":user_id,"force":true}), timeouts::get(TimeoutClass::Interactive))
+      .await?;
+    chat
+      .get("id")
+      .and_then(|v| v.as_i64())
+      .ok_or_else(|| TgError::Other("TDLib не вернул id чата «Избранное»".into()))
   }
 
   async fn recent_chats(&self, limit: i32) -> Result<Vec<ChatInfo>, TgError> {
     self.ensure_authorized().await?;
     let mut out: Vec<ChatInfo> = Vec::new();
-    let mut seen: std::collections::HashSet<i64> = std::collections::HashSet::new();
 
-    if let Ok(me) = self.request(json!({"@type":"getMe"}), Duration::from_secs(10)).await {
+    if let Ok(me) = self.request_with(json!({"@type":"getMe"}), timeouts::policy(TimeoutClass::Interactive)).await {
       if let Some(user_id) = me.get("id").and_then(|v| v.as_i64()) {
-        if let Ok(chat) = self.request(json!({"@type":"createPrivateChat","user_id":user_id,"force":true}), Duration::from_secs(10)).await {
+        if let Ok(chat) = self.request(json!({"@type":"createPrivateChat","user_id":user_id,"force":true}), timeouts::get(TimeoutClass::Interactive)).await {
           if let Some(chat_id) = chat.get("id").and_then(|v| v.as_i64()) {
-            seen.insert(chat_id);
             out.push(ChatInfo {
               id: chat_id,
               title: "Избранное".to_string(),
               kind: "личный чат".to_string(),
-              username: None
+              username: None,
+              photo_path: None
             });
           }
         }
@@ -1528,98 +2764,59 @@ impl TelegramService for TdlibTelegram {
     }
 
     let res = self
-      .request(json!({"@type":"getChats","chat_list":{"@type":"chatListMain"},"limit":limit}), Duration::from_secs(10))
+      .request_with(json!({"@type":"getChats","chat_list":{"@type":"chatListMain"},"limit":limit}), timeouts::policy(TimeoutClass::Interactive))
       .await?;
-    if let Some(list) = res.get("chat_ids").and_then(|v| v.as_array()) {
-      for id in list {
-        let chat_id = match id.as_i64() {
-          Some(v) => v,
-          None => continue
-        };
-        if seen.contains(&chat_id) {
-          continue;
-        }
-        if let Some(info) = chat_info_from_id(self, chat_id).await {
-          seen.insert(chat_id);
-          out.push(info);
-        }
-      }
-    }
+    let ids: Vec<i64> = res
+      .get("chat_ids")
+      .and_then(|v| v.as_array())
+      .map(|list| list.iter().filter_map(|id| id.as_i64()).collect())
+      .unwrap_or_default();
+    let skip: Vec<i64> = out.iter().map(|c| c.id).collect();
+    out.extend(chat_infos_from_ids(self, ids, &skip).await);
     Ok(out)
   }
 
-  async fn send_text_message(&self, chat_id: ChatId, text: String) -> Result<UploadedMessage, TgError> {
+  async fn chat_folders(&self) -> Result<Vec<ChatFolder>, TgError> {
     self.ensure_authorized().await?;
-    tracing::info!(event = "tdlib_send_text_message", chat_id = chat_id, "Отправка тестового сообщения");
-
-    let res = self
-      .request(
-        json!({
-          "@type":"sendMessage",
-          "chat_id": chat_id,
-          "input_message_content": {
-            "@type":"inputMessageText",
-            "text": { "@type":"formattedText", "text": text },
-            "disable_web_page_preview": true,
-            "clear_draft": false
-          }
-        }),
-        Duration::from_secs(20)
-      )
-      .await?;
-
-    let msg_id = res
-      .get("id")
-      .and_then(|v| v.as_i64())
-      .ok_or_else(|| TgError::Other("TDLib не вернул message.id".into()))?;
-    let chat_id = res
-      .get("chat_id")
-      .and_then(|v| v.as_i64())
-      .unwrap_or(chat_id);
-
-    tracing::info!(event = "tdlib_send_text_message_done", chat_id = chat_id, message_id = msg_id, "Тестовое сообщение отправлено");
-    Ok(UploadedMessage { chat_id, message_id: msg_id, caption_or_text: text })
+    Ok(CHAT_FOLDERS.lock().get(&self.account).cloned().unwrap_or_default())
   }
 
-  async fn send_dir_message(&self, chat_id: ChatId, text: String) -> Result<UploadedMessage, TgError> {
+  async fn folder_chats(&self, folder_id: i32, limit: i32) -> Result<Vec<ChatInfo>, TgError> {
     self.ensure_authorized().await?;
-    tracing::info!(event = "tdlib_send_dir_message", chat_id = chat_id, "Отправка сообщения директории");
-
     let res = self
       .request(
-        json!({
-          "@type":"sendMessage",
-          "chat_id": chat_id,
-          "input_message_content": {
-            "@type":"inputMessageText",
-            "text": { "@type":"formattedText", "text": text },
-            "disable_web_page_preview": true,
-            "clear_draft": false
-          }
-        }),
-        Duration::from_secs(20)
+        json!({"@type":"getChats","chat_list":{"@type":"chatListFolder","chat_folder_id":folder_id},"limit":limit}),
+        timeouts::get(TimeoutClass::Interactive)
       )
-      .await?;
-
-    let msg_id = res
-      .get("id")
-      .and_then(|v| v.as_i64())
-      .ok_or_else(|| TgError::Other("TDLib не вернул message.id".into()))?;
-    let chat_id = res
-      .get("chat_id")
-      .and_then(|v| v.as_i64())
-      .unwrap_or(chat_id);
+      .await?;
+    let ids: Vec<i64> = res
+      .get("chat_ids")
+      .and_then(|v| v.as_array())
+      .map(|list| list.iter().filter_map(|id| id.as_i64()).collect())
+      .unwrap_or_default();
+    Ok(chat_infos_from_ids(self, ids, &[]).await)
+  }
+
+  async fn send_text_message(&self, chat_id: ChatId, text: String) -> Result<UploadedMessage, TgError> {
+    tracing::info!(event = "tdlib_send_text_message", chat_id = chat_id, "Отправка тестового сообщения");
+    let (chat_id, msg_id) = self.send_metadata_text(chat_id, &text).await?;
+    tracing::info!(event = "tdlib_send_text_message_done", chat_id = chat_id, message_id = msg_id, "Тестовое сообщение отправлено");
+    Ok(UploadedMessage { chat_id, message_id: msg_id, caption_or_text: text })
+  }
 
+  async fn send_dir_message(&self, chat_id: ChatId, text: String) -> Result<UploadedMessage, TgError> {
+    tracing::info!(event = "tdlib_send_dir_message", chat_id = chat_id, "Отправка сообщения директории");
+    let (chat_id, msg_id) = self.send_metadata_text(chat_id, &text).await?;
     tracing::info!(event = "tdlib_send_dir_message_done", chat_id = chat_id, message_id = msg_id, "Сообщение директории отправлено");
     Ok(UploadedMessage { chat_id, message_id: msg_id, caption_or_text: text })
   }
 
   async fn edit_message_text(&self, chat_id: ChatId, message_id: MessageId, text: String) -> Result<(), TgError> {
-    self.ensure_authorized().await?;
     tracing::info!(event = "tdlib_edit_message", chat_id = chat_id, message_id = message_id, "Обновление текста сообщения");
 
-    let _ = self
-      .request(
+    self
+      .edit_metadata(
+        chat_id,
         json!({
           "@type":"editMessageText",
           "chat_id": chat_id,
@@ -1630,32 +2827,102 @@ impl TelegramService for TdlibTelegram {
             "disable_web_page_preview": true,
             "clear_draft": false
           }
-        }),
-        Duration::from_secs(20)
+        })
       )
-      .await?;
-
-    Ok(())
+      .await
   }
 
-  async fn edit_message_caption(&self, chat_id: ChatId, message_id: MessageId, caption: String) -> Result<(), TgError> {
-    self.ensure_authorized().await?;
-    tracing::info!(event = "tdlib_edit_message_caption", chat_id = chat_id, message_id = message_id, "Обновление подписи сообщения");
+  async fn pin_message(&self, chat_id: ChatId, message_id: MessageId) -> Result<(), TgError> {
+    tracing::info!(event = "tdlib_pin_message", chat_id = chat_id, message_id = message_id, "Закрепление сообщения");
 
-    let _ = self
-      .request(
+    self
+      .edit_metadata(
+        chat_id,
         json!({
-          "@type":"editMessageCaption",
+          "@type":"pinChatMessage",
           "chat_id": chat_id,
           "message_id": message_id,
-          "caption": { "@type":"formattedText", "text": caption },
-          "show_caption_above_media": false
-        }),
-        Duration::from_secs(20)
+          "disable_notification": true,
+          "only_for_self": false
+        })
       )
-      .await?;
+      .await
+  }
 
-    Ok(())
+  async fn edit_message_caption(&self, chat_id: ChatId, message_id: MessageId, caption: String) -> Result<(), TgError> {
+    tracing::info!(event = "tdlib_edit_message_caption", chat_id = chat_id, message_id = message_id, "Обновление подписи сообщения");
+
+    self.edit_metadata(chat_id, caption_edit_payload(chat_id, message_id, caption)).await
+  }
+
+  async fn edit_message_captions(&self, edits: Vec<CaptionEdit>) -> Result<Vec<Result<(), TgError>>, TgError> {
+    self.ensure_authorized().await?;
+    tracing::info!(event = "tdlib_edit_message_captions", count = edits.len(), "Пакетное обновление подписей");
+
+    let mut by_chat: Vec<(ChatId, Vec<(usize, CaptionEdit)>)> = Vec::new();
+    for (idx, edit) in edits.into_iter().enumerate() {
+      match by_chat.iter_mut().find(|(chat_id, _)| *chat_id == edit.chat_id) {
+        Some((_, list)) => list.push((idx, edit)),
+        None => by_chat.push((edit.chat_id, vec![(idx, edit)]))
+      }
+    }
+
+    // FLOOD_WAIT одной правки останавливает всю пачку: остальные запросы в
+    // полете ждут `retry_after`, а не получают ту же ошибку следом.
+    let gate: Mutex<CaptionFloodGate> = Mutex::new(CaptionFloodGate::default());
+    let mut results: Vec<(usize, Result<(), TgError>)> = Vec::new();
+    for (chat_id, list) in by_chat {
+      // Пачка занимает очередь чата целиком: одиночные правки и сообщения
+      // директорий не вклиниваются между ее запросами.
+      let _turn = self.metadata_queue.acquire(chat_id).await;
+      let gate = &gate;
+      let done: Vec<(usize, Result<(), TgError>)> = futures_util::stream::iter(list.into_iter().enumerate())
+        .then(|(n, item)| async move {
+          if n > 0 {
+            tokio::time::sleep(CAPTION_EDIT_PACING).await;
+          }
+          item
+        })
+        .map(|(idx, edit)| async move {
+          let payload = caption_edit_payload(edit.chat_id, edit.message_id, edit.caption);
+          let mut retries = 0;
+          loop {
+            let until = {
+              let state = gate.lock();
+              if let Some(error) = &state.abort {
+                return (idx, Err(TgError::Other(error.clone())));
+              }
+              state.until
+            };
+            if let Some(until) = until {
+              tokio::time::sleep_until(until).await;
+            }
+            let msg = match self.request(payload.clone(), timeouts::get(TimeoutClass::Mutation)).await {
+              Err(TgError::Other(msg)) => msg,
+              res => return (idx, res.map(|_| ()))
+            };
+            let Some(wait) = limits::flood_wait(&msg) else {
+              return (idx, Err(TgError::Other(msg)));
+            };
+            let mut state = gate.lock();
+            if wait > CAPTION_EDIT_MAX_FLOOD_WAIT || retries == CAPTION_EDIT_FLOOD_RETRIES {
+              state.abort = Some(msg.clone());
+              return (idx, Err(TgError::Other(msg)));
+            }
+            let resume = tokio::time::Instant::now() + wait;
+            state.until = Some(state.until.map_or(resume, |cur| cur.max(resume)));
+            drop(state);
+            tracing::warn!(event = "tdlib_edit_captions_flood_wait", wait_secs = wait.as_secs(), "Telegram ограничил частоту правок, пачка ждет");
+            retries += 1;
+          }
+        })
+        .buffered(CAPTION_EDIT_CONCURRENCY)
+        .collect()
+        .await;
+      results.extend(done);
+    }
+    results.sort_by_key(|(idx, _)| *idx);
+    Ok(results.into_iter().map(|(_, res)| res).collect())
   }
 
   async fn send_file(&self, chat_id: ChatId, path: std::path::PathBuf, caption: String) -> Result<UploadedMessage, TgError> {
@@ -1663,6 +2930,7 @@ impl TelegramService for TdlibTelegram {
     tracing::info!(event = "tdlib_send_file", chat_id = chat_id, "Отправка файла");
 
     let path_str = path.to_string_lossy().to_string();
+    let size = std::fs::metadata(&path).ok().map(|m| m.len());
     let res = self
       .request(
         json!({
@@ -1675,7 +2943,7 @@ impl TelegramService for TdlibTelegram {
             "disable_content_type_detection": false
           }
         }),
-        Duration::from_secs(60)
+        timeouts::for_transfer(size)
       )
       .await?;
 
@@ -1687,6 +2955,20 @@ impl TelegramService for TdlibTelegram {
       .get("chat_id")
       .and_then(|v| v.as_i64())
       .unwrap_or(chat_id);
+    let pending = res
+      .get("sending_state")
+      .and_then(|v| v.get("@type"))
+      .and_then(|v| v.as_str())
+      == Some("messageSendingStatePending");
+    // До подтверждения у сообщения временный id: в базу должен попасть настоящий.
+    let msg_id = if pending {
+      let abandon_on_drop = AbandonSendOnDrop { tg: self, chat_id, message_id: msg_id, armed: true };
+      let confirmed = self.wait_send_confirmation(msg_id, timeouts::for_transfer(size)).await;
+      abandon_on_drop.disarm();
+      confirmed?
+    } else {
+      msg_id
+    };
 
     tracing::info!(event = "tdlib_send_file_done", chat_id = chat_id, message_id = msg_id, "Файл отправлен");
     Ok(UploadedMessage { chat_id, message_id: msg_id, caption_or_text: caption })
@@ -1703,7 +2985,7 @@ impl TelegramService for TdlibTelegram {
           "chat_id": chat_id,
           "message_id": message_id
         }),
-        Duration::from_secs(20)
+        timeouts::get(TimeoutClass::Mutation)
       )
       .await?;
 
@@ -1726,7 +3008,7 @@ impl TelegramService for TdlibTelegram {
               "disable_content_type_detection": false
             }
           }),
-          Duration::from_secs(60)
+          timeouts::get(TimeoutClass::Transfer)
         )
         .await
     };
@@ -1759,36 +3041,7 @@ impl TelegramService for TdlibTelegram {
     let final_id = if msg_id > 0 {
       msg_id
     } else {
-      let immediate = { self.send_results.lock().remove(&msg_id) };
-      if let Some(result) = immediate {
-        match result {
-          Ok(id) if id > 0 => id,
-          Ok(_) => return Err(TgError::Other("TDLib вернул некорректный id отправленного сообщения".into())),
-          Err(err) => return Err(TgError::Other(err))
-        }
-      } else {
-        let (tx, rx) = oneshot::channel();
-        {
-          let mut guard = self.send_waiters.lock();
-          guard.insert(msg_id, tx);
-        }
-        match tokio::time::timeout(Duration::from_secs(20), rx).await {
-          Ok(Ok(Ok(id))) if id > 0 => id,
-          Ok(Ok(Ok(_))) => {
-            return Err(TgError::Other("TDLib вернул некорректный id отправленного сообщения".into()));
-          }
-          Ok(Ok(Err(e))) => {
-            return Err(TgError::Other(e.to_string()));
-          }
-          Ok(Err(_)) => {
-            return Err(TgError::Other("TDLib не подтвердил отправку сообщения".into()));
-          }
-          Err(_) => {
-            self.send_waiters.lock().remove(&msg_id);
-            return Err(TgError::Other("Таймаут подтверждения отправки сообщения".into()));
-          }
-        }
-      }
+      self.wait_send_confirmation(msg_id, timeouts::get(TimeoutClass::Mutation)).await?
     };
 
     Ok(UploadedMessage { chat_id, message_id: final_id, caption_or_text: caption })
@@ -1812,7 +3065,7 @@ impl TelegramService for TdlibTelegram {
             "protect_content": false
           }
         }),
-        Duration::from_secs(30)
+        timeouts::get(TimeoutClass::Bulk)
       )
       .await?;
     if let Some(list) = res.get("messages").and_then(|v| v.as_array()) {
@@ -1854,7 +3107,7 @@ impl TelegramService for TdlibTelegram {
             "protect_content": false
           }
         }),
-        Duration::from_secs(30)
+        timeouts::get(TimeoutClass::Bulk)
       )
       .await?;
     let mut out = Vec::new();
@@ -1885,7 +3138,7 @@ impl TelegramService for TdlibTelegram {
           "message_ids": message_ids,
           "revoke": revoke
         }),
-        Duration::from_secs(20)
+        timeouts::get(TimeoutClass::Mutation)
       )
       .await?;
     Ok(())
@@ -1893,6 +3146,25 @@ impl TelegramService for TdlibTelegram {
 
   async fn download_message_file(&self, chat_id: ChatId, message_id: MessageId, target: std::path::PathBuf)
     -> Result<std::path::PathBuf, TgError> {
+    self.download_to(chat_id, message_id, target, None).await
+  }
+
+  async fn download_message_file_with_progress(
+    &self,
+    chat_id: ChatId,
+    message_id: MessageId,
+    target: std::path::PathBuf,
+    progress: ProgressSink
+  ) -> Result<std::path::PathBuf, TgError> {
+    self.download_to(chat_id, message_id, target, Some(progress)).await
+  }
+
+  async fn stream_message_file(
+    &self,
+    chat_id: ChatId,
+    message_id: MessageId,
+    ranges: RangeSink
+  ) -> Result<StreamingFile, TgError> {
     self.ensure_authorized().await?;
     let session_name = tdlib_session_name();
     ensure_tdlib_files_session_dirs(&self.paths, &session_name).map_err(TgError::Io)?;
@@ -1903,58 +3175,113 @@ impl TelegramService for TdlibTelegram {
           "chat_id": chat_id,
           "message_id": message_id
         }),
-        Duration::from_secs(20)
+        timeouts::get(TimeoutClass::Mutation)
       )
       .await?;
-
     let content = msg
       .get("content")
       .ok_or_else(|| TgError::Other("Не удалось получить содержимое сообщения".into()))?;
     let (file_id, _) = extract_file_ref_from_content(content)
       .ok_or_else(|| TgError::Other("Не удалось получить файл из сообщения".into()))?;
-
-    let downloaded = self
+    let total = extract_file_size(content).filter(|v| *v > 0).map(|v| v as u64);
+
+    // Итог скачивания здесь не ждем: диапазоны приходят в `ranges`, пока файл
+    // читают, а ждущего снимает `stream_stop`.
+    let token = NEXT_DOWNLOAD_TOKEN.fetch_add(1, Ordering::Relaxed);
+    let (tx, _rx) = oneshot::channel();
+    self.download_watches.lock().entry(file_id).or_default().push(DownloadWatch {
+      token,
+      progress: None,
+      ranges: Some(ranges.clone()),
+      last_emit: None,
+      started: false,
+      done: tx
+    });
+    // Высший приоритет и скачивание с начала: плееру нужно сначала начало файла.
+    let mut file = match self
       .request(
         json!({
           "@type":"downloadFile",
           "file_id": file_id,
-          "priority": 1,
+          "priority": 32,
           "offset": 0,
           "limit": 0,
-          "synchronous": true
+          "synchronous": false
         }),
-        Duration::from_secs(60)
+        timeouts::get(TimeoutClass::Mutation)
       )
-      .await?;
+      .await
+    {
+      Ok(file) => file,
+      Err(e) => {
+        self.forget_download_watch(file_id, token);
+        return Err(e);
+      }
+    };
 
-    let mut local_path = local_path_from_file(&downloaded);
-    if local_path.is_none() {
-      if let Ok(file) = self.request(json!({"@type":"getFile","file_id":file_id}), Duration::from_secs(10)).await {
-        local_path = local_path_from_file(&file);
+    // Путь появляется, когда TDLib начал писать файл.
+    let deadline = Instant::now() + timeouts::get(TimeoutClass::Interactive);
+    loop {
+      if download_completed(&file) {
+        self.forget_download_watch(file_id, token);
+      }
+      if let Some(path) = partial_local_path(&file) {
+        let (start, end) = stream_range_of(&file);
+        ranges(start, end);
+        return Ok(StreamingFile { path, total: total.or(download_progress_of(&file).total), file_id, token });
       }
+      if Instant::now() >= deadline {
+        if self.forget_download_watch(file_id, token) {
+          let _ = self
+            .request(json!({"@type":"cancelDownloadFile","file_id": file_id,"only_if_pending": false}), timeouts::get(TimeoutClass::Quick))
+            .await;
+        }
+        return Err(TgError::Other("TDLib не начал скачивание файла".into()));
+      }
+      tokio::time::sleep(Duration::from_millis(200)).await;
+      file = self.request(json!({"@type":"getFile","file_id": file_id}), timeouts::get(TimeoutClass::Quick)).await?;
     }
+  }
 
-    let Some(src) = local_path else {
-      return Err(TgError::Other("Не удалось получить локальный путь к файлу".into()));
-    };
-    let src_path = PathBuf::from(src);
-    if !src_path.exists() {
-      return Err(TgError::Other("Файл не найден в кеше TDLib".into()));
+  async fn stream_seek(&self, stream: &StreamingFile, offset: u64) -> Result<(), TgError> {
+    tracing::debug!(event = "tdlib_stream_seek", file_id = stream.file_id, offset = offset, "Перемотка потокового скачивания");
+    let file = self
+      .request(
+        json!({
+          "@type":"downloadFile",
+          "file_id": stream.file_id,
+          "priority": 32,
+          "offset": offset,
+          "limit": 0,
+          "synchronous": false
+        }),
+        timeouts::get(TimeoutClass::Mutation)
+      )
+      .await?;
+    let sink = self
+      .download_watches
+      .lock()
+      .get(&stream.file_id)
+      .and_then(|list| list.iter().find(|w| w.token == stream.token))
+      .and_then(|w| w.ranges.clone());
+    if let Some(sink) = sink {
+      let (start, end) = stream_range_of(&file);
+      sink(start, end);
     }
+    Ok(())
+  }
 
-    if let Some(parent) = target.parent() {
-      std::fs::create_dir_all(parent).map_err(TgError::Io)?;
-    }
-    std::fs::copy(&src_path, &target).map_err(TgError::Io)?;
-    // Локальная копия уже сохранена в cache/downloads. Просим TDLib удалить внутренний кеш-файл,
-    // чтобы не накапливались дубли в cache/tdlib_files.
-    if let Err(e) = self
-      .request(json!({"@type":"deleteFile","file_id": file_id}), Duration::from_secs(5))
-      .await
-    {
-      tracing::debug!(event = "tdlib_delete_cache_file_failed", file_id = file_id, error = %e, "Не удалось очистить кеш TDLib после скачивания");
+  async fn stream_stop(&self, stream: &StreamingFile) -> Result<(), TgError> {
+    // Докачанный файл остается в кеше TDLib; недокачанный больше никто не ждет.
+    if self.forget_download_watch(stream.file_id, stream.token) {
+      self
+        .request(
+          json!({"@type":"cancelDownloadFile","file_id": stream.file_id,"only_if_pending": false}),
+          timeouts::get(TimeoutClass::Quick)
+        )
+        .await?;
     }
-    Ok(target)
+    Ok(())
   }
 
   async fn message_exists(&self, chat_id: ChatId, message_id: MessageId) -> Result<bool, TgError> {
@@ -1966,7 +3293,7 @@ impl TelegramService for TdlibTelegram {
           "chat_id": chat_id,
           "message_id": message_id
         }),
-        Duration::from_secs(10)
+        timeouts::get(TimeoutClass::Interactive)
       )
       .await;
 
@@ -1986,6 +3313,87 @@ impl TelegramService for TdlibTelegram {
       Err(e) => Err(e)
     }
   }
+
+  async fn messages_exist(&self, chat_id: ChatId, message_ids: Vec<MessageId>) -> Result<Vec<bool>, TgError> {
+    self.ensure_authorized().await?;
+    let mut out = Vec::with_capacity(message_ids.len());
+    // getMessages отвечает null на каждое отсутствующее сообщение.
+    for chunk in message_ids.chunks(100) {
+      let res = self
+        .request(
+          json!({
+            "@type":"getMessages",
+            "chat_id": chat_id,
+            "message_ids": chunk
+          }),
+          timeouts::get(TimeoutClass::Interactive)
+        )
+        .await?;
+      let messages = res.get("messages").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+      for (idx, message_id) in chunk.iter().enumerate() {
+        let id = messages.get(idx).and_then(|m| m.get("id")).and_then(|v| v.as_i64()).unwrap_or(0);
+        out.push(id == *message_id);
+      }
+    }
+    Ok(out)
+  }
+
+  async fn recognize_speech(&self, chat_id: ChatId, message_id: MessageId) -> Result<String, TgError> {
+    self.ensure_authorized().await?;
+    // Ожидание регистрируется до запроса, чтобы не пропустить быстрый ответ.
+    let waiter = SpeechWaiter::register(chat_id, message_id);
+    self
+      .request(
+        json!({
+          "@type":"recognizeSpeech",
+          "chat_id": chat_id,
+          "message_id": message_id
+        }),
+        timeouts::get(TimeoutClass::Mutation)
+      )
+      .await?;
+
+    // Результат приходит обновлением содержимого сообщения: перечитываем его
+    // только после такого обновления или после смены лимита пробных распознаваний.
+    let deadline = tokio::time::Instant::now() + timeouts::get(TimeoutClass::Transfer);
+    loop {
+      let msg = self
+        .request(
+          json!({
+            "@type":"getMessage",
+            "chat_id": chat_id,
+            "message_id": message_id
+          }),
+          timeouts::get(TimeoutClass::Interactive)
+        )
+        .await?;
+      if let Some(text) = speech_result(msg.get("content").unwrap_or(&Value::Null))? {
+        return Ok(text);
+      }
+      if tokio::time::timeout_at(deadline, waiter.notify.notified()).await.is_err() {
+        return Err(TgError::Other("Распознавание речи не завершилось вовремя".into()));
+      }
+    }
+  }
+
+  async fn sticker_set_info(&self, set_id: String) -> Result<StickerSetInfo, TgError> {
+    self.ensure_authorized().await?;
+    let res = self
+      .request(
+        json!({
+          "@type":"getStickerSet",
+          "set_id": set_id
+        }),
+        timeouts::get(TimeoutClass::Interactive)
+      )
+      .await?;
+    let field = |key: &str| res.get(key).and_then(|v| v.as_str()).unwrap_or("").to_string();
+    Ok(StickerSetInfo {
+      id: set_id,
+      name: field("name"),
+      title: field("title")
+    })
+  }
 }
 
 struct CommandCtx<'a> {
@@ -2000,7 +3408,7 @@ struct CommandCtx<'a> {
   build_attempted: &'a mut bool,
   pending: &'a mut Vec<String>,
   app: &'a tauri::AppHandle,
-  last_state: &'a mut Option<AuthState>
+  last_state: &'a mut AuthTracker
 }
 
 fn handle_command(cmd: TdlibCommand, ctx: &mut CommandCtx<'_>) {
@@ -2293,18 +3701,8 @@ fn extract_github_repo(raw: &str) -> Option<String> {
   None
 }
 
-fn github_token() -> Option<String> {
-  std::env::var("GITHUB_TOKEN")
-    .ok()
-    .or_else(|| std::env::var("GH_TOKEN").ok())
-}
-
 fn http_agent() -> ureq::Agent {
-  ureq::Agent::config_builder()
-    .timeout_connect(Some(Duration::from_secs(10)))
-    .timeout_recv_body(Some(Duration::from_secs(60)))
-    .build()
-    .into()
+  github::agent(Duration::from_secs(60))
 }
 
 fn allow_unsafe_tdlib_urls() -> bool {
@@ -2317,41 +3715,8 @@ fn allow_unsafe_tdlib_urls() -> bool {
     .unwrap_or(false)
 }
 
-fn extract_https_host(url: &str) -> Option<String> {
-  let trimmed = url.trim();
-  let rest = trimmed.strip_prefix("https://")?;
-  let host_port = rest.split(&['/', '?', '#'][..]).next()?.trim();
-  if host_port.is_empty() || host_port.contains('@') {
-    return None;
-  }
-  let host = host_port
-    .split(':')
-    .next()
-    .unwrap_or("")
-    .trim_matches(&['[', ']'][..])
-    .to_ascii_lowercase();
-  if host.is_empty() {
-    None
-  } else {
-    Some(host)
-  }
-}
-
-fn is_trusted_tdlib_host(host: &str) -> bool {
-  matches!(
-    host,
-    "github.com"
-      | "api.github.com"
-      | "raw.githubusercontent.com"
-      | "objects.githubusercontent.com"
-      | "github-releases.githubusercontent.com"
-      | "githubusercontent.com"
-      | "codeload.github.com"
-  ) || host.ends_with(".githubusercontent.com")
-}
-
 fn validate_tdlib_download_url(url: &str, label: &str) -> anyhow::Result<()> {
-  let Some(host) = extract_https_host(url) else {
+  let Some(host) = github::https_host(url) else {
     return Err(anyhow::anyhow!(
       "{label}: разрешены только корректные https URL"
     ));
@@ -2367,7 +3732,7 @@ fn validate_tdlib_download_url(url: &str, label: &str) -> anyhow::Result<()> {
     return Ok(());
   }
 
-  if !is_trusted_tdlib_host(&host) {
+  if !github::is_trusted_host(&host) {
     return Err(anyhow::anyhow!(
       "{label}: недоверенный хост '{host}'. Разреши явно через CLOUDTG_ALLOW_UNSAFE_TDLIB_URLS=1"
     ));
@@ -2428,14 +3793,8 @@ fn resolve_tdlib_manifest_url(repo: &str) -> anyhow::Result<Option<String>> {
   }
 
   let agent = http_agent();
-  let mut req = agent
-    .get(&format!("https://api.github.com/repos/{repo}/releases/latest"))
-    .header("User-Agent", "cloudtg")
-    .header("Accept", "application/vnd.github+json");
-  if let Some(token) = github_token() {
-    req = req.header("Authorization", &format!("Bearer {token}"));
-  }
-  let response = req.call().map_err(|e| anyhow::anyhow!("Не удалось получить релиз TDLib: {e}"))?;
+  let response = github::api_get(&agent, &format!("https://api.github.com/repos/{repo}/releases/latest"))
+    .call().map_err(|e| anyhow::anyhow!("Не удалось получить релиз TDLib: {e}"))?;
   let body = response.into_body().read_to_string().map_err(|e| anyhow::anyhow!("Не удалось прочитать ответ релиза: {e}"))?;
   let json: Value = serde_json::from_str(&body)?;
   if let Some(url) = find_manifest_url(&json) {
@@ -2444,14 +3803,8 @@ fn resolve_tdlib_manifest_url(repo: &str) -> anyhow::Result<Option<String>> {
   let tag = json.get("tag_name").and_then(|v| v.as_str()).unwrap_or("");
   tracing::info!(event = "tdlib_manifest_missing", tag = tag, "Манифест TDLib не найден в latest релизе");
 
-  let mut req = agent
-    .get(&format!("https://api.github.com/repos/{repo}/releases?per_page=10"))
-    .header("User-Agent", "cloudtg")
-    .header("Accept", "application/vnd.github+json");
-  if let Some(token) = github_token() {
-    req = req.header("Authorization", &format!("Bearer {token}"));
-  }
-  let response = req.call().map_err(|e| anyhow::anyhow!("Не удалось получить список релизов TDLib: {e}"))?;
+  let response = github::api_get(&agent, &format!("https://api.github.com/repos/{repo}/releases?per_page=10"))
+    .call().map_err(|e| anyhow::anyhow!("Не удалось получить список релизов TDLib: {e}"))?;
   let body = response.into_body().read_to_string().map_err(|e| anyhow::anyhow!("Не удалось прочитать список релизов: {e}"))?;
   let releases: Value = serde_json::from_str(&body)?;
   let Some(list) = releases.as_array() else {
@@ -2485,11 +3838,7 @@ fn find_manifest_url(release: &Value) -> Option<String> {
 fn fetch_tdlib_manifest(url: &str) -> anyhow::Result<TdlibManifest> {
   validate_tdlib_download_url(url, "URL манифеста TDLib")?;
   let agent = http_agent();
-  let mut req = agent.get(url).header("User-Agent", "cloudtg");
-  if let Some(token) = github_token() {
-    req = req.header("Authorization", &format!("Bearer {token}"));
-  }
-  let response = req.call().map_err(|e| anyhow::anyhow!("Не удалось скачать манифест TDLib: {e}"))?;
+  let response = github::get(&agent, url).call().map_err(|e| anyhow::anyhow!("Не удалось скачать манифест TDLib: {e}"))?;
   let body = response.into_body().read_to_string().map_err(|e| anyhow::anyhow!("Не удалось прочитать манифест: {e}"))?;
   let manifest: TdlibManifest = serde_json::from_str(&body)?;
   Ok(manifest)
@@ -2504,11 +3853,7 @@ fn download_tdlib_asset(
   validate_tdlib_download_url(url, "URL артефакта TDLib")?;
   let expected_sha256 = normalize_expected_sha256(expected_sha256)?;
   let agent = http_agent();
-  let mut req = agent.get(url).header("User-Agent", "cloudtg");
-  if let Some(token) = github_token() {
-    req = req.header("Authorization", &format!("Bearer {token}"));
-  }
-  let response = req.call().map_err(|e| anyhow::anyhow!("Не удалось скачать TDLib: {e}"))?;
+  let response = github::get(&agent, url).call().map_err(|e| anyhow::anyhow!("Не удалось скачать TDLib: {e}"))?;
   let mut total = response
     .headers()
     .get("Content-Length")
@@ -2742,9 +4087,10 @@ struct ResponseCtx<'a> {
   waiting_for_params: &'a mut bool,
   params_sent: &'a mut bool,
   app: &'a tauri::AppHandle,
-  last_state: &'a mut Option<AuthState>,
+  last_state: &'a mut AuthTracker,
   send_waiters: &'a SendWaiters,
-  send_results: &'a SendResults
+  send_results: &'a SendResults,
+  download_watches: &'a DownloadWatches
 }
 
 fn handle_tdlib_response(v: &Value, ctx: &mut ResponseCtx<'_>) -> anyhow::Result<()> {
@@ -2765,6 +4111,59 @@ fn handle_tdlib_response(v: &Value, ctx: &mut ResponseCtx<'_>) -> anyhow::Result
     return Ok(());
   }
 
+  if t == "updateOption" {
+    let name = v.get("name").and_then(|n| n.as_str()).unwrap_or("");
+    // int64 в JSON TDLib приходит строкой.
+    let value = v
+      .get("value")
+      .and_then(|o| o.get("value"))
+      .and_then(|n| n.as_i64().or_else(|| n.as_str().and_then(|s| s.parse::<i64>().ok())));
+    if let Some(value) = value {
+      limits::record_option(&ctx.last_state.account, name, value);
+    }
+    return Ok(());
+  }
+
+  if t == "updateChatPosition" {
+    let position = v.get("position");
+    let is_main = position
+      .and_then(|p| p.get("list"))
+      .and_then(|l| l.get("@type"))
+      .and_then(|l| l.as_str())
+      == Some("chatListMain");
+    if is_main {
+      let chat_id = v.get("chat_id").and_then(|c| c.as_i64()).unwrap_or(0);
+      let is_pinned = position
+        .and_then(|p| p.get("is_pinned"))
+        .and_then(|p| p.as_bool())
+        .unwrap_or(false);
+      limits::record_chat_position(&ctx.last_state.account, chat_id, is_pinned);
+    }
+    return Ok(());
+  }
+
+  if t == "updateChatFolders" {
+    record_chat_folders(&ctx.last_state.account, v);
+    return Ok(());
+  }
+
+  if t == "updateFile" {
+    if let Some(file) = v.get("file") {
+      handle_file_update(file, ctx.download_watches);
+    }
+    return Ok(());
+  }
+
+  if t == "updateConnectionState" {
+    let ready = v
+      .get("state")
+      .and_then(|s| s.get("@type"))
+      .and_then(|s| s.as_str())
+      == Some("connectionStateReady");
+    crate::metrics::record_connection_state(ready);
+    return Ok(());
+  }
+
   if t.starts_with("authorizationState") {
     handle_auth_state(
       v,
@@ -2778,8 +4177,12 @@ fn handle_tdlib_response(v: &Value, ctx: &mut ResponseCtx<'_>) -> anyhow::Result
     return Ok(());
   }
 
+  // Обновления неактивного аккаунта в базу не пишем: ее сейчас держит другой
+  // аккаунт, а пропущенное догонит синхронизация после переключения.
+  let active = ctx.last_state.is_active(ctx.app);
+
   if t == "updateNewMessage" {
-    if let Some(message) = v.get("message") {
+    if let Some(message) = v.get("message").filter(|_| active) {
       if let Some((chat_id, msg)) = history_message_from_object(message) {
         schedule_storage_index(ctx.app, chat_id, msg);
       }
@@ -2790,7 +4193,8 @@ fn handle_tdlib_response(v: &Value, ctx: &mut ResponseCtx<'_>) -> anyhow::Result
   if t == "updateMessageContent" {
     let chat_id = v.get("chat_id").and_then(|v| v.as_i64()).unwrap_or(0);
     let message_id = v.get("message_id").and_then(|v| v.as_i64()).unwrap_or(0);
-    if chat_id != 0 && message_id != 0 {
+    wake_speech_waiters(Some((chat_id, message_id)));
+    if active && chat_id != 0 && message_id != 0 {
       if let Some(content) = v.get("new_content") {
         let msg = history_message_from_content(message_id, Utc::now().timestamp(), content);
         schedule_storage_index(ctx.app, chat_id, msg);
@@ -2799,6 +4203,29 @@ fn handle_tdlib_response(v: &Value, ctx: &mut ResponseCtx<'_>) -> anyhow::Result
     return Ok(());
   }
 
+  // Закончились или восстановились пробные распознавания: ожидающие
+  // перечитывают сообщения, статус у них мог смениться на ошибку.
+  if t == "updateSpeechRecognitionTrial" {
+    wake_speech_waiters(None);
+    return Ok(());
+  }
+
+  if t == "updateDeleteMessages" {
+    // from_cache — TDLib только выгрузил сообщения из памяти, в чате они остались.
+    let permanent = v.get("is_permanent").and_then(|v| v.as_bool()).unwrap_or(false);
+    let from_cache = v.get("from_cache").and_then(|v| v.as_bool()).unwrap_or(false);
+    let chat_id = v.get("chat_id").and_then(|v| v.as_i64()).unwrap_or(0);
+    let message_ids: Vec<MessageId> = v
+      .get("message_ids")
+      .and_then(|ids| ids.as_array())
+      .map(|ids| ids.iter().filter_map(|id| id.as_i64()).collect())
+      .unwrap_or_default();
+    if active && permanent && !from_cache && chat_id != 0 && !message_ids.is_empty() {
+      schedule_storage_delete(ctx.app, chat_id, message_ids);
+    }
+    return Ok(());
+  }
+
   if t == "updateMessageSendSucceeded" {
     if let Some(old_id) = v.get("old_message_id").and_then(|v| v.as_i64()) {
       let new_id = v
@@ -2816,6 +4243,11 @@ fn handle_tdlib_response(v: &Value, ctx: &mut ResponseCtx<'_>) -> anyhow::Result
         }
       }
     }
+    // Отправка могла завершиться уже после таймаута вызова: индексатор найдет
+    // файл по f= в подписи и переведет запись на настоящее сообщение.
+    if let Some((chat_id, msg)) = v.get("message").filter(|_| active).and_then(history_message_from_object) {
+      schedule_storage_index(ctx.app, chat_id, msg);
+    }
     return Ok(());
   }
 
@@ -2827,6 +4259,7 @@ fn handle_tdlib_response(v: &Value, ctx: &mut ResponseCtx<'_>) -> anyhow::Result
         .and_then(|m| m.as_str())
         .unwrap_or("Не удалось отправить сообщение")
         .to_string();
+      limits::record_error(&ctx.last_state.account, &err);
       if let Some(tx) = ctx.send_waiters.lock().remove(&old_id) {
         let _ = tx.send(Err(anyhow::anyhow!(err.clone())));
       } else {
@@ -2836,48 +4269,62 @@ fn handle_tdlib_response(v: &Value, ctx: &mut ResponseCtx<'_>) -> anyhow::Result
           guard.clear();
         }
       }
+      if let Some(message) = v.get("message").filter(|_| active) {
+        schedule_send_failed(ctx.app, message, old_id);
+      }
     }
     return Ok(());
   }
 
   if t == "error" {
     let msg = v.get("message").and_then(|m| m.as_str()).unwrap_or("неизвестная ошибка");
+    limits::record_error(&ctx.last_state.account, msg);
     tracing::error!("TDLib вернул ошибку: {msg}");
   }
 
   Ok(())
 }
 
-fn handle_request_response(
-  v: &Value,
-  pending_requests: &mut HashMap<u64, oneshot::Sender<anyhow::Result<Value>>>
-) -> bool {
-  let Some(extra) = v.get("@extra") else {
-    return false;
-  };
-  let id = match extra {
-    Value::Number(n) => n.as_u64(),
-    Value::String(s) => s.parse::<u64>().ok(),
-    _ => None
+/// Отдает ответ ждущему запросу и возвращает `None`; остальное (обновления)
+/// возвращает обратно для полного разбора. Текст ответа уходит запросу без
+/// копии и без промежуточного `Value`. Обновления не несут `@extra`, поэтому
+/// без него служебные поля не разбираются: текст разбирается один раз.
+fn handle_request_response(raw: String, pending_requests: &mut PendingRequests, account: &str) -> Option<String> {
+  if !raw.contains("\"@extra\"") {
+    return Some(raw);
+  }
+  let Ok(envelope) = serde_json::from_str::<ResponseEnvelope>(&raw) else {
+    return Some(raw);
   };
-  let Some(id) = id else {
-    return false;
+  let id = match envelope.extra {
+    Some(RequestExtra::Number(n)) => Some(n),
+    Some(RequestExtra::Text(s)) => s.parse::<u64>().ok(),
+    None => None
   };
-  let Some(tx) = pending_requests.remove(&id) else {
-    return false;
+  let Some(tx) = id.and_then(|id| pending_requests.remove(&id)) else {
+    return Some(raw);
   };
 
-  if v.get("@type").and_then(|t| t.as_str()) == Some("error") {
-    let msg = v
-      .get("message")
-      .and_then(|m| m.as_str())
+  if envelope.kind.as_deref() == Some("error") {
+    let body = serde_json::from_str::<ErrorBody>(&raw).ok();
+    let msg = body
+      .as_ref()
+      .and_then(|b| b.message.as_deref())
       .unwrap_or("неизвестная ошибка")
       .to_string();
+    let limit = limits::record_error(account, &msg);
+    if crate::diagnostics::is_enabled() {
+      let code = body.map(|b| b.code).unwrap_or(0);
+      match limit {
+        Some(kind) => crate::diagnostics::record_error(kind.as_str()),
+        None => crate::diagnostics::record_error(&format!("tdlib_{code}"))
+      }
+    }
     let _ = tx.send(Err(anyhow::anyhow!(msg)));
   } else {
-    let _ = tx.send(Ok(v.clone()));
+    let _ = tx.send(Ok(raw));
   }
-  true
+  None
 }
 
 fn handle_auth_state(
@@ -2887,7 +4334,7 @@ fn handle_auth_state(
   waiting_for_params: &mut bool,
   params_sent: &mut bool,
   app: &tauri::AppHandle,
-  last_state: &mut Option<AuthState>
+  last_state: &mut AuthTracker
 ) -> anyhow::Result<()> {
   let t = state.get("@type").and_then(|v| v.as_str()).unwrap_or("");
 
@@ -2933,7 +4380,12 @@ fn handle_auth_state(
     "authorizationStateReady" => {
       set_auth_state(app, AuthState::Ready, last_state);
     }
-    "authorizationStateClosing" | "authorizationStateLoggingOut" => {
+    "authorizationStateLoggingOut" => {
+      *params_sent = false;
+      last_state.logging_out = true;
+      set_auth_state(app, AuthState::Unknown, last_state);
+    }
+    "authorizationStateClosing" => {
       *params_sent = false;
       set_auth_state(app, AuthState::Unknown, last_state);
     }
@@ -2953,16 +4405,33 @@ fn handle_auth_state(
   Ok(())
 }
 
-fn set_auth_state(app: &tauri::AppHandle, state: AuthState, last_state: &mut Option<AuthState>) {
-  if last_state.as_ref() == Some(&state) {
+/// Последнее состояние авторизации клиента и аккаунт, которому он принадлежит.
+struct AuthTracker {
+  account: String,
+  last: Option<AuthState>,
+  /// TDLib начал выход из аккаунта, и следующее закрытие клиента ожидаемо.
+  logging_out: bool
+}
+
+impl AuthTracker {
+  fn is_active(&self, app: &tauri::AppHandle) -> bool {
+    app.state::<AppState>().active_account() == self.account
+  }
+}
+
+fn set_auth_state(app: &tauri::AppHandle, state: AuthState, last_state: &mut AuthTracker) {
+  if last_state.last.as_ref() == Some(&state) {
     return;
   }
 
   let app_state = app.state::<AppState>();
-  app_state.set_auth_state(state.clone());
-  *last_state = Some(state.clone());
+  last_state.last = Some(state.clone());
+  // Интерфейс показывает только активный аккаунт.
+  if !app_state.set_account_auth_state(&last_state.account, state.clone()) {
+    return;
+  }
 
-  let payload = AuthEvent { state: auth_state_to_str(&state).to_string() };
+  let payload = AuthEvent { state: state.as_str().to_string() };
   let _ = app.emit("auth_state_changed", payload);
 }
 
@@ -2978,18 +4447,6 @@ struct BuildEvent {
   detail: Option<String>
 }
 
-fn auth_state_to_str(state: &AuthState) -> &'static str {
-  match state {
-    AuthState::Unknown => "unknown",
-    AuthState::WaitConfig => "wait_config",
-    AuthState::WaitPhone => "wait_phone",
-    AuthState::WaitCode => "wait_code",
-    AuthState::WaitPassword => "wait_password",
-    AuthState::Ready => "ready",
-    AuthState::Closed => "closed"
-  }
-}
-
 fn emit_build(app: &tauri::AppHandle, state: &str, message: &str, detail: Option<String>) {
   let detail_for_log = detail.clone();
   let payload = BuildEvent {
@@ -3047,3 +4504,141 @@ fn build_tdlib_parameters(config: &TdlibConfig) -> String {
 fn path_to_str(p: &Path) -> String {
   p.to_string_lossy().to_string()
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn watch(token: i64, ranges: Option<RangeSink>) -> (DownloadWatch, oneshot::Receiver<Result<Value, String>>) {
+    let (done, rx) = oneshot::channel();
+    (DownloadWatch { token, progress: None, ranges, last_emit: None, started: false, done }, rx)
+  }
+
+  fn file_update(file_id: i64, active: bool, completed: bool, downloaded: i64) -> Value {
+    json!({
+      "@type": "file",
+      "id": file_id,
+      "size": 100,
+      "local": {
+        "is_downloading_active": active,
+        "is_downloading_completed": completed,
+        "downloaded_size": downloaded
+      }
+    })
+  }
+
+  #[test]
+  fn file_updates_finish_only_started_downloads() {
+    let watches: DownloadWatches = Default::default();
+    let (first, mut first_rx) = watch(1, None);
+    let (second, mut second_rx) = watch(2, None);
+    let (stream, mut stream_rx) = watch(3, Some(std::sync::Arc::new(|_, _| {})));
+    watches.lock().insert(7, vec![first, second, stream]);
+
+    // Старое неактивное состояние до старта никого не снимает.
+    handle_file_update(&file_update(7, false, false, 0), &watches);
+    assert!(first_rx.try_recv().is_err());
+    assert_eq!(watches.lock()[&7].len(), 3);
+
+    // Ответ на downloadFile пришел раньше активного обновления: обрыв после
+    // него все равно распознается.
+    mark_download_started(&watches, 7, 2);
+    handle_file_update(&file_update(7, false, false, 10), &watches);
+    assert!(matches!(second_rx.try_recv(), Ok(Err(_))));
+    assert!(first_rx.try_recv().is_err());
+
+    handle_file_update(&file_update(7, true, false, 20), &watches);
+    handle_file_update(&file_update(7, false, false, 30), &watches);
+    assert!(matches!(first_rx.try_recv(), Ok(Err(_))));
+
+    // Поток ждет до завершения, даже если скачивание замирало.
+    assert!(stream_rx.try_recv().is_err());
+    handle_file_update(&file_update(7, false, true, 100), &watches);
+    assert!(matches!(stream_rx.try_recv(), Ok(Ok(_))));
+    assert!(!watches.lock().contains_key(&7));
+  }
+
+  fn pending(id: u64) -> (PendingRequests, oneshot::Receiver<anyhow::Result<String>>) {
+    let (tx, rx) = oneshot::channel();
+    (HashMap::from([(id, tx)]), rx)
+  }
+
+  #[test]
+  fn responses_go_to_waiting_requests_and_updates_pass_through() {
+    let (mut requests, mut rx) = pending(5);
+    let raw = r#"{"@type":"chat","id":1,"@extra":5}"#.to_string();
+    assert!(handle_request_response(raw.clone(), &mut requests, "main").is_none());
+    assert_eq!(rx.try_recv().expect("response").expect("ok"), raw);
+    assert!(requests.is_empty());
+
+    // Строковый `@extra` сопоставляется так же, как числовой.
+    let (mut requests, mut rx) = pending(6);
+    assert!(handle_request_response(r#"{"@type":"ok","@extra":"6"}"#.to_string(), &mut requests, "main").is_none());
+    assert!(matches!(rx.try_recv(), Ok(Ok(_))));
+
+    let (mut requests, mut rx) = pending(7);
+    let error = r#"{"@type":"error","code":400,"message":"CHAT_NOT_FOUND","@extra":7}"#.to_string();
+    assert!(handle_request_response(error, &mut requests, "main").is_none());
+    let err = rx.try_recv().expect("response").expect_err("error");
+    assert_eq!(err.to_string(), "CHAT_NOT_FOUND");
+
+    // Обновления и ответы без ждущего запроса уходят на полный разбор.
+    let (mut requests, mut rx) = pending(8);
+    let update = r#"{"@type":"updateFile","file":{"id":1}}"#.to_string();
+    assert_eq!(handle_request_response(update.clone(), &mut requests, "main"), Some(update));
+    let unmatched = r#"{"@type":"ok","@extra":9}"#.to_string();
+    assert_eq!(handle_request_response(unmatched.clone(), &mut requests, "main"), Some(unmatched));
+    assert!(rx.try_recv().is_err());
+    assert!(requests.contains_key(&8));
+  }
+
+  #[test]
+  fn messages_page_skips_null_and_empty_messages() -> Result<(), TgError> {
+    let raw = r#"{
+      "@type": "messages",
+      "total_count": 3,
+      "messages": [
+        {"id": 10, "date": 100, "sender_id": {"@type": "messageSenderUser", "user_id": 42},
+         "content": {"@type": "messageText", "text": {"text": "привет"}}, "is_outgoing": true},
+        null,
+        {"date": 200}
+      ]
+    }"#;
+    let page: MessagesPage = parse_response(raw)?;
+    assert_eq!(page.total_count, Some(3));
+    assert_eq!(page.next_from_message_id, None);
+    let history = page.into_history();
+    assert_eq!(history.len(), 1);
+    assert_eq!((history[0].id, history[0].date, history[0].sender_id), (10, 100, Some(42)));
+    assert_eq!(history[0].text.as_deref(), Some("привет"));
+
+    let empty: MessagesPage = parse_response(r#"{"@type":"messages","total_count":0}"#)?;
+    assert!(empty.into_history().is_empty());
+    assert!(parse_response::<MessagesPage>("not json").is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn close_after_logout_is_not_a_crash() {
+    assert_eq!(closed_exit(true), WorkerExit::LoggedOut);
+    assert!(matches!(closed_exit(false), WorkerExit::Restart(_)));
+  }
+
+  #[test]
+  fn restart_backoff_grows_within_window_and_resets_after_it() {
+    let mut policy = RestartPolicy::default();
+    let start = Instant::now();
+    assert_eq!(policy.record(start), (1, Duration::ZERO));
+    assert_eq!(policy.record(start + Duration::from_secs(1)), (2, RESTART_BACKOFF_BASE));
+    assert_eq!(policy.record(start + Duration::from_secs(2)), (3, RESTART_BACKOFF_BASE * 2));
+    for i in 3..10 {
+      policy.record(start + Duration::from_secs(i));
+    }
+    // Пауза ограничена 16 базовыми интервалами.
+    assert_eq!(policy.record(start + Duration::from_secs(10)).1, RESTART_BACKOFF_BASE * 16);
+
+    // Перезапуски старше окна не учитываются.
+    let later = start + Duration::from_secs(10) + RESTART_WINDOW;
+    assert_eq!(policy.record(later), (1, Duration::ZERO));
+  }
+}
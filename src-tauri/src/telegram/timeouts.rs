@@ -0,0 +1,282 @@
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+
+/// Классы запросов к TDLib. Таймаут берется из активного профиля,
+/// чтобы на медленной сети можно было поднять все значения разом.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutClass {
+  /// Локальные операции с кешем TDLib.
+  Quick,
+  /// Чтение состояния, поиск чатов, getChat.
+  Interactive,
+  /// Отправка и правка сообщений, авторизация.
+  Mutation,
+  /// Постраничное чтение истории канала.
+  Bulk,
+  /// Загрузка и скачивание файлов.
+  Transfer
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeoutPreset {
+  Normal,
+  SlowNetwork,
+  Custom
+}
+
+impl TimeoutPreset {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      TimeoutPreset::Normal => "normal",
+      TimeoutPreset::SlowNetwork => "slow_network",
+      TimeoutPreset::Custom => "custom"
+    }
+  }
+
+  pub fn parse(raw: &str) -> Option<Self> {
+    match raw {
+      "normal" => Some(TimeoutPreset::Normal),
+      "slow_network" => Some(TimeoutPreset::SlowNetwork),
+      "custom" => Some(TimeoutPreset::Custom),
+      _ => None
+    }
+  }
+}
+
+/// Сколько раз повторять запрос после таймаута или короткого FLOOD_WAIT.
+/// Отправки и правки не повторяются: TDLib мог выполнить их, не успев ответить.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RetryProfile {
+  pub interactive: u32,
+  pub bulk: u32,
+  pub download: u32,
+  /// Пауза перед первым повтором, дальше удваивается.
+  pub backoff_ms: u64
+}
+
+impl RetryProfile {
+  pub const NORMAL: RetryProfile = RetryProfile { interactive: 1, bulk: 2, download: 2, backoff_ms: 500 };
+
+  pub const SLOW_NETWORK: RetryProfile = RetryProfile { interactive: 3, bulk: 4, download: 4, backoff_ms: 2000 };
+}
+
+impl Default for RetryProfile {
+  fn default() -> Self {
+    Self::NORMAL
+  }
+}
+
+/// Значения в секундах; `min_transfer_kbps` задает минимальную ожидаемую
+/// скорость, от которой таймаут передачи растет с размером файла.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TimeoutProfile {
+  pub quick: u64,
+  pub interactive: u64,
+  pub mutation: u64,
+  pub bulk: u64,
+  pub transfer: u64,
+  pub min_transfer_kbps: u64,
+  /// В профилях, сохраненных до появления повторов, поля нет.
+  #[serde(default)]
+  pub retries: RetryProfile
+}
+
+impl TimeoutProfile {
+  pub const NORMAL: TimeoutProfile = TimeoutProfile {
+    quick: 5,
+    interactive: 10,
+    mutation: 20,
+    bulk: 30,
+    transfer: 60,
+    min_transfer_kbps: 256,
+    retries: RetryProfile::NORMAL
+  };
+
+  pub const SLOW_NETWORK: TimeoutProfile = TimeoutProfile {
+    quick: 15,
+    interactive: 30,
+    mutation: 60,
+    bulk: 90,
+    transfer: 300,
+    min_transfer_kbps: 32,
+    retries: RetryProfile::SLOW_NETWORK
+  };
+
+  pub fn for_preset(preset: TimeoutPreset, custom: Option<TimeoutProfile>) -> TimeoutProfile {
+    match preset {
+      TimeoutPreset::Normal => Self::NORMAL,
+      TimeoutPreset::SlowNetwork => Self::SLOW_NETWORK,
+      TimeoutPreset::Custom => custom.unwrap_or(Self::NORMAL).sanitized()
+    }
+  }
+
+  /// Не даем выставить нулевые или заведомо бессмысленные значения.
+  pub fn sanitized(self) -> TimeoutProfile {
+    let clamp = |v: u64| v.clamp(1, 3600);
+    TimeoutProfile {
+      quick: clamp(self.quick),
+      interactive: clamp(self.interactive),
+      mutation: clamp(self.mutation),
+      bulk: clamp(self.bulk),
+      transfer: clamp(self.transfer),
+      min_transfer_kbps: self.min_transfer_kbps.clamp(1, 1_000_000),
+      retries: RetryProfile {
+        interactive: self.retries.interactive.min(10),
+        bulk: self.retries.bulk.min(10),
+        download: self.retries.download.min(10),
+        backoff_ms: self.retries.backoff_ms.clamp(100, 60_000)
+      }
+    }
+  }
+
+  fn secs(&self, class: TimeoutClass) -> u64 {
+    match class {
+      TimeoutClass::Quick => self.quick,
+      TimeoutClass::Interactive => self.interactive,
+      TimeoutClass::Mutation => self.mutation,
+      TimeoutClass::Bulk => self.bulk,
+      TimeoutClass::Transfer => self.transfer
+    }
+  }
+
+  fn retries(&self, class: TimeoutClass) -> u32 {
+    match class {
+      TimeoutClass::Quick | TimeoutClass::Mutation => 0,
+      TimeoutClass::Interactive => self.retries.interactive,
+      TimeoutClass::Bulk => self.retries.bulk,
+      TimeoutClass::Transfer => self.retries.download
+    }
+  }
+}
+
+/// Текст ошибки, с которой запрос не дождался ответа TDLib.
+pub const RESPONSE_TIMEOUT: &str = "Таймаут ответа TDLib";
+
+/// Дольше этого FLOOD_WAIT не ждем: пусть ошибка уйдет пользователю.
+const MAX_FLOOD_WAIT_RETRY: Duration = Duration::from_secs(30);
+
+/// Таймаут и повторы одного вызова. Берется из профиля по классу, а вызовы,
+/// которые заранее знают, что будут долгими, переопределяют отдельные поля.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestPolicy {
+  pub timeout: Duration,
+  pub attempts: u32,
+  pub backoff: Duration
+}
+
+impl RequestPolicy {
+  pub fn with_timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// Таймаут не короче `min`, даже если в профиле он меньше.
+  pub fn at_least(mut self, min: Duration) -> Self {
+    self.timeout = self.timeout.max(min);
+    self
+  }
+
+  pub fn with_attempts(mut self, attempts: u32) -> Self {
+    self.attempts = attempts.max(1);
+    self
+  }
+
+  /// Пауза перед следующей попыткой после неудачной `attempt` (с единицы)
+  /// или `None`, если повторять нельзя.
+  pub fn retry_delay(&self, attempt: u32, error: &str) -> Option<Duration> {
+    if attempt >= self.attempts {
+      return None;
+    }
+    let backoff = self.backoff * 2u32.pow((attempt - 1).min(5));
+    if error == RESPONSE_TIMEOUT {
+      return Some(backoff);
+    }
+    let wait = super::limits::flood_wait(error)?;
+    (wait <= MAX_FLOOD_WAIT_RETRY).then_some(wait.max(backoff))
+  }
+}
+
+static ACTIVE: Lazy<RwLock<TimeoutProfile>> = Lazy::new(|| RwLock::new(TimeoutProfile::NORMAL));
+
+pub fn set_profile(profile: TimeoutProfile) {
+  *ACTIVE.write() = profile.sanitized();
+}
+
+pub fn profile() -> TimeoutProfile {
+  *ACTIVE.read()
+}
+
+pub fn get(class: TimeoutClass) -> Duration {
+  Duration::from_secs(ACTIVE.read().secs(class))
+}
+
+pub fn policy(class: TimeoutClass) -> RequestPolicy {
+  let p = profile();
+  RequestPolicy {
+    timeout: Duration::from_secs(p.secs(class)),
+    attempts: p.retries(class) + 1,
+    backoff: Duration::from_millis(p.retries.backoff_ms)
+  }
+}
+
+/// Таймаут передачи с поправкой на размер: базовое значение профиля плюс время
+/// на передачу `bytes` при минимальной ожидаемой скорости.
+pub fn for_transfer(bytes: Option<u64>) -> Duration {
+  let p = profile();
+  let extra = bytes
+    .map(|b| b / (p.min_transfer_kbps * 1024))
+    .unwrap_or(0);
+  Duration::from_secs(p.transfer.saturating_add(extra))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn transfer_timeout_grows_with_size() {
+    let p = TimeoutProfile::NORMAL;
+    let base = Duration::from_secs(p.transfer);
+    set_profile(p);
+    assert_eq!(for_transfer(None), base);
+    let big = 100 * 1024 * p.min_transfer_kbps * 1024;
+    assert_eq!(for_transfer(Some(big)), base + Duration::from_secs(100 * 1024));
+  }
+
+  #[test]
+  fn custom_profile_is_clamped() {
+    let custom = TimeoutProfile { quick: 0, ..TimeoutProfile::NORMAL };
+    assert_eq!(TimeoutProfile::for_preset(TimeoutPreset::Custom, Some(custom)).quick, 1);
+    assert_eq!(TimeoutProfile::for_preset(TimeoutPreset::SlowNetwork, Some(custom)), TimeoutProfile::SLOW_NETWORK);
+  }
+
+  #[test]
+  fn profile_saved_without_retries_gets_defaults() {
+    let raw = r#"{"quick":5,"interactive":10,"mutation":20,"bulk":30,"transfer":60,"min_transfer_kbps":256}"#;
+    let parsed: TimeoutProfile = serde_json::from_str(raw).expect("profile");
+    assert_eq!(parsed.retries, RetryProfile::NORMAL);
+  }
+
+  #[test]
+  fn retries_only_timeouts_and_short_flood_waits() {
+    let policy = RequestPolicy { timeout: Duration::from_secs(10), attempts: 3, backoff: Duration::from_millis(500) };
+    assert_eq!(policy.retry_delay(1, RESPONSE_TIMEOUT), Some(Duration::from_millis(500)));
+    assert_eq!(policy.retry_delay(2, RESPONSE_TIMEOUT), Some(Duration::from_secs(1)));
+    assert_eq!(policy.retry_delay(3, RESPONSE_TIMEOUT), None);
+    assert_eq!(policy.retry_delay(1, "FLOOD_WAIT_7"), Some(Duration::from_secs(7)));
+    assert_eq!(policy.retry_delay(1, "FLOOD_WAIT_600"), None);
+    assert_eq!(policy.retry_delay(1, "CHAT_NOT_FOUND"), None);
+    assert_eq!(policy.with_attempts(1).retry_delay(1, RESPONSE_TIMEOUT), None);
+    assert_eq!(policy.with_timeout(Duration::from_secs(1)).at_least(Duration::from_secs(5)).timeout, Duration::from_secs(5));
+  }
+
+  #[test]
+  fn mutations_are_never_retried() {
+    let p = TimeoutProfile::SLOW_NETWORK;
+    assert_eq!(p.retries(TimeoutClass::Mutation), 0);
+    assert_eq!(p.retries(TimeoutClass::Bulk), RetryProfile::SLOW_NETWORK.bulk);
+  }
+}
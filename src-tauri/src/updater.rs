@@ -0,0 +1,420 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use minisign_verify::{PublicKey, Signature};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Emitter, Manager};
+use tempfile::NamedTempFile;
+
+use crate::github;
+
+/// Подкаталог в кеше приложения для скачанных установщиков.
+const UPDATE_DIR: &str = "update";
+const MAX_NOTES_CHARS: usize = 20_000;
+/// Подпись minisign занимает несколько сотен байт; больше не читаем.
+const MAX_SIGNATURE_BYTES: u64 = 16 * 1024;
+/// Публичный ключ minisign в формате `plugins.updater.pubkey` Tauri
+/// (base64 от файла ключа). Задается при сборке релиза.
+const UPDATER_PUBKEY: Option<&str> = option_env!("CLOUDTG_UPDATER_PUBKEY");
+
+static DOWNLOADING: AtomicBool = AtomicBool::new(false);
+/// Последний скачанный и проверенный установщик. UI запускает его по команде,
+/// не передавая путь.
+static INSTALLER: Lazy<Mutex<Option<Installer>>> = Lazy::new(|| Mutex::new(None));
+
+/// Установщик вместе с тем, что было проверено при скачивании: перед запуском
+/// файл проверяется заново.
+#[derive(Debug, Clone)]
+struct Installer {
+  path: PathBuf,
+  sha256: String,
+  signature: String
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct GithubReleaseAsset {
+  name: String,
+  browser_download_url: String,
+  #[serde(default)]
+  size: u64,
+  /// `sha256:<hex>`, если GitHub посчитал хеш файла.
+  #[serde(default)]
+  digest: Option<String>
+}
+
+#[derive(Deserialize)]
+struct GithubRelease {
+  tag_name: String,
+  html_url: String,
+  #[serde(default)]
+  body: Option<String>,
+  assets: Vec<GithubReleaseAsset>
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AppUpdateInfo {
+  pub current_version: String,
+  pub latest_version: Option<String>,
+  pub has_update: bool,
+  pub download_url: Option<String>,
+  pub release_url: Option<String>,
+  /// Описание релиза в markdown, как оно написано на GitHub.
+  pub release_notes: Option<String>,
+  /// Скачанный установщик; появляется в событии `update_available`.
+  pub installer_path: Option<String>
+}
+
+#[derive(Debug, Clone)]
+pub struct UpdateCheck {
+  pub info: AppUpdateInfo,
+  asset: Option<GithubReleaseAsset>,
+  /// `<установщик>.sig` из того же релиза.
+  signature: Option<GithubReleaseAsset>
+}
+
+fn parse_github_repo_slug(url: &str) -> Option<String> {
+  let normalized = url.trim().trim_end_matches('/').trim_end_matches(".git");
+  let path = normalized
+    .strip_prefix("https://github.com/")
+    .or_else(|| normalized.strip_prefix("http://github.com/"))
+    .or_else(|| normalized.strip_prefix("git@github.com:"))?;
+  let mut parts = path.split('/').filter(|s| !s.is_empty());
+  let owner = parts.next()?;
+  let repo = parts.next()?;
+  Some(format!("{owner}/{repo}"))
+}
+
+fn parse_semver_triplet(version: &str) -> Option<(u64, u64, u64)> {
+  let core = version
+    .trim()
+    .trim_start_matches(['v', 'V'])
+    .split('+')
+    .next()?
+    .split('-')
+    .next()?;
+  let mut parts = core.split('.');
+  let major = parts.next()?.parse::<u64>().ok()?;
+  let minor = parts.next().unwrap_or("0").parse::<u64>().ok()?;
+  let patch = parts.next().unwrap_or("0").parse::<u64>().ok()?;
+  Some((major, minor, patch))
+}
+
+fn is_newer_version(candidate: &str, current: &str) -> bool {
+  match (parse_semver_triplet(candidate), parse_semver_triplet(current)) {
+    (Some(c), Some(cur)) => c > cur,
+    (Some(_), None) => true,
+    _ => false
+  }
+}
+
+fn signature_asset<'a>(assets: &'a [GithubReleaseAsset], asset: &GithubReleaseAsset) -> Option<&'a GithubReleaseAsset> {
+  let name = format!("{}.sig", asset.name);
+  assets.iter().find(|a| a.name == name)
+}
+
+fn preferred_asset(assets: &[GithubReleaseAsset]) -> Option<&GithubReleaseAsset> {
+  #[cfg(target_os = "windows")]
+  const PREFERRED_SUFFIXES: &[&str] = &[".msi", ".exe", ".zip"];
+  #[cfg(target_os = "macos")]
+  const PREFERRED_SUFFIXES: &[&str] = &[".dmg", ".pkg", ".zip"];
+  #[cfg(all(not(target_os = "windows"), not(target_os = "macos")))]
+  const PREFERRED_SUFFIXES: &[&str] = &[".AppImage", ".deb", ".rpm", ".tar.gz"];
+
+  PREFERRED_SUFFIXES
+    .iter()
+    .find_map(|suffix| assets.iter().find(|a| a.name.ends_with(suffix)))
+    .or_else(|| assets.iter().find(|a| !a.name.ends_with(".sig")))
+}
+
+fn release_notes(body: Option<String>) -> Option<String> {
+  let body = body?.replace("\r\n", "\n");
+  let body = body.trim();
+  if body.is_empty() {
+    return None;
+  }
+  Some(body.chars().take(MAX_NOTES_CHARS).collect())
+}
+
+/// Имя файла установщика без каталогов: имя приходит из ответа сервера.
+fn safe_asset_name(name: &str) -> Option<String> {
+  let name = Path::new(name.trim()).file_name()?.to_str()?;
+  if name.starts_with('.') {
+    return None;
+  }
+  Some(name.to_string())
+}
+
+/// Запрашивает последний релиз на GitHub и сравнивает его версию с текущей.
+/// Блокирующий вызов.
+pub fn check() -> anyhow::Result<UpdateCheck> {
+  let current_version = env!("CARGO_PKG_VERSION").to_string();
+  let repo_slug = parse_github_repo_slug(env!("CARGO_PKG_REPOSITORY"))
+    .ok_or_else(|| anyhow::anyhow!("Не удалось определить репозиторий приложения"))?;
+  let api_url = format!("https://api.github.com/repos/{repo_slug}/releases/latest");
+
+  let response = github::api_get(&github::agent(Duration::from_secs(20)), &api_url)
+    .call()
+    .map_err(|e| anyhow::anyhow!("Не удалось проверить обновления: {e}"))?;
+  let body = response
+    .into_body()
+    .read_to_string()
+    .map_err(|e| anyhow::anyhow!("Не удалось прочитать ответ сервера обновлений: {e}"))?;
+  let release: GithubRelease = serde_json::from_str(&body)
+    .map_err(|e| anyhow::anyhow!("Некорректный ответ сервера обновлений: {e}"))?;
+
+  let latest_version = release.tag_name.trim().to_string();
+  let has_update = is_newer_version(&latest_version, &current_version);
+  let release_url = if release.html_url.trim().is_empty() {
+    None
+  } else {
+    Some(release.html_url)
+  };
+  let asset = preferred_asset(&release.assets).cloned();
+  let signature = asset.as_ref().and_then(|a| signature_asset(&release.assets, a)).cloned();
+  let download_url = asset
+    .as_ref()
+    .map(|a| a.browser_download_url.clone())
+    .or_else(|| release_url.clone());
+  tracing::info!(
+    event = "app_update_checked",
+    current = current_version.as_str(),
+    latest = latest_version.as_str(),
+    has_update = has_update,
+    "Проверка обновлений выполнена"
+  );
+
+  Ok(UpdateCheck {
+    info: AppUpdateInfo {
+      current_version,
+      latest_version: Some(latest_version),
+      has_update,
+      download_url,
+      release_url,
+      release_notes: release_notes(release.body),
+      installer_path: None
+    },
+    asset,
+    signature
+  })
+}
+
+fn check_download_url(url: &str) -> anyhow::Result<()> {
+  match github::https_host(url) {
+    Some(host) if github::is_trusted_host(&host) => Ok(()),
+    _ => Err(anyhow::anyhow!("Установщик отдается не с GitHub: {url}"))
+  }
+}
+
+fn expected_sha256(asset: &GithubReleaseAsset) -> Option<String> {
+  asset
+    .digest
+    .as_deref()
+    .and_then(|d| d.trim().strip_prefix("sha256:"))
+    .map(str::to_ascii_lowercase)
+    .filter(|d| d.len() == 64 && d.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Скачивает подпись установщика (`.sig` в формате Tauri: base64 от файла
+/// подписи minisign).
+fn download_signature(asset: &GithubReleaseAsset) -> anyhow::Result<String> {
+  let url = asset.browser_download_url.as_str();
+  check_download_url(url)?;
+  let response = github::get(&github::agent(Duration::from_secs(20)), url)
+    .call()
+    .map_err(|e| anyhow::anyhow!("Не удалось скачать подпись обновления: {e}"))?;
+  let mut text = String::new();
+  response
+    .into_body()
+    .into_reader()
+    .take(MAX_SIGNATURE_BYTES)
+    .read_to_string(&mut text)?;
+  Ok(text.trim().to_string())
+}
+
+/// Проверяет подпись minisign файла ключом, встроенным при сборке.
+fn verify_signature(path: &Path, signature: &str) -> anyhow::Result<()> {
+  let key = UPDATER_PUBKEY
+    .filter(|k| !k.trim().is_empty())
+    .ok_or_else(|| anyhow::anyhow!("Сборка без ключа проверки обновлений"))?;
+  let key = String::from_utf8(BASE64.decode(key.trim())?)?;
+  let key = PublicKey::decode(&key).map_err(|e| anyhow::anyhow!("Некорректный ключ проверки обновлений: {e}"))?;
+  let signature = String::from_utf8(BASE64.decode(signature)?)?;
+  let signature = Signature::decode(&signature).map_err(|e| anyhow::anyhow!("Некорректная подпись обновления: {e}"))?;
+  let data = std::fs::read(path)?;
+  key
+    .verify(&data, &signature, false)
+    .map_err(|e| anyhow::anyhow!("Подпись обновления не прошла проверку: {e}"))
+}
+
+/// Скачивает установщик в каталог кеша приложения и проверяет размер,
+/// sha256 и подпись. Без хеша или подписи в релизе установка отклоняется.
+/// Уже скачанный целый файл используется повторно.
+fn download(asset: &GithubReleaseAsset, signature: Option<&GithubReleaseAsset>, dir: &Path) -> anyhow::Result<Installer> {
+  let url = asset.browser_download_url.as_str();
+  check_download_url(url)?;
+  let name = safe_asset_name(&asset.name).ok_or_else(|| anyhow::anyhow!("Некорректное имя установщика"))?;
+  let expected = expected_sha256(asset).ok_or_else(|| anyhow::anyhow!("В релизе нет sha256 установщика"))?;
+  let signature = signature.ok_or_else(|| anyhow::anyhow!("В релизе нет подписи установщика"))?;
+  let signature = download_signature(signature)?;
+
+  std::fs::create_dir_all(dir)?;
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+  }
+  let target = dir.join(&name);
+  if target.is_file() && sha256_file(&target)? == expected {
+    verify_signature(&target, &signature)?;
+    return Ok(Installer { path: target, sha256: expected, signature });
+  }
+
+  let response = github::get(&github::agent(Duration::from_secs(60)), url)
+    .call()
+    .map_err(|e| anyhow::anyhow!("Не удалось скачать обновление: {e}"))?;
+  let mut reader = response.into_body().into_reader();
+  let mut tmp = NamedTempFile::new_in(dir)?;
+  let mut hasher = Sha256::new();
+  let mut buf = [0u8; 64 * 1024];
+  let mut downloaded: u64 = 0;
+  loop {
+    let n = reader.read(&mut buf)?;
+    if n == 0 {
+      break;
+    }
+    tmp.write_all(&buf[..n])?;
+    hasher.update(&buf[..n]);
+    downloaded += n as u64;
+  }
+  tmp.as_file().sync_all()?;
+  if asset.size > 0 && downloaded != asset.size {
+    return Err(anyhow::anyhow!("Размер скачанного обновления не совпадает с размером в релизе"));
+  }
+  if hex::encode(hasher.finalize()) != expected {
+    return Err(anyhow::anyhow!("Checksum обновления не совпадает"));
+  }
+  verify_signature(tmp.path(), &signature)?;
+  #[cfg(unix)]
+  if name.ends_with(".AppImage") {
+    use std::os::unix::fs::PermissionsExt;
+    tmp.as_file().set_permissions(std::fs::Permissions::from_mode(0o700))?;
+  }
+  tmp.persist(&target).map_err(|e| e.error)?;
+  Ok(Installer { path: target, sha256: expected, signature })
+}
+
+fn sha256_file(path: &Path) -> anyhow::Result<String> {
+  let mut file = std::fs::File::open(path)?;
+  let mut hasher = Sha256::new();
+  std::io::copy(&mut file, &mut hasher)?;
+  Ok(hex::encode(hasher.finalize()))
+}
+
+/// Если есть новая версия, в фоне скачивает установщик и сообщает UI
+/// событием `update_available`. Событие приходит и без установщика, если
+/// скачать или проверить его не удалось: ссылка на релиз остается.
+pub fn prepare(app: &AppHandle, check: UpdateCheck) {
+  if !check.info.has_update || DOWNLOADING.swap(true, Ordering::SeqCst) {
+    return;
+  }
+  let app = app.clone();
+  tauri::async_runtime::spawn_blocking(move || {
+    let mut info = check.info;
+    if let Some(asset) = check.asset.as_ref() {
+      let result = app
+        .path()
+        .app_cache_dir()
+        .map_err(|e| anyhow::anyhow!("Не удалось определить каталог кеша: {e}"))
+        .and_then(|cache| download(asset, check.signature.as_ref(), &cache.join(UPDATE_DIR)));
+      match result {
+        Ok(installer) => {
+          tracing::info!(event = "app_update_downloaded", path = %installer.path.display(), "Установщик обновления скачан");
+          info.installer_path = Some(installer.path.to_string_lossy().to_string());
+          *INSTALLER.lock() = Some(installer);
+        }
+        Err(e) => {
+          tracing::warn!(event = "app_update_download_failed", error = %e, "Не удалось скачать обновление");
+        }
+      }
+    }
+    let _ = app.emit("update_available", info);
+    DOWNLOADING.store(false, Ordering::SeqCst);
+  });
+}
+
+/// Скачанный установщик, заново проверенный по sha256 и подписи прямо перед
+/// запуском: между скачиванием и установкой файл мог быть подменен.
+pub fn verified_installer() -> anyhow::Result<PathBuf> {
+  let installer = INSTALLER
+    .lock()
+    .clone()
+    .ok_or_else(|| anyhow::anyhow!("Установщик обновления еще не скачан"))?;
+  if !installer.path.is_file() {
+    return Err(anyhow::anyhow!("Установщик обновления еще не скачан"));
+  }
+  if sha256_file(&installer.path)? != installer.sha256 {
+    *INSTALLER.lock() = None;
+    return Err(anyhow::anyhow!("Установщик обновления изменился после скачивания"));
+  }
+  verify_signature(&installer.path, &installer.signature)?;
+  Ok(installer.path)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn is_newer_version_uses_strict_semver_logic() {
+    assert!(is_newer_version("v1.0.7", "1.0.6"));
+    assert!(!is_newer_version("1.0.6", "1.0.6"));
+    assert!(!is_newer_version("latest", "1.0.6"));
+    assert!(!is_newer_version("release-candidate", "1.0.6"));
+  }
+
+  #[test]
+  fn release_fields_are_sanitized() {
+    assert_eq!(parse_github_repo_slug("https://github.com/sumenkov/cloudtg.git").as_deref(), Some("sumenkov/cloudtg"));
+    assert_eq!(safe_asset_name("CloudTG_1.2.0_amd64.AppImage").as_deref(), Some("CloudTG_1.2.0_amd64.AppImage"));
+    assert_eq!(safe_asset_name("../../.bashrc"), None);
+    assert_eq!(safe_asset_name("dir/setup.exe").as_deref(), Some("setup.exe"));
+    assert_eq!(release_notes(Some("  \r\n".into())), None);
+    assert_eq!(release_notes(Some("- fix\r\n- add\n".into())).as_deref(), Some("- fix\n- add"));
+  }
+
+  fn asset(name: &str, digest: Option<&str>) -> GithubReleaseAsset {
+    GithubReleaseAsset {
+      name: name.into(),
+      browser_download_url: format!("https://github.com/sumenkov/cloudtg/releases/download/v1/{name}"),
+      size: 0,
+      digest: digest.map(str::to_string)
+    }
+  }
+
+  #[test]
+  fn release_without_digest_or_signature_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let hash = format!("sha256:{}", "a".repeat(64));
+    let installer = asset("CloudTG.AppImage", Some(&hash));
+    let err = download(&installer, None, dir.path()).unwrap_err();
+    assert!(err.to_string().contains("подписи"));
+    let sig = asset("CloudTG.AppImage.sig", None);
+    let err = download(&asset("CloudTG.AppImage", None), Some(&sig), dir.path()).unwrap_err();
+    assert!(err.to_string().contains("sha256"));
+    assert_eq!(expected_sha256(&asset("x", Some("sha256:zz"))), None);
+  }
+
+  #[test]
+  fn signature_asset_is_matched_by_installer_name() {
+    let assets = vec![asset("CloudTG.AppImage.sig", None), asset("CloudTG.AppImage", None)];
+    let installer = preferred_asset(&assets).unwrap();
+    assert_eq!(installer.name, "CloudTG.AppImage");
+    assert_eq!(signature_asset(&assets, installer).unwrap().name, "CloudTG.AppImage.sig");
+  }
+}
@@ -5,11 +5,17 @@ import { renderToStaticMarkup } from "react-dom/server";
 import type { FileItem } from "../store/app";
 import { FileList } from "../components/file-manager/FileList";
 import {
+  canStreamFile,
   displayFileSizeBytes,
+  dragFileIds,
+  handleActivateAction,
   handleDownloadAction,
   handleOpenAction,
   handleOpenFolderAction,
-  shouldShowOpenFolderButton
+  handleUploadAction,
+  remoteStatusBadge,
+  shouldShowOpenFolderButton,
+  snippetParts
 } from "../components/file-manager/fileActions";
 
 function makeFile(overrides: Partial<FileItem> = {}): FileItem {
@@ -44,6 +50,36 @@ describe("fileActions", () => {
     expect(shouldShowOpenFolderButton(makeFile({ is_downloaded: false }))).toBe(false);
   });
 
+  it("canStreamFile offers streaming only for media that is not downloaded", () => {
+    expect(canStreamFile(makeFile({ name: "Trip.MP4" }))).toBe(true);
+    expect(canStreamFile(makeFile({ name: "song.flac" }))).toBe(true);
+    expect(canStreamFile(makeFile({ name: "Trip.mp4", is_downloaded: true }))).toBe(false);
+    expect(canStreamFile(makeFile({ name: "report.txt" }))).toBe(false);
+    expect(canStreamFile(makeFile({ name: "mp4" }))).toBe(false);
+  });
+
+  it("dragFileIds drags the whole selection only when the row is in it", () => {
+    const selected = new Set(["a", "b", "c"]);
+    expect(dragFileIds("b", selected)).toEqual(["b", "a", "c"]);
+    expect(dragFileIds("x", selected)).toEqual(["x"]);
+    expect(dragFileIds("a", new Set<string>())).toEqual(["a"]);
+  });
+
+  it("snippetParts splits highlighted matches", () => {
+    expect(snippetParts("Годовой [отчет].pdf")).toEqual([
+      { text: "Годовой ", hit: false },
+      { text: "отчет", hit: true },
+      { text: ".pdf", hit: false }
+    ]);
+    expect(snippetParts("без совпадений")).toEqual([{ text: "без совпадений", hit: false }]);
+  });
+
+  it("remoteStatusBadge labels only non-trivial remote states", () => {
+    expect(remoteStatusBadge(makeFile())).toBeNull();
+    expect(remoteStatusBadge(makeFile({ remote_status: "broken" }))).toBeNull();
+    expect(remoteStatusBadge(makeFile({ remote_status: "pending_upload" }))).toBe("не отправлен");
+  });
+
   it("download action downloads without overwrite for new file", async () => {
     const downloadFile = vi.fn(async () => "/tmp/report.txt");
     const reloadFiles = vi.fn(async () => {});
@@ -109,6 +145,65 @@ describe("fileActions", () => {
     expect(reloadFiles).toHaveBeenCalledTimes(1);
   });
 
+  it("open action retries with token after confirmation", async () => {
+    const openFile = vi
+      .fn(async (_fileId: string, _token?: string) => {})
+      .mockRejectedValueOnce("NEEDS_CONFIRMATION:tok123: Файл setup.exe может запустить программу.");
+    const confirm = vi.fn(() => true);
+    const reloadFiles = vi.fn(async () => {});
+
+    await handleOpenAction({
+      file: makeFile({ id: "exe-id" }),
+      confirm,
+      openFile,
+      reloadFiles
+    });
+
+    expect(confirm).toHaveBeenCalledWith("Файл setup.exe может запустить программу.");
+    expect(openFile).toHaveBeenLastCalledWith("exe-id", "tok123");
+    expect(reloadFiles).toHaveBeenCalledTimes(1);
+  });
+
+  it("activate action follows the backend choice", async () => {
+    const openFile = vi.fn(async () => {});
+    const openFileFolder = vi.fn(async () => {});
+    const reloadFiles = vi.fn(async () => {});
+
+    await handleActivateAction({
+      file: makeFile({ id: "done-id" }),
+      activateFile: vi.fn(async () => "open_folder" as const),
+      openFile,
+      openFileFolder,
+      reloadFiles
+    });
+    expect(openFile).not.toHaveBeenCalled();
+    expect(openFileFolder).not.toHaveBeenCalled();
+    expect(reloadFiles).toHaveBeenCalledTimes(1);
+
+    await handleActivateAction({
+      file: makeFile({ id: "ask-id" }),
+      confirm: vi.fn(() => false),
+      activateFile: vi.fn(async () => "ask" as const),
+      openFile,
+      openFileFolder,
+      reloadFiles
+    });
+    expect(openFileFolder).toHaveBeenCalledWith("ask-id");
+    expect(openFile).not.toHaveBeenCalled();
+  });
+
+  it("upload action retries a duplicate with the user's choice", async () => {
+    const uploadFile = vi
+      .fn(async (_dirId: string, _token: string, _dedup?: string) => {})
+      .mockRejectedValueOnce("DUPLICATE:tok2: Файл с таким же содержимым уже есть: a.iso.");
+    const confirm = vi.fn(() => true);
+
+    await handleUploadAction({ dirId: "dir-1", uploadToken: "tok1", confirm, uploadFile });
+
+    expect(confirm).toHaveBeenCalledWith("Файл с таким же содержимым уже есть: a.iso.");
+    expect(uploadFile).toHaveBeenLastCalledWith("dir-1", "tok2", "reference");
+  });
+
   it("open folder action only opens folder", async () => {
     const openFileFolder = vi.fn(async () => {});
 
@@ -135,6 +230,9 @@ describe("FileList", () => {
         onDownload={() => {}}
         onOpen={() => {}}
         onOpenFolder={() => {}}
+        onStream={() => {}}
+        onCopyToClipboard={() => {}}
+        onDragOut={() => {}}
         onShare={() => {}}
         onRepair={() => {}}
         onDelete={() => {}}
@@ -145,6 +243,7 @@ describe("FileList", () => {
     expect(html).toContain("local.bin");
     const openFolderCount = (html.match(/Открыть папку/g) ?? []).length;
     expect(openFolderCount).toBe(1);
+    expect((html.match(/draggable="true"/g) ?? []).length).toBe(2);
   });
 
   it("shows real local size after download and 0 B before download", () => {
@@ -160,6 +259,9 @@ describe("FileList", () => {
         onDownload={() => {}}
         onOpen={() => {}}
         onOpenFolder={() => {}}
+        onStream={() => {}}
+        onCopyToClipboard={() => {}}
+        onDragOut={() => {}}
         onShare={() => {}}
         onRepair={() => {}}
         onDelete={() => {}}
@@ -1,8 +1,11 @@
 import { describe, expect, it, vi } from "vitest";
 import type { DirNode } from "../store/app";
 import {
+  createDirChangedHandler,
   createDragDropHandler,
+  createFileChangedHandler,
   createTreeUpdatedHandler,
+  createUploadProgressHandler,
   normalizeUploadPaths
 } from "../components/fileManagerListeners";
 
@@ -29,6 +32,42 @@ async function flushMicrotasks(times = 3) {
 }
 
 describe("fileManagerListeners", () => {
+  it("file_changed handler reloads only for the selected folder or unknown folder", async () => {
+    const reloadFiles = vi.fn(async () => {});
+    const handler = createFileChangedHandler(
+      { current: folderNode as DirNode | null },
+      { current: false },
+      { current: reloadFiles }
+    );
+
+    await handler({ payload: { id: "f1", change: "created", dir_id: "dir-b" } });
+    expect(reloadFiles).not.toHaveBeenCalled();
+
+    await handler({ payload: { id: "f1", change: "created", dir_id: "dir-a" } });
+    await handler({ payload: { id: "f1", change: "deleted", dir_id: null } });
+    expect(reloadFiles).toHaveBeenCalledTimes(2);
+
+    await handler({ payload: { id: "f2", change: "moved", dir_id: "dir-b", from_dir_id: "dir-a" } });
+    await handler({ payload: { id: "f3", change: "moved", dir_id: "dir-b", from_dir_id: "dir-c" } });
+    expect(reloadFiles).toHaveBeenCalledTimes(3);
+  });
+
+  it("dir_changed handler reloads only when the open folder's contents changed", async () => {
+    const reloadFiles = vi.fn(async () => {});
+    const handler = createDirChangedHandler(
+      { current: folderNode as DirNode | null },
+      { current: false },
+      { current: reloadFiles }
+    );
+
+    await handler({ payload: { id: "dir-b", change: "updated", parent_id: null } });
+    await handler({ payload: { id: "dir-a", change: "moved", parent_id: "dir-b" } });
+    expect(reloadFiles).not.toHaveBeenCalled();
+
+    await handler({ payload: { id: "dir-a", change: "updated", parent_id: null } });
+    expect(reloadFiles).toHaveBeenCalledTimes(1);
+  });
+
   it("tree_updated handler reads latest refs at call time", async () => {
     const reloadOld = vi.fn(async () => {});
     const reloadNew = vi.fn(async () => {});
@@ -64,15 +103,12 @@ describe("fileManagerListeners", () => {
     expect(reloadFiles).not.toHaveBeenCalled();
   });
 
-  it("drop handler uploads dropped files into selected folder", async () => {
-    const prepareUploadPaths = vi.fn(async () => ["tok-1", "tok-2"]);
-    const uploadFile = vi.fn(async () => {});
-    const reloadOld = vi.fn(async () => {});
-    const reloadNew = vi.fn(async () => {});
+  it("drop handler starts a backend upload into the selected folder", async () => {
+    const dropUpload = vi.fn(async () => ({ batch_id: "batch-1", total: 2 }));
     const selectedNodeRef = { current: rootNode as DirNode | null };
     const isRootSelectedRef = { current: true };
-    const reloadFilesRef = { current: reloadOld };
     const uploadInProgressRef = { current: false };
+    const activeBatchRef = { current: null as string | null };
     const setDropActive = vi.fn();
     const setUploadBusy = vi.fn();
     const setError = vi.fn();
@@ -80,10 +116,9 @@ describe("fileManagerListeners", () => {
     const handler = createDragDropHandler(
       selectedNodeRef,
       isRootSelectedRef,
-      reloadFilesRef,
       uploadInProgressRef,
-      prepareUploadPaths,
-      uploadFile,
+      activeBatchRef,
+      dropUpload,
       setDropActive,
       setUploadBusy,
       setError
@@ -91,71 +126,76 @@ describe("fileManagerListeners", () => {
 
     selectedNodeRef.current = folderNode;
     isRootSelectedRef.current = false;
-    reloadFilesRef.current = reloadNew;
 
-    handler({ payload: { type: "drop", paths: ["/tmp/one.txt", "/tmp/two.txt"] } });
+    handler({ payload: { type: "drop", paths: ["/tmp/one.txt", "/tmp/two.txt", "/tmp/one.txt"] } });
     await flushMicrotasks();
 
-    expect(prepareUploadPaths).toHaveBeenCalledWith(["/tmp/one.txt", "/tmp/two.txt"]);
-    expect(uploadFile).toHaveBeenCalledTimes(2);
-    expect(uploadFile).toHaveBeenNthCalledWith(1, "dir-a", "tok-1");
-    expect(uploadFile).toHaveBeenNthCalledWith(2, "dir-a", "tok-2");
-    expect(reloadOld).not.toHaveBeenCalled();
-    expect(reloadNew).toHaveBeenCalledTimes(1);
-    expect(setUploadBusy).toHaveBeenNthCalledWith(1, true);
-    expect(setUploadBusy).toHaveBeenNthCalledWith(2, false);
+    expect(dropUpload).toHaveBeenCalledWith("dir-a", ["/tmp/one.txt", "/tmp/two.txt"]);
+    expect(activeBatchRef.current).toBe("batch-1");
+    expect(uploadInProgressRef.current).toBe(true);
+    expect(setUploadBusy).toHaveBeenCalledTimes(1);
+    expect(setUploadBusy).toHaveBeenCalledWith(true);
     expect(setError).not.toHaveBeenCalled();
   });
 
-  it("drop handler reports error when no folder is selected", async () => {
-    const prepareUploadPaths = vi.fn(async () => ["tok-1"]);
-    const uploadFile = vi.fn(async () => {});
-    const reloadFilesRef = { current: vi.fn(async () => {}) };
+  it("drop handler releases the busy state when nothing was uploaded", async () => {
+    const dropUpload = vi.fn(async () => ({ batch_id: "", total: 0 }));
     const uploadInProgressRef = { current: false };
-    const setDropActive = vi.fn();
     const setUploadBusy = vi.fn();
-    const setError = vi.fn();
-    const selectedNodeRef = { current: rootNode as DirNode | null };
-    const isRootSelectedRef = { current: true };
 
     const handler = createDragDropHandler(
-      selectedNodeRef,
-      isRootSelectedRef,
-      reloadFilesRef,
+      { current: folderNode as DirNode | null },
+      { current: false },
       uploadInProgressRef,
-      prepareUploadPaths,
-      uploadFile,
-      setDropActive,
+      { current: null as string | null },
+      dropUpload,
+      vi.fn(),
       setUploadBusy,
+      vi.fn()
+    );
+
+    handler({ payload: { type: "drop", paths: ["/tmp/folder"] } });
+    await flushMicrotasks();
+
+    expect(uploadInProgressRef.current).toBe(false);
+    expect(setUploadBusy).toHaveBeenNthCalledWith(1, true);
+    expect(setUploadBusy).toHaveBeenNthCalledWith(2, false);
+  });
+
+  it("drop handler reports error when no folder is selected", async () => {
+    const dropUpload = vi.fn(async () => ({ batch_id: "batch-1", total: 1 }));
+    const setError = vi.fn();
+
+    const handler = createDragDropHandler(
+      { current: rootNode as DirNode | null },
+      { current: true },
+      { current: false },
+      { current: null as string | null },
+      dropUpload,
+      vi.fn(),
+      vi.fn(),
       setError
     );
 
     handler({ payload: { type: "drop", paths: ["/tmp/file.txt"] } });
     await flushMicrotasks();
 
-    expect(prepareUploadPaths).not.toHaveBeenCalled();
-    expect(uploadFile).not.toHaveBeenCalled();
+    expect(dropUpload).not.toHaveBeenCalled();
     expect(setError).toHaveBeenCalledWith("Выбери папку, чтобы загрузить файлы.");
   });
 
   it("drop handler handles over/leave events without starting upload", async () => {
-    const prepareUploadPaths = vi.fn(async () => ["tok-1"]);
-    const uploadFile = vi.fn(async () => {});
-    const reloadFilesRef = { current: vi.fn(async () => {}) };
-    const uploadInProgressRef = { current: false };
+    const dropUpload = vi.fn(async () => ({ batch_id: "batch-1", total: 1 }));
     const setDropActive = vi.fn();
     const setUploadBusy = vi.fn();
     const setError = vi.fn();
-    const selectedNodeRef = { current: folderNode as DirNode | null };
-    const isRootSelectedRef = { current: false };
 
     const handler = createDragDropHandler(
-      selectedNodeRef,
-      isRootSelectedRef,
-      reloadFilesRef,
-      uploadInProgressRef,
-      prepareUploadPaths,
-      uploadFile,
+      { current: folderNode as DirNode | null },
+      { current: false },
+      { current: false },
+      { current: null as string | null },
+      dropUpload,
       setDropActive,
       setUploadBusy,
       setError
@@ -168,12 +208,45 @@ describe("fileManagerListeners", () => {
 
     expect(setDropActive).toHaveBeenNthCalledWith(1, true);
     expect(setDropActive).toHaveBeenNthCalledWith(2, false);
-    expect(prepareUploadPaths).not.toHaveBeenCalled();
-    expect(uploadFile).not.toHaveBeenCalled();
+    expect(dropUpload).not.toHaveBeenCalled();
     expect(setUploadBusy).not.toHaveBeenCalled();
     expect(setError).not.toHaveBeenCalled();
   });
 
+  it("upload_progress handler tracks the active batch and reloads when it finishes", async () => {
+    const reloadFiles = vi.fn(async () => {});
+    const activeBatchRef = { current: "batch-1" as string | null };
+    const uploadInProgressRef = { current: true };
+    const setUploadBusy = vi.fn();
+    const setUploadCounter = vi.fn();
+    const setError = vi.fn();
+    const handler = createUploadProgressHandler(
+      activeBatchRef,
+      uploadInProgressRef,
+      { current: reloadFiles },
+      setUploadBusy,
+      setUploadCounter,
+      setError
+    );
+    const base = { batch_id: "batch-1", dir_id: "dir-a", total: 2, name: null, file_id: null, error: null, finished: false };
+
+    await handler({ payload: { ...base, batch_id: "other", done: 1 } });
+    expect(setUploadCounter).not.toHaveBeenCalled();
+
+    await handler({ payload: { ...base, done: 1, name: "one.txt", file_id: "f1" } });
+    await handler({ payload: { ...base, done: 2, name: "two.txt", error: "нет сети" } });
+    expect(setUploadCounter).toHaveBeenLastCalledWith({ done: 2, total: 2 });
+    expect(setError).toHaveBeenCalledWith("two.txt: нет сети");
+    expect(reloadFiles).not.toHaveBeenCalled();
+
+    await handler({ payload: { ...base, done: 2, finished: true } });
+    expect(activeBatchRef.current).toBeNull();
+    expect(uploadInProgressRef.current).toBe(false);
+    expect(setUploadCounter).toHaveBeenLastCalledWith(null);
+    expect(setUploadBusy).toHaveBeenCalledWith(false);
+    expect(reloadFiles).toHaveBeenCalledTimes(1);
+  });
+
   it("normalizeUploadPaths trims and de-duplicates values", () => {
     const result = normalizeUploadPaths([" /a ", "/a", "", "  ", "/b"]);
     expect(result).toEqual(["/a", "/b"]);
@@ -0,0 +1,33 @@
+import { describe, expect, it } from "vitest";
+import { removeDirNode, upsertDirNode, type DirNode } from "../store/app";
+
+function dir(id: string, name: string, parentId: string | null, children: DirNode[] = []): DirNode {
+  return { id, name, parent_id: parentId, is_broken: false, children };
+}
+
+const tree = dir("ROOT", "ROOT", null, [
+  dir("a", "Альфа", null, [dir("a1", "Вложенная", "a")]),
+  dir("c", "Гамма", null)
+]);
+
+describe("tree patch", () => {
+  it("inserts a created folder under its parent in name order", () => {
+    const next = upsertDirNode(tree, dir("b", "Бета", null));
+    expect(next?.children.map((n) => n.id)).toEqual(["a", "b", "c"]);
+    expect(upsertDirNode(tree, dir("x", "Икс", "missing"))).toBeNull();
+  });
+
+  it("moves and renames a folder without losing its children", () => {
+    const moved = upsertDirNode(tree, dir("a", "Альфа", "c"));
+    expect(moved?.children.map((n) => n.id)).toEqual(["c"]);
+    expect(moved?.children[0].children[0].children.map((n) => n.id)).toEqual(["a1"]);
+
+    const renamed = upsertDirNode(tree, dir("a", "Омега", null));
+    expect(renamed?.children.map((n) => n.name)).toEqual(["Гамма", "Омега"]);
+  });
+
+  it("removes a deleted folder with its subtree", () => {
+    expect(removeDirNode(tree, "a").children.map((n) => n.id)).toEqual(["c"]);
+    expect(removeDirNode(tree, "missing")).toBe(tree);
+  });
+});
@@ -1,14 +1,31 @@
 import React, { useCallback, useEffect, useMemo, useRef, useState } from "react";
-import { useAppStore, DirNode, ChatItem, FileItem } from "../store/app";
+import {
+  useAppStore,
+  DirNode,
+  ChatFolder,
+  ChatItem,
+  DeepLinkNavigation,
+  FileDownloadProgress,
+  FileItem,
+  FileSearchSort,
+  UploadProgress
+} from "../store/app";
 import { listenSafe } from "../tauri";
 import { getCurrentWindow } from "@tauri-apps/api/window";
-import { createDragDropHandler, createTreeUpdatedHandler } from "./fileManagerListeners";
+import { startDrag } from "@crabnebula/tauri-plugin-drag";
+import {
+  createDirChangedHandler,
+  createDragDropHandler,
+  createFileChangedHandler,
+  createTreeUpdatedHandler,
+  createUploadProgressHandler
+} from "./fileManagerListeners";
 import { TreePanel } from "./file-manager/TreePanel";
-import { SearchPanel } from "./file-manager/SearchPanel";
+import { SearchPanel, SearchStateFilter } from "./file-manager/SearchPanel";
 import { SharePanel } from "./file-manager/SharePanel";
 import { FileList } from "./file-manager/FileList";
 import { Hint } from "./common/Hint";
-import { handleDownloadAction, handleOpenAction, handleOpenFolderAction } from "./file-manager/fileActions";
+import { dragFileIds, handleActivateAction, handleDownloadAction, handleOpenFolderAction, handleUploadAction } from "./file-manager/fileActions";
 
 function containsNode(root: DirNode, id: string): boolean {
   if (root.id === id) return true;
@@ -59,20 +76,31 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
     refreshFiles,
     searchFiles,
     pickUploadFiles,
-    prepareUploadPaths,
     uploadFile,
+    dropUpload,
     moveFiles,
     deleteFiles,
     repairFile,
     downloadFile,
     openFile,
     openFileFolder,
+    streamFile,
+    copyFileToClipboard,
+    prepareDrag,
+    activateFile,
     searchChats,
     shareFileToChat,
     getRecentChats,
+    getChatFolders,
+    getFolderChats,
+    takePendingDeepLink,
+    openFolderWindow,
     setError
   } = useAppStore();
-  const [parentId, setParentId] = useState<string | null>(tree?.id ?? "ROOT");
+  // Отдельное окно папки открывается с ?dir=<id>.
+  const [parentId, setParentId] = useState<string | null>(
+    () => new URLSearchParams(window.location.search).get("dir") ?? tree?.id ?? "ROOT"
+  );
   const [name, setName] = useState("");
   const [collapsed, setCollapsed] = useState<Set<string>>(() => new Set());
   const [renameValue, setRenameValue] = useState("");
@@ -83,20 +111,27 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
   const [shareFile, setShareFile] = useState<FileItem | null>(null);
   const [shareQuery, setShareQuery] = useState("");
   const [shareResults, setShareResults] = useState<ChatItem[]>([]);
+  const [shareFolders, setShareFolders] = useState<ChatFolder[]>([]);
   const [shareBusy, setShareBusy] = useState(false);
   const [shareStatus, setShareStatus] = useState<string | null>(null);
   const [searchName, setSearchName] = useState("");
   const [searchType, setSearchType] = useState("");
   const [searchAll, setSearchAll] = useState(false);
+  const [searchRecursive, setSearchRecursive] = useState(false);
+  const [searchState, setSearchState] = useState<SearchStateFilter>("all");
+  const [searchSort, setSearchSort] = useState<FileSearchSort>("name_asc");
   const [searchActive, setSearchActive] = useState(false);
   const [searchBusy, setSearchBusy] = useState(false);
   const [uploadBusy, setUploadBusy] = useState(false);
+  const [uploadCounter, setUploadCounter] = useState<{ done: number; total: number } | null>(null);
   const [downloadingFiles, setDownloadingFiles] = useState<Record<string, string>>({});
+  const [downloadProgress, setDownloadProgress] = useState<Record<string, FileDownloadProgress>>({});
   const [activeTab, setActiveTab] = useState<MainTab>("files");
   const selectedNodeRef = useRef<DirNode | null>(null);
   const isRootSelectedRef = useRef<boolean>(false);
   const reloadFilesRef = useRef<() => Promise<void>>(async () => {});
   const uploadInProgressRef = useRef<boolean>(false);
+  const activeBatchRef = useRef<string | null>(null);
   const prevSelectedNodeIdRef = useRef<string | null>(null);
 
   useEffect(() => {
@@ -123,7 +158,7 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
     if (!selectedNode) return;
     const name = searchName.trim();
     const fileType = searchType.trim();
-    if (!name && !fileType) {
+    if (!name && !fileType && searchState === "all") {
       setSearchActive(false);
       await refreshFiles(selectedNode.id);
       setSelectedFiles(new Set());
@@ -133,8 +168,12 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
       setSearchBusy(true);
       await searchFiles({
         dirId: searchAll ? null : selectedNode.id,
-        name: name || undefined,
-        fileType: fileType || undefined
+        recursive: !searchAll && searchRecursive,
+        text: name || undefined,
+        fileType: fileType || undefined,
+        isDownloaded: searchState === "downloaded" ? true : searchState === "not_downloaded" ? false : undefined,
+        isBroken: searchState === "broken" ? true : undefined,
+        sort: searchSort
       });
       setSearchActive(true);
       setSelectedFiles(new Set());
@@ -143,7 +182,7 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
     } finally {
       setSearchBusy(false);
     }
-  }, [selectedNode, searchName, searchType, refreshFiles, searchFiles, searchAll, setError]);
+  }, [selectedNode, searchName, searchType, refreshFiles, searchFiles, searchAll, searchRecursive, searchState, searchSort, setError]);
 
   const reloadFiles = useCallback(async () => {
     if (!selectedNode) return;
@@ -159,6 +198,8 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
     setSearchName("");
     setSearchType("");
     setSearchAll(false);
+    setSearchRecursive(false);
+    setSearchState("all");
     setSearchActive(false);
     setSelectedFiles(new Set());
     try {
@@ -180,6 +221,8 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
       setSearchName("");
       setSearchType("");
       setSearchAll(false);
+      setSearchRecursive(false);
+      setSearchState("all");
       setSearchActive(false);
       return;
     }
@@ -193,6 +236,8 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
     setSearchName("");
     setSearchType("");
     setSearchAll(false);
+    setSearchRecursive(false);
+    setSearchState("all");
     setSearchActive(false);
   }, [selectedNode]);
 
@@ -209,8 +254,25 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
       } finally {
         setShareBusy(false);
       }
+      // Папки — необязательное дополнение: без них остаются поиск и недавние чаты.
+      try {
+        setShareFolders(await getChatFolders());
+      } catch {
+        setShareFolders([]);
+      }
     })();
-  }, [shareFile, getRecentChats, setError]);
+  }, [shareFile, getRecentChats, getChatFolders, setError]);
+
+  const loadShareFolder = useCallback(async (folderId: number) => {
+    try {
+      setShareBusy(true);
+      setShareResults(await getFolderChats(folderId));
+    } catch (e: any) {
+      setError(String(e));
+    } finally {
+      setShareBusy(false);
+    }
+  }, [getFolderChats, setError]);
 
   const moveOptions = useMemo(() => {
     if (!tree) return [];
@@ -258,9 +320,26 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
       isRootSelectedRef,
       reloadFilesRef
     );
+    const handleFileChanged = createFileChangedHandler(
+      selectedNodeRef,
+      isRootSelectedRef,
+      reloadFilesRef
+    );
+    const handleDirChanged = createDirChangedHandler(
+      selectedNodeRef,
+      isRootSelectedRef,
+      reloadFilesRef
+    );
     (async () => {
       try {
-        const cleanup = await listenSafe("tree_updated", handleTreeUpdated);
+        const cleanupTree = await listenSafe("tree_updated", handleTreeUpdated);
+        const cleanupFile = await listenSafe("file_changed", handleFileChanged);
+        const cleanupDir = await listenSafe("dir_changed", handleDirChanged);
+        const cleanup = () => {
+          cleanupTree();
+          cleanupFile();
+          cleanupDir();
+        };
         if (disposed) {
           cleanup();
           return;
@@ -276,6 +355,34 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
     };
   }, [setError]);
 
+  useEffect(() => {
+    let unlisten: (() => void) | null = null;
+    let disposed = false;
+    const navigate = (nav: DeepLinkNavigation | null) => {
+      if (!nav || disposed) return;
+      setParentId(nav.dir_id);
+      setActiveTab("files");
+      setSelectedFiles(new Set(nav.file_id ? [nav.file_id] : []));
+    };
+    (async () => {
+      try {
+        const cleanup = await listenSafe<DeepLinkNavigation>("deep_link", (event) => navigate(event.payload));
+        if (disposed) {
+          cleanup();
+          return;
+        }
+        unlisten = cleanup;
+        navigate(await takePendingDeepLink());
+      } catch (e: any) {
+        setError(String(e));
+      }
+    })();
+    return () => {
+      disposed = true;
+      if (unlisten) unlisten();
+    };
+  }, [takePendingDeepLink, setError]);
+
   useEffect(() => {
     let unlisten: (() => void) | null = null;
     let disposed = false;
@@ -283,10 +390,9 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
     const handleDragDropEvent = createDragDropHandler(
       selectedNodeRef,
       isRootSelectedRef,
-      reloadFilesRef,
       uploadInProgressRef,
-      prepareUploadPaths,
-      uploadFile,
+      activeBatchRef,
+      dropUpload,
       setDropActive,
       setUploadBusy,
       (message) => setError(message)
@@ -307,7 +413,37 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
       disposed = true;
       if (unlisten) unlisten();
     };
-  }, [prepareUploadPaths, uploadFile, setError]);
+  }, [dropUpload, setError]);
+
+  useEffect(() => {
+    let unlisten: (() => void) | null = null;
+    let disposed = false;
+    const handleUploadProgress = createUploadProgressHandler(
+      activeBatchRef,
+      uploadInProgressRef,
+      reloadFilesRef,
+      setUploadBusy,
+      setUploadCounter,
+      (message) => setError(message)
+    );
+    listenSafe<UploadProgress>("upload_progress", (event) => {
+      handleUploadProgress(event).catch((e) => setError(String(e)));
+    })
+      .then((u) => {
+        if (disposed) {
+          u();
+          return;
+        }
+        unlisten = u;
+      })
+      .catch(() => {
+        // В браузере событие может быть недоступно.
+      });
+    return () => {
+      disposed = true;
+      if (unlisten) unlisten();
+    };
+  }, [setError]);
 
   const fileMoveOptions = useMemo(() => {
     if (!tree) return [];
@@ -375,16 +511,62 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
         delete next[file.id];
         return next;
       });
+      setDownloadProgress((prev) => {
+        const next = { ...prev };
+        delete next[file.id];
+        return next;
+      });
     }
   };
 
-  const downloadingNames = useMemo(() => Object.values(downloadingFiles), [downloadingFiles]);
+  useEffect(() => {
+    let unlisten: (() => void) | null = null;
+    let disposed = false;
+    listenSafe<FileDownloadProgress>("file_download_progress", (event) => {
+      const progress = event.payload;
+      setDownloadProgress((prev) => {
+        const next = { ...prev };
+        // Готовые записи убираем сразу: события идут и от очереди загрузок.
+        if (progress.total && progress.downloaded >= progress.total) delete next[progress.file_id];
+        else next[progress.file_id] = progress;
+        return next;
+      });
+    })
+      .then((u) => {
+        if (disposed) {
+          u();
+          return;
+        }
+        unlisten = u;
+      })
+      .catch(() => {
+        // Без событий прогресса баннер просто покажет имя файла.
+      });
+    return () => {
+      disposed = true;
+      if (unlisten) unlisten();
+    };
+  }, []);
+
+  const downloadingNames = useMemo(
+    () =>
+      Object.entries(downloadingFiles).map(([fileId, name]) => {
+        const progress = downloadProgress[fileId];
+        if (!progress || !progress.total) return name;
+        const percent = Math.min(100, Math.floor((progress.downloaded / progress.total) * 100));
+        return `${name} (${percent}%)`;
+      }),
+    [downloadingFiles, downloadProgress]
+  );
   const downloadingFileIds = useMemo(() => new Set(Object.keys(downloadingFiles)), [downloadingFiles]);
 
   const onFileOpen = async (file: FileItem) => {
-    await handleOpenAction({
+    await handleActivateAction({
       file,
+      confirm: (message) => window.confirm(message),
+      activateFile,
       openFile,
+      openFileFolder,
       reloadFiles
     });
   };
@@ -449,6 +631,29 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
           setError(String(e));
         }
       }}
+      onStream={async (file) => {
+        try {
+          await streamFile(file.id);
+        } catch (e: any) {
+          setError(String(e));
+        }
+      }}
+      onCopyToClipboard={async (file) => {
+        try {
+          await copyFileToClipboard(file.id);
+        } catch (e: any) {
+          setError(String(e));
+        }
+      }}
+      onDragOut={async (file) => {
+        try {
+          // Нескачанные файлы сначала скачиваются, прогресс виден в списке.
+          const payload = await prepareDrag(dragFileIds(file.id, selectedFiles));
+          await startDrag({ item: payload.paths, icon: payload.icon });
+        } catch (e: any) {
+          setError(String(e));
+        }
+      }}
       onShare={(file) => {
         setShareFile(file);
         setShareStatus(null);
@@ -496,7 +701,19 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
               Выбери вкладку по сценарию: файлы, папки, поиск и сервис.
             </div>
           </div>
-          <div style={{ fontSize: 12, opacity: 0.65 }}>Файлов в текущем списке: {files.length}</div>
+          <div style={{ display: "flex", alignItems: "center", gap: 10 }}>
+            <div style={{ fontSize: 12, opacity: 0.65 }}>Файлов в текущем списке: {files.length}</div>
+            {canUseFiles && selectedNode ? (
+              <button
+                type="button"
+                title="Открыть эту папку в отдельном небольшом окне"
+                onClick={() => openFolderWindow(selectedNode.id).catch((e) => setError(String(e)))}
+                style={{ padding: "4px 10px", borderRadius: 8, border: "1px solid #d8d8d8", background: "#fff" }}
+              >
+                В отдельном окне
+              </button>
+            ) : null}
+          </div>
         </div>
 
         <div style={{ marginTop: 12, display: "flex", gap: 8, flexWrap: "wrap" }}>
@@ -569,7 +786,11 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
                     if (!selectedNode || isRootSelected) return;
                     if (!renameValue.trim()) return;
                     try {
-                      await renameDir(selectedNode.id, renameValue.trim());
+                      const retag = await renameDir(selectedNode.id, renameValue.trim());
+                      if (retag.failed.length > 0) {
+                        const [, reason] = retag.failed[0];
+                        setError(`Папка переименована, но тег не обновился в подписях файлов: ${retag.failed.length}. ${reason}`);
+                      }
                     } catch (e: any) {
                       setError(String(e));
                     }
@@ -680,7 +901,12 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
                         const uploadTokens = await pickUploadFiles();
                         if (uploadTokens.length === 0) return;
                         for (const uploadToken of uploadTokens) {
-                          await uploadFile(selectedNode.id, uploadToken);
+                          await handleUploadAction({
+                            dirId: selectedNode.id,
+                            uploadToken,
+                            confirm: (message) => window.confirm(message),
+                            uploadFile
+                          });
                         }
                         await reloadFiles();
                       } catch (e: any) {
@@ -693,7 +919,11 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
                     disabled={uploadBusy}
                     style={{ padding: 10, borderRadius: 10 }}
                   >
-                    {uploadBusy ? "Загрузка..." : "Выбрать и загрузить"}
+                    {uploadCounter
+                      ? `Загрузка ${uploadCounter.done}/${uploadCounter.total}...`
+                      : uploadBusy
+                        ? "Загрузка..."
+                        : "Выбрать и загрузить"}
                   </button>
                 </div>
 
@@ -821,6 +1051,7 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
                     shareFile={shareFile}
                     shareQuery={shareQuery}
                     shareResults={shareResults}
+                    shareFolders={shareFolders}
                     shareBusy={shareBusy}
                     onShareQueryChange={setShareQuery}
                     onClose={() => {
@@ -852,6 +1083,7 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
                         setShareBusy(false);
                       }
                     }}
+                    onLoadFolder={loadShareFolder}
                     onSend={async (chatId) => {
                       if (!shareFile) return;
                       try {
@@ -886,12 +1118,18 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
                   searchName={searchName}
                   searchType={searchType}
                   searchAll={searchAll}
+                  searchRecursive={searchRecursive}
+                  searchState={searchState}
+                  searchSort={searchSort}
                   searchBusy={searchBusy}
                   searchActive={searchActive}
                   foundCount={files.length}
                   onSearchNameChange={setSearchName}
                   onSearchTypeChange={setSearchType}
                   onSearchAllChange={setSearchAll}
+                  onSearchRecursiveChange={setSearchRecursive}
+                  onSearchStateChange={setSearchState}
+                  onSearchSortChange={setSearchSort}
                   onRunSearch={runSearch}
                   onReset={resetSearch}
                 />
@@ -903,6 +1141,7 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
                         shareFile={shareFile}
                         shareQuery={shareQuery}
                         shareResults={shareResults}
+                        shareFolders={shareFolders}
                         shareBusy={shareBusy}
                         onShareQueryChange={setShareQuery}
                         onClose={() => {
@@ -934,6 +1173,7 @@ export function FileManager({ tree }: { tree: DirNode | null }) {
                             setShareBusy(false);
                           }
                         }}
+                        onLoadFolder={loadShareFolder}
                         onSend={async (chatId) => {
                           if (!shareFile) return;
                           try {
@@ -416,7 +416,7 @@ export function Login() {
 
       {auth === "closed" ? (
         <div style={{ padding: 12, border: "1px solid #f99", borderRadius: 10, background: "#fee" }}>
-          Сессия закрыта. Перезапусти приложение.
+          Сессия закрыта. Перезапускаю TDLib...
         </div>
       ) : null}
     </div>
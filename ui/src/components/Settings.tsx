@@ -1,6 +1,6 @@
 import React, { useEffect, useState } from "react";
 import { invokeSafe } from "../tauri";
-import { useAppStore } from "../store/app";
+import { useAppStore, type LocalNamePreview, type LocalNameRules } from "../store/app";
 import { Hint } from "./common/Hint";
 
 const RECONCILE_SYNC_REQUIRED = "RECONCILE_SYNC_REQUIRED";
@@ -41,6 +41,16 @@ const groupStyle: React.CSSProperties = {
   background: "#fcfcfc"
 };
 
+type ChannelMigrationReport = {
+  old_deleted: boolean;
+  not_migrated: { file_id: string; name: string; reason: string }[];
+};
+
+type OldStorageChannel = {
+  chat_id: number;
+  files_left: number;
+};
+
 type SettingsProps = {
   onRequestLogout?: () => void;
   logoutBusy?: boolean;
@@ -87,6 +97,8 @@ export function Settings({
   const [openBackupBusy, setOpenBackupBusy] = useState(false);
   const [backupStatus, setBackupStatus] = useState<string | null>(null);
   const [channelStatus, setChannelStatus] = useState<string | null>(null);
+  const [oldChannel, setOldChannel] = useState<OldStorageChannel | null>(null);
+  const [oldChannelBusy, setOldChannelBusy] = useState(false);
   const [saveWarning, setSaveWarning] = useState<string | null>(null);
   const [tdlibCacheMb, setTdlibCacheMb] = useState<number | null>(null);
   const [tdlibCacheStatus, setTdlibCacheStatus] = useState<string | null>(null);
@@ -121,6 +133,10 @@ export function Settings({
   const keychainFallbackWarning =
     "Системное хранилище недоступно. Укажи пароль шифрования или выбери режим «Зашифрованный файл».";
 
+  async function refreshOldChannel() {
+    setOldChannel(await invokeSafe<OldStorageChannel | null>("tg_old_channel"));
+  }
+
   async function refreshTdlibCacheSize() {
     const cache = await invokeSafe<{ bytes: number; megabytes: number }>("tdlib_cache_size");
     setTdlibCacheMb(cache.megabytes);
@@ -140,6 +156,11 @@ export function Settings({
       } catch {
         setTdlibCacheStatus("Не удалось получить размер кеша TDLib.");
       }
+      try {
+        await refreshOldChannel();
+      } catch {
+        setOldChannel(null);
+      }
     })();
   }, [refreshSettings, setError]);
 
@@ -631,8 +652,17 @@ export function Settings({
                   try {
                     setCreating(true);
                     setChannelStatus("Создаю новый канал и переношу данные...");
-                    await invokeSafe("tg_create_channel");
-                    setChannelStatus("Канал создан. Данные перенесены. Проверь новый канал CloudTG.");
+                    const report = await invokeSafe<ChannelMigrationReport>("tg_create_channel");
+                    if (report.not_migrated.length === 0) {
+                      setChannelStatus("Канал создан. Данные перенесены. Проверь новый канал CloudTG.");
+                    } else {
+                      const names = report.not_migrated.slice(0, 5).map((f) => f.name).join(", ");
+                      const more = report.not_migrated.length > 5 ? ` и еще ${report.not_migrated.length - 5}` : "";
+                      setChannelStatus(
+                        `Канал создан, но не все файлы перенесены (${report.not_migrated.length}): ${names}${more}. Старый канал сохранен.`
+                      );
+                    }
+                    await refreshOldChannel();
                   } catch (e: any) {
                     setChannelStatus("Не удалось создать новый канал");
                     setError(String(e));
@@ -651,6 +681,34 @@ export function Settings({
               >
                 {creating ? "Создаю..." : "Создать новый канал CloudTG"}
               </button>
+              {oldChannel ? (
+                <button
+                  onClick={async () => {
+                    const warning =
+                      oldChannel.files_left > 0
+                        ? `В старом канале остались файлы, которые не удалось перенести (${oldChannel.files_left}). После удаления они станут недоступны. Удалить старый канал?`
+                        : "Удалить старый канал хранения?";
+                    if (!window.confirm(warning)) {
+                      return;
+                    }
+                    try {
+                      setOldChannelBusy(true);
+                      await invokeSafe("tg_delete_old_channel");
+                      setOldChannel(null);
+                      setChannelStatus("Старый канал удален.");
+                    } catch (e: any) {
+                      setChannelStatus("Не удалось удалить старый канал");
+                      setError(String(e));
+                    } finally {
+                      setOldChannelBusy(false);
+                    }
+                  }}
+                  disabled={oldChannelBusy}
+                  style={{ ...buttonStyle, opacity: oldChannelBusy ? 0.7 : 1, cursor: oldChannelBusy ? "wait" : "pointer" }}
+                >
+                  {oldChannelBusy ? "Удаляю..." : "Удалить старый канал"}
+                </button>
+              ) : null}
             </div>
             {channelStatus ? <div style={{ marginTop: 8, fontSize: 12, opacity: 0.75 }}>{channelStatus}</div> : null}
           </div>
@@ -723,6 +781,8 @@ export function Settings({
         </div>
       </div>
 
+      <LocalNamesPanel />
+
       {tdlibBuild.state && tdlibBuild.state !== "success" ? (
         <div
           style={{
@@ -760,3 +820,118 @@ export function Settings({
     </div>
   );
 }
+
+const LOCAL_NAME_SAMPLES = ["Отчет: итоги?.pdf", "CON.txt", "Заметки...", `${"Очень длинное имя ".repeat(20)}.docx`];
+
+const ADJUSTMENT_LABELS: Record<LocalNamePreview["adjustments"][number], string> = {
+  forbidden_chars: "заменены символы",
+  relative_segment: "служебное имя",
+  trailing_dots: "убраны точки в конце",
+  reserved_name: "имя устройства Windows",
+  truncated: "укорочено"
+};
+
+// Правила, по которым имена из канала превращаются в имена файлов на диске.
+function LocalNamesPanel() {
+  const { setError, getLocalNameRules, setLocalNameRules, previewLocalNames } = useAppStore();
+  const [rules, setRules] = useState<LocalNameRules | null>(null);
+  const [preview, setPreview] = useState<LocalNamePreview[]>([]);
+  const [saving, setSaving] = useState(false);
+  const [status, setStatus] = useState<string | null>(null);
+
+  useEffect(() => {
+    getLocalNameRules()
+      .then(setRules)
+      .catch((e) => setError(String(e)));
+  }, [getLocalNameRules, setError]);
+
+  useEffect(() => {
+    if (!rules) return;
+    let stale = false;
+    previewLocalNames(LOCAL_NAME_SAMPLES, rules)
+      .then((items) => {
+        if (!stale) setPreview(items);
+      })
+      .catch(() => setPreview([]));
+    return () => {
+      stale = true;
+    };
+  }, [rules, previewLocalNames]);
+
+  if (!rules) return null;
+
+  return (
+    <div style={panelStyle}>
+      <div style={{ display: "flex", alignItems: "center", gap: 6 }}>
+        <b>Имена файлов на диске</b>
+        <Hint text="Смена правил меняет ожидаемые имена: уже скачанные файлы с другими именами будут скачаны заново." />
+      </div>
+      <div style={{ marginTop: 12, display: "grid", gap: 10, gridTemplateColumns: "repeat(auto-fit, minmax(220px, 1fr))" }}>
+        <label style={{ display: "grid", gap: 6 }}>
+          <span style={{ fontSize: 13 }}>Правила</span>
+          <select
+            value={rules.profile}
+            onChange={(e) => setRules({ ...rules, profile: e.target.value as LocalNameRules["profile"] })}
+            style={inputStyle}
+          >
+            <option value="auto">Как в этой ОС</option>
+            <option value="windows">Windows</option>
+            <option value="macos">macOS</option>
+            <option value="linux">Linux</option>
+            <option value="portable">Переносимые (для флешек и сетевых дисков)</option>
+          </select>
+        </label>
+        <label style={{ display: "grid", gap: 6 }}>
+          <span style={{ fontSize: 13 }}>Замена запрещенных символов</span>
+          <input
+            value={rules.replacement}
+            maxLength={1}
+            onChange={(e) => setRules({ ...rules, replacement: e.target.value || "_" })}
+            style={{ ...inputStyle, width: 80 }}
+          />
+        </label>
+        <label style={{ display: "flex", gap: 8, alignItems: "center", fontSize: 13 }}>
+          <input
+            type="checkbox"
+            checked={rules.file_id_suffix}
+            onChange={(e) => setRules({ ...rules, file_id_suffix: e.target.checked })}
+          />
+          Добавлять к имени метку файла, чтобы одноименные файлы не путались
+        </label>
+      </div>
+      {preview.length ? (
+        <div style={{ ...groupStyle, marginTop: 10, display: "grid", gap: 4, fontSize: 12 }}>
+          {preview.map((item) => (
+            <div key={item.name} style={{ overflowWrap: "anywhere" }}>
+              {item.name} → <b>{item.local || "файл"}</b>
+              {item.adjustments.length ? (
+                <span style={{ opacity: 0.7 }}> ({item.adjustments.map((a) => ADJUSTMENT_LABELS[a]).join(", ")})</span>
+              ) : null}
+            </div>
+          ))}
+        </div>
+      ) : null}
+      <div style={{ marginTop: 10, display: "flex", gap: 10, alignItems: "center", flexWrap: "wrap" }}>
+        <button
+          onClick={async () => {
+            try {
+              setSaving(true);
+              setRules(await setLocalNameRules(rules));
+              setStatus("Правила сохранены.");
+            } catch (e: any) {
+              setStatus(null);
+              setError(String(e));
+            } finally {
+              setSaving(false);
+            }
+          }}
+          disabled={saving}
+          style={{ ...buttonStyle, opacity: saving ? 0.7 : 1, cursor: saving ? "wait" : "pointer" }}
+        >
+          {saving ? "Сохраняю..." : "Сохранить правила"}
+        </button>
+        {status ? <span style={{ fontSize: 12, opacity: 0.75 }}>{status}</span> : null}
+      </div>
+    </div>
+  );
+}
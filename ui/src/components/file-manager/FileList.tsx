@@ -1,6 +1,6 @@
 import React from "react";
 import type { FileItem } from "../../store/app";
-import { displayFileSizeBytes, shouldShowOpenFolderButton } from "./fileActions";
+import { canStreamFile, displayFileSizeBytes, remoteStatusBadge, shouldShowOpenFolderButton, snippetParts } from "./fileActions";
 
 type FileListProps = {
   files: FileItem[];
@@ -10,6 +10,9 @@ type FileListProps = {
   onDownload: (file: FileItem) => void | Promise<void>;
   onOpen: (file: FileItem) => void | Promise<void>;
   onOpenFolder: (file: FileItem) => void | Promise<void>;
+  onStream: (file: FileItem) => void | Promise<void>;
+  onCopyToClipboard: (file: FileItem) => void | Promise<void>;
+  onDragOut: (file: FileItem) => void | Promise<void>;
   onShare: (file: FileItem) => void;
   onRepair: (file: FileItem) => void | Promise<void>;
   onDelete: (file: FileItem) => void | Promise<void>;
@@ -23,6 +26,9 @@ export function FileList({
   onDownload,
   onOpen,
   onOpenFolder,
+  onStream,
+  onCopyToClipboard,
+  onDragOut,
   onShare,
   onRepair,
   onDelete
@@ -58,6 +64,19 @@ export function FileList({
                   padding: "8px 10px",
                   borderTop: "1px solid #f0f0f0"
                 }}
+                onDoubleClick={() => {
+                  if (!isDownloading) {
+                    void onOpen(file);
+                  }
+                }}
+                draggable={!isDownloading}
+                title="Перетащите строку в другое приложение, чтобы передать файл"
+                onDragStart={(e) => {
+                  // Файлы перетаскивает ОС через плагин, а не HTML-перетаскивание
+                  // страницы.
+                  e.preventDefault();
+                  void onDragOut(file);
+                }}
               >
                 <input
                   type="checkbox"
@@ -95,7 +114,28 @@ export function FileList({
                         битый
                       </span>
                     ) : null}
+                    {remoteStatusBadge(file) ? (
+                      <span
+                        style={{
+                          fontSize: 11,
+                          color: "#555",
+                          border: "1px solid #ddd",
+                          background: "#f6f6f6",
+                          borderRadius: 999,
+                          padding: "1px 6px"
+                        }}
+                      >
+                        {remoteStatusBadge(file)}
+                      </span>
+                    ) : null}
                   </div>
+                  {file.snippet ? (
+                    <span style={{ fontSize: 12, opacity: 0.8 }}>
+                      {snippetParts(file.snippet).map((part, i) =>
+                        part.hit ? <mark key={i}>{part.text}</mark> : <React.Fragment key={i}>{part.text}</React.Fragment>
+                      )}
+                    </span>
+                  ) : null}
                   <span style={{ fontSize: 11, opacity: 0.6 }}>
                     {formatBytes(displaySize)} • #{file.hash}
                   </span>
@@ -160,6 +200,15 @@ export function FileList({
                           {isDownloading ? "Идет скачивание..." : "Скачать заново"}
                         </button>
                       )}
+                      {canStreamFile(file) ? (
+                        <button
+                          onClick={() => void onStream(file)}
+                          disabled={isDownloading}
+                          style={{ padding: "6px 10px", borderRadius: 8, textAlign: "left" }}
+                        >
+                          Смотреть без скачивания
+                        </button>
+                      ) : null}
                       {shouldShowOpenFolderButton(file) ? (
                         <button
                           onClick={() => void onOpenFolder(file)}
@@ -168,6 +217,13 @@ export function FileList({
                           Открыть папку
                         </button>
                       ) : null}
+                      <button
+                        onClick={() => void onCopyToClipboard(file)}
+                        disabled={isDownloading}
+                        style={{ padding: "6px 10px", borderRadius: 8, textAlign: "left" }}
+                      >
+                        Копировать в буфер
+                      </button>
                       <button
                         onClick={() => onShare(file)}
                         style={{ padding: "6px 10px", borderRadius: 8, textAlign: "left" }}
@@ -1,16 +1,25 @@
 import React from "react";
+import type { FileSearchSort } from "../../store/app";
 import { Hint } from "../common/Hint";
 
+export type SearchStateFilter = "all" | "downloaded" | "not_downloaded" | "broken";
+
 type SearchPanelProps = {
   searchName: string;
   searchType: string;
   searchAll: boolean;
+  searchRecursive: boolean;
+  searchState: SearchStateFilter;
+  searchSort: FileSearchSort;
   searchBusy: boolean;
   searchActive: boolean;
   foundCount: number;
   onSearchNameChange: (value: string) => void;
   onSearchTypeChange: (value: string) => void;
   onSearchAllChange: (value: boolean) => void;
+  onSearchRecursiveChange: (value: boolean) => void;
+  onSearchStateChange: (value: SearchStateFilter) => void;
+  onSearchSortChange: (value: FileSearchSort) => void;
   onRunSearch: () => void | Promise<void>;
   onReset: () => void | Promise<void>;
 };
@@ -19,12 +28,18 @@ export function SearchPanel({
   searchName,
   searchType,
   searchAll,
+  searchRecursive,
+  searchState,
+  searchSort,
   searchBusy,
   searchActive,
   foundCount,
   onSearchNameChange,
   onSearchTypeChange,
   onSearchAllChange,
+  onSearchRecursiveChange,
+  onSearchStateChange,
+  onSearchSortChange,
   onRunSearch,
   onReset
 }: SearchPanelProps) {
@@ -32,9 +47,9 @@ export function SearchPanel({
     <div style={{ marginTop: 10, padding: 12, border: "1px solid #eee", borderRadius: 12, background: "#fafafa" }}>
       <div style={{ display: "flex", alignItems: "center", gap: 6 }}>
         <b>Поиск</b>
-        <Hint text="Можно искать по имени и/или расширению. Если отметить «Во всех папках», поиск не ограничивается текущей папкой." />
+        <Hint text="Можно искать по имени, пути папки и подписи из Telegram и/или по расширению. Если отметить «Во всех папках», поиск не ограничивается текущей папкой, а «С подпапками» добавляет вложенные папки текущей." />
       </div>
-      <div style={{ marginTop: 8, display: "grid", gridTemplateColumns: "1fr 1fr 160px", gap: 8 }}>
+      <div style={{ marginTop: 8, display: "grid", gridTemplateColumns: "1fr 1fr 140px 140px", gap: 8 }}>
         <input
           value={searchName}
           onChange={(e) => onSearchNameChange(e.target.value)}
@@ -44,7 +59,7 @@ export function SearchPanel({
               void onRunSearch();
             }
           }}
-          placeholder="Имя"
+          placeholder="Имя, папка или подпись"
           style={{ padding: 10, borderRadius: 10, border: "1px solid #ccc" }}
         />
         <input
@@ -67,6 +82,40 @@ export function SearchPanel({
           />
           Во всех папках
         </label>
+        <label style={{ display: "flex", alignItems: "center", gap: 6, fontSize: 12, opacity: searchAll ? 0.4 : 0.8 }}>
+          <input
+            type="checkbox"
+            checked={searchRecursive}
+            disabled={searchAll}
+            onChange={(e) => onSearchRecursiveChange(e.target.checked)}
+          />
+          С подпапками
+        </label>
+      </div>
+      <div style={{ marginTop: 8, display: "grid", gridTemplateColumns: "1fr 1fr", gap: 8 }}>
+        <select
+          value={searchState}
+          onChange={(e) => onSearchStateChange(e.target.value as SearchStateFilter)}
+          style={{ padding: 8, borderRadius: 10, border: "1px solid #ccc" }}
+        >
+          <option value="all">Любое состояние</option>
+          <option value="downloaded">Скачанные</option>
+          <option value="not_downloaded">Не скачанные</option>
+          <option value="broken">Битые</option>
+        </select>
+        <select
+          value={searchSort}
+          onChange={(e) => onSearchSortChange(e.target.value as FileSearchSort)}
+          style={{ padding: 8, borderRadius: 10, border: "1px solid #ccc" }}
+        >
+          <option value="relevance">По релевантности</option>
+          <option value="name_asc">По имени (А–Я)</option>
+          <option value="name_desc">По имени (Я–А)</option>
+          <option value="size_desc">Сначала большие</option>
+          <option value="size_asc">Сначала маленькие</option>
+          <option value="created_desc">Сначала новые</option>
+          <option value="created_asc">Сначала старые</option>
+        </select>
       </div>
       <div style={{ marginTop: 8, display: "flex", gap: 8, alignItems: "center" }}>
         <button
@@ -1,16 +1,18 @@
 import React from "react";
-import type { ChatItem, FileItem } from "../../store/app";
+import type { ChatFolder, ChatItem, FileItem } from "../../store/app";
 import { Hint } from "../common/Hint";
 
 type SharePanelProps = {
   shareFile: FileItem | null;
   shareQuery: string;
   shareResults: ChatItem[];
+  shareFolders: ChatFolder[];
   shareBusy: boolean;
   onShareQueryChange: (value: string) => void;
   onClose: () => void;
   onSearch: () => void | Promise<void>;
   onLoadRecent: () => void | Promise<void>;
+  onLoadFolder: (folderId: number) => void | Promise<void>;
   onSend: (chatId: number) => void | Promise<void>;
 };
 
@@ -18,11 +20,13 @@ export function SharePanel({
   shareFile,
   shareQuery,
   shareResults,
+  shareFolders,
   shareBusy,
   onShareQueryChange,
   onClose,
   onSearch,
   onLoadRecent,
+  onLoadFolder,
   onSend
 }: SharePanelProps) {
   if (!shareFile) {
@@ -67,6 +71,16 @@ export function SharePanel({
         >
           Недавние чаты
         </button>
+        {shareFolders.map((folder) => (
+          <button
+            key={folder.id}
+            onClick={() => void onLoadFolder(folder.id)}
+            disabled={shareBusy}
+            style={{ padding: "6px 10px", borderRadius: 8 }}
+          >
+            {folder.title}
+          </button>
+        ))}
       </div>
       <div style={{ marginTop: 8 }}>
         {shareResults.length === 0 ? (
@@ -80,7 +94,7 @@ export function SharePanel({
                 key={chat.id}
                 style={{
                   display: "grid",
-                  gridTemplateColumns: "1fr 140px",
+                  gridTemplateColumns: "32px 1fr 140px",
                   gap: 10,
                   alignItems: "center",
                   padding: "8px 10px",
@@ -88,6 +102,30 @@ export function SharePanel({
                   borderRadius: 8
                 }}
               >
+                {chat.photo ? (
+                  <img
+                    src={chat.photo}
+                    alt=""
+                    style={{ width: 32, height: 32, borderRadius: "50%", objectFit: "cover" }}
+                  />
+                ) : (
+                  <div
+                    style={{
+                      width: 32,
+                      height: 32,
+                      borderRadius: "50%",
+                      background: "#e8eef7",
+                      display: "flex",
+                      alignItems: "center",
+                      justifyContent: "center",
+                      fontSize: 13,
+                      fontWeight: 600,
+                      color: "#4a5f80"
+                    }}
+                  >
+                    {chat.title.trim().charAt(0).toUpperCase()}
+                  </div>
+                )}
                 <div>
                   <div style={{ fontWeight: 500 }}>{chat.title}</div>
                   <div style={{ fontSize: 12, opacity: 0.6 }}>
@@ -1,4 +1,4 @@
-import type { FileItem } from "../../store/app";
+import type { FileAction, FileItem, UploadDedup } from "../../store/app";
 
 type DownloadActionArgs = {
   file: FileItem;
@@ -9,10 +9,43 @@ type DownloadActionArgs = {
 
 type OpenActionArgs = {
   file: FileItem;
-  openFile: (fileId: string) => Promise<void>;
+  confirm?: (message: string) => boolean;
+  openFile: (fileId: string, confirmToken?: string) => Promise<void>;
   reloadFiles: () => Promise<void>;
 };
 
+const OPEN_NEEDS_CONFIRMATION = "NEEDS_CONFIRMATION:";
+const UPLOAD_DUPLICATE = "DUPLICATE:";
+
+export function parseOpenConfirmation(err: unknown): { token: string; message: string } | null {
+  return parseTokenPrompt(err, OPEN_NEEDS_CONFIRMATION);
+}
+
+export function parseUploadDuplicate(err: unknown): { token: string; message: string } | null {
+  return parseTokenPrompt(err, UPLOAD_DUPLICATE);
+}
+
+function parseTokenPrompt(err: unknown, prefix: string): { token: string; message: string } | null {
+  const text = String(err);
+  const start = text.indexOf(prefix);
+  if (start < 0) {
+    return null;
+  }
+  const rest = text.slice(start + prefix.length);
+  const sep = rest.indexOf(":");
+  if (sep <= 0) {
+    return null;
+  }
+  return { token: rest.slice(0, sep), message: rest.slice(sep + 1).trim() };
+}
+
+type UploadActionArgs = {
+  dirId: string;
+  uploadToken: string;
+  confirm: (message: string) => boolean;
+  uploadFile: (dirId: string, uploadToken: string, dedup?: UploadDedup) => Promise<void>;
+};
+
 type OpenFolderActionArgs = {
   file: FileItem;
   openFileFolder: (fileId: string) => Promise<void>;
@@ -22,10 +55,56 @@ export function displayFileSizeBytes(file: Pick<FileItem, "is_downloaded" | "loc
   return file.is_downloaded ? (file.local_size ?? 0) : 0;
 }
 
+/** Подпись значка для состояний копии в канале, кроме «ok» и «broken» (у битых свой значок). */
+export function remoteStatusBadge(file: Pick<FileItem, "remote_status">): string | null {
+  switch (file.remote_status) {
+    case "pending_upload":
+      return "не отправлен";
+    case "chunked":
+      return "по частям";
+    case "encrypted":
+      return "зашифрован";
+    default:
+      return null;
+  }
+}
+
+/** Делит фрагмент полнотекстового поиска на куски; совпадения бэкенд обрамляет в `[` и `]`. */
+export function snippetParts(snippet: string): { text: string; hit: boolean }[] {
+  const parts: { text: string; hit: boolean }[] = [];
+  const re = /\[([^\]]*)\]/g;
+  let last = 0;
+  for (let m = re.exec(snippet); m; m = re.exec(snippet)) {
+    if (m.index > last) parts.push({ text: snippet.slice(last, m.index), hit: false });
+    parts.push({ text: m[1], hit: true });
+    last = m.index + m[0].length;
+  }
+  if (last < snippet.length) parts.push({ text: snippet.slice(last), hit: false });
+  return parts;
+}
+
 export function shouldShowOpenFolderButton(file: Pick<FileItem, "is_downloaded">): boolean {
   return file.is_downloaded;
 }
 
+const STREAMABLE_EXTENSIONS = new Set([
+  "mp4", "m4v", "mov", "mkv", "webm", "avi",
+  "mp3", "m4a", "aac", "ogg", "oga", "opus", "flac", "wav"
+]);
+
+// Те же расширения, что понимает сервер потокового открытия.
+export function canStreamFile(file: Pick<FileItem, "name" | "is_downloaded">): boolean {
+  if (file.is_downloaded) return false;
+  const ext = file.name.split(".").pop()?.toLowerCase() ?? "";
+  return file.name.includes(".") && STREAMABLE_EXTENSIONS.has(ext);
+}
+
+// Перетаскивается весь выбор, если строка в нем, иначе только она сама.
+export function dragFileIds(fileId: string, selected: Set<string>): string[] {
+  if (!selected.has(fileId)) return [fileId];
+  return [fileId, ...Array.from(selected).filter((id) => id !== fileId)];
+}
+
 export async function handleDownloadAction({
   file,
   confirm,
@@ -44,11 +123,77 @@ export async function handleDownloadAction({
   await reloadFiles();
 }
 
-export async function handleOpenAction({ file, openFile, reloadFiles }: OpenActionArgs): Promise<void> {
-  await openFile(file.id);
+async function withOpenConfirmation<T>(
+  run: (confirmToken?: string) => Promise<T>,
+  confirm?: (message: string) => boolean
+): Promise<T | null> {
+  try {
+    return await run();
+  } catch (err) {
+    const pending = parseOpenConfirmation(err);
+    if (!pending || !confirm) {
+      throw err;
+    }
+    if (!confirm(pending.message)) {
+      return null;
+    }
+    return run(pending.token);
+  }
+}
+
+export async function handleOpenAction({ file, confirm, openFile, reloadFiles }: OpenActionArgs): Promise<void> {
+  const opened = await withOpenConfirmation(async (token) => {
+    await openFile(file.id, token);
+    return true;
+  }, confirm);
+  if (!opened) {
+    return;
+  }
+  await reloadFiles();
+}
+
+type ActivateActionArgs = OpenActionArgs & {
+  activateFile: (fileId: string, confirmToken?: string) => Promise<FileAction>;
+  openFileFolder: (fileId: string) => Promise<void>;
+};
+
+// Двойной клик: действие выбирает бэкенд по настройкам расширений.
+// Просмотра в интерфейсе пока нет, поэтому "preview" открывает файл.
+export async function handleActivateAction({
+  file,
+  confirm,
+  activateFile,
+  openFile,
+  openFileFolder,
+  reloadFiles
+}: ActivateActionArgs): Promise<void> {
+  const action = await withOpenConfirmation((token) => activateFile(file.id, token), confirm);
+  if (!action) {
+    return;
+  }
+  if (action === "ask" && confirm && !confirm(`Открыть файл ${file.name}? Отмена — показать его в папке.`)) {
+    await openFileFolder(file.id);
+  } else if (action === "ask" || action === "preview") {
+    await handleOpenAction({ file, confirm, openFile, reloadFiles });
+    return;
+  }
   await reloadFiles();
 }
 
 export async function handleOpenFolderAction({ file, openFileFolder }: OpenFolderActionArgs): Promise<void> {
   await openFileFolder(file.id);
 }
+
+// Бэкенд сообщает о таком же файле в хранилище и выдает новый токен:
+// согласие добавляет ссылку на него, отказ загружает файл заново.
+export async function handleUploadAction({ dirId, uploadToken, confirm, uploadFile }: UploadActionArgs): Promise<void> {
+  try {
+    await uploadFile(dirId, uploadToken);
+  } catch (err) {
+    const duplicate = parseUploadDuplicate(err);
+    if (!duplicate) {
+      throw err;
+    }
+    await uploadFile(dirId, duplicate.token, confirm(duplicate.message) ? "reference" : "upload");
+  }
+}
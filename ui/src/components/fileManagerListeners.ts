@@ -1,4 +1,4 @@
-import type { DirNode } from "../store/app";
+import type { DirChangedPayload, DirNode, DropUpload, UploadProgress } from "../store/app";
 
 type RefValue<T> = { current: T };
 
@@ -35,13 +35,50 @@ export function createTreeUpdatedHandler(
   };
 }
 
+export type FileChangedPayload = {
+  id: string;
+  change: "created" | "updated" | "moved" | "deleted";
+  dir_id: string | null;
+  from_dir_id?: string | null;
+};
+
+export function createFileChangedHandler(
+  selectedNodeRef: RefValue<DirNode | null>,
+  isRootSelectedRef: RefValue<boolean>,
+  reloadFilesRef: RefValue<() => Promise<void>>
+): (event: { payload: FileChangedPayload }) => Promise<void> {
+  return async (event) => {
+    const selected = selectedNodeRef.current;
+    if (!selected || isRootSelectedRef.current) return;
+    const dirId = event.payload?.dir_id ?? null;
+    const fromDirId = event.payload?.from_dir_id ?? null;
+    // Перенесенный файл касается и прежней папки, и новой.
+    if (dirId && dirId !== selected.id && fromDirId !== selected.id) return;
+    await reloadFilesRef.current();
+  };
+}
+
+// Событие папки перечитывает список, только если изменилась открытая папка
+// (например, из нее удалили несколько файлов разом).
+export function createDirChangedHandler(
+  selectedNodeRef: RefValue<DirNode | null>,
+  isRootSelectedRef: RefValue<boolean>,
+  reloadFilesRef: RefValue<() => Promise<void>>
+): (event: { payload: DirChangedPayload }) => Promise<void> {
+  return async (event) => {
+    const selected = selectedNodeRef.current;
+    if (!selected || isRootSelectedRef.current) return;
+    if (event.payload?.change !== "updated" || event.payload.id !== selected.id) return;
+    await reloadFilesRef.current();
+  };
+}
+
 export function createDragDropHandler(
   selectedNodeRef: RefValue<DirNode | null>,
   isRootSelectedRef: RefValue<boolean>,
-  reloadFilesRef: RefValue<() => Promise<void>>,
   uploadInProgressRef: RefValue<boolean>,
-  prepareUploadPaths: (paths: string[]) => Promise<string[]>,
-  uploadFile: (dirId: string, uploadToken: string) => Promise<void>,
+  activeBatchRef: RefValue<string | null>,
+  dropUpload: (dirId: string, paths: string[]) => Promise<DropUpload>,
   setDropActive: (active: boolean) => void,
   setUploadBusy: (busy: boolean) => void,
   setError: (message: string) => void
@@ -74,20 +111,45 @@ export function createDragDropHandler(
     uploadInProgressRef.current = true;
     setUploadBusy(true);
 
+    // Загрузка идет в фоне на стороне backend; конец пакета приходит событием upload_progress.
     void (async () => {
       try {
-        const uploadTokens = await prepareUploadPaths(paths);
-        if (uploadTokens.length === 0) return;
-        for (const uploadToken of uploadTokens) {
-          await uploadFile(currentNode.id, uploadToken);
+        const batch = await dropUpload(currentNode.id, paths);
+        if (batch.total > 0) {
+          activeBatchRef.current = batch.batch_id;
+          return;
         }
-        await reloadFilesRef.current();
       } catch (e: any) {
         setError(String(e));
-      } finally {
-        uploadInProgressRef.current = false;
-        setUploadBusy(false);
       }
+      uploadInProgressRef.current = false;
+      setUploadBusy(false);
     })();
   };
 }
+
+export function createUploadProgressHandler(
+  activeBatchRef: RefValue<string | null>,
+  uploadInProgressRef: RefValue<boolean>,
+  reloadFilesRef: RefValue<() => Promise<void>>,
+  setUploadBusy: (busy: boolean) => void,
+  setUploadCounter: (counter: { done: number; total: number } | null) => void,
+  setError: (message: string) => void
+): (event: { payload: UploadProgress }) => Promise<void> {
+  return async (event) => {
+    const progress = event.payload;
+    if (progress.batch_id !== activeBatchRef.current) return;
+    if (progress.error) {
+      setError(progress.name ? `${progress.name}: ${progress.error}` : progress.error);
+    }
+    if (!progress.finished) {
+      setUploadCounter({ done: progress.done, total: progress.total });
+      return;
+    }
+    activeBatchRef.current = null;
+    uploadInProgressRef.current = false;
+    setUploadCounter(null);
+    setUploadBusy(false);
+    await reloadFilesRef.current();
+  };
+}
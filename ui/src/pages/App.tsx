@@ -1,7 +1,7 @@
 import React, { useCallback, useEffect, useRef, useState } from "react";
 import { getVersion } from "@tauri-apps/api/app";
 import { invokeSafe, listenSafe, isTauri } from "../tauri";
-import { useAppStore } from "../store/app";
+import { useAppStore, type DirChangedPayload } from "../store/app";
 import { FileManager } from "../components/FileManager";
 import { Login } from "../components/Login";
 import { Settings } from "../components/Settings";
@@ -19,6 +19,8 @@ type AppUpdateInfo = {
   has_update: boolean;
   download_url: string | null;
   release_url: string | null;
+  release_notes: string | null;
+  installer_path: string | null;
 };
 
 type HelpBlock =
@@ -231,6 +233,7 @@ export default function App() {
     setAuth,
     tree,
     refreshTree,
+    applyDirChange,
     error,
     setError,
     refreshAuth,
@@ -368,6 +371,18 @@ export default function App() {
           if (disposedRef.current) return;
           await refreshTree();
         });
+
+        await addListener<DirChangedPayload>("dir_changed", async (event) => {
+          if (disposedRef.current) return;
+          await applyDirChange(event.payload);
+        });
+
+        await addListener<{ cause: string; attempt: number }>("tdlib_restarted", async () => {
+          if (disposedRef.current) return;
+          // Новый клиент сам пришлет состояние авторизации; синхронизацию запустит обработчик auth_state_changed.
+          syncStartedRef.current = false;
+          await refreshAuth();
+        });
       } catch (e: any) {
         if (!disposedRef.current) {
           setError(String(e));
@@ -382,6 +397,7 @@ export default function App() {
     refreshAuth,
     refreshSettings,
     refreshTree,
+    applyDirChange,
     setAuth,
     setError,
     setTdlibBuild,
@@ -391,8 +407,18 @@ export default function App() {
     setTgSync
   ]);
 
+  useEffect(() => {
+    if (!isTauri()) return;
+    // Кадр после монтирования уже на экране: бэкенд начинает отложенный прогрев.
+    const frame = requestAnimationFrame(() => {
+      invokeSafe("app_first_paint").catch(() => {});
+    });
+    return () => cancelAnimationFrame(frame);
+  }, []);
+
   useEffect(() => {
     let active = true;
+    let unlisten: (() => void) | null = null;
     (async () => {
       if (!isTauri()) return;
       try {
@@ -404,9 +430,18 @@ export default function App() {
         // Ignore version read errors, UI can work without it.
       }
       try {
-        const update = await invokeSafe<AppUpdateInfo>("app_check_update");
+        // Установщик скачивается в фоне; готовность приходит событием update_available.
+        const cleanup = await listenSafe<AppUpdateInfo>("update_available", (event) => {
+          if (active) setAppUpdate(event.payload);
+        });
+        if (active) {
+          unlisten = cleanup;
+        } else {
+          cleanup();
+        }
+        const update = await invokeSafe<AppUpdateInfo>("app_update_check");
         if (active && update.has_update) {
-          setAppUpdate(update);
+          setAppUpdate((prev) => prev ?? update);
         }
       } catch {
         // Ignore update check errors to avoid noisy UX when offline.
@@ -414,6 +449,7 @@ export default function App() {
     })();
     return () => {
       active = false;
+      if (unlisten) unlisten();
     };
   }, []);
 
@@ -530,7 +566,31 @@ export default function App() {
           <div style={{ marginTop: 4, fontSize: 12, opacity: 0.8 }}>
             Текущая версия: {appUpdate.current_version}
           </div>
-          {appUpdate.download_url ? (
+          {appUpdate.release_notes ? (
+            <details style={{ marginTop: 8 }}>
+              <summary style={{ cursor: "pointer", fontSize: 13 }}>Что нового</summary>
+              <div style={{ marginTop: 6, fontSize: 13, whiteSpace: "pre-wrap", maxHeight: 240, overflowY: "auto" }}>
+                {appUpdate.release_notes}
+              </div>
+            </details>
+          ) : null}
+          {appUpdate.installer_path ? (
+            <div style={{ marginTop: 8, display: "flex", alignItems: "center", gap: 8, flexWrap: "wrap" }}>
+              <button
+                onClick={async () => {
+                  try {
+                    await invokeSafe("app_update_install");
+                  } catch (e: any) {
+                    setError(String(e));
+                  }
+                }}
+                style={{ padding: "8px 12px", borderRadius: 10 }}
+              >
+                Установить
+              </button>
+              <span style={{ fontSize: 12, opacity: 0.8 }}>{appUpdate.installer_path}</span>
+            </div>
+          ) : appUpdate.download_url ? (
             <div style={{ marginTop: 8, display: "flex", alignItems: "center", gap: 8, flexWrap: "wrap" }}>
               <button
                 onClick={async () => {
@@ -6,9 +6,23 @@ export type DirNode = {
   name: string;
   parent_id: string | null;
   is_broken: boolean;
+  link_target_id?: string | null;
   children: DirNode[];
 };
 
+// Точечное изменение дерева из события dir_changed.
+export type DirChangedPayload = {
+  id: string;
+  change: "created" | "updated" | "moved" | "deleted";
+  parent_id: string | null;
+};
+
+export type RemoteStatus = "ok" | "broken" | "pending_upload" | "chunked" | "encrypted";
+
+export type FileAction = "open" | "open_folder" | "preview" | "ask";
+
+export type UploadDedup = "reference" | "upload";
+
 export type FileItem = {
   id: string;
   dir_id: string;
@@ -21,6 +35,11 @@ export type FileItem = {
   tg_msg_id: number;
   created_at: number;
   is_broken: boolean;
+  is_favorite?: boolean;
+  link_id?: string | null;
+  media_group_id?: string | null;
+  remote_status?: RemoteStatus;
+  snippet?: string;
 };
 
 export type RepairResult = {
@@ -29,20 +48,247 @@ export type RepairResult = {
   code?: string | null;
 };
 
+// Итог пакетной операции: `failed` — пары [id файла, ошибка].
+export type BatchOutcome = {
+  done: string[];
+  failed: Array<[string, string]>;
+};
+
+// Итог копирования папки: id копии и скопированных файлов, `failed` — как в BatchOutcome.
+// Непустые `failed_dirs` значат, что копия неполная.
+export type DirCopy = {
+  dir_id: string;
+  created: string[];
+  failed: Array<[string, string]>;
+  failed_dirs: Array<[string, string]>;
+  failed_links: Array<[string, string]>;
+};
+
 export type ChatItem = {
   id: number;
   title: string;
   kind: string;
   username: string | null;
+  photo_path?: string | null;
+  photo?: string | null;
 };
 
+export type FileSearchSort =
+  | "name_asc"
+  | "name_desc"
+  | "size_asc"
+  | "size_desc"
+  | "created_asc"
+  | "created_desc"
+  | "relevance";
+
 export type FileSearchFilters = {
   dirId?: string | null;
+  recursive?: boolean;
   name?: string;
+  text?: string;
   fileType?: string;
+  isDownloaded?: boolean;
+  isBroken?: boolean;
+  isFavorite?: boolean;
+  minSize?: number;
+  maxSize?: number;
+  createdFrom?: number;
+  createdTo?: number;
+  sort?: FileSearchSort;
   limit?: number;
 };
 
+// Умная папка: сохраненные фильтры поиска в том виде, в каком их хранит бэкенд.
+export type VirtualFolder = {
+  id: string;
+  name: string;
+  query: Record<string, unknown>;
+  created_at: number;
+};
+
+export type ChatFolder = {
+  id: number;
+  title: string;
+};
+
+export type DirPrefs = {
+  sort: FileSearchSort;
+  grouping: "none" | "type" | "date";
+  view_mode: "list" | "grid";
+};
+
+export type RecentError = {
+  id: number;
+  code: string;
+  message: string;
+  command: string | null;
+  at: number;
+};
+
+export type Account = {
+  id: string;
+  label: string;
+  created_at: number;
+  active: boolean;
+  auth_state: string | null;
+};
+
+export type StorageChannel = {
+  chat_id: number;
+  title: string;
+  last_message_id: number;
+  created_at: number;
+  dir_ids: string[];
+  file_count: number;
+};
+
+export type DirStats = {
+  dir_id: string;
+  parent_id: string | null;
+  name: string;
+  own_files: number;
+  own_size: number;
+  total_files: number;
+  total_size: number;
+  cached_bytes: number;
+};
+
+export type StorageStats = {
+  total_size: number;
+  file_count: number;
+  dir_count: number;
+  broken_count: number;
+  dirs: DirStats[];
+  largest: { id: string; dir_id: string; name: string; size: number }[];
+  cache: { bytes: number; file_count: number };
+  rollups_updated_at: number | null;
+};
+
+export type CacheLimit = {
+  enabled: boolean;
+  max_bytes: number;
+};
+
+export type CacheUsage = {
+  bytes: number;
+  file_count: number;
+  limit: CacheLimit;
+};
+
+export type CacheClearReport = {
+  removed_files: number;
+  freed_bytes: number;
+  // Измененные после скачивания копии, которые вытеснение оставило.
+  kept_edited: number;
+};
+
+export type DropUpload = {
+  batch_id: string;
+  total: number;
+};
+
+export type UploadProgress = {
+  batch_id: string;
+  dir_id: string;
+  done: number;
+  total: number;
+  name: string | null;
+  file_id: string | null;
+  error: string | null;
+  finished: boolean;
+};
+
+export type FileStream = {
+  url: string;
+  local: boolean;
+};
+
+// Локальные пути для перетаскивания из приложения и картинка под курсором.
+export type DragPayload = {
+  paths: string[];
+  icon: string;
+};
+
+export type DeepLinkNavigation = {
+  dir_id: string;
+  file_id: string | null;
+};
+
+export type AutoSyncStatus = {
+  enabled: boolean;
+  interval_secs: number;
+  running: boolean;
+  failures: number;
+  last_run_at: number | null;
+  last_success_at: number | null;
+  last_error: string | null;
+  next_run_at: number | null;
+};
+
+export type LocalNameRules = {
+  profile: "auto" | "windows" | "macos" | "linux" | "portable";
+  replacement: string;
+  file_id_suffix: boolean;
+};
+
+export type LocalNamePreview = {
+  name: string;
+  local: string;
+  adjustments: Array<"forbidden_chars" | "relative_segment" | "trailing_dots" | "reserved_name" | "truncated">;
+};
+
+export type FileDownloadProgress = {
+  file_id: string;
+  downloaded: number;
+  total: number | null;
+};
+
+export type InboxCaptured = {
+  file_id: string;
+  dir_id: string;
+  dir_name: string;
+};
+
+export type Note = {
+  file_id: string;
+  dir_id: string;
+  name: string;
+  body: string;
+  hash: string;
+  updated_at: number;
+};
+
+export type NoteSaved = {
+  note: Note;
+  conflict: boolean;
+};
+
+export type NoteHit = {
+  file_id: string;
+  name: string;
+  snippet: string;
+};
+
+export type MigrationFailure = {
+  file_id: string;
+  dir_id: string;
+  name: string;
+  size: number;
+  old_chat_id: number;
+  old_msg_id: number;
+  reason: string;
+  attempts: number;
+  created_at: number;
+  has_local_copy: boolean;
+};
+
+export type MigrationRetryResult = {
+  file_id: string;
+  outcome: "copied" | "reuploaded" | "failed";
+  error?: string | null;
+};
+
 type State = {
   auth: "unknown" | "wait_config" | "wait_phone" | "wait_code" | "wait_password" | "ready" | "closed";
   tree: DirNode | null;
@@ -69,6 +315,7 @@ type State = {
       keychain_available: boolean;
       encrypted_present: boolean;
       locked: boolean;
+      password_hint: string | null;
     };
   };
 
@@ -83,24 +330,69 @@ type State = {
   refreshAuth: () => Promise<string>;
   refreshSettings: () => Promise<void>;
   refreshTree: () => Promise<void>;
+  applyDirChange: (change: DirChangedPayload) => Promise<void>;
   createDir: (parentId: string | null, name: string) => Promise<void>;
-  renameDir: (dirId: string, name: string) => Promise<void>;
+  renameDir: (dirId: string, name: string) => Promise<BatchOutcome>;
   moveDir: (dirId: string, parentId: string | null) => Promise<void>;
+  copyDir: (dirId: string, parentId: string | null) => Promise<DirCopy>;
   deleteDir: (dirId: string) => Promise<void>;
   repairDir: (dirId: string) => Promise<RepairResult>;
   refreshFiles: (dirId: string) => Promise<void>;
   searchFiles: (filters: FileSearchFilters) => Promise<void>;
+  setFavorite: (fileId: string, favorite: boolean) => Promise<void>;
+  createVirtualFolder: (name: string, query: FileSearchFilters) => Promise<VirtualFolder>;
+  listVirtualFolders: () => Promise<VirtualFolder[]>;
+  openVirtualFolder: (folderId: string) => Promise<void>;
+  deleteVirtualFolder: (folderId: string) => Promise<void>;
+  getDirPrefs: (dirId: string) => Promise<DirPrefs>;
+  setDirPrefs: (dirId: string, prefs: DirPrefs) => Promise<DirPrefs>;
   pickFiles: () => Promise<string[]>;
   pickUploadFiles: () => Promise<string[]>;
   prepareUploadPaths: (paths: string[]) => Promise<string[]>;
-  uploadFile: (dirId: string, uploadToken: string) => Promise<void>;
+  uploadFile: (dirId: string, uploadToken: string, dedup?: UploadDedup) => Promise<void>;
+  dropUpload: (dirId: string, paths: string[]) => Promise<DropUpload>;
+  inboxCapture: (capture: { text: string } | { uploadToken: string }) => Promise<InboxCaptured>;
+  createNote: (dirId: string, title: string, body: string) => Promise<Note>;
+  getNote: (fileId: string) => Promise<Note>;
+  updateNote: (fileId: string, body: string, baseHash?: string) => Promise<NoteSaved>;
+  searchNotes: (query: string) => Promise<NoteHit[]>;
+  listMigrationFailures: () => Promise<MigrationFailure[]>;
+  retryMigrationFailures: (fileIds?: string[]) => Promise<MigrationRetryResult[]>;
   moveFiles: (fileIds: string[], dirId: string) => Promise<void>;
+  renameFile: (fileId: string, name: string) => Promise<void>;
+  copyFile: (fileId: string, dirId: string) => Promise<string>;
   deleteFiles: (fileIds: string[]) => Promise<void>;
   repairFile: (fileId: string, uploadToken?: string) => Promise<RepairResult>;
   downloadFile: (fileId: string, overwrite?: boolean) => Promise<string>;
-  openFile: (fileId: string) => Promise<void>;
+  openFile: (fileId: string, confirmToken?: string) => Promise<void>;
   openFileFolder: (fileId: string) => Promise<void>;
+  streamFile: (fileId: string) => Promise<FileStream>;
+  copyFileToClipboard: (fileId: string) => Promise<void>;
+  prepareDrag: (fileIds: string[]) => Promise<DragPayload>;
+  getRecentErrors: (limit?: number) => Promise<RecentError[]>;
+  clearRecentErrors: () => Promise<void>;
+  listAccounts: () => Promise<Account[]>;
+  addAccount: (label: string) => Promise<Account>;
+  switchAccount: (accountId: string) => Promise<void>;
+  listStorageChannels: () => Promise<StorageChannel[]>;
+  createStorageChannel: (title: string) => Promise<StorageChannel>;
+  assignStorageChannel: (dirId: string, chatId: number | null) => Promise<number>;
+  getStorageStats: () => Promise<StorageStats>;
+  takePendingDeepLink: () => Promise<DeepLinkNavigation | null>;
+  openFolderWindow: (dirId: string) => Promise<void>;
+  getCacheUsage: () => Promise<CacheUsage>;
+  clearCache: (dirId?: string) => Promise<CacheClearReport>;
+  getCacheLimit: () => Promise<CacheLimit>;
+  setCacheLimit: (limit: CacheLimit) => Promise<CacheLimit>;
+  getAutoSyncStatus: () => Promise<AutoSyncStatus>;
+  setAutoSync: (enabled: boolean, intervalSecs?: number) => Promise<AutoSyncStatus>;
+  getLocalNameRules: () => Promise<LocalNameRules>;
+  setLocalNameRules: (rules: LocalNameRules) => Promise<LocalNameRules>;
+  previewLocalNames: (names: string[], rules?: LocalNameRules) => Promise<LocalNamePreview[]>;
+  activateFile: (fileId: string, confirmToken?: string) => Promise<FileAction>;
   searchChats: (query: string) => Promise<ChatItem[]>;
+  getChatFolders: () => Promise<ChatFolder[]>;
+  getFolderChats: (folderId: number) => Promise<ChatItem[]>;
   shareFileToChat: (fileId: string, chatId: number) => Promise<string>;
   getRecentChats: () => Promise<ChatItem[]>;
 };
@@ -120,7 +412,8 @@ export const useAppStore = create<State>((set, get) => ({
       source: null,
       keychain_available: true,
       encrypted_present: false,
-      locked: false
+      locked: false,
+      password_hint: null
     }
   },
 
@@ -171,6 +464,7 @@ export const useAppStore = create<State>((set, get) => ({
         keychain_available: boolean;
         encrypted_present: boolean;
         locked: boolean;
+        password_hint: string | null;
       };
     }>("settings_get_tg");
     set({ tgSettings: s });
@@ -181,25 +475,57 @@ export const useAppStore = create<State>((set, get) => ({
     set({ tree: t });
   },
 
+  applyDirChange: async (change) => {
+    const tree = get().tree;
+    if (!tree) {
+      await get().refreshTree();
+      return;
+    }
+    if (change.change === "deleted") {
+      set({ tree: removeDirNode(tree, change.id) });
+      return;
+    }
+    const node = await invokeSafe<DirNode | null>("dir_get_node", { dirId: change.id });
+    const current = get().tree;
+    if (!current) return;
+    if (!node) {
+      set({ tree: removeDirNode(current, change.id) });
+      return;
+    }
+    // Родителя еще нет в дереве (например, пришел раньше своей папки): перечитываем целиком.
+    const next = upsertDirNode(current, node);
+    if (next) {
+      set({ tree: next });
+    } else {
+      await get().refreshTree();
+    }
+  },
+
   createDir: async (parentId, name) => {
-    await invokeSafe("dir_create", { parentId, name });
-    await get().refreshTree();
+    const id = await invokeSafe<string>("dir_create", { parentId, name });
+    await get().applyDirChange({ id, change: "created", parent_id: parentId });
   },
   renameDir: async (dirId, name) => {
-    await invokeSafe("dir_rename", { dirId, name });
-    await get().refreshTree();
+    const retag = await invokeSafe<BatchOutcome>("dir_rename", { dirId, name });
+    await get().applyDirChange({ id: dirId, change: "updated", parent_id: null });
+    return retag;
   },
   moveDir: async (dirId, parentId) => {
     await invokeSafe("dir_move", { dirId, parentId });
+    await get().applyDirChange({ id: dirId, change: "moved", parent_id: parentId });
+  },
+  copyDir: async (dirId, parentId) => {
+    const copied = await invokeSafe<DirCopy>("dir_copy", { dirId, parentId });
     await get().refreshTree();
+    return copied;
   },
   deleteDir: async (dirId) => {
     await invokeSafe("dir_delete", { dirId });
-    await get().refreshTree();
+    await get().applyDirChange({ id: dirId, change: "deleted", parent_id: null });
   },
   repairDir: async (dirId) => {
     const res = await invokeSafe<RepairResult>("dir_repair", { dirId });
-    await get().refreshTree();
+    await get().applyDirChange({ id: dirId, change: "updated", parent_id: null });
     return res;
   },
   refreshFiles: async (dirId) => {
@@ -210,6 +536,29 @@ export const useAppStore = create<State>((set, get) => ({
     const items = await invokeSafe<FileItem[]>("file_search", { input: filters });
     set({ files: items });
   },
+  setFavorite: async (fileId, favorite) => {
+    await invokeSafe("file_set_favorite", { fileId, favorite });
+    set({ files: get().files.map((f) => (f.id === fileId ? { ...f, is_favorite: favorite } : f)) });
+  },
+  createVirtualFolder: async (name, query) => {
+    return invokeSafe<VirtualFolder>("virtual_folder_create", { name, query });
+  },
+  listVirtualFolders: async () => {
+    return invokeSafe<VirtualFolder[]>("virtual_folder_list");
+  },
+  openVirtualFolder: async (folderId) => {
+    const items = await invokeSafe<FileItem[]>("virtual_folder_files", { folderId });
+    set({ files: items });
+  },
+  deleteVirtualFolder: async (folderId) => {
+    await invokeSafe("virtual_folder_delete", { folderId });
+  },
+  getDirPrefs: async (dirId) => {
+    return invokeSafe<DirPrefs>("dir_prefs_get", { dirId });
+  },
+  setDirPrefs: async (dirId, prefs) => {
+    return invokeSafe<DirPrefs>("dir_prefs_set", { dirId, prefs });
+  },
   pickFiles: async () => {
     const files = await invokeSafe<string[]>("file_pick");
     return files;
@@ -220,14 +569,54 @@ export const useAppStore = create<State>((set, get) => ({
   prepareUploadPaths: async (paths) => {
     return invokeSafe<string[]>("file_prepare_upload_paths", { paths });
   },
-  uploadFile: async (dirId, uploadToken) => {
-    await invokeSafe("file_upload", { dirId, uploadToken });
+  uploadFile: async (dirId, uploadToken, dedup) => {
+    await invokeSafe("file_upload", { dirId, uploadToken, dedup: dedup ?? null });
+  },
+  dropUpload: async (dirId, paths) => {
+    // Перетаскивание не спрашивает про дубликаты: одинаковое содержимое добавляется ссылкой.
+    return invokeSafe<DropUpload>("file_drop_upload", { dirId, paths, dedup: "reference" });
+  },
+  inboxCapture: async (capture) => {
+    const text = "text" in capture ? capture.text : null;
+    const uploadToken = "uploadToken" in capture ? capture.uploadToken : null;
+    return invokeSafe<InboxCaptured>("inbox_capture", { text, uploadToken });
+  },
+  createNote: async (dirId, title, body) => {
+    return invokeSafe<Note>("note_create", { dirId, title, body });
+  },
+  getNote: async (fileId) => {
+    return invokeSafe<Note>("note_get", { fileId });
+  },
+  updateNote: async (fileId, body, baseHash) => {
+    return invokeSafe<NoteSaved>("note_update", { fileId, body, baseHash: baseHash ?? null });
+  },
+  searchNotes: async (query) => {
+    return invokeSafe<NoteHit[]>("notes_search", { query });
+  },
+  listMigrationFailures: async () => {
+    return invokeSafe<MigrationFailure[]>("migration_failures_list");
+  },
+  retryMigrationFailures: async (fileIds) => {
+    return invokeSafe<MigrationRetryResult[]>("migration_failures_retry", { fileIds: fileIds ?? null });
   },
   moveFiles: async (fileIds, dirId) => {
-    for (const fileId of fileIds) {
-      await invokeSafe("file_move", { fileId, dirId });
+    if (fileIds.length === 0) return;
+    if (fileIds.length === 1) {
+      await invokeSafe("file_move", { fileId: fileIds[0], dirId });
+      return;
+    }
+    const outcome = await invokeSafe<BatchOutcome>("file_move_many", { fileIds, dirId });
+    if (outcome.failed.length > 0) {
+      const [, error] = outcome.failed[0];
+      throw new Error(`Не удалось перенести файлов: ${outcome.failed.length}. ${error}`);
     }
   },
+  renameFile: async (fileId, name) => {
+    await invokeSafe("file_rename", { fileId, name });
+  },
+  copyFile: async (fileId, dirId) => {
+    return invokeSafe<string>("file_copy", { fileId, dirId });
+  },
   deleteFiles: async (fileIds) => {
     if (fileIds.length === 0) return;
     if (fileIds.length === 1) {
@@ -242,15 +631,94 @@ export const useAppStore = create<State>((set, get) => ({
   downloadFile: async (fileId, overwrite = false) => {
     return invokeSafe<string>("file_download", { fileId, overwrite });
   },
-  openFile: async (fileId) => {
-    await invokeSafe("file_open", { fileId });
+  openFile: async (fileId, confirmToken) => {
+    await invokeSafe("file_open", { fileId, confirmToken: confirmToken ?? null });
   },
   openFileFolder: async (fileId) => {
     await invokeSafe("file_open_folder", { fileId });
   },
+  streamFile: async (fileId) => {
+    return invokeSafe<FileStream>("file_stream", { fileId });
+  },
+  copyFileToClipboard: async (fileId) => {
+    await invokeSafe("file_copy_to_clipboard", { fileId });
+  },
+  prepareDrag: async (fileIds) => {
+    return invokeSafe<DragPayload>("file_prepare_drag", { fileIds });
+  },
+  getRecentErrors: async (limit) => {
+    return invokeSafe<RecentError[]>("errors_recent", { limit: limit ?? null });
+  },
+  clearRecentErrors: async () => {
+    await invokeSafe("errors_clear");
+  },
+  listAccounts: async () => {
+    return invokeSafe<Account[]>("account_list");
+  },
+  addAccount: async (label) => {
+    return invokeSafe<Account>("account_add", { label });
+  },
+  switchAccount: async (accountId) => {
+    await invokeSafe("account_switch", { accountId });
+    await get().refreshAuth();
+  },
+  listStorageChannels: async () => {
+    return invokeSafe<StorageChannel[]>("storage_channels_list");
+  },
+  createStorageChannel: async (title) => {
+    return invokeSafe<StorageChannel>("storage_channel_create", { title });
+  },
+  assignStorageChannel: async (dirId, chatId) => {
+    return invokeSafe<number>("storage_channel_assign", { dirId, chatId });
+  },
+  getStorageStats: async () => {
+    return invokeSafe<StorageStats>("storage_stats");
+  },
+  takePendingDeepLink: async () => {
+    return invokeSafe<DeepLinkNavigation | null>("deep_link_pending");
+  },
+  openFolderWindow: async (dirId) => {
+    await invokeSafe("window_open_folder", { dirId });
+  },
+  getCacheUsage: async () => {
+    return invokeSafe<CacheUsage>("cache_usage");
+  },
+  clearCache: async (dirId) => {
+    return invokeSafe<CacheClearReport>("cache_clear", { dirId: dirId ?? null });
+  },
+  getCacheLimit: async () => {
+    return invokeSafe<CacheLimit>("settings_get_cache_limit");
+  },
+  setCacheLimit: async (limit) => {
+    return invokeSafe<CacheLimit>("settings_set_cache_limit", { limit });
+  },
+  getAutoSyncStatus: async () => {
+    return invokeSafe<AutoSyncStatus>("sync_auto_status");
+  },
+  setAutoSync: async (enabled, intervalSecs) => {
+    return invokeSafe<AutoSyncStatus>("sync_auto_enable", { enabled, intervalSecs: intervalSecs ?? null });
+  },
+  getLocalNameRules: async () => {
+    return invokeSafe<LocalNameRules>("settings_get_local_names");
+  },
+  setLocalNameRules: async (rules) => {
+    return invokeSafe<LocalNameRules>("settings_set_local_names", { rules });
+  },
+  previewLocalNames: async (names, rules) => {
+    return invokeSafe<LocalNamePreview[]>("local_name_preview", { names, rules: rules ?? null });
+  },
+  activateFile: async (fileId, confirmToken) => {
+    return invokeSafe<FileAction>("file_activate", { fileId, confirmToken: confirmToken ?? null });
+  },
   searchChats: async (query) => {
     return invokeSafe<ChatItem[]>("tg_search_chats", { query });
   },
+  getChatFolders: async () => {
+    return invokeSafe<ChatFolder[]>("tg_chat_folders");
+  },
+  getFolderChats: async (folderId) => {
+    return invokeSafe<ChatItem[]>("tg_folder_chats", { folderId });
+  },
   shareFileToChat: async (fileId, chatId) => {
     const res = await invokeSafe<{ message: string }>("file_share_to_chat", { fileId, chatId });
     return res.message;
@@ -260,6 +728,43 @@ export const useAppStore = create<State>((set, get) => ({
   }
 }));
 
+function findDirNode(node: DirNode, id: string): DirNode | null {
+  if (node.id === id) return node;
+  for (const child of node.children) {
+    const found = findDirNode(child, id);
+    if (found) return found;
+  }
+  return null;
+}
+
+// Дерево без узла `id` (вместе с его поддеревом).
+export function removeDirNode(tree: DirNode, id: string): DirNode {
+  if (!findDirNode(tree, id) || tree.id === id) return tree;
+  const strip = (node: DirNode): DirNode => ({
+    ...node,
+    children: node.children.filter((child) => child.id !== id).map(strip)
+  });
+  return strip(tree);
+}
+
+// Ставит узел под его родителя, сохраняя уже загруженных детей; порядок — по
+// имени, как в dir_list_tree. Возвращает null, если родителя в дереве нет.
+export function upsertDirNode(tree: DirNode, node: DirNode): DirNode | null {
+  const parentId = node.parent_id ?? tree.id;
+  const existing = findDirNode(tree, node.id);
+  const placed: DirNode = { ...node, children: existing?.children ?? node.children };
+  const base = removeDirNode(tree, node.id);
+  if (!findDirNode(base, parentId)) return null;
+  const insert = (current: DirNode): DirNode => {
+    if (current.id === parentId) {
+      const children = [...current.children, placed].sort((a, b) => (a.name < b.name ? -1 : a.name > b.name ? 1 : 0));
+      return { ...current, children };
+    }
+    return { ...current, children: current.children.map(insert) };
+  };
+  return insert(base);
+}
+
 function extractPercent(line: string): number | null {
   const match = line.match(/(?:^|[^0-9])(\d{1,3})%/);
   if (!match) return null;
@@ -13,7 +13,14 @@ export async function invokeSafe<T>(cmd: string, args?: Record<string, any>): Pr
   if (!isTauri()) {
     throw new Error("Tauri API недоступны в браузере. Запусти приложение через Tauri.");
   }
-  return invoke<T>(cmd, args as any);
+  try {
+    return await invoke<T>(cmd, args as any);
+  } catch (e) {
+    if (cmd !== "errors_report") {
+      void invoke("errors_report", { command: cmd, message: String(e) }).catch(() => {});
+    }
+    throw e;
+  }
 }
 
 export async function listenSafe<T>(event: string, handler: EventCallback<T>): Promise<UnlistenFn> {